@@ -0,0 +1,276 @@
+use crate::{
+    _tally_sealed, env::set_certified_data, Proposal, ProposalStatus, VoteDirection,
+    PROPOSAL_STORAGE,
+};
+use candid::Principal;
+use ic_certified_map::{AsHashTree, Hash, RbTree};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+thread_local! {
+    // proposal id (big endian) -> digest of the fields voters rely on. Lives on the heap and is
+    // rebuilt from stable storage after an upgrade
+    static TREE: RefCell<RbTree<Vec<u8>, Hash>> = const { RefCell::new(RbTree::new()) };
+}
+
+// Record the current state of a proposal in the certified tree
+pub(crate) fn certify(proposal: &Proposal) {
+    TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        tree.insert(key(proposal.id), digest(proposal));
+        set_certified_data(&tree.root_hash());
+    });
+}
+
+pub(crate) fn uncertify(proposal_id: u64) {
+    TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        tree.delete(&key(proposal_id));
+        set_certified_data(&tree.root_hash());
+    });
+}
+
+// Certify every stored proposal, e.g. after an upgrade cleared the heap
+pub(crate) fn rebuild() {
+    TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        *tree = RbTree::new();
+        PROPOSAL_STORAGE.with(|service| {
+            service
+                .borrow()
+                .iter()
+                .for_each(|(id, proposal)| tree.insert(key(id), digest(&proposal)))
+        });
+        set_certified_data(&tree.root_hash());
+    });
+}
+
+// CBOR encoded witness proving the digest of a proposal against the certified root hash
+pub(crate) fn witness(proposal_id: u64) -> Vec<u8> {
+    TREE.with(|tree| {
+        let tree = tree.borrow();
+        let witness = tree.witness(&key(proposal_id));
+
+        let mut serializer = serde_cbor::ser::Serializer::new(vec![]);
+        serializer
+            .self_describe()
+            .expect("cannot write the cbor tag");
+        witness
+            .serialize(&mut serializer)
+            .expect("cannot serialize the witness");
+        serializer.into_inner()
+    })
+}
+
+fn key(proposal_id: u64) -> Vec<u8> {
+    proposal_id.to_be_bytes().to_vec()
+}
+
+// sha256(upvote_count || downvote_count || status || deadline), counts and deadline as big
+// endian u64 and the status as a single byte (0 open, 1 approved, 2 rejected,
+// 3 draft, 4 cancelled, 5 blocked, 6 scheduled, 7 queued). While the DAO hides the tally of an
+// open proposal both counts are written as 0, a handful of guesses would find them otherwise
+fn digest(proposal: &Proposal) -> Hash {
+    let status: u8 = match proposal.status {
+        ProposalStatus::Open => 0,
+        ProposalStatus::Approved => 1,
+        ProposalStatus::Rejected => 2,
+        ProposalStatus::Draft => 3,
+        ProposalStatus::Cancelled => 4,
+        ProposalStatus::Blocked => 5,
+        ProposalStatus::Scheduled => 6,
+        ProposalStatus::Queued => 7,
+    };
+
+    let (upvote_count, downvote_count) = if _tally_sealed(proposal) {
+        (0, 0)
+    } else {
+        (proposal.upvote_count, proposal.downvote_count)
+    };
+
+    let mut hasher = Sha256::new();
+    hasher.update(upvote_count.to_be_bytes());
+    hasher.update(downvote_count.to_be_bytes());
+    hasher.update([status]);
+    hasher.update(proposal.deadline.to_be_bytes());
+    hasher.finalize().into()
+}
+
+// sha256 over the ballots of a proposal ordered by voter, so the same ballots always give the same
+// digest. Each ballot is written as principal length (1 byte) || principal bytes || direction
+// (0 up, 1 down) || weight as big endian u32
+pub(crate) fn ballot_digest(mut ballots: Vec<(Principal, VoteDirection, u32)>) -> Vec<u8> {
+    ballots.sort_by(|(a, _, _), (b, _, _)| a.as_slice().cmp(b.as_slice()));
+
+    let mut hasher = Sha256::new();
+    for (voter, direction, weight) in ballots {
+        let direction: u8 = match direction {
+            VoteDirection::Up => 0,
+            VoteDirection::Down => 1,
+        };
+        hasher.update([voter.as_slice().len() as u8]);
+        hasher.update(voter.as_slice());
+        hasher.update([direction]);
+        hasher.update(weight.to_be_bytes());
+    }
+    hasher.finalize().to_vec()
+}
+
+// sha256(direction || salt || voter) a vote on an anonymous ballot is committed to, with the
+// direction as a single byte (0 up, 1 down) and the voter as its principal bytes
+pub(crate) fn ballot_commitment(
+    direction: VoteDirection,
+    salt: &[u8],
+    voter: &Principal,
+) -> Vec<u8> {
+    let direction: u8 = match direction {
+        VoteDirection::Up => 0,
+        VoteDirection::Down => 1,
+    };
+
+    let mut hasher = Sha256::new();
+    hasher.update([direction]);
+    hasher.update(salt);
+    hasher.update(voter.as_slice());
+    hasher.finalize().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::certified_data;
+    use ic_certified_map::{fork_hash, labeled_hash, leaf_hash, HashTree};
+    use serde_cbor::Value;
+
+    fn proposal(id: u64, upvote_count: u64) -> Proposal {
+        Proposal {
+            id,
+            upvote_count,
+            downvote_count: 1,
+            deadline: 1_000,
+            ..Default::default()
+        }
+    }
+
+    fn bytes(value: &Value) -> &[u8] {
+        match value {
+            Value::Bytes(bytes) => bytes,
+            _ => panic!("expected bytes"),
+        }
+    }
+
+    // Recompute the root hash of a decoded witness like a client would, collecting its leaves
+    fn reconstruct(tree: &Value, leaves: &mut Vec<(Vec<u8>, Vec<u8>)>, label: &[u8]) -> Hash {
+        let Value::Array(node) = tree else {
+            panic!("expected a tree node")
+        };
+        match (&node[0], node.len()) {
+            (Value::Integer(0), 1) => HashTree::Empty.reconstruct(),
+            (Value::Integer(1), 3) => fork_hash(
+                &reconstruct(&node[1], leaves, label),
+                &reconstruct(&node[2], leaves, label),
+            ),
+            (Value::Integer(2), 3) => {
+                let label = bytes(&node[1]);
+                labeled_hash(label, &reconstruct(&node[2], leaves, label))
+            }
+            (Value::Integer(3), 2) => {
+                leaves.push((label.to_vec(), bytes(&node[1]).to_vec()));
+                leaf_hash(bytes(&node[1]))
+            }
+            (Value::Integer(4), 2) => bytes(&node[1]).try_into().unwrap(),
+            _ => panic!("unknown tree node"),
+        }
+    }
+
+    // the leaves of the witness of a proposal, after checking it hashes to the certified root
+    fn verified_leaves(proposal_id: u64) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let tree: Value = serde_cbor::from_slice(&witness(proposal_id)).unwrap();
+        let mut leaves = Vec::new();
+        let root = reconstruct(&tree, &mut leaves, &[]);
+        assert_eq!(root.to_vec(), certified_data());
+        leaves
+    }
+
+    #[test]
+    fn witnesses_prove_the_digest_against_the_certified_root() {
+        for id in 1..=3 {
+            certify(&proposal(id, id));
+        }
+        assert_eq!(
+            verified_leaves(2),
+            vec![(key(2), digest(&proposal(2, 2)).to_vec())]
+        );
+
+        // a changed count changes the root and the leaf proven
+        let root = certified_data();
+        certify(&proposal(2, 5));
+        assert!(root != certified_data());
+        assert_eq!(
+            verified_leaves(2),
+            vec![(key(2), digest(&proposal(2, 5)).to_vec())]
+        );
+
+        // deleted proposals are proven absent
+        uncertify(2);
+        assert!(verified_leaves(2).is_empty());
+        assert_eq!(verified_leaves(3).len(), 1);
+    }
+
+    #[test]
+    fn digests_cover_counts_status_and_deadline_only() {
+        let base = proposal(1, 2);
+        let renamed = Proposal {
+            title: "Renamed".to_string(),
+            ..base.clone()
+        };
+        assert!(digest(&renamed) == digest(&base));
+        for changed in [
+            Proposal {
+                downvote_count: 2,
+                ..base.clone()
+            },
+            Proposal {
+                status: ProposalStatus::Approved,
+                ..base.clone()
+            },
+            Proposal {
+                deadline: 2_000,
+                ..base.clone()
+            },
+        ] {
+            assert!(digest(&changed) != digest(&base));
+        }
+    }
+
+    #[test]
+    fn ballot_digests_ignore_order_but_not_any_ballot() {
+        let voter = |n: u8| Principal::from_slice(&[n]);
+        let ballots = vec![
+            (voter(1), VoteDirection::Up, 2),
+            (voter(2), VoteDirection::Down, 1),
+            (voter(3), VoteDirection::Up, 1),
+        ];
+        let digest = ballot_digest(ballots.clone());
+        assert_eq!(digest.len(), 32);
+        assert_eq!(
+            ballot_digest(ballots.iter().rev().cloned().collect()),
+            digest
+        );
+
+        let changed = |index: usize, ballot| {
+            let mut ballots = ballots.clone();
+            ballots[index] = ballot;
+            ballot_digest(ballots)
+        };
+        for other in [
+            changed(0, (voter(1), VoteDirection::Down, 2)),
+            changed(0, (voter(1), VoteDirection::Up, 1)),
+            changed(1, (voter(4), VoteDirection::Down, 1)),
+            ballot_digest(ballots[..2].to_vec()),
+        ] {
+            assert!(other != digest);
+        }
+    }
+}
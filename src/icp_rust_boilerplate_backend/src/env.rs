@@ -0,0 +1,204 @@
+// The caller and clock of the current message, the canister's id, controllers and cycles, its
+// stable memory and its size, certified data, instruction counter and timers. Everything reaches
+// them through here instead of ic_cdk, whose system calls only exist inside a canister, so native
+// unit tests can run the code using them
+use candid::Principal;
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::DefaultMemoryImpl;
+use std::cell::Cell;
+use std::time::Duration;
+
+thread_local! {
+    // the user a bucket runs a forwarded call for, see act_for
+    static ACTING_FOR: Cell<Option<Principal>> = const { Cell::new(None) };
+}
+
+// Make `caller` return `user` until it is cleared with None. A bucket runs the calls the main
+// canister forwards to it as the user who made them, see sharding::handle_update. It is never
+// left set across an await, other messages would run as that user too
+pub(crate) fn act_for(user: Option<Principal>) {
+    ACTING_FOR.with(|acting_for| acting_for.set(user));
+}
+
+#[cfg(not(test))]
+pub(crate) fn caller() -> Principal {
+    ACTING_FOR
+        .with(Cell::get)
+        .unwrap_or_else(ic_cdk::api::caller)
+}
+
+#[cfg(not(test))]
+pub(crate) fn time() -> u64 {
+    ic_cdk::api::time()
+}
+
+#[cfg(not(test))]
+pub(crate) fn canister_id() -> Principal {
+    ic_cdk::api::id()
+}
+
+#[cfg(not(test))]
+pub(crate) fn is_controller(principal: &Principal) -> bool {
+    ic_cdk::api::is_controller(principal)
+}
+
+#[cfg(not(test))]
+pub(crate) fn cycle_balance() -> u64 {
+    ic_cdk::api::canister_balance()
+}
+
+#[cfg(not(test))]
+pub(crate) fn stable_memory() -> DefaultMemoryImpl {
+    DefaultMemoryImpl::default()
+}
+
+#[cfg(not(test))]
+pub(crate) fn stable_pages() -> u64 {
+    ic_cdk::api::stable::stable64_size()
+}
+
+#[cfg(not(test))]
+pub(crate) fn set_certified_data(data: &[u8]) {
+    ic_cdk::api::set_certified_data(data)
+}
+
+#[cfg(not(test))]
+pub(crate) fn data_certificate() -> Option<Vec<u8>> {
+    ic_cdk::api::data_certificate()
+}
+
+#[cfg(not(test))]
+pub(crate) fn instruction_counter() -> u64 {
+    ic_cdk::api::performance_counter(0)
+}
+
+#[cfg(not(test))]
+pub(crate) fn set_timer(delay: Duration, func: impl FnOnce() + 'static) -> TimerId {
+    ic_cdk_timers::set_timer(delay, func)
+}
+
+#[cfg(not(test))]
+pub(crate) fn set_timer_interval(interval: Duration, func: impl FnMut() + 'static) -> TimerId {
+    ic_cdk_timers::set_timer_interval(interval, func)
+}
+
+#[cfg(not(test))]
+pub(crate) fn clear_timer(id: TimerId) {
+    ic_cdk_timers::clear_timer(id)
+}
+
+#[cfg(test)]
+thread_local! {
+    static CALLER: std::cell::Cell<Principal> = const { std::cell::Cell::new(Principal::anonymous()) };
+    static TIME: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+    static STABLE_PAGES: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+    static CERTIFIED_DATA: std::cell::RefCell<Vec<u8>> = const { std::cell::RefCell::new(Vec::new()) };
+    static STABLE_MEMORY: DefaultMemoryImpl = DefaultMemoryImpl::default();
+}
+
+// Natively messages come from the anonymous principal at time 0 until a test sets them
+#[cfg(test)]
+pub(crate) fn caller() -> Principal {
+    ACTING_FOR
+        .with(Cell::get)
+        .unwrap_or_else(|| CALLER.with(|caller| caller.get()))
+}
+
+#[cfg(test)]
+pub(crate) fn time() -> u64 {
+    TIME.with(|time| time.get())
+}
+
+// Natively the canister has no id, the management canister's stands in for it
+#[cfg(test)]
+pub(crate) fn canister_id() -> Principal {
+    Principal::management_canister()
+}
+
+// Natively the canister has no controllers
+#[cfg(test)]
+pub(crate) fn is_controller(_principal: &Principal) -> bool {
+    false
+}
+
+// Natively the canister holds no cycles
+#[cfg(test)]
+pub(crate) fn cycle_balance() -> u64 {
+    0
+}
+
+// Natively stable memory is a VectorMemory each test thread starts with empty, see upgrade
+#[cfg(test)]
+pub(crate) fn stable_memory() -> DefaultMemoryImpl {
+    STABLE_MEMORY.with(|memory| memory.clone())
+}
+
+// Natively stable memory is never grown, the maps live in VectorMemory. Tests can pretend it was
+#[cfg(test)]
+pub(crate) fn stable_pages() -> u64 {
+    STABLE_PAGES.with(|pages| pages.get())
+}
+
+// Natively there is no certificate, the data is kept for tests to check witnesses against
+#[cfg(test)]
+pub(crate) fn set_certified_data(data: &[u8]) {
+    CERTIFIED_DATA.with(|certified| *certified.borrow_mut() = data.to_vec());
+}
+
+// Natively the certified data stands in for the certificate covering it
+#[cfg(test)]
+pub(crate) fn data_certificate() -> Option<Vec<u8>> {
+    Some(certified_data())
+}
+
+#[cfg(test)]
+pub(crate) fn instruction_counter() -> u64 {
+    0
+}
+
+// Natively timers never fire, tests run what they would have run themselves
+#[cfg(test)]
+pub(crate) fn set_timer(_delay: Duration, _func: impl FnOnce() + 'static) -> TimerId {
+    TimerId::default()
+}
+
+#[cfg(test)]
+pub(crate) fn set_timer_interval(_interval: Duration, _func: impl FnMut() + 'static) -> TimerId {
+    TimerId::default()
+}
+
+#[cfg(test)]
+pub(crate) fn clear_timer(_id: TimerId) {}
+
+#[cfg(test)]
+pub(crate) fn set_caller(principal: Principal) {
+    CALLER.with(|caller| caller.set(principal));
+}
+
+#[cfg(test)]
+pub(crate) fn set_time(now: u64) {
+    TIME.with(|time| time.set(now));
+}
+
+#[cfg(test)]
+pub(crate) fn set_stable_pages(pages: u64) {
+    STABLE_PAGES.with(|stable| stable.set(pages));
+}
+
+#[cfg(test)]
+pub(crate) fn certified_data() -> Vec<u8> {
+    CERTIFIED_DATA.with(|certified| certified.borrow().clone())
+}
+
+// Runs `after_upgrade` the way an upgrade leaves the canister: on a thread of its own, so that every
+// thread-local starts over, with a copy of this thread's stable memory
+#[cfg(test)]
+pub(crate) fn upgrade(after_upgrade: impl FnOnce() + Send + 'static) {
+    let bytes = STABLE_MEMORY.with(|memory| memory.borrow().clone());
+    std::thread::spawn(move || {
+        STABLE_MEMORY.with(|memory| *memory.borrow_mut() = bytes);
+        after_upgrade()
+    })
+    .join()
+    .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+}
@@ -0,0 +1,466 @@
+use crate::env::time;
+use crate::{
+    _comment_ids, _dao_proposal_ids, _get_attachments, _get_comment, _get_milestones,
+    _get_proposal, _get_settings, _memberships, _set_membership, _validate_attachments,
+    _validate_milestones,
+};
+use crate::{
+    Attachment, Comment, Dao, DaoSettings, Error, ErrorContext, ImageRef, Membership, Milestone,
+    PrincipalKey, Proposal, ProposalStatus, Vote, CONFIG, DAO_SETTINGS, ID_COUNTER,
+    PROPOSAL_NUMBERS, VOTE_STORAGE,
+};
+use candid::Principal;
+
+// Version of the export layout, bumped whenever DaoExport changes shape
+pub(crate) const EXPORT_FORMAT_VERSION: u32 = 2;
+// Most entities (the dao, proposals, comments, votes and attachments) an import may create,
+// unless the canister admin configures another cap
+pub(crate) const DEFAULT_MAX_IMPORT_ENTITIES: u64 = 5_000;
+
+// Everything needed to re-create a DAO, as produced by export_dao
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DaoExport {
+    version: u32,
+    exported_at: u64,
+    dao: Dao,
+    // the admins, members, observers and banned users of the DAO
+    memberships: Vec<(Principal, Membership)>,
+    proposals: Vec<ProposalExport>,
+    // missing from exports made before DAOs had settings, the imported DAO then derives them
+    settings: Option<DaoSettings>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ProposalExport {
+    proposal: Proposal,
+    comments: Vec<Comment>,
+    votes: Vec<Vote>,
+    attachments: Vec<Attachment>,
+    // missing from exports made before milestones
+    milestones: Option<Vec<Milestone>>,
+}
+
+// Bundle a DAO with its proposals, their comments, votes and attachments. Who voted which way is
+// only exported when the DAO's ballots are public, like get_ballots. Otherwise the voters of the
+// votes are replaced by placeholders, so the import keeps its tally
+pub(crate) fn build(dao: Dao) -> DaoExport {
+    let mut settings = _get_settings(&dao.id);
+    // the webhook secret never leaves the canister, imported DAOs set their webhook again
+    settings.webhook = None;
+    let proposals = _dao_proposal_ids(&dao.id)
+        .iter()
+        .filter_map(_get_proposal)
+        .map(|proposal| {
+            let mut votes = proposal_votes(proposal.id);
+            if !settings.public_ballots {
+                redact_voters(&mut votes);
+            }
+            ProposalExport {
+                comments: _comment_ids(&proposal.id)
+                    .iter()
+                    .filter_map(_get_comment)
+                    .collect(),
+                votes,
+                attachments: _get_attachments(&proposal.id),
+                milestones: Some(_get_milestones(&proposal.id)),
+                proposal,
+            }
+        })
+        .collect();
+
+    DaoExport {
+        version: EXPORT_FORMAT_VERSION,
+        exported_at: time(),
+        memberships: _memberships(dao.id),
+        dao,
+        proposals,
+        settings: Some(settings),
+    }
+}
+
+// Re-create an exported DAO owned by `owner`. Every entity gets a fresh id while timestamps,
+// flags and vote tallies are kept. Imported votes are history, their voters don't need to be members
+pub(crate) fn restore(export: DaoExport, owner: &Principal) -> Result<Dao, Error> {
+    if export.version != EXPORT_FORMAT_VERSION {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            format!(
+                "export version {} is not supported, expected {}",
+                export.version, EXPORT_FORMAT_VERSION
+            ),
+        ));
+    }
+
+    let max_entities = CONFIG
+        .with(|config| config.borrow().get().max_import_entities)
+        .unwrap_or(DEFAULT_MAX_IMPORT_ENTITIES);
+    let entity_count = 1 + export
+        .proposals
+        .iter()
+        .map(|entry| {
+            1 + entry.comments.len()
+                + entry.votes.len()
+                + entry.attachments.len()
+                + entry.milestones.as_ref().map(Vec::len).unwrap_or_default()
+        })
+        .sum::<usize>() as u64;
+    if entity_count > max_entities {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            format!(
+                "export contains {} entities, at most {} can be imported",
+                entity_count, max_entities
+            ),
+        ));
+    }
+    for entry in export.proposals.iter() {
+        _validate_attachments(&entry.attachments)?;
+        _validate_milestones(
+            &entry.proposal.kind,
+            entry.milestones.as_deref().unwrap_or_default(),
+        )?;
+    }
+
+    let mut dao = export.dao;
+    dao.id = next_id();
+    // the exported DAO may still be around and keeps its slugs
+    crate::_assign_dao_slug(&mut dao);
+    dao.owner = Some(*owner);
+    dao.member_count = 0;
+    for (user, membership) in export.memberships.iter() {
+        if user != owner {
+            _set_membership(&mut dao, user, Some(*membership));
+        }
+    }
+    dao.proposal_count = 0;
+    // uploaded images aren't part of exports
+    if let ImageRef::Image(_) = dao.avatar {
+        dao.avatar = ImageRef::None;
+    }
+
+    for entry in export.proposals {
+        let mut proposal = entry.proposal;
+        proposal.id = next_id();
+        proposal.dao_id = dao.id;
+        proposal.comment_count = 0;
+        // deposits stay with the treasury of the exported DAO
+        proposal.deposit = None;
+        crate::_assign_proposal_slug(&mut proposal);
+        // numbers are kept so references like "DIP-12" still point at the same proposal
+        let taken = PROPOSAL_NUMBERS.with(|service| {
+            service
+                .borrow()
+                .contains_key(&(dao.id, proposal.proposal_number))
+        });
+        if proposal.proposal_number == 0 || taken {
+            proposal.proposal_number = crate::_next_proposal_number(&mut dao);
+        }
+        dao.last_proposal_number = dao.last_proposal_number.max(proposal.proposal_number);
+        crate::_index_proposal_number(&proposal);
+
+        // replies point at the ids the comments had in the exported DAO
+        let mut new_ids = std::collections::BTreeMap::new();
+        for mut comment in entry.comments {
+            let old_id = comment.id;
+            comment.id = next_id();
+            new_ids.insert(old_id, comment.id);
+            comment.proposal_id = proposal.id;
+            comment.reply_to = comment
+                .reply_to
+                .and_then(|parent_id| new_ids.get(&parent_id).copied());
+            // exports made before COMMENT_LIKES list the likes on the comment
+            crate::_backfill_likes(&mut comment);
+            if let Some(author) = comment.author {
+                crate::_record_participation(&author, &proposal.id);
+            }
+            crate::_list_comment(&mut proposal, comment.id);
+            crate::do_insert_comment(&comment);
+        }
+        for mut vote in entry.votes {
+            vote.proposal_id = proposal.id;
+            crate::do_insert_vote(&vote);
+        }
+        crate::_recount_votes(&mut proposal);
+        crate::_set_attachments(&proposal.id, entry.attachments);
+        crate::_set_milestones(&proposal.id, entry.milestones.unwrap_or_default());
+        // fills the participant index of the new id
+        crate::_backfill_discussion(&mut proposal);
+
+        crate::_list_dao_proposal(&mut dao, proposal.id);
+        crate::do_insert_proposal(&mut proposal);
+        // proposals are imported oldest first, so queued ones keep their turn
+        if proposal.status == ProposalStatus::Queued {
+            crate::_enqueue_proposal(&proposal);
+        }
+    }
+
+    crate::do_insert_dao(&mut dao);
+    if let Some(mut settings) = export.settings {
+        settings.dao_id = dao.id;
+        // the import stands on its own, whatever the exported DAO was merged into
+        settings.merged_into = None;
+        DAO_SETTINGS.with(|service| service.borrow_mut().insert(dao.id, settings));
+    }
+    Ok(dao)
+}
+
+// Replace the voters of the votes on a proposal with distinct placeholder principals. They are 8
+// bytes long, which no user or canister principal is
+fn redact_voters(votes: &mut [Vote]) {
+    let mut placeholders = (0u64..).map(|n| Principal::from_slice(&n.to_be_bytes()));
+    for vote in votes.iter_mut() {
+        vote.voter = placeholders.next();
+    }
+}
+
+fn proposal_votes(proposal_id: u64) -> Vec<Vote> {
+    VOTE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((proposal_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| *id == proposal_id)
+            .map(|(_, vote)| vote)
+            .collect()
+    })
+}
+
+fn next_id() -> u64 {
+    ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("cannot increment id counter")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::set_caller;
+    use crate::{do_insert_vote, VoteDirection, PROPOSAL_STORAGE};
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    // DAO 1 with a proposal upvoted by users 2 and 3 and downvoted by user 4
+    fn dao(public_ballots: bool) -> Dao {
+        let proposal = Proposal {
+            id: 10,
+            dao_id: 1,
+            owner: Some(user(1)),
+            upvote_count: 2,
+            downvote_count: 1,
+            ..Default::default()
+        };
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(10, proposal));
+        for (voter, direction) in [
+            (2, VoteDirection::Up),
+            (3, VoteDirection::Up),
+            (4, VoteDirection::Down),
+        ] {
+            do_insert_vote(&Vote {
+                proposal_id: 10,
+                voter: Some(user(voter)),
+                direction,
+                voted_at: 0,
+                weight: None,
+                ranking: None,
+            });
+        }
+        let settings = DaoSettings {
+            dao_id: 1,
+            public_ballots,
+            ..Default::default()
+        };
+        DAO_SETTINGS.with(|service| service.borrow_mut().insert(1, settings));
+        let mut dao = Dao {
+            id: 1,
+            owner: Some(user(1)),
+            ..Default::default()
+        };
+        crate::_list_dao_proposal(&mut dao, 10);
+        for voter in 2..=4 {
+            _set_membership(&mut dao, &user(voter), Some(Membership::Member));
+        }
+        dao
+    }
+
+    #[test]
+    fn exports_of_private_ballots_hide_the_voters() {
+        let export = build(dao(false));
+        let entry = &export.proposals[0];
+        assert_eq!(entry.votes.len(), 3);
+        assert_eq!(
+            (entry.proposal.upvote_count, entry.proposal.downvote_count),
+            (2, 1)
+        );
+        let mut voters: Vec<Principal> = entry.votes.iter().filter_map(|vote| vote.voter).collect();
+        assert_eq!(voters.len(), 3);
+        assert!(voters
+            .iter()
+            .all(|voter| ![2, 3, 4].map(user).contains(voter)));
+        assert_eq!(crate::service::dedupe_principals(&mut voters), 0);
+    }
+
+    #[test]
+    fn exports_of_public_ballots_keep_the_voters() {
+        let export = build(dao(true));
+        let entry = &export.proposals[0];
+        assert_eq!(entry.votes.len(), 3);
+        let voters: Vec<Principal> = entry.votes.iter().filter_map(|vote| vote.voter).collect();
+        assert_eq!(voters, vec![user(2), user(3), user(4)]);
+    }
+
+    #[test]
+    fn imports_keep_the_tally_of_private_ballots() {
+        let export = build(dao(false));
+        let dao = restore(export, &user(1)).ok().unwrap();
+        let id = crate::_dao_proposal_ids(&dao.id)[0];
+        let proposal = _get_proposal(&id).unwrap();
+        assert_eq!((proposal.upvote_count, proposal.downvote_count), (2, 1));
+        assert_eq!(crate::_votes(&id).len(), 3);
+    }
+
+    #[test]
+    fn exports_keep_the_memberships() {
+        let mut dao = dao(false);
+        _set_membership(&mut dao, &user(2), Some(Membership::Admin));
+        _set_membership(&mut dao, &user(9), Some(Membership::Banned));
+        let export = build(dao);
+        assert!(
+            export.memberships
+                == vec![
+                    (user(2), Membership::Admin),
+                    (user(3), Membership::Member),
+                    (user(4), Membership::Member),
+                    (user(9), Membership::Banned),
+                ]
+        );
+        assert_eq!(export.dao.member_count, 3);
+    }
+
+    // the comments of a proposal as (content, position of the comment it replies to)
+    fn thread(proposal_id: u64) -> Vec<(String, Option<usize>)> {
+        let comments: Vec<Comment> = _comment_ids(&proposal_id)
+            .iter()
+            .filter_map(_get_comment)
+            .collect();
+        comments
+            .iter()
+            .map(|comment| {
+                let parent = comment
+                    .reply_to
+                    .and_then(|parent_id| comments.iter().position(|other| other.id == parent_id));
+                (comment.content.clone(), parent)
+            })
+            .collect()
+    }
+
+    fn voters(proposal_id: u64) -> Vec<(Principal, bool)> {
+        proposal_votes(proposal_id)
+            .into_iter()
+            .filter_map(|vote| {
+                vote.voter
+                    .map(|voter| (voter, vote.direction == VoteDirection::Up))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn daos_round_trip_through_deletion() {
+        let mut dao = dao(true);
+        let mut proposal = Proposal {
+            title: "Fund the bridge".to_string(),
+            status: ProposalStatus::Approved,
+            created_at: 5,
+            ..(_get_proposal(&10).unwrap())
+        };
+        for (id, reply_to) in [(20, None), (21, Some(20))] {
+            let comment = Comment {
+                id,
+                content: format!("comment {}", id),
+                author: Some(user(2)),
+                proposal_id: 10,
+                created_at: id,
+                reply_to,
+                ..Default::default()
+            };
+            crate::do_insert_comment(&comment);
+            crate::_list_comment(&mut proposal, id);
+        }
+        crate::do_insert_proposal(&mut proposal);
+        let attachment = Attachment {
+            name: "Plan".to_string(),
+            url: "https://example.com/plan.pdf".to_string(),
+            sha256: None,
+        };
+        crate::_set_attachments(&10, vec![attachment]);
+        crate::do_insert_dao(&mut dao);
+        let original_thread = thread(10);
+        let original_voters = voters(10);
+        let original_members = _memberships(1);
+
+        set_caller(user(1));
+        let export = crate::export_dao(1).ok().unwrap();
+        assert!(crate::archive_dao(1).is_ok());
+        assert!(crate::delete_dao(1).is_ok());
+        assert!(_get_proposal(&10).is_none() && _get_comment(&20).is_none());
+
+        set_caller(user(5));
+        let imported = crate::import_dao(export).ok().unwrap();
+        assert!(imported.id != 1 && imported.owner == Some(user(5)));
+        assert!(_memberships(1).is_empty());
+        assert!(_memberships(imported.id) == original_members);
+        assert_eq!(imported.member_count, 3);
+        let ids = _dao_proposal_ids(&imported.id);
+        assert_eq!(ids.len(), 1);
+        assert!(ids[0] != 10);
+        let restored = _get_proposal(&ids[0]).unwrap();
+        assert!(restored.title == "Fund the bridge" && restored.status == ProposalStatus::Approved);
+        assert_eq!(
+            (
+                restored.created_at,
+                restored.upvote_count,
+                restored.downvote_count,
+                restored.comment_count
+            ),
+            (5, 2, 1, 2)
+        );
+        assert!(thread(restored.id) == original_thread);
+        assert_eq!(original_thread[1].1, Some(0));
+        assert!(voters(restored.id) == original_voters);
+        let urls: Vec<String> = _get_attachments(&restored.id)
+            .into_iter()
+            .map(|attachment| attachment.url)
+            .collect();
+        assert_eq!(urls, vec!["https://example.com/plan.pdf".to_string()]);
+    }
+
+    #[test]
+    fn imports_check_the_version_and_the_entity_cap() {
+        let mut export = build(dao(false));
+        export.version = EXPORT_FORMAT_VERSION - 1;
+        assert!(matches!(
+            restore(export.clone(), &user(1)),
+            Err(Error::InvalidInput { .. })
+        ));
+
+        // the dao, its proposal and the proposal's three votes
+        export.version = EXPORT_FORMAT_VERSION;
+        let mut config = CONFIG.with(|config| config.borrow().get().clone());
+        config.max_import_entities = Some(4);
+        CONFIG
+            .with(|cell| cell.borrow_mut().set(config.clone()))
+            .expect("cannot update the config");
+        assert!(matches!(
+            restore(export.clone(), &user(1)),
+            Err(Error::InvalidInput { msg, .. }) if msg.contains("5 entities")
+        ));
+        config.max_import_entities = Some(5);
+        CONFIG
+            .with(|cell| cell.borrow_mut().set(config))
+            .expect("cannot update the config");
+        assert!(restore(export, &user(1)).is_ok());
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{Error, ErrorContext, CONFIG};
+
+// Endpoints that can be turned on and off for the whole canister with set_feature_flag. New
+// endpoints ship behind a flag that is off by default, so they can be deployed before every
+// frontend is ready for them and enabled later
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum Feature {
+    // commit_vote and creating proposals with an anonymous ballot. reveal_vote stays available
+    // so ballots committed before the flag was turned off still count
+    AnonymousBallots,
+    // create_share_link and get_proposal_by_token
+    ShareLinks,
+}
+
+impl Feature {
+    pub(crate) const ALL: [Feature; 2] = [Feature::AnonymousBallots, Feature::ShareLinks];
+
+    // whether the feature is on as long as the admin never set its flag
+    fn enabled_by_default(&self) -> bool {
+        match self {
+            Feature::AnonymousBallots | Feature::ShareLinks => false,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Feature::AnonymousBallots => "anonymous_ballots",
+            Feature::ShareLinks => "share_links",
+        }
+    }
+}
+
+// Version of the candid interface, taken from the package version. Breaking changes bump major,
+// new endpoints and fields bump minor
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ApiVersion {
+    major: u32,
+    minor: u32,
+    patch: u32,
+    // names of the features that are currently enabled
+    features: Vec<String>,
+}
+
+pub(crate) fn api_version() -> ApiVersion {
+    ApiVersion {
+        major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap_or_default(),
+        minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap_or_default(),
+        patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap_or_default(),
+        features: Feature::ALL
+            .iter()
+            .filter(|feature| is_enabled(**feature))
+            .map(|feature| feature.name().to_string())
+            .collect(),
+    }
+}
+
+pub(crate) fn is_enabled(feature: Feature) -> bool {
+    CONFIG
+        .with(|config| {
+            config
+                .borrow()
+                .get()
+                .feature_flags
+                .as_ref()
+                .and_then(|flags| flags.iter().find(|(flag, _)| *flag == feature))
+                .map(|(_, enabled)| *enabled)
+        })
+        .unwrap_or_else(|| feature.enabled_by_default())
+}
+
+// Fail with FeatureDisabled unless `feature` is enabled
+pub(crate) fn check_enabled(feature: Feature) -> Result<(), Error> {
+    if is_enabled(feature) {
+        Ok(())
+    } else {
+        Err(Error::feature_disabled(
+            ErrorContext::None,
+            format!("the {} feature is disabled", feature.name()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::set_caller;
+    use crate::{get_api_version, get_proposal_by_token, set_feature_flag};
+    use candid::Principal;
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    fn make_admin(admin: Principal) {
+        let mut config = CONFIG.with(|config| config.borrow().get().clone());
+        config.admin = Some(admin);
+        CONFIG
+            .with(|cell| cell.borrow_mut().set(config))
+            .expect("cannot update the config");
+    }
+
+    #[test]
+    fn features_are_off_until_the_admin_turns_them_on() {
+        make_admin(user(1));
+        set_caller(user(1));
+        assert!(get_api_version().features.is_empty());
+        assert!(matches!(
+            get_proposal_by_token("unknown".to_string()),
+            Err(Error::FeatureDisabled { .. })
+        ));
+
+        assert!(set_feature_flag(Feature::ShareLinks, true).is_ok());
+        assert_eq!(get_api_version().features, vec!["share_links".to_string()]);
+        assert!(matches!(
+            get_proposal_by_token("unknown".to_string()),
+            Err(Error::NotFound { .. })
+        ));
+
+        assert!(set_feature_flag(Feature::ShareLinks, false).is_ok());
+        assert!(matches!(
+            get_proposal_by_token("unknown".to_string()),
+            Err(Error::FeatureDisabled { .. })
+        ));
+    }
+
+    #[test]
+    fn only_admins_set_feature_flags() {
+        make_admin(user(1));
+        set_caller(user(2));
+        assert!(matches!(
+            set_feature_flag(Feature::ShareLinks, true),
+            Err(Error::Unauthorized { .. })
+        ));
+        assert!(get_api_version().features.is_empty());
+    }
+
+    #[test]
+    fn api_version_is_the_package_version() {
+        let version = get_api_version();
+        assert_eq!(
+            format!("{}.{}.{}", version.major, version.minor, version.patch),
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+}
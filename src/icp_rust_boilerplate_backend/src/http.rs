@@ -0,0 +1,272 @@
+use crate::{images, list_public_daos, DaoSummary, DaoVisibility, MAX_PAGE_SIZE};
+
+// How long clients may cache JSON responses, in seconds
+const JSON_MAX_AGE: u64 = 60;
+// Image ids are never reused, so image bytes can be cached for as long as clients like
+const IMAGE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+type HeaderField = (String, String);
+
+#[derive(candid::CandidType, Clone, Deserialize)]
+pub(crate) struct HttpRequest {
+    method: String,
+    url: String,
+    // part of the http interface, the router doesn't need them
+    #[allow(dead_code)]
+    headers: Vec<HeaderField>,
+    #[allow(dead_code)]
+    body: Vec<u8>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize)]
+pub(crate) struct HttpResponse {
+    status_code: u16,
+    headers: Vec<HeaderField>,
+    body: Vec<u8>,
+}
+
+// Route a read-only request. Only public data is served since requests are anonymous. Private DAOs
+// and their images get a 404 rather than a 403, the same answer as an id that doesn't exist, so
+// that ids can't be probed for private DAOs over HTTP either, see _dao_access
+pub(crate) fn route(request: &HttpRequest) -> HttpResponse {
+    if request.method != "GET" {
+        return error(405, "method not allowed");
+    }
+
+    let (path, query) = match request.url.split_once('?') {
+        Some((path, query)) => (path, query),
+        None => (request.url.as_str(), ""),
+    };
+    let segments: Vec<&str> = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+
+    match segments.as_slice() {
+        ["daos"] => {
+            let offset = query_param(query, "offset").unwrap_or(0);
+            let limit = query_param(query, "limit").unwrap_or(MAX_PAGE_SIZE);
+            json(&list_public_daos(offset, limit))
+        }
+        // private DAOs and their images are answered like ids that don't exist
+        ["daos", id] => match id.parse::<u64>().ok().and_then(|id| crate::_get_dao(&id)) {
+            Some(dao) if crate::_get_settings(&dao.id).visibility == DaoVisibility::Public => {
+                json(&DaoSummary::from(&dao))
+            }
+            _ => error(404, "dao not found"),
+        },
+        ["images", id] => match id.parse::<u64>().ok().and_then(|id| images::get(&id)) {
+            Some(image) => {
+                let is_private = image
+                    .dao_id()
+                    .and_then(|dao_id| crate::_get_dao(&dao_id))
+                    .is_some_and(|dao| {
+                        crate::_get_settings(&dao.id).visibility == DaoVisibility::Private
+                    });
+                if is_private {
+                    return error(404, "image not found");
+                }
+
+                let body = images::read(&image);
+                HttpResponse {
+                    status_code: 200,
+                    headers: vec![
+                        ("Content-Type".to_string(), content_type(&body).to_string()),
+                        ("Content-Length".to_string(), body.len().to_string()),
+                        ("Cache-Control".to_string(), IMAGE_CACHE_CONTROL.to_string()),
+                    ],
+                    body,
+                }
+            }
+            None => error(404, "image not found"),
+        },
+        _ => error(404, "not found"),
+    }
+}
+
+fn json<T: serde::Serialize>(value: &T) -> HttpResponse {
+    let body = serde_json::to_vec(value).expect("summaries are serializable");
+    HttpResponse {
+        status_code: 200,
+        headers: vec![
+            ("Content-Type".to_string(), "application/json".to_string()),
+            ("Content-Length".to_string(), body.len().to_string()),
+            (
+                "Cache-Control".to_string(),
+                format!("public, max-age={}", JSON_MAX_AGE),
+            ),
+        ],
+        body,
+    }
+}
+
+fn error(status_code: u16, message: &str) -> HttpResponse {
+    let body = serde_json::json!({ "error": message })
+        .to_string()
+        .into_bytes();
+    HttpResponse {
+        status_code,
+        headers: vec![
+            ("Content-Type".to_string(), "application/json".to_string()),
+            ("Content-Length".to_string(), body.len().to_string()),
+            ("Cache-Control".to_string(), "no-store".to_string()),
+        ],
+        body,
+    }
+}
+
+fn query_param(query: &str, name: &str) -> Option<u64> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+// Guess the type of an image from its first bytes
+fn content_type(bytes: &[u8]) -> &'static str {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [0xFF, 0xD8, 0xFF, ..] => "image/jpeg",
+        [b'G', b'I', b'F', b'8', ..] => "image/gif",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dao, DaoSettings, ImageRef, DAO_SETTINGS, DAO_STORAGE};
+    use candid::Principal;
+
+    fn get(url: &str) -> HttpResponse {
+        route(&HttpRequest {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        })
+    }
+
+    fn header<'a>(response: &'a HttpResponse, name: &str) -> Option<&'a str> {
+        response
+            .headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // DAO 1 is public and DAO 2 private
+    fn insert_daos() {
+        for (id, visibility) in [(1, DaoVisibility::Public), (2, DaoVisibility::Private)] {
+            let dao = Dao {
+                id,
+                name: format!("Dao {}", id),
+                ..Default::default()
+            };
+            DAO_STORAGE.with(|service| service.borrow_mut().insert(id, dao));
+            let settings = DaoSettings {
+                dao_id: id,
+                visibility,
+                ..Default::default()
+            };
+            DAO_SETTINGS.with(|service| service.borrow_mut().insert(id, settings));
+        }
+    }
+
+    // an image of user 1 with `bytes`, used as the avatar of `dao_id`
+    fn insert_image(upload_id: u64, bytes: &[u8], dao_id: u64) -> images::ImageId {
+        let owner = Principal::from_slice(&[1]);
+        assert!(images::store_chunk(upload_id, 0, bytes.to_vec(), &owner).is_ok());
+        let image_id = images::finalize(upload_id, &owner).ok().unwrap();
+        images::attach(&ImageRef::Image(image_id), dao_id);
+        image_id
+    }
+
+    #[test]
+    fn private_daos_are_answered_like_missing_ones() {
+        insert_daos();
+        let private_image = insert_image(1, &[0xFF, 0xD8, 0xFF, 0xE0], 2);
+
+        assert_eq!(get("/daos/1").status_code, 200);
+        for (private, missing) in [
+            ("/daos/2".to_string(), "/daos/3".to_string()),
+            (
+                format!("/images/{}", private_image),
+                "/images/999".to_string(),
+            ),
+        ] {
+            let (private, missing) = (get(&private), get(&missing));
+            assert_eq!(private.status_code, 404);
+            assert_eq!(private.status_code, missing.status_code);
+            assert_eq!(private.headers, missing.headers);
+            assert_eq!(private.body, missing.body);
+        }
+    }
+
+    #[test]
+    fn serves_public_summaries_as_cacheable_json() {
+        insert_daos();
+
+        let list = get("/daos?offset=0&limit=10");
+        assert_eq!(list.status_code, 200);
+        assert_eq!(header(&list, "Content-Type"), Some("application/json"));
+        assert_eq!(header(&list, "Cache-Control"), Some("public, max-age=60"));
+        let daos: serde_json::Value = serde_json::from_slice(&list.body).unwrap();
+        let names: Vec<&str> = daos
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|dao| dao["name"].as_str())
+            .collect();
+        assert_eq!(names, vec!["Dao 1"]);
+
+        let one: serde_json::Value = serde_json::from_slice(&get("/daos/1").body).unwrap();
+        assert_eq!(one["id"], 1);
+        assert_eq!(get("/daos?offset=1").body, b"[]".to_vec());
+    }
+
+    #[test]
+    fn serves_images_with_their_content_type_unless_their_dao_is_private() {
+        insert_daos();
+        let png = [0x89, b'P', b'N', b'G', 0x0D, 0x0A];
+        let public = insert_image(1, &png, 1);
+        let private = insert_image(2, &[0xFF, 0xD8, 0xFF, 0xE0], 2);
+
+        let response = get(&format!("/images/{}", public));
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body, png.to_vec());
+        assert_eq!(header(&response, "Content-Type"), Some("image/png"));
+        assert_eq!(header(&response, "Content-Length"), Some("6"));
+        assert_eq!(
+            header(&response, "Cache-Control"),
+            Some(IMAGE_CACHE_CONTROL)
+        );
+
+        for url in [
+            format!("/images/{}", private),
+            "/images/999".to_string(),
+            "/images/avatar".to_string(),
+        ] {
+            let response = get(&url);
+            assert_eq!(response.status_code, 404);
+            assert_eq!(header(&response, "Cache-Control"), Some("no-store"));
+        }
+    }
+
+    #[test]
+    fn refuses_writes_and_unknown_routes() {
+        let post = route(&HttpRequest {
+            method: "POST".to_string(),
+            url: "/daos".to_string(),
+            headers: Vec::new(),
+            body: b"{}".to_vec(),
+        });
+        assert_eq!(post.status_code, 405);
+        assert_eq!(get("/proposals/1").status_code, 404);
+        assert_eq!(get("/").status_code, 404);
+        assert_eq!(content_type(b"GIF89a"), "image/gif");
+        assert_eq!(content_type(b"plain"), "application/octet-stream");
+    }
+}
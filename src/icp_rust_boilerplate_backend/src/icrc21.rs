@@ -0,0 +1,610 @@
+// ICRC-21 consent messages. A wallet asks icrc21_canister_call_consent_message what an update
+// call would do before the user signs it, and shows the text it gets back
+use crate::env::caller;
+use crate::{
+    _can_see_proposal, _dao_access, _get_milestones, _get_proposal, _get_settings, messages,
+    DaoAccess, DaoPayload, MembershipAction, Proposal, ProposalKind, ProposalPayload,
+};
+use candid::{Decode, Nat};
+
+const DEFAULT_LANGUAGE: &str = "en";
+// Error code of GenericError when the arguments of a known method don't decode
+const UNDECODABLE_ARGS: u64 = 1;
+
+// Texts of the messages by key and language. {placeholders} are filled in by `text`
+const ENGLISH: [(&str, &str); 11] = [
+    (
+        "create_dao",
+        "Create the DAO \"{name}\". You will be its owner.",
+    ),
+    ("add_proposal", "Submit proposal \"{title}\" in DAO {dao}."),
+    ("requests", "It requests {amount} from the treasury."),
+    (
+        "deposit",
+        "A deposit of {amount} is taken from your account.",
+    ),
+    (
+        "upvote",
+        "Upvote proposal #{number} \"{title}\" in DAO {dao}.",
+    ),
+    (
+        "downvote",
+        "Downvote proposal #{number} \"{title}\" in DAO {dao}.",
+    ),
+    (
+        "execute_proposal",
+        "Execute proposal #{number} \"{title}\" in DAO {dao}.",
+    ),
+    ("pays", "It pays {amount} from the treasury to {recipient}."),
+    ("membership", "It {action} {target} as a member."),
+    ("pay_dues", "Pay {amount} in dues to DAO {dao}."),
+    ("generic", "Call {method} on the DAO canister."),
+];
+const SPANISH: [(&str, &str); 11] = [
+    (
+        "create_dao",
+        "Crear la DAO \"{name}\". Serás su propietario.",
+    ),
+    (
+        "add_proposal",
+        "Enviar la propuesta \"{title}\" en la DAO {dao}.",
+    ),
+    ("requests", "Solicita {amount} de la tesorería."),
+    ("deposit", "Se toma un depósito de {amount} de tu cuenta."),
+    (
+        "upvote",
+        "Votar a favor de la propuesta #{number} \"{title}\" en la DAO {dao}.",
+    ),
+    (
+        "downvote",
+        "Votar en contra de la propuesta #{number} \"{title}\" en la DAO {dao}.",
+    ),
+    (
+        "execute_proposal",
+        "Ejecutar la propuesta #{number} \"{title}\" en la DAO {dao}.",
+    ),
+    ("pays", "Paga {amount} de la tesorería a {recipient}."),
+    ("membership", "{action} a {target} como miembro."),
+    ("pay_dues", "Pagar {amount} de cuotas a la DAO {dao}."),
+    ("generic", "Llamar a {method} en el canister de la DAO."),
+];
+// Words filled into the membership message, by language
+const ADDS: [(&str, &str); 2] = [("en", "adds"), ("es", "Agrega")];
+const REMOVES: [(&str, &str); 2] = [("en", "removes"), ("es", "Elimina")];
+
+#[derive(candid::CandidType, Deserialize)]
+pub(crate) struct ConsentMessageRequest {
+    method: String,
+    arg: Vec<u8>,
+    user_preferences: ConsentMessageSpec,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+pub(crate) struct ConsentMessageSpec {
+    metadata: ConsentMessageMetadata,
+    device_spec: Option<DisplayDevice>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+pub(crate) struct ConsentMessageMetadata {
+    language: String,
+    utc_offset_minutes: Option<i16>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+pub(crate) enum DisplayDevice {
+    GenericDisplay,
+    // a screen showing a few short lines at a time, such as on a hardware wallet
+    LineDisplay {
+        characters_per_line: u16,
+        lines_per_page: u16,
+    },
+}
+
+#[derive(candid::CandidType, Deserialize)]
+pub(crate) struct LineDisplayPage {
+    lines: Vec<String>,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+pub(crate) enum ConsentMessage {
+    GenericDisplayMessage(String),
+    LineDisplayMessage { pages: Vec<LineDisplayPage> },
+}
+
+// The message along with the language it was written in, which is English when the one asked
+// for isn't available
+#[derive(candid::CandidType, Deserialize)]
+pub(crate) struct ConsentInfo {
+    consent_message: ConsentMessage,
+    metadata: ConsentMessageMetadata,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+pub(crate) struct ErrorInfo {
+    description: String,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+pub(crate) enum Icrc21Error {
+    UnsupportedCanisterCall(ErrorInfo),
+    ConsentMessageUnavailable(ErrorInfo),
+    InsufficientPayment(ErrorInfo),
+    GenericError {
+        error_code: Nat,
+        description: String,
+    },
+}
+
+// A standard the canister implements, as listed by icrc10_supported_standards
+#[derive(candid::CandidType, Deserialize)]
+pub(crate) struct SupportedStandard {
+    name: String,
+    url: String,
+}
+
+pub(crate) fn supported_standards() -> Vec<SupportedStandard> {
+    [
+        (
+            "ICRC-10",
+            "https://github.com/dfinity/ICRC/blob/main/ICRCs/ICRC-10/ICRC-10.md",
+        ),
+        (
+            "ICRC-21",
+            "https://github.com/dfinity/ICRC/blob/main/ICRCs/ICRC-21/ICRC-21.md",
+        ),
+    ]
+    .into_iter()
+    .map(|(name, url)| SupportedStandard {
+        name: name.to_string(),
+        url: url.to_string(),
+    })
+    .collect()
+}
+
+// Describe the call in `request` in the language the user prefers, laid out for their device.
+// Methods without a description of their own get a generic one naming the method. Proposals and
+// DAOs the caller can't see are never described, so the message can't reveal them
+pub(crate) fn consent_message(request: ConsentMessageRequest) -> Result<ConsentInfo, Icrc21Error> {
+    let language = language(&request.user_preferences.metadata.language);
+    let text = describe(&request.method, &request.arg, language)?;
+    let consent_message = match request.user_preferences.device_spec {
+        Some(DisplayDevice::LineDisplay {
+            characters_per_line,
+            lines_per_page,
+        }) => ConsentMessage::LineDisplayMessage {
+            pages: paginate(&text, characters_per_line, lines_per_page)?,
+        },
+        _ => ConsentMessage::GenericDisplayMessage(text),
+    };
+    Ok(ConsentInfo {
+        consent_message,
+        metadata: ConsentMessageMetadata {
+            language: language.to_string(),
+            utc_offset_minutes: request.user_preferences.metadata.utc_offset_minutes,
+        },
+    })
+}
+
+// the language of the catalog to write in: the tag asked for, then its language without a
+// region, then English
+fn language(requested: &str) -> &'static str {
+    let locale = messages::normalize_locale(requested).unwrap_or_default();
+    match locale.split('-').next().unwrap_or_default() {
+        "es" => "es",
+        _ => DEFAULT_LANGUAGE,
+    }
+}
+
+fn describe(method: &str, arg: &[u8], language: &str) -> Result<String, Icrc21Error> {
+    let sentences = match method {
+        "create_dao" => {
+            let dao = decode(method, Decode!(arg, DaoPayload))?;
+            vec![text(language, "create_dao", &[("name", dao.name)])]
+        }
+        "add_proposal" => {
+            let payload = decode(method, Decode!(arg, ProposalPayload))?;
+            let dao = dao_name(&payload.dao_id)?;
+            let kind = payload
+                .kind
+                .unwrap_or_else(|| ProposalKind::from_amount(payload.amount_requested));
+            let mut sentences = vec![text(
+                language,
+                "add_proposal",
+                &[("title", payload.title), ("dao", dao)],
+            )];
+            if kind.amount() > 0 {
+                sentences.push(text(
+                    language,
+                    "requests",
+                    &[("amount", amount(kind.amount()))],
+                ));
+            }
+            let deposit = _get_settings(&payload.dao_id)
+                .proposal_deposit
+                .unwrap_or_default();
+            if deposit > 0 {
+                sentences.push(text(language, "deposit", &[("amount", amount(deposit))]));
+            }
+            sentences
+        }
+        "upvote" | "downvote" => {
+            let proposal = proposal(decode(method, Decode!(arg, u64))?)?;
+            vec![proposal_text(language, method, &proposal)?]
+        }
+        "execute_proposal" => {
+            let proposal = proposal(decode(method, Decode!(arg, u64))?)?;
+            let mut sentences = vec![proposal_text(language, method, &proposal)?];
+            match proposal.kind.clone() {
+                // milestones are paid with release_milestone, executing doesn't pay anything
+                ProposalKind::Funding {
+                    amount: paid,
+                    recipient,
+                } if _get_milestones(&proposal.id).is_empty() => {
+                    let recipient = recipient
+                        .or(proposal.owner)
+                        .map(|recipient| recipient.to_text())
+                        .unwrap_or_else(|| "?".to_string());
+                    sentences.push(text(
+                        language,
+                        "pays",
+                        &[("amount", amount(paid)), ("recipient", recipient)],
+                    ));
+                }
+                ProposalKind::MembershipChange { target, action } => {
+                    let words = match action {
+                        MembershipAction::Add => &ADDS,
+                        MembershipAction::Remove => &REMOVES,
+                    };
+                    sentences.push(text(
+                        language,
+                        "membership",
+                        &[
+                            ("action", word(words, language)),
+                            ("target", target.to_text()),
+                        ],
+                    ));
+                }
+                _ => (),
+            }
+            sentences
+        }
+        "pay_dues" => {
+            let dao_id = decode(method, Decode!(arg, u64))?;
+            let dao = dao_name(&dao_id)?;
+            let dues = _get_settings(&dao_id).dues_amount;
+            vec![text(
+                language,
+                "pay_dues",
+                &[("amount", amount(dues)), ("dao", dao)],
+            )]
+        }
+        _ => vec![text(language, "generic", &[("method", method.to_string())])],
+    };
+    Ok(sentences.join(" "))
+}
+
+// the arguments of `method`, or an error telling the wallet they don't match its signature
+fn decode<T>(method: &str, decoded: Result<T, candid::Error>) -> Result<T, Icrc21Error> {
+    decoded.map_err(|e| Icrc21Error::GenericError {
+        error_code: Nat::from(UNDECODABLE_ARGS),
+        description: format!(
+            "the arguments don't match the signature of {}: {}",
+            method, e
+        ),
+    })
+}
+
+fn proposal(id: u64) -> Result<Proposal, Icrc21Error> {
+    _get_proposal(&id)
+        .filter(|proposal| _can_see_proposal(proposal, &caller()))
+        .ok_or_else(|| unavailable(format!("a proposal with id={} not found", id)))
+}
+
+// the name of a DAO the caller can see
+fn dao_name(id: &u64) -> Result<String, Icrc21Error> {
+    match _dao_access(id, &caller()) {
+        DaoAccess::Member(dao, _) => Ok(dao.name),
+        DaoAccess::NotMember => Ok(crate::_get_dao(id).map(|dao| dao.name).unwrap_or_default()),
+        DaoAccess::NotFound => Err(unavailable(format!("a dao with id={} not found", id))),
+    }
+}
+
+fn proposal_text(language: &str, key: &str, proposal: &Proposal) -> Result<String, Icrc21Error> {
+    Ok(text(
+        language,
+        key,
+        &[
+            ("number", proposal.proposal_number.to_string()),
+            ("title", proposal.title.clone()),
+            ("dao", dao_name(&proposal.dao_id)?),
+        ],
+    ))
+}
+
+fn unavailable(description: String) -> Icrc21Error {
+    Icrc21Error::ConsentMessageUnavailable(ErrorInfo { description })
+}
+
+// Amounts are in the smallest unit of the ledger the treasury uses, like every amount the
+// canister takes
+fn amount(value: u64) -> String {
+    format!("{} (ledger base units)", value)
+}
+
+fn text(language: &str, key: &str, values: &[(&str, String)]) -> String {
+    let catalog: &[(&str, &str)] = match language {
+        "es" => &SPANISH,
+        _ => &ENGLISH,
+    };
+    let template = catalog
+        .iter()
+        .chain(ENGLISH.iter())
+        .find(|(entry, _)| *entry == key)
+        .map(|(_, template)| *template)
+        .unwrap_or_default();
+    values
+        .iter()
+        .fold(template.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), value)
+        })
+}
+
+fn word(words: &[(&str, &str)], language: &str) -> String {
+    words
+        .iter()
+        .find(|(entry, _)| *entry == language)
+        .or_else(|| words.first())
+        .map(|(_, word)| word.to_string())
+        .unwrap_or_default()
+}
+
+// Wrap `text` at word boundaries into lines of `characters_per_line`, split words longer than a
+// line, and group the lines into pages of `lines_per_page`
+fn paginate(
+    text: &str,
+    characters_per_line: u16,
+    lines_per_page: u16,
+) -> Result<Vec<LineDisplayPage>, Icrc21Error> {
+    if characters_per_line == 0 || lines_per_page == 0 {
+        return Err(unavailable(
+            "a line display needs at least one character per line and one line per page"
+                .to_string(),
+        ));
+    }
+    let width = characters_per_line as usize;
+    let mut lines: Vec<String> = vec![];
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut chars: Vec<char> = word.chars().collect();
+        while !chars.is_empty() {
+            let used = line.chars().count();
+            let space = usize::from(used > 0);
+            if used + space + chars.len() <= width {
+                if space > 0 {
+                    line.push(' ');
+                }
+                line.extend(chars.drain(..));
+            } else if used > 0 {
+                lines.push(std::mem::take(&mut line));
+            } else {
+                line.extend(chars.drain(..width));
+                lines.push(std::mem::take(&mut line));
+            }
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    Ok(lines
+        .chunks(lines_per_page as usize)
+        .map(|lines| LineDisplayPage {
+            lines: lines.to_vec(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::set_caller;
+    use crate::{
+        _set_membership, Dao, DaoSettings, Membership, ProposalStatus, DAO_SETTINGS, DAO_STORAGE,
+        PROPOSAL_STORAGE,
+    };
+    use candid::{Encode, Principal};
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    // private DAO 1, "Builders Guild", owned by user 1 with user 2 as a member. It asks for dues
+    // and proposal deposits
+    fn insert_dao() {
+        let mut dao = Dao {
+            id: 1,
+            name: "Builders Guild".to_string(),
+            owner: Some(user(1)),
+            ..Default::default()
+        };
+        _set_membership(&mut dao, &user(2), Some(Membership::Member));
+        DAO_STORAGE.with(|service| service.borrow_mut().insert(1, dao));
+        DAO_SETTINGS.with(|service| {
+            service.borrow_mut().insert(
+                1,
+                DaoSettings {
+                    dao_id: 1,
+                    dues_amount: 25,
+                    proposal_deposit: Some(10),
+                    ..Default::default()
+                },
+            )
+        });
+    }
+
+    fn insert_proposal(id: u64, kind: ProposalKind, status: ProposalStatus) {
+        let proposal = Proposal {
+            id,
+            dao_id: 1,
+            proposal_number: 12,
+            title: "Buy new servers".to_string(),
+            owner: Some(user(1)),
+            kind,
+            status,
+            ..Default::default()
+        };
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal));
+    }
+
+    fn request(method: &str, arg: Vec<u8>, language: &str) -> ConsentMessageRequest {
+        ConsentMessageRequest {
+            method: method.to_string(),
+            arg,
+            user_preferences: ConsentMessageSpec {
+                metadata: ConsentMessageMetadata {
+                    language: language.to_string(),
+                    utc_offset_minutes: None,
+                },
+                device_spec: None,
+            },
+        }
+    }
+
+    // the text shown for a call, in the language it was written in
+    fn rendered(method: &str, arg: Vec<u8>, language: &str) -> (String, String) {
+        match consent_message(request(method, arg, language)) {
+            Ok(ConsentInfo {
+                consent_message: ConsentMessage::GenericDisplayMessage(text),
+                metadata,
+            }) => (text, metadata.language),
+            _ => panic!("expected a consent message"),
+        }
+    }
+
+    fn english(method: &str, arg: Vec<u8>) -> String {
+        rendered(method, arg, "en-US").0
+    }
+
+    #[test]
+    fn votes_and_executions_name_the_proposal_and_what_it_pays() {
+        insert_dao();
+        insert_proposal(40, ProposalKind::Signal, ProposalStatus::Open);
+        let recipient = user(9);
+        insert_proposal(
+            41,
+            ProposalKind::Funding {
+                amount: 500,
+                recipient: Some(recipient),
+            },
+            ProposalStatus::Approved,
+        );
+        set_caller(user(2));
+
+        assert_eq!(
+            english("upvote", Encode!(&40u64).unwrap()),
+            "Upvote proposal #12 \"Buy new servers\" in DAO Builders Guild."
+        );
+        assert_eq!(
+            english("downvote", Encode!(&40u64).unwrap()),
+            "Downvote proposal #12 \"Buy new servers\" in DAO Builders Guild."
+        );
+        assert_eq!(
+            english("execute_proposal", Encode!(&41u64).unwrap()),
+            format!(
+                "Execute proposal #12 \"Buy new servers\" in DAO Builders Guild. It pays 500 (ledger base units) from the treasury to {}.",
+                recipient.to_text()
+            )
+        );
+        assert_eq!(
+            english("pay_dues", Encode!(&1u64).unwrap()),
+            "Pay 25 (ledger base units) in dues to DAO Builders Guild."
+        );
+    }
+
+    #[test]
+    fn new_daos_and_proposals_mention_amounts_and_deposits() {
+        insert_dao();
+        set_caller(user(2));
+        let dao = DaoPayload {
+            name: "Makers".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            english("create_dao", Encode!(&dao).unwrap()),
+            "Create the DAO \"Makers\". You will be its owner."
+        );
+        let proposal = ProposalPayload {
+            title: "Buy new servers".to_string(),
+            dao_id: 1,
+            amount_requested: 300,
+            ..Default::default()
+        };
+        assert_eq!(
+            english("add_proposal", Encode!(&proposal).unwrap()),
+            "Submit proposal \"Buy new servers\" in DAO Builders Guild. It requests 300 (ledger base units) from the treasury. A deposit of 10 (ledger base units) is taken from your account."
+        );
+    }
+
+    #[test]
+    fn messages_follow_the_language_and_fall_back_to_english() {
+        insert_dao();
+        insert_proposal(40, ProposalKind::Signal, ProposalStatus::Open);
+        set_caller(user(2));
+        assert_eq!(
+            rendered("upvote", Encode!(&40u64).unwrap(), "es-MX"),
+            (
+                "Votar a favor de la propuesta #12 \"Buy new servers\" en la DAO Builders Guild."
+                    .to_string(),
+                "es".to_string()
+            )
+        );
+        assert_eq!(
+            rendered("leave_dao", Vec::new(), "fr").0,
+            "Call leave_dao on the DAO canister."
+        );
+        assert_eq!(rendered("leave_dao", Vec::new(), "fr").1, DEFAULT_LANGUAGE);
+    }
+
+    #[test]
+    fn bad_arguments_and_unseen_proposals_get_no_message() {
+        insert_dao();
+        insert_proposal(40, ProposalKind::Signal, ProposalStatus::Draft);
+        set_caller(user(2));
+        assert!(matches!(
+            consent_message(request("upvote", Encode!(&"forty").unwrap(), "en")),
+            Err(Icrc21Error::GenericError { .. })
+        ));
+        // someone else's draft is described as if it didn't exist
+        for id in [40u64, 99] {
+            assert!(matches!(
+                consent_message(request("upvote", Encode!(&id).unwrap(), "en")),
+                Err(Icrc21Error::ConsentMessageUnavailable(_))
+            ));
+        }
+        // and so are private DAOs to outsiders
+        set_caller(user(3));
+        assert!(matches!(
+            consent_message(request("pay_dues", Encode!(&1u64).unwrap(), "en")),
+            Err(Icrc21Error::ConsentMessageUnavailable(_))
+        ));
+    }
+
+    #[test]
+    fn line_displays_get_wrapped_pages() {
+        let pages = paginate("Upvote proposal #12 in DAO Builders", 10, 2)
+            .ok()
+            .unwrap();
+        let pages: Vec<Vec<String>> = pages.into_iter().map(|page| page.lines).collect();
+        assert_eq!(
+            pages,
+            vec![
+                vec!["Upvote".to_string(), "proposal".to_string()],
+                vec!["#12 in DAO".to_string(), "Builders".to_string()],
+            ]
+        );
+        // words longer than a line are split
+        let pages = paginate("abcdefghijk", 4, 5).ok().unwrap();
+        assert_eq!(pages[0].lines, vec!["abcd", "efgh", "ijk"]);
+        assert!(paginate("text", 0, 1).is_err());
+    }
+}
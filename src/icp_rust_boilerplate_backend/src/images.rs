@@ -0,0 +1,294 @@
+use crate::env::time;
+use crate::{
+    Error, ErrorContext, ImageRef, ID_COUNTER, IMAGE_CHUNK_STORAGE, IMAGE_STORAGE, UPLOAD_STORAGE,
+};
+use candid::{Decode, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, Storable};
+use std::borrow::Cow;
+
+pub(crate) type ImageId = u64;
+
+// Largest chunk a single upload call can carry
+pub(crate) const MAX_CHUNK_SIZE: usize = 64 * 1024;
+// Largest image that can be stored, across all of its chunks
+pub(crate) const MAX_IMAGE_SIZE: u64 = 512 * 1024;
+
+// An image whose chunks are still being uploaded
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct Upload {
+    image_id: ImageId,
+    owner: Option<Principal>,
+    total_bytes: u64,
+    created_at: u64,
+}
+
+// A finalized image. Its bytes live in IMAGE_CHUNK_STORAGE under (id, 0..chunk_count)
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct Image {
+    id: ImageId,
+    owner: Option<Principal>,
+    dao_id: Option<u64>,
+    chunk_count: u64,
+    total_bytes: u64,
+    created_at: u64,
+}
+
+// Raw bytes of a chunk, stored as is rather than candid encoded
+#[derive(Clone, Default)]
+pub(crate) struct ImageChunk(Vec<u8>);
+
+impl Image {
+    pub(crate) fn dao_id(&self) -> Option<u64> {
+        self.dao_id
+    }
+}
+
+impl Storable for Upload {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for Image {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for ImageChunk {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        ImageChunk(bytes.into_owned())
+    }
+}
+
+impl BoundedStorable for Upload {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl BoundedStorable for Image {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl BoundedStorable for ImageChunk {
+    const MAX_SIZE: u32 = MAX_CHUNK_SIZE as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Store a chunk of an upload, starting the upload on its first chunk. Uploading the same index
+// again replaces the chunk
+pub(crate) fn store_chunk(
+    upload_id: u64,
+    chunk_index: u64,
+    bytes: Vec<u8>,
+    owner: &Principal,
+) -> Result<(), Error> {
+    if bytes.is_empty() || bytes.len() > MAX_CHUNK_SIZE {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            format!("a chunk must be between 1 and {} bytes", MAX_CHUNK_SIZE),
+        ));
+    }
+
+    let mut upload = match UPLOAD_STORAGE.with(|service| service.borrow().get(&upload_id)) {
+        Some(upload) => {
+            if upload.owner != Some(*owner) {
+                return Err(Error::permission_error(
+                    ErrorContext::Upload(upload_id),
+                    format!("upload with id={} belongs to someone else", upload_id),
+                ));
+            }
+            upload
+        }
+        None => {
+            let image_id = ID_COUNTER
+                .with(|counter| {
+                    let current_value = *counter.borrow().get();
+                    counter.borrow_mut().set(current_value + 1)
+                })
+                .expect("cannot increment id counter");
+            Upload {
+                image_id,
+                owner: Some(*owner),
+                total_bytes: 0,
+                created_at: time(),
+            }
+        }
+    };
+
+    let key = (upload.image_id, chunk_index);
+    let replaced_bytes = IMAGE_CHUNK_STORAGE
+        .with(|service| service.borrow().get(&key))
+        .map(|chunk| chunk.0.len() as u64)
+        .unwrap_or_default();
+    let total_bytes = upload.total_bytes - replaced_bytes + bytes.len() as u64;
+    if total_bytes > MAX_IMAGE_SIZE {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            format!("an image can't be larger than {} bytes", MAX_IMAGE_SIZE),
+        ));
+    }
+
+    upload.total_bytes = total_bytes;
+    IMAGE_CHUNK_STORAGE.with(|service| service.borrow_mut().insert(key, ImageChunk(bytes)));
+    UPLOAD_STORAGE.with(|service| service.borrow_mut().insert(upload_id, upload));
+    Ok(())
+}
+
+// Turn a complete upload into an image. Chunks must have been uploaded with indices 0..n
+pub(crate) fn finalize(upload_id: u64, owner: &Principal) -> Result<ImageId, Error> {
+    let upload = match UPLOAD_STORAGE.with(|service| service.borrow().get(&upload_id)) {
+        Some(upload) if upload.owner == Some(*owner) => upload,
+        Some(_) => {
+            return Err(Error::permission_error(
+                ErrorContext::Upload(upload_id),
+                format!("upload with id={} belongs to someone else", upload_id),
+            ))
+        }
+        None => {
+            return Err(Error::not_found(
+                ErrorContext::Upload(upload_id),
+                format!("upload with id={} not found", upload_id),
+            ))
+        }
+    };
+
+    let indices = chunk_indices(upload.image_id);
+    let is_contiguous = indices
+        .iter()
+        .enumerate()
+        .all(|(position, index)| position as u64 == *index);
+    if indices.is_empty() || !is_contiguous {
+        return Err(Error::invalid_input(
+            ErrorContext::Upload(upload_id),
+            format!(
+                "upload with id={} is missing chunks, they must be numbered from 0",
+                upload_id
+            ),
+        ));
+    }
+
+    let image = Image {
+        id: upload.image_id,
+        owner: upload.owner,
+        dao_id: None,
+        chunk_count: indices.len() as u64,
+        total_bytes: upload.total_bytes,
+        created_at: time(),
+    };
+    IMAGE_STORAGE.with(|service| service.borrow_mut().insert(image.id, image));
+    UPLOAD_STORAGE.with(|service| service.borrow_mut().remove(&upload_id));
+    Ok(upload.image_id)
+}
+
+pub(crate) fn get(image_id: &ImageId) -> Option<Image> {
+    IMAGE_STORAGE.with(|service| service.borrow().get(image_id))
+}
+
+// The bytes of an image with all of its chunks put back together
+pub(crate) fn read(image: &Image) -> Vec<u8> {
+    (0..image.chunk_count)
+        .filter_map(|index| get_chunk(image.id, index))
+        .flatten()
+        .collect()
+}
+
+pub(crate) fn get_chunk(image_id: ImageId, chunk_index: u64) -> Option<Vec<u8>> {
+    IMAGE_CHUNK_STORAGE
+        .with(|service| service.borrow().get(&(image_id, chunk_index)))
+        .map(|chunk| chunk.0)
+}
+
+// Check that an avatar can be used by a DAO: uploaded images must belong to the user and
+// not be in use by another DAO
+pub(crate) fn check_avatar(
+    avatar: &ImageRef,
+    user: &Principal,
+    dao_id: Option<u64>,
+) -> Result<(), Error> {
+    match avatar {
+        ImageRef::None => Ok(()),
+        ImageRef::Image(image_id) => match get(image_id) {
+            Some(image) => {
+                if image.owner != Some(*user) || (image.dao_id.is_some() && image.dao_id != dao_id)
+                {
+                    return Err(Error::permission_error(
+                        ErrorContext::Image(*image_id),
+                        format!("image with id={} can't be used here", image_id),
+                    ));
+                }
+                Ok(())
+            }
+            None => Err(Error::not_found(
+                ErrorContext::Image(*image_id),
+                format!("image with id={} not found", image_id),
+            )),
+        },
+        ImageRef::Url(url) => {
+            if !url.starts_with("https://") || url.len() > crate::MAX_AVATAR_URL_LEN {
+                return Err(Error::invalid_input(
+                    ErrorContext::None,
+                    format!(
+                        "avatar url must be an https url of at most {} characters",
+                        crate::MAX_AVATAR_URL_LEN
+                    ),
+                ));
+            }
+            Ok(())
+        }
+    }
+}
+
+// Mark an uploaded image as the avatar of a DAO so it's cleaned up along with it
+pub(crate) fn attach(avatar: &ImageRef, dao_id: u64) {
+    if let ImageRef::Image(image_id) = avatar {
+        if let Some(mut image) = get(image_id) {
+            image.dao_id = Some(dao_id);
+            IMAGE_STORAGE.with(|service| service.borrow_mut().insert(image.id, image));
+        }
+    }
+}
+
+pub(crate) fn delete(image_id: ImageId) {
+    IMAGE_STORAGE.with(|service| service.borrow_mut().remove(&image_id));
+    for index in chunk_indices(image_id) {
+        IMAGE_CHUNK_STORAGE.with(|service| service.borrow_mut().remove(&(image_id, index)));
+    }
+}
+
+// Delete every image attached to a DAO
+pub(crate) fn delete_dao_images(dao_id: u64) {
+    let image_ids: Vec<ImageId> = IMAGE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, image)| image.dao_id == Some(dao_id))
+            .map(|(id, _)| id)
+            .collect()
+    });
+    image_ids.into_iter().for_each(delete);
+}
+
+fn chunk_indices(image_id: ImageId) -> Vec<u64> {
+    IMAGE_CHUNK_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((image_id, 0)..)
+            .take_while(|((id, _), _)| *id == image_id)
+            .map(|((_, index), _)| index)
+            .collect()
+    })
+}
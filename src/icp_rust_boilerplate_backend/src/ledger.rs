@@ -0,0 +1,280 @@
+use crate::{env::canister_id, Error, ErrorContext, CONFIG};
+use candid::{Nat, Principal};
+
+// An ICRC-1 account, the canister's own principal plus a DAO's subaccount for treasuries
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Account {
+    pub(crate) owner: Principal,
+    pub(crate) subaccount: Option<Vec<u8>>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct TransferFromArgs {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct AllowanceArgs {
+    account: Account,
+    spender: Account,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Allowance {
+    allowance: Nat,
+    expires_at: Option<u64>,
+}
+
+// Errors icrc2_transfer_from can return, as defined by the ICRC-2 standard. The errors of
+// icrc1_transfer are the same minus InsufficientAllowance, so they decode into this too
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+// The canister's account holding a DAO's treasury. The subaccount is the DAO id, big endian, in
+// the last 8 of 32 bytes
+pub(crate) fn dao_treasury(dao_id: u64) -> Account {
+    let mut subaccount = vec![0u8; 32];
+    subaccount[24..].copy_from_slice(&dao_id.to_be_bytes());
+    Account {
+        owner: canister_id(),
+        subaccount: Some(subaccount),
+    }
+}
+
+// Move `amount` from `from` into `to` using an allowance `from` gave this canister beforehand.
+// Returns the ledger's block index
+pub(crate) async fn transfer_from(
+    context: ErrorContext,
+    from: Principal,
+    to: Account,
+    amount: u64,
+) -> Result<Nat, Error> {
+    let ledger = ledger_canister(context)?;
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account {
+            owner: from,
+            subaccount: None,
+        },
+        to,
+        amount: Nat::from(amount),
+        fee: None,
+        memo: None,
+        created_at_time: Some(crate::env::time()),
+    };
+    let result: Result<(Result<Nat, TransferFromError>,), _> =
+        ic_cdk::call(ledger, "icrc2_transfer_from", (args,)).await;
+    to_block_index(context, amount, result)
+}
+
+// Move `amount` out of a DAO's treasury into `to`. Returns the ledger's block index
+pub(crate) async fn transfer_from_treasury(
+    context: ErrorContext,
+    dao_id: u64,
+    to: Account,
+    amount: u64,
+) -> Result<Nat, Error> {
+    let ledger = ledger_canister(context)?;
+    let args = TransferArg {
+        from_subaccount: dao_treasury(dao_id).subaccount,
+        to,
+        amount: Nat::from(amount),
+        fee: None,
+        memo: None,
+        created_at_time: Some(crate::env::time()),
+    };
+    let result: Result<(Result<Nat, TransferFromError>,), _> =
+        ic_cdk::call(ledger, "icrc1_transfer", (args,)).await;
+    to_block_index(context, amount, result)
+}
+
+// Fail like transfer_from would when `from` can't pay `amount` plus the ledger fee, either
+// because of their balance or of the allowance they gave this canister. Only calls queries of
+// the ledger, so it can be used from composite queries
+pub(crate) async fn check_can_pay(
+    context: ErrorContext,
+    from: Principal,
+    amount: u64,
+) -> Result<(), Error> {
+    let ledger = ledger_canister(context)?;
+    let account = Account {
+        owner: from,
+        subaccount: None,
+    };
+    let fee = fee(context).await?;
+    let (balance,): (Nat,) = ic_cdk::call(ledger, "icrc1_balance_of", (account.clone(),))
+        .await
+        .map_err(|rejection| rejected(context, rejection))?;
+    let args = AllowanceArgs {
+        account,
+        spender: Account {
+            owner: canister_id(),
+            subaccount: None,
+        },
+    };
+    let (allowance,): (Allowance,) = ic_cdk::call(ledger, "icrc2_allowance", (args,))
+        .await
+        .map_err(|rejection| rejected(context, rejection))?;
+
+    let required = Nat::from(amount) + fee;
+    let expired = allowance
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= crate::env::time());
+    if balance < required {
+        Err(insufficient_balance(context, balance, amount))
+    } else if expired || allowance.allowance < required {
+        Err(insufficient_allowance(context, allowance.allowance, amount))
+    } else {
+        Ok(())
+    }
+}
+
+// Move everything in a DAO's treasury but the ledger fee into another DAO's treasury. Returns the
+// amount moved and the ledger's block index, nothing when the balance doesn't cover the fee. The
+// balance is read right before the transfer, so running this again after an interrupted call only
+// moves what is left
+pub(crate) async fn transfer_treasury(
+    context: ErrorContext,
+    from_dao_id: u64,
+    to_dao_id: u64,
+) -> Result<Option<(u64, Nat)>, Error> {
+    let ledger = ledger_canister(context)?;
+    let fee = fee(context).await?;
+    let (balance,): (Nat,) = ic_cdk::call(ledger, "icrc1_balance_of", (dao_treasury(from_dao_id),))
+        .await
+        .map_err(|rejection| rejected(context, rejection))?;
+    if balance <= fee {
+        return Ok(None);
+    }
+
+    let amount = u64::try_from(&(balance - fee).0).unwrap_or(u64::MAX);
+    let block_index =
+        transfer_from_treasury(context, from_dao_id, dao_treasury(to_dao_id), amount).await?;
+    Ok(Some((amount, block_index)))
+}
+
+// The fee the ledger charges for a transfer, on top of the amount moved
+pub(crate) async fn fee(context: ErrorContext) -> Result<Nat, Error> {
+    let ledger = ledger_canister(context)?;
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|rejection| rejected(context, rejection))?;
+    Ok(fee)
+}
+
+// Whether a ledger is configured, without it there are no treasuries to move
+pub(crate) fn is_configured() -> bool {
+    CONFIG.with(|config| config.borrow().get().ledger_canister_id.is_some())
+}
+
+fn ledger_canister(context: ErrorContext) -> Result<Principal, Error> {
+    match CONFIG.with(|config| config.borrow().get().ledger_canister_id) {
+        Some(ledger) => Ok(ledger),
+        None => Err(Error::ledger_error(
+            context,
+            "no ledger is configured for this canister".to_string(),
+        )),
+    }
+}
+
+fn to_block_index(
+    context: ErrorContext,
+    amount: u64,
+    result: Result<(Result<Nat, TransferFromError>,), (ic_cdk::api::call::RejectionCode, String)>,
+) -> Result<Nat, Error> {
+    match result {
+        Ok((Ok(block_index),)) => Ok(block_index),
+        Ok((Err(TransferFromError::InsufficientFunds { balance }),)) => {
+            Err(insufficient_balance(context, balance, amount))
+        }
+        Ok((Err(TransferFromError::InsufficientAllowance { allowance }),)) => {
+            Err(insufficient_allowance(context, allowance, amount))
+        }
+        Ok((Err(error),)) => Err(Error::ledger_error(context, describe(error))),
+        Err(rejection) => Err(rejected(context, rejection)),
+    }
+}
+
+fn rejected(
+    context: ErrorContext,
+    (code, msg): (ic_cdk::api::call::RejectionCode, String),
+) -> Error {
+    Error::ledger_error(
+        context,
+        format!("couldn't reach the ledger: {:?} {}", code, msg),
+    )
+}
+
+fn insufficient_balance(context: ErrorContext, balance: Nat, amount: u64) -> Error {
+    Error::insufficient_funds(
+        context,
+        format!("a balance of {} is not enough to pay {}", balance, amount),
+    )
+}
+
+fn insufficient_allowance(context: ErrorContext, allowance: Nat, amount: u64) -> Error {
+    Error::insufficient_funds(
+        context,
+        format!(
+            "an allowance of {} is not enough to pay {}, approve this canister first",
+            allowance, amount
+        ),
+    )
+}
+
+fn describe(error: TransferFromError) -> String {
+    match error {
+        TransferFromError::BadFee { expected_fee } => {
+            format!("the ledger expects a fee of {}", expected_fee)
+        }
+        TransferFromError::BadBurn { min_burn_amount } => {
+            format!("the ledger burns at least {}", min_burn_amount)
+        }
+        TransferFromError::InsufficientFunds { balance } => {
+            format!("insufficient funds, the balance is {}", balance)
+        }
+        TransferFromError::InsufficientAllowance { allowance } => {
+            format!("insufficient allowance, the allowance is {}", allowance)
+        }
+        TransferFromError::TooOld => "the transfer is too old".to_string(),
+        TransferFromError::CreatedInFuture { ledger_time } => {
+            format!("the transfer is ahead of the ledger time {}", ledger_time)
+        }
+        TransferFromError::Duplicate { duplicate_of } => {
+            format!("the transfer duplicates block {}", duplicate_of)
+        }
+        TransferFromError::TemporarilyUnavailable => {
+            "the ledger is temporarily unavailable".to_string()
+        }
+        TransferFromError::GenericError {
+            error_code,
+            message,
+        } => format!("the ledger failed with code {}: {}", error_code, message),
+    }
+}
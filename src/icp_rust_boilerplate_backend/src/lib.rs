@@ -1,14 +1,174 @@
 #[macro_use]
 extern crate serde;
 use candid::{Decode, Encode, Principal};
-use ic_cdk::api::{caller, time};
+use env::time;
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Blob;
 use ic_stable_structures::{BoundedStorable, Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
-use std::{borrow::Cow, cell::RefCell};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+};
+
+mod certification;
+mod env;
+mod export;
+mod features;
+mod http;
+mod icrc21;
+mod images;
+mod ledger;
+mod likes;
+mod locks;
+mod maintenance;
+mod merge;
+mod messages;
+mod metrics;
+mod migrations;
+mod previews;
+mod ranked;
+mod rate_limit;
+mod registry;
+mod reputation;
+mod service;
+mod sharding;
+mod storage;
+#[cfg(test)]
+mod tests;
+mod treasury;
+mod webhooks;
 
 // Define custom types for memory and id cell
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 type IdCell = Cell<u64, Memory>;
+type ConfigCell = Cell<CanisterConfig, Memory>;
+type MigrationCell = Cell<migrations::MigrationStatus, Memory>;
+type BucketWasmCell = Cell<sharding::BucketWasm, Memory>;
+// Principals are at most 29 bytes, so they fit in a fixed-capacity blob when used as map keys
+type PrincipalKey = Blob<29>;
+// random part of a share link token
+type ShareTokenKey = Blob<16>;
+// Lowercased display names, which are restricted to ASCII so their length in bytes is bounded
+type DisplayNameKey = Blob<30>;
+// Slugs are ASCII and at most MAX_SLUG_BASE_LEN bytes plus a numbered suffix
+type SlugKey = Blob<64>;
+
+// How long an invite to a private DAO stays valid
+const INVITE_TTL: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+// How long a code from start_link can be used to complete the link
+const LINK_CODE_TTL: u64 = 10 * 60 * 1_000_000_000;
+// Most principals that can be linked to a single primary principal
+const MAX_ALIASES_PER_PRINCIPAL: usize = 5;
+// How long a proposal is open for voting once it is published
+const VOTING_PERIOD: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+// How far ahead voting on a proposal can be scheduled
+const MAX_SCHEDULE_AHEAD: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+// How far back votes and comments count towards a proposal's place in the home feed
+const FEED_ACTIVITY_WINDOW: u64 = 48 * 60 * 60 * 1_000_000_000;
+// How close a deadline has to be for a proposal to be listed as ending soon
+const ENDING_SOON_WINDOW: u64 = 72 * 60 * 60 * 1_000_000_000;
+// Upper bound on the number of items returned by paginated queries
+const MAX_PAGE_SIZE: u64 = 100;
+// Maximum length of the message attached to a join request
+const MAX_JOIN_MESSAGE_LEN: usize = 500;
+// Number of reports after which content is hidden from regular members, unless a DAO sets its own
+const DEFAULT_REPORT_THRESHOLD: u64 = 3;
+// Share of upvotes a proposal needs to pass, unless a DAO sets its own
+const DEFAULT_APPROVAL_THRESHOLD_PERCENT: u8 = 50;
+// Maximum number of notifications sent for a single event on a followed proposal
+const MAX_NOTIFICATION_FANOUT: usize = 500;
+// Largest weight a role's votes can be given
+const MAX_ROLE_VOTE_WEIGHT: u32 = 10;
+// How many proposals a chain of dependencies can go through
+const MAX_DEPENDENCY_DEPTH: usize = 10;
+// Maximum length of a DAO's constitution, in bytes
+const MAX_CONSTITUTION_LEN: usize = 20 * 1024;
+// What a deleted comment shows in place of its content
+const DELETED_COMMENT_CONTENT: &str = "[deleted]";
+// Maximum length of the reason given when reporting content
+const MAX_REPORT_REASON_LEN: usize = 500;
+// What a comment or proposal taken down by a DAO admin shows in place of its content
+const REMOVED_CONTENT: &str = "[removed by a moderator]";
+// Bounds on the name and description of a DAO, so that its record stays within Dao::MAX_SIZE
+const MAX_DAO_NAME_LEN: usize = 64;
+const MAX_DAO_DESCRIPTION_LEN: usize = 500;
+// Same for the title and details of a proposal and Proposal::MAX_SIZE
+const MAX_PROPOSAL_TITLE_LEN: usize = 200;
+const MAX_PROPOSAL_DETAILS_LEN: usize = 2_000;
+// Longest part of a slug taken from a name or title, leaves room for the -N of a collision
+const MAX_SLUG_BASE_LEN: usize = 40;
+// Maximum length of the reason given when taking down content. Kept short since it is stored on
+// the comment or proposal itself
+const MAX_REMOVAL_REASON_LEN: usize = 200;
+// Maximum length of the reason given when banning a user from the whole canister
+const MAX_GLOBAL_BAN_REASON_LEN: usize = 200;
+// Maximum length of the note left with leave_dao. Kept short since it is stored on the audit event
+const MAX_EXIT_NOTE_LEN: usize = 100;
+// Maximum number of observers a DAO can have
+const MAX_OBSERVERS_PER_DAO: usize = 20;
+// Share links of a proposal that can be active at once, and the longest they can be valid for
+const MAX_SHARE_LINKS_PER_PROPOSAL: usize = 10;
+const MAX_SHARE_LINK_TTL_SECONDS: u64 = 30 * 24 * 60 * 60;
+// Maximum number of proposals that can be pinned in a DAO at once
+const MAX_PINNED_PROPOSALS: usize = 3;
+// Bounds on proposal attachments
+const MAX_ATTACHMENTS_PER_PROPOSAL: usize = 5;
+const MAX_ATTACHMENT_NAME_LEN: usize = 100;
+const MAX_ATTACHMENT_URL_LEN: usize = 500;
+// Bounds on the milestones a Funding proposal is paid out in
+const MAX_MILESTONES_PER_PROPOSAL: usize = 10;
+const MAX_MILESTONE_DESCRIPTION_LEN: usize = 200;
+const MAX_MILESTONE_REPORT_LEN: usize = 1_000;
+// Maximum number of notifications kept per user, older ones are evicted first
+const MAX_NOTIFICATIONS_PER_USER: usize = 200;
+// Maximum number of members that can be mentioned in a single comment
+const MAX_MENTIONS_PER_COMMENT: usize = 10;
+// Maximum length of a comment, so that its record stays within Comment::MAX_SIZE
+const MAX_COMMENT_LEN: usize = 1_000;
+// Bounds on profile fields
+const MIN_DISPLAY_NAME_LEN: usize = 3;
+const MAX_DISPLAY_NAME_LEN: usize = 30;
+const MAX_BIO_LEN: usize = 500;
+const MAX_AVATAR_URL_LEN: usize = 300;
+// Number of comments included in a proposal bundle unless the caller asks for another amount
+const DEFAULT_BUNDLE_COMMENTS: u64 = 20;
+// Maximum number of votes that can be cast in a single cast_votes call
+const MAX_VOTES_PER_BATCH: usize = 20;
+// Most co-authors a proposal can have besides its owner
+const MAX_CO_AUTHORS: usize = 5;
+// Members an owner can add in a single add_members_bulk call
+const MAX_MEMBERS_PER_BATCH: usize = 100;
+// Bounds on the length of a search query
+const MIN_SEARCH_QUERY_LEN: usize = 2;
+const MAX_SEARCH_QUERY_LEN: usize = 100;
+// Bounds on the voting period a DAO can set for its proposals, in seconds
+const MIN_VOTING_PERIOD_SECONDS: u64 = 60 * 60;
+const MAX_VOTING_PERIOD_SECONDS: u64 = 30 * 24 * 60 * 60;
+// Longest a DAO can make approved proposals wait before they can be executed, in seconds
+const MAX_TIMELOCK_SECONDS: u64 = 30 * 24 * 60 * 60;
+// How long after the deadline voters on an anonymous ballot have to reveal their votes, unless
+// the DAO configures another window
+const DEFAULT_REVEAL_WINDOW_SECONDS: u64 = 24 * 60 * 60;
+const MAX_REVEAL_WINDOW_SECONDS: u64 = 7 * 24 * 60 * 60;
+// Bounds on the salt hidden in a ballot commitment
+const MIN_BALLOT_SALT_LEN: usize = 16;
+const MAX_BALLOT_SALT_LEN: usize = 64;
+// Most proposals or comments per rate limit window a DAO can allow a member
+const MAX_ACTIONS_PER_WINDOW: u64 = 1_000;
+// Longest window get_dao_activity reports on, longer ones are cut to their most recent part
+const MAX_ACTIVITY_WINDOW: u64 = 90 * 24 * 60 * 60 * 1_000_000_000;
+// Number of proposals listed as the most engaging ones in an activity report
+const ACTIVITY_TOP_PROPOSALS: usize = 3;
+// Shortest bucket get_voting_timeline groups votes into, in seconds
+const MIN_TIMELINE_BUCKET_SECONDS: u64 = 60 * 60;
+// Number of items get_owner_todo lists per category, the rest are only counted
+const TODO_ITEMS_PER_CATEGORY: usize = 20;
+// How soon an invite has to expire for get_owner_todo to list it
+const INVITE_EXPIRY_NOTICE: u64 = 48 * 60 * 60 * 1_000_000_000;
+// Number of most liked comments listed per stance in a stance summary
+const STANCE_TOP_COMMENTS: usize = 3;
 
 // Define structs for Proposal, Dao, and Comment
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
@@ -17,899 +177,12644 @@ struct Proposal {
     dao_id: u64,
     title: String,
     details: String,
-    amount_requested: u64,
+    kind: ProposalKind,
     owner: Option<Principal>,
-    upvotes: Vec<Principal>,
-    downvotes: Vec<Principal>,
+    // the votes themselves are in VOTE_STORAGE, see _record_vote
+    upvote_count: u64,
+    downvote_count: u64,
     is_approved: bool,
     created_at: u64,
-    comments: Vec<u64>,
+    // comments listed in PROPOSAL_COMMENTS, deleted ones included until they are removed
+    comment_count: u64,
     deadline: u64,
     updated_at: Option<u64>,
+    hidden: bool,
+    pinned: bool,
+    comments_locked: bool,
+    status: ProposalStatus,
+    schema_version: u8,
+    // bumped on every write, see do_insert_proposal
+    version: u64,
+    // a proposal in the same DAO that has to be approved before this one can be voted on
+    depends_on: Option<u64>,
+    // voting doesn't start before this time
+    voting_starts_at: Option<u64>,
+    // digest of the ballots the result was computed from, see certification::ballot_digest.
+    // Empty until the proposal is finalized
+    result_digest: Vec<u8>,
+    // the vote counts the result was computed from
+    final_tally: Option<VoteBreakdown>,
+    // latest of when it was created, last voted on and last commented on
+    last_activity_at: u64,
+    // how many different users voted or commented, see PROPOSAL_PARTICIPANTS
+    unique_participants: u32,
+    // when an approved proposal was carried out with execute_proposal
+    executed_at: Option<u64>,
+    // set when a DAO admin took the proposal down with admin_remove_proposal
+    removal: Option<Removal>,
+    // votes are committed with commit_vote and only count once revealed with reveal_vote
+    anonymous_ballot: bool,
+    // derived from the title and unique within the DAO, see get_proposal_by_slug
+    slug: String,
+    // 1 for the first proposal of its DAO, 2 for the next and so on, see get_proposal_by_number
+    proposal_number: u64,
+    // no new comments are taken after this, see ProposalPayload::comment_window_seconds
+    comments_close_at: Option<u64>,
+    // what the proposer staked to create it, see DaoSettings::proposal_deposit
+    deposit: Option<ProposalDeposit>,
+    // stances of the comments that aren't deleted, kept up to date as comments change
+    stance_counts: StanceCounts,
+    // when the vote was ended, see _finalize_vote
+    finalized_at: Option<u64>,
+    // the ledger block a Funding proposal was paid out in by execute_proposal
+    execution_block: Option<candid::Nat>,
+    // why a Cancelled proposal was cancelled. None for proposals cancelled before reasons were
+    // kept and for those taken down by admin_remove_proposal, see removal
+    cancel_reason: Option<CancelReason>,
+    // set when a downvote made the proposal clearly fail before its deadline
+    closed_early: Option<EarlyClosure>,
+    // the instant-runoff count of a finalized RankedChoice proposal
+    ranked_result: Option<ranked::RankedResult>,
 }
 
-#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
-struct Dao {
+// Proposal layout of the first release, with its voters and comments stored on the record. The
+// migration moves these records out of LEGACY_PROPOSAL_STORAGE, see migrations::move_proposals
+#[derive(candid::CandidType, Deserialize)]
+struct BaselineProposal {
     id: u64,
-    name: String,
-    description: String,
-    avatar: String,
+    dao_id: u64,
+    title: String,
+    details: String,
+    amount_requested: u64,
     owner: Option<Principal>,
-    members: Vec<Principal>,
-    proposals: Vec<u64>,
+    upvotes: Vec<Principal>,
+    downvotes: Vec<Principal>,
+    is_approved: bool,
     created_at: u64,
+    comments: Vec<u64>,
+    deadline: u64,
     updated_at: Option<u64>,
 }
 
-#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
-struct Comment {
-    id: u64,
-    content: String,
-    author: Option<Principal>,
-    likes: Vec<Principal>,
-    proposal_id: u64,
-    created_at: u64,
-    updated_at: Option<u64>,
+impl BaselineProposal {
+    // Split the record into the current Proposal and its upvoters, downvoters and comments. The
+    // counts, slug, number and discussion summary are left to the migration
+    fn split(self) -> (Proposal, Vec<Principal>, Vec<Principal>, Vec<u64>) {
+        let proposal = Proposal {
+            id: self.id,
+            dao_id: self.dao_id,
+            title: self.title,
+            details: self.details,
+            // every proposal of the first release was a funding request, even for nothing
+            kind: ProposalKind::from_amount(self.amount_requested),
+            owner: self.owner,
+            is_approved: self.is_approved,
+            created_at: self.created_at,
+            deadline: self.deadline,
+            updated_at: self.updated_at,
+            // only approvals were recorded, the rest are treated as open until they are
+            // finalized again. Votes were only ended after the deadline
+            status: if self.is_approved {
+                ProposalStatus::Approved
+            } else {
+                ProposalStatus::Open
+            },
+            finalized_at: self.is_approved.then_some(self.deadline),
+            last_activity_at: self.created_at,
+            ..Default::default()
+        };
+        (proposal, self.upvotes, self.downvotes, self.comments)
+    }
 }
 
-// Implement Storable trait for Proposal, Dao, and Comment
-impl Storable for Proposal {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+// What a proposal asks for and what executing it once approved does. Funding pays `amount` out of
+// the DAO's treasury to the recipient, the proposer if none is given. Signal proposals only record
+// the DAO's opinion. MembershipChange adds or removes `target`. RankedChoice asks members to rank
+// its options with vote_option instead of voting up or down, see ranked::instant_runoff
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default, PartialEq)]
+enum ProposalKind {
+    Funding {
+        amount: u64,
+        recipient: Option<Principal>,
+    },
+    #[default]
+    Signal,
+    MembershipChange {
+        target: Principal,
+        action: MembershipAction,
+    },
+    RankedChoice {
+        options: Vec<String>,
+    },
+}
+
+impl ProposalKind {
+    // the kind clients that only send amount_requested mean
+    fn from_amount(amount: u64) -> Self {
+        if amount == 0 {
+            ProposalKind::Signal
+        } else {
+            ProposalKind::Funding {
+                amount,
+                recipient: None,
+            }
+        }
     }
 
-    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+    fn amount(&self) -> u64 {
+        match self {
+            ProposalKind::Funding { amount, .. } => *amount,
+            _ => 0,
+        }
     }
 }
 
-impl Storable for Dao {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
-    }
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+enum MembershipAction {
+    Add,
+    Remove,
+}
 
-    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
-    }
+// Where a proposal is in its lifecycle. Open proposals past their deadline are waiting to be
+// finalized with end_proposal_vote. Drafts are only visible to their owner and the DAO admins
+// and have no deadline until they are published. Cancelled proposals are kept, with their votes,
+// but left out of listings. Blocked proposals wait for the proposal they depend on to be
+// approved, their voting starts then. Scheduled proposals open for voting at voting_starts_at.
+// Queued proposals wait for their DAO to have fewer than max_open_proposals open ones
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+enum ProposalStatus {
+    Draft,
+    #[default]
+    Open,
+    Approved,
+    Rejected,
+    Cancelled,
+    Blocked,
+    Scheduled,
+    Queued,
 }
 
-impl Storable for Comment {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
-    }
+// Why a proposal was cancelled
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+enum CancelReason {
+    // its owner withdrew it with cancel_proposal
+    Withdrawn,
+    // it was still open when its DAO was merged into `target_dao_id`, see propose_merge
+    Merge { target_dao_id: u64 },
+    // its owner left the DAO or was removed from it before it was finalized
+    AuthorLeft,
+}
 
-    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
-    }
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+enum DaoVisibility {
+    Public,
+    #[default]
+    Private,
 }
 
-// Implement BoundedStorable trait for Proposal, Dao, and Comment
-impl BoundedStorable for Proposal {
-    const MAX_SIZE: u32 = 1024;
-    const IS_FIXED_SIZE: bool = false;
+// How the caller relates to a DAO, see _dao_access
+enum DaoAccess {
+    // there is no such DAO, or it is private and the caller isn't part of it
+    NotFound,
+    // a public DAO the caller isn't part of
+    NotMember,
+    // a member, or an observer, along with their role
+    Member(Box<Dao>, Role),
 }
 
-impl BoundedStorable for Dao {
-    const MAX_SIZE: u32 = 1024;
-    const IS_FIXED_SIZE: bool = false;
+// What add_proposal takes from a payload that passed _check_new_proposal
+struct CheckedProposal {
+    kind: ProposalKind,
+    attachments: Vec<Attachment>,
+    milestones: Vec<Milestone>,
+    // the proposal it depends on, if any
+    prerequisite: Option<Proposal>,
+    // proposals the caller can create in the DAO per rate_limit::PROPOSAL_WINDOW
+    proposal_limit: usize,
+    co_authors: Vec<Principal>,
 }
 
-impl BoundedStorable for Comment {
-    const MAX_SIZE: u32 = 1024;
-    const IS_FIXED_SIZE: bool = false;
+// What happens to the votes already cast on a proposal when its owner amends it
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+enum AmendmentPolicy {
+    // votes are discarded and voters have to vote again
+    #[default]
+    ResetVotes,
+    // votes stop counting until their voter confirms them with reconfirm_vote
+    RequireReconfirmation,
 }
 
-// Thread-local storage for memory manager, id counter, proposal storage, dao storage, and comment storage
-thread_local! {
-    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
-        MemoryManager::init(DefaultMemoryImpl::default())
-    );
+// Roles in a DAO, ordered from least to most privileged
+#[derive(
+    candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq, PartialOrd,
+)]
+enum Role {
+    // can read a DAO's proposals and comments but not take part, see add_observer
+    Observer,
+    #[default]
+    Member,
+    Admin,
+    Owner,
+}
 
-    static ID_COUNTER: RefCell<IdCell> = RefCell::new(
-        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), 0)
-            .expect("Cannot create a counter")
-    );
+// How many votes a vote cast by each role counts as
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+struct RoleVoteWeights {
+    owner: u32,
+    admin: u32,
+    member: u32,
+}
 
-    static PROPOSAL_STORAGE: RefCell<StableBTreeMap<u64, Proposal, Memory>> =
-        RefCell::new(StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
-    ));
+impl Default for RoleVoteWeights {
+    fn default() -> Self {
+        RoleVoteWeights {
+            owner: 1,
+            admin: 1,
+            member: 1,
+        }
+    }
+}
 
-    static DAO_STORAGE: RefCell<StableBTreeMap<u64, Dao, Memory>> =
-        RefCell::new(StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))
-    ));
+impl RoleVoteWeights {
+    fn weight_of(&self, role: Role) -> u32 {
+        match role {
+            Role::Owner => self.owner,
+            Role::Admin => self.admin,
+            Role::Member => self.member,
+            Role::Observer => 0,
+        }
+    }
+}
 
-    static COMMENT_STORAGE: RefCell<StableBTreeMap<u64, Comment, Memory>> =
-        RefCell::new(StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
-    ));
+// An image shown for a DAO, either uploaded to the canister or hosted elsewhere
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default, PartialEq)]
+enum ImageRef {
+    #[default]
+    None,
+    Image(images::ImageId),
+    Url(String),
 }
 
-// Structs for payload data (ProposalPayload, DaoPayload, CommentPayload)
-#[derive(candid::CandidType, Serialize, Deserialize, Default)]
-struct ProposalPayload {
-    title: String,
-    details: String,
-    amount_requested: u64,
-    dao_id: u64,
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Dao {
+    id: u64,
+    name: String,
+    description: String,
+    avatar: ImageRef,
+    owner: Option<Principal>,
+    // members and admins besides the owner, the memberships themselves are in DAO_MEMBERSHIPS
+    member_count: u64,
+    // the proposals themselves are listed in DAO_PROPOSALS
+    proposal_count: u64,
+    created_at: u64,
+    updated_at: Option<u64>,
+    schema_version: u8,
+    // bumped on every write, see do_insert_dao
+    version: u64,
+    // archived DAOs are read-only and hidden from listings, see archive_dao
+    archived: bool,
+    // derived from the name and unique in the canister, see get_dao_by_slug
+    slug: String,
+    // the number given to the DAO's latest proposal. Numbers of deleted or moved proposals aren't
+    // given out again
+    last_proposal_number: u64,
 }
 
-#[derive(candid::CandidType, Serialize, Deserialize, Default)]
-struct DaoPayload {
+// How a user belongs to a DAO, kept in DAO_MEMBERSHIPS. The owner has no entry
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+enum Membership {
+    Member,
+    Admin,
+    // can read the DAO but not take part in it, see add_observer
+    Observer,
+    Banned,
+}
+
+impl Membership {
+    // admins are members too
+    fn is_member(self) -> bool {
+        matches!(self, Membership::Member | Membership::Admin)
+    }
+}
+
+// Dao layout of the first release, with its members and proposals stored on the record. The
+// migration moves these records out of LEGACY_DAO_STORAGE, see migrations::move_daos
+#[derive(candid::CandidType, Deserialize)]
+struct BaselineDao {
+    id: u64,
     name: String,
     description: String,
     avatar: String,
+    owner: Option<Principal>,
+    members: Vec<Principal>,
+    proposals: Vec<u64>,
+    created_at: u64,
+    updated_at: Option<u64>,
 }
 
-#[derive(candid::CandidType, Serialize, Deserialize, Default)]
-struct CommentPayload {
-    content: String,
-    proposal_id: u64,
+impl BaselineDao {
+    // Split the record into the current Dao, its memberships and its proposals. Owners were listed
+    // as members of their DAO, they get no membership
+    fn split(self) -> (Dao, Vec<(Principal, Membership)>, BTreeSet<u64>) {
+        let memberships: BTreeMap<Principal, Membership> = self
+            .members
+            .into_iter()
+            .filter(|principal| self.owner != Some(*principal))
+            .map(|principal| (principal, Membership::Member))
+            .collect();
+        let proposals: BTreeSet<u64> = self.proposals.into_iter().collect();
+        let dao = Dao {
+            id: self.id,
+            name: self.name,
+            description: self.description,
+            // avatars were free form strings, they are kept as external urls
+            avatar: if self.avatar.is_empty() {
+                ImageRef::None
+            } else {
+                ImageRef::Url(self.avatar)
+            },
+            owner: self.owner,
+            member_count: memberships.len() as u64,
+            proposal_count: proposals.len() as u64,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            ..Default::default()
+        };
+        (dao, memberships.into_iter().collect(), proposals)
+    }
 }
 
-/**
- * -----------------------------------------------------------------------------
- * DAO RELATED FUNCTIONS
- * -----------------------------------------------------------------------------
-**/
-// Ability to get DAOs user is part of
-#[ic_cdk::query]
-fn get_user_daos() -> Result<Vec<Dao>, Error> {
-    let daos_map: Vec<(u64, Dao)> = DAO_STORAGE.with(|service| service.borrow().iter().collect());
-    if daos_map.is_empty() {
-        return Err(Error::NotFound {
-            msg: format!("No dao found. Why don't you try joining or creating one"),
-        });
+// Governance settings of a DAO, kept apart from the Dao in DAO_SETTINGS. Read them through
+// _get_settings, DAOs moved from the first release have none stored and get the defaults
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct DaoSettings {
+    dao_id: u64,
+    // how long proposals stay open for voting, the canister's default when None
+    voting_period_seconds: Option<u64>,
+    // share of the members, in percent, that have to vote for a proposal to pass. 0 turns it off
+    quorum_percent: u8,
+    // share of the votes, in percent, that have to be upvotes for a proposal to pass
+    approval_threshold_percent: u8,
+    visibility: DaoVisibility,
+    // how long after its deadline an approved proposal has to wait before it can be executed
+    timelock_seconds: u64,
+    // charged every dues_period_seconds to stay an active member, 0 turns dues off
+    dues_amount: u64,
+    dues_period_seconds: u64,
+    // how many proposals and comments a member can add per rate limit window, the canister's
+    // default when None
+    max_proposals_per_window: Option<u64>,
+    max_comments_per_window: Option<u64>,
+    updated_at: Option<u64>,
+    // where proposal outcomes are posted, see set_webhook
+    webhook: Option<webhooks::Webhook>,
+    // how long voters on an anonymous ballot have to reveal their votes after the deadline,
+    // DEFAULT_REVEAL_WINDOW_SECONDS when None
+    reveal_window_seconds: Option<u64>,
+    // staked by proposers with add_proposal and paid back, less the ledger fee, once their
+    // proposal reaches quorum. 0 or None turns deposits off
+    proposal_deposit: Option<u64>,
+    // the DAO this one was merged into, see accept_merge. Merged DAOs stay archived
+    merged_into: Option<u64>,
+    // reject proposals as soon as they are clearly failing instead of waiting for their deadline.
+    // There is no early approval, proposals that pass always get their full voting period
+    early_close: Option<EarlyClose>,
+    // how many proposals can be open or scheduled for voting at once. Proposals past that are
+    // rejected, or wait in the queue when created with queue_if_full. 0 or None means no limit
+    max_open_proposals: Option<u32>,
+    // keep the vote counts of open proposals from members until the vote is finalized, so
+    // voters don't follow the running score. The owner and admins still see them unless
+    // hide_tally_from_admins is set too. Both default to false
+    hide_tally_until_closed: Option<bool>,
+    hide_tally_from_admins: Option<bool>,
+    // let the owner see who voted which way, see get_vote_breakdown
+    public_ballots: bool,
+    // who can create proposals, and how long they have to have been a member, see
+    // set_proposal_rules
+    min_membership_age_seconds: u64,
+    proposal_creation_role: Role,
+    // how many reports hide a proposal or comment
+    report_threshold: u64,
+    // stop comments, edits and likes once a proposal's voting is over, see set_auto_lock_comments
+    auto_lock_comments: bool,
+    // what happens to the votes cast on a proposal when it is amended
+    amendment_policy: AmendmentPolicy,
+    // members have to accept the latest constitution before they can vote
+    constitution_required: bool,
+    // when set, votes are weighted by the voter's role at the time they voted
+    role_vote_weights: Option<RoleVoteWeights>,
+}
+
+// A proposal is rejected by the downvote that brings it to at least `min_votes` votes of which at
+// least `reject_ratio_percent` are downvotes. The share is compared exactly, like in service::tally
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+struct EarlyClose {
+    min_votes: u32,
+    reject_ratio_percent: u8,
+}
+
+impl Default for DaoSettings {
+    fn default() -> Self {
+        DaoSettings {
+            dao_id: 0,
+            voting_period_seconds: None,
+            quorum_percent: 0,
+            approval_threshold_percent: DEFAULT_APPROVAL_THRESHOLD_PERCENT,
+            visibility: DaoVisibility::default(),
+            timelock_seconds: 0,
+            dues_amount: 0,
+            dues_period_seconds: 0,
+            max_proposals_per_window: None,
+            max_comments_per_window: None,
+            updated_at: None,
+            webhook: None,
+            reveal_window_seconds: None,
+            proposal_deposit: None,
+            merged_into: None,
+            early_close: None,
+            max_open_proposals: None,
+            hide_tally_until_closed: None,
+            hide_tally_from_admins: None,
+            public_ballots: false,
+            min_membership_age_seconds: 0,
+            proposal_creation_role: Role::Member,
+            report_threshold: DEFAULT_REPORT_THRESHOLD,
+            auto_lock_comments: true,
+            amendment_policy: AmendmentPolicy::ResetVotes,
+            constitution_required: false,
+            role_vote_weights: None,
+        }
     }
+}
 
-    let all_daos: Vec<Dao> = daos_map.into_iter().map(|(_, dao)| dao).collect();
+impl DaoSettings {
+    // in nanoseconds
+    fn reveal_window(&self) -> u64 {
+        self.reveal_window_seconds
+            .unwrap_or(DEFAULT_REVEAL_WINDOW_SECONDS)
+            .saturating_mul(1_000_000_000)
+    }
+}
 
-    let user_daos: Vec<Dao> = all_daos
-        .iter()
-        .filter(|dao| dao.owner == Some(caller()) || dao.members.contains(&caller()))
-        .cloned()
-        .collect();
+// Changes to a DAO's settings, fields left out keep their current value
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct DaoSettingsPatch {
+    voting_period_seconds: Option<u64>,
+    quorum_percent: Option<u8>,
+    approval_threshold_percent: Option<u8>,
+    visibility: Option<DaoVisibility>,
+    timelock_seconds: Option<u64>,
+    dues_amount: Option<u64>,
+    dues_period_seconds: Option<u64>,
+    max_proposals_per_window: Option<u64>,
+    max_comments_per_window: Option<u64>,
+    reveal_window_seconds: Option<u64>,
+    proposal_deposit: Option<u64>,
+    // a min_votes of 0 turns early close off
+    early_close: Option<EarlyClose>,
+    // 0 removes the limit
+    max_open_proposals: Option<u32>,
+    hide_tally_until_closed: Option<bool>,
+    hide_tally_from_admins: Option<bool>,
+}
 
-    Ok(user_daos)
+// Public facing view of a DAO that doesn't expose its members
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct DaoSummary {
+    id: u64,
+    slug: String,
+    name: String,
+    description: String,
+    avatar: ImageRef,
+    member_count: u64,
+    proposal_count: u64,
+    approval_threshold_percent: u8,
 }
 
-// Ability to get a single DAO
-#[ic_cdk::query]
-fn get_dao(id: u64) -> Result<Dao, Error> {
-    let is_user_part_of_dao: Option<bool> = _is_user_part_of_dao(&id);
-
-    match is_user_part_of_dao {
-        Some(_is_true) => match _get_dao(&id) {
-            Some(dao) => Ok(dao),
-            None => Err(Error::NotFound {
-                msg: format!("a dao with id={} not found", id),
-            }),
-        },
-        None => Err(Error::NotAMember {
-            msg: format!("unable to get a dao with id={}. Not a member", id),
-        }),
+impl From<&Dao> for DaoSummary {
+    fn from(dao: &Dao) -> Self {
+        DaoSummary {
+            id: dao.id,
+            slug: dao.slug.clone(),
+            name: dao.name.clone(),
+            description: dao.description.clone(),
+            avatar: dao.avatar.clone(),
+            member_count: dao.member_count,
+            proposal_count: dao.proposal_count,
+            approval_threshold_percent: _get_settings(&dao.id).approval_threshold_percent,
+        }
     }
 }
 
-// Ability to create a DAO
-#[ic_cdk::update]
-fn create_dao(dao: DaoPayload) -> Option<Dao> {
-    let members: Vec<Principal> = Vec::new();
-    let proposals: Vec<u64> = Vec::new();
+// A DAO the caller is part of, along with their role and the DAO's counts
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct MyDaoView {
+    dao: Dao,
+    role: Role,
+    member_count: u64,
+    proposal_count: u64,
+}
 
-    let id = ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("cannot increment id counter");
-    let dao = Dao {
-        id,
-        name: dao.name,
-        description: dao.description,
-        avatar: dao.avatar,
-        owner: Some(caller()),
-        created_at: time(),
-        updated_at: None,
-        members,
-        proposals,
-    };
+// What happened to each principal passed to add_members_bulk
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct BulkResult {
+    added: Vec<Principal>,
+    already_members: Vec<Principal>,
+    banned: Vec<Principal>,
+}
 
-    do_insert_dao(&dao);
-    Some(dao)
+// A DAO the caller owns or is a member of. Inactive members have let their dues lapse
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct MembershipStatus {
+    dao_id: u64,
+    role: Role,
+    is_active: bool,
 }
 
-// Ability to update a DAO providing you're the owner
-#[ic_cdk::update]
-fn update_dao(id: u64, payload: DaoPayload) -> Result<Dao, Error> {
-    match DAO_STORAGE.with(|service| service.borrow().get(&id)) {
-        Some(mut dao) => {
-            if dao.owner.is_some() && dao.owner != Some(caller()) {
-                return Err(Error::PermissionError {
-                    msg: format!("Couldn't update dao with id={}. You are not the owner", id),
-                });
-            }
+// Everything a frontend needs to render the session of the caller in one call
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct WhoamiResponse {
+    principal: Principal,
+    is_anonymous: bool,
+    profile: Option<Profile>,
+    memberships: Vec<MembershipStatus>,
+    unread_notifications: u64,
+}
 
-            dao.name = payload.name;
-            dao.description = payload.description;
-            dao.avatar = payload.avatar;
-            dao.updated_at = Some(time());
+// What a member has done in a DAO, kept up to date as they do it, see _count_activity
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct MemberActivity {
+    proposals_created: u64,
+    votes_cast: u64,
+    comments_made: u64,
+}
 
-            do_insert_dao(&dao);
-            Ok(dao)
-        }
-        None => Err(Error::NotFound {
-            msg: format!("couldn't update a dao with id={}. dao not found", id),
-        }),
+impl MemberActivity {
+    fn total(&self) -> u64 {
+        self.proposals_created + self.votes_cast + self.comments_made
     }
 }
 
-// Ability to delete DAO provided you're the owner
-#[ic_cdk::update]
-fn delete_dao(id: u64) -> Result<Dao, Error> {
-    match DAO_STORAGE.with(|service| service.borrow_mut().remove(&id)) {
-        Some(dao) => {
-            if dao.owner.is_some() && dao.owner != Some(caller()) {
-                return Err(Error::PermissionError {
-                    msg: format!(
-                        "Couldn't delete a dao with id={}. You are not the owner",
-                        id
-                    ),
-                });
-            }
+// An entry of a DAO's member directory
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct MemberEntry {
+    principal: Principal,
+    display_name: Option<String>,
+    role: Role,
+    // None for members that joined before join dates were recorded
+    joined_at: Option<u64>,
+    proposals_created: u64,
+    votes_cast: u64,
+    comments_made: u64,
+}
 
-            dao.proposals.iter().for_each(|proposal_id| {
-                PROPOSAL_STORAGE.with(|service| service.borrow_mut().remove(&proposal_id));
-            });
+// A page of a DAO's member directory
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct MemberPage {
+    items: Vec<MemberEntry>,
+    // the owner and members of the DAO, across all pages
+    total_count: u64,
+}
 
-            Ok(dao)
-        }
-        None => Err(Error::NotFound {
-            msg: format!("Couldn't delete a dao with id={}. dao not found.", id),
-        }),
-    }
+// Order of the member directory
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+enum MemberSort {
+    // who joined first comes first
+    ByJoined,
+    // most proposals, votes and comments first
+    ByActivity,
 }
 
-/**
-* -----------------------------------------------------------------------------
-* PROPOSAL FUNCTIONS (callable if user is part of DAO)
-* -----------------------------------------------------------------------------
-*/
+// Order of the proposals list_proposals returns
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+enum SortBy {
+    // most recently created first
+    Newest,
+    // most recently voted or commented on first, see Proposal.last_activity_at
+    RecentlyActive,
+    // most comments first, deleted ones that still show as such included
+    MostCommented,
+}
 
-// Ability to get a single proposal
-#[ic_cdk::query]
-fn get_proposal(id: u64) -> Result<Proposal, Error> {
-    match _get_proposal(&id) {
-        Some(proposal) => {
-            let is_user_part_of_dao: Option<bool> = _is_user_part_of_dao(&proposal.dao_id);
-            match is_user_part_of_dao {
-                Some(_is_true) => Ok(proposal),
-                None => Err(Error::NotAMember {
-                    msg: format!("unable to get a dao with id={}. Not a member", id),
-                }),
-            }
-        }
-        None => Err(Error::NotFound {
-            msg: format!("a proposal with id={} not found", id),
-        }),
-    }
+// The kind of activity _count_activity records
+#[derive(Clone, Copy)]
+enum ActivityKind {
+    Proposal,
+    Vote,
+    Comment,
 }
 
-// Ability to get all proposals in the DAO
-#[ic_cdk::query]
-fn get_all_proposals(dao_id: u64) -> Result<Vec<Proposal>, Error> {
-    let is_user_part_of_dao: Option<bool> = _is_user_part_of_dao(&dao_id);
-    match is_user_part_of_dao {
-        Some(_is_true) => {
-            let proposals_map: Vec<(u64, Proposal)> =
-                PROPOSAL_STORAGE.with(|service| service.borrow().iter().collect());
-            let length = proposals_map.len();
-            if length == 0 {
-                return Err(Error::NotFound {
-                    msg: format!("No proposals found"),
-                });
-            }
+// A page of a proposal's comments. Pass next_cursor as after_id to get the following page, it is
+// None once there is nothing left
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct CommentPage {
+    items: Vec<CommentView>,
+    next_cursor: Option<u64>,
+    // comments on the proposal the caller can see, across all pages
+    total_count: u64,
+    // previews of the links in the items, see Comment::link_previews
+    link_previews: Vec<previews::LinkPreview>,
+}
 
-            let mut proposals: Vec<Proposal> = Vec::new();
+// A proposal in one of the caller's feeds, with the name of its DAO so the UI can group them
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct FeedItem {
+    proposal: ProposalView,
+    dao_name: String,
+    // votes and comments within FEED_ACTIVITY_WINDOW
+    recent_activity: u64,
+}
 
-            for key in 0..length {
-                let proposal = proposals_map.get(key).unwrap().clone().1;
-                if proposal.dao_id == dao_id {
-                    proposals.push(proposal);
-                } else {
-                    continue;
-                }
-            }
+// Members get the full DAO while non-members of a public DAO only get its summary
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+enum DaoResponse {
+    Full(Dao),
+    Summary(DaoSummary),
+}
 
-            Ok(proposals)
-        }
-        None => Err(Error::NotAMember {
-            msg: format!("unable to get a dao with id={}. Not a member", dao_id),
-        }),
-    }
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+enum AuditAction {
+    #[default]
+    DaoCreated,
+    DaoUpdated,
+    DaoDeleted,
+    DaoImported,
+    MaintenanceRun,
+    VisibilityChanged,
+    BallotsSettingChanged,
+    ProposalRulesChanged,
+    MemberInvited,
+    MemberJoined,
+    AdminChanged,
+    MemberBanned,
+    MemberUnbanned,
+    JoinRequested,
+    JoinRequestApproved,
+    JoinRequestRejected,
+    ProposalCreated,
+    ProposalUpdated,
+    ProposalPublished,
+    ProposalDeleted,
+    VoteCast,
+    ProposalFinalized,
+    CommentCreated,
+    CommentUpdated,
+    CommentLiked,
+    CommentDeleted,
+    CommentPurged,
+    ProposalCancelled,
+    ContentReported,
+    ReportDismissed,
+    ContentRemoved,
+    ProposalPinned,
+    ProposalUnpinned,
+    AttachmentsChanged,
+    CommentsLocked,
+    CommentsUnlocked,
+    AutoLockSettingChanged,
+    AmendmentPolicyChanged,
+    ApprovalThresholdChanged,
+    ConstitutionUpdated,
+    ConstitutionRequirementChanged,
+    DuesChanged,
+    DuesPaid,
+    RoleVoteWeightsChanged,
+    SettingsChanged,
+    WebhookChanged,
+    WebhookDelivered,
+    WebhookFailed,
+    CommentRemoved,
+    ProposalRemoved,
+    DaoArchived,
+    DaoUnarchived,
+    ProposalUnblocked,
+    ProposalOpened,
+    ProposalExecuted,
+    ProposalAmended,
+    VoteReconfirmed,
+    VoteCommitted,
+    MilestoneReported,
+    MilestoneReleased,
+    ObserverAdded,
+    ObserverRemoved,
+    ShareLinkCreated,
+    ShareLinkRevoked,
+    DuplicatesRemoved,
+    DepositRefunded,
+    DepositForfeited,
+    IntegrityRepaired,
+    MergeProposed,
+    MergeAccepted,
+    MergeCompleted,
+    MemberLeft,
+    RankedTieResolved,
+    // stable memory usage crossed 80% or 95% of the soft limit, see storage::is_full
+    StorageAlert,
+    ExecutionLockCleared,
 }
 
-// Ability to get all approved proposals that has ended
-#[ic_cdk::query]
-fn get_final_approved_proposals(dao_id: u64) -> Result<Vec<Proposal>, Error> {
-    let is_user_part_of_dao: Option<bool> = _is_user_part_of_dao(&dao_id);
-    match is_user_part_of_dao {
-        Some(_is_true) => {
-            let proposals_map: Vec<(u64, Proposal)> =
-                PROPOSAL_STORAGE.with(|service| service.borrow().iter().collect());
-            let length = proposals_map.len();
-            if length == 0 {
-                return Err(Error::NotFound {
-                    msg: format!("No proposals found"),
-                });
-            }
+// The entity an audit event is about
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq)]
+enum AuditTarget {
+    Dao(u64),
+    Proposal(u64),
+    Comment {
+        id: u64,
+        proposal_id: u64,
+    },
+    Member(Principal),
+    // a member who left with leave_dao, along with the note they left
+    Departure {
+        member: Principal,
+        note: Option<String>,
+    },
+    JoinRequest(u64),
+    Maintenance(maintenance::MaintenanceReport),
+    Integrity(maintenance::IntegrityReport),
+    // the stable memory usage level that was crossed
+    Storage {
+        percent: u8,
+        used_bytes: u64,
+        soft_limit_bytes: u64,
+    },
+}
 
-            let mut proposals: Vec<Proposal> = Vec::new();
+// A tranche of a Funding proposal. Once the proposal is approved its proposer reports on the
+// work with submit_milestone_report and an admin pays it with release_milestone
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq)]
+struct Milestone {
+    description: String,
+    amount: u64,
+    released: bool,
+    report: Option<String>,
+    reported_at: Option<u64>,
+    released_at: Option<u64>,
+}
 
-            for key in 0..length {
-                let proposal = proposals_map.get(key).unwrap().clone().1;
-                if proposal.is_approved
-                    && proposal.dao_id == dao_id
-                    && is_deadline_not_reaached(proposal.deadline)
-                {
-                    proposals.push(proposal);
-                } else {
-                    continue;
-                }
-            }
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct MilestonePayload {
+    description: String,
+    amount: u64,
+}
 
-            Ok(proposals)
+impl From<MilestonePayload> for Milestone {
+    fn from(milestone: MilestonePayload) -> Self {
+        Milestone {
+            description: milestone.description,
+            amount: milestone.amount,
+            released: false,
+            report: None,
+            reported_at: None,
+            released_at: None,
         }
-        None => Err(Error::NotAMember {
-            msg: format!("unable to get a dao with id={}. Not a member", dao_id),
-        }),
     }
 }
 
-// Ability to create a proposal that can be voted on within a week
-#[ic_cdk::update]
-fn add_proposal(proposal: ProposalPayload) -> Result<Proposal, Error> {
-    let is_user_part_of_dao: Option<bool> = _is_user_part_of_dao(&proposal.dao_id);
-    match is_user_part_of_dao {
-        Some(_is_true) => {
-            let upvotes: Vec<Principal> = Vec::new();
-            let downvotes: Vec<Principal> = Vec::new();
-            let comments: Vec<u64> = Vec::new();
-
+// A document a proposal references, e.g. a budget spreadsheet
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Attachment {
+    name: String,
+    url: String,
+    sha256: Option<String>,
+}
+
+// Content that can be reported to the owner and admins of a DAO
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+enum ReportTarget {
+    Proposal(u64),
+    Comment(u64),
+}
+
+impl Default for ReportTarget {
+    fn default() -> Self {
+        ReportTarget::Proposal(0)
+    }
+}
+
+impl ReportTarget {
+    fn id(&self) -> u64 {
+        match self {
+            ReportTarget::Proposal(id) | ReportTarget::Comment(id) => *id,
+        }
+    }
+}
+
+// What the owner or an admin decides to do about reported content
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+enum ReportAction {
+    Dismiss,
+    Remove,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Report {
+    target: ReportTarget,
+    dao_id: u64,
+    reporter: Option<Principal>,
+    reason: String,
+    created_at: u64,
+}
+
+// Reported content along with every report made against it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ReportedContent {
+    target: ReportTarget,
+    hidden: bool,
+    reports: Vec<Report>,
+}
+
+// An entry of the append-only log of governance actions
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct AuditEvent {
+    id: u64,
+    timestamp: u64,
+    actor: Option<Principal>,
+    dao_id: u64,
+    action: AuditAction,
+    target: AuditTarget,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+enum NotificationKind {
+    #[default]
+    NewComment,
+    NewVote,
+    ProposalFinalized,
+    Invited,
+    Mention,
+    ProposalAmended,
+    AddedToDao,
+    Reply,
+    ContentRemoved,
+    VoteReminder,
+    CoAuthorAdded,
+    // rejected before its deadline, see DaoSettings::early_close
+    ProposalClosedEarly,
+    // the owner of another DAO proposed merging it into yours, see list_merges
+    MergeProposed,
+    // your comment was liked, `count` times since the last one of these
+    CommentLiked,
+    // a queued proposal got a free slot and voting on it started
+    ProposalOpened,
+    // the proposal was cancelled because its owner left the DAO, see CancelReason::AuthorLeft
+    ProposalCancelled,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Notification {
+    id: u64,
+    recipient: Option<Principal>,
+    kind: NotificationKind,
+    dao_id: u64,
+    proposal_id: Option<u64>,
+    comment_id: Option<u64>,
+    created_at: u64,
+    read: bool,
+    // how many events a batched notification stands for
+    count: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Profile {
+    principal: Option<Principal>,
+    display_name: String,
+    bio: String,
+    avatar_url: String,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+// Per-user settings that don't belong on the public profile
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct UserPreferences {
+    // follow a proposal when voting on it
+    auto_follow_on_vote: bool,
+    // get reminded about open proposals you haven't voted on yet, see maintenance::schedule_reminder
+    vote_reminders: bool,
+    // language of error messages, e.g. "es". English when not set, see messages::localize
+    locale: Option<String>,
+}
+
+impl Default for UserPreferences {
+    fn default() -> Self {
+        UserPreferences {
+            auto_follow_on_vote: true,
+            vote_reminders: true,
+            locale: None,
+        }
+    }
+}
+
+// Canister wide settings
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct CanisterConfig {
+    admin: Option<Principal>,
+    // overrides export::DEFAULT_MAX_IMPORT_ENTITIES
+    max_import_entities: Option<u64>,
+    // overrides maintenance::DEFAULT_INTERVAL_SECONDS
+    maintenance_interval_seconds: Option<u64>,
+    // ICRC-2 ledger dues are paid through
+    ledger_canister_id: Option<Principal>,
+    // admins besides `admin`, usually given at install
+    additional_admins: Option<Vec<Principal>>,
+    // overrides VOTING_PERIOD, in seconds
+    voting_period_seconds: Option<u64>,
+    // how many DAOs a single user may own, unlimited when None
+    max_daos_per_user: Option<u64>,
+    // override rate_limit::MAX_PROPOSALS_PER_WINDOW and rate_limit::MAX_COMMENTS_PER_WINDOW
+    max_proposals_per_window: Option<u64>,
+    max_comments_per_window: Option<u64>,
+    // flags set with set_feature_flag, features without one use their default
+    feature_flags: Option<Vec<(features::Feature, bool)>>,
+    // discovery canister public DAOs are announced to, see registry::announce
+    registry_canister: Option<Principal>,
+    // overrides storage::DEFAULT_SOFT_LIMIT_BYTES
+    storage_soft_limit_bytes: Option<u64>,
+    // overrides sharding::DEFAULT_MAX_DAOS_PER_BUCKET
+    max_daos_per_bucket: Option<u64>,
+    // the canister that spawned this one, set on buckets, see sharding
+    main_canister: Option<Principal>,
+}
+
+// Settings the canister is installed or upgraded with, and that the admin can change later with
+// update_config. Anything left out keeps its current value
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct InitArgs {
+    // the first becomes `admin`, the rest `additional_admins`. Left as is when empty
+    admins: Vec<Principal>,
+    voting_period_seconds: Option<u64>,
+    max_daos_per_user: Option<u64>,
+    ledger_canister_id: Option<Principal>,
+    maintenance_interval_seconds: Option<u64>,
+    max_import_entities: Option<u64>,
+    max_proposals_per_window: Option<u64>,
+    max_comments_per_window: Option<u64>,
+    storage_soft_limit_bytes: Option<u64>,
+    max_daos_per_bucket: Option<u64>,
+    // given by the main canister to the buckets it spawns
+    bucket: Option<sharding::BucketInit>,
+}
+
+// Dashboard numbers of a DAO, computed on demand
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct DaoStats {
+    dao_id: u64,
+    member_count: u64,
+    total_proposals: u64,
+    // open, scheduled, queued and blocked proposals, whose vote isn't over yet
+    open_proposals: u64,
+    approved_proposals: u64,
+    rejected_proposals: u64,
+    draft_proposals: u64,
+    cancelled_proposals: u64,
+    total_comments: u64,
+    total_votes_cast: u64,
+    treasury_balance: Option<u64>,
+    most_active_member: Option<Principal>,
+    computed_at: u64,
+}
+
+// What waits for the owner and admins of a DAO, see get_owner_todo. Each category lists its first
+// TODO_ITEMS_PER_CATEGORY items and counts all of them
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct OwnerTodo {
+    dao_id: u64,
+    // open proposals whose deadline passed, oldest first
+    awaiting_finalization: TodoList,
+    // approved proposals that weren't executed yet, whether their timelock ended or not
+    awaiting_execution: TodoList,
+    join_requests: TodoList,
+    // proposals and comments with at least report_threshold reports
+    reported_content: TodoList,
+    // queued proposals in the order they get a slot
+    queued_proposals: TodoList,
+    // invites expiring within 48 hours, soonest first
+    expiring_invites: TodoList,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct TodoList {
+    total: u64,
+    items: Vec<TodoItem>,
+}
+
+// An entry of an OwnerTodo category, with just enough to show it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct TodoItem {
+    // the proposal, join request or reported proposal or comment. None for invites
+    id: Option<u64>,
+    // the requester of a join request or the invitee of an invite
+    principal: Option<Principal>,
+    // the title of the proposal, or of the proposal a reported comment was made on
+    title: Option<String>,
+    // the deadline that passed, when the timelock ends, when the request was made or the proposal
+    // created, when the last report was made, or when the invite expires
+    at: u64,
+}
+
+// What happened in a DAO between `since` (inclusive) and `until` (exclusive), from its audit log
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct DaoActivity {
+    dao_id: u64,
+    since: u64,
+    until: u64,
+    new_proposals: u64,
+    finalized_proposals: Vec<FinalizedProposal>,
+    new_members: u64,
+    new_comments: u64,
+    votes_cast: u64,
+    // most votes and comments received in the window first
+    top_proposals: Vec<ProposalEngagement>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct FinalizedProposal {
+    proposal_id: u64,
+    title: String,
+    status: ProposalStatus,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct ProposalEngagement {
+    proposal_id: u64,
+    title: String,
+    votes: u64,
+    comments: u64,
+}
+
+// The stances taken in a proposal's discussion along with the most liked comments of each
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct StanceSummary {
+    proposal_id: u64,
+    counts: StanceCounts,
+    top_for: Vec<CommentView>,
+    top_against: Vec<CommentView>,
+    top_neutral: Vec<CommentView>,
+}
+
+// A link started with start_link, waiting for the other principal to complete it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct LinkRequest {
+    primary: Principal,
+    // only this principal can complete the link when set
+    secondary_hint: Option<Principal>,
+    expires_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Invite {
+    dao_id: u64,
+    invitee: Option<Principal>,
+    invited_by: Option<Principal>,
+    created_at: u64,
+    expires_at: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Comment {
+    id: u64,
+    content: String,
+    author: Option<Principal>,
+    // who liked a comment lives in COMMENT_LIKES, this only counts them
+    like_count: u64,
+    // only filled while a comment of the first release is moved, see migrations::move_comments
+    likes: Vec<Principal>,
+    proposal_id: u64,
+    created_at: u64,
+    updated_at: Option<u64>,
+    mentions: Vec<Principal>,
+    hidden: bool,
+    schema_version: u8,
+    // deleted comments keep their place in the thread with their content blanked
+    deleted: bool,
+    // the comment on the same proposal this one replies to
+    reply_to: Option<u64>,
+    // set when a DAO admin took the comment down with admin_remove_comment
+    removal: Option<Removal>,
+    // where the author stands on the proposal, counted in Proposal::stance_counts. Cleared when
+    // the comment is deleted
+    stance: Option<Stance>,
+    // previews of the links in the content, see previews::attach
+    link_previews: Vec<u64>,
+}
+
+// Comment layout of the first release, with its likes stored on the record. The migration moves
+// these records out of LEGACY_COMMENT_STORAGE, see migrations::move_comments
+#[derive(candid::CandidType, Deserialize)]
+struct BaselineComment {
+    id: u64,
+    content: String,
+    author: Option<Principal>,
+    likes: Vec<Principal>,
+    proposal_id: u64,
+    created_at: u64,
+    updated_at: Option<u64>,
+}
+
+impl From<BaselineComment> for Comment {
+    fn from(comment: BaselineComment) -> Self {
+        Comment {
+            id: comment.id,
+            content: comment.content,
+            author: comment.author,
+            like_count: comment.likes.len() as u64,
+            likes: comment.likes,
+            proposal_id: comment.proposal_id,
+            created_at: comment.created_at,
+            updated_at: comment.updated_at,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+enum Stance {
+    For,
+    Against,
+    Neutral,
+}
+
+// How many comments on a proposal take each stance
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct StanceCounts {
+    for_count: u64,
+    against_count: u64,
+    neutral_count: u64,
+}
+
+impl StanceCounts {
+    fn count_mut(&mut self, stance: Stance) -> &mut u64 {
+        match stance {
+            Stance::For => &mut self.for_count,
+            Stance::Against => &mut self.against_count,
+            Stance::Neutral => &mut self.neutral_count,
+        }
+    }
+
+    // move a comment from its `previous` stance to its `current` one, either may be None
+    fn apply(&mut self, previous: Option<Stance>, current: Option<Stance>) {
+        if let Some(stance) = previous {
+            let count = self.count_mut(stance);
+            *count = count.saturating_sub(1);
+        }
+        if let Some(stance) = current {
+            *self.count_mut(stance) += 1;
+        }
+    }
+}
+
+// A link letting anyone holding its token read a proposal until it expires, see
+// create_share_link
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ShareLink {
+    token: String,
+    proposal_id: u64,
+    created_by: Principal,
+    created_at: u64,
+    expires_at: u64,
+}
+
+impl Storable for ShareLink {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ShareLink {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// A user the canister admin banned from changing anything on the canister
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct GlobalBan {
+    user: Principal,
+    reason: String,
+    banned_by: Principal,
+    banned_at: u64,
+}
+
+impl Storable for GlobalBan {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for GlobalBan {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Who took down a comment or proposal, when and why
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Removal {
+    removed_by: Principal,
+    reason: String,
+    removed_at: u64,
+}
+
+// Why a proposal was rejected before its deadline
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+struct EarlyClosure {
+    // the rule of its DAO at the time
+    rule: EarlyClose,
+    // the vote totals that met it, weighted when the DAO weights votes
+    upvotes: u64,
+    downvotes: u64,
+    // the deadline was moved to when the proposal was closed
+    original_deadline: u64,
+}
+
+// A deposit a proposer staked with add_proposal, held in the DAO's treasury
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct ProposalDeposit {
+    amount: u64,
+    status: DepositStatus,
+    // when it was paid back or forfeited
+    settled_at: Option<u64>,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+enum DepositStatus {
+    // until the proposal is finalized
+    Held,
+    // the proposal reached quorum but paying the deposit back failed, see claim_deposit_refund
+    RefundPending,
+    Refunded,
+    // the proposal missed quorum, the deposit stays in the treasury
+    Forfeited,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+enum JoinRequestStatus {
+    #[default]
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct JoinRequest {
+    id: u64,
+    dao_id: u64,
+    requester: Option<Principal>,
+    message: String,
+    created_at: u64,
+    status: JoinRequestStatus,
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+enum VoteDirection {
+    #[default]
+    Up,
+    Down,
+}
+
+// One version of the rules text of a DAO
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Constitution {
+    dao_id: u64,
+    version: u64,
+    text: String,
+    author: Option<Principal>,
+    created_at: u64,
+}
+
+// A single vote on a proposal. The proposal only keeps how many votes it got, see _record_vote
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Vote {
+    proposal_id: u64,
+    voter: Option<Principal>,
+    direction: VoteDirection,
+    voted_at: u64,
+    // what the vote counts as in DAOs with role_vote_weights, captured when it was cast so role
+    // changes don't rewrite history. None for votes cast before weights existed, which count as 1
+    weight: Option<u32>,
+    // the ballot of a RankedChoice proposal, option indexes from most to least preferred. The
+    // direction means nothing then
+    ranking: Option<Vec<u8>>,
+}
+
+// A vote on an anonymous ballot before it is revealed. Only the commitment
+// sha256(direction || salt || voter) is known, see certification::ballot_commitment
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct BallotCommitment {
+    voter: Principal,
+    commitment: Vec<u8>,
+    committed_at: u64,
+    revealed: bool,
+}
+
+// Vote counts of a proposal, as cast and as weighted by role when the DAO weights votes
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct VoteBreakdown {
+    proposal_id: u64,
+    upvotes: u64,
+    downvotes: u64,
+    weighted_upvotes: u64,
+    weighted_downvotes: u64,
+}
+
+// The votes cast on a proposal during one stretch of its voting period, see get_voting_timeline
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct TimelineBucket {
+    start: u64,
+    upvotes: u64,
+    downvotes: u64,
+}
+
+// What a voter needs to check that their vote was counted in a proposal's result: their vote as
+// recorded, the digest of all ballots and the totals
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct VoteReceipt {
+    proposal_id: u64,
+    vote: Option<Vote>,
+    result_digest: Vec<u8>,
+    tally: VoteBreakdown,
+}
+
+// A change the owner made to a proposal after voting started, along with what it replaced
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Amendment {
+    proposal_id: u64,
+    amended_by: Option<Principal>,
+    amended_at: u64,
+    previous_title: String,
+    title: String,
+    previous_details: String,
+    details: String,
+    previous_amount_requested: u64,
+    amount_requested: u64,
+    policy: AmendmentPolicy,
+    // votes that were discarded or are waiting to be reconfirmed
+    affected_votes: u64,
+}
+
+// Proposal as returned to clients. Voter identities are replaced by counts and fields that depend
+// on who is asking are computed per call
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct ProposalView {
+    id: u64,
+    dao_id: u64,
+    title: String,
+    details: String,
+    // the amount of Funding proposals, 0 for other kinds
+    amount_requested: u64,
+    kind: ProposalKind,
+    executed_at: Option<u64>,
+    // a treasury payment of the proposal is waiting for the ledger, see locks::acquire
+    execution_in_flight: bool,
+    owner: Option<Principal>,
+    // None while the tally is hidden from the caller, see DaoSettings::hide_tally_until_closed
+    upvote_count: Option<u64>,
+    downvote_count: Option<u64>,
+    // votes cast so far, shown even while the tally is hidden
+    vote_count: u64,
+    is_approved: bool,
+    created_at: u64,
+    comments: Vec<u64>,
+    deadline: u64,
+    updated_at: Option<u64>,
+    caller_has_voted: bool,
+    hidden: bool,
+    pinned: bool,
+    comments_locked: bool,
+    attachments: Vec<Attachment>,
+    milestones: Vec<Milestone>,
+    status: ProposalStatus,
+    version: u64,
+    depends_on: Option<u64>,
+    voting_starts_at: Option<u64>,
+    comment_count: u64,
+    last_activity_at: u64,
+    unique_participants: u32,
+    removal: Option<Removal>,
+    anonymous_ballot: bool,
+    slug: String,
+    proposal_number: u64,
+    comments_close_at: Option<u64>,
+    deposit: Option<ProposalDeposit>,
+    stance_counts: StanceCounts,
+    finalized_at: Option<u64>,
+    execution_block: Option<candid::Nat>,
+    co_authors: Vec<Principal>,
+    cancel_reason: Option<CancelReason>,
+    closed_early: Option<EarlyClosure>,
+    // 1 for the next proposal to open, None unless the proposal is queued
+    queue_position: Option<u64>,
+    ranked_result: Option<ranked::RankedResult>,
+    // until the deadline, rounded down. None once it has passed
+    time_remaining_seconds: Option<u64>,
+    // whether upvote and downvote, or vote_option on a ranked-choice proposal, would accept the
+    // caller's vote right now
+    is_votable_by_caller: bool,
+    caller_is_owner: bool,
+}
+
+impl From<Proposal> for ProposalView {
+    fn from(proposal: Proposal) -> Self {
+        let caller_has_voted = _get_vote_direction(&proposal, &caller()).is_some()
+            || _get_vote(&proposal.id, &caller()).is_some();
+        let comment_count = proposal.comment_count;
+        let now = time();
+        let time_remaining_seconds = (!service::is_deadline_passed(proposal.deadline, now))
+            .then(|| (proposal.deadline - now) / 1_000_000_000);
+        let is_votable_by_caller = _check_not_globally_banned().is_ok()
+            && match proposal.kind {
+                ProposalKind::RankedChoice { .. } => _check_can_rank(&proposal).is_ok(),
+                _ => _check_can_vote_on(&proposal, &caller(), now).is_ok(),
+            };
+        let vote_count = match proposal.kind {
+            ProposalKind::RankedChoice { .. } => _ranked_ballots(&proposal.id, false).len() as u64,
+            _ => proposal.upvote_count + proposal.downvote_count,
+        };
+        let caller_is_owner = proposal.owner == Some(caller());
+        let queue_position =
+            (proposal.status == ProposalStatus::Queued).then(|| _queue_position(&proposal));
+        let tally_visible = !_tally_hidden(&proposal);
+        ProposalView {
+            id: proposal.id,
+            dao_id: proposal.dao_id,
+            title: proposal.title,
+            details: proposal.details,
+            amount_requested: proposal.kind.amount(),
+            kind: proposal.kind,
+            executed_at: proposal.executed_at,
+            execution_in_flight: locks::get(&proposal.id).is_some(),
+            owner: proposal.owner,
+            upvote_count: tally_visible.then_some(proposal.upvote_count),
+            downvote_count: tally_visible.then_some(proposal.downvote_count),
+            vote_count,
+            is_approved: proposal.is_approved,
+            created_at: proposal.created_at,
+            comments: _comment_ids(&proposal.id),
+            deadline: proposal.deadline,
+            updated_at: proposal.updated_at,
+            caller_has_voted,
+            hidden: proposal.hidden,
+            pinned: proposal.pinned,
+            comments_locked: proposal.comments_locked,
+            attachments: _get_attachments(&proposal.id),
+            milestones: _get_milestones(&proposal.id),
+            status: proposal.status,
+            version: proposal.version,
+            depends_on: proposal.depends_on,
+            comment_count,
+            last_activity_at: proposal.last_activity_at,
+            unique_participants: proposal.unique_participants,
+            voting_starts_at: proposal.voting_starts_at,
+            removal: proposal.removal,
+            anonymous_ballot: proposal.anonymous_ballot,
+            slug: proposal.slug,
+            proposal_number: proposal.proposal_number,
+            comments_close_at: proposal.comments_close_at,
+            deposit: proposal.deposit,
+            stance_counts: proposal.stance_counts,
+            finalized_at: proposal.finalized_at,
+            execution_block: proposal.execution_block,
+            co_authors: _get_co_authors(&proposal.id),
+            cancel_reason: proposal.cancel_reason,
+            closed_early: proposal.closed_early,
+            queue_position,
+            ranked_result: proposal.ranked_result,
+            time_remaining_seconds,
+            is_votable_by_caller,
+            caller_is_owner,
+        }
+    }
+}
+
+// A proposal along with what a client needs to check its counts, status and deadline against
+// the canister's certified data
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CertifiedProposal {
+    proposal: ProposalView,
+    certificate: Vec<u8>,
+    witness: Vec<u8>,
+}
+
+// Public facing view of a comment, what comment queries return. Who liked it stays private, only
+// whether the caller did is shown
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct CommentView {
+    id: u64,
+    proposal_id: u64,
+    content: String,
+    author: Option<Principal>,
+    // from the author's profile, None when they have none or left the name empty
+    author_display_name: Option<String>,
+    created_at: u64,
+    updated_at: Option<u64>,
+    // the author changed the content after posting it
+    edited: bool,
+    like_count: u64,
+    liked_by_caller: bool,
+    // replies to this comment, not counting hidden ones
+    reply_count: u64,
+    reply_to: Option<u64>,
+    mentions: Vec<Principal>,
+    hidden: bool,
+    deleted: bool,
+    removal: Option<Removal>,
+    stance: Option<Stance>,
+    link_previews: Vec<u64>,
+}
+
+// A comment along with the previews of its links
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct CommentEntry {
+    comment: CommentView,
+    link_previews: Vec<previews::LinkPreview>,
+}
+
+// Where search_comments looks
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+enum CommentScope {
+    Proposal(u64),
+    // every proposal of the DAO
+    Dao(u64),
+}
+
+// A comment found by search_comments along with the proposal it was made on
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct CommentMatch {
+    proposal_id: u64,
+    proposal_title: String,
+    comment: CommentView,
+}
+
+// Everything needed to render a proposal page, fetched in a single call
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct ProposalBundle {
+    proposal: ProposalView,
+    dao: DaoSummary,
+    // newest first
+    comments: Vec<CommentEntry>,
+    comment_count: u64,
+    my_vote: Option<VoteDirection>,
+}
+
+// Detailed breakdown of who voted which way on a proposal
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct Ballots {
+    proposal_id: u64,
+    upvotes: Vec<Principal>,
+    downvotes: Vec<Principal>,
+}
+
+// A vote of the caller together with the title of the proposal it was cast on
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+struct VoteEntry {
+    proposal_id: u64,
+    proposal_title: String,
+    direction: VoteDirection,
+    voted_at: u64,
+    // set instead of a meaningful direction on ranked-choice proposals
+    ranking: Option<Vec<u8>>,
+}
+
+// Implement Storable trait for Proposal, Dao, and Comment
+impl Storable for Proposal {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for BaselineProposal {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for Dao {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for BaselineDao {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for Membership {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for Comment {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for BaselineComment {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for CanisterConfig {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for AuditEvent {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for Notification {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for Attachment {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for Report {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for Profile {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for UserPreferences {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for Constitution {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for DaoSettings {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for LinkRequest {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for LinkRequest {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for Invite {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// Implement BoundedStorable trait for Proposal, Dao, and Comment
+// Holds the longest title, details and options a proposal can have, see MAX_PROPOSAL_TITLE_LEN
+impl BoundedStorable for Proposal {
+    const MAX_SIZE: u32 = 12288;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl BoundedStorable for BaselineProposal {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Holds the longest name and description a DAO can have, see MAX_DAO_NAME_LEN
+impl BoundedStorable for Dao {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl BoundedStorable for BaselineDao {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl BoundedStorable for Membership {
+    const MAX_SIZE: u32 = 64;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Holds the longest content and removal reason a comment can have, at four bytes per character,
+// plus MAX_MENTIONS_PER_COMMENT mentions, its link previews, the other fields and the candid type
+impl BoundedStorable for Comment {
+    const MAX_SIZE: u32 = 4 * (MAX_COMMENT_LEN + MAX_REMOVAL_REASON_LEN) as u32 + 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl BoundedStorable for BaselineComment {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for JoinRequest {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for Vote {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl Storable for Amendment {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Invite {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl BoundedStorable for JoinRequest {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl BoundedStorable for Vote {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for MemberActivity {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for MemberActivity {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for Milestone {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+// texts are bounded in characters, which take up to 4 bytes each
+impl BoundedStorable for Milestone {
+    const MAX_SIZE: u32 =
+        (MAX_MILESTONE_DESCRIPTION_LEN + MAX_MILESTONE_REPORT_LEN) as u32 * 4 + 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl Storable for BallotCommitment {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for BallotCommitment {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// holds two versions of a proposal's title and details
+impl BoundedStorable for Amendment {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// The candid type of an event alone takes up several hundred bytes, the largest target is a
+// departure note of MAX_EXIT_NOTE_LEN characters of up to four bytes each
+impl BoundedStorable for AuditEvent {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl BoundedStorable for Notification {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl BoundedStorable for Attachment {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl BoundedStorable for Report {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl BoundedStorable for Profile {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// room for MAX_CONSTITUTION_LEN bytes of text plus the other fields
+impl BoundedStorable for Constitution {
+    const MAX_SIZE: u32 = MAX_CONSTITUTION_LEN as u32 + 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// room for a webhook url and secret at their longest plus the other fields
+impl BoundedStorable for DaoSettings {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+impl BoundedStorable for UserPreferences {
+    const MAX_SIZE: u32 = 256;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Thread-local storage for memory manager, id counter, proposal storage, dao storage, and comment storage
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
+        MemoryManager::init(env::stable_memory())
+    );
+
+    static ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), 0)
+            .expect("Cannot create a counter")
+    );
+
+    // Proposals as the first release stored them, the migration moves them to PROPOSAL_STORAGE.
+    // Its records are bounded too tightly to be written again
+    static LEGACY_PROPOSAL_STORAGE: RefCell<StableBTreeMap<u64, BaselineProposal, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
+    ));
+
+    // DAOs as the first release stored them, the migration moves them to DAO_STORAGE. Its
+    // records are bounded too tightly to be written again
+    static LEGACY_DAO_STORAGE: RefCell<StableBTreeMap<u64, BaselineDao, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2)))
+    ));
+
+    // Comments as the first release stored them, the migration moves them to COMMENT_STORAGE.
+    // Its records are bounded too tightly to be written again
+    static LEGACY_COMMENT_STORAGE: RefCell<StableBTreeMap<u64, BaselineComment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3)))
+    ));
+
+    static INVITE_STORAGE: RefCell<StableBTreeMap<(u64, PrincipalKey), Invite, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4)))
+    ));
+
+    static JOIN_REQUEST_STORAGE: RefCell<StableBTreeMap<u64, JoinRequest, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5)))
+    ));
+
+    static VOTE_STORAGE: RefCell<StableBTreeMap<(u64, PrincipalKey), Vote, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6)))
+    ));
+
+    static AUDIT_STORAGE: RefCell<StableBTreeMap<u64, AuditEvent, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8)))
+    ));
+
+    static NOTIFICATION_STORAGE: RefCell<StableBTreeMap<u64, Notification, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9)))
+    ));
+
+    // (recipient, notification id) so a user's notifications can be read without a full scan
+    static NOTIFICATIONS_BY_RECIPIENT: RefCell<StableBTreeMap<(PrincipalKey, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10)))
+    ));
+
+    static PROFILE_STORAGE: RefCell<StableBTreeMap<PrincipalKey, Profile, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)))
+    ));
+
+    // lowercased display name -> owner, keeps display names unique regardless of case
+    static PROFILE_NAME_INDEX: RefCell<StableBTreeMap<DisplayNameKey, PrincipalKey, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12)))
+    ));
+
+    // (dao_id, member) -> reputation points
+    static REPUTATION_STORAGE: RefCell<StableBTreeMap<(u64, PrincipalKey), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)))
+    ));
+
+    // (dao_id, member) -> when the member joined the DAO
+    static MEMBER_JOINED_AT: RefCell<StableBTreeMap<(u64, PrincipalKey), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14)))
+    ));
+
+    // (member, dao or proposal id) -> recent actions, for rate limiting
+    static RATE_LIMIT_STORAGE: RefCell<StableBTreeMap<(PrincipalKey, u64), rate_limit::RateWindow, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15)))
+    ));
+
+    // (reported proposal or comment id, reporter) -> report
+    static REPORT_STORAGE: RefCell<StableBTreeMap<(u64, PrincipalKey), Report, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16)))
+    ));
+
+    // (proposal_id, position) -> attachment, positions are kept contiguous from 0
+    static ATTACHMENT_STORAGE: RefCell<StableBTreeMap<(u64, u64), Attachment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17)))
+    ));
+
+    // upload_id -> image being uploaded
+    static UPLOAD_STORAGE: RefCell<StableBTreeMap<u64, images::Upload, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18)))
+    ));
+
+    static IMAGE_STORAGE: RefCell<StableBTreeMap<images::ImageId, images::Image, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19)))
+    ));
+
+    // (image_id, chunk_index) -> bytes of the chunk
+    static IMAGE_CHUNK_STORAGE: RefCell<StableBTreeMap<(images::ImageId, u64), images::ImageChunk, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20)))
+    ));
+
+    static CONFIG: RefCell<ConfigCell> = RefCell::new(
+        ConfigCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7))),
+            CanisterConfig::default(),
+        )
+        .expect("Cannot create the config cell")
+    );
+
+    // (member, dao_id) -> (), the DAOs each user owns or is a member of
+    static MEMBER_DAOS: RefCell<StableBTreeMap<(PrincipalKey, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21)))
+    ));
+
+    // (proposal_id, position) -> amendment, in the order they were made
+    static AMENDMENT_STORAGE: RefCell<StableBTreeMap<(u64, u64), Amendment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22)))
+    ));
+
+    // (proposal_id, voter) -> votes set aside by an amendment until their voter reconfirms them
+    static PENDING_RECONFIRMATIONS: RefCell<StableBTreeMap<(u64, PrincipalKey), Vote, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23)))
+    ));
+
+    // (proposal_id, follower) -> () for the members notified about a proposal
+    static PROPOSAL_FOLLOWERS: RefCell<StableBTreeMap<(u64, PrincipalKey), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24)))
+    ));
+
+    // (follower, proposal_id) -> (), the reverse of PROPOSAL_FOLLOWERS
+    static FOLLOWED_PROPOSALS: RefCell<StableBTreeMap<(PrincipalKey, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25)))
+    ));
+
+    static PREFERENCES_STORAGE: RefCell<StableBTreeMap<PrincipalKey, UserPreferences, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26)))
+    ));
+
+    // (dao_id, version) -> constitution, versions start at 1
+    static CONSTITUTION_STORAGE: RefCell<StableBTreeMap<(u64, u64), Constitution, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27)))
+    ));
+
+    // (dao_id, member) -> latest constitution version the member accepted
+    static CONSTITUTION_ACCEPTANCES: RefCell<StableBTreeMap<(u64, PrincipalKey), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28)))
+    ));
+
+    // (dao_id, member) -> time until which the member's dues are paid
+    static DUES_PAID_UNTIL: RefCell<StableBTreeMap<(u64, PrincipalKey), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29)))
+    ));
+
+    // (proposal_id, user) -> (), everyone who voted or commented on a proposal
+    static PROPOSAL_PARTICIPANTS: RefCell<StableBTreeMap<(u64, PrincipalKey), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30)))
+    ));
+
+    // dao_id -> settings of the DAO, see _get_settings
+    static DAO_SETTINGS: RefCell<StableBTreeMap<u64, DaoSettings, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31)))
+    ));
+
+    // (comment_id, user) -> (), everyone who liked a comment
+    static COMMENT_LIKES: RefCell<StableBTreeMap<(u64, PrincipalKey), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32)))
+    ));
+
+    // alias -> primary, principals that act as another principal, see caller
+    static ACCOUNT_ALIASES: RefCell<StableBTreeMap<PrincipalKey, PrincipalKey, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33)))
+    ));
+
+    // (primary, alias) -> (), the reverse of ACCOUNT_ALIASES
+    static ALIASES_BY_PRIMARY: RefCell<StableBTreeMap<(PrincipalKey, PrincipalKey), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34)))
+    ));
+
+    // link code -> link waiting to be completed
+    static LINK_REQUESTS: RefCell<StableBTreeMap<u64, LinkRequest, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35)))
+    ));
+
+    // (user, proposal_id) -> (), the proposals each user commented on
+    static THREAD_PARTICIPATION: RefCell<StableBTreeMap<(PrincipalKey, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36)))
+    ));
+
+    // (proposal_id, voter) -> the voter's commitment on an anonymous ballot
+    static BALLOT_COMMITMENTS: RefCell<StableBTreeMap<(u64, PrincipalKey), BallotCommitment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(37)))
+    ));
+
+    // user -> ban, the users banned from the whole canister, see global_ban
+    static GLOBAL_BANS: RefCell<StableBTreeMap<PrincipalKey, GlobalBan, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(38)))
+    ));
+
+    // (proposal_id, position) -> milestone of a Funding proposal
+    static MILESTONE_STORAGE: RefCell<StableBTreeMap<(u64, u64), Milestone, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(39)))
+    ));
+
+    // (proposal_id, random part of the token) -> share link of the proposal
+    static SHARE_LINKS: RefCell<StableBTreeMap<(u64, ShareTokenKey), ShareLink, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(40)))
+    ));
+
+    // proposal_id -> how many voters the deadline reminder went through, see
+    // maintenance::send_reminders
+    static VOTE_REMINDERS: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(41)))
+    ));
+
+    // slug -> DAO, also holds the slugs DAOs had before they were renamed
+    static DAO_SLUGS: RefCell<StableBTreeMap<SlugKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(42)))
+    ));
+
+    // (dao_id, slug) -> proposal, also holds the slugs proposals had before their title changed
+    static PROPOSAL_SLUGS: RefCell<StableBTreeMap<(u64, SlugKey), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(43)))
+    ));
+
+    // (dao_id, member) -> what the member has done in the DAO
+    static MEMBER_ACTIVITY: RefCell<StableBTreeMap<(u64, PrincipalKey), MemberActivity, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(44)))
+    ));
+
+    // preview id -> what the link points at, see previews::attach
+    static LINK_PREVIEWS: RefCell<StableBTreeMap<u64, previews::LinkPreview, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(45)))
+    ));
+
+    // (dao_id, day) -> how many link previews were fetched for the DAO's comments that day
+    static PREVIEW_FETCHES: RefCell<StableBTreeMap<(u64, u64), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(46)))
+    ));
+
+    // (error code, locale) -> message template set by the admin, see messages::localize
+    static MESSAGE_CATALOG: RefCell<StableBTreeMap<(u16, messages::LocaleKey), messages::MessageTemplate, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(47)))
+    ));
+
+    // (proposal_id, co-author) -> () for the members who can edit a proposal with its owner
+    static PROPOSAL_CO_AUTHORS: RefCell<StableBTreeMap<(u64, PrincipalKey), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(48)))
+    ));
+
+    // dao_id -> latest announcement about the DAO the registry canister hasn't received yet
+    static REGISTRY_OUTBOX: RefCell<StableBTreeMap<u64, registry::OutboxEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(49)))
+    ));
+
+    // merge_id -> merge of one DAO into another, see merge::propose
+    static MERGE_STORAGE: RefCell<StableBTreeMap<u64, merge::DaoMerge, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(50)))
+    ));
+
+    // tx_id -> change to a DAO's treasury, see treasury::record
+    static TREASURY_HISTORY: RefCell<StableBTreeMap<u64, treasury::TreasuryTx, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(51)))
+    ));
+
+    // (dao_id, tx_id) -> () for the treasury transactions of each DAO
+    static TREASURY_INDEX: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(52)))
+    ));
+
+    // (comment_id, author) -> likes the author hasn't been told about yet, see likes::liked
+    static PENDING_LIKES: RefCell<StableBTreeMap<(u64, PrincipalKey), likes::PendingLikes, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(53)))
+    ));
+
+    // (dao_id, proposal_number) -> proposal, see get_proposal_by_number
+    static PROPOSAL_NUMBERS: RefCell<StableBTreeMap<(u64, u64), u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(54)))
+    ));
+
+    // (dao_id, proposal_id) of the proposals waiting for a free slot, see
+    // DaoSettings::max_open_proposals. Ids only grow, so each DAO's entries are in FIFO order
+    static PROPOSAL_QUEUE: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(55)))
+    ));
+
+    // (dao_id, request_id) -> () for the pending join requests of each DAO, see
+    // do_insert_join_request
+    static PENDING_JOIN_REQUESTS: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(56)))
+    ));
+
+    // (dao_id, reported proposal or comment id) -> () for the content of each DAO that has reports
+    static REPORTED_CONTENT: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(57)))
+    ));
+
+    // proposal_id -> treasury payment of the proposal waiting for the ledger, see locks::acquire
+    static EXECUTION_LOCKS: RefCell<StableBTreeMap<u64, locks::ExecutionLock, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(58)))
+    ));
+
+    // progress of the storage migration, see migrations::start
+    static MIGRATION_STATE: RefCell<MigrationCell> = RefCell::new(
+        MigrationCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(59))),
+            migrations::MigrationStatus::default(),
+        )
+        .expect("Cannot create the migration cell")
+    );
+
+    static DAO_STORAGE: RefCell<StableBTreeMap<u64, Dao, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(61)))
+    ));
+
+    // (dao_id, user) -> how the user belongs to the DAO, see _set_membership
+    static DAO_MEMBERSHIPS: RefCell<StableBTreeMap<(u64, PrincipalKey), Membership, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(62)))
+    ));
+
+    // (dao_id, proposal_id) -> (), the proposals of each DAO, see _list_dao_proposal
+    static DAO_PROPOSALS: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(63)))
+    ));
+
+    static PROPOSAL_STORAGE: RefCell<StableBTreeMap<u64, Proposal, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(64)))
+    ));
+
+    // (proposal_id, comment_id) -> (), the comments of each proposal, see _list_comment
+    static PROPOSAL_COMMENTS: RefCell<StableBTreeMap<(u64, u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(65)))
+    ));
+
+    static COMMENT_STORAGE: RefCell<StableBTreeMap<u64, Comment, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(66)))
+    ));
+
+    // the wasm buckets are installed with, see upload_bucket_wasm
+    static BUCKET_WASM: RefCell<BucketWasmCell> = RefCell::new(
+        BucketWasmCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(67))),
+            sharding::BucketWasm::default(),
+        )
+        .expect("Cannot create the bucket wasm cell")
+    );
+
+    // index -> bucket, the buckets this canister spawned in the order it did
+    static BUCKETS: RefCell<StableBTreeMap<u64, sharding::Bucket, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(68)))
+    ));
+
+    // dao_id -> index of the bucket the DAO was placed in, see sharding::place
+    static DAO_ROUTES: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(69)))
+    ));
+
+    // proposal_id -> dao_id of the proposals created in a bucket
+    static ROUTED_PROPOSALS: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(70)))
+    ));
+}
+
+// Structs for payload data (ProposalPayload, DaoPayload, CommentPayload)
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct ProposalPayload {
+    title: String,
+    details: String,
+    amount_requested: u64,
+    dao_id: u64,
+    // what the proposal asks for. When left out it is a Funding proposal for amount_requested, or
+    // a Signal proposal if that is 0
+    kind: Option<ProposalKind>,
+    // replaces the attachments of the proposal when set
+    attachments: Option<Vec<Attachment>>,
+    // when false the proposal is created as a draft, defaults to true. Ignored by updates
+    publish: Option<bool>,
+    // id of a proposal in the same DAO that has to pass first. Ignored by updates
+    depends_on: Option<u64>,
+    // when voting opens, at most 30 days from now. Ignored by updates
+    voting_starts_at: Option<u64>,
+    // hide who voted what until voting is over, see commit_vote. Ignored by updates
+    anonymous_ballot: Option<bool>,
+    // pay a Funding proposal out in tranches adding up to its amount. Replaces the milestones of
+    // the proposal when set
+    milestones: Option<Vec<MilestonePayload>>,
+    // only take comments for this long after the proposal is created, at most the voting
+    // period. Ignored by updates
+    comment_window_seconds: Option<u64>,
+    // members who can edit the proposal along with its owner. Replaces the co-authors when set,
+    // which only the owner can do
+    co_authors: Option<Vec<Principal>>,
+    // wait in the queue instead of failing when the DAO already has max_open_proposals open.
+    // Ignored by updates
+    queue_if_full: Option<bool>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct DaoPayload {
+    name: String,
+    description: String,
+    avatar: ImageRef,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct CommentPayload {
+    content: String,
+    proposal_id: u64,
+    // the comment this one replies to, its author is notified. Ignored by updates
+    reply_to: Option<u64>,
+    // updates keep the current stance when None
+    stance: Option<Stance>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+struct ProfilePayload {
+    display_name: String,
+    bio: String,
+    avatar_url: String,
+}
+
+/**
+ * -----------------------------------------------------------------------------
+ * DAO RELATED FUNCTIONS
+ * -----------------------------------------------------------------------------
+**/
+// Ability to get DAOs user is part of
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_user_daos() -> Result<Vec<MyDaoView>, Error> {
+    let user_daos: Vec<MyDaoView> = _get_member_dao_ids(&caller())
+        .iter()
+        .filter_map(_get_dao)
+        .map(|dao| MyDaoView {
+            role: _get_member_role(&dao, &caller()),
+            member_count: dao.member_count,
+            proposal_count: dao.proposal_count,
+            dao,
+        })
+        .collect();
+
+    Ok(user_daos)
+}
+
+// Ability to get a single DAO. Non-members only get the summary of a public DAO
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_dao(id: u64) -> Result<DaoResponse, Error> {
+    match _can_read_dao(&id) {
+        Ok(dao) => Ok(DaoResponse::Full(dao)),
+        Err(error) => match _get_dao(&id) {
+            Some(dao) if _get_settings(&id).visibility == DaoVisibility::Public => {
+                Ok(DaoResponse::Summary(DaoSummary::from(&dao)))
+            }
+            _ => Err(error),
+        },
+    }
+}
+
+// Ability to get a DAO by its slug, or a slug it had before it was renamed. Works like get_dao
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_dao_by_slug(slug: String) -> Result<DaoResponse, Error> {
+    match _slug_key(&slug).and_then(|key| DAO_SLUGS.with(|service| service.borrow().get(&key))) {
+        Some(id) => get_dao(id),
+        None => Err(Error::not_found(
+            ErrorContext::None,
+            format!("a dao with slug={} not found", slug),
+        )),
+    }
+}
+
+// Ability to get the statistics of a DAO you're part of. Everything comes from the DAO's
+// proposal list and the counters kept on its proposals and members, not from its votes and comments
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_dao_stats(dao_id: u64) -> Result<DaoStats, Error> {
+    match _can_read_dao(&dao_id) {
+        Ok(dao) => {
+            let mut stats = DaoStats {
+                dao_id,
+                member_count: dao.member_count,
+                computed_at: time(),
+                ..Default::default()
+            };
+
+            for proposal in _dao_proposal_ids(&dao.id).iter().filter_map(_get_proposal) {
+                stats.total_proposals += 1;
+                match proposal.status {
+                    ProposalStatus::Open
+                    | ProposalStatus::Scheduled
+                    | ProposalStatus::Queued
+                    | ProposalStatus::Blocked => stats.open_proposals += 1,
+                    ProposalStatus::Approved => stats.approved_proposals += 1,
+                    ProposalStatus::Rejected => stats.rejected_proposals += 1,
+                    ProposalStatus::Draft => stats.draft_proposals += 1,
+                    ProposalStatus::Cancelled => stats.cancelled_proposals += 1,
+                }
+                stats.total_comments += proposal.comment_count;
+                stats.total_votes_cast += match proposal.kind {
+                    ProposalKind::RankedChoice { .. } => {
+                        _ranked_ballots(&proposal.id, false).len() as u64
+                    }
+                    _ => proposal.upvote_count + proposal.downvote_count,
+                };
+            }
+
+            // proposals, comments and votes made by each principal, see _count_activity
+            stats.most_active_member = MEMBER_ACTIVITY.with(|service| {
+                service
+                    .borrow()
+                    .range((dao_id, PrincipalKey::default())..)
+                    .take_while(|((id, _), _)| *id == dao_id)
+                    .map(|((_, member), activity)| {
+                        (Principal::from_slice(member.as_slice()), activity.total())
+                    })
+                    .filter(|(_, total)| *total > 0)
+                    .max_by(|(a_user, a_count), (b_user, b_count)| {
+                        a_count.cmp(b_count).then(b_user.cmp(a_user))
+                    })
+                    .map(|(user, _)| user)
+            });
+
+            Ok(stats)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+// Ability to list the owner and members of a DAO you're a member of, with their role, when they
+// joined and what they have done in it
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_dao_members(
+    dao_id: u64,
+    offset: u64,
+    limit: u64,
+    sort: MemberSort,
+) -> Result<MemberPage, Error> {
+    let dao = _is_user_part_of_dao(&dao_id)?;
+    let mut participants = _dao_participants(&dao);
+    service::dedupe_principals(&mut participants);
+
+    let mut entries: Vec<(MemberEntry, u64)> = participants
+        .into_iter()
+        .map(|member| {
+            let activity = _get_member_activity(&dao_id, &member);
+            let entry = MemberEntry {
+                principal: member,
+                display_name: _get_profile(&member)
+                    .map(|profile| profile.display_name)
+                    .filter(|name| !name.is_empty()),
+                role: _get_member_role(&dao, &member),
+                joined_at: _joined_at(&dao, &member),
+                proposals_created: activity.proposals_created,
+                votes_cast: activity.votes_cast,
+                comments_made: activity.comments_made,
+            };
+            (entry, activity.total())
+        })
+        .collect();
+    match sort {
+        MemberSort::ByJoined => {
+            entries.sort_by_key(|(entry, _)| entry.joined_at.unwrap_or_default())
+        }
+        MemberSort::ByActivity => entries.sort_by(|(a, a_total), (b, b_total)| {
+            b_total.cmp(a_total).then(
+                a.joined_at
+                    .unwrap_or_default()
+                    .cmp(&b.joined_at.unwrap_or_default()),
+            )
+        }),
+    }
+
+    Ok(MemberPage {
+        total_count: entries.len() as u64,
+        items: entries
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .map(|(entry, _)| entry)
+            .collect(),
+    })
+}
+
+// Ability to list the admins, members, observers or banned users of a DAO you can read, a page at
+// a time in the order of their principals. Members are listed without the admins and the owner
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_dao_memberships(
+    dao_id: u64,
+    membership: Membership,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<Principal>, Error> {
+    _can_read_dao(&dao_id)?;
+    Ok(_memberships(dao_id)
+        .into_iter()
+        .filter(|(_, kind)| *kind == membership)
+        .skip(offset as usize)
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .map(|(user, _)| user)
+        .collect())
+}
+
+// Ability to browse public DAOs in order to find one to join
+#[ic_cdk::query(guard = "_check_can_read")]
+fn list_public_daos(offset: u64, limit: u64) -> Vec<DaoSummary> {
+    DAO_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, dao)| {
+                _get_settings(&dao.id).visibility == DaoVisibility::Public && !dao.archived
+            })
+            .skip(offset as usize)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .map(|(_, dao)| DaoSummary::from(&dao))
+            .collect()
+    })
+}
+
+// Ability to create a DAO, up to the max_daos_per_user the canister is configured with
+#[ic_cdk::update(guard = "_check_can_update")]
+fn create_dao(dao: DaoPayload) -> Option<Dao> {
+    if _validate_dao_payload(&dao).is_err() {
+        return None;
+    }
+    if images::check_avatar(&dao.avatar, &caller(), None).is_err() {
+        return None;
+    }
+    let max_daos_per_user = CONFIG.with(|config| config.borrow().get().max_daos_per_user);
+    if let Some(max_daos_per_user) = max_daos_per_user {
+        if _count_owned_daos(&caller()) >= max_daos_per_user {
+            return None;
+        }
+    }
+
+    let id = ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("cannot increment id counter");
+    let mut dao = Dao {
+        id,
+        name: dao.name,
+        description: dao.description,
+        avatar: dao.avatar,
+        owner: Some(caller()),
+        created_at: time(),
+        updated_at: None,
+        member_count: 0,
+        proposal_count: 0,
+        schema_version: migrations::SCHEMA_VERSION,
+        version: 0,
+        archived: false,
+        slug: String::new(),
+        last_proposal_number: 0,
+    };
+    _assign_dao_slug(&mut dao);
+
+    do_insert_dao(&mut dao);
+    let settings = DaoSettings {
+        dao_id: dao.id,
+        ..Default::default()
+    };
+    DAO_SETTINGS.with(|service| service.borrow_mut().insert(dao.id, settings));
+    images::attach(&dao.avatar, dao.id);
+    log_event(dao.id, AuditAction::DaoCreated, AuditTarget::Dao(dao.id));
+    sharding::place(dao.id);
+    Some(dao)
+}
+
+// Ability to update a DAO providing you're the owner
+#[ic_cdk::update(guard = "_check_can_update")]
+fn update_dao(id: u64, payload: DaoPayload, expected_version: Option<u64>) -> Result<Dao, Error> {
+    match DAO_STORAGE.with(|service| service.borrow().get(&id)) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!("Couldn't update dao with id={}. You are not the owner", id),
+                ));
+            }
+            _check_not_archived(&dao)?;
+            _check_version(ErrorContext::Dao(id), dao.version, expected_version)?;
+            _validate_dao_payload(&payload)?;
+
+            images::check_avatar(&payload.avatar, &caller(), Some(id))?;
+            if let ImageRef::Image(previous) = dao.avatar {
+                if payload.avatar != dao.avatar {
+                    images::delete(previous);
+                }
+            }
+            images::attach(&payload.avatar, id);
+
+            let renamed = dao.name != payload.name;
+            dao.name = payload.name;
+            if renamed {
+                _assign_dao_slug(&mut dao);
+            }
+            dao.description = payload.description;
+            dao.avatar = payload.avatar;
+            dao.updated_at = Some(time());
+
+            do_insert_dao(&mut dao);
+            log_event(id, AuditAction::DaoUpdated, AuditTarget::Dao(id));
+            registry::announce(&dao, registry::RegistryEvent::Updated);
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't update a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to make a DAO read-only providing you're the owner. Members can still read and export
+// it, but nothing in it can change and it is no longer listed
+#[ic_cdk::update(guard = "_check_can_update")]
+fn archive_dao(id: u64) -> Result<Dao, Error> {
+    _set_archived(id, true)
+}
+
+// Ability to make an archived DAO writable again providing you're the owner
+#[ic_cdk::update(guard = "_check_can_update")]
+fn unarchive_dao(id: u64) -> Result<Dao, Error> {
+    _set_archived(id, false)
+}
+
+// Ability to delete DAO provided you're the owner. As a safeguard it has to be archived first and
+// have no proposals open for voting
+#[ic_cdk::update(guard = "_check_can_update")]
+fn delete_dao(id: u64) -> Result<Dao, Error> {
+    match _get_dao(&id) {
+        Some(dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!(
+                        "Couldn't delete a dao with id={}. You are not the owner",
+                        id
+                    ),
+                ));
+            }
+            if !dao.archived {
+                return Err(Error::invalid_input(
+                    ErrorContext::Dao(id),
+                    format!("Couldn't delete a dao with id={}. Archive it first", id),
+                ));
+            }
+            merge::check_not_merging(id)?;
+            let has_open_proposals = _dao_proposal_ids(&dao.id)
+                .iter()
+                .filter_map(_get_proposal)
+                .any(|proposal| {
+                    matches!(
+                        proposal.status,
+                        ProposalStatus::Open | ProposalStatus::Scheduled
+                    )
+                });
+            if has_open_proposals {
+                return Err(Error::invalid_input(
+                    ErrorContext::Dao(id),
+                    format!(
+                        "Couldn't delete a dao with id={}. It still has proposals open for voting",
+                        id
+                    ),
+                ));
+            }
+
+            // announced while the DAO's settings still say whether it is public
+            registry::announce(&dao, registry::RegistryEvent::Delisted);
+            DAO_STORAGE.with(|service| service.borrow_mut().remove(&id));
+            _clear_dao_slugs(&id);
+            // removes the comments, votes, reports and attachments of each proposal with it
+            _dao_proposal_ids(&dao.id)
+                .iter()
+                .filter_map(_get_proposal)
+                .for_each(|proposal| _delete_proposal_records(&proposal));
+            images::delete_dao_images(id);
+            _clear_constitutions(&id);
+            _clear_dues(&id);
+            _clear_member_activity(&id);
+            DAO_SETTINGS.with(|service| service.borrow_mut().remove(&id));
+            _clear_memberships(&dao);
+            log_event(id, AuditAction::DaoDeleted, AuditTarget::Dao(id));
+
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("Couldn't delete a dao with id={}. dao not found.", id),
+        )),
+    }
+}
+
+// Ability to propose merging a DAO you own into another one. Once the owner of the other DAO
+// accepts, the members and proposals of yours are moved over and yours is archived
+#[ic_cdk::update(guard = "_check_can_update")]
+fn propose_merge(source_dao: u64, target_dao: u64) -> Result<merge::DaoMerge, Error> {
+    merge::propose(source_dao, target_dao)
+}
+
+// Ability to accept merging another DAO into one you own. Its members are added to yours, its open
+// proposals are cancelled and all of its proposals move to yours along with its treasury. This
+// runs in steps after the call returns, list_merges shows how far it got
+#[ic_cdk::update(guard = "_check_can_update")]
+fn accept_merge(merge_id: u64) -> Result<merge::DaoMerge, Error> {
+    merge::accept(merge_id)
+}
+
+// Ability to call off a merge that wasn't accepted yet provided you own either DAO
+#[ic_cdk::update(guard = "_check_can_update")]
+fn cancel_merge(merge_id: u64) -> Result<merge::DaoMerge, Error> {
+    merge::cancel(merge_id)
+}
+
+// Ability to see the merges a DAO took part in provided you're the owner or an admin
+#[ic_cdk::query(guard = "_check_can_read")]
+fn list_merges(dao_id: u64) -> Result<Vec<merge::DaoMerge>, Error> {
+    merge::list(dao_id)
+}
+
+// Ability to make a DAO public or private providing you're the owner
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_dao_visibility(id: u64, visibility: DaoVisibility) -> Result<Dao, Error> {
+    match DAO_STORAGE.with(|service| service.borrow().get(&id)) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!(
+                        "Couldn't change visibility of dao with id={}. You are not the owner",
+                        id
+                    ),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            let mut settings = _get_settings(&id);
+            settings.visibility = visibility;
+            _save_settings(&mut dao, settings);
+            log_event(id, AuditAction::VisibilityChanged, AuditTarget::Dao(id));
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't update a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to allow or disallow the owner from seeing who voted which way providing you're the owner
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_public_ballots(id: u64, public_ballots: bool) -> Result<Dao, Error> {
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!("Couldn't update dao with id={}. You are not the owner", id),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            let mut settings = _get_settings(&id);
+            settings.public_ballots = public_ballots;
+            _save_settings(&mut dao, settings);
+            log_event(id, AuditAction::BallotsSettingChanged, AuditTarget::Dao(id));
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't update a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to set the share of upvotes, from 1 to 100 percent, a proposal needs to pass providing
+// you're the owner
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_approval_threshold(id: u64, approval_threshold_percent: u8) -> Result<Dao, Error> {
+    if !(1..=100).contains(&approval_threshold_percent) {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(id),
+            format!(
+                "approval threshold must be between 1 and 100 percent, got {}",
+                approval_threshold_percent
+            ),
+        ));
+    }
+
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!("Couldn't update dao with id={}. You are not the owner", id),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            let mut settings = _get_settings(&id);
+            settings.approval_threshold_percent = approval_threshold_percent;
+            _save_settings(&mut dao, settings);
+            log_event(
+                id,
+                AuditAction::ApprovalThresholdChanged,
+                AuditTarget::Dao(id),
+            );
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't update a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to see the governance settings of a DAO you're part of
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_dao_settings(dao_id: u64) -> Result<DaoSettings, Error> {
+    _can_read_dao(&dao_id)?;
+    let mut settings = _get_settings(&dao_id);
+    // the secret is only shared with the receiver of the webhook
+    if let Some(webhook) = settings.webhook.as_mut() {
+        webhook.secret = String::new();
+    }
+    Ok(settings)
+}
+
+// Ability to change the governance settings of a DAO providing you're the owner. Only the fields
+// set in the patch change, and nothing changes if any of them is out of range
+#[ic_cdk::update(guard = "_check_can_update")]
+fn update_dao_settings(dao_id: u64, patch: DaoSettingsPatch) -> Result<DaoSettings, Error> {
+    match _get_dao(&dao_id) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(dao_id),
+                    format!(
+                        "Couldn't update settings of dao with id={}. You are not the owner",
+                        dao_id
+                    ),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            let settings = _patch_settings(_get_settings(&dao_id), patch)?;
+            _save_settings(&mut dao, settings.clone());
+            // a raised or removed limit makes room for queued proposals
+            _promote_queued(dao_id);
+            log_event(
+                dao_id,
+                AuditAction::SettingsChanged,
+                AuditTarget::Dao(dao_id),
+            );
+            Ok(settings)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(dao_id),
+            format!("couldn't update a dao with id={}. dao not found", dao_id),
+        )),
+    }
+}
+
+// Ability to have the outcome of every finalized proposal posted to `url` providing you're the
+// owner. Deliveries are signed with an HMAC of `secret`. An empty url removes the webhook
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_webhook(dao_id: u64, url: String, secret: String) -> Result<DaoSettings, Error> {
+    let webhook = if url.is_empty() {
+        None
+    } else {
+        let webhook = webhooks::Webhook { url, secret };
+        webhooks::validate(&webhook)
+            .map_err(|msg| Error::invalid_input(ErrorContext::Dao(dao_id), msg))?;
+        Some(webhook)
+    };
+
+    match _get_dao(&dao_id) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(dao_id),
+                    format!(
+                        "Couldn't set the webhook of dao with id={}. You are not the owner",
+                        dao_id
+                    ),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            let mut settings = _get_settings(&dao_id);
+            settings.webhook = webhook;
+            _save_settings(&mut dao, settings.clone());
+            log_event(
+                dao_id,
+                AuditAction::WebhookChanged,
+                AuditTarget::Dao(dao_id),
+            );
+            Ok(settings)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(dao_id),
+            format!("couldn't update a dao with id={}. dao not found", dao_id),
+        )),
+    }
+}
+
+// Ability to weight votes by the voter's role, from 1 to 10 each, providing you're the owner.
+// None counts every vote once
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_role_vote_weights(
+    id: u64,
+    role_vote_weights: Option<RoleVoteWeights>,
+) -> Result<Dao, Error> {
+    if let Some(weights) = role_vote_weights {
+        let valid = [weights.owner, weights.admin, weights.member]
+            .iter()
+            .all(|weight| (1..=MAX_ROLE_VOTE_WEIGHT).contains(weight));
+        if !valid {
+            return Err(Error::invalid_input(
+                ErrorContext::Dao(id),
+                format!(
+                    "vote weights must be between 1 and {}",
+                    MAX_ROLE_VOTE_WEIGHT
+                ),
+            ));
+        }
+    }
+
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!("Couldn't update dao with id={}. You are not the owner", id),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            let mut settings = _get_settings(&id);
+            settings.role_vote_weights = role_vote_weights;
+            _save_settings(&mut dao, settings);
+            log_event(
+                id,
+                AuditAction::RoleVoteWeightsChanged,
+                AuditTarget::Dao(id),
+            );
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't update a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to choose what happens to existing votes when a proposal is amended providing you're
+// the owner
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_amendment_policy(id: u64, amendment_policy: AmendmentPolicy) -> Result<Dao, Error> {
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!("Couldn't update dao with id={}. You are not the owner", id),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            let mut settings = _get_settings(&id);
+            settings.amendment_policy = amendment_policy;
+            _save_settings(&mut dao, settings);
+            log_event(
+                id,
+                AuditAction::AmendmentPolicyChanged,
+                AuditTarget::Dao(id),
+            );
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't update a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to restrict who can create proposals in a DAO provided you're the owner
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_proposal_rules(
+    id: u64,
+    min_membership_age_seconds: u64,
+    proposal_creation_role: Role,
+) -> Result<Dao, Error> {
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!("Couldn't update dao with id={}. You are not the owner", id),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            let mut settings = _get_settings(&id);
+            settings.min_membership_age_seconds = min_membership_age_seconds;
+            settings.proposal_creation_role = proposal_creation_role;
+            _save_settings(&mut dao, settings);
+            log_event(id, AuditAction::ProposalRulesChanged, AuditTarget::Dao(id));
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't update a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to set how many reports hide content in a DAO provided you're the owner
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_report_threshold(id: u64, report_threshold: u64) -> Result<Dao, Error> {
+    if report_threshold == 0 {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            "report threshold must be at least 1".to_string(),
+        ));
+    }
+
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!("Couldn't update dao with id={}. You are not the owner", id),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            let mut settings = _get_settings(&id);
+            settings.report_threshold = report_threshold;
+            _save_settings(&mut dao, settings);
+            log_event(id, AuditAction::DaoUpdated, AuditTarget::Dao(id));
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't update a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to choose whether proposals stop taking comments, comment edits and likes once their
+// vote is over, finalized or past its deadline, provided you're the owner. On for new DAOs.
+// Existing comments stay readable either way
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_auto_lock_comments(id: u64, auto_lock_comments: bool) -> Result<Dao, Error> {
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!("Couldn't update dao with id={}. You are not the owner", id),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            let mut settings = _get_settings(&id);
+            settings.auto_lock_comments = auto_lock_comments;
+            _save_settings(&mut dao, settings);
+            log_event(
+                id,
+                AuditAction::AutoLockSettingChanged,
+                AuditTarget::Dao(id),
+            );
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't update a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to invite a user to a DAO providing you're the owner
+#[ic_cdk::update(guard = "_check_can_update")]
+fn invite_to_dao(id: u64, invitee: Principal) -> Result<Invite, Error> {
+    match _get_dao(&id) {
+        Some(dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!(
+                        "Couldn't invite to dao with id={}. You are not the owner",
+                        id
+                    ),
+                ));
+            }
+            _check_not_archived(&dao)?;
+            if _is_member(&dao, &invitee) {
+                return Err(Error::already_member(
+                    ErrorContext::Member(invitee),
+                    format!("{} is already a member of dao with id={}", invitee, id),
+                ));
+            }
+            if _is_banned(id, &invitee) {
+                return Err(Error::banned(
+                    ErrorContext::Member(invitee),
+                    format!("{} is banned from dao with id={}", invitee, id),
+                ));
+            }
+
+            let invite = Invite {
+                dao_id: id,
+                invitee: Some(invitee),
+                invited_by: Some(caller()),
+                created_at: time(),
+                expires_at: time() + INVITE_TTL,
+            };
+
+            do_insert_invite(&invite);
+            notify(&invitee, NotificationKind::Invited, id, None, None);
+            log_event(id, AuditAction::MemberInvited, AuditTarget::Member(invitee));
+            Ok(invite)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't invite to a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to join a public DAO, or a private DAO you've been invited to
+#[ic_cdk::update(guard = "_check_can_update")]
+fn join_dao(id: u64) -> Result<Dao, Error> {
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            _check_not_archived(&dao)?;
+            if _is_member(&dao, &caller()) {
+                return Err(Error::already_member(
+                    ErrorContext::Dao(id),
+                    format!("You are already a member of dao with id={}", id),
+                ));
+            }
+            if _is_banned(id, &caller()) {
+                return Err(Error::banned(
+                    ErrorContext::Dao(id),
+                    format!("Couldn't join dao with id={}. You are banned", id),
+                ));
+            }
+
+            if _get_settings(&id).visibility == DaoVisibility::Private {
+                let key = (id, principal_key(&caller()));
+                let invite = INVITE_STORAGE.with(|service| service.borrow_mut().remove(&key));
+                let has_valid_invite =
+                    matches!(invite, Some(invite) if invite.expires_at >= time());
+                if !has_valid_invite {
+                    return Err(Error::permission_error(
+                        ErrorContext::Dao(id),
+                        format!(
+                            "Couldn't join dao with id={}. It is private and requires an invite",
+                            id
+                        ),
+                    ));
+                }
+            }
+
+            _admit_member(&mut dao, &caller());
+
+            do_insert_dao(&mut dao);
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't join a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to leave a DAO you're part of, optionally with a short note for the owner that is kept
+// in the DAO's audit log. Your proposals that aren't finalized yet are cancelled. The owner can't
+// leave
+#[ic_cdk::update(guard = "_check_can_update")]
+fn leave_dao(id: u64, note: Option<String>) -> Result<(), Error> {
+    let mut dao = _is_user_part_of_dao(&id)?;
+    _check_not_archived(&dao)?;
+    if dao.owner == Some(caller()) {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(id),
+            format!("Couldn't leave dao with id={}. You are its owner", id),
+        ));
+    }
+    let note = note
+        .map(|note| note.trim().to_string())
+        .filter(|note| !note.is_empty());
+    if note
+        .as_ref()
+        .is_some_and(|note| note.chars().count() > MAX_EXIT_NOTE_LEN)
+    {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(id),
+            format!("note must be at most {} characters", MAX_EXIT_NOTE_LEN),
+        ));
+    }
+
+    let member = caller();
+    _set_membership(&mut dao, &member, None);
+    dao.updated_at = Some(time());
+
+    do_insert_dao(&mut dao);
+    _withdraw_proposals_of(&dao, &member);
+    log_event(
+        id,
+        AuditAction::MemberLeft,
+        AuditTarget::Departure { member, note },
+    );
+    Ok(())
+}
+
+// Ability to add up to 100 members at once without inviting them, e.g. when moving an existing
+// community over, providing you're the owner. Members already in the DAO and banned users are
+// skipped and reported
+#[ic_cdk::update(guard = "_check_can_update")]
+fn add_members_bulk(id: u64, members: Vec<Principal>) -> Result<BulkResult, Error> {
+    _check_storage_available(ErrorContext::Dao(id))?;
+    if members.len() > MAX_MEMBERS_PER_BATCH {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(id),
+            format!(
+                "at most {} members can be added at once",
+                MAX_MEMBERS_PER_BATCH
+            ),
+        ));
+    }
+
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!(
+                        "Couldn't add members to dao with id={}. You are not the owner",
+                        id
+                    ),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            let mut result = BulkResult::default();
+            for member in members {
+                if _is_member(&dao, &member) {
+                    result.already_members.push(member);
+                } else if _is_banned(id, &member) {
+                    result.banned.push(member);
+                } else {
+                    _admit_member(&mut dao, &member);
+                    INVITE_STORAGE
+                        .with(|service| service.borrow_mut().remove(&(id, principal_key(&member))));
+                    result.added.push(member);
+                }
+            }
+
+            if !result.added.is_empty() {
+                do_insert_dao(&mut dao);
+                result.added.iter().for_each(|member| {
+                    notify(member, NotificationKind::AddedToDao, id, None, None)
+                });
+            }
+            Ok(result)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!(
+                "couldn't add members to a dao with id={}. dao not found",
+                id
+            ),
+        )),
+    }
+}
+
+// Ability to promote a member to admin or demote an admin providing you're the owner
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_dao_admin(id: u64, user: Principal, is_admin: bool) -> Result<Dao, Error> {
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!(
+                        "Couldn't change admins of dao with id={}. You are not the owner",
+                        id
+                    ),
+                ));
+            }
+            _check_not_archived(&dao)?;
+            if !_membership(id, &user).is_some_and(Membership::is_member) {
+                return Err(Error::not_a_member(
+                    ErrorContext::Member(user),
+                    format!("{} is not a member of dao with id={}", user, id),
+                ));
+            }
+
+            let membership = if is_admin {
+                Membership::Admin
+            } else {
+                Membership::Member
+            };
+            _set_membership(&mut dao, &user, Some(membership));
+            dao.updated_at = Some(time());
+
+            do_insert_dao(&mut dao);
+            log_event(id, AuditAction::AdminChanged, AuditTarget::Member(user));
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't update a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to ban a user from a DAO providing you're the owner or an admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn ban_member(id: u64, user: Principal) -> Result<Dao, Error> {
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_dao_admin(&dao, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!("Couldn't ban from dao with id={}. You are not an admin", id),
+                ));
+            }
+            _check_not_archived(&dao)?;
+            if dao.owner == Some(user)
+                || (_membership(id, &user) == Some(Membership::Admin)
+                    && dao.owner != Some(caller()))
+            {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!(
+                        "Couldn't ban {} from dao with id={}. Only the owner can ban admins",
+                        user, id
+                    ),
+                ));
+            }
+
+            _set_membership(&mut dao, &user, Some(Membership::Banned));
+            dao.updated_at = Some(time());
+
+            INVITE_STORAGE.with(|service| service.borrow_mut().remove(&(id, principal_key(&user))));
+
+            do_insert_dao(&mut dao);
+            _withdraw_proposals_of(&dao, &user);
+            log_event(id, AuditAction::MemberBanned, AuditTarget::Member(user));
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't ban from a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to lift a ban providing you're the owner or an admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn unban_member(id: u64, user: Principal) -> Result<Dao, Error> {
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_dao_admin(&dao, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!(
+                        "Couldn't unban from dao with id={}. You are not an admin",
+                        id
+                    ),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            if _is_banned(id, &user) {
+                _set_membership(&mut dao, &user, None);
+            }
+            dao.updated_at = Some(time());
+
+            do_insert_dao(&mut dao);
+            log_event(id, AuditAction::MemberUnbanned, AuditTarget::Member(user));
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't unban from a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to let someone who isn't a member read a DAO providing you're the owner or an admin.
+// Observers can read its proposals and comments but can't propose, vote or comment
+#[ic_cdk::update(guard = "_check_can_update")]
+fn add_observer(id: u64, user: Principal) -> Result<Dao, Error> {
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_dao_admin(&dao, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!(
+                        "Couldn't add an observer to dao with id={}. You are not an admin",
+                        id
+                    ),
+                ));
+            }
+            _check_not_archived(&dao)?;
+            if _is_member(&dao, &user) {
+                return Err(Error::already_member(
+                    ErrorContext::Member(user),
+                    format!("{} is already a member of dao with id={}", user, id),
+                ));
+            }
+            if _is_banned(id, &user) {
+                return Err(Error::banned(
+                    ErrorContext::Member(user),
+                    format!("{} is banned from dao with id={}", user, id),
+                ));
+            }
+            if _membership(id, &user) == Some(Membership::Observer) {
+                return Ok(dao);
+            }
+            let observer_count = _memberships(id)
+                .iter()
+                .filter(|(_, membership)| *membership == Membership::Observer)
+                .count();
+            if observer_count >= MAX_OBSERVERS_PER_DAO {
+                return Err(Error::invalid_input(
+                    ErrorContext::Dao(id),
+                    format!(
+                        "a dao can't have more than {} observers",
+                        MAX_OBSERVERS_PER_DAO
+                    ),
+                ));
+            }
+
+            _set_membership(&mut dao, &user, Some(Membership::Observer));
+            dao.updated_at = Some(time());
+
+            do_insert_dao(&mut dao);
+            log_event(id, AuditAction::ObserverAdded, AuditTarget::Member(user));
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't update a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to stop someone from observing a DAO providing you're the owner or an admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn remove_observer(id: u64, user: Principal) -> Result<Dao, Error> {
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_dao_admin(&dao, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!(
+                        "Couldn't remove an observer from dao with id={}. You are not an admin",
+                        id
+                    ),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            if _membership(id, &user) == Some(Membership::Observer) {
+                _set_membership(&mut dao, &user, None);
+            }
+            dao.updated_at = Some(time());
+
+            do_insert_dao(&mut dao);
+            log_event(id, AuditAction::ObserverRemoved, AuditTarget::Member(user));
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't update a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+/**
+* -----------------------------------------------------------------------------
+* JOIN REQUEST FUNCTIONS
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to ask to join a private DAO. Requests from banned users are rejected straight away
+#[ic_cdk::update(guard = "_check_can_update")]
+fn request_to_join(dao_id: u64, message: String) -> Result<JoinRequest, Error> {
+    match _get_dao(&dao_id) {
+        Some(dao) => {
+            _check_not_archived(&dao)?;
+            if _is_member(&dao, &caller()) {
+                return Err(Error::already_member(
+                    ErrorContext::Dao(dao_id),
+                    format!("You are already a member of dao with id={}", dao_id),
+                ));
+            }
+            if _get_settings(&dao_id).visibility == DaoVisibility::Public {
+                return Err(Error::invalid_input(
+                    ErrorContext::Dao(dao_id),
+                    format!(
+                        "dao with id={} is public. Join it directly instead of requesting",
+                        dao_id
+                    ),
+                ));
+            }
+            if message.chars().count() > MAX_JOIN_MESSAGE_LEN {
+                return Err(Error::invalid_input(
+                    ErrorContext::None,
+                    format!(
+                        "join request message can't be longer than {} characters",
+                        MAX_JOIN_MESSAGE_LEN
+                    ),
+                ));
+            }
+
+            let has_pending_request = JOIN_REQUEST_STORAGE.with(|service| {
+                service.borrow().iter().any(|(_, request)| {
+                    request.dao_id == dao_id
+                        && request.requester == Some(caller())
+                        && request.status == JoinRequestStatus::Pending
+                })
+            });
+            if has_pending_request {
+                return Err(Error::already_requested(
+                    ErrorContext::Dao(dao_id),
+                    format!(
+                        "You already have a pending request to join dao with id={}",
+                        dao_id
+                    ),
+                ));
+            }
+
+            let id = ID_COUNTER
+                .with(|counter| {
+                    let current_value = *counter.borrow().get();
+                    counter.borrow_mut().set(current_value + 1)
+                })
+                .expect("cannot increment id counter");
+
+            let status = if _is_banned(dao_id, &caller()) {
+                JoinRequestStatus::Rejected
+            } else {
+                JoinRequestStatus::Pending
+            };
+
+            let request = JoinRequest {
+                id,
+                dao_id,
+                requester: Some(caller()),
+                message,
+                created_at: time(),
+                status,
+            };
+
+            do_insert_join_request(&request);
+            log_event(
+                dao_id,
+                AuditAction::JoinRequested,
+                AuditTarget::JoinRequest(id),
+            );
+            Ok(request)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(dao_id),
+            format!(
+                "couldn't request to join a dao with id={}. dao not found",
+                dao_id
+            ),
+        )),
+    }
+}
+
+// Ability to see pending join requests of a DAO providing you're the owner or an admin
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_pending_requests(dao_id: u64) -> Result<Vec<JoinRequest>, Error> {
+    match _get_dao(&dao_id) {
+        Some(dao) => {
+            if !_is_dao_admin(&dao, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(dao_id),
+                    format!(
+                        "Couldn't get join requests of dao with id={}. You are not an admin",
+                        dao_id
+                    ),
+                ));
+            }
+
+            Ok(_pending_join_requests(dao_id).collect())
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(dao_id),
+            format!("a dao with id={} not found", dao_id),
+        )),
+    }
+}
+
+// Ability to see what waits for you in a DAO you're the owner or an admin of: votes to end,
+// proposals to execute, join requests, reported content, queued proposals and invites about to
+// expire
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_owner_todo(dao_id: u64) -> Result<OwnerTodo, Error> {
+    let dao = match _get_dao(&dao_id) {
+        Some(dao) => dao,
+        None => {
+            return Err(Error::not_found(
+                ErrorContext::Dao(dao_id),
+                format!("a dao with id={} not found", dao_id),
+            ))
+        }
+    };
+    if !_is_dao_admin(&dao, &caller()) {
+        return Err(Error::permission_error(
+            ErrorContext::Dao(dao_id),
+            format!(
+                "Couldn't get the todo list of dao with id={}. You are not an admin",
+                dao_id
+            ),
+        ));
+    }
+
+    let now = time();
+    let settings = _get_settings(&dao_id);
+    let timelock = settings.timelock_seconds.saturating_mul(1_000_000_000);
+    let proposals: Vec<Proposal> = _dao_proposal_ids(&dao.id)
+        .iter()
+        .filter_map(_get_proposal)
+        .collect();
+    let proposal_item = |proposal: &Proposal, at: u64| TodoItem {
+        id: Some(proposal.id),
+        title: Some(proposal.title.clone()),
+        at,
+        ..Default::default()
+    };
+
+    let awaiting_finalization = _todo_list(
+        proposals
+            .iter()
+            .filter(|proposal| proposal.status == ProposalStatus::Open)
+            .filter(|proposal| service::is_deadline_passed(proposal.deadline, now))
+            .map(|proposal| proposal_item(proposal, proposal.deadline)),
+    );
+    let awaiting_execution = _todo_list(
+        proposals
+            .iter()
+            .filter(|proposal| proposal.status == ProposalStatus::Approved)
+            .filter(|proposal| proposal.executed_at.is_none())
+            .filter(|proposal| {
+                matches!(
+                    proposal.kind,
+                    ProposalKind::Funding { .. } | ProposalKind::MembershipChange { .. }
+                )
+            })
+            .map(|proposal| proposal_item(proposal, proposal.deadline.saturating_add(timelock))),
+    );
+    let join_requests = _todo_list(_pending_join_requests(dao_id).map(|request| TodoItem {
+        id: Some(request.id),
+        principal: request.requester,
+        at: request.created_at,
+        ..Default::default()
+    }));
+
+    let reported_ids: Vec<u64> = REPORTED_CONTENT.with(|service| {
+        service
+            .borrow()
+            .range((dao_id, 0)..=(dao_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    let reported_content = _todo_list(reported_ids.iter().filter_map(|id| {
+        let reports = _get_reports(id);
+        let report = reports.iter().max_by_key(|report| report.created_at)?;
+        if (reports.len() as u64) < settings.report_threshold {
+            return None;
+        }
+        let proposal_id = match report.target {
+            ReportTarget::Proposal(id) => Some(id),
+            ReportTarget::Comment(id) => _get_comment(&id).map(|comment| comment.proposal_id),
+        };
+        Some(TodoItem {
+            id: Some(*id),
+            title: proposal_id
+                .and_then(|id| _get_proposal(&id))
+                .map(|proposal| proposal.title),
+            at: report.created_at,
+            ..Default::default()
+        })
+    }));
+
+    let queued_ids: Vec<u64> = PROPOSAL_QUEUE.with(|service| {
+        service
+            .borrow()
+            .range((dao_id, 0)..=(dao_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    let queued_proposals = _todo_list(
+        queued_ids
+            .iter()
+            .filter_map(_get_proposal)
+            .map(|proposal| proposal_item(&proposal, proposal.created_at)),
+    );
+
+    let mut invites: Vec<Invite> = INVITE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((dao_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| *id == dao_id)
+            .map(|(_, invite)| invite)
+            .filter(|invite| invite.expires_at > now)
+            .filter(|invite| invite.expires_at <= now.saturating_add(INVITE_EXPIRY_NOTICE))
+            .collect()
+    });
+    invites.sort_by_key(|invite| invite.expires_at);
+    let expiring_invites = _todo_list(invites.into_iter().map(|invite| TodoItem {
+        principal: invite.invitee,
+        at: invite.expires_at,
+        ..Default::default()
+    }));
+
+    Ok(OwnerTodo {
+        dao_id,
+        awaiting_finalization,
+        awaiting_execution,
+        join_requests,
+        reported_content,
+        queued_proposals,
+        expiring_invites,
+    })
+}
+
+// Ability to see the status of all the join requests you've made
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_my_join_requests() -> Vec<JoinRequest> {
+    JOIN_REQUEST_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, request)| request.requester == Some(caller()))
+            .map(|(_, request)| request)
+            .collect()
+    })
+}
+
+// Ability to accept a join request providing you're the owner or an admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn approve_join_request(request_id: u64) -> Result<JoinRequest, Error> {
+    _resolve_join_request(request_id, JoinRequestStatus::Approved)
+}
+
+// Ability to decline a join request providing you're the owner or an admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn reject_join_request(request_id: u64) -> Result<JoinRequest, Error> {
+    _resolve_join_request(request_id, JoinRequestStatus::Rejected)
+}
+
+/**
+* -----------------------------------------------------------------------------
+* CONSTITUTION FUNCTIONS
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to publish a new version of a DAO's constitution providing you're the owner. Members
+// have to accept it again if the DAO requires it
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_constitution(dao_id: u64, text: String) -> Result<Constitution, Error> {
+    if text.trim().is_empty() || text.len() > MAX_CONSTITUTION_LEN {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(dao_id),
+            format!(
+                "a constitution must be between 1 and {} bytes",
+                MAX_CONSTITUTION_LEN
+            ),
+        ));
+    }
+
+    match _get_dao(&dao_id) {
+        Some(dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(dao_id),
+                    format!(
+                        "Couldn't update dao with id={}. You are not the owner",
+                        dao_id
+                    ),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            let constitution = Constitution {
+                dao_id,
+                version: _latest_constitution_version(&dao_id) + 1,
+                text,
+                author: Some(caller()),
+                created_at: time(),
+            };
+            CONSTITUTION_STORAGE.with(|service| {
+                service
+                    .borrow_mut()
+                    .insert((dao_id, constitution.version), constitution.clone())
+            });
+            log_event(
+                dao_id,
+                AuditAction::ConstitutionUpdated,
+                AuditTarget::Dao(dao_id),
+            );
+            Ok(constitution)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(dao_id),
+            format!("couldn't update a dao with id={}. dao not found", dao_id),
+        )),
+    }
+}
+
+// Ability to read a version of the constitution of a DAO you're part of, the latest by default
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_constitution(dao_id: u64, version: Option<u64>) -> Result<Constitution, Error> {
+    _can_read_dao(&dao_id)?;
+
+    let version = version.unwrap_or_else(|| _latest_constitution_version(&dao_id));
+    match CONSTITUTION_STORAGE.with(|service| service.borrow().get(&(dao_id, version))) {
+        Some(constitution) => Ok(constitution),
+        None => Err(Error::not_found(
+            ErrorContext::Dao(dao_id),
+            format!(
+                "version {} of the constitution of dao with id={} not found",
+                version, dao_id
+            ),
+        )),
+    }
+}
+
+// Ability to accept a version of the constitution of a DAO you're part of
+#[ic_cdk::update(guard = "_check_can_update")]
+fn accept_constitution(dao_id: u64, version: u64) -> Result<(), Error> {
+    _is_user_part_of_dao(&dao_id)?;
+    _check_dao_not_archived(&dao_id)?;
+    if !CONSTITUTION_STORAGE.with(|service| service.borrow().contains_key(&(dao_id, version))) {
+        return Err(Error::not_found(
+            ErrorContext::Dao(dao_id),
+            format!(
+                "version {} of the constitution of dao with id={} not found",
+                version, dao_id
+            ),
+        ));
+    }
+
+    // accepting an older version never undoes accepting a newer one
+    if version > _accepted_constitution_version(&dao_id, &caller()) {
+        CONSTITUTION_ACCEPTANCES.with(|service| {
+            service
+                .borrow_mut()
+                .insert((dao_id, principal_key(&caller())), version)
+        });
+    }
+    Ok(())
+}
+
+// Ability to require members to accept the latest constitution before voting providing you're
+// the owner
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_constitution_required(id: u64, constitution_required: bool) -> Result<Dao, Error> {
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!("Couldn't update dao with id={}. You are not the owner", id),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            let mut settings = _get_settings(&id);
+            settings.constitution_required = constitution_required;
+            _save_settings(&mut dao, settings);
+            log_event(
+                id,
+                AuditAction::ConstitutionRequirementChanged,
+                AuditTarget::Dao(id),
+            );
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't update a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+/**
+* -----------------------------------------------------------------------------
+* DUES FUNCTIONS
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to charge members dues every period providing you're the owner. A dues_amount of 0
+// turns dues off
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_dues(id: u64, dues_amount: u64, dues_period_seconds: u64) -> Result<Dao, Error> {
+    if dues_amount > 0 && dues_period_seconds == 0 {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(id),
+            "dues period must be at least 1 second".to_string(),
+        ));
+    }
+
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!("Couldn't update dao with id={}. You are not the owner", id),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            let mut settings = _get_settings(&id);
+            settings.dues_amount = dues_amount;
+            settings.dues_period_seconds = dues_period_seconds;
+            _save_settings(&mut dao, settings);
+            log_event(id, AuditAction::DuesChanged, AuditTarget::Dao(id));
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't update a dao with id={}. dao not found", id),
+        )),
+    }
+}
+
+// Ability to pay one period of dues in a DAO you're part of. The amount is moved from your
+// account into the DAO's treasury with icrc2_transfer_from, so this canister must have been
+// approved to spend it first. Returns the time until which your dues are paid
+#[ic_cdk::update(guard = "_check_can_update")]
+async fn pay_dues(dao_id: u64) -> Result<u64, Error> {
+    let dao = match _get_dao(&dao_id) {
+        Some(dao) if _is_member(&dao, &caller()) => dao,
+        Some(_) => {
+            return Err(Error::not_a_member(
+                ErrorContext::Dao(dao_id),
+                format!("unable to get a dao with id={}. Not a member", dao_id),
+            ))
+        }
+        None => {
+            return Err(Error::not_found(
+                ErrorContext::Dao(dao_id),
+                format!("a dao with id={} not found", dao_id),
+            ))
+        }
+    };
+    _check_not_archived(&dao)?;
+    let settings = _get_settings(&dao_id);
+    if settings.dues_amount == 0 {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(dao_id),
+            format!("dao with id={} doesn't charge dues", dao_id),
+        ));
+    }
+
+    // the caller is read before the call, the period after it so a change made meanwhile applies
+    let member = caller();
+    let block_index = ledger::transfer_from(
+        ErrorContext::Dao(dao_id),
+        member,
+        ledger::dao_treasury(dao_id),
+        settings.dues_amount,
+    )
+    .await?;
+    treasury::record(
+        dao_id,
+        treasury::TreasuryTxKind::DuesPayment,
+        settings.dues_amount,
+        Some(member),
+        None,
+        Some(block_index),
+    );
+
+    let paid_until = _extend_paid_until(&dao_id, &member);
+    log_event(dao_id, AuditAction::DuesPaid, AuditTarget::Member(member));
+    Ok(paid_until)
+}
+
+// Ability to see until when your dues in a DAO you're part of are paid, 0 if you never paid
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_my_paid_until(dao_id: u64) -> Result<u64, Error> {
+    match _can_read_dao(&dao_id) {
+        Ok(_) => Ok(_get_paid_until(&dao_id, &caller())),
+        Err(error) => Err(error),
+    }
+}
+
+// Ability to see what went in and out of the treasury of a DAO you're part of, oldest first.
+// Dues, proposal deposits and their refunds or forfeits, payouts and merges are all recorded
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_treasury_history(
+    dao_id: u64,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<treasury::TreasuryTx>, Error> {
+    _can_read_dao(&dao_id)?;
+    Ok(treasury::history(
+        dao_id,
+        offset as usize,
+        limit.min(MAX_PAGE_SIZE) as usize,
+    ))
+}
+
+/**
+* -----------------------------------------------------------------------------
+* PROPOSAL FUNCTIONS (callable if user is part of DAO)
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to get a single proposal. Exported by routed_get_proposal
+fn get_proposal(id: u64) -> Result<ProposalView, Error> {
+    _get_readable_proposal(&id).map(ProposalView::from)
+}
+
+// Ability to get a proposal of a DAO you're part of by its slug, or a slug it had before its
+// title changed. Works like get_proposal
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_proposal_by_slug(dao_id: u64, slug: String) -> Result<ProposalView, Error> {
+    _can_read_dao(&dao_id)?;
+    let id = _slug_key(&slug)
+        .and_then(|key| PROPOSAL_SLUGS.with(|service| service.borrow().get(&(dao_id, key))));
+    match id {
+        Some(id) => get_proposal(id),
+        None => Err(Error::not_found(
+            ErrorContext::Dao(dao_id),
+            format!("a proposal with slug={} not found", slug),
+        )),
+    }
+}
+
+// Ability to get a proposal of a DAO you're part of by its number in the DAO, as in "DIP-12".
+// Works like get_proposal
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_proposal_by_number(dao_id: u64, number: u64) -> Result<ProposalView, Error> {
+    _can_read_dao(&dao_id)?;
+    match PROPOSAL_NUMBERS.with(|service| service.borrow().get(&(dao_id, number))) {
+        Some(id) => get_proposal(id),
+        None => Err(Error::not_found(
+            ErrorContext::Dao(dao_id),
+            format!("a proposal with number={} not found", number),
+        )),
+    }
+}
+
+// Ability to get a proposal with a certificate and witness proving its vote counts, status and
+// deadline. Not available while its DAO hides the tally, see certification::digest
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_proposal_certified(id: u64) -> Result<CertifiedProposal, Error> {
+    let proposal = get_proposal(id)?;
+    if _get_proposal(&id).is_some_and(|proposal| _tally_sealed(&proposal)) {
+        return Err(Error::permission_error(
+            ErrorContext::Proposal(id),
+            format!(
+                "Couldn't certify the votes of proposal with id={}. Its dao hides them until the vote is finalized",
+                id
+            ),
+        ));
+    }
+
+    match env::data_certificate() {
+        Some(certificate) => Ok(CertifiedProposal {
+            proposal,
+            certificate,
+            witness: certification::witness(id),
+        }),
+        None => Err(Error::invalid_input(
+            ErrorContext::None,
+            "certificates are only available in query calls".to_string(),
+        )),
+    }
+}
+
+// Ability to get a proposal together with its DAO, newest comments and your vote in one call
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_proposal_bundle(
+    proposal_id: u64,
+    comment_limit: Option<u64>,
+) -> Result<ProposalBundle, Error> {
+    let proposal = _get_readable_proposal(&proposal_id)?;
+    let dao = _can_read_dao(&proposal.dao_id)?;
+
+    let can_see_hidden = _can_see_hidden(&dao.id);
+    let visible_comments: Vec<Comment> = _comment_ids(&proposal.id)
+        .iter()
+        .rev()
+        .filter_map(_get_comment)
+        .filter(|comment| !comment.hidden || can_see_hidden)
+        .collect();
+    let comment_count = visible_comments.len() as u64;
+    let page: Vec<Comment> = visible_comments
+        .into_iter()
+        .take(
+            comment_limit
+                .unwrap_or(DEFAULT_BUNDLE_COMMENTS)
+                .min(MAX_PAGE_SIZE) as usize,
+        )
+        .collect();
+    let comments = _comment_views(page)
+        .into_iter()
+        .map(|comment| CommentEntry {
+            link_previews: previews::resolve(&comment.link_previews),
+            comment,
+        })
+        .collect();
+
+    Ok(ProposalBundle {
+        my_vote: _get_vote_direction(&proposal, &caller()),
+        dao: DaoSummary::from(&dao),
+        proposal: ProposalView::from(proposal),
+        comments,
+        comment_count,
+    })
+}
+
+// Ability to let anyone holding a link read a proposal for `ttl_seconds`, providing you're its
+// owner or an admin of its DAO. Returns the token to pass to get_proposal_by_token
+#[ic_cdk::update(guard = "_check_can_update")]
+async fn create_share_link(proposal_id: u64, ttl_seconds: u64) -> Result<String, Error> {
+    features::check_enabled(features::Feature::ShareLinks)?;
+    let context = ErrorContext::Proposal(proposal_id);
+    _get_shareable_proposal(&proposal_id)?;
+    if !(1..=MAX_SHARE_LINK_TTL_SECONDS).contains(&ttl_seconds) {
+        return Err(Error::invalid_input(
+            context,
+            format!(
+                "a share link can be valid for 1 to {} seconds",
+                MAX_SHARE_LINK_TTL_SECONDS
+            ),
+        ));
+    }
+
+    let (random,) = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .map_err(|(code, msg)| {
+            Error::invalid_input(
+                context,
+                format!("couldn't generate a share link: {:?} {}", code, msg),
+            )
+        })?;
+    // checked again after the call since links may have been created meanwhile
+    let proposal = _get_shareable_proposal(&proposal_id)?;
+    _clear_expired_share_links(&proposal_id);
+    if _get_share_links(&proposal_id).len() >= MAX_SHARE_LINKS_PER_PROPOSAL {
+        return Err(Error::invalid_input(
+            context,
+            format!(
+                "a proposal can't have more than {} share links, revoke one first",
+                MAX_SHARE_LINKS_PER_PROPOSAL
+            ),
+        ));
+    }
+
+    let key = ShareTokenKey::try_from(&random[..16]).expect("raw_rand returns 32 bytes");
+    let token = format!(
+        "{:016x}{}",
+        proposal_id,
+        random[..16]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+    );
+    SHARE_LINKS.with(|service| {
+        service.borrow_mut().insert(
+            (proposal_id, key),
+            ShareLink {
+                token: token.clone(),
+                proposal_id,
+                created_by: caller(),
+                created_at: time(),
+                expires_at: time().saturating_add(ttl_seconds.saturating_mul(1_000_000_000)),
+            },
+        )
+    });
+    log_event(
+        proposal.dao_id,
+        AuditAction::ShareLinkCreated,
+        AuditTarget::Proposal(proposal_id),
+    );
+    Ok(token)
+}
+
+// Ability to see the share links of a proposal that haven't expired, providing you're its owner
+// or an admin of its DAO
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_share_links(proposal_id: u64) -> Result<Vec<ShareLink>, Error> {
+    _get_shareable_proposal(&proposal_id)?;
+    Ok(_get_share_links(&proposal_id)
+        .into_iter()
+        .filter(|link| link.expires_at > time())
+        .collect())
+}
+
+// Ability to revoke a share link, providing you're the owner of its proposal or an admin of its DAO
+#[ic_cdk::update(guard = "_check_can_update")]
+fn revoke_share_link(token: String) -> Result<(), Error> {
+    let not_found = || {
+        Error::not_found(
+            ErrorContext::None,
+            "couldn't revoke the share link. link not found".to_string(),
+        )
+    };
+    let key = _parse_share_token(&token).ok_or_else(not_found)?;
+    if !SHARE_LINKS.with(|service| service.borrow().contains_key(&key)) {
+        return Err(not_found());
+    }
+    let proposal = _get_shareable_proposal(&key.0)?;
+
+    SHARE_LINKS.with(|service| service.borrow_mut().remove(&key));
+    log_event(
+        proposal.dao_id,
+        AuditAction::ShareLinkRevoked,
+        AuditTarget::Proposal(proposal.id),
+    );
+    Ok(())
+}
+
+// Ability to read a proposal through a share link, whether or not you're part of its DAO
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_proposal_by_token(token: String) -> Result<ProposalView, Error> {
+    features::check_enabled(features::Feature::ShareLinks)?;
+    let link = _parse_share_token(&token)
+        .and_then(|key| SHARE_LINKS.with(|service| service.borrow().get(&key)))
+        .filter(|link| link.expires_at > time());
+    match link.and_then(|link| _get_proposal(&link.proposal_id)) {
+        Some(proposal) => Ok(ProposalView::from(proposal)),
+        None => Err(Error::not_found(
+            ErrorContext::None,
+            "share link not found or expired".to_string(),
+        )),
+    }
+}
+
+// Ability to check whether and how you voted on a proposal
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_my_vote(proposal_id: u64) -> Result<Option<VoteDirection>, Error> {
+    _get_readable_proposal(&proposal_id).map(|proposal| _get_vote_direction(&proposal, &caller()))
+}
+
+// Ability to see who voted which way on a proposal providing you're the DAO owner and the DAO
+// has public ballots enabled
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_ballots(proposal_id: u64) -> Result<Ballots, Error> {
+    let proposal = _get_readable_proposal(&proposal_id)?;
+    match _get_dao(&proposal.dao_id) {
+        Some(dao) => {
+            if dao.owner != Some(caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Proposal(proposal_id),
+                    format!(
+                        "Couldn't get ballots of proposal with id={}. You are not the owner",
+                        proposal_id
+                    ),
+                ));
+            }
+            if !_get_settings(&dao.id).public_ballots {
+                return Err(Error::permission_error(ErrorContext::Dao(dao.id), format!(
+                        "Couldn't get ballots of proposal with id={}. Ballots are private in dao with id={}",
+                        proposal_id, dao.id
+                    )));
+            }
+            _check_tally_visible(&proposal)?;
+
+            Ok(Ballots {
+                proposal_id,
+                upvotes: _voters(&proposal_id, VoteDirection::Up),
+                downvotes: _voters(&proposal_id, VoteDirection::Down),
+            })
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(proposal.dao_id),
+            format!("a dao with id={} not found", proposal.dao_id),
+        )),
+    }
+}
+
+// Ability to see how many votes a proposal got, both as cast and weighted by role, in a DAO
+// you're part of. DAOs that hide the tally only show it once the vote is finalized
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_vote_breakdown(proposal_id: u64) -> Result<VoteBreakdown, Error> {
+    let proposal = _get_readable_proposal(&proposal_id)?;
+    let dao = _can_read_dao(&proposal.dao_id)?;
+    _check_tally_visible(&proposal)?;
+    let (weighted_upvotes, weighted_downvotes) = _vote_totals(
+        &proposal,
+        _get_settings(&dao.id).role_vote_weights.is_some(),
+    );
+    Ok(VoteBreakdown {
+        proposal_id,
+        upvotes: proposal.upvote_count,
+        downvotes: proposal.downvote_count,
+        weighted_upvotes,
+        weighted_downvotes,
+    })
+}
+
+// Ability to see how the votes on a proposal of a DAO you're a member of came in over time, in
+// buckets of `bucket_seconds` from its creation up to its deadline. Buckets are at least an hour
+// and at most the whole period, buckets without votes are listed too. Only votes with a vote
+// record have a time, so ballots cast before those existed are left out. DAOs that hide the
+// tally only show it once the vote is finalized
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_voting_timeline(
+    proposal_id: u64,
+    bucket_seconds: u64,
+) -> Result<Vec<TimelineBucket>, Error> {
+    let proposal = _get_readable_proposal(&proposal_id)?;
+    match _get_dao(&proposal.dao_id) {
+        Some(dao) if _is_member(&dao, &caller()) => {
+            _check_tally_visible(&proposal)?;
+            let start = proposal.created_at;
+            let period = proposal.deadline.saturating_sub(start);
+            let bucket = bucket_seconds
+                .clamp(
+                    MIN_TIMELINE_BUCKET_SECONDS,
+                    (period / 1_000_000_000).max(MIN_TIMELINE_BUCKET_SECONDS),
+                )
+                .saturating_mul(1_000_000_000);
+            let bucket_count = period.div_ceil(bucket).max(1);
+            let mut timeline: Vec<TimelineBucket> = (0..bucket_count)
+                .map(|index| TimelineBucket {
+                    start: start + index * bucket,
+                    ..Default::default()
+                })
+                .collect();
+
+            VOTE_STORAGE.with(|service| {
+                service
+                    .borrow()
+                    .range((proposal_id, PrincipalKey::default())..)
+                    .take_while(|((id, _), _)| *id == proposal_id)
+                    .filter(|(_, vote)| vote.ranking.is_none())
+                    .for_each(|(_, vote)| {
+                        let index = (vote.voted_at.saturating_sub(start) / bucket)
+                            .min(bucket_count - 1) as usize;
+                        match vote.direction {
+                            VoteDirection::Up => timeline[index].upvotes += 1,
+                            VoteDirection::Down => timeline[index].downvotes += 1,
+                        }
+                    })
+            });
+            Ok(timeline)
+        }
+        _ => Err(Error::not_a_member(
+            ErrorContext::Dao(proposal.dao_id),
+            format!(
+                "unable to get a dao with id={}. Not a member",
+                proposal.dao_id
+            ),
+        )),
+    }
+}
+
+// Ability to check that your vote was counted in a finalized proposal of a DAO you're part of.
+// Anyone with the ballots can recompute the digest, see certification::ballot_digest
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_vote_receipt(proposal_id: u64) -> Result<VoteReceipt, Error> {
+    let proposal = _get_readable_proposal(&proposal_id)?;
+    match proposal.final_tally {
+        Some(tally) => Ok(VoteReceipt {
+            proposal_id,
+            vote: _get_vote(&proposal_id, &caller()),
+            result_digest: proposal.result_digest,
+            tally,
+        }),
+        None => Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal_id),
+            format!(
+                "a proposal with id={} hasn't been finalized yet",
+                proposal_id
+            ),
+        )),
+    }
+}
+
+// Ability to get all proposals in the DAO. Pinned proposals come first, then the rest, each
+// sorted by created_at and then id. A DAO without proposals has an empty list. Exported by
+// routed_get_all_proposals
+fn get_all_proposals(dao_id: u64) -> Result<Vec<ProposalView>, Error> {
+    let mut proposals = _listed_proposals(&dao_id)?;
+
+    proposals.sort_by_key(|proposal| (!proposal.pinned, proposal.created_at, proposal.id));
+    Ok(proposals.into_iter().map(ProposalView::from).collect())
+}
+
+// Ability to page through the proposals of a DAO you can read in the order of `sort`, the ones
+// get_all_proposals lists. Ties are broken by id, newest first. Pinned proposals get no
+// precedence here
+#[ic_cdk::query(guard = "_check_can_read")]
+fn list_proposals(
+    dao_id: u64,
+    sort: SortBy,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<ProposalView>, Error> {
+    let mut proposals = _listed_proposals(&dao_id)?;
+
+    // the counters are kept on the record, so sorting needs nothing else
+    proposals.sort_by_key(|proposal| {
+        let key = match sort {
+            SortBy::Newest => proposal.created_at,
+            SortBy::RecentlyActive => proposal.last_activity_at,
+            SortBy::MostCommented => proposal.comment_count,
+        };
+        Reverse((key, proposal.id))
+    });
+    Ok(proposals
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .map(ProposalView::from)
+        .collect())
+}
+
+// a helper method to get the proposals of a DAO the caller can read, leaving out cancelled ones
+// and hidden ones unless the caller can see those
+fn _listed_proposals(dao_id: &u64) -> Result<Vec<Proposal>, Error> {
+    let dao = _can_read_dao(dao_id)?;
+    let can_see_hidden = _can_see_hidden(dao_id);
+    Ok(_dao_proposal_ids(&dao.id)
+        .iter()
+        .filter_map(_get_proposal)
+        .filter(|proposal| proposal.dao_id == *dao_id)
+        .filter(|proposal| !proposal.hidden || can_see_hidden)
+        .filter(|proposal| proposal.status != ProposalStatus::Cancelled)
+        .filter(|proposal| _can_see_proposal(proposal, &caller()))
+        .collect())
+}
+
+// Ability to get the approved proposals of a DAO you can read, in the order they were finalized.
+// Approved proposals that were already carried out are left out unless `include_executed` is set
+#[ic_cdk::query(guard = "_check_can_read")]
+fn list_approved_proposals(
+    dao_id: u64,
+    include_executed: bool,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<ProposalView>, Error> {
+    Ok(_approved_proposals(&dao_id, include_executed)?
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .map(ProposalView::from)
+        .collect())
+}
+
+// Ability to get all approved proposals in the order they were finalized. Kept for clients from
+// before list_approved_proposals, which pages through them and can leave out executed ones
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_final_approved_proposals(dao_id: u64) -> Result<Vec<ProposalView>, Error> {
+    Ok(_approved_proposals(&dao_id, true)?
+        .into_iter()
+        .map(ProposalView::from)
+        .collect())
+}
+
+// a helper method to get the approved proposals of a DAO the caller can read, sorted by
+// finalized_at and then id
+fn _approved_proposals(dao_id: &u64, include_executed: bool) -> Result<Vec<Proposal>, Error> {
+    let dao = _can_read_dao(dao_id)?;
+    let can_see_hidden = _can_see_hidden(dao_id);
+    let mut proposals: Vec<Proposal> = _dao_proposal_ids(&dao.id)
+        .iter()
+        .filter_map(_get_proposal)
+        .filter(|proposal| proposal.dao_id == *dao_id)
+        .filter(|proposal| proposal.status == ProposalStatus::Approved)
+        .filter(|proposal| !proposal.hidden || can_see_hidden)
+        .filter(|proposal| include_executed || proposal.executed_at.is_none())
+        .collect();
+
+    proposals.sort_by_key(|proposal| (proposal.finalized_at, proposal.id));
+    Ok(proposals)
+}
+
+// Ability to search proposals of a DAO by a keyword in their title or details
+#[ic_cdk::query(guard = "_check_can_read")]
+fn search_proposals(
+    dao_id: u64,
+    query: String,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<ProposalView>, Error> {
+    let query = _search_query(&query)?;
+
+    match _can_read_dao(&dao_id) {
+        Ok(dao) => {
+            let can_see_hidden = _is_dao_admin(&dao, &caller());
+
+            let mut proposals: Vec<Proposal> = _dao_proposal_ids(&dao.id)
+                .iter()
+                .filter_map(_get_proposal)
+                .filter(|proposal| !proposal.hidden || can_see_hidden)
+                .filter(|proposal| proposal.status != ProposalStatus::Cancelled)
+                .filter(|proposal| _can_see_proposal(proposal, &caller()))
+                .filter(|proposal| {
+                    proposal.title.to_lowercase().contains(&query)
+                        || proposal.details.to_lowercase().contains(&query)
+                })
+                .collect();
+            proposals.sort_by_key(|proposal| Reverse(proposal.created_at));
+
+            Ok(proposals
+                .into_iter()
+                .skip(offset as usize)
+                .take(limit.min(MAX_PAGE_SIZE) as usize)
+                .map(ProposalView::from)
+                .collect())
+        }
+        Err(error) => Err(error),
+    }
+}
+
+// Ability to search the comments of a proposal, or of every proposal of a DAO, you're part of by
+// a keyword in their content. Deleted and removed comments are left out, newest first
+#[ic_cdk::query(guard = "_check_can_read")]
+fn search_comments(
+    scope: CommentScope,
+    query: String,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<CommentMatch>, Error> {
+    let query = _search_query(&query)?;
+
+    let (dao, proposals) = match scope {
+        CommentScope::Proposal(proposal_id) => {
+            let proposal = _get_readable_proposal(&proposal_id)?;
+            (_can_read_dao(&proposal.dao_id)?, vec![proposal])
+        }
+        CommentScope::Dao(dao_id) => {
+            let dao = _can_read_dao(&dao_id)?;
+            let can_see_hidden = _is_dao_admin(&dao, &caller());
+            let proposals = _dao_proposal_ids(&dao.id)
+                .iter()
+                .filter_map(_get_proposal)
+                .filter(|proposal| !proposal.hidden || can_see_hidden)
+                .filter(|proposal| _can_see_proposal(proposal, &caller()))
+                .collect();
+            (dao, proposals)
+        }
+    };
+    let can_see_hidden = _is_dao_admin(&dao, &caller());
+
+    let mut matches: Vec<(Comment, String)> = proposals
+        .into_iter()
+        .flat_map(|proposal| {
+            let title = proposal.title;
+            _comment_ids(&proposal.id)
+                .iter()
+                .filter_map(_get_comment)
+                .filter(|comment| !comment.deleted && comment.removal.is_none())
+                .filter(|comment| !comment.hidden || can_see_hidden)
+                .filter(|comment| comment.content.to_lowercase().contains(&query))
+                .map(|comment| (comment, title.clone()))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    matches.sort_by_key(|(comment, _)| Reverse((comment.created_at, comment.id)));
+
+    let (comments, titles): (Vec<Comment>, Vec<String>) = matches
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .unzip();
+    Ok(_comment_views(comments)
+        .into_iter()
+        .zip(titles)
+        .map(|(comment, proposal_title)| CommentMatch {
+            proposal_id: comment.proposal_id,
+            proposal_title,
+            comment,
+        })
+        .collect())
+}
+
+// Ability to create a proposal that can be voted on within a week. When the DAO asks for a
+// proposal deposit it is moved from your account into the DAO's treasury with
+// icrc2_transfer_from, so this canister must have been approved to spend it first. Exported by
+// routed_add_proposal
+async fn add_proposal(proposal: ProposalPayload) -> Result<ProposalView, Error> {
+    _check_storage_available(ErrorContext::Dao(proposal.dao_id))?;
+    match _check_new_proposal(&proposal) {
+        Ok(CheckedProposal {
+            kind,
+            attachments,
+            milestones,
+            prerequisite,
+            proposal_limit,
+            co_authors,
+        }) => {
+            rate_limit::check_and_record(
+                &caller(),
+                proposal.dao_id,
+                proposal_limit,
+                rate_limit::PROPOSAL_WINDOW,
+                time(),
+            )?;
+
+            // the caller is read before the call, nothing is created if the transfer fails
+            let proposer = caller();
+            let deposit = _get_settings(&proposal.dao_id)
+                .proposal_deposit
+                .unwrap_or_default();
+            let deposit_block = if deposit > 0 {
+                Some(
+                    ledger::transfer_from(
+                        ErrorContext::Dao(proposal.dao_id),
+                        proposer,
+                        ledger::dao_treasury(proposal.dao_id),
+                        deposit,
+                    )
+                    .await?,
+                )
+            } else {
+                None
+            };
+
             let id = ID_COUNTER
                 .with(|counter| {
                     let current_value = *counter.borrow().get();
                     counter.borrow_mut().set(current_value + 1)
                 })
-                .expect("cannot increment id counter");
+                .expect("cannot increment id counter");
+
+            let mut proposal_number = 0;
+            match DAO_STORAGE.with(|service| service.borrow().get(&proposal.dao_id)) {
+                Some(mut dao) => {
+                    _list_dao_proposal(&mut dao, id);
+                    dao.updated_at = Some(time());
+                    proposal_number = _next_proposal_number(&mut dao);
+
+                    do_insert_dao(&mut dao);
+                }
+                None => (),
+            }
+
+            let publish = proposal.publish.unwrap_or(true);
+            let status = if !publish {
+                ProposalStatus::Draft
+            } else {
+                _status_after_publishing(prerequisite.as_ref())
+            };
+            let mut proposal = Proposal {
+                id,
+                title: proposal.title,
+                details: proposal.details,
+                kind,
+                owner: Some(proposer),
+                created_at: time(),
+                // set by _start_voting, drafts and blocked proposals don't have one yet
+                deadline: 0,
+                updated_at: None,
+                upvote_count: 0,
+                is_approved: false,
+                dao_id: proposal.dao_id,
+                comment_count: 0,
+                downvote_count: 0,
+                hidden: false,
+                pinned: false,
+                comments_locked: false,
+                status,
+                schema_version: migrations::SCHEMA_VERSION,
+                version: 0,
+                depends_on: proposal.depends_on,
+                voting_starts_at: proposal.voting_starts_at,
+                result_digest: Vec::new(),
+                final_tally: None,
+                last_activity_at: time(),
+                unique_participants: 0,
+                executed_at: None,
+                removal: None,
+                anonymous_ballot: proposal.anonymous_ballot.unwrap_or_default(),
+                slug: String::new(),
+                proposal_number,
+                comments_close_at: proposal
+                    .comment_window_seconds
+                    .map(|window| time() + window * 1_000_000_000),
+                deposit: (deposit > 0).then_some(ProposalDeposit {
+                    amount: deposit,
+                    status: DepositStatus::Held,
+                    settled_at: None,
+                }),
+                stance_counts: StanceCounts::default(),
+                finalized_at: None,
+                execution_block: None,
+                cancel_reason: None,
+                closed_early: None,
+                ranked_result: None,
+            };
+            _assign_proposal_slug(&mut proposal);
+            _index_proposal_number(&proposal);
+            // the DAO may have filled up while the deposit was being transferred, the proposal is
+            // queued then rather than failing after taking the deposit
+            if proposal.status == ProposalStatus::Open {
+                _open_or_queue(&mut proposal);
+            }
+
+            do_insert_proposal(&mut proposal);
+            _set_attachments(&id, attachments);
+            _set_milestones(&id, milestones);
+            _follow(&id, &proposer);
+            _set_co_authors(&proposal, co_authors);
+            if deposit_block.is_some() {
+                treasury::record(
+                    proposal.dao_id,
+                    treasury::TreasuryTxKind::Deposit,
+                    deposit,
+                    Some(proposer),
+                    Some(id),
+                    deposit_block,
+                );
+            }
+            if let Some(voting_starts_at) = proposal.voting_starts_at {
+                maintenance::schedule_opening(id, voting_starts_at);
+            }
+            log_event(
+                proposal.dao_id,
+                AuditAction::ProposalCreated,
+                AuditTarget::Proposal(id),
+            );
+            _count_activity(proposal.dao_id, &proposer, ActivityKind::Proposal);
+            Ok(ProposalView::from(proposal))
+        }
+        Err(error) => Err(error),
+    }
+}
+
+// Ability to find out whether add_proposal would accept a payload without creating anything.
+// Runs the same checks, including whether you can pay the DAO's proposal deposit, and fails
+// with the error add_proposal would return
+#[ic_cdk::query(composite = true, guard = "_check_can_update")]
+async fn can_create_proposal(proposal: ProposalPayload) -> Result<(), Error> {
+    let checked = _check_new_proposal(&proposal)?;
+    rate_limit::check(
+        &caller(),
+        proposal.dao_id,
+        checked.proposal_limit,
+        rate_limit::PROPOSAL_WINDOW,
+        time(),
+    )?;
+
+    let deposit = _get_settings(&proposal.dao_id)
+        .proposal_deposit
+        .unwrap_or_default();
+    if deposit > 0 {
+        ledger::check_can_pay(ErrorContext::Dao(proposal.dao_id), caller(), deposit).await?;
+    }
+    Ok(())
+}
+
+// Ability to update a proposal provided you're the owner or a co-author and the deadline hasn't
+// passed. Only the owner can change the co-authors
+#[ic_cdk::update(guard = "_check_can_update")]
+fn update_proposal(
+    id: u64,
+    payload: ProposalPayload,
+    expected_version: Option<u64>,
+) -> Result<ProposalView, Error> {
+    match PROPOSAL_STORAGE.with(|service| service.borrow().get(&id)) {
+        Some(mut proposal) => {
+            if !_is_author(&proposal, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Proposal(id),
+                    format!(
+                        "Couldn't update proposal with id={}. You are not the owner or a co-author",
+                        id
+                    ),
+                ));
+            }
+            if payload.co_authors.is_some() && !_is_owner(&proposal.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Proposal(id),
+                    format!(
+                        "Couldn't change the co-authors of proposal with id={}. You are not the owner",
+                        id
+                    ),
+                ));
+            }
+            _check_dao_not_archived(&proposal.dao_id)?;
+            if proposal.status == ProposalStatus::Cancelled {
+                return Err(Error::invalid_input(
+                    ErrorContext::Proposal(id),
+                    format!("Couldn't update proposal with id={}. It was cancelled", id),
+                ));
+            }
+            // drafts, blocked and queued proposals have no deadline yet
+            if !matches!(
+                proposal.status,
+                ProposalStatus::Draft | ProposalStatus::Blocked | ProposalStatus::Queued
+            ) && _is_deadline_passed(proposal.deadline)
+            {
+                return Err(Error::deadline_exceeded(
+                    ErrorContext::Proposal(id),
+                    format!(
+                        "Couldn't update a proposal with id={}. Deadline exceeded",
+                        id
+                    ),
+                ));
+            }
+            _check_version(
+                ErrorContext::Proposal(id),
+                proposal.version,
+                expected_version,
+            )?;
+            _validate_proposal_text(&payload)?;
+            let kind = _proposal_kind(&payload)?;
+            let previous_milestones = _get_milestones(&id);
+            let milestones = match payload.milestones {
+                Some(milestones) => milestones.into_iter().map(Milestone::from).collect(),
+                None => previous_milestones.clone(),
+            };
+            _validate_milestones(&kind, &milestones)?;
+            let co_authors = match payload.co_authors {
+                Some(co_authors) => Some(_validate_co_authors(
+                    proposal.dao_id,
+                    &proposal.owner.unwrap_or(caller()),
+                    co_authors,
+                )?),
+                None => None,
+            };
+
+            if let Some(attachments) = payload.attachments {
+                _validate_attachments(&attachments)?;
+                _set_attachments(&id, attachments);
+            }
+            _set_milestones(&id, milestones.clone());
+            if let Some(co_authors) = co_authors {
+                _set_co_authors(&proposal, co_authors);
+            }
+
+            let previous = proposal.clone();
+            proposal.title = payload.title;
+            proposal.details = payload.details;
+            proposal.kind = kind;
+            proposal.updated_at = Some(time());
+            if proposal.title != previous.title {
+                _assign_proposal_slug(&mut proposal);
+            }
+
+            let has_votes = previous.upvote_count + previous.downvote_count > 0;
+            let content_changed = proposal.title != previous.title
+                || proposal.details != previous.details
+                || proposal.kind != previous.kind
+                || milestones != previous_milestones;
+            if has_votes && content_changed {
+                _amend_proposal(&mut proposal, &previous);
+            }
+
+            do_insert_proposal(&mut proposal);
+            log_event(
+                proposal.dao_id,
+                AuditAction::ProposalUpdated,
+                AuditTarget::Proposal(id),
+            );
+            Ok(ProposalView::from(proposal))
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(id),
+            format!(
+                "couldn't update a proposal with id={}. proposal not found",
+                id
+            ),
+        )),
+    }
+}
+
+// Ability to publish a draft proposal provided you're the owner or a co-author. Voting runs
+// from the moment it is published
+#[ic_cdk::update(guard = "_check_can_update")]
+fn publish_proposal(id: u64) -> Result<ProposalView, Error> {
+    match _get_proposal(&id) {
+        Some(mut proposal) => {
+            if !_is_author(&proposal, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Proposal(id),
+                    format!(
+                        "Couldn't publish proposal with id={}. You are not the owner or a co-author",
+                        id
+                    ),
+                ));
+            }
+            _check_dao_not_archived(&proposal.dao_id)?;
+            if proposal.status != ProposalStatus::Draft {
+                return Err(Error::invalid_input(
+                    ErrorContext::Proposal(id),
+                    format!("proposal with id={} is already published", id),
+                ));
+            }
+
+            let prerequisite = match proposal.depends_on {
+                Some(prerequisite_id) => Some(_validate_dependency(
+                    &proposal.dao_id,
+                    &prerequisite_id,
+                    Some(id),
+                )?),
+                None => None,
+            };
+            proposal.status = _status_after_publishing(prerequisite.as_ref());
+            if proposal.status == ProposalStatus::Open {
+                _open_or_queue(&mut proposal);
+            }
+            proposal.updated_at = Some(time());
+
+            do_insert_proposal(&mut proposal);
+            log_event(
+                proposal.dao_id,
+                AuditAction::ProposalPublished,
+                AuditTarget::Proposal(id),
+            );
+            Ok(ProposalView::from(proposal))
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(id),
+            format!(
+                "couldn't publish a proposal with id={}. proposal not found",
+                id
+            ),
+        )),
+    }
+}
+
+// Ability to open a scheduled proposal for voting right away provided you're the owner. The
+// voting period starts now
+#[ic_cdk::update(guard = "_check_can_update")]
+fn open_proposal_now(id: u64) -> Result<ProposalView, Error> {
+    match _get_proposal(&id) {
+        Some(mut proposal) => {
+            if !_is_owner(&proposal.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Proposal(id),
+                    format!(
+                        "Couldn't open proposal with id={}. You are not the owner",
+                        id
+                    ),
+                ));
+            }
+            _check_dao_not_archived(&proposal.dao_id)?;
+            if proposal.status != ProposalStatus::Scheduled {
+                return Err(Error::invalid_input(
+                    ErrorContext::Proposal(id),
+                    format!("proposal with id={} is not scheduled", id),
+                ));
+            }
+
+            proposal.status = ProposalStatus::Open;
+            proposal.deadline = time() + _voting_period(&proposal.dao_id);
+            proposal.updated_at = Some(time());
+
+            do_insert_proposal(&mut proposal);
+            maintenance::schedule_reminder(id, proposal.deadline);
+            log_event(
+                proposal.dao_id,
+                AuditAction::ProposalOpened,
+                AuditTarget::Proposal(id),
+            );
+            Ok(ProposalView::from(proposal))
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(id),
+            format!(
+                "couldn't open a proposal with id={}. proposal not found",
+                id
+            ),
+        )),
+    }
+}
+
+// Ability to see how a proposal was changed after voting on it started
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_proposal_amendments(proposal_id: u64) -> Result<Vec<Amendment>, Error> {
+    _get_readable_proposal(&proposal_id).map(|_| _get_amendments(&proposal_id))
+}
+
+// Ability to make a vote set aside by an amendment count again, provided the proposal can
+// still be voted on
+#[ic_cdk::update(guard = "_check_can_update")]
+fn reconfirm_vote(proposal_id: u64) -> Result<ProposalView, Error> {
+    match _get_proposal(&proposal_id) {
+        Some(mut proposal) => {
+            _check_if_can_vote(&proposal, &proposal.dao_id, &caller(), time())?;
+            let key = (proposal_id, principal_key(&caller()));
+            let mut vote = match PENDING_RECONFIRMATIONS.with(|service| service.borrow().get(&key))
+            {
+                Some(vote) => vote,
+                None => {
+                    return Err(Error::not_found(
+                        ErrorContext::Proposal(proposal_id),
+                        format!(
+                            "you have no vote awaiting reconfirmation on a proposal with id={}",
+                            proposal_id
+                        ),
+                    ))
+                }
+            };
+
+            vote.voted_at = time();
+            _record_vote(&mut proposal, &vote);
+
+            do_insert_proposal(&mut proposal);
+            PENDING_RECONFIRMATIONS.with(|service| service.borrow_mut().remove(&key));
+            log_event(
+                proposal.dao_id,
+                AuditAction::VoteReconfirmed,
+                AuditTarget::Proposal(proposal_id),
+            );
+            Ok(ProposalView::from(proposal))
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(proposal_id),
+            format!("a proposal with id={} not found", proposal_id),
+        )),
+    }
+}
+
+// Ability to upvote a proposal provided you're not the owner, you haven't voted and the deadline hasn't passed.
+// Exported by routed_upvote
+fn upvote(id: u64) -> Result<ProposalView, Error> {
+    let _profile = metrics::profile("upvote");
+    service::cast_vote(id, VoteDirection::Up, &caller(), time()).map(ProposalView::from)
+}
+
+// Ability to downvote a proposal provided you're not the owner, you haven't voted and the deadline hasn't passed.
+// Exported by routed_downvote
+fn downvote(id: u64) -> Result<ProposalView, Error> {
+    service::cast_vote(id, VoteDirection::Down, &caller(), time()).map(ProposalView::from)
+}
+
+// Ability to find out whether upvote or downvote would accept your vote on a proposal, without
+// casting it. Fails with the error they would return
+#[ic_cdk::query(guard = "_check_can_update")]
+fn can_vote(proposal_id: u64) -> Result<(), Error> {
+    service::check_can_cast_vote(proposal_id, &caller(), time()).map(|_| ())
+}
+
+// Ability to vote on several proposals at once. Every vote is applied on its own and keeps
+// its result, so a failing item doesn't undo or block the others
+#[ic_cdk::update(guard = "_check_can_update")]
+fn cast_votes(votes: Vec<(u64, VoteDirection)>) -> Vec<(u64, Result<(), Error>)> {
+    let (voter, now) = (caller(), time());
+    votes
+        .into_iter()
+        .enumerate()
+        .map(|(position, (id, direction))| {
+            if position >= MAX_VOTES_PER_BATCH {
+                return (
+                    id,
+                    Err(Error::invalid_input(ErrorContext::Proposal(id), format!(
+                            "couldn't vote on a proposal with id={}. at most {} votes can be cast per call",
+                            id, MAX_VOTES_PER_BATCH
+                        ))),
+                );
+            }
+            (
+                id,
+                service::cast_vote(id, direction, &voter, now).map(|_| ()),
+            )
+        })
+        .collect()
+}
+
+// Ability to vote on a ranked-choice proposal by ranking its options, most preferred first, on
+// the same terms as upvote/downvote. `ranking` holds option indexes, options left out aren't
+// ranked at all. The ballots are counted by instant runoff when the vote is ended
+#[ic_cdk::update(guard = "_check_can_update")]
+fn vote_option(proposal_id: u64, ranking: Vec<u8>) -> Result<ProposalView, Error> {
+    match _get_proposal(&proposal_id) {
+        Some(mut proposal) => {
+            let option_count = _check_can_rank(&proposal)?;
+            ranked::validate_ranking(&ranking, option_count)
+                .map_err(|msg| Error::invalid_input(ErrorContext::Proposal(proposal_id), msg))?;
+
+            _record_activity(&mut proposal, &caller(), time());
+            do_insert_proposal(&mut proposal);
+            let weight = _get_dao(&proposal.dao_id)
+                .map(|dao| {
+                    _get_settings(&dao.id)
+                        .role_vote_weights
+                        .unwrap_or_default()
+                        .weight_of(_get_member_role(&dao, &caller()))
+                })
+                .unwrap_or(1);
+            do_insert_vote(&Vote {
+                proposal_id,
+                voter: Some(caller()),
+                direction: VoteDirection::default(),
+                voted_at: time(),
+                weight: Some(weight),
+                ranking: Some(ranking),
+            });
+            reputation::award(proposal.dao_id, &caller(), reputation::VOTE_CAST_POINTS);
+            _count_activity(proposal.dao_id, &caller(), ActivityKind::Vote);
+            if _get_preferences(&caller()).auto_follow_on_vote {
+                _follow(&proposal_id, &caller());
+            }
+            log_event(
+                proposal.dao_id,
+                AuditAction::VoteCast,
+                AuditTarget::Proposal(proposal_id),
+            );
+            if let Some(owner) = proposal.owner {
+                notify(
+                    &owner,
+                    NotificationKind::NewVote,
+                    proposal.dao_id,
+                    Some(proposal_id),
+                    None,
+                );
+            }
+            Ok(ProposalView::from(proposal))
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(proposal_id),
+            format!(
+                "couldn't vote on a proposal with id={}. proposal not found",
+                proposal_id
+            ),
+        )),
+    }
+}
+
+// Ability to settle a tie the count of your ranked-choice proposal stopped at, by picking the
+// tied option that stays in the count. The others are dropped and the count goes on, the result
+// records that you decided it
+#[ic_cdk::update(guard = "_check_can_update")]
+fn resolve_ranked_tie(proposal_id: u64, option: u8) -> Result<ProposalView, Error> {
+    match _get_proposal(&proposal_id) {
+        Some(mut proposal) => {
+            if !_is_owner(&proposal.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Proposal(proposal_id),
+                    format!(
+                        "Couldn't settle a tie on proposal with id={}. You are not the owner",
+                        proposal_id
+                    ),
+                ));
+            }
+            _check_dao_not_archived(&proposal.dao_id)?;
+            let option_count = match &proposal.kind {
+                ProposalKind::RankedChoice { options } => options.len(),
+                _ => 0,
+            };
+            let result = proposal
+                .ranked_result
+                .clone()
+                .filter(|result| !result.pending_tie().is_empty());
+            let mut owner_decisions = match result {
+                Some(result) if result.pending_tie().contains(&option) => {
+                    result.owner_decisions().to_vec()
+                }
+                Some(result) => {
+                    return Err(Error::invalid_input(
+                        ErrorContext::Proposal(proposal_id),
+                        format!(
+                            "option {} isn't one of the tied options {:?}",
+                            option,
+                            result.pending_tie()
+                        ),
+                    ))
+                }
+                None => {
+                    return Err(Error::invalid_input(
+                        ErrorContext::Proposal(proposal_id),
+                        format!("proposal with id={} has no tie to settle", proposal_id),
+                    ))
+                }
+            };
+
+            owner_decisions.push(option);
+            let weighted = _get_settings(&proposal.dao_id).role_vote_weights.is_some();
+            proposal.ranked_result = Some(ranked::instant_runoff(
+                option_count,
+                &_ranked_ballots(&proposal_id, weighted),
+                &owner_decisions,
+            ));
+            proposal.updated_at = Some(time());
+
+            do_insert_proposal(&mut proposal);
+            log_event(
+                proposal.dao_id,
+                AuditAction::RankedTieResolved,
+                AuditTarget::Proposal(proposal_id),
+            );
+            Ok(ProposalView::from(proposal))
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(proposal_id),
+            format!("a proposal with id={} not found", proposal_id),
+        )),
+    }
+}
+
+// Ability to vote on a proposal with an anonymous ballot, on the same terms as upvote/downvote.
+// `commitment` is sha256(direction || salt || principal), with direction 0 for up and 1 for down,
+// a salt of 16 to 64 bytes you keep and the bytes of your principal. Reveal the vote with
+// reveal_vote once the deadline has passed, until then nobody can tell how you voted
+#[ic_cdk::update(guard = "_check_can_update")]
+fn commit_vote(proposal_id: u64, commitment: Vec<u8>) -> Result<ProposalView, Error> {
+    features::check_enabled(features::Feature::AnonymousBallots)?;
+    match _get_proposal(&proposal_id).filter(|proposal| proposal.anonymous_ballot) {
+        Some(mut proposal) => {
+            _check_if_can_vote(&proposal, &proposal.dao_id, &caller(), time())?;
+            let key = (proposal_id, principal_key(&caller()));
+            if BALLOT_COMMITMENTS.with(|service| service.borrow().contains_key(&key)) {
+                return Err(Error::has_voted(
+                    ErrorContext::Proposal(proposal_id),
+                    format!(
+                        "Couldn't vote on a proposal with id={}. user voted already",
+                        proposal_id
+                    ),
+                ));
+            }
+            if commitment.len() != 32 {
+                return Err(Error::invalid_input(
+                    ErrorContext::Proposal(proposal_id),
+                    "commitment must be a 32 byte sha256 hash".to_string(),
+                ));
+            }
+
+            BALLOT_COMMITMENTS.with(|service| {
+                service.borrow_mut().insert(
+                    key,
+                    BallotCommitment {
+                        voter: caller(),
+                        commitment,
+                        committed_at: time(),
+                        revealed: false,
+                    },
+                )
+            });
+            _record_activity(&mut proposal, &caller(), time());
+            do_insert_proposal(&mut proposal);
+            log_event(
+                proposal.dao_id,
+                AuditAction::VoteCommitted,
+                AuditTarget::Proposal(proposal_id),
+            );
+            Ok(ProposalView::from(proposal))
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(proposal_id),
+            format!(
+                "couldn't vote on a proposal with id={}. no anonymous ballot with this id",
+                proposal_id
+            ),
+        )),
+    }
+}
+
+// Ability to reveal a vote committed with commit_vote, between the deadline and the end of the
+// DAO's reveal window. The vote counts from then on, commitments never revealed don't count
+#[ic_cdk::update(guard = "_check_can_update")]
+fn reveal_vote(
+    proposal_id: u64,
+    direction: VoteDirection,
+    salt: Vec<u8>,
+) -> Result<ProposalView, Error> {
+    let mut proposal =
+        match _get_proposal(&proposal_id).filter(|proposal| proposal.anonymous_ballot) {
+            Some(proposal) => proposal,
+            None => {
+                return Err(Error::not_found(
+                    ErrorContext::Proposal(proposal_id),
+                    format!(
+                "couldn't reveal a vote on proposal with id={}. no anonymous ballot with this id",
+                proposal_id
+            ),
+                ))
+            }
+        };
+    let context = ErrorContext::Proposal(proposal_id);
+    if proposal.status != ProposalStatus::Open {
+        return Err(Error::invalid_input(
+            context,
+            format!(
+                "Couldn't reveal a vote on proposal with id={}. It is not open",
+                proposal_id
+            ),
+        ));
+    }
+    if !service::is_deadline_passed(proposal.deadline, time()) {
+        return Err(Error::deadline_not_exceeded(
+            context,
+            format!(
+                "Votes on proposal with id={} can be revealed once its deadline has passed",
+                proposal_id
+            ),
+        ));
+    }
+    let reveal_window = _get_settings(&proposal.dao_id).reveal_window();
+    if service::is_deadline_passed(proposal.deadline.saturating_add(reveal_window), time()) {
+        return Err(Error::deadline_exceeded(
+            context,
+            format!(
+                "Couldn't reveal a vote on proposal with id={}. The reveal window is over",
+                proposal_id
+            ),
+        ));
+    }
+
+    let key = (proposal_id, principal_key(&caller()));
+    let mut commitment = match BALLOT_COMMITMENTS.with(|service| service.borrow().get(&key)) {
+        Some(commitment) => commitment,
+        None => {
+            return Err(Error::not_found(
+                context,
+                format!(
+                    "you didn't commit a vote on proposal with id={}",
+                    proposal_id
+                ),
+            ))
+        }
+    };
+    if commitment.revealed {
+        return Err(Error::has_voted(
+            context,
+            format!(
+                "Couldn't reveal a vote on proposal with id={}. It was revealed already",
+                proposal_id
+            ),
+        ));
+    }
+    if !(MIN_BALLOT_SALT_LEN..=MAX_BALLOT_SALT_LEN).contains(&salt.len())
+        || certification::ballot_commitment(direction, &salt, &caller()) != commitment.commitment
+    {
+        return Err(Error::invalid_input(
+            context,
+            format!(
+                "Couldn't reveal a vote on proposal with id={}. It doesn't match your commitment",
+                proposal_id
+            ),
+        ));
+    }
+
+    commitment.revealed = true;
+    BALLOT_COMMITMENTS.with(|service| service.borrow_mut().insert(key, commitment.clone()));
+    let weight = _get_dao(&proposal.dao_id)
+        .map(|dao| {
+            _get_settings(&dao.id)
+                .role_vote_weights
+                .unwrap_or_default()
+                .weight_of(_get_member_role(&dao, &caller()))
+        })
+        .unwrap_or(1);
+    _record_vote(
+        &mut proposal,
+        &Vote {
+            proposal_id,
+            voter: Some(caller()),
+            direction,
+            voted_at: commitment.committed_at,
+            weight: Some(weight),
+            ranking: None,
+        },
+    );
+    do_insert_proposal(&mut proposal);
+    reputation::award(proposal.dao_id, &caller(), reputation::VOTE_CAST_POINTS);
+    _count_activity(proposal.dao_id, &caller(), ActivityKind::Vote);
+    log_event(
+        proposal.dao_id,
+        AuditAction::VoteCast,
+        AuditTarget::Proposal(proposal_id),
+    );
+    Ok(ProposalView::from(proposal))
+}
+
+// Ability to end a proposal provided you're the owner and the deadline has passed. Exported by
+// routed_end_proposal_vote
+fn end_proposal_vote(id: u64) -> Result<ProposalView, Error> {
+    let _profile = metrics::profile("end_proposal_vote");
+    service::end_vote(id, &caller(), time()).map(ProposalView::from)
+}
+
+// a helper method to end the vote on a proposal and record its outcome. Used by
+// end_proposal_vote and, with the `closed_early` record, when a downvote makes a proposal
+// clearly fail, which rejects it whatever the tally. A vote is only ever ended once, a proposal
+// that is already approved or rejected is returned as it is
+fn _finalize_vote(
+    mut proposal: Proposal,
+    closed_early: Option<EarlyClosure>,
+    now: u64,
+) -> Proposal {
+    if matches!(
+        proposal.status,
+        ProposalStatus::Approved | ProposalStatus::Rejected
+    ) {
+        return proposal;
+    }
+    let id = proposal.id;
+    let settings = _get_settings(&proposal.dao_id);
+    let weighted = settings.role_vote_weights.is_some();
+    let member_count = _get_dao(&proposal.dao_id)
+        .map(|dao| dao.member_count)
+        .unwrap_or_default();
+    _recount_votes(&mut proposal);
+    let (upvotes, downvotes) = _vote_totals(&proposal, weighted);
+    let ranked_ballots = match &proposal.kind {
+        ProposalKind::RankedChoice { options } => {
+            Some((options.len(), _ranked_ballots(&id, weighted)))
+        }
+        _ => None,
+    };
+    let voter_count = match &ranked_ballots {
+        Some((_, ballots)) => ballots.len() as u64,
+        None => proposal.upvote_count + proposal.downvote_count,
+    };
+    let quorum_met = service::meets_quorum(voter_count, member_count, settings.quorum_percent);
+    proposal.ranked_result = ranked_ballots
+        .map(|(option_count, ballots)| ranked::instant_runoff(option_count, &ballots, &[]));
+    proposal.status = if quorum_met && closed_early.is_none() {
+        match &proposal.ranked_result {
+            // approved once the count found a winner, or only waits for the owner to settle a tie
+            Some(result) if result.winner().is_some() || !result.pending_tie().is_empty() => {
+                ProposalStatus::Approved
+            }
+            Some(_) => ProposalStatus::Rejected,
+            None => service::tally(upvotes, downvotes, settings.approval_threshold_percent),
+        }
+    } else {
+        ProposalStatus::Rejected
+    };
+    // the vote is over from now on, the deadline it had is kept with the closure
+    if let Some(closure) = closed_early {
+        proposal.deadline = now;
+        proposal.closed_early = Some(closure);
+    }
+    proposal.is_approved = proposal.status == ProposalStatus::Approved;
+    let (weighted_upvotes, weighted_downvotes) = _vote_totals(&proposal, true);
+    proposal.final_tally = Some(VoteBreakdown {
+        proposal_id: id,
+        upvotes: proposal.upvote_count,
+        downvotes: proposal.downvote_count,
+        weighted_upvotes,
+        weighted_downvotes,
+    });
+    proposal.result_digest = certification::ballot_digest(_weighted_ballots(&proposal));
+    let refund_due = _settle_deposit(&mut proposal, quorum_met, now);
+    proposal.finalized_at = Some(now);
+
+    do_insert_proposal(&mut proposal);
+    if refund_due {
+        // a failed refund stays pending and can be retried with claim_deposit_refund
+        ic_cdk::spawn(async move {
+            let _ = _refund_deposit(id).await;
+        });
+    }
+    webhooks::notify_finalized(&proposal);
+    // the queue goes first, its proposals were waiting before the dependents this unblocks
+    _promote_queued(proposal.dao_id);
+    _resolve_dependents(&proposal);
+    if proposal.is_approved {
+        if let Some(owner) = proposal.owner {
+            reputation::award(
+                proposal.dao_id,
+                &owner,
+                reputation::PROPOSAL_APPROVED_POINTS,
+            );
+        }
+    }
+    log_event(
+        proposal.dao_id,
+        AuditAction::ProposalFinalized,
+        AuditTarget::Proposal(id),
+    );
+    let voters: Vec<Principal> = _votes(&id).iter().filter_map(|vote| vote.voter).collect();
+    let kind = if proposal.closed_early.is_some() {
+        NotificationKind::ProposalClosedEarly
+    } else {
+        NotificationKind::ProposalFinalized
+    };
+    _notify_followers(&proposal, kind, None, &voters, &[]);
+    proposal
+}
+
+// Ability to get the deposit of your proposal back when it reached quorum but paying it back
+// when the proposal was finalized failed
+#[ic_cdk::update(guard = "_check_can_update")]
+async fn claim_deposit_refund(proposal_id: u64) -> Result<ProposalView, Error> {
+    match _get_proposal(&proposal_id) {
+        Some(proposal) if proposal.owner == Some(caller()) => (),
+        Some(_) => {
+            return Err(Error::permission_error(
+                ErrorContext::Proposal(proposal_id),
+                format!(
+                    "Couldn't claim the deposit of proposal with id={}. You are not the owner",
+                    proposal_id
+                ),
+            ))
+        }
+        None => {
+            return Err(Error::not_found(
+                ErrorContext::Proposal(proposal_id),
+                format!("a proposal with id={} not found", proposal_id),
+            ))
+        }
+    }
+    _refund_deposit(proposal_id).await.map(ProposalView::from)
+}
+
+// a helper method to settle the deposit of a proposal being finalized. It is owed back when the
+// proposal reached quorum and forfeited otherwise, staying in the DAO's treasury where it
+// already is. Returns whether a refund is due. Finalizing again leaves a settled deposit as it is
+fn _settle_deposit(proposal: &mut Proposal, quorum_met: bool, now: u64) -> bool {
+    let deposit = match proposal.deposit.as_mut() {
+        Some(deposit) if deposit.status == DepositStatus::Held => deposit,
+        _ => return false,
+    };
+    if quorum_met {
+        deposit.status = DepositStatus::RefundPending;
+        return true;
+    }
+    deposit.status = DepositStatus::Forfeited;
+    deposit.settled_at = Some(now);
+    treasury::record(
+        proposal.dao_id,
+        treasury::TreasuryTxKind::DepositForfeit,
+        deposit.amount,
+        proposal.owner,
+        Some(proposal.id),
+        None,
+    );
+    log_event(
+        proposal.dao_id,
+        AuditAction::DepositForfeited,
+        AuditTarget::Proposal(proposal.id),
+    );
+    false
+}
+
+// a helper method to pay a pending deposit back to its proposer out of the DAO's treasury
+async fn _refund_deposit(id: u64) -> Result<Proposal, Error> {
+    let mut proposal = match _get_proposal(&id) {
+        Some(proposal) => proposal,
+        None => {
+            return Err(Error::not_found(
+                ErrorContext::Proposal(id),
+                format!("a proposal with id={} not found", id),
+            ))
+        }
+    };
+    let _lock = _lock_execution(id, locks::LockedOperation::DepositRefund)?;
+    let (amount, owner) = match (&proposal.deposit, proposal.owner) {
+        (Some(deposit), Some(owner)) if deposit.status == DepositStatus::RefundPending => {
+            (deposit.amount, owner)
+        }
+        _ => {
+            return Err(Error::invalid_input(
+                ErrorContext::Proposal(id),
+                format!("proposal with id={} has no deposit to refund", id),
+            ))
+        }
+    };
+
+    // marked before the call so a second refund can't start while this one waits for the
+    // ledger, and set back to pending if the payment fails
+    proposal.deposit = Some(ProposalDeposit {
+        amount,
+        status: DepositStatus::Refunded,
+        settled_at: Some(time()),
+    });
+    do_insert_proposal(&mut proposal);
+    // the treasury holds the deposit and nothing more for it, so the ledger fee of paying it
+    // back comes out of the deposit
+    let context = ErrorContext::Proposal(id);
+    let dao_id = proposal.dao_id;
+    let paid = async {
+        let fee = ledger::fee(context).await?;
+        let refund = u64::try_from(&fee.0).map_or(0, |fee| amount.saturating_sub(fee));
+        let to = ledger::Account {
+            owner,
+            subaccount: None,
+        };
+        ledger::transfer_from_treasury(context, dao_id, to, refund).await
+    }
+    .await;
+    proposal = _get_proposal(&id).unwrap_or(proposal);
+    let block_index = match paid {
+        Ok(block_index) => block_index,
+        Err(error) => {
+            if let Some(deposit) = proposal.deposit.as_mut() {
+                deposit.status = DepositStatus::RefundPending;
+                deposit.settled_at = None;
+            }
+            do_insert_proposal(&mut proposal);
+            return Err(error);
+        }
+    };
+    treasury::record(
+        proposal.dao_id,
+        treasury::TreasuryTxKind::Refund,
+        amount,
+        Some(owner),
+        Some(id),
+        Some(block_index),
+    );
+    log_event(
+        proposal.dao_id,
+        AuditAction::DepositRefunded,
+        AuditTarget::Proposal(id),
+    );
+    Ok(proposal)
+}
+
+// Ability to carry out an approved proposal providing you're the owner or an admin of its DAO.
+// Funding proposals are paid out of the DAO's treasury and MembershipChange proposals add or
+// remove their target. Signal proposals have nothing to carry out. A proposal is executed once
+#[ic_cdk::update(guard = "_check_can_update")]
+async fn execute_proposal(id: u64) -> Result<ProposalView, Error> {
+    let mut proposal = match _get_proposal(&id) {
+        Some(proposal) => proposal,
+        None => {
+            return Err(Error::not_found(
+                ErrorContext::Proposal(id),
+                format!("a proposal with id={} not found", id),
+            ))
+        }
+    };
+    let mut dao = match _get_dao(&proposal.dao_id) {
+        Some(dao) => dao,
+        None => {
+            return Err(Error::not_found(
+                ErrorContext::Dao(proposal.dao_id),
+                format!("a dao with id={} not found", proposal.dao_id),
+            ))
+        }
+    };
+    if !_is_dao_admin(&dao, &caller()) {
+        return Err(Error::permission_error(
+            ErrorContext::Proposal(id),
+            format!(
+                "Couldn't execute proposal with id={}. You are not an admin",
+                id
+            ),
+        ));
+    }
+    let _lock = _lock_execution(id, locks::LockedOperation::Execution)?;
+    _check_not_archived(&dao)?;
+    if proposal.status != ProposalStatus::Approved {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(id),
+            format!("proposal with id={} hasn't been approved", id),
+        ));
+    }
+    if proposal.executed_at.is_some() {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(id),
+            format!("proposal with id={} was already executed", id),
+        ));
+    }
+    let executable_at = proposal
+        .deadline
+        .saturating_add(_get_settings(&proposal.dao_id).timelock_seconds * 1_000_000_000);
+    if time() < executable_at {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(id),
+            format!(
+                "proposal with id={} is timelocked until {}",
+                id, executable_at
+            ),
+        ));
+    }
+
+    match proposal.kind.clone() {
+        ProposalKind::Signal => {
+            return Err(Error::invalid_input(
+                ErrorContext::Proposal(id),
+                format!(
+                    "proposal with id={} is a signal proposal, there is nothing to execute",
+                    id
+                ),
+            ))
+        }
+        ProposalKind::RankedChoice { .. } => {
+            return Err(Error::invalid_input(
+                ErrorContext::Proposal(id),
+                format!(
+                    "proposal with id={} is a ranked-choice proposal, there is nothing to execute",
+                    id
+                ),
+            ))
+        }
+        ProposalKind::MembershipChange { target, action } => {
+            _check_membership_change(&dao, &target, action)?;
+            match action {
+                MembershipAction::Add => _admit_member(&mut dao, &target),
+                MembershipAction::Remove => {
+                    _set_membership(&mut dao, &target, None);
+                    dao.updated_at = Some(time());
+                }
+            }
+            do_insert_dao(&mut dao);
+            if action == MembershipAction::Remove {
+                _withdraw_proposals_of(&dao, &target);
+            }
+        }
+        ProposalKind::Funding { amount, recipient } => {
+            if !_get_milestones(&id).is_empty() {
+                return Err(Error::invalid_input(
+                    ErrorContext::Proposal(id),
+                    format!(
+                        "proposal with id={} is paid out in milestones, release them with release_milestone",
+                        id
+                    ),
+                ));
+            }
+            let recipient = match recipient.or(proposal.owner) {
+                Some(recipient) => recipient,
+                None => {
+                    return Err(Error::invalid_input(
+                        ErrorContext::Proposal(id),
+                        format!("proposal with id={} has no one to pay", id),
+                    ))
+                }
+            };
+            // marked before the call so a second execution can't start while this one waits
+            // for the ledger, and cleared again if the payment fails
+            proposal.executed_at = Some(time());
+            do_insert_proposal(&mut proposal);
+            let paid = ledger::transfer_from_treasury(
+                ErrorContext::Proposal(id),
+                proposal.dao_id,
+                ledger::Account {
+                    owner: recipient,
+                    subaccount: None,
+                },
+                amount,
+            )
+            .await;
+            proposal = _get_proposal(&id).unwrap_or(proposal);
+            match paid {
+                Ok(block_index) => {
+                    treasury::record(
+                        proposal.dao_id,
+                        treasury::TreasuryTxKind::Payout,
+                        amount,
+                        Some(recipient),
+                        Some(id),
+                        Some(block_index.clone()),
+                    );
+                    proposal.execution_block = Some(block_index);
+                }
+                Err(error) => {
+                    proposal.executed_at = None;
+                    do_insert_proposal(&mut proposal);
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    proposal.executed_at = Some(time());
+    do_insert_proposal(&mut proposal);
+    log_event(
+        proposal.dao_id,
+        AuditAction::ProposalExecuted,
+        AuditTarget::Proposal(id),
+    );
+    Ok(ProposalView::from(proposal))
+}
+
+// Ability to remove the lock a payment of a proposal left behind when its ledger call never came
+// back, providing you're the owner or an admin of its DAO. Only locks held for over an hour can be
+// cleared. Whatever the payment marked before the call, like the proposal being executed or the
+// milestone released, stays as it is, check the ledger before undoing it
+#[ic_cdk::update(guard = "_check_can_update")]
+fn clear_stuck_execution(proposal_id: u64) -> Result<locks::ExecutionLock, Error> {
+    let context = ErrorContext::Proposal(proposal_id);
+    let proposal = match _get_proposal(&proposal_id) {
+        Some(proposal) => proposal,
+        None => {
+            return Err(Error::not_found(
+                context,
+                format!("a proposal with id={} not found", proposal_id),
+            ))
+        }
+    };
+    if !_get_dao(&proposal.dao_id)
+        .map(|dao| _is_dao_admin(&dao, &caller()))
+        .unwrap_or_default()
+    {
+        return Err(Error::permission_error(
+            context,
+            format!(
+                "Couldn't clear the execution lock of proposal with id={}. You are not an admin",
+                proposal_id
+            ),
+        ));
+    }
+
+    match locks::clear_stuck(&proposal_id) {
+        Some(Ok(lock)) => {
+            log_event(
+                proposal.dao_id,
+                AuditAction::ExecutionLockCleared,
+                AuditTarget::Proposal(proposal_id),
+            );
+            Ok(lock)
+        }
+        Some(Err(lock)) => Err(Error::execution_in_progress(
+            context,
+            format!(
+                "the payment of proposal with id={} started at {} and can't be cleared before {}",
+                proposal_id,
+                lock.locked_at(),
+                lock.locked_at().saturating_add(locks::STUCK_AFTER)
+            ),
+        )),
+        None => Err(Error::not_found(
+            context,
+            format!(
+                "proposal with id={} has no payment in progress",
+                proposal_id
+            ),
+        )),
+    }
+}
+
+// Ability to report on the work done for a milestone of your approved Funding proposal. A report
+// can be replaced until the milestone is released
+#[ic_cdk::update(guard = "_check_can_update")]
+fn submit_milestone_report(
+    proposal_id: u64,
+    index: u64,
+    report: String,
+) -> Result<Milestone, Error> {
+    let proposal = match _get_proposal(&proposal_id) {
+        Some(proposal) => proposal,
+        None => {
+            return Err(Error::not_found(
+                ErrorContext::Proposal(proposal_id),
+                format!("a proposal with id={} not found", proposal_id),
+            ))
+        }
+    };
+    if !_is_owner(&proposal.owner, &caller()) {
+        return Err(Error::permission_error(
+            ErrorContext::Proposal(proposal_id),
+            format!(
+                "Couldn't report on proposal with id={}. You are not the owner",
+                proposal_id
+            ),
+        ));
+    }
+    _check_dao_not_archived(&proposal.dao_id)?;
+    if proposal.status != ProposalStatus::Approved {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal_id),
+            format!("proposal with id={} hasn't been approved", proposal_id),
+        ));
+    }
+    let report_length = report.trim().chars().count();
+    if report_length == 0 || report_length > MAX_MILESTONE_REPORT_LEN {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal_id),
+            format!(
+                "report must be between 1 and {} characters",
+                MAX_MILESTONE_REPORT_LEN
+            ),
+        ));
+    }
+
+    let mut milestone = _get_milestone(&proposal_id, &index)?;
+    if milestone.released {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal_id),
+            format!(
+                "milestone {} of proposal with id={} was already released",
+                index, proposal_id
+            ),
+        ));
+    }
+
+    milestone.report = Some(report.trim().to_string());
+    milestone.reported_at = Some(time());
+    MILESTONE_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert((proposal_id, index), milestone.clone())
+    });
+    log_event(
+        proposal.dao_id,
+        AuditAction::MilestoneReported,
+        AuditTarget::Proposal(proposal_id),
+    );
+    Ok(milestone)
+}
+
+// Ability to pay a milestone of an approved Funding proposal out of the DAO's treasury providing
+// you're the owner or an admin of its DAO. Milestones are released in order, each once and only
+// after the proposer reported on it. The proposal counts as executed once all are released
+#[ic_cdk::update(guard = "_check_can_update")]
+async fn release_milestone(proposal_id: u64, index: u64) -> Result<Milestone, Error> {
+    let context = ErrorContext::Proposal(proposal_id);
+    let proposal = match _get_proposal(&proposal_id) {
+        Some(proposal) => proposal,
+        None => {
+            return Err(Error::not_found(
+                context,
+                format!("a proposal with id={} not found", proposal_id),
+            ))
+        }
+    };
+    let dao = _get_dao(&proposal.dao_id).unwrap_or_default();
+    if !_is_dao_admin(&dao, &caller()) {
+        return Err(Error::permission_error(
+            context,
+            format!(
+                "Couldn't release a milestone of proposal with id={}. You are not an admin",
+                proposal_id
+            ),
+        ));
+    }
+    let _lock = _lock_execution(proposal_id, locks::LockedOperation::MilestoneRelease(index))?;
+    _check_not_archived(&dao)?;
+    if proposal.status != ProposalStatus::Approved {
+        return Err(Error::invalid_input(
+            context,
+            format!("proposal with id={} hasn't been approved", proposal_id),
+        ));
+    }
+    let executable_at = proposal
+        .deadline
+        .saturating_add(_get_settings(&proposal.dao_id).timelock_seconds * 1_000_000_000);
+    if time() < executable_at {
+        return Err(Error::invalid_input(
+            context,
+            format!(
+                "proposal with id={} is timelocked until {}",
+                proposal_id, executable_at
+            ),
+        ));
+    }
+
+    let mut milestone = _get_milestone(&proposal_id, &index)?;
+    if milestone.released {
+        return Err(Error::invalid_input(
+            context,
+            format!(
+                "milestone {} of proposal with id={} was already released",
+                index, proposal_id
+            ),
+        ));
+    }
+    let next = _get_milestones(&proposal_id)
+        .iter()
+        .position(|milestone| !milestone.released)
+        .unwrap_or_default() as u64;
+    if index != next {
+        return Err(Error::invalid_input(
+            context,
+            format!(
+                "milestones are released in order, release milestone {} of proposal with id={} first",
+                next, proposal_id
+            ),
+        ));
+    }
+    if milestone.report.is_none() {
+        return Err(Error::invalid_input(
+            context,
+            format!(
+                "the proposer hasn't reported on milestone {} of proposal with id={} yet",
+                index, proposal_id
+            ),
+        ));
+    }
+    let recipient = match proposal.kind {
+        ProposalKind::Funding { recipient, .. } => recipient.or(proposal.owner),
+        _ => None,
+    };
+    let recipient = match recipient {
+        Some(recipient) => recipient,
+        None => {
+            return Err(Error::invalid_input(
+                context,
+                format!("proposal with id={} has no one to pay", proposal_id),
+            ))
+        }
+    };
+
+    // marked before the call so the milestone can't be released twice while this one waits for
+    // the ledger, and cleared again if the payment fails
+    milestone.released = true;
+    milestone.released_at = Some(time());
+    MILESTONE_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert((proposal_id, index), milestone.clone())
+    });
+    let paid = ledger::transfer_from_treasury(
+        context,
+        proposal.dao_id,
+        ledger::Account {
+            owner: recipient,
+            subaccount: None,
+        },
+        milestone.amount,
+    )
+    .await;
+    let block_index = match paid {
+        Ok(block_index) => block_index,
+        Err(error) => {
+            milestone.released = false;
+            milestone.released_at = None;
+            MILESTONE_STORAGE
+                .with(|service| service.borrow_mut().insert((proposal_id, index), milestone));
+            return Err(error);
+        }
+    };
+    treasury::record(
+        proposal.dao_id,
+        treasury::TreasuryTxKind::Payout,
+        milestone.amount,
+        Some(recipient),
+        Some(proposal_id),
+        Some(block_index),
+    );
+
+    log_event(
+        proposal.dao_id,
+        AuditAction::MilestoneReleased,
+        AuditTarget::Proposal(proposal_id),
+    );
+    if _get_milestones(&proposal_id)
+        .iter()
+        .all(|milestone| milestone.released)
+    {
+        let mut proposal = _get_proposal(&proposal_id).unwrap_or(proposal);
+        proposal.executed_at = Some(time());
+        do_insert_proposal(&mut proposal);
+        log_event(
+            proposal.dao_id,
+            AuditAction::ProposalExecuted,
+            AuditTarget::Proposal(proposal_id),
+        );
+    }
+    Ok(milestone)
+}
+
+// Ability to see the milestones of a proposal in a DAO you're part of, and which were released
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_milestone_status(proposal_id: u64) -> Result<Vec<Milestone>, Error> {
+    _get_readable_proposal(&proposal_id).map(|_| _get_milestones(&proposal_id))
+}
+
+// Ability to delete proposal provided you're the owner, the deadline hasn't passed and nobody has
+// voted on it yet. Proposals with votes can only be cancelled
+#[ic_cdk::update(guard = "_check_can_update")]
+fn delete_proposal(id: u64) -> Result<ProposalView, Error> {
+    match _get_proposal(&id) {
+        Some(proposal) => {
+            if !_is_owner(&proposal.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Proposal(id),
+                    format!(
+                        "Couldn't delete a proposal with id={}. You are not the owner",
+                        id
+                    ),
+                ));
+            }
+            _check_dao_not_archived(&proposal.dao_id)?;
+            if _is_deadline_passed(proposal.deadline) {
+                return Err(Error::deadline_exceeded(
+                    ErrorContext::Proposal(id),
+                    format!(
+                        "Couldn't delete a proposal with id={}. Deadline exceeded",
+                        id
+                    ),
+                ));
+            }
+            if proposal.upvote_count + proposal.downvote_count > 0 {
+                return Err(Error::invalid_input(
+                    ErrorContext::Proposal(id),
+                    format!(
+                        "Couldn't delete a proposal with id={}. It has votes, cancel it instead",
+                        id
+                    ),
+                ));
+            }
+
+            _delete_proposal_records(&proposal);
+            _promote_queued(proposal.dao_id);
+            log_event(
+                proposal.dao_id,
+                AuditAction::ProposalDeleted,
+                AuditTarget::Proposal(id),
+            );
+
+            Ok(ProposalView::from(proposal))
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(id),
+            format!(
+                "Couldn't delete a proposal with id={}. proposal not found.",
+                id
+            ),
+        )),
+    }
+}
+
+// Ability to withdraw an open or queued proposal provided you're the owner. The proposal and its
+// votes are kept but it can no longer be voted or commented on
+#[ic_cdk::update(guard = "_check_can_update")]
+fn cancel_proposal(id: u64) -> Result<ProposalView, Error> {
+    match _get_proposal(&id) {
+        Some(mut proposal) => {
+            if !_is_owner(&proposal.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Proposal(id),
+                    format!(
+                        "Couldn't cancel a proposal with id={}. You are not the owner",
+                        id
+                    ),
+                ));
+            }
+            _check_dao_not_archived(&proposal.dao_id)?;
+            if !matches!(
+                proposal.status,
+                ProposalStatus::Open | ProposalStatus::Queued
+            ) {
+                return Err(Error::invalid_input(
+                    ErrorContext::Proposal(id),
+                    format!(
+                        "Couldn't cancel a proposal with id={}. It is not open or queued",
+                        id
+                    ),
+                ));
+            }
+
+            proposal.status = ProposalStatus::Cancelled;
+            proposal.cancel_reason = Some(CancelReason::Withdrawn);
+            proposal.updated_at = Some(time());
+
+            do_insert_proposal(&mut proposal);
+            _dequeue_proposal(&proposal);
+            _promote_queued(proposal.dao_id);
+            _resolve_dependents(&proposal);
+            log_event(
+                proposal.dao_id,
+                AuditAction::ProposalCancelled,
+                AuditTarget::Proposal(id),
+            );
+            Ok(ProposalView::from(proposal))
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(id),
+            format!(
+                "Couldn't cancel a proposal with id={}. proposal not found.",
+                id
+            ),
+        )),
+    }
+}
+
+// Ability to take down any proposal of a DAO, e.g. spam, provided you're the owner or an admin.
+// The proposal is kept with its content blanked. If it wasn't finalized yet it is cancelled, and
+// the votes of an open proposal are discarded
+#[ic_cdk::update(guard = "_check_can_update")]
+fn admin_remove_proposal(id: u64, reason: String) -> Result<ProposalView, Error> {
+    match _get_proposal(&id).filter(|proposal| proposal.removal.is_none()) {
+        Some(mut proposal) => {
+            let dao = _get_dao(&proposal.dao_id).unwrap_or_default();
+            if !_is_dao_admin(&dao, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Proposal(id),
+                    format!(
+                        "Couldn't remove a proposal with id={}. You are not an admin of its dao",
+                        id
+                    ),
+                ));
+            }
+            _check_not_archived(&dao)?;
+            let removal = _removal(ErrorContext::Proposal(id), reason)?;
+
+            if proposal.status == ProposalStatus::Open {
+                _remove_proposal_votes(&id);
+                proposal.upvote_count = 0;
+                proposal.downvote_count = 0;
+            }
+            let finalized = matches!(
+                proposal.status,
+                ProposalStatus::Approved | ProposalStatus::Rejected | ProposalStatus::Cancelled
+            );
+            if !finalized {
+                proposal.status = ProposalStatus::Cancelled;
+            }
+            proposal.title = REMOVED_CONTENT.to_string();
+            proposal.details = REMOVED_CONTENT.to_string();
+            // the old slugs give the title away
+            _clear_proposal_slugs(&proposal);
+            _assign_proposal_slug(&mut proposal);
+            proposal.removal = Some(removal);
+            proposal.updated_at = Some(time());
+
+            do_insert_proposal(&mut proposal);
+            _set_attachments(&id, Vec::new());
+            _clear_reports(&id);
+            if !finalized {
+                _dequeue_proposal(&proposal);
+                _promote_queued(proposal.dao_id);
+                _resolve_dependents(&proposal);
+            }
+            log_event(
+                proposal.dao_id,
+                AuditAction::ProposalRemoved,
+                AuditTarget::Proposal(id),
+            );
+            if let Some(owner) = proposal.owner.filter(|owner| *owner != caller()) {
+                notify(
+                    &owner,
+                    NotificationKind::ContentRemoved,
+                    proposal.dao_id,
+                    Some(id),
+                    None,
+                );
+            }
+            Ok(ProposalView::from(proposal))
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(id),
+            format!(
+                "Couldn't remove a proposal with id={}. proposal not found.",
+                id
+            ),
+        )),
+    }
+}
+
+// Ability to pin a proposal to the top of its DAO provided you're the owner or an admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn pin_proposal(id: u64) -> Result<ProposalView, Error> {
+    _set_pinned(id, true)
+}
+
+// Ability to unpin a proposal provided you're the owner or an admin of its DAO
+#[ic_cdk::update(guard = "_check_can_update")]
+fn unpin_proposal(id: u64) -> Result<ProposalView, Error> {
+    _set_pinned(id, false)
+}
+
+// Ability to stop new comments, edits and likes on a proposal provided you're the owner or an admin of its DAO
+#[ic_cdk::update(guard = "_check_can_update")]
+fn lock_comments(proposal_id: u64) -> Result<ProposalView, Error> {
+    _set_comments_locked(proposal_id, true)
+}
+
+// Ability to reopen the comments of a proposal provided you're the owner or an admin of its DAO
+#[ic_cdk::update(guard = "_check_can_update")]
+fn unlock_comments(proposal_id: u64) -> Result<ProposalView, Error> {
+    _set_comments_locked(proposal_id, false)
+}
+
+// Ability to get the attachments of a proposal in a DAO you're part of
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_proposal_attachments(proposal_id: u64) -> Result<Vec<Attachment>, Error> {
+    _get_readable_proposal(&proposal_id).map(|_| _get_attachments(&proposal_id))
+}
+
+// Ability to attach a document to a proposal provided you're the owner or a co-author and the deadline hasn't passed
+#[ic_cdk::update(guard = "_check_can_update")]
+fn add_attachment(proposal_id: u64, attachment: Attachment) -> Result<Vec<Attachment>, Error> {
+    let proposal = _get_editable_proposal(&proposal_id)?;
+
+    let mut attachments = _get_attachments(&proposal_id);
+    attachments.push(attachment);
+    _validate_attachments(&attachments)?;
+
+    _set_attachments(&proposal_id, attachments.clone());
+    log_event(
+        proposal.dao_id,
+        AuditAction::AttachmentsChanged,
+        AuditTarget::Proposal(proposal_id),
+    );
+    Ok(attachments)
+}
+
+// Ability to remove an attachment by position provided you're the owner or a co-author of the proposal and the deadline hasn't passed
+#[ic_cdk::update(guard = "_check_can_update")]
+fn remove_attachment(proposal_id: u64, index: u64) -> Result<Vec<Attachment>, Error> {
+    let proposal = _get_editable_proposal(&proposal_id)?;
+
+    let mut attachments = _get_attachments(&proposal_id);
+    if index as usize >= attachments.len() {
+        return Err(Error::not_found(
+            ErrorContext::Attachment { proposal_id, index },
+            format!(
+                "couldn't remove attachment {} of proposal with id={}. attachment not found",
+                index, proposal_id
+            ),
+        ));
+    }
+    attachments.remove(index as usize);
+
+    _set_attachments(&proposal_id, attachments.clone());
+    log_event(
+        proposal.dao_id,
+        AuditAction::AttachmentsChanged,
+        AuditTarget::Proposal(proposal_id),
+    );
+    Ok(attachments)
+}
+
+/**
+* -----------------------------------------------------------------------------
+* COMMENT FUNCTIONS
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to get all comments on a proposal in a DAO you're part of, sorted by created_at and
+// then id. A proposal without comments has an empty list. Exported by
+// routed_get_all_comments_on_proposal
+fn get_all_comments_on_proposal(proposal_id: u64) -> Result<Vec<CommentView>, Error> {
+    let proposal = _get_readable_proposal(&proposal_id)?;
+
+    let can_see_hidden = _can_see_hidden(&proposal.dao_id);
+    let mut comments: Vec<Comment> = _comment_ids(&proposal.id)
+        .iter()
+        .filter_map(_get_comment)
+        .filter(|comment| comment.proposal_id == proposal_id)
+        .filter(|comment| !comment.hidden || can_see_hidden)
+        .collect();
+
+    comments.sort_by_key(|comment| (comment.created_at, comment.id));
+    Ok(_comment_views(comments))
+}
+
+// Ability to see how many comments on a proposal you can read are for, against or neutral, along
+// with the most liked comments of each stance
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_comment_stance_summary(proposal_id: u64) -> Result<StanceSummary, Error> {
+    let proposal = _get_readable_proposal(&proposal_id)?;
+
+    let can_see_hidden = _can_see_hidden(&proposal.dao_id);
+    let mut comments: Vec<Comment> = _comment_ids(&proposal.id)
+        .iter()
+        .filter_map(_get_comment)
+        .filter(|comment| comment.stance.is_some())
+        .filter(|comment| !comment.hidden || can_see_hidden)
+        .collect();
+    comments.sort_by(|a, b| b.like_count.cmp(&a.like_count).then(a.id.cmp(&b.id)));
+
+    let mut top_comments: Vec<Comment> = Vec::new();
+    for stance in [Stance::For, Stance::Against, Stance::Neutral] {
+        top_comments.extend(
+            comments
+                .iter()
+                .filter(|comment| comment.stance == Some(stance))
+                .take(STANCE_TOP_COMMENTS)
+                .cloned(),
+        );
+    }
+    let views = _comment_views(top_comments);
+    let top = |stance: Stance| {
+        views
+            .iter()
+            .filter(|view| view.stance == Some(stance))
+            .cloned()
+            .collect()
+    };
+    Ok(StanceSummary {
+        proposal_id,
+        top_for: top(Stance::For),
+        top_against: top(Stance::Against),
+        top_neutral: top(Stance::Neutral),
+        counts: proposal.stance_counts,
+    })
+}
+
+// Ability to get the proposals you commented on in a DAO you're part of, most recently active first
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_threads_i_participated_in(
+    dao_id: u64,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<ProposalView>, Error> {
+    _can_read_dao(&dao_id)?;
+    let key = principal_key(&caller());
+    let can_see_hidden = _can_see_hidden(&dao_id);
+    let mut proposals: Vec<Proposal> = THREAD_PARTICIPATION.with(|service| {
+        service
+            .borrow()
+            .range((key, 0)..)
+            .take_while(|((user, _), _)| *user == key)
+            .filter_map(|((_, proposal_id), _)| _get_proposal(&proposal_id))
+            .filter(|proposal| proposal.dao_id == dao_id)
+            .filter(|proposal| !proposal.hidden || can_see_hidden)
+            .filter(|proposal| _can_see_proposal(proposal, &caller()))
+            .collect()
+    });
+
+    proposals.sort_by(|a, b| {
+        b.last_activity_at
+            .cmp(&a.last_activity_at)
+            .then(a.id.cmp(&b.id))
+    });
+    Ok(proposals
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .map(ProposalView::from)
+        .collect())
+}
+
+// Ability to page through the comments on a proposal in order of creation. Paging by cursor
+// means comments posted between two fetches don't shift the pages
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_comments_after(
+    proposal_id: u64,
+    after_id: Option<u64>,
+    limit: u64,
+    newest_first: bool,
+) -> Result<CommentPage, Error> {
+    let proposal = _get_readable_proposal(&proposal_id)?;
+
+    let can_see_hidden = _can_see_hidden(&proposal.dao_id);
+    let mut comments: Vec<Comment> = _comment_ids(&proposal.id)
+        .iter()
+        .filter_map(_get_comment)
+        .filter(|comment| !comment.hidden || can_see_hidden)
+        .collect();
+    // ties on created_at are broken by id so the order is total and cursors are unambiguous
+    comments.sort_by_key(|comment| (comment.created_at, comment.id));
+    if newest_first {
+        comments.reverse();
+    }
+    let total_count = comments.len() as u64;
+
+    let start = match after_id {
+        Some(after_id) => match comments.iter().position(|comment| comment.id == after_id) {
+            Some(position) => position + 1,
+            None => {
+                return Err(Error::not_found(
+                    ErrorContext::Comment(after_id),
+                    format!(
+                        "a comment with id={} not found on proposal with id={}",
+                        after_id, proposal_id
+                    ),
+                ))
+            }
+        },
+        None => 0,
+    };
+    let items: Vec<Comment> = comments
+        .iter()
+        .skip(start)
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .cloned()
+        .collect();
+    let next_cursor = if start + items.len() < comments.len() {
+        items.last().map(|comment| comment.id)
+    } else {
+        None
+    };
+
+    let mut preview_ids: Vec<u64> = items
+        .iter()
+        .flat_map(|comment| comment.link_previews.iter().copied())
+        .collect();
+    preview_ids.sort_unstable();
+    preview_ids.dedup();
+
+    Ok(CommentPage {
+        items: _comment_views(items),
+        next_cursor,
+        total_count,
+        link_previews: previews::resolve(&preview_ids),
+    })
+}
+
+// Ability to comment a proposal that can be voted on within a week. Exported by
+// routed_comment_on_post
+fn comment_on_post(comment: CommentPayload) -> Result<Comment, Error> {
+    let _profile = metrics::profile("comment_on_post");
+    _check_storage_available(ErrorContext::Proposal(comment.proposal_id))?;
+    _validate_comment_text(
+        &comment.content,
+        ErrorContext::Proposal(comment.proposal_id),
+    )?;
+    match _get_proposal(&comment.proposal_id)
+        .filter(|proposal| _can_see_proposal(proposal, &caller()))
+    {
+        Some(mut proposal) => {
+            match _is_user_part_of_dao(&proposal.dao_id) {
+                Ok(_) => {
+                    _check_if_thread_open(&proposal)?;
+                    _check_comment_window(&proposal)?;
+                    rate_limit::check_and_record(
+                        &caller(),
+                        proposal.id,
+                        _get_settings(&proposal.dao_id)
+                            .max_comments_per_window
+                            .or(CONFIG.with(|config| config.borrow().get().max_comments_per_window))
+                            .map(|limit| limit as usize)
+                            .unwrap_or(rate_limit::MAX_COMMENTS_PER_WINDOW),
+                        rate_limit::COMMENT_WINDOW,
+                        time(),
+                    )?;
+
+                    let mentions = _parse_mentions(&comment.content, &proposal.dao_id);
+                    let parent = match comment.reply_to {
+                        Some(parent_id) => Some(_get_reply_parent(&proposal, &parent_id)?),
+                        None => None,
+                    };
+
+                    let id = ID_COUNTER
+                        .with(|counter| {
+                            let current_value = *counter.borrow().get();
+                            counter.borrow_mut().set(current_value + 1)
+                        })
+                        .expect("cannot increment id counter");
+
+                    _list_comment(&mut proposal, id);
+                    proposal.stance_counts.apply(None, comment.stance);
+                    proposal.updated_at = Some(time());
+                    _record_activity(&mut proposal, &caller(), time());
+
+                    do_insert_proposal(&mut proposal);
+
+                    let link_previews = previews::attach(&comment.content, proposal.dao_id);
+                    let comment = Comment {
+                        id,
+                        content: comment.content,
+                        proposal_id: comment.proposal_id,
+                        created_at: time(),
+                        updated_at: None,
+                        like_count: 0,
+                        likes: Vec::new(),
+                        author: Some(caller()),
+                        mentions,
+                        hidden: false,
+                        schema_version: migrations::SCHEMA_VERSION,
+                        deleted: false,
+                        reply_to: comment.reply_to,
+                        removal: None,
+                        stance: comment.stance,
+                        link_previews,
+                    };
+
+                    do_insert_comment(&comment);
+                    _record_participation(&caller(), &proposal.id);
+                    _count_activity(proposal.dao_id, &caller(), ActivityKind::Comment);
+                    log_event(
+                        proposal.dao_id,
+                        AuditAction::CommentCreated,
+                        AuditTarget::Comment {
+                            id,
+                            proposal_id: proposal.id,
+                        },
+                    );
+                    // the author of the parent gets a Reply, unless they replied to themselves
+                    let replied_to: Vec<Principal> = parent
+                        .and_then(|parent| parent.author)
+                        .filter(|author| *author != caller())
+                        .into_iter()
+                        .collect();
+                    replied_to.iter().for_each(|author| {
+                        notify(
+                            author,
+                            NotificationKind::Reply,
+                            proposal.dao_id,
+                            Some(proposal.id),
+                            Some(id),
+                        )
+                    });
+                    // mentioned members and the replied to author get their own notification
+                    let skip: Vec<Principal> = comment
+                        .mentions
+                        .iter()
+                        .chain(replied_to.iter())
+                        .copied()
+                        .collect();
+                    _notify_followers(
+                        &proposal,
+                        NotificationKind::NewComment,
+                        Some(id),
+                        &[],
+                        &skip,
+                    );
+                    _notify_mentions(&comment, &[], &proposal.dao_id);
+                    Ok(comment)
+                }
+                Err(error) => Err(error),
+            }
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(comment.proposal_id),
+            format!(
+                "cannot comment on proposal with id={}. proposal not found",
+                comment.proposal_id
+            ),
+        )),
+    }
+}
+
+// Ability to update a proposal provided you're the owner and the deadline hasn't passed
+#[ic_cdk::update(guard = "_check_can_update")]
+fn update_comment(id: u64, payload: CommentPayload) -> Result<Comment, Error> {
+    match COMMENT_STORAGE.with(|service| service.borrow().get(&id)) {
+        Some(mut comment) => {
+            if !_is_owner(&comment.author, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Comment(id),
+                    format!(
+                        "Couldn't update comment with id={}. You are not the owner",
+                        id
+                    ),
+                ));
+            }
+            if comment.deleted {
+                return Err(Error::invalid_input(
+                    ErrorContext::Comment(id),
+                    format!("Couldn't update comment with id={}. It was deleted", id),
+                ));
+            }
+            _validate_comment_text(&payload.content, ErrorContext::Comment(id))?;
+
+            if let Some(proposal) = _get_proposal(&comment.proposal_id) {
+                _check_if_thread_open(&proposal)?;
+            }
+
+            let previous_mentions = comment.mentions.clone();
+            let dao_id = _get_proposal(&comment.proposal_id).map(|proposal| proposal.dao_id);
+
+            comment.content = payload.content;
+            comment.mentions = match dao_id {
+                Some(dao_id) => _parse_mentions(&comment.content, &dao_id),
+                None => Vec::new(),
+            };
+            comment.link_previews = match dao_id {
+                Some(dao_id) => previews::attach(&comment.content, dao_id),
+                None => Vec::new(),
+            };
+            comment.updated_at = Some(time());
+            if let Some(stance) = payload.stance {
+                _change_stance(&mut comment, Some(stance));
+            }
+
+            do_insert_comment(&comment);
+            log_comment_event(&comment, AuditAction::CommentUpdated);
+            if let Some(dao_id) = dao_id {
+                _notify_mentions(&comment, &previous_mentions, &dao_id);
+            }
+            Ok(comment)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Comment(id),
+            format!(
+                "couldn't update a comment with id={}. comment not found",
+                id
+            ),
+        )),
+    }
+}
+
+// Ability to like a coment in a DAO you're part of, provided you're not the owner and you haven't
+// liked
+#[ic_cdk::update(guard = "_check_can_update")]
+fn like_comment(id: u64) -> Result<Comment, Error> {
+    let not_found = || {
+        Error::not_found(
+            ErrorContext::Comment(id),
+            format!("Couldn't like a comment with id={}. Comment not found", id),
+        )
+    };
+    let dao_id = match _get_comment(&id).and_then(|comment| _get_comment_dao_id(&comment)) {
+        Some(dao_id) => dao_id,
+        None => return Err(not_found()),
+    };
+    match COMMENT_STORAGE.with(|service| service.borrow_mut().get(&id)) {
+        Some(mut comment) => match _is_user_part_of_dao(&dao_id) {
+            Ok(_) => {
+                if comment.author.is_some() && comment.author == Some(caller()) {
+                    return Err(Error::cant_like_yours(
+                        ErrorContext::Comment(comment.id),
+                        format!(
+                            "Couldn't like a comment with id={} because you created the comment",
+                            comment.id
+                        ),
+                    ));
+                }
+
+                if comment.deleted {
+                    return Err(Error::invalid_input(
+                        ErrorContext::Comment(comment.id),
+                        format!(
+                            "Couldn't like a comment with id={}. It was deleted",
+                            comment.id
+                        ),
+                    ));
+                }
+
+                if let Some(proposal) = _get_proposal(&comment.proposal_id) {
+                    _check_if_thread_open(&proposal)?;
+                }
+
+                if _has_liked(&comment.id, &caller()) {
+                    return Err(Error::already_liked(
+                        ErrorContext::Comment(comment.id),
+                        format!(
+                            "Couldn't like a comment with id={}. You have already liked it",
+                            comment.id
+                        ),
+                    ));
+                }
+
+                COMMENT_LIKES.with(|service| {
+                    service
+                        .borrow_mut()
+                        .insert((comment.id, principal_key(&caller())), ())
+                });
+                comment.like_count += 1;
+
+                do_insert_comment(&comment);
+                likes::liked(&comment);
+                if let (Some(author), Some(proposal)) =
+                    (comment.author, _get_proposal(&comment.proposal_id))
+                {
+                    reputation::award(proposal.dao_id, &author, reputation::COMMENT_LIKED_POINTS);
+                }
+                log_comment_event(&comment, AuditAction::CommentLiked);
+                Ok(comment)
+            }
+            // a comment in a private DAO the caller isn't part of doesn't exist for them
+            Err(Error::NotFound { .. }) => Err(not_found()),
+            Err(error) => Err(error),
+        },
+        None => Err(not_found()),
+    }
+}
+
+// Ability to take back your like of a comment in a DAO you're part of
+#[ic_cdk::update(guard = "_check_can_update")]
+fn unlike_comment(id: u64) -> Result<Comment, Error> {
+    let mut comment = match _get_comment(&id) {
+        Some(comment) => comment,
+        None => {
+            return Err(Error::not_found(
+                ErrorContext::Comment(id),
+                format!(
+                    "Couldn't unlike a comment with id={}. Comment not found",
+                    id
+                ),
+            ))
+        }
+    };
+    if let Some(dao_id) = _get_comment_dao_id(&comment) {
+        _is_user_part_of_dao(&dao_id)?;
+    }
+    if let Some(proposal) = _get_proposal(&comment.proposal_id) {
+        _check_if_thread_open(&proposal)?;
+    }
+
+    let removed = COMMENT_LIKES.with(|service| {
+        service
+            .borrow_mut()
+            .remove(&(comment.id, principal_key(&caller())))
+    });
+    if removed.is_none() {
+        return Err(Error::invalid_input(
+            ErrorContext::Comment(comment.id),
+            format!(
+                "Couldn't unlike a comment with id={}. You haven't liked it",
+                comment.id
+            ),
+        ));
+    }
+    comment.like_count = comment.like_count.saturating_sub(1);
+
+    do_insert_comment(&comment);
+    likes::unliked(&comment);
+    Ok(comment)
+}
+
+// Ability to delete your comment. It stays in the thread with its content blanked so replies
+// keep their context
+#[ic_cdk::update(guard = "_check_can_update")]
+fn delete_comment(id: u64) -> Result<Comment, Error> {
+    match _get_comment(&id).filter(|comment| !comment.deleted) {
+        Some(mut comment) => {
+            if !_is_owner(&comment.author, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Comment(id),
+                    format!(
+                        "Couldn't delete a comment with id={}. You are not the owner",
+                        id
+                    ),
+                ));
+            }
+            if let Some(proposal) = _get_proposal(&comment.proposal_id) {
+                _check_dao_not_archived(&proposal.dao_id)?;
+            }
+
+            comment.deleted = true;
+            comment.content = DELETED_COMMENT_CONTENT.to_string();
+            comment.mentions = Vec::new();
+            comment.link_previews = Vec::new();
+            comment.updated_at = Some(time());
+            _change_stance(&mut comment, None);
+
+            do_insert_comment(&comment);
+            _clear_reports(&id);
+            log_comment_event(&comment, AuditAction::CommentDeleted);
+
+            Ok(comment)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Comment(id),
+            format!(
+                "Couldn't delete a comment with id={}. comment not found.",
+                id
+            ),
+        )),
+    }
+}
+
+// Ability to remove a comment for good, e.g. for legal reasons, provided you're the owner of its
+// DAO. Unlike delete_comment nothing of it is kept
+#[ic_cdk::update(guard = "_check_can_update")]
+fn purge_comment(id: u64) -> Result<(), Error> {
+    match _get_comment(&id) {
+        Some(comment) => {
+            let dao =
+                _get_proposal(&comment.proposal_id).and_then(|proposal| _get_dao(&proposal.dao_id));
+            let is_dao_owner = dao
+                .as_ref()
+                .map(|dao| _is_owner(&dao.owner, &caller()))
+                .unwrap_or_default();
+            if !is_dao_owner {
+                return Err(Error::permission_error(
+                    ErrorContext::Comment(id),
+                    format!(
+                        "Couldn't purge a comment with id={}. You are not the owner of its dao",
+                        id
+                    ),
+                ));
+            }
+            if let Some(dao) = &dao {
+                _check_not_archived(dao)?;
+            }
+
+            _delete_comment_records(&comment);
+            if let Some(dao) = dao {
+                log_event(
+                    dao.id,
+                    AuditAction::CommentPurged,
+                    AuditTarget::Comment {
+                        id,
+                        proposal_id: comment.proposal_id,
+                    },
+                );
+            }
+            Ok(())
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Comment(id),
+            format!(
+                "Couldn't purge a comment with id={}. comment not found.",
+                id
+            ),
+        )),
+    }
+}
+
+// Ability to take down any comment of a DAO, e.g. spam or doxxing, provided you're the owner or
+// an admin. Like delete_comment the comment keeps its place in the thread with its content
+// blanked, and its author can't edit it anymore
+#[ic_cdk::update(guard = "_check_can_update")]
+fn admin_remove_comment(id: u64, reason: String) -> Result<Comment, Error> {
+    match _get_comment(&id).filter(|comment| comment.removal.is_none()) {
+        Some(mut comment) => {
+            let proposal = _get_proposal(&comment.proposal_id).unwrap_or_default();
+            let dao = _get_dao(&proposal.dao_id).unwrap_or_default();
+            if !_is_dao_admin(&dao, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Comment(id),
+                    format!(
+                        "Couldn't remove a comment with id={}. You are not an admin of its dao",
+                        id
+                    ),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            comment.removal = Some(_removal(ErrorContext::Comment(id), reason)?);
+            comment.deleted = true;
+            comment.content = REMOVED_CONTENT.to_string();
+            comment.mentions = Vec::new();
+            comment.link_previews = Vec::new();
+            comment.updated_at = Some(time());
+            _change_stance(&mut comment, None);
+
+            do_insert_comment(&comment);
+            _clear_reports(&id);
+            log_comment_event(&comment, AuditAction::CommentRemoved);
+            if let Some(author) = comment.author.filter(|author| *author != caller()) {
+                notify(
+                    &author,
+                    NotificationKind::ContentRemoved,
+                    dao.id,
+                    Some(proposal.id),
+                    Some(id),
+                );
+            }
+            Ok(comment)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Comment(id),
+            format!(
+                "Couldn't remove a comment with id={}. comment not found.",
+                id
+            ),
+        )),
+    }
+}
+
+// Ability to get the comments of a DAO that mention you, newest first
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_comments_mentioning_me(
+    dao_id: u64,
+    offset: u64,
+    limit: u64,
+) -> Result<Vec<CommentView>, Error> {
+    match _can_read_dao(&dao_id) {
+        Ok(dao) => {
+            let can_see_hidden = _is_dao_admin(&dao, &caller());
+
+            let mut comments: Vec<Comment> = _dao_proposal_ids(&dao.id)
+                .iter()
+                .filter_map(_get_proposal)
+                .flat_map(|proposal| _comment_ids(&proposal.id))
+                .filter_map(|comment_id| _get_comment(&comment_id))
+                .filter(|comment| comment.mentions.contains(&caller()))
+                .filter(|comment| !comment.hidden || can_see_hidden)
+                .collect();
+            comments.sort_by_key(|comment| Reverse(comment.created_at));
+
+            Ok(_comment_views(
+                comments
+                    .into_iter()
+                    .skip(offset as usize)
+                    .take(limit.min(MAX_PAGE_SIZE) as usize)
+                    .collect(),
+            ))
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/**
+* -----------------------------------------------------------------------------
+* MODERATION FUNCTIONS
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to report a proposal or comment in a DAO you're part of. Reporting the same content
+// twice has no effect, and content reported often enough is hidden from regular members
+#[ic_cdk::update(guard = "_check_can_update")]
+fn report_content(target: ReportTarget, reason: String) -> Result<(), Error> {
+    if reason.chars().count() > MAX_REPORT_REASON_LEN {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            format!(
+                "reason can't be longer than {} characters",
+                MAX_REPORT_REASON_LEN
+            ),
+        ));
+    }
+
+    match _locate_report_target(&target) {
+        Some((dao_id, audit_target)) => {
+            _is_user_part_of_dao(&dao_id)?;
+            _check_dao_not_archived(&dao_id)?;
+
+            let key = (target.id(), principal_key(&caller()));
+            if REPORT_STORAGE.with(|service| service.borrow().contains_key(&key)) {
+                return Ok(());
+            }
+
+            let report = Report {
+                target,
+                dao_id,
+                reporter: Some(caller()),
+                reason,
+                created_at: time(),
+            };
+            REPORT_STORAGE.with(|service| service.borrow_mut().insert(key, report));
+            REPORTED_CONTENT.with(|service| service.borrow_mut().insert((dao_id, target.id()), ()));
+            log_event(dao_id, AuditAction::ContentReported, audit_target);
+
+            if _get_reports(&target.id()).len() as u64 >= _get_settings(&dao_id).report_threshold {
+                _set_hidden(&target, true);
+            }
+            Ok(())
+        }
+        None => Err(Error::not_found(
+            ErrorContext::from(&target),
+            format!("couldn't report content with id={}. not found", target.id()),
+        )),
+    }
+}
+
+// Ability to get the reported content of a DAO provided you're the owner or an admin
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_reported_content(dao_id: u64) -> Result<Vec<ReportedContent>, Error> {
+    match _get_dao(&dao_id) {
+        Some(dao) => {
+            if !_is_dao_admin(&dao, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(dao_id),
+                    format!(
+                        "Couldn't get reports of dao with id={}. You are not an admin",
+                        dao_id
+                    ),
+                ));
+            }
+
+            let mut reported: BTreeMap<u64, ReportedContent> = BTreeMap::new();
+            REPORT_STORAGE.with(|service| {
+                service
+                    .borrow()
+                    .iter()
+                    .filter(|(_, report)| report.dao_id == dao_id)
+                    .for_each(|((target_id, _), report)| {
+                        reported
+                            .entry(target_id)
+                            .or_insert_with(|| ReportedContent {
+                                target: report.target,
+                                hidden: _is_hidden(&report.target),
+                                reports: Vec::new(),
+                            })
+                            .reports
+                            .push(report);
+                    })
+            });
+
+            Ok(reported.into_values().collect())
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(dao_id),
+            format!("couldn't get a dao with id={}. dao not found", dao_id),
+        )),
+    }
+}
+
+// Ability to dismiss the reports against content or remove it regardless of who wrote it,
+// provided you're the owner or an admin of its DAO
+#[ic_cdk::update(guard = "_check_can_update")]
+fn resolve_report(target: ReportTarget, action: ReportAction) -> Result<(), Error> {
+    match _locate_report_target(&target) {
+        Some((dao_id, audit_target)) => {
+            let dao = _get_dao(&dao_id).unwrap_or_default();
+            if !_is_dao_admin(&dao, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(dao_id),
+                    format!(
+                        "Couldn't resolve reports in dao with id={}. You are not an admin",
+                        dao_id
+                    ),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            match action {
+                ReportAction::Dismiss => {
+                    _set_hidden(&target, false);
+                    log_event(dao_id, AuditAction::ReportDismissed, audit_target);
+                }
+                ReportAction::Remove => {
+                    match target {
+                        ReportTarget::Proposal(id) => {
+                            if let Some(proposal) = _get_proposal(&id) {
+                                _delete_proposal_records(&proposal);
+                            }
+                        }
+                        ReportTarget::Comment(id) => {
+                            if let Some(comment) = _get_comment(&id) {
+                                _delete_comment_records(&comment);
+                            }
+                        }
+                    }
+                    log_event(dao_id, AuditAction::ContentRemoved, audit_target);
+                }
+            }
+            _clear_reports(&target.id());
+            Ok(())
+        }
+        None => Err(Error::not_found(
+            ErrorContext::from(&target),
+            format!(
+                "couldn't resolve reports on content with id={}. not found",
+                target.id()
+            ),
+        )),
+    }
+}
+
+/**
+* -----------------------------------------------------------------------------
+* USER ACTIVITY FUNCTIONS (scoped to DAOs the caller is still part of)
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to get the proposals you've created
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_my_proposals(offset: u64, limit: u64) -> Vec<ProposalView> {
+    let mut proposals: Vec<Proposal> = _get_caller_dao_proposals()
+        .into_iter()
+        .filter(|proposal| proposal.owner == Some(caller()))
+        .filter(|proposal| proposal.status != ProposalStatus::Cancelled)
+        .collect();
+    proposals.sort_by_key(|proposal| Reverse(proposal.created_at));
+
+    proposals
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .map(ProposalView::from)
+        .collect()
+}
+
+// Ability to get the comments you've written
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_my_comments(offset: u64, limit: u64) -> Vec<CommentView> {
+    let mut comments: Vec<Comment> = _get_caller_dao_proposals()
+        .iter()
+        .flat_map(|proposal| {
+            _comment_ids(&proposal.id)
+                .iter()
+                .filter_map(_get_comment)
+                .collect::<Vec<_>>()
+        })
+        .filter(|comment| comment.author == Some(caller()))
+        .collect();
+    comments.sort_by_key(|comment| Reverse(comment.created_at));
+
+    _comment_views(
+        comments
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit.min(MAX_PAGE_SIZE) as usize)
+            .collect(),
+    )
+}
+
+// Ability to get the votes you've cast along with the titles of the proposals voted on
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_my_votes(offset: u64, limit: u64) -> Vec<VoteEntry> {
+    let mut votes: Vec<VoteEntry> = _get_caller_dao_proposals()
+        .into_iter()
+        .filter_map(|proposal| {
+            _get_vote(&proposal.id, &caller()).map(|vote| VoteEntry {
+                proposal_id: proposal.id,
+                proposal_title: proposal.title,
+                direction: vote.direction,
+                voted_at: vote.voted_at,
+                ranking: vote.ranking,
+            })
+        })
+        .collect();
+    votes.sort_by_key(|vote| Reverse(vote.voted_at));
+
+    votes
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .collect()
+}
+
+// Ability to get the proposals with the most recent votes and comments across the DAOs you're
+// part of
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_home_feed(limit: u64) -> Vec<FeedItem> {
+    let since = time().saturating_sub(FEED_ACTIVITY_WINDOW);
+    let mut items: Vec<FeedItem> = _get_feed_proposals()
+        .into_iter()
+        .map(|(dao_name, proposal)| FeedItem {
+            recent_activity: _count_recent_activity(&proposal, since),
+            proposal: ProposalView::from(proposal),
+            dao_name,
+        })
+        .collect();
+    items.sort_by(|a, b| {
+        b.recent_activity.cmp(&a.recent_activity).then(
+            b.proposal
+                .last_activity_at
+                .cmp(&a.proposal.last_activity_at),
+        )
+    });
+
+    items
+        .into_iter()
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .collect()
+}
+
+// Ability to get the open proposals across the DAOs you're part of whose voting ends within the
+// next 72 hours, soonest first
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_ending_soon(limit: u64) -> Vec<FeedItem> {
+    let now = time();
+    let since = now.saturating_sub(FEED_ACTIVITY_WINDOW);
+    let mut items: Vec<FeedItem> = _get_feed_proposals()
+        .into_iter()
+        .filter(|(_, proposal)| {
+            proposal.status == ProposalStatus::Open
+                && proposal.deadline > now
+                && proposal.deadline - now <= ENDING_SOON_WINDOW
+        })
+        .map(|(dao_name, proposal)| FeedItem {
+            recent_activity: _count_recent_activity(&proposal, since),
+            proposal: ProposalView::from(proposal),
+            dao_name,
+        })
+        .collect();
+    items.sort_by_key(|item| item.proposal.deadline);
+
+    items
+        .into_iter()
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .collect()
+}
+
+/**
+* -----------------------------------------------------------------------------
+* REPUTATION FUNCTIONS
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to get the reputation a member has accrued in a DAO you're part of
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_member_reputation(dao_id: u64, member: Principal) -> Result<u64, Error> {
+    match _can_read_dao(&dao_id) {
+        Ok(_) => Ok(reputation::get(dao_id, &member)),
+        Err(error) => Err(error),
+    }
+}
+
+// Ability to get the members with the most reputation in a DAO you're part of
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_dao_leaderboard(dao_id: u64, limit: u64) -> Result<Vec<reputation::ReputationEntry>, Error> {
+    match _can_read_dao(&dao_id) {
+        Ok(_) => Ok(reputation::leaderboard(
+            dao_id,
+            limit.min(MAX_PAGE_SIZE) as usize,
+        )),
+        Err(error) => Err(error),
+    }
+}
+
+/**
+* -----------------------------------------------------------------------------
+* IMAGE FUNCTIONS
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to upload an image in chunks. The first chunk of an unknown upload_id starts the upload
+#[ic_cdk::update(guard = "_check_can_update")]
+fn upload_image_chunk(upload_id: u64, chunk_index: u64, bytes: Vec<u8>) -> Result<(), Error> {
+    _check_storage_available(ErrorContext::Upload(upload_id))?;
+    images::store_chunk(upload_id, chunk_index, bytes, &caller())
+}
+
+// Ability to finish an upload you started, turning it into an image that can be used as an avatar
+#[ic_cdk::update(guard = "_check_can_update")]
+fn finalize_image(upload_id: u64) -> Result<images::ImageId, Error> {
+    images::finalize(upload_id, &caller())
+}
+
+// Ability to get the size and number of chunks of an image
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_image_info(image_id: images::ImageId) -> Result<images::Image, Error> {
+    match images::get(&image_id) {
+        Some(image) => Ok(image),
+        None => Err(Error::not_found(
+            ErrorContext::Image(image_id),
+            format!("image with id={} not found", image_id),
+        )),
+    }
+}
+
+// Ability to download a chunk of an image
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_image(image_id: images::ImageId, chunk_index: u64) -> Result<Vec<u8>, Error> {
+    if images::get(&image_id).is_none() {
+        return Err(Error::not_found(
+            ErrorContext::Image(image_id),
+            format!("image with id={} not found", image_id),
+        ));
+    }
+
+    match images::get_chunk(image_id, chunk_index) {
+        Some(bytes) => Ok(bytes),
+        None => Err(Error::not_found(
+            ErrorContext::Image(image_id),
+            format!(
+                "chunk {} of image with id={} not found",
+                chunk_index, image_id
+            ),
+        )),
+    }
+}
+
+// Ability to read public DAOs and their avatars over plain HTTP, without an agent
+#[ic_cdk::query(guard = "_check_can_read")]
+fn http_request(request: http::HttpRequest) -> http::HttpResponse {
+    http::route(&request)
+}
+
+// Strips webhook responses down to their status so replicas agree on them, see webhooks
+#[ic_cdk::query(guard = "_check_can_read")]
+fn webhook_transform(
+    args: ic_cdk::api::management_canister::http_request::TransformArgs,
+) -> ic_cdk::api::management_canister::http_request::HttpResponse {
+    webhooks::transform(args)
+}
+
+// Strips fetched pages down to their title, description and image so replicas agree on them, see
+// previews
+#[ic_cdk::query(guard = "_check_can_read")]
+fn link_preview_transform(
+    args: ic_cdk::api::management_canister::http_request::TransformArgs,
+) -> ic_cdk::api::management_canister::http_request::HttpResponse {
+    previews::transform(args)
+}
+
+/**
+* -----------------------------------------------------------------------------
+* PROFILE FUNCTIONS
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to create or update your profile. Display names are unique regardless of case
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_my_profile(payload: ProfilePayload) -> Result<Profile, Error> {
+    _validate_profile(&payload)?;
+
+    let name_key = display_name_key(&payload.display_name);
+    let owner = PROFILE_NAME_INDEX.with(|service| service.borrow().get(&name_key));
+    if matches!(owner, Some(owner) if owner != principal_key(&caller())) {
+        return Err(Error::display_name_taken(
+            ErrorContext::None,
+            format!("display name {} is already taken", payload.display_name),
+        ));
+    }
+
+    let profile = match _get_profile(&caller()) {
+        Some(mut profile) => {
+            PROFILE_NAME_INDEX.with(|service| {
+                service
+                    .borrow_mut()
+                    .remove(&display_name_key(&profile.display_name))
+            });
+
+            profile.display_name = payload.display_name;
+            profile.bio = payload.bio;
+            profile.avatar_url = payload.avatar_url;
+            profile.updated_at = Some(time());
+            profile
+        }
+        None => Profile {
+            principal: Some(caller()),
+            display_name: payload.display_name,
+            bio: payload.bio,
+            avatar_url: payload.avatar_url,
+            created_at: time(),
+            updated_at: None,
+        },
+    };
+
+    do_insert_profile(&profile);
+    Ok(profile)
+}
+
+// Ability to get the profile of a user
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_profile(user: Principal) -> Result<Profile, Error> {
+    match _get_profile(&user) {
+        Some(profile) => Ok(profile),
+        None => Err(Error::not_found(
+            ErrorContext::Member(user),
+            format!("a profile for {} not found", user),
+        )),
+    }
+}
+
+// Ability to get your own profile
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_my_profile() -> Result<Profile, Error> {
+    get_profile(caller())
+}
+
+// Ability to get who you are, your profile, your DAOs and how many notifications you haven't read.
+// Never fails, a principal without any data gets empty values
+#[ic_cdk::query(guard = "_check_can_read")]
+fn whoami() -> WhoamiResponse {
+    let user = caller();
+    let memberships = _get_member_dao_ids(&user)
+        .iter()
+        .filter_map(_get_dao)
+        .map(|dao| MembershipStatus {
+            dao_id: dao.id,
+            role: _get_member_role(&dao, &user),
+            is_active: _check_dues_paid(&dao, &user, time()).is_ok(),
+        })
+        .collect();
+    let unread_notifications = _get_notification_ids(&user)
+        .iter()
+        .filter_map(_get_notification)
+        .filter(|notification| !notification.read)
+        .count() as u64;
+
+    WhoamiResponse {
+        principal: user,
+        is_anonymous: user == Principal::anonymous(),
+        profile: _get_profile(&user),
+        memberships,
+        unread_notifications,
+    }
+}
+
+// Ability to get the profiles of many users at once, e.g. to decorate a member list
+#[ic_cdk::query(guard = "_check_can_read")]
+fn resolve_principals(users: Vec<Principal>) -> Vec<(Principal, Option<Profile>)> {
+    users
+        .into_iter()
+        .take(MAX_PAGE_SIZE as usize)
+        .map(|user| (user, _get_profile(&user)))
+        .collect()
+}
+
+// Ability to get your preferences
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_my_preferences() -> UserPreferences {
+    _get_preferences(&caller())
+}
+
+// Ability to change your preferences
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_my_preferences(mut preferences: UserPreferences) -> UserPreferences {
+    // locales that can't be stored are dropped, the returned preferences show what was kept
+    preferences.locale = preferences
+        .locale
+        .as_deref()
+        .and_then(messages::normalize_locale);
+    PREFERENCES_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert(principal_key(&caller()), preferences.clone())
+    });
+    preferences
+}
+
+/**
+* -----------------------------------------------------------------------------
+* ACCOUNT LINKING FUNCTIONS
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to start linking another principal, e.g. from another device, to yours. Returns a
+// one-time code the other principal passes to complete_link within 10 minutes. When
+// `secondary_hint` is set only that principal can use the code
+#[ic_cdk::update(guard = "_check_can_update")]
+async fn start_link(secondary_hint: Option<Principal>) -> Result<String, Error> {
+    let primary = env::caller();
+    if primary == Principal::anonymous() {
+        return Err(Error::permission_error(
+            ErrorContext::Member(primary),
+            "the anonymous principal can't link accounts".to_string(),
+        ));
+    }
+    _check_can_link_to(&primary)?;
+
+    let (random,) = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .map_err(|(code, msg)| {
+            Error::invalid_input(
+                ErrorContext::Member(primary),
+                format!("couldn't generate a link code: {:?} {}", code, msg),
+            )
+        })?;
+    let mut code_bytes = [0u8; 8];
+    code_bytes.copy_from_slice(&random[..8]);
+    let code = u64::from_be_bytes(code_bytes);
+    LINK_REQUESTS.with(|service| {
+        service.borrow_mut().insert(
+            code,
+            LinkRequest {
+                primary,
+                secondary_hint,
+                expires_at: time() + LINK_CODE_TTL,
+            },
+        )
+    });
+    Ok(format!("{:016x}", code))
+}
+
+// Ability to link your principal to the one that started the link with `code`. From then on you
+// act as that principal in every DAO. Your principal can't be part of a DAO or have its own links
+#[ic_cdk::update(guard = "_check_can_update")]
+fn complete_link(code: String) -> Result<Vec<Principal>, Error> {
+    let alias = env::caller();
+    let request = u64::from_str_radix(&code, 16)
+        .ok()
+        .and_then(|code| LINK_REQUESTS.with(|service| service.borrow_mut().remove(&code)))
+        .filter(|request| request.expires_at >= time())
+        .filter(|request| request.secondary_hint.is_none_or(|hint| hint == alias));
+    let primary = match request {
+        Some(request) => request.primary,
+        None => {
+            return Err(Error::not_found(
+                ErrorContext::Member(alias),
+                "the link code is invalid or expired".to_string(),
+            ))
+        }
+    };
+
+    if alias == Principal::anonymous() || alias == primary {
+        return Err(Error::invalid_input(
+            ErrorContext::Member(alias),
+            "a principal can only be linked to another, non-anonymous principal".to_string(),
+        ));
+    }
+    if _get_primary(&alias).is_some() || !_get_aliases(&alias).is_empty() {
+        return Err(Error::invalid_input(
+            ErrorContext::Member(alias),
+            format!("{} is already linked to another principal", alias),
+        ));
+    }
+    let has_memberships = MEMBER_DAOS.with(|service| {
+        service
+            .borrow()
+            .range((principal_key(&alias), 0)..)
+            .next()
+            .is_some_and(|((member, _), _)| member == principal_key(&alias))
+    });
+    if has_memberships {
+        return Err(Error::invalid_input(
+            ErrorContext::Member(alias),
+            format!(
+                "{} is part of a DAO and would lose access to it. Leave it before linking",
+                alias
+            ),
+        ));
+    }
+    _check_can_link_to(&primary)?;
+
+    ACCOUNT_ALIASES.with(|service| {
+        service
+            .borrow_mut()
+            .insert(principal_key(&alias), principal_key(&primary))
+    });
+    ALIASES_BY_PRIMARY.with(|service| {
+        service
+            .borrow_mut()
+            .insert((principal_key(&primary), principal_key(&alias)), ())
+    });
+    Ok(_get_aliases(&primary))
+}
+
+// Ability to unlink a principal from yours. Only the primary principal itself can unlink
+#[ic_cdk::update(guard = "_check_can_update")]
+fn unlink(alias: Principal) -> Result<Vec<Principal>, Error> {
+    let primary = env::caller();
+    if _get_primary(&alias) != Some(primary) {
+        return Err(Error::not_found(
+            ErrorContext::Member(alias),
+            format!("{} is not linked to your principal", alias),
+        ));
+    }
+
+    ACCOUNT_ALIASES.with(|service| service.borrow_mut().remove(&principal_key(&alias)));
+    ALIASES_BY_PRIMARY.with(|service| {
+        service
+            .borrow_mut()
+            .remove(&(principal_key(&primary), principal_key(&alias)))
+    });
+    Ok(_get_aliases(&primary))
+}
+
+// Ability to see the principals linked to the one you act as
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_linked_principals() -> Vec<Principal> {
+    _get_aliases(&caller())
+}
+
+/**
+* -----------------------------------------------------------------------------
+* NOTIFICATION FUNCTIONS
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to get your notifications, newest first
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_my_notifications(only_unread: bool, offset: u64, limit: u64) -> Vec<Notification> {
+    _get_notification_ids(&caller())
+        .iter()
+        .rev()
+        .filter_map(_get_notification)
+        .filter(|notification| !only_unread || !notification.read)
+        .skip(offset as usize)
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .collect()
+}
+
+// Ability to mark one of your notifications as read
+#[ic_cdk::update(guard = "_check_can_update")]
+fn mark_notification_read(id: u64) -> Result<Notification, Error> {
+    match _get_notification(&id) {
+        Some(mut notification) if notification.recipient == Some(caller()) => {
+            notification.read = true;
+
+            do_insert_notification(&notification);
+            Ok(notification)
+        }
+        _ => Err(Error::not_found(
+            ErrorContext::Notification(id),
+            format!("a notification with id={} not found", id),
+        )),
+    }
+}
+
+// Ability to mark all your notifications as read. Returns how many were updated
+#[ic_cdk::update(guard = "_check_can_update")]
+fn mark_all_read() -> u64 {
+    let mut updated = 0;
+    for mut notification in _get_notification_ids(&caller())
+        .iter()
+        .filter_map(_get_notification)
+        .filter(|notification| !notification.read)
+    {
+        notification.read = true;
+        do_insert_notification(&notification);
+        updated += 1;
+    }
+
+    updated
+}
+
+// Ability to get notified about a proposal's comments, amendments and outcome providing you're a
+// member of its DAO
+#[ic_cdk::update(guard = "_check_can_update")]
+fn follow_proposal(id: u64) -> Result<(), Error> {
+    let proposal = _get_readable_proposal(&id)?;
+    _is_user_part_of_dao(&proposal.dao_id)?;
+    _follow(&id, &caller());
+    Ok(())
+}
+
+// Ability to stop getting notified about a proposal
+#[ic_cdk::update(guard = "_check_can_update")]
+fn unfollow_proposal(id: u64) -> Result<(), Error> {
+    let key = principal_key(&caller());
+    match PROPOSAL_FOLLOWERS.with(|service| service.borrow_mut().remove(&(id, key))) {
+        Some(_) => {
+            FOLLOWED_PROPOSALS.with(|service| service.borrow_mut().remove(&(key, id)));
+            Ok(())
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(id),
+            format!("you are not following a proposal with id={}", id),
+        )),
+    }
+}
+
+// Ability to get the proposals you follow, newest first
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_followed_proposals(offset: u64, limit: u64) -> Vec<ProposalView> {
+    let key = principal_key(&caller());
+    let ids: Vec<u64> = FOLLOWED_PROPOSALS.with(|service| {
+        service
+            .borrow()
+            .range((key, 0)..)
+            .take_while(|((follower, _), _)| *follower == key)
+            .map(|((_, proposal_id), _)| proposal_id)
+            .collect()
+    });
+
+    ids.iter()
+        .rev()
+        .filter_map(_get_proposal)
+        .filter(|proposal| _can_read_dao(&proposal.dao_id).is_ok())
+        .filter(|proposal| _can_see_proposal(proposal, &caller()))
+        .skip(offset as usize)
+        .take(limit.min(MAX_PAGE_SIZE) as usize)
+        .map(ProposalView::from)
+        .collect()
+}
+
+/**
+* -----------------------------------------------------------------------------
+* AUDIT LOG FUNCTIONS
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to get the governance history of a DAO you're part of, oldest first
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_dao_audit_log(dao_id: u64, offset: u64, limit: u64) -> Result<Vec<AuditEvent>, Error> {
+    match _can_read_dao(&dao_id) {
+        Ok(_) => Ok(AUDIT_STORAGE.with(|service| {
+            service
+                .borrow()
+                .iter()
+                .filter(|(_, event)| event.dao_id == dao_id)
+                .skip(offset as usize)
+                .take(limit.min(MAX_PAGE_SIZE) as usize)
+                .map(|(_, event)| event)
+                .collect()
+        })),
+        Err(error) => Err(error),
+    }
+}
+
+// Ability to get the history of a proposal and its comments, even after it was deleted
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_proposal_audit_log(proposal_id: u64) -> Result<Vec<AuditEvent>, Error> {
+    let events: Vec<AuditEvent> = AUDIT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, event)| match event.target {
+                AuditTarget::Proposal(id) => id == proposal_id,
+                AuditTarget::Comment {
+                    proposal_id: id, ..
+                } => id == proposal_id,
+                _ => false,
+            })
+            .map(|(_, event)| event)
+            .collect()
+    });
+
+    match events.first() {
+        Some(event) => match _can_read_dao(&event.dao_id) {
+            Ok(_) => Ok(events),
+            Err(error) => Err(error),
+        },
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(proposal_id),
+            format!("no history found for a proposal with id={}", proposal_id),
+        )),
+    }
+}
+
+// Ability to get a report of what happened in a DAO you're part of from `since` up to, but not
+// including, `until`. Windows longer than 90 days only cover their last 90 days
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_dao_activity(dao_id: u64, since: u64, until: u64) -> Result<DaoActivity, Error> {
+    _can_read_dao(&dao_id)?;
+    if until < since {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(dao_id),
+            format!("the window ends at {} before it starts at {}", until, since),
+        ));
+    }
+    let since = since.max(until.saturating_sub(MAX_ACTIVITY_WINDOW));
+
+    let mut activity = DaoActivity {
+        dao_id,
+        since,
+        until,
+        ..Default::default()
+    };
+    let mut finalized: BTreeSet<u64> = BTreeSet::new();
+    // proposal id -> (votes, comments)
+    let mut engagement: BTreeMap<u64, (u64, u64)> = BTreeMap::new();
+    let first_id = _first_audit_event_at(since);
+    AUDIT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range(first_id..)
+            .take_while(|(_, event)| event.timestamp < until)
+            .filter(|(_, event)| event.dao_id == dao_id)
+            .for_each(|(_, event)| match (event.action, event.target) {
+                (AuditAction::ProposalCreated, _) => activity.new_proposals += 1,
+                (AuditAction::ProposalFinalized, AuditTarget::Proposal(id)) => {
+                    finalized.insert(id);
+                }
+                (AuditAction::MemberJoined, _) => activity.new_members += 1,
+                (AuditAction::CommentCreated, AuditTarget::Comment { proposal_id, .. }) => {
+                    activity.new_comments += 1;
+                    engagement.entry(proposal_id).or_default().1 += 1;
+                }
+                (AuditAction::VoteCast, AuditTarget::Proposal(id)) => {
+                    activity.votes_cast += 1;
+                    engagement.entry(id).or_default().0 += 1;
+                }
+                _ => {}
+            })
+    });
+
+    // deleted proposals are left out, their outcome and title are gone with them
+    activity.finalized_proposals = finalized
+        .iter()
+        .filter_map(_get_proposal)
+        .map(|proposal| FinalizedProposal {
+            proposal_id: proposal.id,
+            title: proposal.title,
+            status: proposal.status,
+        })
+        .collect();
+    let mut top_proposals: Vec<ProposalEngagement> = engagement
+        .into_iter()
+        .filter_map(|(id, (votes, comments))| {
+            _get_proposal(&id).map(|proposal| ProposalEngagement {
+                proposal_id: id,
+                title: proposal.title,
+                votes,
+                comments,
+            })
+        })
+        .collect();
+    top_proposals.sort_by(|a, b| {
+        (b.votes + b.comments)
+            .cmp(&(a.votes + a.comments))
+            .then(a.proposal_id.cmp(&b.proposal_id))
+    });
+    top_proposals.truncate(ACTIVITY_TOP_PROPOSALS);
+    activity.top_proposals = top_proposals;
+    Ok(activity)
+}
+
+/**
+* -----------------------------------------------------------------------------
+* BACKUP FUNCTIONS
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to export everything in a DAO provided you're the owner
+#[ic_cdk::query(guard = "_check_can_read")]
+fn export_dao(dao_id: u64) -> Result<export::DaoExport, Error> {
+    match _get_dao(&dao_id) {
+        Some(dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(dao_id),
+                    format!(
+                        "Couldn't export dao with id={}. You are not the owner",
+                        dao_id
+                    ),
+                ));
+            }
+
+            Ok(export::build(dao))
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(dao_id),
+            format!("couldn't export a dao with id={}. dao not found", dao_id),
+        )),
+    }
+}
+
+// Ability to re-create an exported DAO with you as its owner
+#[ic_cdk::update(guard = "_check_can_update")]
+fn import_dao(export: export::DaoExport) -> Result<Dao, Error> {
+    _check_storage_available(ErrorContext::None)?;
+    let dao = export::restore(export, &caller())?;
+    log_event(dao.id, AuditAction::DaoImported, AuditTarget::Dao(dao.id));
+    registry::announce(&dao, registry::RegistryEvent::Listed);
+    Ok(dao)
+}
+
+/**
+* -----------------------------------------------------------------------------
+* CANISTER ADMIN FUNCTIONS
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to set the canister admin providing you're a controller or the current admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_admin(admin: Principal) -> Result<(), Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't set the admin. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    let mut config = CONFIG.with(|config| config.borrow().get().clone());
+    config.admin = Some(admin);
+    CONFIG
+        .with(|cell| cell.borrow_mut().set(config))
+        .expect("cannot update the config");
+
+    Ok(())
+}
+
+// Ability to change how many entities a single import may create provided you're a controller or the admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_max_import_entities(max_import_entities: u64) -> Result<(), Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't set the import limit. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    let mut config = CONFIG.with(|config| config.borrow().get().clone());
+    config.max_import_entities = Some(max_import_entities);
+    CONFIG
+        .with(|cell| cell.borrow_mut().set(config))
+        .expect("cannot update the config");
+
+    Ok(())
+}
+
+// Ability to set the message of an error code in a locale provided you're a controller or the
+// admin. Passing no template goes back to the message shipped with the canister
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_error_message(code: u16, locale: String, template: Option<String>) -> Result<(), Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't set the error message. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    messages::set_template(code, &locale, template)
+}
+
+// Ability to change how often maintenance runs provided you're a controller or the admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_maintenance_interval(seconds: u64) -> Result<(), Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't set the maintenance interval. Only a controller or the admin can".to_string(),
+        ));
+    }
+    if seconds == 0 {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            "maintenance interval must be at least 1 second".to_string(),
+        ));
+    }
+
+    let mut config = CONFIG.with(|config| config.borrow().get().clone());
+    config.maintenance_interval_seconds = Some(seconds);
+    CONFIG
+        .with(|cell| cell.borrow_mut().set(config))
+        .expect("cannot update the config");
+
+    maintenance::schedule();
+    Ok(())
+}
+
+// Ability to choose the ICRC-2 ledger dues are paid through provided you're a controller or the
+// admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_ledger_canister(ledger: Principal) -> Result<(), Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't set the ledger. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    let mut config = CONFIG.with(|config| config.borrow().get().clone());
+    config.ledger_canister_id = Some(ledger);
+    CONFIG
+        .with(|cell| cell.borrow_mut().set(config))
+        .expect("cannot update the config");
+    Ok(())
+}
+
+// Ability to choose the registry canister public DAOs are announced to, or to stop announcing
+// them, provided you're a controller or the admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_registry_canister(registry: Option<Principal>) -> Result<(), Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't set the registry. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    let mut config = CONFIG.with(|config| config.borrow().get().clone());
+    config.registry_canister = registry;
+    CONFIG
+        .with(|cell| cell.borrow_mut().set(config))
+        .expect("cannot update the config");
+    registry::resume();
+    Ok(())
+}
+
+// Ability to see the announcements waiting for the registry canister provided you're a
+// controller or the admin
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_outbox_status() -> Result<registry::OutboxStatus, Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't get the outbox. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    Ok(registry::status())
+}
+
+// Ability to see how far the storage migration after an upgrade got provided you're a controller
+// or the admin
+#[ic_cdk::query]
+fn get_migration_status() -> Result<migrations::MigrationStatus, Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't get the migration status. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    Ok(migrations::status())
+}
+
+// Ability to deliver the waiting announcements to the registry canister right away provided
+// you're a controller or the admin. Returns what is still waiting afterwards
+#[ic_cdk::update(guard = "_check_can_update")]
+async fn flush_outbox() -> Result<registry::OutboxStatus, Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't flush the outbox. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    registry::flush().await;
+    Ok(registry::status())
+}
+
+// Ability to ban a user from changing anything on the canister, e.g. a spam ring, providing
+// you're a controller or the admin. What they posted before stays as it is
+#[ic_cdk::update(guard = "_check_can_update")]
+fn global_ban(user: Principal, reason: String) -> Result<GlobalBan, Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::Member(user),
+            "Couldn't ban the user. Only a controller or the admin can".to_string(),
+        ));
+    }
+    if _is_canister_admin(&user) {
+        return Err(Error::invalid_input(
+            ErrorContext::Member(user),
+            "Couldn't ban the user. Controllers and admins can't be banned".to_string(),
+        ));
+    }
+    let reason = reason.trim().to_string();
+    if reason.is_empty() || reason.chars().count() > MAX_GLOBAL_BAN_REASON_LEN {
+        return Err(Error::invalid_input(
+            ErrorContext::Member(user),
+            format!(
+                "reason must be between 1 and {} characters",
+                MAX_GLOBAL_BAN_REASON_LEN
+            ),
+        ));
+    }
+
+    let ban = GlobalBan {
+        user,
+        reason,
+        banned_by: caller(),
+        banned_at: time(),
+    };
+    GLOBAL_BANS.with(|service| {
+        service
+            .borrow_mut()
+            .insert(principal_key(&user), ban.clone())
+    });
+    Ok(ban)
+}
+
+// Ability to lift a ban from the whole canister providing you're a controller or the admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn global_unban(user: Principal) -> Result<(), Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::Member(user),
+            "Couldn't unban the user. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    match GLOBAL_BANS.with(|service| service.borrow_mut().remove(&principal_key(&user))) {
+        Some(_) => Ok(()),
+        None => Err(Error::not_found(
+            ErrorContext::Member(user),
+            format!("couldn't unban {}. user isn't banned", user),
+        )),
+    }
+}
+
+// Ability to see who is banned from the whole canister providing you're a controller or the admin
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_global_bans() -> Result<Vec<GlobalBan>, Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't get the bans. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    Ok(GLOBAL_BANS.with(|service| service.borrow().iter().map(|(_, ban)| ban).collect()))
+}
+
+// Ability to see how the canister is configured
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_config() -> CanisterConfig {
+    CONFIG.with(|config| config.borrow().get().clone())
+}
+
+// Ability to see which version of the interface the canister serves and which features are on
+#[ic_cdk::query]
+fn get_api_version() -> features::ApiVersion {
+    features::api_version()
+}
+
+// Ability to list the ICRC standards the canister implements
+#[ic_cdk::query(guard = "_check_can_read")]
+fn icrc10_supported_standards() -> Vec<icrc21::SupportedStandard> {
+    icrc21::supported_standards()
+}
+
+// Ability to get a description of an update call to show before signing it, in the language you
+// prefer. Calls that move tokens include the amount
+#[ic_cdk::update(guard = "_check_can_update")]
+fn icrc21_canister_call_consent_message(
+    request: icrc21::ConsentMessageRequest,
+) -> Result<icrc21::ConsentInfo, icrc21::Icrc21Error> {
+    icrc21::consent_message(request)
+}
+
+// Ability to turn a feature on or off provided you're a controller or the admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn set_feature_flag(feature: features::Feature, enabled: bool) -> Result<(), Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't set a feature flag. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    let mut config = CONFIG.with(|config| config.borrow().get().clone());
+    let flags = config.feature_flags.get_or_insert_with(Vec::new);
+    flags.retain(|(flag, _)| *flag != feature);
+    flags.push((feature, enabled));
+    CONFIG
+        .with(|cell| cell.borrow_mut().set(config))
+        .expect("cannot update the config");
+    Ok(())
+}
+
+// Ability to change the canister settings provided you're a controller or the admin. Anything
+// left out keeps its current value
+#[ic_cdk::update(guard = "_check_can_update")]
+fn update_config(args: InitArgs) -> Result<CanisterConfig, Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't update the config. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    let config =
+        _apply_init_args(args).map_err(|msg| Error::invalid_input(ErrorContext::None, msg))?;
+    maintenance::schedule();
+    storage::refresh();
+    Ok(config)
+}
+
+// Ability to run maintenance right away provided you're a controller or the admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn run_maintenance() -> Result<maintenance::MaintenanceReport, Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't run maintenance. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    Ok(maintenance::run())
+}
+
+// Ability to remove duplicate voters from the proposals of a DAO provided you're a controller or
+// the admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn dedupe_dao(dao_id: u64) -> Result<maintenance::DedupeReport, Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::Dao(dao_id),
+            "Couldn't dedupe the dao. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    maintenance::dedupe_dao(dao_id).ok_or_else(|| {
+        Error::not_found(
+            ErrorContext::Dao(dao_id),
+            format!("a dao with id={} not found", dao_id),
+        )
+    })
+}
+
+// Ability to find and repair references between DAOs, proposals and comments that point at
+// records that are gone, providing you're a controller or the admin. Nothing is changed when
+// `dry_run` is set. Each call goes through a batch of records, call again with the returned
+// cursor until it is None
+#[ic_cdk::update(guard = "_check_can_update")]
+fn repair_integrity(
+    dry_run: bool,
+    cursor: Option<maintenance::IntegrityCursor>,
+) -> Result<maintenance::IntegrityReport, Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't repair integrity. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    Ok(maintenance::repair_integrity(dry_run, cursor))
+}
+
+// Ability to get canister wide metrics providing you're a controller or the admin
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_canister_metrics() -> Result<metrics::CanisterMetrics, Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't get canister metrics. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    Ok(metrics::collect())
+}
+
+// Ability to see how many instructions the profiled endpoints used since the canister started or
+// the stats were reset providing you're a controller or the admin
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_perf_stats() -> Result<Vec<metrics::EndpointStats>, Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't get the perf stats. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    Ok(metrics::perf_stats())
+}
+
+// Ability to clear the instruction stats of the profiled endpoints providing you're a controller
+// or the admin
+#[ic_cdk::update(guard = "_check_can_update")]
+fn reset_perf_stats() -> Result<(), Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't reset the perf stats. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    metrics::reset_perf_stats();
+    Ok(())
+}
+
+// Ability to see how full this canister is as a shard and which DAOs are the largest providing
+// you're a controller or the admin
+#[ic_cdk::query(guard = "_check_can_read")]
+fn get_shard_info() -> Result<sharding::ShardInfo, Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't get shard info. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    Ok(sharding::shard_info())
+}
+
+// Ability to check that the treasury history of a DAO adds up provided you're a controller or
+// the admin. Every recorded balance is recomputed from the transactions before it
+#[ic_cdk::query(guard = "_check_can_read")]
+fn verify_treasury(dao_id: u64) -> Result<treasury::TreasuryCheck, Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't verify the treasury. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    Ok(treasury::verify(dao_id))
+}
+
+/**
+* -----------------------------------------------------------------------------
+* SHARDING FUNCTIONS (calls of DAOs kept in a bucket are forwarded to it)
+* -----------------------------------------------------------------------------
+*/
+
+// Ability to upload the wasm buckets are installed with providing you're a controller or the
+// admin. It is sent in chunks, the first one with `append` false. Returns its size so far
+#[ic_cdk::update(guard = "_check_can_update")]
+fn upload_bucket_wasm(chunk: Vec<u8>, append: bool) -> Result<u64, Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't upload the bucket wasm. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    Ok(sharding::upload_wasm(chunk, append))
+}
+
+// Ability to spawn a bucket providing you're a controller or the admin. The first one starts
+// placing new DAOs in buckets, the next ones are spawned when the last one is full
+#[ic_cdk::update(guard = "_check_can_update")]
+async fn spawn_bucket() -> Result<sharding::Bucket, Error> {
+    if !_is_canister_admin(&caller()) {
+        return Err(Error::unauthorized(
+            ErrorContext::None,
+            "Couldn't spawn a bucket. Only a controller or the admin can".to_string(),
+        ));
+    }
+
+    sharding::spawn_bucket().await
+}
+
+// Ability to create a proposal, see add_proposal. Routed DAOs don't take proposal deposits yet
+#[ic_cdk::update(name = "add_proposal", guard = "_check_can_update")]
+async fn routed_add_proposal(proposal: ProposalPayload) -> Result<ProposalView, Error> {
+    let dao_id = proposal.dao_id;
+    if !sharding::is_routed(dao_id) {
+        return add_proposal(proposal).await;
+    }
+    if _get_settings(&dao_id).proposal_deposit.unwrap_or_default() > 0 {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(dao_id),
+            format!(
+                "Couldn't create a proposal in dao with id={}. Its bucket doesn't take deposits yet",
+                dao_id
+            ),
+        ));
+    }
+
+    let proposal = sharding::forward(dao_id, sharding::BucketCall::AddProposal(proposal))
+        .await?
+        .into_proposal()?;
+    sharding::record_proposal(&proposal);
+    Ok(proposal)
+}
+
+// Ability to get a single proposal, see get_proposal
+#[ic_cdk::query(name = "get_proposal", composite = true, guard = "_check_can_read")]
+async fn routed_get_proposal(id: u64) -> Result<ProposalView, Error> {
+    match sharding::routed_proposal(id) {
+        Some(dao_id) => sharding::forward(dao_id, sharding::BucketCall::GetProposal(id))
+            .await?
+            .into_proposal(),
+        None => get_proposal(id),
+    }
+}
+
+// Ability to get all proposals in the DAO, see get_all_proposals
+#[ic_cdk::query(
+    name = "get_all_proposals",
+    composite = true,
+    guard = "_check_can_read"
+)]
+async fn routed_get_all_proposals(dao_id: u64) -> Result<Vec<ProposalView>, Error> {
+    if !sharding::is_routed(dao_id) {
+        return get_all_proposals(dao_id);
+    }
+
+    sharding::forward(dao_id, sharding::BucketCall::GetAllProposals(dao_id))
+        .await?
+        .into_proposals()
+}
+
+// Ability to upvote a proposal, see upvote. The constitution is kept here, so it is checked
+// before the vote is forwarded
+#[ic_cdk::update(name = "upvote", guard = "_check_can_update")]
+async fn routed_upvote(id: u64) -> Result<ProposalView, Error> {
+    match sharding::routed_proposal(id) {
+        Some(dao_id) => {
+            _check_constitution_accepted(&dao_id, &caller())?;
+            sharding::forward(dao_id, sharding::BucketCall::Upvote(id))
+                .await?
+                .into_proposal()
+        }
+        None => upvote(id),
+    }
+}
+
+// Ability to downvote a proposal, see downvote and routed_upvote
+#[ic_cdk::update(name = "downvote", guard = "_check_can_update")]
+async fn routed_downvote(id: u64) -> Result<ProposalView, Error> {
+    match sharding::routed_proposal(id) {
+        Some(dao_id) => {
+            _check_constitution_accepted(&dao_id, &caller())?;
+            sharding::forward(dao_id, sharding::BucketCall::Downvote(id))
+                .await?
+                .into_proposal()
+        }
+        None => downvote(id),
+    }
+}
+
+// Ability to end a proposal, see end_proposal_vote
+#[ic_cdk::update(name = "end_proposal_vote", guard = "_check_can_update")]
+async fn routed_end_proposal_vote(id: u64) -> Result<ProposalView, Error> {
+    match sharding::routed_proposal(id) {
+        Some(dao_id) => sharding::forward(dao_id, sharding::BucketCall::EndProposalVote(id))
+            .await?
+            .into_proposal(),
+        None => end_proposal_vote(id),
+    }
+}
+
+// Ability to comment a proposal, see comment_on_post
+#[ic_cdk::update(name = "comment_on_post", guard = "_check_can_update")]
+async fn routed_comment_on_post(comment: CommentPayload) -> Result<Comment, Error> {
+    match sharding::routed_proposal(comment.proposal_id) {
+        Some(dao_id) => sharding::forward(dao_id, sharding::BucketCall::CommentOnPost(comment))
+            .await?
+            .into_comment(),
+        None => comment_on_post(comment),
+    }
+}
+
+// Ability to get all comments on a proposal, see get_all_comments_on_proposal
+#[ic_cdk::query(
+    name = "get_all_comments_on_proposal",
+    composite = true,
+    guard = "_check_can_read"
+)]
+async fn routed_get_all_comments_on_proposal(proposal_id: u64) -> Result<Vec<CommentView>, Error> {
+    match sharding::routed_proposal(proposal_id) {
+        Some(dao_id) => sharding::forward(
+            dao_id,
+            sharding::BucketCall::GetAllCommentsOnProposal(proposal_id),
+        )
+        .await?
+        .into_comments(),
+        None => get_all_comments_on_proposal(proposal_id),
+    }
+}
+
+// Ability for the main canister to run a call it forwarded to this bucket, see sharding
+#[ic_cdk::update(guard = "_check_forwarded")]
+async fn bucket_update(request: sharding::BucketRequest) -> Result<sharding::BucketReply, Error> {
+    sharding::handle_update(request).await
+}
+
+// Ability for the main canister to run a query it forwarded to this bucket
+#[ic_cdk::query(guard = "_check_forwarded")]
+fn bucket_query(request: sharding::BucketRequest) -> Result<sharding::BucketReply, Error> {
+    sharding::handle_query(request)
+}
+
+/**
+* -----------------------------------------------------------------------------
+* ERRORS
+* -----------------------------------------------------------------------------
+*/
+
+// Every error carries a stable numeric code clients can match on, what it is about and a
+// readable message. Codes are part of the public interface: never renumber or reuse one
+#[derive(candid::CandidType, Clone, Deserialize, Serialize)]
+enum Error {
+    NotFound {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    NotAMember {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    AlreadyMember {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    AlreadyRequested {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    Banned {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    InvalidInput {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    DisplayNameTaken {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    Unauthorized {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    NotEligible {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    RateLimited {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+        retry_after_seconds: u64,
+    },
+    ThreadLocked {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    HasVoted {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    CantVoteYours {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    CantLikeYours {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    // no longer returned, kept so existing clients still decode every variant
+    CantEditProposal {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    PermissionError {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    DeadlineExceeded {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    DeadlineNotExceeded {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    AlreadyLiked {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    InsufficientFunds {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    // the record changed since the caller read it
+    VersionConflict {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+        current_version: u64,
+    },
+    // the DAO requires its latest constitution to be accepted first
+    ConstitutionNotAccepted {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    // the ledger couldn't be reached or rejected a transfer
+    LedgerError {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    DaoArchived {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    // voting on the proposal is over and its DAO locks the thread after that
+    ProposalClosed {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    // the proposal's comment window is over, existing comments can still be liked
+    CommentWindowClosed {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    // the endpoint is behind a feature flag that is off, see get_api_version
+    FeatureDisabled {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    // the DAO has max_open_proposals open, see ProposalPayload::queue_if_full
+    TooManyOpenProposals {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    // stable memory reached its soft limit, nothing new is stored until there is room again
+    StorageFull {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    // another treasury payment of the proposal is waiting for the ledger, see locks::acquire
+    ExecutionInProgress {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+    // the bucket canister holding the DAO couldn't be reached or spawned, see sharding
+    BucketUnavailable {
+        code: u16,
+        context: ErrorContext,
+        msg: String,
+    },
+}
+
+// The entity an error is about
+#[derive(candid::CandidType, Clone, Copy, Deserialize, Serialize, Default, PartialEq)]
+enum ErrorContext {
+    #[default]
+    None,
+    Dao(u64),
+    Proposal(u64),
+    Comment(u64),
+    Attachment {
+        proposal_id: u64,
+        index: u64,
+    },
+    JoinRequest(u64),
+    Notification(u64),
+    Image(u64),
+    Upload(u64),
+    Member(Principal),
+}
+
+impl From<&ReportTarget> for ErrorContext {
+    fn from(target: &ReportTarget) -> Self {
+        match target {
+            ReportTarget::Proposal(id) => ErrorContext::Proposal(*id),
+            ReportTarget::Comment(id) => ErrorContext::Comment(*id),
+        }
+    }
+}
+
+impl Error {
+    fn not_found(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1001, &context, msg);
+        Error::NotFound {
+            code: 1001,
+            context,
+            msg,
+        }
+    }
+
+    fn not_a_member(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1002, &context, msg);
+        Error::NotAMember {
+            code: 1002,
+            context,
+            msg,
+        }
+    }
+
+    fn already_member(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1003, &context, msg);
+        Error::AlreadyMember {
+            code: 1003,
+            context,
+            msg,
+        }
+    }
+
+    fn already_requested(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1004, &context, msg);
+        Error::AlreadyRequested {
+            code: 1004,
+            context,
+            msg,
+        }
+    }
+
+    fn banned(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1005, &context, msg);
+        Error::Banned {
+            code: 1005,
+            context,
+            msg,
+        }
+    }
+
+    fn invalid_input(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1006, &context, msg);
+        Error::InvalidInput {
+            code: 1006,
+            context,
+            msg,
+        }
+    }
+
+    fn display_name_taken(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1007, &context, msg);
+        Error::DisplayNameTaken {
+            code: 1007,
+            context,
+            msg,
+        }
+    }
+
+    fn unauthorized(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1008, &context, msg);
+        Error::Unauthorized {
+            code: 1008,
+            context,
+            msg,
+        }
+    }
+
+    fn not_eligible(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1009, &context, msg);
+        Error::NotEligible {
+            code: 1009,
+            context,
+            msg,
+        }
+    }
+
+    fn rate_limited(retry_after_seconds: u64) -> Self {
+        Error::RateLimited {
+            code: 1010,
+            context: ErrorContext::None,
+            msg: messages::localize(
+                1010,
+                &ErrorContext::None,
+                format!(
+                    "rate limit reached, retry in {} seconds",
+                    retry_after_seconds
+                ),
+            ),
+            retry_after_seconds,
+        }
+    }
+
+    fn thread_locked(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1011, &context, msg);
+        Error::ThreadLocked {
+            code: 1011,
+            context,
+            msg,
+        }
+    }
+
+    fn has_voted(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1012, &context, msg);
+        Error::HasVoted {
+            code: 1012,
+            context,
+            msg,
+        }
+    }
+
+    fn cant_vote_yours(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1013, &context, msg);
+        Error::CantVoteYours {
+            code: 1013,
+            context,
+            msg,
+        }
+    }
+
+    fn cant_like_yours(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1014, &context, msg);
+        Error::CantLikeYours {
+            code: 1014,
+            context,
+            msg,
+        }
+    }
+
+    fn permission_error(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1016, &context, msg);
+        Error::PermissionError {
+            code: 1016,
+            context,
+            msg,
+        }
+    }
+
+    fn deadline_exceeded(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1017, &context, msg);
+        Error::DeadlineExceeded {
+            code: 1017,
+            context,
+            msg,
+        }
+    }
+
+    fn deadline_not_exceeded(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1018, &context, msg);
+        Error::DeadlineNotExceeded {
+            code: 1018,
+            context,
+            msg,
+        }
+    }
+
+    fn already_liked(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1019, &context, msg);
+        Error::AlreadyLiked {
+            code: 1019,
+            context,
+            msg,
+        }
+    }
+
+    fn insufficient_funds(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1020, &context, msg);
+        Error::InsufficientFunds {
+            code: 1020,
+            context,
+            msg,
+        }
+    }
+
+    fn version_conflict(context: ErrorContext, current_version: u64) -> Self {
+        let msg = messages::localize(
+            1021,
+            &context,
+            format!(
+                "the record was changed since it was read, its current version is {}",
+                current_version
+            ),
+        );
+        Error::VersionConflict {
+            code: 1021,
+            context,
+            msg,
+            current_version,
+        }
+    }
+
+    fn constitution_not_accepted(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1022, &context, msg);
+        Error::ConstitutionNotAccepted {
+            code: 1022,
+            context,
+            msg,
+        }
+    }
+
+    fn ledger_error(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1023, &context, msg);
+        Error::LedgerError {
+            code: 1023,
+            context,
+            msg,
+        }
+    }
+
+    fn dao_archived(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1024, &context, msg);
+        Error::DaoArchived {
+            code: 1024,
+            context,
+            msg,
+        }
+    }
+
+    fn proposal_closed(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1025, &context, msg);
+        Error::ProposalClosed {
+            code: 1025,
+            context,
+            msg,
+        }
+    }
+
+    fn comment_window_closed(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1026, &context, msg);
+        Error::CommentWindowClosed {
+            code: 1026,
+            context,
+            msg,
+        }
+    }
+
+    fn feature_disabled(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1027, &context, msg);
+        Error::FeatureDisabled {
+            code: 1027,
+            context,
+            msg,
+        }
+    }
+
+    fn too_many_open_proposals(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1028, &context, msg);
+        Error::TooManyOpenProposals {
+            code: 1028,
+            context,
+            msg,
+        }
+    }
+
+    fn storage_full(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1029, &context, msg);
+        Error::StorageFull {
+            code: 1029,
+            context,
+            msg,
+        }
+    }
+
+    fn execution_in_progress(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1030, &context, msg);
+        Error::ExecutionInProgress {
+            code: 1030,
+            context,
+            msg,
+        }
+    }
+
+    fn bucket_unavailable(context: ErrorContext, msg: String) -> Self {
+        let msg = messages::localize(1031, &context, msg);
+        Error::BucketUnavailable {
+            code: 1031,
+            context,
+            msg,
+        }
+    }
+}
+
+/**
+* -----------------------------------------------------------------------------
+* HELPER FUNCTIONS
+* -----------------------------------------------------------------------------
+*/
+
+// helper method to perform insert.
+fn do_insert_proposal(proposal: &mut Proposal) {
+    proposal.version += 1;
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(proposal.id, proposal.clone()));
+    certification::certify(proposal);
+}
+
+// helper method to perform insert.
+fn do_insert_dao(dao: &mut Dao) {
+    dao.version += 1;
+    let previous = DAO_STORAGE.with(|service| service.borrow_mut().insert(dao.id, dao.clone()));
+
+    // members are indexed as their membership changes, see _set_membership. Only a change of
+    // owner is left to the member index here
+    if let Some(previous_owner) = previous.and_then(|previous| previous.owner) {
+        if dao.owner != Some(previous_owner) && !_is_member(dao, &previous_owner) {
+            MEMBER_DAOS.with(|service| {
+                service
+                    .borrow_mut()
+                    .remove(&(principal_key(&previous_owner), dao.id))
+            });
+        }
+    }
+    if let Some(owner) = dao.owner {
+        MEMBER_DAOS.with(|service| {
+            service
+                .borrow_mut()
+                .insert((principal_key(&owner), dao.id), ())
+        });
+    }
+}
+
+// helper method to perform insert.
+fn do_insert_comment(comment: &Comment) {
+    COMMENT_STORAGE.with(|service| service.borrow_mut().insert(comment.id, comment.clone()));
+}
+
+// helper method to perform insert.
+fn do_insert_invite(invite: &Invite) {
+    if let Some(invitee) = invite.invitee {
+        INVITE_STORAGE.with(|service| {
+            service
+                .borrow_mut()
+                .insert((invite.dao_id, principal_key(&invitee)), invite.clone())
+        });
+    }
+}
+
+// helper method to perform insert. Keeps PENDING_JOIN_REQUESTS in step with the request's status
+fn do_insert_join_request(request: &JoinRequest) {
+    JOIN_REQUEST_STORAGE.with(|service| service.borrow_mut().insert(request.id, request.clone()));
+    PENDING_JOIN_REQUESTS.with(|service| {
+        let key = (request.dao_id, request.id);
+        if request.status == JoinRequestStatus::Pending {
+            service.borrow_mut().insert(key, ());
+        } else {
+            service.borrow_mut().remove(&key);
+        }
+    });
+}
+
+// helper method to perform insert.
+fn do_insert_vote(vote: &Vote) {
+    if let Some(voter) = vote.voter {
+        VOTE_STORAGE.with(|service| {
+            service
+                .borrow_mut()
+                .insert((vote.proposal_id, principal_key(&voter)), vote.clone())
+        });
+    }
+}
+
+// helper method to append to the audit log. Events are never removed
+fn log_event(dao_id: u64, action: AuditAction, target: AuditTarget) {
+    AUDIT_STORAGE.with(|service| {
+        let id = service.borrow().len();
+        let event = AuditEvent {
+            id,
+            timestamp: time(),
+            actor: Some(caller()),
+            dao_id,
+            action,
+            target,
+        };
+        service.borrow_mut().insert(id, event)
+    });
+}
+
+// a helper method to find the id of the first audit event logged at or after `timestamp`. Events
+// are numbered in the order they were logged, so their timestamps never go down
+fn _first_audit_event_at(timestamp: u64) -> u64 {
+    AUDIT_STORAGE.with(|service| {
+        let service = service.borrow();
+        let (mut low, mut high) = (0, service.len());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match service.get(&middle) {
+                Some(event) if event.timestamp < timestamp => low = middle + 1,
+                _ => high = middle,
+            }
+        }
+        low
+    })
+}
+
+// helper method to log an event about a comment under the DAO its proposal belongs to
+fn log_comment_event(comment: &Comment, action: AuditAction) {
+    if let Some(proposal) = _get_proposal(&comment.proposal_id) {
+        log_event(
+            proposal.dao_id,
+            action,
+            AuditTarget::Comment {
+                id: comment.id,
+                proposal_id: comment.proposal_id,
+            },
+        );
+    }
+}
+
+// helper method to perform insert.
+fn do_insert_notification(notification: &Notification) {
+    NOTIFICATION_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert(notification.id, notification.clone())
+    });
+}
+
+// helper method to send a notification
+fn notify(
+    recipient: &Principal,
+    kind: NotificationKind,
+    dao_id: u64,
+    proposal_id: Option<u64>,
+    comment_id: Option<u64>,
+) {
+    notify_count(recipient, kind, dao_id, proposal_id, comment_id, None)
+}
+
+// helper method to send a notification, evicting the recipient's oldest ones past the cap. `count`
+// is set on notifications standing for several events, see likes::liked
+fn notify_count(
+    recipient: &Principal,
+    kind: NotificationKind,
+    dao_id: u64,
+    proposal_id: Option<u64>,
+    comment_id: Option<u64>,
+    count: Option<u64>,
+) {
+    let id = ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("cannot increment id counter");
+
+    do_insert_notification(&Notification {
+        id,
+        recipient: Some(*recipient),
+        kind,
+        dao_id,
+        proposal_id,
+        comment_id,
+        created_at: time(),
+        read: false,
+        count,
+    });
+    NOTIFICATIONS_BY_RECIPIENT.with(|service| {
+        service
+            .borrow_mut()
+            .insert((principal_key(recipient), id), ())
+    });
+
+    let ids = _get_notification_ids(recipient);
+    if ids.len() > MAX_NOTIFICATIONS_PER_USER {
+        for evicted in &ids[..ids.len() - MAX_NOTIFICATIONS_PER_USER] {
+            NOTIFICATION_STORAGE.with(|service| service.borrow_mut().remove(evicted));
+            NOTIFICATIONS_BY_RECIPIENT.with(|service| {
+                service
+                    .borrow_mut()
+                    .remove(&(principal_key(recipient), *evicted))
+            });
+        }
+    }
+}
+
+// helper method to perform insert. Also claims the profile's display name
+fn do_insert_profile(profile: &Profile) {
+    if let Some(principal) = profile.principal {
+        PROFILE_STORAGE.with(|service| {
+            service
+                .borrow_mut()
+                .insert(principal_key(&principal), profile.clone())
+        });
+        PROFILE_NAME_INDEX.with(|service| {
+            service.borrow_mut().insert(
+                display_name_key(&profile.display_name),
+                principal_key(&principal),
+            )
+        });
+    }
+}
+
+// a helper method to add a member to a DAO, remembering when they joined. An observer becomes a
+// member. The DAO still has to be saved with do_insert_dao
+fn _admit_member(dao: &mut Dao, member: &Principal) {
+    if _membership(dao.id, member).is_some_and(Membership::is_member) {
+        return;
+    }
+    _set_membership(dao, member, Some(Membership::Member));
+    dao.updated_at = Some(time());
+    record_joined_at(dao.id, member);
+    log_event(
+        dao.id,
+        AuditAction::MemberJoined,
+        AuditTarget::Member(*member),
+    );
+}
+
+// a helper method to get how a user belongs to a DAO. None for the owner and for outsiders
+fn _membership(dao_id: u64, user: &Principal) -> Option<Membership> {
+    DAO_MEMBERSHIPS.with(|service| service.borrow().get(&(dao_id, principal_key(user))))
+}
+
+// a helper method to change how a user belongs to a DAO, None takes them out of it. Keeps the
+// member count of the DAO and the member index up to date. The DAO still has to be saved with
+// do_insert_dao
+fn _set_membership(dao: &mut Dao, user: &Principal, membership: Option<Membership>) {
+    let key = (dao.id, principal_key(user));
+    let previous = DAO_MEMBERSHIPS.with(|service| match membership {
+        Some(membership) => service.borrow_mut().insert(key, membership),
+        None => service.borrow_mut().remove(&key),
+    });
+    let was_member = previous.is_some_and(Membership::is_member);
+    let is_member = membership.is_some_and(Membership::is_member);
+    if was_member == is_member {
+        return;
+    }
+
+    let index_key = (principal_key(user), dao.id);
+    if is_member {
+        dao.member_count += 1;
+        MEMBER_DAOS.with(|service| service.borrow_mut().insert(index_key, ()));
+    } else {
+        dao.member_count = dao.member_count.saturating_sub(1);
+        if dao.owner != Some(*user) {
+            MEMBER_DAOS.with(|service| service.borrow_mut().remove(&index_key));
+        }
+    }
+}
+
+// a helper method to list who has a membership in a DAO, in the order of their principals
+fn _memberships(dao_id: u64) -> Vec<(Principal, Membership)> {
+    DAO_MEMBERSHIPS.with(|service| {
+        service
+            .borrow()
+            .range((dao_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| *id == dao_id)
+            .map(|((_, user), membership)| (Principal::from_slice(user.as_slice()), membership))
+            .collect()
+    })
+}
+
+// a helper method to remove every membership of a DAO, along with its owner and members in the
+// member index
+fn _clear_memberships(dao: &Dao) {
+    _dao_participants(dao).iter().for_each(|user| {
+        MEMBER_DAOS.with(|service| service.borrow_mut().remove(&(principal_key(user), dao.id)));
+    });
+    _memberships(dao.id).iter().for_each(|(user, _)| {
+        DAO_MEMBERSHIPS.with(|service| service.borrow_mut().remove(&(dao.id, principal_key(user))));
+    });
+}
+
+// a helper method to list the proposals of a DAO in the order of their ids, which is the order
+// they were created in apart from those moved in by a merge
+fn _dao_proposal_ids(dao_id: &u64) -> Vec<u64> {
+    DAO_PROPOSALS.with(|service| {
+        service
+            .borrow()
+            .range((*dao_id, 0)..)
+            .take_while(|((id, _), _)| id == dao_id)
+            .map(|((_, proposal_id), _)| proposal_id)
+            .collect()
+    })
+}
+
+// a helper method to add a proposal to the ones of a DAO, keeping its proposal count up to date.
+// The DAO still has to be saved with do_insert_dao
+fn _list_dao_proposal(dao: &mut Dao, proposal_id: u64) {
+    let is_new = DAO_PROPOSALS.with(|service| {
+        service
+            .borrow_mut()
+            .insert((dao.id, proposal_id), ())
+            .is_none()
+    });
+    if is_new {
+        dao.proposal_count += 1;
+    }
+}
+
+// a helper method to take a proposal out of the ones of a DAO. The DAO still has to be saved
+fn _unlist_dao_proposal(dao: &mut Dao, proposal_id: u64) {
+    let was_listed = DAO_PROPOSALS.with(|service| {
+        service
+            .borrow_mut()
+            .remove(&(dao.id, proposal_id))
+            .is_some()
+    });
+    if was_listed {
+        dao.proposal_count = dao.proposal_count.saturating_sub(1);
+    }
+}
+
+// a helper method to get the ids of the comments on a proposal, oldest first
+fn _comment_ids(proposal_id: &u64) -> Vec<u64> {
+    PROPOSAL_COMMENTS.with(|service| {
+        service
+            .borrow()
+            .range((*proposal_id, 0)..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .map(|((_, comment_id), _)| comment_id)
+            .collect()
+    })
+}
+
+// a helper method to add a comment to the ones of a proposal, keeping its comment count up to date.
+// The proposal still has to be saved with do_insert_proposal
+fn _list_comment(proposal: &mut Proposal, comment_id: u64) {
+    let is_new = PROPOSAL_COMMENTS.with(|service| {
+        service
+            .borrow_mut()
+            .insert((proposal.id, comment_id), ())
+            .is_none()
+    });
+    if is_new {
+        proposal.comment_count += 1;
+    }
+}
+
+// a helper method to take a comment out of the ones of a proposal. The proposal still has to be
+// saved
+fn _unlist_comment(proposal: &mut Proposal, comment_id: u64) {
+    let was_listed = PROPOSAL_COMMENTS.with(|service| {
+        service
+            .borrow_mut()
+            .remove(&(proposal.id, comment_id))
+            .is_some()
+    });
+    if was_listed {
+        proposal.comment_count = proposal.comment_count.saturating_sub(1);
+    }
+}
+
+// Check if a user is the owner or a member of a DAO, see service::is_member
+fn _is_member(dao: &Dao, user: &Principal) -> bool {
+    service::is_member(dao, user, _membership(dao.id, user))
+}
+
+fn _is_banned(dao_id: u64, user: &Principal) -> bool {
+    _membership(dao_id, user) == Some(Membership::Banned)
+}
+
+// a helper method to get when a member joined a DAO. The owner joined when they created it
+fn _joined_at(dao: &Dao, member: &Principal) -> Option<u64> {
+    MEMBER_JOINED_AT
+        .with(|service| service.borrow().get(&(dao.id, principal_key(member))))
+        .or(if dao.owner == Some(*member) {
+            Some(dao.created_at)
+        } else {
+            None
+        })
+}
+
+// a helper method to get what a member has done in a DAO
+fn _get_member_activity(dao_id: &u64, member: &Principal) -> MemberActivity {
+    MEMBER_ACTIVITY
+        .with(|service| service.borrow().get(&(*dao_id, principal_key(member))))
+        .unwrap_or_default()
+}
+
+// a helper method to count a proposal, vote or comment towards what a member has done in a DAO
+fn _count_activity(dao_id: u64, member: &Principal, kind: ActivityKind) {
+    let mut activity = _get_member_activity(&dao_id, member);
+    match kind {
+        ActivityKind::Proposal => activity.proposals_created += 1,
+        ActivityKind::Vote => activity.votes_cast += 1,
+        ActivityKind::Comment => activity.comments_made += 1,
+    }
+    MEMBER_ACTIVITY.with(|service| {
+        service
+            .borrow_mut()
+            .insert((dao_id, principal_key(member)), activity)
+    });
+}
+
+// a helper method to remove the activity counters of a DAO
+fn _clear_member_activity(dao_id: &u64) {
+    MEMBER_ACTIVITY.with(|service| {
+        let keys: Vec<(u64, PrincipalKey)> = service
+            .borrow()
+            .range((*dao_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| id == dao_id)
+            .map(|(key, _)| key)
+            .collect();
+        keys.iter().for_each(|key| {
+            service.borrow_mut().remove(key);
+        });
+    });
+}
+
+// helper method to remember when a member joined a DAO
+fn record_joined_at(dao_id: u64, member: &Principal) {
+    MEMBER_JOINED_AT.with(|service| {
+        service
+            .borrow_mut()
+            .insert((dao_id, principal_key(member)), time())
+    });
+}
+
+// helper method to remove a proposal along with its comments, votes and reports
+fn _delete_proposal_records(proposal: &Proposal) {
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().remove(&proposal.id));
+    certification::uncertify(proposal.id);
+
+    if let Some(mut dao) = DAO_STORAGE.with(|service| service.borrow().get(&proposal.dao_id)) {
+        _unlist_dao_proposal(&mut dao, proposal.id);
+
+        do_insert_dao(&mut dao);
+    } else {
+        // the DAO is being deleted along with its proposals
+        DAO_PROPOSALS.with(|service| service.borrow_mut().remove(&(proposal.dao_id, proposal.id)));
+    }
+
+    _comment_ids(&proposal.id).iter().for_each(|comment_id| {
+        PROPOSAL_COMMENTS.with(|service| service.borrow_mut().remove(&(proposal.id, *comment_id)));
+        let removed = COMMENT_STORAGE.with(|service| service.borrow_mut().remove(comment_id));
+        if let Some(author) = removed.and_then(|comment| comment.author) {
+            THREAD_PARTICIPATION.with(|service| {
+                service
+                    .borrow_mut()
+                    .remove(&(principal_key(&author), proposal.id))
+            });
+        }
+        _clear_likes(comment_id);
+        _clear_reports(comment_id);
+    });
+    _remove_proposal_votes(&proposal.id);
+    _clear_amendments(&proposal.id);
+    _clear_followers(&proposal.id);
+    _clear_co_authors(&proposal.id);
+    _clear_participants(&proposal.id);
+    _clear_reports(&proposal.id);
+    _set_attachments(&proposal.id, Vec::new());
+    _set_milestones(&proposal.id, Vec::new());
+    _clear_share_links(&proposal.id, false);
+    VOTE_REMINDERS.with(|service| service.borrow_mut().remove(&proposal.id));
+    _clear_proposal_slugs(proposal);
+    _clear_proposal_number(proposal);
+    _dequeue_proposal(proposal);
+}
+
+// helper method to remove a comment along with its reports
+fn _delete_comment_records(comment: &Comment) {
+    COMMENT_STORAGE.with(|service| service.borrow_mut().remove(&comment.id));
+    _clear_likes(&comment.id);
+
+    if let Some(mut proposal) =
+        PROPOSAL_STORAGE.with(|service| service.borrow().get(&comment.proposal_id))
+    {
+        _unlist_comment(&mut proposal, comment.id);
+        proposal.stance_counts.apply(comment.stance, None);
+
+        do_insert_proposal(&mut proposal);
+    } else {
+        PROPOSAL_COMMENTS.with(|service| {
+            service
+                .borrow_mut()
+                .remove(&(comment.proposal_id, comment.id))
+        });
+    }
+    _clear_reports(&comment.id);
+}
+
+// a helper method to set the stance of a comment, moving it between the counters of its proposal
+fn _change_stance(comment: &mut Comment, stance: Option<Stance>) {
+    if comment.stance == stance {
+        return;
+    }
+    if let Some(mut proposal) = _get_proposal(&comment.proposal_id) {
+        proposal.stance_counts.apply(comment.stance, stance);
+        do_insert_proposal(&mut proposal);
+    }
+    comment.stance = stance;
+}
+
+// a helper method to get a proposal by id. used in get_proposal/update_proposal
+fn _get_proposal(id: &u64) -> Option<Proposal> {
+    PROPOSAL_STORAGE.with(|service| service.borrow().get(id))
+}
+
+fn _get_dao(id: &u64) -> Option<Dao> {
+    DAO_STORAGE.with(|service| service.borrow().get(id))
+}
+
+// a helper method to get the settings of a DAO, the defaults for DAOs without stored settings
+fn _get_settings(dao_id: &u64) -> DaoSettings {
+    DAO_SETTINGS
+        .with(|service| service.borrow().get(dao_id))
+        .unwrap_or_else(|| DaoSettings {
+            dao_id: *dao_id,
+            ..Default::default()
+        })
+}
+
+// a helper method to store the settings of a DAO, marking the DAO as updated. The registry is
+// told about DAOs that are made public or private
+fn _save_settings(dao: &mut Dao, mut settings: DaoSettings) {
+    let previous_visibility = _get_settings(&dao.id).visibility;
+    // private DAOs aren't announced, so the registry hears about it while the DAO is still public
+    if previous_visibility == DaoVisibility::Public && settings.visibility == DaoVisibility::Private
+    {
+        registry::announce(dao, registry::RegistryEvent::Delisted);
+    }
+    settings.dao_id = dao.id;
+    settings.updated_at = Some(time());
+    dao.updated_at = Some(time());
+
+    do_insert_dao(dao);
+    let previous =
+        DAO_SETTINGS.with(|service| service.borrow_mut().insert(dao.id, settings.clone()));
+    // the digests of open proposals leave their counts out only while the tally is hidden
+    let was_hidden = previous.and_then(|previous| previous.hide_tally_until_closed);
+    if was_hidden.unwrap_or_default() != settings.hide_tally_until_closed.unwrap_or_default() {
+        for proposal in _dao_proposal_ids(&dao.id).iter().filter_map(_get_proposal) {
+            certification::certify(&proposal);
+        }
+    }
+    if previous_visibility == DaoVisibility::Private && settings.visibility == DaoVisibility::Public
+    {
+        registry::announce(dao, registry::RegistryEvent::Listed);
+    }
+}
+
+// a helper method to apply a patch to settings, checking the range of every field it sets
+fn _patch_settings(
+    mut settings: DaoSettings,
+    patch: DaoSettingsPatch,
+) -> Result<DaoSettings, Error> {
+    let context = ErrorContext::Dao(settings.dao_id);
+    if let Some(seconds) = patch.voting_period_seconds {
+        if !(MIN_VOTING_PERIOD_SECONDS..=MAX_VOTING_PERIOD_SECONDS).contains(&seconds) {
+            return Err(Error::invalid_input(
+                context,
+                format!(
+                    "voting period must be between {} and {} seconds, got {}",
+                    MIN_VOTING_PERIOD_SECONDS, MAX_VOTING_PERIOD_SECONDS, seconds
+                ),
+            ));
+        }
+        settings.voting_period_seconds = Some(seconds);
+    }
+    if let Some(quorum_percent) = patch.quorum_percent {
+        if quorum_percent > 100 {
+            return Err(Error::invalid_input(
+                context,
+                format!(
+                    "quorum must be between 0 and 100 percent, got {}",
+                    quorum_percent
+                ),
+            ));
+        }
+        settings.quorum_percent = quorum_percent;
+    }
+    if let Some(approval_threshold_percent) = patch.approval_threshold_percent {
+        if !(1..=100).contains(&approval_threshold_percent) {
+            return Err(Error::invalid_input(
+                context,
+                format!(
+                    "approval threshold must be between 1 and 100 percent, got {}",
+                    approval_threshold_percent
+                ),
+            ));
+        }
+        settings.approval_threshold_percent = approval_threshold_percent;
+    }
+    if let Some(visibility) = patch.visibility {
+        settings.visibility = visibility;
+    }
+    if let Some(timelock_seconds) = patch.timelock_seconds {
+        if timelock_seconds > MAX_TIMELOCK_SECONDS {
+            return Err(Error::invalid_input(
+                context,
+                format!(
+                    "timelock can be at most {} seconds, got {}",
+                    MAX_TIMELOCK_SECONDS, timelock_seconds
+                ),
+            ));
+        }
+        settings.timelock_seconds = timelock_seconds;
+    }
+    settings.dues_amount = patch.dues_amount.unwrap_or(settings.dues_amount);
+    settings.dues_period_seconds = patch
+        .dues_period_seconds
+        .unwrap_or(settings.dues_period_seconds);
+    if settings.dues_amount > 0 && settings.dues_period_seconds == 0 {
+        return Err(Error::invalid_input(
+            context,
+            "dues period must be at least 1 second".to_string(),
+        ));
+    }
+    let limits = [
+        ("proposals", patch.max_proposals_per_window),
+        ("comments", patch.max_comments_per_window),
+    ];
+    for (name, limit) in limits {
+        if let Some(limit) = limit {
+            if !(1..=MAX_ACTIONS_PER_WINDOW).contains(&limit) {
+                return Err(Error::invalid_input(
+                    context,
+                    format!(
+                        "{} per window must be between 1 and {}, got {}",
+                        name, MAX_ACTIONS_PER_WINDOW, limit
+                    ),
+                ));
+            }
+        }
+    }
+    settings.max_proposals_per_window = patch
+        .max_proposals_per_window
+        .or(settings.max_proposals_per_window);
+    settings.max_comments_per_window = patch
+        .max_comments_per_window
+        .or(settings.max_comments_per_window);
+    if let Some(seconds) = patch.reveal_window_seconds {
+        if !(1..=MAX_REVEAL_WINDOW_SECONDS).contains(&seconds) {
+            return Err(Error::invalid_input(
+                context,
+                format!(
+                    "reveal window must be between 1 and {} seconds, got {}",
+                    MAX_REVEAL_WINDOW_SECONDS, seconds
+                ),
+            ));
+        }
+        settings.reveal_window_seconds = Some(seconds);
+    }
+    settings.proposal_deposit = patch.proposal_deposit.or(settings.proposal_deposit);
+    if let Some(rule) = patch.early_close {
+        if rule.min_votes > 0 && !(51..=100).contains(&rule.reject_ratio_percent) {
+            return Err(Error::invalid_input(
+                context,
+                format!(
+                    "early close reject ratio must be between 51 and 100 percent, got {}",
+                    rule.reject_ratio_percent
+                ),
+            ));
+        }
+        settings.early_close = Some(rule).filter(|rule| rule.min_votes > 0);
+    }
+    if let Some(limit) = patch.max_open_proposals {
+        settings.max_open_proposals = Some(limit).filter(|limit| *limit > 0);
+    }
+    settings.hide_tally_until_closed = patch
+        .hide_tally_until_closed
+        .or(settings.hide_tally_until_closed);
+    settings.hide_tally_from_admins = patch
+        .hide_tally_from_admins
+        .or(settings.hide_tally_from_admins);
+    Ok(settings)
+}
+
+// a helper method to turn a page of comments into what comment queries return. Profiles of the
+// authors and the replies of each proposal are looked up once per page, not once per comment
+fn _comment_views(comments: Vec<Comment>) -> Vec<CommentView> {
+    let mut display_names: BTreeMap<Principal, Option<String>> = BTreeMap::new();
+    let mut reply_counts: BTreeMap<u64, BTreeMap<u64, u64>> = BTreeMap::new();
+    for comment in comments.iter() {
+        if let Some(author) = comment.author {
+            display_names.entry(author).or_insert_with(|| {
+                _get_profile(&author)
+                    .map(|profile| profile.display_name)
+                    .filter(|name| !name.is_empty())
+            });
+        }
+        reply_counts
+            .entry(comment.proposal_id)
+            .or_insert_with(|| _reply_counts(&comment.proposal_id));
+    }
+
+    comments
+        .into_iter()
+        .map(|comment| CommentView {
+            author_display_name: comment
+                .author
+                .and_then(|author| display_names.get(&author).cloned().flatten()),
+            edited: comment.updated_at.is_some() && !comment.deleted && comment.removal.is_none(),
+            liked_by_caller: _has_liked(&comment.id, &caller()),
+            reply_count: reply_counts
+                .get(&comment.proposal_id)
+                .and_then(|counts| counts.get(&comment.id))
+                .copied()
+                .unwrap_or_default(),
+            id: comment.id,
+            proposal_id: comment.proposal_id,
+            content: comment.content,
+            author: comment.author,
+            created_at: comment.created_at,
+            updated_at: comment.updated_at,
+            like_count: comment.like_count,
+            reply_to: comment.reply_to,
+            mentions: comment.mentions,
+            hidden: comment.hidden,
+            deleted: comment.deleted,
+            removal: comment.removal,
+            stance: comment.stance,
+            link_previews: comment.link_previews,
+        })
+        .collect()
+}
+
+// a helper method to count the replies to each comment of a proposal that aren't hidden, by the
+// id of the comment they reply to
+fn _reply_counts(proposal_id: &u64) -> BTreeMap<u64, u64> {
+    let mut counts: BTreeMap<u64, u64> = BTreeMap::new();
+    let comment_ids = _comment_ids(proposal_id);
+    for reply in comment_ids.iter().filter_map(_get_comment) {
+        if let Some(parent_id) = reply.reply_to.filter(|_| !reply.hidden) {
+            *counts.entry(parent_id).or_default() += 1;
+        }
+    }
+    counts
+}
+
+fn _get_comment(id: &u64) -> Option<Comment> {
+    COMMENT_STORAGE.with(|service| service.borrow().get(id))
+}
+
+// a helper method to find the DAO a comment belongs to through its proposal. None when the
+// proposal is gone or the caller can't see it
+fn _get_comment_dao_id(comment: &Comment) -> Option<u64> {
+    _get_proposal(&comment.proposal_id)
+        .filter(|proposal| _can_see_proposal(proposal, &caller()))
+        .map(|proposal| proposal.dao_id)
+}
+
+fn _get_vote(proposal_id: &u64, voter: &Principal) -> Option<Vote> {
+    VOTE_STORAGE.with(|service| service.borrow().get(&(*proposal_id, principal_key(voter))))
+}
+
+// a helper method to get the upvotes and downvotes cast on a proposal, oldest voter key first.
+// Ranked-choice ballots are left out
+fn _votes(proposal_id: &u64) -> Vec<Vote> {
+    VOTE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((*proposal_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .map(|(_, vote)| vote)
+            .filter(|vote| vote.ranking.is_none())
+            .collect()
+    })
+}
+
+// a helper method to get who voted `direction` on a proposal, in the order they voted
+fn _voters(proposal_id: &u64, direction: VoteDirection) -> Vec<Principal> {
+    let mut votes: Vec<Vote> = _votes(proposal_id)
+        .into_iter()
+        .filter(|vote| vote.direction == direction)
+        .collect();
+    votes.sort_by_key(|vote| vote.voted_at);
+    votes.into_iter().filter_map(|vote| vote.voter).collect()
+}
+
+// a helper method to add up the votes on a proposal. When `weighted` each vote counts as the
+// weight it was cast with, otherwise as 1
+fn _vote_totals(proposal: &Proposal, weighted: bool) -> (u64, u64) {
+    if !weighted {
+        return (proposal.upvote_count, proposal.downvote_count);
+    }
+    _votes(&proposal.id)
+        .iter()
+        .fold((0, 0), |(up, down), vote| {
+            let weight = vote.weight.unwrap_or(1) as u64;
+            match vote.direction {
+                VoteDirection::Up => (up + weight, down),
+                VoteDirection::Down => (up, down + weight),
+            }
+        })
+}
+
+// a helper method to get every ballot cast on a proposal with the weight it was cast with
+fn _weighted_ballots(proposal: &Proposal) -> Vec<(Principal, VoteDirection, u32)> {
+    _votes(&proposal.id)
+        .into_iter()
+        .filter_map(|vote| {
+            vote.voter
+                .map(|voter| (voter, vote.direction, vote.weight.unwrap_or(1)))
+        })
+        .collect()
+}
+
+fn _get_notification(id: &u64) -> Option<Notification> {
+    NOTIFICATION_STORAGE.with(|service| service.borrow().get(id))
+}
+
+fn _get_profile(user: &Principal) -> Option<Profile> {
+    PROFILE_STORAGE.with(|service| service.borrow().get(&principal_key(user)))
+}
+
+fn _get_attachments(proposal_id: &u64) -> Vec<Attachment> {
+    ATTACHMENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((*proposal_id, 0)..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .map(|(_, attachment)| attachment)
+            .collect()
+    })
+}
+
+// a helper method to replace every attachment of a proposal
+fn _set_attachments(proposal_id: &u64, attachments: Vec<Attachment>) {
+    ATTACHMENT_STORAGE.with(|service| {
+        let keys: Vec<(u64, u64)> = service
+            .borrow()
+            .range((*proposal_id, 0)..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .map(|(key, _)| key)
+            .collect();
+        keys.iter().for_each(|key| {
+            service.borrow_mut().remove(key);
+        });
+
+        for (index, attachment) in attachments.into_iter().enumerate() {
+            service
+                .borrow_mut()
+                .insert((*proposal_id, index as u64), attachment);
+        }
+    });
+}
+
+fn _get_milestones(proposal_id: &u64) -> Vec<Milestone> {
+    MILESTONE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((*proposal_id, 0)..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .map(|(_, milestone)| milestone)
+            .collect()
+    })
+}
+
+fn _get_milestone(proposal_id: &u64, index: &u64) -> Result<Milestone, Error> {
+    match MILESTONE_STORAGE.with(|service| service.borrow().get(&(*proposal_id, *index))) {
+        Some(milestone) => Ok(milestone),
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(*proposal_id),
+            format!(
+                "milestone {} of proposal with id={} not found",
+                index, proposal_id
+            ),
+        )),
+    }
+}
+
+// a helper method to replace every milestone of a proposal
+fn _set_milestones(proposal_id: &u64, milestones: Vec<Milestone>) {
+    MILESTONE_STORAGE.with(|service| {
+        let keys: Vec<(u64, u64)> = service
+            .borrow()
+            .range((*proposal_id, 0)..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .map(|(key, _)| key)
+            .collect();
+        keys.iter().for_each(|key| {
+            service.borrow_mut().remove(key);
+        });
+
+        for (index, milestone) in milestones.into_iter().enumerate() {
+            service
+                .borrow_mut()
+                .insert((*proposal_id, index as u64), milestone);
+        }
+    });
+}
+
+// a helper method to get a proposal the caller can manage share links of, as its owner or an
+// admin of its DAO
+fn _get_shareable_proposal(proposal_id: &u64) -> Result<Proposal, Error> {
+    match _get_proposal(proposal_id) {
+        Some(proposal) => {
+            let is_dao_admin = _get_dao(&proposal.dao_id)
+                .map(|dao| _is_dao_admin(&dao, &caller()))
+                .unwrap_or_default();
+            if proposal.owner != Some(caller()) && !is_dao_admin {
+                return Err(Error::permission_error(
+                    ErrorContext::Proposal(*proposal_id),
+                    format!(
+                        "Couldn't manage the share links of proposal with id={}. You are not the owner",
+                        proposal_id
+                    ),
+                ));
+            }
+            _check_dao_not_archived(&proposal.dao_id)?;
+            Ok(proposal)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(*proposal_id),
+            format!("a proposal with id={} not found", proposal_id),
+        )),
+    }
+}
+
+fn _get_share_links(proposal_id: &u64) -> Vec<ShareLink> {
+    SHARE_LINKS.with(|service| {
+        service
+            .borrow()
+            .range((*proposal_id, ShareTokenKey::default())..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .map(|(_, link)| link)
+            .collect()
+    })
+}
+
+// a helper method to remove the share links of a proposal, or only the expired ones
+fn _clear_share_links(proposal_id: &u64, only_expired: bool) {
+    SHARE_LINKS.with(|service| {
+        let keys: Vec<(u64, ShareTokenKey)> = service
+            .borrow()
+            .range((*proposal_id, ShareTokenKey::default())..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .filter(|(_, link)| !only_expired || link.expires_at <= time())
+            .map(|(key, _)| key)
+            .collect();
+        keys.iter().for_each(|key| {
+            service.borrow_mut().remove(key);
+        });
+    });
+}
+
+fn _clear_expired_share_links(proposal_id: &u64) {
+    _clear_share_links(proposal_id, true);
+}
+
+// a helper method to get the storage key of a share link token, the proposal id followed by the
+// random part, both hex encoded
+fn _parse_share_token(token: &str) -> Option<(u64, ShareTokenKey)> {
+    if token.len() != 48 || !token.is_ascii() {
+        return None;
+    }
+    let proposal_id = u64::from_str_radix(&token[..16], 16).ok()?;
+    let random: Vec<u8> = (16..48)
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&token[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    let key = ShareTokenKey::try_from(random.as_slice()).ok()?;
+    Some((proposal_id, key))
+}
+
+// a helper method to get a proposal the caller owns and can still edit
+fn _get_editable_proposal(id: &u64) -> Result<Proposal, Error> {
+    match _get_proposal(id) {
+        Some(proposal) => {
+            if !_is_author(&proposal, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Proposal(*id),
+                    format!(
+                        "Couldn't update proposal with id={}. You are not the owner or a co-author",
+                        id
+                    ),
+                ));
+            }
+            _check_dao_not_archived(&proposal.dao_id)?;
+            if proposal.status == ProposalStatus::Cancelled {
+                return Err(Error::invalid_input(
+                    ErrorContext::Proposal(*id),
+                    format!("Couldn't update proposal with id={}. It was cancelled", id),
+                ));
+            }
+            // drafts, blocked and queued proposals have no deadline yet
+            if !matches!(
+                proposal.status,
+                ProposalStatus::Draft | ProposalStatus::Blocked | ProposalStatus::Queued
+            ) && _is_deadline_passed(proposal.deadline)
+            {
+                return Err(Error::deadline_exceeded(
+                    ErrorContext::Proposal(*id),
+                    format!("Couldn't update proposal with id={}. Deadline exceeded", id),
+                ));
+            }
+            Ok(proposal)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(*id),
+            format!(
+                "couldn't update a proposal with id={}. proposal not found",
+                id
+            ),
+        )),
+    }
+}
+
+// a helper method to work out and validate the kind of a new or updated proposal
+fn _proposal_kind(payload: &ProposalPayload) -> Result<ProposalKind, Error> {
+    let mut kind = payload
+        .kind
+        .clone()
+        .unwrap_or_else(|| ProposalKind::from_amount(payload.amount_requested));
+    match &mut kind {
+        ProposalKind::Funding { amount, recipient } => {
+            if *amount == 0 {
+                return Err(Error::invalid_input(
+                    ErrorContext::Dao(payload.dao_id),
+                    "a funding proposal has to request more than 0".to_string(),
+                ));
+            }
+            if *recipient == Some(Principal::anonymous()) {
+                return Err(Error::invalid_input(
+                    ErrorContext::Dao(payload.dao_id),
+                    "a funding proposal can't pay the anonymous principal".to_string(),
+                ));
+            }
+        }
+        ProposalKind::Signal => {}
+        ProposalKind::MembershipChange { target, action } => {
+            let dao = _get_dao(&payload.dao_id).unwrap_or_default();
+            _check_membership_change(&dao, target, *action)?;
+        }
+        ProposalKind::RankedChoice { options } => {
+            ranked::validate_options(options)
+                .map_err(|msg| Error::invalid_input(ErrorContext::Dao(payload.dao_id), msg))?;
+            if payload.anonymous_ballot.unwrap_or_default() {
+                return Err(Error::invalid_input(
+                    ErrorContext::Dao(payload.dao_id),
+                    "a ranked-choice proposal can't use an anonymous ballot".to_string(),
+                ));
+            }
+            options
+                .iter_mut()
+                .for_each(|option| *option = option.trim().to_string());
+        }
+    }
+    Ok(kind)
+}
+
+// a helper method to check that a membership change still makes sense for a DAO
+fn _check_membership_change(
+    dao: &Dao,
+    target: &Principal,
+    action: MembershipAction,
+) -> Result<(), Error> {
+    let is_member = _is_member(dao, target);
+    match action {
+        MembershipAction::Add if is_member => Err(Error::already_member(
+            ErrorContext::Member(*target),
+            format!("{} is already a member of dao with id={}", target, dao.id),
+        )),
+        MembershipAction::Add if _is_banned(dao.id, target) => Err(Error::banned(
+            ErrorContext::Member(*target),
+            format!("{} is banned from dao with id={}", target, dao.id),
+        )),
+        MembershipAction::Remove if dao.owner == Some(*target) => Err(Error::permission_error(
+            ErrorContext::Member(*target),
+            format!("the owner of dao with id={} can't be removed", dao.id),
+        )),
+        MembershipAction::Remove if !is_member => Err(Error::not_a_member(
+            ErrorContext::Member(*target),
+            format!(
+                "{} is not a member of dao with id={} that can be removed",
+                target, dao.id
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// a helper method to get the pending join requests of a DAO, oldest first
+fn _pending_join_requests(dao_id: u64) -> impl Iterator<Item = JoinRequest> {
+    let ids: Vec<u64> = PENDING_JOIN_REQUESTS.with(|service| {
+        service
+            .borrow()
+            .range((dao_id, 0)..=(dao_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    ids.into_iter()
+        .filter_map(|id| JOIN_REQUEST_STORAGE.with(|service| service.borrow().get(&id)))
+        .filter(|request| request.status == JoinRequestStatus::Pending)
+}
+
+// a helper method to keep the first TODO_ITEMS_PER_CATEGORY of `items` and count all of them
+fn _todo_list(items: impl Iterator<Item = TodoItem>) -> TodoList {
+    let mut list = TodoList::default();
+    items.for_each(|item| {
+        list.total += 1;
+        if list.items.len() < TODO_ITEMS_PER_CATEGORY {
+            list.items.push(item);
+        }
+    });
+    list
+}
+
+// a helper method to get the reports made against a proposal or comment
+fn _get_reports(target_id: &u64) -> Vec<Report> {
+    REPORT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((*target_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| id == target_id)
+            .map(|(_, report)| report)
+            .collect()
+    })
+}
+
+fn _clear_reports(target_id: &u64) {
+    if let Some(report) = _get_reports(target_id).first() {
+        REPORTED_CONTENT.with(|service| service.borrow_mut().remove(&(report.dao_id, *target_id)));
+    }
+    REPORT_STORAGE.with(|service| {
+        let keys: Vec<(u64, PrincipalKey)> = service
+            .borrow()
+            .range((*target_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| id == target_id)
+            .map(|(key, _)| key)
+            .collect();
+        keys.iter().for_each(|key| {
+            service.borrow_mut().remove(key);
+        });
+    });
+}
+
+// a helper method to find the DAO of reported content and how it appears in the audit log
+fn _locate_report_target(target: &ReportTarget) -> Option<(u64, AuditTarget)> {
+    match target {
+        ReportTarget::Proposal(id) => {
+            _get_proposal(id).map(|proposal| (proposal.dao_id, AuditTarget::Proposal(*id)))
+        }
+        ReportTarget::Comment(id) => {
+            let comment = _get_comment(id)?;
+            let proposal = _get_proposal(&comment.proposal_id)?;
+            Some((
+                proposal.dao_id,
+                AuditTarget::Comment {
+                    id: *id,
+                    proposal_id: proposal.id,
+                },
+            ))
+        }
+    }
+}
+
+fn _is_hidden(target: &ReportTarget) -> bool {
+    match target {
+        ReportTarget::Proposal(id) => _get_proposal(id)
+            .map(|proposal| proposal.hidden)
+            .unwrap_or_default(),
+        ReportTarget::Comment(id) => _get_comment(id)
+            .map(|comment| comment.hidden)
+            .unwrap_or_default(),
+    }
+}
+
+fn _set_hidden(target: &ReportTarget, hidden: bool) {
+    match target {
+        ReportTarget::Proposal(id) => {
+            if let Some(mut proposal) = _get_proposal(id) {
+                proposal.hidden = hidden;
+                do_insert_proposal(&mut proposal);
+            }
+        }
+        ReportTarget::Comment(id) => {
+            if let Some(mut comment) = _get_comment(id) {
+                comment.hidden = hidden;
+                do_insert_comment(&comment);
+            }
+        }
+    }
+}
+
+// a helper method to get the ids of a user's notifications, oldest first
+fn _get_notification_ids(recipient: &Principal) -> Vec<u64> {
+    let key = principal_key(recipient);
+    NOTIFICATIONS_BY_RECIPIENT.with(|service| {
+        service
+            .borrow()
+            .range((key, 0)..)
+            .take_while(|((user, _), _)| *user == key)
+            .map(|((_, id), _)| id)
+            .collect()
+    })
+}
+
+// a helper method to store an upvote or downvote and count it on the proposal. Every vote goes
+// through here, a voter who already voted is left alone so no one can be counted twice. The
+// proposal still has to be saved with do_insert_proposal
+fn _record_vote(proposal: &mut Proposal, vote: &Vote) {
+    let Some(voter) = vote.voter else {
+        return;
+    };
+    if _get_vote(&proposal.id, &voter).is_some() {
+        return;
+    }
+    do_insert_vote(vote);
+    match vote.direction {
+        VoteDirection::Up => proposal.upvote_count += 1,
+        VoteDirection::Down => proposal.downvote_count += 1,
+    }
+}
+
+// a helper method to count the votes of a proposal again from its vote records. Returns by how
+// much the counts it had were off
+fn _recount_votes(proposal: &mut Proposal) -> u64 {
+    let (upvotes, downvotes) = _votes(&proposal.id)
+        .iter()
+        .fold((0, 0), |(up, down), vote| match vote.direction {
+            VoteDirection::Up => (up + 1, down),
+            VoteDirection::Down => (up, down + 1),
+        });
+    let off = proposal.upvote_count.abs_diff(upvotes) + proposal.downvote_count.abs_diff(downvotes);
+    proposal.upvote_count = upvotes;
+    proposal.downvote_count = downvotes;
+    off
+}
+
+// a helper method to get how a user voted on a proposal. Ranked-choice ballots have no direction
+fn _get_vote_direction(proposal: &Proposal, voter: &Principal) -> Option<VoteDirection> {
+    _get_vote(&proposal.id, voter)
+        .filter(|vote| vote.ranking.is_none())
+        .map(|vote| vote.direction)
+}
+
+// a helper method to remove every vote cast on a proposal
+fn _remove_proposal_votes(proposal_id: &u64) {
+    VOTE_STORAGE.with(|service| {
+        let keys: Vec<(u64, PrincipalKey)> = service
+            .borrow()
+            .range((*proposal_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .map(|(key, _)| key)
+            .collect();
+        keys.iter().for_each(|key| {
+            service.borrow_mut().remove(key);
+        });
+    });
+    _clear_commitments(proposal_id);
+}
+
+// a helper method to drop the commitments made on an anonymous ballot. Returns who made them
+fn _clear_commitments(proposal_id: &u64) -> Vec<Principal> {
+    BALLOT_COMMITMENTS.with(|service| {
+        let commitments: Vec<((u64, PrincipalKey), BallotCommitment)> = service
+            .borrow()
+            .range((*proposal_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .collect();
+        commitments
+            .into_iter()
+            .map(|(key, commitment)| {
+                service.borrow_mut().remove(&key);
+                commitment.voter
+            })
+            .collect()
+    })
+}
+
+// a helper method to record an amendment to a proposal that was already voted on. Depending on
+// the DAO's policy the existing votes are discarded or set aside until their voter reconfirms
+fn _amend_proposal(proposal: &mut Proposal, previous: &Proposal) {
+    let policy = _get_settings(&proposal.dao_id).amendment_policy;
+    let votes: Vec<Vote> = _votes(&previous.id);
+    // commitments on an anonymous ballot can't be carried over, their voters commit again
+    let committers = _clear_commitments(&proposal.id);
+
+    _remove_proposal_votes(&proposal.id);
+    proposal.upvote_count = 0;
+    proposal.downvote_count = 0;
+    if policy == AmendmentPolicy::ResetVotes {
+        _clear_pending_reconfirmations(&proposal.id);
+    }
+
+    let voters: Vec<Principal> = votes
+        .iter()
+        .filter_map(|vote| vote.voter)
+        .chain(committers)
+        .collect();
+    if policy == AmendmentPolicy::RequireReconfirmation {
+        for vote in votes.iter() {
+            if let Some(voter) = vote.voter {
+                PENDING_RECONFIRMATIONS.with(|service| {
+                    service
+                        .borrow_mut()
+                        .insert((proposal.id, principal_key(&voter)), vote.clone())
+                });
+            }
+        }
+    }
+    _notify_followers(
+        proposal,
+        NotificationKind::ProposalAmended,
+        None,
+        &voters,
+        &[],
+    );
+
+    let position = _get_amendments(&proposal.id).len() as u64;
+    let amendment = Amendment {
+        proposal_id: proposal.id,
+        amended_by: Some(caller()),
+        amended_at: time(),
+        previous_title: previous.title.clone(),
+        title: proposal.title.clone(),
+        previous_details: previous.details.clone(),
+        details: proposal.details.clone(),
+        previous_amount_requested: previous.kind.amount(),
+        amount_requested: proposal.kind.amount(),
+        policy,
+        affected_votes: votes.len() as u64,
+    };
+    AMENDMENT_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert((proposal.id, position), amendment)
+    });
+    log_event(
+        proposal.dao_id,
+        AuditAction::ProposalAmended,
+        AuditTarget::Proposal(proposal.id),
+    );
+}
+
+// a helper method to get the amendments of a proposal, oldest first
+fn _get_amendments(proposal_id: &u64) -> Vec<Amendment> {
+    AMENDMENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((*proposal_id, 0)..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .map(|(_, amendment)| amendment)
+            .collect()
+    })
+}
+
+// a helper method to remove the amendments of a proposal and the votes waiting on them
+fn _clear_amendments(proposal_id: &u64) {
+    AMENDMENT_STORAGE.with(|service| {
+        let keys: Vec<(u64, u64)> = service
+            .borrow()
+            .range((*proposal_id, 0)..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .map(|(key, _)| key)
+            .collect();
+        keys.iter().for_each(|key| {
+            service.borrow_mut().remove(key);
+        });
+    });
+    _clear_pending_reconfirmations(proposal_id);
+}
+
+// a helper method to drop the votes on a proposal that are waiting to be reconfirmed
+fn _clear_pending_reconfirmations(proposal_id: &u64) {
+    PENDING_RECONFIRMATIONS.with(|service| {
+        let keys: Vec<(u64, PrincipalKey)> = service
+            .borrow()
+            .range((*proposal_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .map(|(key, _)| key)
+            .collect();
+        keys.iter().for_each(|key| {
+            service.borrow_mut().remove(key);
+        });
+    });
+}
+
+// a helper method to get the proposals of every DAO the caller is part of
+fn _get_caller_dao_proposals() -> Vec<Proposal> {
+    _get_member_dao_ids(&caller())
+        .iter()
+        .filter_map(_get_dao)
+        .flat_map(|dao| _dao_proposal_ids(&dao.id))
+        .collect::<Vec<u64>>()
+        .iter()
+        .filter_map(_get_proposal)
+        .collect()
+}
+
+// a helper method to get the proposals the caller can see in the DAOs they are part of, along
+// with the name of their DAO
+fn _get_feed_proposals() -> Vec<(String, Proposal)> {
+    _get_member_dao_ids(&caller())
+        .iter()
+        .filter_map(_get_dao)
+        .flat_map(|dao| {
+            let can_see_hidden = _is_dao_admin(&dao, &caller());
+            _dao_proposal_ids(&dao.id)
+                .iter()
+                .filter_map(_get_proposal)
+                .filter(|proposal| !proposal.hidden || can_see_hidden)
+                .filter(|proposal| proposal.status != ProposalStatus::Cancelled)
+                .filter(|proposal| _can_see_proposal(proposal, &caller()))
+                .map(|proposal| (dao.name.clone(), proposal))
+                .collect::<Vec<(String, Proposal)>>()
+        })
+        .collect()
+}
+
+// a helper method to count the votes and comments on a proposal since a point in time
+fn _count_recent_activity(proposal: &Proposal, since: u64) -> u64 {
+    // nothing happened since, no need to look at the votes and comments
+    if proposal.last_activity_at < since {
+        return 0;
+    }
+    let votes = VOTE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((proposal.id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| *id == proposal.id)
+            .filter(|(_, vote)| vote.voted_at >= since)
+            .count()
+    });
+    let comments = _comment_ids(&proposal.id)
+        .iter()
+        .filter_map(_get_comment)
+        .filter(|comment| comment.created_at >= since)
+        .count();
+
+    (votes + comments) as u64
+}
+
+// a helper method to get the ids of the DAOs a user owns or is a member of
+fn _get_member_dao_ids(user: &Principal) -> Vec<u64> {
+    let key = principal_key(user);
+    MEMBER_DAOS.with(|service| {
+        service
+            .borrow()
+            .range((key, 0)..)
+            .take_while(|((member, _), _)| *member == key)
+            .map(|((_, dao_id), _)| dao_id)
+            .collect()
+    })
+}
+
+// a helper method to count the DAOs a user owns
+fn _count_owned_daos(user: &Principal) -> u64 {
+    _get_member_dao_ids(user)
+        .iter()
+        .filter_map(_get_dao)
+        .filter(|dao| dao.owner == Some(*user))
+        .count() as u64
+}
+
+// a helper method to get the owner and members of a DAO
+fn _dao_participants(dao: &Dao) -> Vec<Principal> {
+    let members = _memberships(dao.id)
+        .into_iter()
+        .filter(|(user, membership)| membership.is_member() && dao.owner != Some(*user))
+        .map(|(user, _)| user);
+    dao.owner.into_iter().chain(members).collect()
+}
+
+// a helper method to add the owner and members of a DAO to the member index
+fn _index_dao_members(dao: &Dao) {
+    _dao_participants(dao).iter().for_each(|user| {
+        MEMBER_DAOS.with(|service| {
+            service
+                .borrow_mut()
+                .insert((principal_key(user), dao.id), ())
+        });
+    });
+}
+
+// a helper method to check that the name and description of a DAO fit in its record
+fn _validate_dao_payload(payload: &DaoPayload) -> Result<(), Error> {
+    if payload.name.chars().count() > MAX_DAO_NAME_LEN {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            format!(
+                "dao name can't be longer than {} characters",
+                MAX_DAO_NAME_LEN
+            ),
+        ));
+    }
+    if payload.description.chars().count() > MAX_DAO_DESCRIPTION_LEN {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            format!(
+                "dao description can't be longer than {} characters",
+                MAX_DAO_DESCRIPTION_LEN
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// a helper method to check that the title and details of a proposal fit in its record
+fn _validate_proposal_text(payload: &ProposalPayload) -> Result<(), Error> {
+    if payload.title.chars().count() > MAX_PROPOSAL_TITLE_LEN {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(payload.dao_id),
+            format!(
+                "proposal title can't be longer than {} characters",
+                MAX_PROPOSAL_TITLE_LEN
+            ),
+        ));
+    }
+    if payload.details.chars().count() > MAX_PROPOSAL_DETAILS_LEN {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(payload.dao_id),
+            format!(
+                "proposal details can't be longer than {} characters",
+                MAX_PROPOSAL_DETAILS_LEN
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// a helper method to check that the content of a comment fits in its record
+fn _validate_comment_text(content: &str, context: ErrorContext) -> Result<(), Error> {
+    if content.chars().count() > MAX_COMMENT_LEN {
+        return Err(Error::invalid_input(
+            context,
+            format!(
+                "comment can't be longer than {} characters",
+                MAX_COMMENT_LEN
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// a helper method to normalize a search query, checking that it is neither too short nor too long
+fn _search_query(query: &str) -> Result<String, Error> {
+    let query = query.trim().to_lowercase();
+    let query_length = query.chars().count();
+    if !(MIN_SEARCH_QUERY_LEN..=MAX_SEARCH_QUERY_LEN).contains(&query_length) {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            format!(
+                "search query must be between {} and {} characters",
+                MIN_SEARCH_QUERY_LEN, MAX_SEARCH_QUERY_LEN
+            ),
+        ));
+    }
+    Ok(query)
+}
+
+// a helper method to lock the treasury payments of a proposal while one of them waits for the
+// ledger. The lock is released when the returned guard is dropped
+fn _lock_execution(
+    proposal_id: u64,
+    operation: locks::LockedOperation,
+) -> Result<locks::LockGuard, Error> {
+    locks::acquire(proposal_id, operation, &caller()).map_err(|lock| {
+        Error::execution_in_progress(
+            ErrorContext::Proposal(proposal_id),
+            format!(
+                "a payment of proposal with id={} started at {} is still waiting for the ledger",
+                proposal_id,
+                lock.locked_at()
+            ),
+        )
+    })
+}
+
+// a helper method to refuse a write that adds records once stable memory reached its soft limit
+fn _check_storage_available(context: ErrorContext) -> Result<(), Error> {
+    if storage::is_full() {
+        return Err(Error::storage_full(
+            context,
+            format!(
+                "the canister reached its storage limit of {} bytes, nothing new can be stored for now",
+                storage::soft_limit_bytes()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// a helper method to check if a proposal deadline has passed
+fn _is_deadline_passed(deadline: u64) -> bool {
+    service::is_deadline_passed(deadline, time())
+}
+
+// Check if `voter` is eligible to vote at `now`. Outsiders of a private DAO learn nothing about its
+// proposals, they get the same not found as for a missing proposal
+fn _check_if_can_vote(
+    proposal: &Proposal,
+    id: &u64,
+    voter: &Principal,
+    now: u64,
+) -> Result<(), Error> {
+    _check_readable_proposal(proposal, voter)?;
+    match _get_dao(id) {
+        Some(dao) => {
+            _check_not_archived(&dao)?;
+            service::check_if_can_vote(
+                proposal,
+                &dao,
+                _membership(dao.id, voter),
+                &_get_co_authors(&proposal.id),
+                _get_vote(&proposal.id, voter).is_some(),
+                voter,
+                now,
+            )?;
+            _check_dues_paid(&dao, voter, now)?;
+            _check_constitution_accepted(&dao.id, voter)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(*id),
+            format!("Dao of id={} not found.", id),
+        )),
+    }
+}
+
+// a helper method to check that `voter` can upvote or downvote a proposal that was already read
+// at `now`. Also behind ProposalView::is_votable_by_caller, so the view never disagrees with a vote
+fn _check_can_vote_on(proposal: &Proposal, voter: &Principal, now: u64) -> Result<(), Error> {
+    _check_readable_proposal(proposal, voter)?;
+    if let ProposalKind::RankedChoice { .. } = proposal.kind {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "couldn't vote on a proposal with id={}. It is a ranked-choice proposal, rank its options with vote_option",
+                proposal.id
+            ),
+        ));
+    }
+    if proposal.anonymous_ballot {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "couldn't vote on a proposal with id={}. It uses an anonymous ballot, commit your vote with commit_vote",
+                proposal.id
+            ),
+        ));
+    }
+    _check_if_can_vote(proposal, &proposal.dao_id, voter, now)
+}
+
+// a helper method to check that the caller can rank the options of a proposal with vote_option.
+// Returns how many options it has
+fn _check_can_rank(proposal: &Proposal) -> Result<usize, Error> {
+    _check_readable_proposal(proposal, &caller())?;
+    let option_count = match &proposal.kind {
+        ProposalKind::RankedChoice { options } => options.len(),
+        _ => {
+            return Err(Error::invalid_input(
+                ErrorContext::Proposal(proposal.id),
+                format!(
+                    "couldn't rank the options of proposal with id={}. It isn't a ranked-choice proposal, vote on it with upvote or downvote",
+                    proposal.id
+                ),
+            ))
+        }
+    };
+    _check_if_can_vote(proposal, &proposal.dao_id, &caller(), time())?;
+    if _get_vote(&proposal.id, &caller()).is_some() {
+        return Err(Error::has_voted(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't vote on a proposal with id={}. user voted already",
+                proposal.id
+            ),
+        ));
+    }
+    Ok(option_count)
+}
+
+// a helper method to get the ballots cast on a ranked-choice proposal along with what each of
+// them weighs. When not `weighted` every ballot weighs 1
+fn _ranked_ballots(proposal_id: &u64, weighted: bool) -> Vec<(Vec<u8>, u64)> {
+    VOTE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((*proposal_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .filter_map(|(_, vote)| {
+                let weight = if weighted {
+                    vote.weight.unwrap_or(1) as u64
+                } else {
+                    1
+                };
+                vote.ranking.map(|ranking| (ranking, weight))
+            })
+            .collect()
+    })
+}
+
+// a helper method to reject a proposal right away once its downvotes reach the DAO's early close
+// rule at `now`, see DaoSettings::early_close. Returns the proposal as it is afterwards
+fn _close_if_clearly_failing(proposal: Proposal, now: u64) -> Proposal {
+    let rule = match _get_settings(&proposal.dao_id).early_close {
+        Some(rule) => rule,
+        None => return proposal,
+    };
+    let weighted = _get_settings(&proposal.dao_id).role_vote_weights.is_some();
+    let (upvotes, downvotes) = _vote_totals(&proposal, weighted);
+    let voter_count = proposal.upvote_count + proposal.downvote_count;
+    if !service::is_clearly_failing(voter_count, upvotes, downvotes, &rule) {
+        return proposal;
+    }
+
+    let closure = EarlyClosure {
+        rule,
+        upvotes,
+        downvotes,
+        original_deadline: proposal.deadline,
+    };
+    _finalize_vote(proposal, Some(closure), now)
+}
+
+// a helper method to reject writes based on a stale read. Callers that don't pass the version
+// they read keep last-write-wins behaviour
+fn _check_version(
+    context: ErrorContext,
+    current_version: u64,
+    expected_version: Option<u64>,
+) -> Result<(), Error> {
+    match expected_version {
+        Some(expected) if expected != current_version => {
+            Err(Error::version_conflict(context, current_version))
+        }
+        _ => Ok(()),
+    }
+}
+
+// a helper method to approve or reject a pending join request
+fn _resolve_join_request(request_id: u64, status: JoinRequestStatus) -> Result<JoinRequest, Error> {
+    match JOIN_REQUEST_STORAGE.with(|service| service.borrow().get(&request_id)) {
+        Some(mut request) => {
+            let mut dao = match _get_dao(&request.dao_id) {
+                Some(dao) => dao,
+                None => {
+                    return Err(Error::not_found(
+                        ErrorContext::Dao(request.dao_id),
+                        format!("a dao with id={} not found", request.dao_id),
+                    ))
+                }
+            };
+            if !_is_dao_admin(&dao, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::JoinRequest(request_id),
+                    format!(
+                        "Couldn't resolve join request with id={}. You are not an admin",
+                        request_id
+                    ),
+                ));
+            }
+            _check_not_archived(&dao)?;
+            if request.status != JoinRequestStatus::Pending {
+                return Err(Error::invalid_input(
+                    ErrorContext::JoinRequest(request_id),
+                    format!("join request with id={} is not pending", request_id),
+                ));
+            }
+
+            request.status = status;
+            if status == JoinRequestStatus::Approved {
+                if let Some(requester) = request.requester {
+                    if _is_banned(dao.id, &requester) {
+                        return Err(Error::banned(
+                            ErrorContext::Member(requester),
+                            format!("{} is banned from dao with id={}", requester, dao.id),
+                        ));
+                    }
+                    if !_is_member(&dao, &requester) {
+                        _admit_member(&mut dao, &requester);
+
+                        do_insert_dao(&mut dao);
+                    }
+                }
+            }
+
+            do_insert_join_request(&request);
+            let action = match status {
+                JoinRequestStatus::Approved => AuditAction::JoinRequestApproved,
+                _ => AuditAction::JoinRequestRejected,
+            };
+            log_event(dao.id, action, AuditTarget::JoinRequest(request_id));
+            Ok(request)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::JoinRequest(request_id),
+            format!("a join request with id={} not found", request_id),
+        )),
+    }
+}
+
+// a helper method to extract the members of a DAO mentioned as `@<principal>` in a text.
+// Malformed principals and non-members are ignored
+fn _parse_mentions(content: &str, dao_id: &u64) -> Vec<Principal> {
+    let dao = match _get_dao(dao_id) {
+        Some(dao) => dao,
+        None => return Vec::new(),
+    };
+
+    let mut mentions: Vec<Principal> = Vec::new();
+    for word in content.split_whitespace() {
+        let text: String = match word.strip_prefix('@') {
+            Some(text) => text
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+                .collect(),
+            None => continue,
+        };
+        let principal = match Principal::from_text(text) {
+            Ok(principal) => principal,
+            Err(_) => continue,
+        };
+
+        if _is_member(&dao, &principal) && !mentions.contains(&principal) {
+            mentions.push(principal);
+        }
+        if mentions.len() == MAX_MENTIONS_PER_COMMENT {
+            break;
+        }
+    }
+
+    mentions
+}
+
+// a helper method to notify members newly mentioned in a comment, except its author
+fn _notify_mentions(comment: &Comment, previous_mentions: &[Principal], dao_id: &u64) {
+    comment
+        .mentions
+        .iter()
+        .filter(|mentioned| !previous_mentions.contains(mentioned))
+        .filter(|mentioned| comment.author != Some(**mentioned))
+        .for_each(|mentioned| {
+            notify(
+                mentioned,
+                NotificationKind::Mention,
+                *dao_id,
+                Some(comment.proposal_id),
+                Some(comment.id),
+            )
+        });
+}
+
+// a helper method to notify the owner and followers of a proposal, along with `also`, about an
+// event. The caller and anyone in `skip` are left out, and at most MAX_NOTIFICATION_FANOUT
+// notifications are sent so a popular proposal can't exhaust the instruction limit
+fn _notify_followers(
+    proposal: &Proposal,
+    kind: NotificationKind,
+    comment_id: Option<u64>,
+    also: &[Principal],
+    skip: &[Principal],
+) {
+    let mut recipients: Vec<Principal> = proposal.owner.into_iter().collect();
+    for user in also.iter().copied().chain(_get_followers(&proposal.id)) {
+        if recipients.len() >= MAX_NOTIFICATION_FANOUT {
+            break;
+        }
+        if !recipients.contains(&user) {
+            recipients.push(user);
+        }
+    }
+
+    recipients
+        .iter()
+        .filter(|recipient| **recipient != caller() && !skip.contains(recipient))
+        .for_each(|recipient| {
+            notify(
+                recipient,
+                kind,
+                proposal.dao_id,
+                Some(proposal.id),
+                comment_id,
+            )
+        });
+}
+
+// a helper method to get the followers of a proposal
+fn _get_followers(proposal_id: &u64) -> Vec<Principal> {
+    PROPOSAL_FOLLOWERS.with(|service| {
+        service
+            .borrow()
+            .range((*proposal_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .map(|((_, follower), _)| Principal::from_slice(follower.as_slice()))
+            .collect()
+    })
+}
+
+// a helper method to make a user follow a proposal
+fn _follow(proposal_id: &u64, user: &Principal) {
+    let key = principal_key(user);
+    PROPOSAL_FOLLOWERS.with(|service| service.borrow_mut().insert((*proposal_id, key), ()));
+    FOLLOWED_PROPOSALS.with(|service| service.borrow_mut().insert((key, *proposal_id), ()));
+}
+
+// a helper method to get the co-authors of a proposal
+fn _get_co_authors(proposal_id: &u64) -> Vec<Principal> {
+    PROPOSAL_CO_AUTHORS.with(|service| {
+        service
+            .borrow()
+            .range((*proposal_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .map(|((_, co_author), _)| Principal::from_slice(co_author.as_slice()))
+            .collect()
+    })
+}
+
+// a helper method to check if a user can edit a proposal, as its owner or a co-author
+fn _is_author(proposal: &Proposal, user: &Principal) -> bool {
+    _is_owner(&proposal.owner, user)
+        || PROPOSAL_CO_AUTHORS.with(|service| {
+            service
+                .borrow()
+                .contains_key(&(proposal.id, principal_key(user)))
+        })
+}
+
+// a helper method to replace the co-authors of a proposal. Co-authors who weren't there before
+// are notified
+fn _set_co_authors(proposal: &Proposal, co_authors: Vec<Principal>) {
+    let previous = _get_co_authors(&proposal.id);
+    _clear_co_authors(&proposal.id);
+    for co_author in co_authors {
+        PROPOSAL_CO_AUTHORS.with(|service| {
+            service
+                .borrow_mut()
+                .insert((proposal.id, principal_key(&co_author)), ())
+        });
+        if !previous.contains(&co_author) {
+            notify(
+                &co_author,
+                NotificationKind::CoAuthorAdded,
+                proposal.dao_id,
+                Some(proposal.id),
+                None,
+            );
+        }
+    }
+}
+
+// a helper method to remove every co-author of a proposal
+fn _clear_co_authors(proposal_id: &u64) {
+    _get_co_authors(proposal_id).iter().for_each(|co_author| {
+        PROPOSAL_CO_AUTHORS.with(|service| {
+            service
+                .borrow_mut()
+                .remove(&(*proposal_id, principal_key(co_author)))
+        });
+    });
+}
 
-            match DAO_STORAGE.with(|service| service.borrow().get(&proposal.dao_id)) {
-                Some(mut dao) => {
-                    dao.proposals.push(id);
-                    dao.updated_at = Some(time());
+// a helper method to check the co-authors of a proposal `owner` creates or owns in a DAO.
+// Repeated principals are dropped
+fn _validate_co_authors(
+    dao_id: u64,
+    owner: &Principal,
+    mut co_authors: Vec<Principal>,
+) -> Result<Vec<Principal>, Error> {
+    service::dedupe_principals(&mut co_authors);
+    if co_authors.len() > MAX_CO_AUTHORS {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(dao_id),
+            format!("a proposal can have at most {} co-authors", MAX_CO_AUTHORS),
+        ));
+    }
+    if co_authors.contains(owner) {
+        return Err(Error::invalid_input(
+            ErrorContext::Member(*owner),
+            "the owner of a proposal can't be one of its co-authors".to_string(),
+        ));
+    }
+    let dao = _get_dao(&dao_id).unwrap_or_default();
+    match co_authors
+        .iter()
+        .find(|co_author| !_is_member(&dao, co_author))
+    {
+        Some(outsider) => Err(Error::not_a_member(
+            ErrorContext::Member(*outsider),
+            format!(
+                "{} can't co-author a proposal in dao with id={}. Not a member",
+                outsider, dao_id
+            ),
+        )),
+        None => Ok(co_authors),
+    }
+}
 
-                    do_insert_dao(&dao);
-                }
-                None => (),
+// a helper method to remove every follower of a proposal
+fn _clear_followers(proposal_id: &u64) {
+    _get_followers(proposal_id).iter().for_each(|follower| {
+        let key = principal_key(follower);
+        PROPOSAL_FOLLOWERS.with(|service| service.borrow_mut().remove(&(*proposal_id, key)));
+        FOLLOWED_PROPOSALS.with(|service| service.borrow_mut().remove(&(key, *proposal_id)));
+    });
+}
+
+// a helper method to note that a user voted or commented on a proposal at `now`. The proposal
+// still has to be saved with do_insert_proposal
+fn _record_activity(proposal: &mut Proposal, participant: &Principal, now: u64) {
+    proposal.last_activity_at = now;
+    let is_new = PROPOSAL_PARTICIPANTS.with(|service| {
+        service
+            .borrow_mut()
+            .insert((proposal.id, principal_key(participant)), ())
+            .is_none()
+    });
+    if is_new {
+        proposal.unique_participants += 1;
+    }
+}
+
+// a helper method to compute the discussion summary of a proposal from its votes and comments,
+// for proposals stored before it was kept up to date as they happen
+fn _backfill_discussion(proposal: &mut Proposal) {
+    let votes: Vec<Vote> = VOTE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((proposal.id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| *id == proposal.id)
+            .map(|(_, vote)| vote)
+            .collect()
+    });
+    let comments: Vec<Comment> = _comment_ids(&proposal.id)
+        .iter()
+        .filter_map(_get_comment)
+        .collect();
+
+    let participants: Vec<Principal> = _votes(&proposal.id)
+        .iter()
+        .filter_map(|vote| vote.voter)
+        .chain(comments.iter().filter_map(|comment| comment.author))
+        .collect();
+    participants.iter().for_each(|participant| {
+        PROPOSAL_PARTICIPANTS.with(|service| {
+            service
+                .borrow_mut()
+                .insert((proposal.id, principal_key(participant)), ())
+        });
+    });
+    proposal.unique_participants = PROPOSAL_PARTICIPANTS.with(|service| {
+        service
+            .borrow()
+            .range((proposal.id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| *id == proposal.id)
+            .count() as u32
+    });
+
+    proposal.last_activity_at = votes
+        .iter()
+        .map(|vote| vote.voted_at)
+        .chain(comments.iter().map(|comment| comment.created_at))
+        .fold(proposal.created_at, u64::max);
+}
+
+// a helper method to forget who took part in a proposal
+fn _clear_participants(proposal_id: &u64) {
+    PROPOSAL_PARTICIPANTS.with(|service| {
+        let keys: Vec<(u64, PrincipalKey)> = service
+            .borrow()
+            .range((*proposal_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| id == proposal_id)
+            .map(|(key, _)| key)
+            .collect();
+        let mut service = service.borrow_mut();
+        keys.iter().for_each(|key| {
+            service.remove(key);
+        });
+    });
+}
+
+// a helper method to remember that a user commented on a proposal. Only the first comment adds
+// an entry
+fn _record_participation(user: &Principal, proposal_id: &u64) {
+    let key = (principal_key(user), *proposal_id);
+    THREAD_PARTICIPATION.with(|service| {
+        if !service.borrow().contains_key(&key) {
+            service.borrow_mut().insert(key, ());
+        }
+    });
+}
+
+// a helper method to get the comment a new comment on `proposal` replies to. It has to be a
+// comment on the same proposal that wasn't deleted
+fn _get_reply_parent(proposal: &Proposal, parent_id: &u64) -> Result<Comment, Error> {
+    match _get_comment(parent_id).filter(|parent| parent.proposal_id == proposal.id) {
+        Some(parent) if !parent.deleted => Ok(parent),
+        Some(_) => Err(Error::invalid_input(
+            ErrorContext::Comment(*parent_id),
+            format!(
+                "Couldn't reply to a comment with id={}. It was deleted",
+                parent_id
+            ),
+        )),
+        None => Err(Error::not_found(
+            ErrorContext::Comment(*parent_id),
+            format!(
+                "comment with id={} not found on proposal with id={}",
+                parent_id, proposal.id
+            ),
+        )),
+    }
+}
+
+// a helper method to record that the caller takes down content for `reason`
+fn _removal(context: ErrorContext, reason: String) -> Result<Removal, Error> {
+    let reason = reason.trim().to_string();
+    if reason.is_empty() || reason.chars().count() > MAX_REMOVAL_REASON_LEN {
+        return Err(Error::invalid_input(
+            context,
+            format!(
+                "reason must be between 1 and {} characters",
+                MAX_REMOVAL_REASON_LEN
+            ),
+        ));
+    }
+    Ok(Removal {
+        removed_by: caller(),
+        reason,
+        removed_at: time(),
+    })
+}
+
+// a helper method to check whether a user liked a comment
+fn _has_liked(comment_id: &u64, user: &Principal) -> bool {
+    COMMENT_LIKES.with(|service| {
+        service
+            .borrow()
+            .contains_key(&(*comment_id, principal_key(user)))
+    })
+}
+
+// a helper method to move the likes a comment was decoded with into COMMENT_LIKES and recount
+// them. Comments without such likes are left as they are
+fn _backfill_likes(comment: &mut Comment) {
+    if comment.likes.is_empty() {
+        return;
+    }
+    COMMENT_LIKES.with(|service| {
+        let mut service = service.borrow_mut();
+        comment.likes.iter().for_each(|user| {
+            service.insert((comment.id, principal_key(user)), ());
+        });
+    });
+    comment.likes = Vec::new();
+    comment.like_count = COMMENT_LIKES.with(|service| {
+        service
+            .borrow()
+            .range((comment.id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| *id == comment.id)
+            .count() as u64
+    });
+}
+
+fn _clear_likes(comment_id: &u64) {
+    COMMENT_LIKES.with(|service| {
+        let keys: Vec<(u64, PrincipalKey)> = service
+            .borrow()
+            .range((*comment_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| id == comment_id)
+            .map(|(key, _)| key)
+            .collect();
+        let mut service = service.borrow_mut();
+        keys.iter().for_each(|key| {
+            service.remove(key);
+        });
+    });
+}
+
+// a helper method to get a user's preferences, or the defaults if they never set any
+fn _get_preferences(user: &Principal) -> UserPreferences {
+    PREFERENCES_STORAGE
+        .with(|service| service.borrow().get(&principal_key(user)))
+        .unwrap_or_default()
+}
+
+// a helper method to get the latest constitution version of a DAO, 0 if it has none
+fn _latest_constitution_version(dao_id: &u64) -> u64 {
+    CONSTITUTION_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((*dao_id, 0)..)
+            .take_while(|((id, _), _)| id == dao_id)
+            .map(|((_, version), _)| version)
+            .last()
+            .unwrap_or_default()
+    })
+}
+
+// a helper method to get the latest constitution version a member accepted, 0 if none
+fn _accepted_constitution_version(dao_id: &u64, member: &Principal) -> u64 {
+    CONSTITUTION_ACCEPTANCES
+        .with(|service| service.borrow().get(&(*dao_id, principal_key(member))))
+        .unwrap_or_default()
+}
+
+// a helper method to remove the constitutions of a DAO and who accepted them
+fn _clear_constitutions(dao_id: &u64) {
+    CONSTITUTION_STORAGE.with(|service| {
+        let keys: Vec<(u64, u64)> = service
+            .borrow()
+            .range((*dao_id, 0)..)
+            .take_while(|((id, _), _)| id == dao_id)
+            .map(|(key, _)| key)
+            .collect();
+        keys.iter().for_each(|key| {
+            service.borrow_mut().remove(key);
+        });
+    });
+    CONSTITUTION_ACCEPTANCES.with(|service| {
+        let keys: Vec<(u64, PrincipalKey)> = service
+            .borrow()
+            .range((*dao_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| id == dao_id)
+            .map(|(key, _)| key)
+            .collect();
+        keys.iter().for_each(|key| {
+            service.borrow_mut().remove(key);
+        });
+    });
+}
+
+// a helper method to validate the fields of a profile
+fn _validate_profile(payload: &ProfilePayload) -> Result<(), Error> {
+    let name_length = payload.display_name.len();
+    if !(MIN_DISPLAY_NAME_LEN..=MAX_DISPLAY_NAME_LEN).contains(&name_length) {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            format!(
+                "display name must be between {} and {} characters",
+                MIN_DISPLAY_NAME_LEN, MAX_DISPLAY_NAME_LEN
+            ),
+        ));
+    }
+    let has_valid_characters = payload
+        .display_name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    if !has_valid_characters {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            "display name can only contain letters, digits, '_', '-' and '.'".to_string(),
+        ));
+    }
+    if payload.bio.chars().count() > MAX_BIO_LEN {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            format!("bio can't be longer than {} characters", MAX_BIO_LEN),
+        ));
+    }
+    if payload.avatar_url.chars().count() > MAX_AVATAR_URL_LEN {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            format!(
+                "avatar url can't be longer than {} characters",
+                MAX_AVATAR_URL_LEN
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+// Check if the caller has the role and has been a member for long enough to create proposals in a DAO
+fn _check_if_can_propose(dao: &Dao) -> Result<(), Error> {
+    _check_not_archived(dao)?;
+    let settings = _get_settings(&dao.id);
+    let role = _get_member_role(dao, &caller());
+    if role < settings.proposal_creation_role {
+        return Err(Error::not_eligible(
+            ErrorContext::Dao(dao.id),
+            format!(
+                "Couldn't create a proposal in dao with id={}. Your role is too low",
+                dao.id
+            ),
+        ));
+    }
+
+    _check_dues_paid(dao, &caller(), time())?;
+
+    // the owner and members who joined before joins were recorded are always old enough
+    let joined_at =
+        MEMBER_JOINED_AT.with(|service| service.borrow().get(&(dao.id, principal_key(&caller()))));
+    if let (Role::Member | Role::Admin, Some(joined_at)) = (role, joined_at) {
+        let eligible_at = joined_at.saturating_add(
+            settings
+                .min_membership_age_seconds
+                .saturating_mul(1_000_000_000),
+        );
+        if time() < eligible_at {
+            return Err(Error::not_eligible(ErrorContext::Dao(dao.id), format!(
+                    "Couldn't create a proposal in dao with id={}. You can create one in {} seconds",
+                    dao.id,
+                    (eligible_at - time()).div_ceil(1_000_000_000)
+                )));
+        }
+    }
+
+    Ok(())
+}
+
+// a helper method to check that a member accepted the latest constitution of a DAO asking for it
+// before they vote
+fn _check_constitution_accepted(dao_id: &u64, voter: &Principal) -> Result<(), Error> {
+    if _get_settings(dao_id).constitution_required {
+        let latest = _latest_constitution_version(dao_id);
+        if latest > _accepted_constitution_version(dao_id, voter) {
+            return Err(Error::constitution_not_accepted(
+                ErrorContext::Dao(*dao_id),
+                format!(
+                    "accept version {} of the constitution of dao with id={} to vote",
+                    latest, dao_id
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Check that `user`'s dues in a DAO are paid up at `now`. The owner never owes dues and DAOs without
+// dues have nothing to check
+fn _check_dues_paid(dao: &Dao, user: &Principal, now: u64) -> Result<(), Error> {
+    if _get_settings(&dao.id).dues_amount == 0 || _is_owner(&dao.owner, user) {
+        return Ok(());
+    }
+
+    if _get_paid_until(&dao.id, user) <= now {
+        return Err(Error::not_eligible(
+            ErrorContext::Dao(dao.id),
+            format!(
+                "your dues in dao with id={} have lapsed, pay them with pay_dues to take part",
+                dao.id
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// a helper method to refuse changes to an archived DAO, its proposals and their comments
+fn _check_not_archived(dao: &Dao) -> Result<(), Error> {
+    if dao.archived {
+        return Err(Error::dao_archived(
+            ErrorContext::Dao(dao.id),
+            format!(
+                "dao with id={} is archived and can't be changed until it is unarchived",
+                dao.id
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// a helper method to refuse changes to a DAO by id if it is archived
+fn _check_dao_not_archived(dao_id: &u64) -> Result<(), Error> {
+    match _get_dao(dao_id) {
+        Some(dao) => _check_not_archived(&dao),
+        None => Ok(()),
+    }
+}
+
+// a helper method to archive or unarchive a DAO
+fn _set_archived(id: u64, archived: bool) -> Result<Dao, Error> {
+    match _get_dao(&id) {
+        Some(mut dao) => {
+            if !_is_owner(&dao.owner, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Dao(id),
+                    format!("Couldn't update dao with id={}. You are not the owner", id),
+                ));
+            }
+            if let Some(target) = _get_settings(&id).merged_into.filter(|_| !archived) {
+                return Err(Error::invalid_input(
+                    ErrorContext::Dao(id),
+                    format!(
+                        "Couldn't unarchive dao with id={}. It was merged into dao with id={}",
+                        id, target
+                    ),
+                ));
             }
 
-            let proposal = Proposal {
-                id,
-                title: proposal.title,
-                details: proposal.details,
-                amount_requested: proposal.amount_requested,
-                owner: Some(caller()),
-                created_at: time(),
-                deadline: time() + (7 * 24 * 60 * 60 * 1_000_000_000), // one week
-                updated_at: None,
-                upvotes,
-                is_approved: false,
-                dao_id: proposal.dao_id,
-                comments,
-                downvotes,
+            dao.archived = archived;
+            dao.updated_at = Some(time());
+
+            do_insert_dao(&mut dao);
+            let action = if archived {
+                AuditAction::DaoArchived
+            } else {
+                AuditAction::DaoUnarchived
             };
+            log_event(id, action, AuditTarget::Dao(id));
+            registry::announce(
+                &dao,
+                if archived {
+                    registry::RegistryEvent::Archived
+                } else {
+                    registry::RegistryEvent::Unarchived
+                },
+            );
+            Ok(dao)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Dao(id),
+            format!("couldn't update a dao with id={}. dao not found", id),
+        )),
+    }
+}
 
-            do_insert_proposal(&proposal);
-            Ok(proposal)
+// a helper method to get the time until which a member's dues in a DAO are paid, 0 if never paid
+fn _get_paid_until(dao_id: &u64, member: &Principal) -> u64 {
+    DUES_PAID_UNTIL
+        .with(|service| service.borrow().get(&(*dao_id, principal_key(member))))
+        .unwrap_or_default()
+}
+
+// a helper method to add one period of dues to what a member has paid. Paying early extends the
+// current period instead of starting a new one now
+fn _extend_paid_until(dao_id: &u64, member: &Principal) -> u64 {
+    let period = _get_settings(dao_id).dues_period_seconds;
+    let paid_until = _get_paid_until(dao_id, member)
+        .max(time())
+        .saturating_add(period.saturating_mul(1_000_000_000));
+    DUES_PAID_UNTIL.with(|service| {
+        service
+            .borrow_mut()
+            .insert((*dao_id, principal_key(member)), paid_until)
+    });
+    paid_until
+}
+
+// a helper method to forget who paid dues in a DAO
+fn _clear_dues(dao_id: &u64) {
+    DUES_PAID_UNTIL.with(|service| {
+        let keys: Vec<(u64, PrincipalKey)> = service
+            .borrow()
+            .range((*dao_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| id == dao_id)
+            .map(|(key, _)| key)
+            .collect();
+        keys.iter().for_each(|key| {
+            service.borrow_mut().remove(key);
+        });
+    });
+}
+
+// Get the role of a user in a DAO, assuming they're part of it
+fn _get_member_role(dao: &Dao, user: &Principal) -> Role {
+    if dao.owner == Some(*user) {
+        return Role::Owner;
+    }
+    match _membership(dao.id, user) {
+        Some(Membership::Admin) => Role::Admin,
+        Some(Membership::Observer) => Role::Observer,
+        _ => Role::Member,
+    }
+}
+
+fn _set_pinned(id: u64, pinned: bool) -> Result<ProposalView, Error> {
+    match _get_proposal(&id) {
+        Some(mut proposal) => {
+            let dao = _get_dao(&proposal.dao_id).unwrap_or_default();
+            if !_is_dao_admin(&dao, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Proposal(id),
+                    format!("Couldn't pin proposal with id={}. You are not an admin", id),
+                ));
+            }
+            _check_not_archived(&dao)?;
+
+            if pinned && !proposal.pinned {
+                let pinned_count = _dao_proposal_ids(&dao.id)
+                    .iter()
+                    .filter_map(_get_proposal)
+                    .filter(|proposal| proposal.pinned)
+                    .count();
+                if pinned_count >= MAX_PINNED_PROPOSALS {
+                    return Err(Error::invalid_input(
+                        ErrorContext::Dao(dao.id),
+                        format!(
+                            "a dao can't have more than {} pinned proposals",
+                            MAX_PINNED_PROPOSALS
+                        ),
+                    ));
+                }
+            }
+
+            proposal.pinned = pinned;
+
+            do_insert_proposal(&mut proposal);
+            let action = if pinned {
+                AuditAction::ProposalPinned
+            } else {
+                AuditAction::ProposalUnpinned
+            };
+            log_event(proposal.dao_id, action, AuditTarget::Proposal(id));
+            Ok(ProposalView::from(proposal))
         }
-        None => Err(Error::NotAMember {
-            msg: format!(
-                "unable to get a dao with id={}. Not a member",
-                proposal.dao_id
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(id),
+            format!(
+                "couldn't update a proposal with id={}. proposal not found",
+                id
             ),
-        }),
+        )),
     }
 }
 
-// Ability to update a proposal provided you're the owner and the deadline hasn't passed
-#[ic_cdk::update]
-fn update_proposal(id: u64, payload: ProposalPayload) -> Result<Proposal, Error> {
-    match PROPOSAL_STORAGE.with(|service| service.borrow().get(&id)) {
+fn _set_comments_locked(id: u64, comments_locked: bool) -> Result<ProposalView, Error> {
+    match _get_proposal(&id) {
         Some(mut proposal) => {
-            if proposal.owner.is_some() && proposal.owner != Some(caller()) {
-                return Err(Error::PermissionError {
-                    msg: format!(
-                        "Couldn't update proposal with id={}. You are not the owner",
-                        id
-                    ),
-                });
-            }
-            if is_deadline_not_reaached(proposal.deadline) {
-                return Err(Error::DeadlineExceeded {
-                    msg: format!(
-                        "Couldn't vote on a proposal with id={}. Deadline exceeded",
+            let dao = _get_dao(&proposal.dao_id).unwrap_or_default();
+            if !_is_dao_admin(&dao, &caller()) {
+                return Err(Error::permission_error(
+                    ErrorContext::Proposal(id),
+                    format!(
+                        "Couldn't lock comments of proposal with id={}. You are not an admin",
                         id
                     ),
-                });
+                ));
             }
+            _check_not_archived(&dao)?;
 
-            proposal.title = payload.title;
-            proposal.details = payload.details;
-            proposal.amount_requested = payload.amount_requested;
-            proposal.updated_at = Some(time());
+            proposal.comments_locked = comments_locked;
 
-            do_insert_proposal(&proposal);
-            Ok(proposal)
+            do_insert_proposal(&mut proposal);
+            let action = if comments_locked {
+                AuditAction::CommentsLocked
+            } else {
+                AuditAction::CommentsUnlocked
+            };
+            log_event(proposal.dao_id, action, AuditTarget::Proposal(id));
+            Ok(ProposalView::from(proposal))
         }
-        None => Err(Error::NotFound {
-            msg: format!(
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(id),
+            format!(
                 "couldn't update a proposal with id={}. proposal not found",
                 id
             ),
-        }),
+        )),
     }
 }
 
-// Ability to upvote a proposal provided you're not the owner, you haven't voted and the deadline hasn't passed
-#[ic_cdk::update]
-fn upvote(id: u64) -> Result<Proposal, Error> {
-    match PROPOSAL_STORAGE.with(|service| service.borrow().get(&id)) {
-        Some(mut proposal) => {
-            let can_vote = _check_if_can_vote(&proposal, &proposal.dao_id);
-            if can_vote.is_err() {
-                return Err(can_vote.unwrap_err());
+// a helper method to validate the attachments of a proposal
+fn _validate_attachments(attachments: &[Attachment]) -> Result<(), Error> {
+    if attachments.len() > MAX_ATTACHMENTS_PER_PROPOSAL {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            format!(
+                "a proposal can't have more than {} attachments",
+                MAX_ATTACHMENTS_PER_PROPOSAL
+            ),
+        ));
+    }
+
+    for attachment in attachments {
+        let name_length = attachment.name.chars().count();
+        if name_length == 0 || name_length > MAX_ATTACHMENT_NAME_LEN {
+            return Err(Error::invalid_input(
+                ErrorContext::None,
+                format!(
+                    "attachment name must be between 1 and {} characters",
+                    MAX_ATTACHMENT_NAME_LEN
+                ),
+            ));
+        }
+        let is_https = attachment.url.starts_with("https://")
+            && attachment.url.len() > "https://".len()
+            && !attachment.url.chars().any(char::is_whitespace);
+        if !is_https || attachment.url.len() > MAX_ATTACHMENT_URL_LEN {
+            return Err(Error::invalid_input(
+                ErrorContext::None,
+                format!(
+                    "attachment url {} must be an https url of at most {} characters",
+                    attachment.url, MAX_ATTACHMENT_URL_LEN
+                ),
+            ));
+        }
+        if let Some(sha256) = &attachment.sha256 {
+            if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(Error::invalid_input(
+                    ErrorContext::None,
+                    "attachment sha256 must be 64 hexadecimal characters".to_string(),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// a helper method to validate the milestones of a proposal. Only Funding proposals have them and
+// their amounts have to add up to what the proposal asks for
+fn _validate_milestones(kind: &ProposalKind, milestones: &[Milestone]) -> Result<(), Error> {
+    if milestones.is_empty() {
+        return Ok(());
+    }
+    let amount = match kind {
+        ProposalKind::Funding { amount, .. } => *amount,
+        _ => {
+            return Err(Error::invalid_input(
+                ErrorContext::None,
+                "only funding proposals can have milestones".to_string(),
+            ))
+        }
+    };
+    if milestones.len() > MAX_MILESTONES_PER_PROPOSAL {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            format!(
+                "a proposal can't have more than {} milestones",
+                MAX_MILESTONES_PER_PROPOSAL
+            ),
+        ));
+    }
+
+    for milestone in milestones {
+        let description_length = milestone.description.trim().chars().count();
+        if description_length == 0 || description_length > MAX_MILESTONE_DESCRIPTION_LEN {
+            return Err(Error::invalid_input(
+                ErrorContext::None,
+                format!(
+                    "milestone description must be between 1 and {} characters",
+                    MAX_MILESTONE_DESCRIPTION_LEN
+                ),
+            ));
+        }
+        if milestone.amount == 0 {
+            return Err(Error::invalid_input(
+                ErrorContext::None,
+                "every milestone must pay something".to_string(),
+            ));
+        }
+        // only imported milestones come with a report
+        let report_length = milestone
+            .report
+            .as_ref()
+            .map(|report| report.chars().count())
+            .unwrap_or_default();
+        if report_length > MAX_MILESTONE_REPORT_LEN {
+            return Err(Error::invalid_input(
+                ErrorContext::None,
+                format!(
+                    "milestone report can be at most {} characters",
+                    MAX_MILESTONE_REPORT_LEN
+                ),
+            ));
+        }
+    }
+    let total = milestones
+        .iter()
+        .try_fold(0u64, |total, milestone| total.checked_add(milestone.amount));
+    if total != Some(amount) {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            format!("milestone amounts must add up to the requested {}", amount),
+        ));
+    }
+    Ok(())
+}
+
+// Check that the comment thread of a proposal accepts new comments, edits and likes
+fn _check_if_thread_open(proposal: &Proposal) -> Result<(), Error> {
+    _check_dao_not_archived(&proposal.dao_id)?;
+    if proposal.status == ProposalStatus::Draft {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "proposal with id={} is a draft and doesn't take comments yet",
+                proposal.id
+            ),
+        ));
+    }
+    if proposal.status == ProposalStatus::Cancelled {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "proposal with id={} was cancelled and doesn't take comments",
+                proposal.id
+            ),
+        ));
+    }
+    if proposal.comments_locked {
+        return Err(Error::thread_locked(
+            ErrorContext::Proposal(proposal.id),
+            format!("comments on proposal with id={} are locked", proposal.id),
+        ));
+    }
+    let voting_over = match proposal.status {
+        ProposalStatus::Approved | ProposalStatus::Rejected => true,
+        ProposalStatus::Open => service::is_deadline_passed(proposal.deadline, time()),
+        _ => false,
+    };
+    if voting_over && _get_settings(&proposal.dao_id).auto_lock_comments {
+        return Err(Error::proposal_closed(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "voting on proposal with id={} is over and it doesn't take comments anymore",
+                proposal.id
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// Check that a proposal's comment window, if it has one, is still open for new comments. Edits
+// and likes of existing comments aren't affected
+fn _check_comment_window(proposal: &Proposal) -> Result<(), Error> {
+    match proposal.comments_close_at {
+        Some(closes_at) if time() >= closes_at => Err(Error::comment_window_closed(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "proposal with id={} stopped taking comments at {}",
+                proposal.id, closes_at
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// a helper method to check that a comment window fits in the voting period of a DAO
+fn _validate_comment_window(dao_id: &u64, window_seconds: u64) -> Result<(), Error> {
+    let voting_period_seconds = _voting_period(dao_id) / 1_000_000_000;
+    if window_seconds == 0 || window_seconds > voting_period_seconds {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(*dao_id),
+            format!(
+                "the comment window has to be between 1 and {} seconds, the voting period",
+                voting_period_seconds
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// a helper method to check that a proposal can depend on another in a DAO. The prerequisite has
+// to be in the same DAO and still able to pass, and its own dependencies can't lead back to
+// `dependent_id` or go deeper than MAX_DEPENDENCY_DEPTH. Returns the prerequisite
+fn _validate_dependency(
+    dao_id: &u64,
+    prerequisite_id: &u64,
+    dependent_id: Option<u64>,
+) -> Result<Proposal, Error> {
+    let prerequisite = match _get_proposal(prerequisite_id).filter(|p| p.dao_id == *dao_id) {
+        Some(prerequisite) => prerequisite,
+        None => {
+            return Err(Error::not_found(
+                ErrorContext::Proposal(*prerequisite_id),
+                format!(
+                    "a proposal with id={} not found in dao with id={}",
+                    prerequisite_id, dao_id
+                ),
+            ))
+        }
+    };
+    if matches!(
+        prerequisite.status,
+        ProposalStatus::Rejected | ProposalStatus::Cancelled
+    ) {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(*prerequisite_id),
+            format!(
+                "proposal with id={} can no longer be approved so nothing can depend on it",
+                prerequisite_id
+            ),
+        ));
+    }
+
+    let mut current = Some(prerequisite.clone());
+    for _ in 0..MAX_DEPENDENCY_DEPTH {
+        match current {
+            Some(proposal) if Some(proposal.id) == dependent_id => {
+                return Err(Error::invalid_input(
+                    ErrorContext::Proposal(*prerequisite_id),
+                    format!(
+                        "proposal with id={} already depends on this proposal",
+                        prerequisite_id
+                    ),
+                ))
             }
-            proposal.upvotes.push(caller());
+            Some(proposal) => current = proposal.depends_on.and_then(|id| _get_proposal(&id)),
+            None => return Ok(prerequisite),
+        }
+    }
+    Err(Error::invalid_input(
+        ErrorContext::Proposal(*prerequisite_id),
+        format!(
+            "dependencies can't be chained more than {} proposals deep",
+            MAX_DEPENDENCY_DEPTH
+        ),
+    ))
+}
+
+// a helper method to get the status of a proposal being published: open, unless it depends on a
+// proposal that isn't approved yet
+fn _status_after_publishing(prerequisite: Option<&Proposal>) -> ProposalStatus {
+    match prerequisite {
+        Some(prerequisite) if prerequisite.status != ProposalStatus::Approved => {
+            ProposalStatus::Blocked
+        }
+        _ => ProposalStatus::Open,
+    }
+}
+
+// a helper method to start the voting period of a proposal, or schedule it when its voting
+// starts later. The deadline counts from the start of voting
+fn _start_voting(proposal: &mut Proposal) {
+    match proposal.voting_starts_at {
+        Some(voting_starts_at) if voting_starts_at > time() => {
+            proposal.status = ProposalStatus::Scheduled;
+            proposal.deadline = voting_starts_at + _voting_period(&proposal.dao_id);
+        }
+        _ => {
+            proposal.status = ProposalStatus::Open;
+            proposal.deadline = time() + _voting_period(&proposal.dao_id);
+        }
+    }
+    maintenance::schedule_reminder(proposal.id, proposal.deadline);
+}
+
+// a helper method to check that voting on a new proposal can start at `voting_starts_at`
+fn _validate_voting_start(dao_id: &u64, voting_starts_at: u64) -> Result<(), Error> {
+    if voting_starts_at <= time() || voting_starts_at - time() > MAX_SCHEDULE_AHEAD {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(*dao_id),
+            "voting can only be scheduled to start within the next 30 days".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// a helper method to run every check of add_proposal on a payload except the rate limit and the
+// deposit, which add_proposal and can_create_proposal handle on their own
+fn _check_new_proposal(proposal: &ProposalPayload) -> Result<CheckedProposal, Error> {
+    _is_user_part_of_dao(&proposal.dao_id)?;
+    _validate_proposal_text(proposal)?;
+    let kind = _proposal_kind(proposal)?;
+    let attachments = proposal.attachments.clone().unwrap_or_default();
+    _validate_attachments(&attachments)?;
+    let milestones: Vec<Milestone> = proposal
+        .milestones
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .map(Milestone::from)
+        .collect();
+    _validate_milestones(&kind, &milestones)?;
+    if let Some(dao) = _get_dao(&proposal.dao_id) {
+        _check_if_can_propose(&dao)?;
+    }
+    if let Some(voting_starts_at) = proposal.voting_starts_at {
+        _validate_voting_start(&proposal.dao_id, voting_starts_at)?;
+    }
+    if proposal.anonymous_ballot.unwrap_or_default() {
+        features::check_enabled(features::Feature::AnonymousBallots)?;
+    }
+    if let Some(window) = proposal.comment_window_seconds {
+        _validate_comment_window(&proposal.dao_id, window)?;
+    }
+    let prerequisite = match proposal.depends_on {
+        Some(prerequisite_id) => Some(_validate_dependency(
+            &proposal.dao_id,
+            &prerequisite_id,
+            None,
+        )?),
+        None => None,
+    };
+    let opens = proposal.publish.unwrap_or(true)
+        && _status_after_publishing(prerequisite.as_ref()) == ProposalStatus::Open;
+    if opens && !proposal.queue_if_full.unwrap_or_default() && _is_dao_full(&proposal.dao_id) {
+        return Err(Error::too_many_open_proposals(
+            ErrorContext::Dao(proposal.dao_id),
+            format!(
+                "Couldn't create a proposal in dao with id={}. It already has the most open proposals it allows, set queue_if_full to wait for a free slot",
+                proposal.dao_id
+            ),
+        ));
+    }
+    let co_authors = _validate_co_authors(
+        proposal.dao_id,
+        &caller(),
+        proposal.co_authors.clone().unwrap_or_default(),
+    )?;
+    let proposal_limit = _get_settings(&proposal.dao_id)
+        .max_proposals_per_window
+        .or(CONFIG.with(|config| config.borrow().get().max_proposals_per_window))
+        .map(|limit| limit as usize)
+        .unwrap_or(rate_limit::MAX_PROPOSALS_PER_WINDOW);
+
+    Ok(CheckedProposal {
+        kind,
+        attachments,
+        milestones,
+        prerequisite,
+        proposal_limit,
+        co_authors,
+    })
+}
+
+// a helper method to move the proposals blocked on a finalized proposal along. They open for
+// voting when it was approved, and are rejected, along with their own dependents, otherwise
+fn _resolve_dependents(prerequisite: &Proposal) {
+    let next_status = match prerequisite.status {
+        ProposalStatus::Approved => ProposalStatus::Open,
+        ProposalStatus::Rejected | ProposalStatus::Cancelled => ProposalStatus::Rejected,
+        _ => return,
+    };
+    let dependents: Vec<Proposal> = _dao_proposal_ids(&prerequisite.dao_id)
+        .iter()
+        .filter_map(_get_proposal)
+        .filter(|proposal| proposal.depends_on == Some(prerequisite.id))
+        .filter(|proposal| proposal.status == ProposalStatus::Blocked)
+        .collect();
+
+    for mut dependent in dependents {
+        dependent.status = next_status;
+        if next_status == ProposalStatus::Open {
+            _open_or_queue(&mut dependent);
+        }
+        dependent.updated_at = Some(time());
+
+        do_insert_proposal(&mut dependent);
+        log_event(
+            dependent.dao_id,
+            if next_status == ProposalStatus::Open {
+                AuditAction::ProposalUnblocked
+            } else {
+                AuditAction::ProposalFinalized
+            },
+            AuditTarget::Proposal(dependent.id),
+        );
+        if next_status == ProposalStatus::Rejected {
+            webhooks::notify_finalized(&dependent);
+        }
+        // chains are at most MAX_DEPENDENCY_DEPTH deep so this recursion is bounded
+        _resolve_dependents(&dependent);
+    }
+}
+
+// a helper method to cancel the proposals a member owns in a DAO they left or were removed from,
+// unless they were already finalized. Their followers are told, the member isn't
+fn _withdraw_proposals_of(dao: &Dao, member: &Principal) {
+    let withdrawn: Vec<Proposal> = _dao_proposal_ids(&dao.id)
+        .iter()
+        .filter_map(_get_proposal)
+        .filter(|proposal| proposal.owner == Some(*member))
+        .filter(|proposal| {
+            matches!(
+                proposal.status,
+                ProposalStatus::Open
+                    | ProposalStatus::Scheduled
+                    | ProposalStatus::Draft
+                    | ProposalStatus::Blocked
+                    | ProposalStatus::Queued
+            )
+        })
+        .collect();
+
+    for mut proposal in withdrawn {
+        proposal.status = ProposalStatus::Cancelled;
+        proposal.cancel_reason = Some(CancelReason::AuthorLeft);
+        proposal.updated_at = Some(time());
+
+        do_insert_proposal(&mut proposal);
+        _dequeue_proposal(&proposal);
+        log_event(
+            dao.id,
+            AuditAction::ProposalCancelled,
+            AuditTarget::Proposal(proposal.id),
+        );
+        _notify_followers(
+            &proposal,
+            NotificationKind::ProposalCancelled,
+            None,
+            &[],
+            &[*member],
+        );
+        _resolve_dependents(&proposal);
+    }
+    _promote_queued(dao.id);
+}
 
-            do_insert_proposal(&proposal);
-            Ok(proposal)
-        }
-        None => Err(Error::NotFound {
-            msg: format!(
-                "couldn't vote on a proposal with id={}. proposal not found",
-                id
-            ),
-        }),
+// a helper method to check if the vote counts of a proposal are kept from the caller. Only open
+// proposals of DAOs with hide_tally_until_closed hide them, from everyone but the owner and
+// admins unless hide_tally_from_admins is set too
+fn _tally_hidden(proposal: &Proposal) -> bool {
+    if !_tally_sealed(proposal) {
+        return false;
     }
+    _get_settings(&proposal.dao_id)
+        .hide_tally_from_admins
+        .unwrap_or_default()
+        || !_get_dao(&proposal.dao_id).is_some_and(|dao| _is_dao_admin(&dao, &caller()))
 }
 
-// Ability to downvote a proposal provided you're not the owner, you haven't voted and the deadline hasn't passed
-#[ic_cdk::update]
-fn downvote(id: u64) -> Result<Proposal, Error> {
-    match PROPOSAL_STORAGE.with(|service| service.borrow().get(&id)) {
-        Some(mut proposal) => {
-            let can_vote = _check_if_can_vote(&proposal, &proposal.dao_id);
-            if can_vote.is_err() {
-                return Err(can_vote.unwrap_err());
-            }
-            proposal.downvotes.push(caller());
+// a helper method to check if the vote counts of a proposal are kept from members, whoever
+// asks. Nothing public, like the certified digest, may give them away then
+fn _tally_sealed(proposal: &Proposal) -> bool {
+    proposal.status == ProposalStatus::Open
+        && _get_settings(&proposal.dao_id)
+            .hide_tally_until_closed
+            .unwrap_or_default()
+}
 
-            do_insert_proposal(&proposal);
-            Ok(proposal)
-        }
-        None => Err(Error::NotFound {
-            msg: format!(
-                "couldn't vote on a proposal with id={}. proposal not found",
-                id
+// a helper method to fail for endpoints that show how a proposal's vote stands while its tally
+// is hidden from the caller
+fn _check_tally_visible(proposal: &Proposal) -> Result<(), Error> {
+    if _tally_hidden(proposal) {
+        return Err(Error::permission_error(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't get the votes of proposal with id={}. Its dao hides them until the vote is finalized",
+                proposal.id
             ),
-        }),
+        ));
     }
+    Ok(())
 }
 
-// Ability to end a proposal provided you're the owner and the deadline has passed
-#[ic_cdk::update]
-fn end_proposal_vote(id: u64) -> Result<Proposal, Error> {
-    match PROPOSAL_STORAGE.with(|service| service.borrow().get(&id)) {
-        Some(mut proposal) => {
-            if proposal.owner.is_some() && proposal.owner != Some(caller()) {
-                return Err(Error::CantEditProposal {
-                    msg: format!(
-                        "Couldn't update proposal with id={}. You are not the owner",
-                        id
-                    ),
-                });
-            }
-            if !is_deadline_not_reaached(proposal.deadline) {
-                return Err(Error::DeadlineNotExceeded {
-                    msg: format!("Voting period for proposal with id={} isn't over.", id),
-                });
-            }
+// a helper method to check if `user` can see a proposal. Drafts are only shown to their owner
+// and the DAO admins
+fn _can_see_proposal(proposal: &Proposal, user: &Principal) -> bool {
+    proposal.status != ProposalStatus::Draft
+        || proposal.owner == Some(*user)
+        || _get_dao(&proposal.dao_id).is_some_and(|dao| _is_dao_admin(&dao, user))
+}
 
-            let total_votes = proposal.downvotes.len() - proposal.upvotes.len();
-            if total_votes > 0 {
-                proposal.is_approved = true;
-            } else {
-                proposal.is_approved = false;
-            }
+// Check if the caller can see content hidden by reports in a DAO
+fn _can_see_hidden(dao_id: &u64) -> bool {
+    _get_dao(dao_id)
+        .map(|dao| _is_dao_admin(&dao, &caller()))
+        .unwrap_or_default()
+}
 
-            do_insert_proposal(&proposal);
-            Ok(proposal)
-        }
-        None => Err(Error::NotFound {
-            msg: format!(
-                "couldn't update a proposal with id={}. proposal not found",
-                id
-            ),
-        }),
+// Check if a user is the owner or one of the admins of a DAO
+fn _is_dao_admin(dao: &Dao, user: &Principal) -> bool {
+    dao.owner == Some(*user) || _membership(dao.id, user) == Some(Membership::Admin)
+}
+
+// Check if a user owns a record, see service::is_owner
+fn _is_owner(owner: &Option<Principal>, user: &Principal) -> bool {
+    service::is_owner(owner, user, owner.is_none() && _is_canister_admin(user))
+}
+
+// Check if a user may perform canister wide administration
+fn _is_canister_admin(user: &Principal) -> bool {
+    env::is_controller(user)
+        || CONFIG.with(|config| {
+            let config = config.borrow();
+            config.get().admin == Some(*user)
+                || config
+                    .get()
+                    .additional_admins
+                    .as_ref()
+                    .map(|admins| admins.contains(user))
+                    .unwrap_or_default()
+        })
+}
+
+// Guard of the queries. While the storage migration runs, records can be halfway between the map
+// they were stored in and the one they move to, so nothing is read until it is done. Buckets are
+// only read through their main canister
+fn _check_can_read() -> Result<(), String> {
+    if migrations::is_running() {
+        return Err(
+            "Unavailable: the canister is migrating its storage, try again shortly".to_string(),
+        );
     }
+    sharding::check_direct_call()
 }
 
-// Ability to delete proposal provided you're the owner and the deadline hasn't passed
-#[ic_cdk::update]
-fn delete_proposal(id: u64) -> Result<Proposal, Error> {
-    match PROPOSAL_STORAGE.with(|service| service.borrow_mut().remove(&id)) {
-        Some(proposal) => {
-            if proposal.owner.is_some() && proposal.owner != Some(caller()) {
-                return Err(Error::PermissionError {
-                    msg: format!(
-                        "Couldn't delete a proposal with id={}. You are not the owner",
-                        id
-                    ),
-                });
-            }
-            if is_deadline_not_reaached(proposal.deadline) {
-                return Err(Error::DeadlineExceeded {
-                    msg: format!(
-                        "Couldn't delete a proposal with id={}. Deadline exceeded",
-                        id
-                    ),
-                });
-            }
+// Guard of every update call. Nothing is changed while the storage migration runs, so it never
+// works on a record while it is being moved, and banned users are rejected
+fn _check_can_update() -> Result<(), String> {
+    _check_can_read()?;
+    _check_not_globally_banned()
+}
 
-            match DAO_STORAGE.with(|service| service.borrow().get(&proposal.dao_id)) {
-                Some(mut dao) => {
-                    dao.proposals.retain(|x| *x != id);
+// Guard of the calls a main canister forwards to its bucket
+fn _check_forwarded() -> Result<(), String> {
+    _check_can_read()?;
+    sharding::check_forwarded()
+}
 
-                    do_insert_dao(&dao);
-                }
-                None => {}
-            }
+// Rejects users banned from the whole canister. Either the calling principal or the account it is
+// linked to can be banned. Guards can't return an Error, so the rejection only carries its message
+fn _check_not_globally_banned() -> Result<(), String> {
+    let ban = [env::caller(), caller()]
+        .iter()
+        .find_map(|user| GLOBAL_BANS.with(|service| service.borrow().get(&principal_key(user))));
+    match ban {
+        Some(ban) => Err(format!(
+            "Unauthorized: {} is banned from this canister. Reason: {}",
+            ban.user, ban.reason
+        )),
+        None => Ok(()),
+    }
+}
 
-            proposal.comments.iter().for_each(|comment_id| {
-                COMMENT_STORAGE.with(|service| service.borrow_mut().remove(&comment_id));
-            });
+// a helper method to get how long proposals of a DAO stay open for voting
+fn _voting_period(dao_id: &u64) -> u64 {
+    _get_settings(dao_id)
+        .voting_period_seconds
+        .or(CONFIG.with(|config| config.borrow().get().voting_period_seconds))
+        .map(|seconds| seconds * 1_000_000_000)
+        .unwrap_or(VOTING_PERIOD)
+}
 
-            Ok(proposal)
+// a helper method to validate settings and save them over the current config. Fails with what's
+// wrong with them
+fn _apply_init_args(args: InitArgs) -> Result<CanisterConfig, String> {
+    let positive = [
+        ("voting period", args.voting_period_seconds),
+        ("maintenance interval", args.maintenance_interval_seconds),
+        ("proposals per window", args.max_proposals_per_window),
+        ("comments per window", args.max_comments_per_window),
+        ("storage soft limit", args.storage_soft_limit_bytes),
+        ("DAOs per bucket", args.max_daos_per_bucket),
+    ];
+    if let Some((name, _)) = positive.iter().find(|(_, value)| *value == Some(0)) {
+        return Err(format!("{} must be at least 1", name));
+    }
+    if let Some(seconds) = args.voting_period_seconds {
+        if seconds.checked_mul(1_000_000_000).is_none() {
+            return Err("voting period is too long".to_string());
         }
-        None => Err(Error::NotFound {
-            msg: format!(
-                "Couldn't delete a proposal with id={}. proposal not found.",
-                id
-            ),
-        }),
     }
+
+    let mut config = CONFIG.with(|config| config.borrow().get().clone());
+    if let Some((admin, additional_admins)) = args.admins.split_first() {
+        config.admin = Some(*admin);
+        config.additional_admins = Some(additional_admins.to_vec());
+    }
+    config.voting_period_seconds = args.voting_period_seconds.or(config.voting_period_seconds);
+    config.max_daos_per_user = args.max_daos_per_user.or(config.max_daos_per_user);
+    config.ledger_canister_id = args.ledger_canister_id.or(config.ledger_canister_id);
+    config.maintenance_interval_seconds = args
+        .maintenance_interval_seconds
+        .or(config.maintenance_interval_seconds);
+    config.max_import_entities = args.max_import_entities.or(config.max_import_entities);
+    config.max_proposals_per_window = args
+        .max_proposals_per_window
+        .or(config.max_proposals_per_window);
+    config.max_comments_per_window = args
+        .max_comments_per_window
+        .or(config.max_comments_per_window);
+    config.storage_soft_limit_bytes = args
+        .storage_soft_limit_bytes
+        .or(config.storage_soft_limit_bytes);
+    config.max_daos_per_bucket = args.max_daos_per_bucket.or(config.max_daos_per_bucket);
+    if let Some(bucket) = args.bucket {
+        sharding::become_bucket(bucket, &mut config);
+    }
+    CONFIG
+        .with(|cell| cell.borrow_mut().set(config.clone()))
+        .expect("cannot update the config");
+    Ok(config)
 }
 
-/**
-* -----------------------------------------------------------------------------
-* COMMENT FUNCTIONS
-* -----------------------------------------------------------------------------
-*/
+// convert a validated display name into its case-insensitive index key
+fn display_name_key(display_name: &str) -> DisplayNameKey {
+    DisplayNameKey::try_from(display_name.to_ascii_lowercase().as_bytes())
+        .expect("display name is at most 30 bytes")
+}
 
-// Ability to get all comments on a proposal
-#[ic_cdk::query]
-fn get_all_comments_on_proposal(proposal_id: u64, dao_id: u64) -> Result<Vec<Comment>, Error> {
-    let is_user_part_of_dao: Option<bool> = _is_user_part_of_dao(&dao_id);
-    match is_user_part_of_dao {
-        Some(_is_true) => {
-            let comments_map: Vec<(u64, Comment)> =
-                COMMENT_STORAGE.with(|service| service.borrow().iter().collect());
-            if comments_map.is_empty() {
-                return Err(Error::NotFound {
-                    msg: format!("No comments found. Why don't you try creating one"),
-                });
-            }
+// convert a slug looked up by a client into its index key. None if it can't be a slug
+fn _slug_key(slug: &str) -> Option<SlugKey> {
+    SlugKey::try_from(slug.to_ascii_lowercase().as_bytes()).ok()
+}
 
-            let proposal_comments: Vec<Comment> = comments_map
-                .into_iter()
-                .filter(|(_, comment)| comment.proposal_id == proposal_id)
-                .map(|(_, comment)| comment)
-                .collect();
+// a helper method to find the first of `base`, `base-2`, `base-3`, ... that `is_free`
+fn _free_slug(base: &str, is_free: impl Fn(&SlugKey) -> bool) -> String {
+    (1..)
+        .map(|n| service::numbered_slug(base, n))
+        .find(|slug| _slug_key(slug).map(|key| is_free(&key)).unwrap_or_default())
+        .expect("numbered slugs never run out")
+}
 
-            Ok(proposal_comments)
-        }
-        None => Err(Error::NotAMember {
-            msg: format!("unable to get a dao with id={}. Not a member", dao_id),
-        }),
+// a helper method to give a DAO the first slug derived from its name that no other DAO has used.
+// The slugs it had before stay in DAO_SLUGS so links using them keep working
+fn _assign_dao_slug(dao: &mut Dao) {
+    let base = service::slugify(&dao.name, MAX_SLUG_BASE_LEN, "dao");
+    let slug = _free_slug(&base, |key| {
+        DAO_SLUGS
+            .with(|service| service.borrow().get(key))
+            .is_none_or(|id| id == dao.id)
+    });
+    if let Some(key) = _slug_key(&slug) {
+        DAO_SLUGS.with(|service| service.borrow_mut().insert(key, dao.id));
     }
+    dao.slug = slug;
 }
 
-// Ability to comment a proposal that can be voted on within a week
-#[ic_cdk::update]
-fn comment_on_post(comment: CommentPayload) -> Result<Comment, Error> {
-    match PROPOSAL_STORAGE.with(|service| service.borrow().get(&comment.proposal_id)) {
-        Some(mut proposal) => {
-            let is_user_part_of_dao: Option<bool> = _is_user_part_of_dao(&proposal.dao_id);
-            match is_user_part_of_dao {
-                Some(_is_true) => {
-                    let likes: Vec<Principal> = Vec::new();
+// a helper method to give a proposal the first slug derived from its title that no other
+// proposal in its DAO has used. Like DAO slugs, the previous ones keep resolving
+fn _assign_proposal_slug(proposal: &mut Proposal) {
+    let base = service::slugify(&proposal.title, MAX_SLUG_BASE_LEN, "proposal");
+    let slug = _free_slug(&base, |key| {
+        PROPOSAL_SLUGS
+            .with(|service| service.borrow().get(&(proposal.dao_id, *key)))
+            .is_none_or(|id| id == proposal.id)
+    });
+    if let Some(key) = _slug_key(&slug) {
+        PROPOSAL_SLUGS.with(|service| {
+            service
+                .borrow_mut()
+                .insert((proposal.dao_id, key), proposal.id)
+        });
+    }
+    proposal.slug = slug;
+}
 
-                    let id = ID_COUNTER
-                        .with(|counter| {
-                            let current_value = *counter.borrow().get();
-                            counter.borrow_mut().set(current_value + 1)
-                        })
-                        .expect("cannot increment id counter");
+// a helper method to remove every slug pointing at a DAO
+fn _clear_dao_slugs(dao_id: &u64) {
+    DAO_SLUGS.with(|service| {
+        let keys: Vec<SlugKey> = service
+            .borrow()
+            .iter()
+            .filter(|(_, id)| id == dao_id)
+            .map(|(key, _)| key)
+            .collect();
+        keys.iter().for_each(|key| {
+            service.borrow_mut().remove(key);
+        });
+    });
+}
 
-                    proposal.comments.push(id);
-                    proposal.updated_at = Some(time());
+// a helper method to remove every slug pointing at a proposal
+fn _clear_proposal_slugs(proposal: &Proposal) {
+    PROPOSAL_SLUGS.with(|service| {
+        let keys: Vec<(u64, SlugKey)> = service
+            .borrow()
+            .range((proposal.dao_id, SlugKey::default())..)
+            .take_while(|((dao_id, _), _)| *dao_id == proposal.dao_id)
+            .filter(|(_, id)| *id == proposal.id)
+            .map(|(key, _)| key)
+            .collect();
+        keys.iter().for_each(|key| {
+            service.borrow_mut().remove(key);
+        });
+    });
+}
 
-                    do_insert_proposal(&proposal);
+// a helper method to take the next proposal number of a DAO. The caller saves the DAO
+fn _next_proposal_number(dao: &mut Dao) -> u64 {
+    dao.last_proposal_number += 1;
+    dao.last_proposal_number
+}
 
-                    let comment = Comment {
-                        id,
-                        content: comment.content,
-                        proposal_id: comment.proposal_id,
-                        created_at: time(),
-                        updated_at: None,
-                        likes,
-                        author: Some(caller()),
-                    };
+// a helper method to make a proposal findable by its number in its DAO
+fn _index_proposal_number(proposal: &Proposal) {
+    if proposal.proposal_number > 0 {
+        PROPOSAL_NUMBERS.with(|service| {
+            service
+                .borrow_mut()
+                .insert((proposal.dao_id, proposal.proposal_number), proposal.id)
+        });
+    }
+}
 
-                    do_insert_comment(&comment);
-                    Ok(comment)
-                }
-                None => Err(Error::NotAMember {
-                    msg: format!(
-                        "unable to get a dao with id={}. Not a member",
-                        proposal.dao_id
-                    ),
-                }),
-            }
+// a helper method to remove the number of a proposal from its DAO's index. The number stays used
+fn _clear_proposal_number(proposal: &Proposal) {
+    let key = (proposal.dao_id, proposal.proposal_number);
+    PROPOSAL_NUMBERS.with(|service| {
+        let indexed = service.borrow().get(&key);
+        if indexed == Some(proposal.id) {
+            service.borrow_mut().remove(&key);
         }
-        None => Err(Error::NotAMember {
-            msg: format!(
-                "cannot comment on proposal with id={}. Not found",
-                comment.proposal_id
-            ),
-        }),
+    });
+}
+
+// a helper method to number the proposals of a DAO from before proposal numbers, oldest first
+// after the ones that already have a number
+fn _number_proposals(dao: &mut Dao) {
+    let mut unnumbered: Vec<Proposal> = _dao_proposal_ids(&dao.id)
+        .iter()
+        .filter_map(_get_proposal)
+        .filter(|proposal| proposal.proposal_number == 0)
+        .collect();
+    unnumbered.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+    for mut proposal in unnumbered {
+        proposal.proposal_number = _next_proposal_number(dao);
+        _index_proposal_number(&proposal);
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(proposal.id, proposal));
     }
 }
 
-// Ability to update a proposal provided you're the owner and the deadline hasn't passed
-#[ic_cdk::update]
-fn update_comment(id: u64, payload: CommentPayload) -> Result<Comment, Error> {
-    match COMMENT_STORAGE.with(|service| service.borrow().get(&id)) {
-        Some(mut comment) => {
-            if comment.author.is_some() && comment.author != Some(caller()) {
-                return Err(Error::PermissionError {
-                    msg: format!(
-                        "Couldn't update comment with id={}. You are not the owner",
-                        id
-                    ),
-                });
-            }
+// a helper method to count the proposals of a DAO that are open or scheduled for voting
+fn _open_proposal_count(dao_id: &u64) -> usize {
+    _dao_proposal_ids(dao_id)
+        .iter()
+        .filter_map(_get_proposal)
+        .filter(|proposal| {
+            matches!(
+                proposal.status,
+                ProposalStatus::Open | ProposalStatus::Scheduled
+            )
+        })
+        .count()
+}
 
-            comment.content = payload.content;
-            comment.updated_at = Some(time());
+// a helper method to check if a DAO has as many proposals open as its max_open_proposals allows
+fn _is_dao_full(dao_id: &u64) -> bool {
+    match _get_settings(dao_id).max_open_proposals {
+        Some(limit) => _open_proposal_count(dao_id) >= limit as usize,
+        None => false,
+    }
+}
 
-            do_insert_comment(&comment);
-            Ok(comment)
-        }
-        None => Err(Error::NotFound {
-            msg: format!(
-                "couldn't update a comment with id={}. comment not found",
-                id
-            ),
-        }),
+// a helper method to start the voting period of a proposal that is being opened, or to queue it
+// when its DAO is full
+fn _open_or_queue(proposal: &mut Proposal) {
+    if _is_dao_full(&proposal.dao_id) {
+        proposal.status = ProposalStatus::Queued;
+        _enqueue_proposal(proposal);
+    } else {
+        _start_voting(proposal);
     }
 }
 
-// Ability to like a coment provided you're not the owner and you haven't liked
-#[ic_cdk::update]
-fn like_comment(id: u64, dao_id: u64) -> Result<Comment, Error> {
-    match COMMENT_STORAGE.with(|service| service.borrow_mut().get(&id)) {
-        Some(mut comment) => match _is_user_part_of_dao(&dao_id) {
-            Some(_is_true) => {
-                if comment.author.is_some() && comment.author == Some(caller()) {
-                    return Err(Error::CantLikeYours {
-                        msg: format!(
-                            "Couldn't like a comment with id={} because you created the comment",
-                            comment.id
-                        ),
-                    });
-                }
+// a helper method to put a proposal at the end of its DAO's queue
+fn _enqueue_proposal(proposal: &Proposal) {
+    PROPOSAL_QUEUE.with(|service| {
+        service
+            .borrow_mut()
+            .insert((proposal.dao_id, proposal.id), ())
+    });
+}
 
-                let has_liked = comment.likes.iter().any(|user| *user == caller());
-                if has_liked {
-                    return Err(Error::HasVoted {
-                        msg: format!(
-                            "Couldn't like a comment with id={}. User has already liked",
-                            comment.id
-                        ),
-                    });
-                }
+// a helper method to take a proposal out of its DAO's queue, if it is in there
+fn _dequeue_proposal(proposal: &Proposal) {
+    PROPOSAL_QUEUE.with(|service| service.borrow_mut().remove(&(proposal.dao_id, proposal.id)));
+}
 
-                comment.likes.push(caller());
+// a helper method to get where a queued proposal is in its DAO's queue, 1 for the next to open
+fn _queue_position(proposal: &Proposal) -> u64 {
+    PROPOSAL_QUEUE.with(|service| {
+        service
+            .borrow()
+            .range((proposal.dao_id, 0)..=(proposal.dao_id, proposal.id))
+            .count() as u64
+    })
+}
 
-                do_insert_comment(&comment);
-                Ok(comment)
-            }
-            None => Err(Error::NotFound {
-                msg: format!("Dao of id={} not found.", dao_id),
-            }),
-        },
-        None => Err(Error::NotFound {
-            msg: format!(
-                "Couldn't vote on a comment with id={}. Comment not found",
-                id
-            ),
-        }),
+// a helper method to open the oldest queued proposals of a DAO for as long as it has room for
+// them. Called whenever an open proposal is finalized, cancelled or removed, or the limit changes
+fn _promote_queued(dao_id: u64) {
+    while !_is_dao_full(&dao_id) {
+        let next = PROPOSAL_QUEUE.with(|service| {
+            service
+                .borrow()
+                .range((dao_id, 0)..=(dao_id, u64::MAX))
+                .next()
+                .map(|((_, id), _)| id)
+        });
+        let id = match next {
+            Some(id) => id,
+            None => break,
+        };
+        PROPOSAL_QUEUE.with(|service| service.borrow_mut().remove(&(dao_id, id)));
+        // an entry left behind by a proposal that moved on some other way
+        let mut proposal = match _get_proposal(&id).filter(|p| p.status == ProposalStatus::Queued) {
+            Some(proposal) => proposal,
+            None => continue,
+        };
+
+        _start_voting(&mut proposal);
+        proposal.updated_at = Some(time());
+        do_insert_proposal(&mut proposal);
+        log_event(
+            dao_id,
+            AuditAction::ProposalOpened,
+            AuditTarget::Proposal(id),
+        );
+        _notify_followers(&proposal, NotificationKind::ProposalOpened, None, &[], &[]);
     }
 }
 
-// Ability to delete proposal provided you're the owner and the deadline hasn't passed
-#[ic_cdk::update]
-fn delete_comment(id: u64) -> Result<Comment, Error> {
-    match COMMENT_STORAGE.with(|service| service.borrow_mut().remove(&id)) {
-        Some(comment) => {
-            if comment.author.is_some() && comment.author != Some(caller()) {
-                return Err(Error::PermissionError {
-                    msg: format!(
-                        "Couldn't delete a comment with id={}. You are not the owner",
-                        id
-                    ),
-                });
-            }
+// The principal the caller acts as. Principals linked with complete_link act as their primary
+// principal, so every membership, ownership and vote check resolves the caller through here
+// instead of calling ic_cdk's caller directly
+fn caller() -> Principal {
+    let principal = env::caller();
+    _get_primary(&principal).unwrap_or(principal)
+}
 
-            match PROPOSAL_STORAGE.with(|service| service.borrow().get(&comment.proposal_id)) {
-                Some(mut proposal) => {
-                    proposal.comments.retain(|x| *x != id);
+// a helper method to get the principal an alias is linked to
+fn _get_primary(alias: &Principal) -> Option<Principal> {
+    ACCOUNT_ALIASES
+        .with(|service| service.borrow().get(&principal_key(alias)))
+        .map(|primary| Principal::from_slice(primary.as_slice()))
+}
 
-                    do_insert_proposal(&proposal);
-                }
-                None => {}
-            }
+// a helper method to get the principals linked to a primary principal
+fn _get_aliases(primary: &Principal) -> Vec<Principal> {
+    let key = principal_key(primary);
+    ALIASES_BY_PRIMARY.with(|service| {
+        service
+            .borrow()
+            .range((key, PrincipalKey::default())..)
+            .take_while(|((owner, _), _)| *owner == key)
+            .map(|((_, alias), _)| Principal::from_slice(alias.as_slice()))
+            .collect()
+    })
+}
 
-            Ok(comment)
-        }
-        None => Err(Error::NotFound {
-            msg: format!(
-                "Couldn't delete a comment with id={}. comment not found.",
-                id
+// a helper method to check that another principal can be linked to `primary`. Links don't
+// chain, so a principal that is itself an alias can't be linked to
+fn _check_can_link_to(primary: &Principal) -> Result<(), Error> {
+    if _get_primary(primary).is_some() {
+        return Err(Error::invalid_input(
+            ErrorContext::Member(*primary),
+            format!(
+                "{} is linked to another principal. Start the link from that one instead",
+                primary
             ),
-        }),
+        ));
+    }
+    if _get_aliases(primary).len() >= MAX_ALIASES_PER_PRINCIPAL {
+        return Err(Error::invalid_input(
+            ErrorContext::Member(*primary),
+            format!(
+                "at most {} principals can be linked to {}",
+                MAX_ALIASES_PER_PRINCIPAL, primary
+            ),
+        ));
     }
+    Ok(())
 }
 
-/**
-* -----------------------------------------------------------------------------
-* ERRORS
-* -----------------------------------------------------------------------------
-*/
-
-#[derive(candid::CandidType, Deserialize, Serialize)]
-enum Error {
-    NotFound { msg: String },
-    NotAMember { msg: String },
-    HasVoted { msg: String },
-    CantVoteYours { msg: String },
-    CantLikeYours { msg: String },
-    CantEditProposal { msg: String },
-    PermissionError { msg: String },
-    DeadlineExceeded { msg: String },
-    DeadlineNotExceeded { msg: String },
+// convert a principal into a key usable in stable maps
+fn principal_key(principal: &Principal) -> PrincipalKey {
+    PrincipalKey::try_from(principal.as_slice()).expect("principal is at most 29 bytes")
 }
 
-/**
-* -----------------------------------------------------------------------------
-* HELPER FUNCTIONS
-* -----------------------------------------------------------------------------
-*/
-
-// helper method to perform insert.
-fn do_insert_proposal(proposal: &Proposal) {
-    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(proposal.id, proposal.clone()));
+// Check if the caller is part of a DAO, telling a DAO that doesn't exist apart from one the
+// caller isn't a member of
+fn _is_user_part_of_dao(id: &u64) -> Result<Dao, Error> {
+    match _dao_access(id, &caller()) {
+        DaoAccess::Member(dao, role) if role != Role::Observer => Ok(*dao),
+        DaoAccess::Member(..) | DaoAccess::NotMember => Err(_not_a_member_error(id)),
+        DaoAccess::NotFound => Err(_dao_not_found_error(id)),
+    }
 }
 
-// helper method to perform insert.
-fn do_insert_dao(dao: &Dao) {
-    DAO_STORAGE.with(|service| service.borrow_mut().insert(dao.id, dao.clone()));
+// Check if the caller can read a DAO, as a member or an observer. Only queries accept observers,
+// anything changing a DAO checks _is_user_part_of_dao
+fn _can_read_dao(id: &u64) -> Result<Dao, Error> {
+    match _dao_access(id, &caller()) {
+        DaoAccess::Member(dao, _) => Ok(*dao),
+        DaoAccess::NotMember => Err(_not_a_member_error(id)),
+        DaoAccess::NotFound => Err(_dao_not_found_error(id)),
+    }
 }
 
-// helper method to perform insert.
-fn do_insert_comment(comment: &Comment) {
-    COMMENT_STORAGE.with(|service| service.borrow_mut().insert(comment.id, comment.clone()));
+// a helper method to find out how `user` relates to a DAO. Only public DAOs admit to existing to
+// outsiders, a private DAO the user isn't part of is NotFound just like an id that was never used
+fn _dao_access(id: &u64, user: &Principal) -> DaoAccess {
+    match _get_dao(id) {
+        Some(dao) if service::can_read(&dao, user, _membership(*id, user)) => {
+            let role = _get_member_role(&dao, user);
+            DaoAccess::Member(Box::new(dao), role)
+        }
+        Some(_) if _get_settings(id).visibility == DaoVisibility::Public => DaoAccess::NotMember,
+        _ => DaoAccess::NotFound,
+    }
 }
 
-// a helper method to get a proposal by id. used in get_proposal/update_proposal
-fn _get_proposal(id: &u64) -> Option<Proposal> {
-    PROPOSAL_STORAGE.with(|service| service.borrow().get(id))
+fn _dao_not_found_error(id: &u64) -> Error {
+    Error::not_found(
+        ErrorContext::Dao(*id),
+        format!("a dao with id={} not found", id),
+    )
 }
 
-fn _get_dao(id: &u64) -> Option<Dao> {
-    DAO_STORAGE.with(|service| service.borrow().get(id))
+fn _not_a_member_error(id: &u64) -> Error {
+    Error::not_a_member(
+        ErrorContext::Dao(*id),
+        format!("unable to get a dao with id={}. Not a member", id),
+    )
 }
 
-fn _get_comment(id: &u64) -> Option<Comment> {
-    COMMENT_STORAGE.with(|service| service.borrow().get(id))
+// a helper method to get a proposal the caller can read. One in a private DAO the caller isn't
+// part of is reported exactly like an id that doesn't exist
+fn _get_readable_proposal(id: &u64) -> Result<Proposal, Error> {
+    match _get_proposal(id) {
+        Some(proposal) => _check_readable_proposal(&proposal, &caller()).map(|_| proposal),
+        None => Err(_proposal_not_found_error(id)),
+    }
 }
 
-// a helper method to check if a proposal deadline has passed
-fn is_deadline_not_reaached(deadline: u64) -> bool {
-    time() > deadline
+// a helper method to check that `user` can read a proposal that was already loaded, see
+// _get_readable_proposal. Anything acting on a proposal checks this first so its answers can't
+// tell a hidden proposal apart from a missing one
+fn _check_readable_proposal(proposal: &Proposal, user: &Principal) -> Result<(), Error> {
+    if !_can_see_proposal(proposal, user) {
+        return Err(_proposal_not_found_error(&proposal.id));
+    }
+    match _dao_access(&proposal.dao_id, user) {
+        DaoAccess::Member(..) => Ok(()),
+        DaoAccess::NotMember => Err(_not_a_member_error(&proposal.dao_id)),
+        DaoAccess::NotFound => Err(_proposal_not_found_error(&proposal.id)),
+    }
 }
 
-// Check if a user is eligible to vote
-fn _check_if_can_vote(proposal: &Proposal, id: &u64) -> Result<(), Error> {
-    let is_user_part_of_dao: Option<bool> = _is_user_part_of_dao(&id);
-    match is_user_part_of_dao {
-        Some(_is_true) => {
-            if proposal.owner.is_some() && proposal.owner == Some(caller()) {
-                return Err(Error::CantVoteYours {
-                    msg: format!(
-                        "Couldn't vote on a proposal with id={} because you created the proposal",
-                        proposal.id
-                    ),
-                });
-            }
-
-            let has_upvoted = proposal
-                .upvotes
-                .iter()
-                .position(|&user| user.to_string() == caller().to_string());
-            if has_upvoted.is_some() {
-                return Err(Error::HasVoted {
-                    msg: format!(
-                        "Couldn't vote on a proposal with id={}. user voted already",
-                        proposal.id
-                    ),
-                });
-            }
-            let has_downvoted = proposal
-                .downvotes
-                .iter()
-                .position(|&user| user.to_string() == caller().to_string());
-            if has_downvoted.is_some() {
-                return Err(Error::HasVoted {
-                    msg: format!(
-                        "Couldn't vote on a proposal with id={}. user voted already",
-                        proposal.id
-                    ),
-                });
-            }
-
-            if is_deadline_not_reaached(proposal.deadline) {
-                return Err(Error::DeadlineExceeded {
-                    msg: format!(
-                        "Couldn't vote on a proposal with id={}. Deadline exceeded",
-                        proposal.id
-                    ),
-                });
-            }
+fn _proposal_not_found_error(id: &u64) -> Error {
+    Error::not_found(
+        ErrorContext::Proposal(*id),
+        format!("a proposal with id={} not found", id),
+    )
+}
 
-            Ok(())
+#[ic_cdk::init]
+fn init(args: Option<InitArgs>) {
+    if let Some(args) = args {
+        if let Err(error) = _apply_init_args(args) {
+            ic_cdk::trap(&format!("invalid init args: {}", error));
         }
-        None => Err(Error::NotFound {
-            msg: format!("Dao of id={} not found.", id),
-        }),
     }
+    migrations::mark_done();
+    maintenance::schedule();
 }
 
-// Check if a user is part of a DAO
-fn _is_user_part_of_dao(id: &u64) -> Option<bool> {
-    match _get_dao(&id) {
-        Some(dao) => {
-            let is_part = dao.owner == Some(caller()) || dao.members.contains(&caller());
-
-            if is_part {
-                return Some(true);
-            }
-            return None;
+// Bring records written by earlier versions up to the current layout. The config persists across
+// upgrades, args given to the upgrade override parts of it
+#[ic_cdk::post_upgrade]
+fn post_upgrade(args: Option<InitArgs>) {
+    if let Some(args) = args {
+        if let Err(error) = _apply_init_args(args) {
+            ic_cdk::trap(&format!("invalid upgrade args: {}", error));
         }
-        None => return None,
     }
+    certification::rebuild();
+    storage::resume();
+    if migrations::start() {
+        resume_background_work();
+    }
+}
+
+// Restart the work that runs on timers, which upgrades drop. Waits for the storage migration to be
+// done, it works on records in the current layout
+fn resume_background_work() {
+    maintenance::schedule();
+    maintenance::reschedule_openings();
+    maintenance::reschedule_reminders();
+    registry::resume();
+    merge::resume();
+    likes::resume();
 }
 
 // need this to generate candid
@@ -0,0 +1,168 @@
+use crate::env::{clear_timer, set_timer, time};
+use crate::{
+    _get_comment, _get_proposal, notify_count, principal_key, Comment, NotificationKind,
+    PrincipalKey, PENDING_LIKES,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::{BoundedStorable, Storable};
+use std::{borrow::Cow, cell::RefCell, time::Duration};
+
+// How long likes of a comment are gathered before its author is told about them
+const BATCH_WINDOW: u64 = 15 * 60 * 1_000_000_000;
+// Like counts that are worth telling the author about right away, whatever is left of the window
+const MILESTONES: [u64; 3] = [5, 25, 100];
+
+thread_local! {
+    // the timer delivering the next batch and when it fires, there is at most one
+    static TIMER: RefCell<Option<(u64, TimerId)>> = const { RefCell::new(None) };
+}
+
+// Likes a comment received since its author was last told
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct PendingLikes {
+    count: u64,
+    // when the first of them came in, the batch is delivered BATCH_WINDOW later
+    since: u64,
+}
+
+impl Storable for PendingLikes {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for PendingLikes {
+    const MAX_SIZE: u32 = 64;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Count a like of `comment` towards the next notification of its author. Reaching one of the
+// MILESTONES delivers the batch right away
+pub(crate) fn liked(comment: &Comment) {
+    let author = match comment.author {
+        Some(author) => author,
+        None => return,
+    };
+    let key = (comment.id, principal_key(&author));
+    let now = time();
+    let pending = PENDING_LIKES
+        .with(|service| service.borrow().get(&key))
+        .map(|pending| PendingLikes {
+            count: pending.count + 1,
+            since: pending.since,
+        })
+        .unwrap_or(PendingLikes {
+            count: 1,
+            since: now,
+        });
+    PENDING_LIKES.with(|service| service.borrow_mut().insert(key, pending));
+
+    if MILESTONES.contains(&comment.like_count) {
+        deliver(key);
+    } else {
+        schedule(pending.since.saturating_add(BATCH_WINDOW));
+    }
+}
+
+// Take back a like that wasn't delivered yet. Likes that were already delivered stay told
+pub(crate) fn unliked(comment: &Comment) {
+    let author = match comment.author {
+        Some(author) => author,
+        None => return,
+    };
+    let key = (comment.id, principal_key(&author));
+    PENDING_LIKES.with(|service| {
+        let mut service = service.borrow_mut();
+        match service.get(&key) {
+            Some(pending) if pending.count > 1 => {
+                service.insert(
+                    key,
+                    PendingLikes {
+                        count: pending.count - 1,
+                        since: pending.since,
+                    },
+                );
+            }
+            Some(_) => {
+                service.remove(&key);
+            }
+            None => (),
+        }
+    });
+}
+
+// Deliver the batches that are due and arm the timer for the next one. Called after an upgrade,
+// which drops the timers
+pub(crate) fn resume() {
+    TIMER.with(|timer| *timer.borrow_mut() = None);
+    let now = time();
+    let due: Vec<(u64, PrincipalKey)> = PENDING_LIKES.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, pending)| pending.since.saturating_add(BATCH_WINDOW) <= now)
+            .map(|(key, _)| key)
+            .collect()
+    });
+    for key in due {
+        deliver(key);
+    }
+
+    let next = PENDING_LIKES.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, pending)| pending.since.saturating_add(BATCH_WINDOW))
+            .min()
+    });
+    if let Some(next) = next {
+        schedule(next);
+    }
+}
+
+// Notify the author of a comment of its pending likes. A comment that was deleted or removed
+// meanwhile drops them
+fn deliver(key: (u64, PrincipalKey)) {
+    let pending = match PENDING_LIKES.with(|service| service.borrow_mut().remove(&key)) {
+        Some(pending) => pending,
+        None => return,
+    };
+    let comment = match _get_comment(&key.0).filter(|comment| !comment.deleted) {
+        Some(comment) => comment,
+        None => return,
+    };
+    if let Some(proposal) = _get_proposal(&comment.proposal_id) {
+        notify_count(
+            &Principal::from_slice(key.1.as_slice()),
+            NotificationKind::CommentLiked,
+            proposal.dao_id,
+            Some(proposal.id),
+            Some(comment.id),
+            Some(pending.count),
+        );
+    }
+}
+
+// Arm the timer for `at` unless it already fires sooner
+fn schedule(at: u64) {
+    let armed = TIMER.with(|timer| {
+        timer
+            .borrow()
+            .as_ref()
+            .is_some_and(|(fires_at, _)| *fires_at <= at)
+    });
+    if armed {
+        return;
+    }
+
+    let delay = Duration::from_nanos(at.saturating_sub(time()));
+    let timer_id = set_timer(delay, resume);
+    if let Some((_, previous)) = TIMER.with(|timer| timer.borrow_mut().replace((at, timer_id))) {
+        clear_timer(previous);
+    }
+}
@@ -0,0 +1,102 @@
+use crate::env::time;
+use crate::EXECUTION_LOCKS;
+use candid::{Decode, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, Storable};
+use std::borrow::Cow;
+
+// How long a lock has to be held before clear_stuck_execution may remove it. A ledger call that
+// hasn't come back by then was lost along with the callback that would have released the lock
+pub(crate) const STUCK_AFTER: u64 = 60 * 60 * 1_000_000_000;
+
+// What moves money out of a DAO's treasury on behalf of a proposal
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum LockedOperation {
+    Execution,
+    // the milestone at this position
+    MilestoneRelease(u64),
+    DepositRefund,
+}
+
+// A treasury payment of a proposal waiting for the ledger. While it is held no other payment of
+// the proposal can start, so calling twice before the first call returns can't pay twice
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ExecutionLock {
+    operation: LockedOperation,
+    holder: Option<Principal>,
+    locked_at: u64,
+}
+
+impl ExecutionLock {
+    pub(crate) fn locked_at(&self) -> u64 {
+        self.locked_at
+    }
+}
+
+impl Storable for ExecutionLock {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for ExecutionLock {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Releases the lock of its proposal when dropped, so every way out of the endpoint holding it
+// releases it. Only a callback that traps after the ledger call leaves it behind
+pub(crate) struct LockGuard {
+    proposal_id: u64,
+    locked_at: u64,
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        // a lock cleared as stuck may have been taken again since, that one isn't ours to release
+        if get(&self.proposal_id).is_some_and(|lock| lock.locked_at == self.locked_at) {
+            EXECUTION_LOCKS.with(|service| service.borrow_mut().remove(&self.proposal_id));
+        }
+    }
+}
+
+// Lock the treasury payments of a proposal for `operation`. Fails with the lock that is already
+// held, whoever holds it
+pub(crate) fn acquire(
+    proposal_id: u64,
+    operation: LockedOperation,
+    holder: &Principal,
+) -> Result<LockGuard, ExecutionLock> {
+    if let Some(lock) = get(&proposal_id) {
+        return Err(lock);
+    }
+    let locked_at = time();
+    let lock = ExecutionLock {
+        operation,
+        holder: Some(*holder),
+        locked_at,
+    };
+    EXECUTION_LOCKS.with(|service| service.borrow_mut().insert(proposal_id, lock));
+    Ok(LockGuard {
+        proposal_id,
+        locked_at,
+    })
+}
+
+pub(crate) fn get(proposal_id: &u64) -> Option<ExecutionLock> {
+    EXECUTION_LOCKS.with(|service| service.borrow().get(proposal_id))
+}
+
+// Remove a lock that was held for longer than STUCK_AFTER. Returns it, or the lock that is still
+// too young to be cleared as the error. None when the proposal isn't locked
+pub(crate) fn clear_stuck(proposal_id: &u64) -> Option<Result<ExecutionLock, ExecutionLock>> {
+    let lock = get(proposal_id)?;
+    if time().saturating_sub(lock.locked_at) < STUCK_AFTER {
+        return Some(Err(lock));
+    }
+    EXECUTION_LOCKS.with(|service| service.borrow_mut().remove(proposal_id));
+    Some(Ok(lock))
+}
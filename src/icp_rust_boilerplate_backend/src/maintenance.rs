@@ -0,0 +1,1050 @@
+use crate::env::{clear_timer, set_timer, set_timer_interval, time};
+use crate::{
+    _comment_ids, _dao_participants, _dao_proposal_ids, _delete_comment_records,
+    _delete_proposal_records, _get_comment, _get_dao, _get_preferences, _get_proposal, _get_vote,
+    _list_comment, _list_dao_proposal, _recount_votes, _unlist_comment, _unlist_dao_proposal,
+    do_insert_dao, do_insert_proposal, log_event, notify, principal_key,
+};
+use crate::{
+    rate_limit, AuditAction, AuditTarget, Comment, NotificationKind, PrincipalKey, Proposal,
+    ProposalStatus, BALLOT_COMMITMENTS, COMMENT_STORAGE, CONFIG, DAO_PROPOSALS, DAO_STORAGE,
+    INVITE_STORAGE, MAX_NOTIFICATIONS_PER_USER, NOTIFICATIONS_BY_RECIPIENT, NOTIFICATION_STORAGE,
+    PROPOSAL_COMMENTS, PROPOSAL_STORAGE, VOTE_REMINDERS,
+};
+use candid::Principal;
+use ic_cdk_timers::TimerId;
+use std::{cell::RefCell, collections::BTreeMap, ops::Bound, time::Duration};
+
+// How often maintenance runs unless the canister admin configures another interval
+pub(crate) const DEFAULT_INTERVAL_SECONDS: u64 = 60 * 60;
+// Drafts that haven't been published within this long are removed
+pub(crate) const DRAFT_TTL: u64 = 90 * 24 * 60 * 60 * 1_000_000_000;
+// Audit events that aren't about a single DAO are recorded under this dao_id
+pub(crate) const CANISTER_AUDIT_SCOPE: u64 = u64::MAX;
+// How long before the deadline members who haven't voted get reminded
+pub(crate) const REMINDER_LEAD: u64 = 24 * 60 * 60 * 1_000_000_000;
+// Members a single reminder slice goes through, the rest are left to the next slice
+const REMINDER_BATCH_SIZE: u64 = 100;
+// Stored in VOTE_REMINDERS once every member was gone through
+const REMINDER_DONE: u64 = u64::MAX;
+// Records a single repair_integrity call goes through, the rest is left to the next call
+const INTEGRITY_BATCH_SIZE: u64 = 500;
+
+thread_local! {
+    static TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    // pending reminder of each proposal, so a new deadline replaces the old reminder
+    static REMINDER_TIMERS: RefCell<BTreeMap<u64, TimerId>> =
+        const { RefCell::new(BTreeMap::new()) };
+}
+
+// Number of records each step of a maintenance run removed
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub(crate) struct MaintenanceReport {
+    expired_invites: u64,
+    trimmed_notifications: u64,
+    pruned_rate_limits: u64,
+    expired_drafts: u64,
+    orphaned_proposals: u64,
+    orphaned_comments: u64,
+    ran_at: u64,
+}
+
+// Number of votes dedupe_dao found miscounted on the proposals of a DAO
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct DedupeReport {
+    dao_id: u64,
+    // (proposal_id, how far its vote counts were off) of the proposals that were recounted
+    proposals: Vec<(u64, u64)>,
+}
+
+// The map repair_integrity is going through, in the order they are gone through
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum IntegrityStage {
+    Daos,
+    Proposals,
+    Comments,
+}
+
+// Where a repair_integrity call stopped, pass it to the next call to continue from there
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) struct IntegrityCursor {
+    stage: IntegrityStage,
+    // the last id gone through in that stage, None before the first one
+    after: Option<u64>,
+}
+
+// Number of broken references repair_integrity found, and repaired unless it was a dry run
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub(crate) struct IntegrityReport {
+    dry_run: bool,
+    records_checked: u64,
+    // entries in DAO_PROPOSALS of proposals that are gone or belong to another DAO, removed
+    dangling_proposal_refs: u64,
+    // DAOs whose proposal count didn't match the proposals listed for them, recounted
+    miscounted_daos: u64,
+    // entries in PROPOSAL_COMMENTS of comments that are gone or belong to another proposal, removed
+    dangling_comment_refs: u64,
+    // proposals whose vote or comment counts didn't match their votes and listed comments, recounted
+    miscounted_proposals: u64,
+    // proposals whose DAO is gone, deleted along with their comments and votes
+    orphaned_proposals: u64,
+    // comments whose proposal is gone, deleted
+    orphaned_comments: u64,
+    // proposals and comments missing from their parent's list, added back to it
+    unlisted_proposals: u64,
+    unlisted_comments: u64,
+    // where the next call continues, None once every map was gone through
+    next_cursor: Option<IntegrityCursor>,
+}
+
+impl IntegrityReport {
+    fn found_anything(&self) -> bool {
+        self.dangling_proposal_refs
+            + self.miscounted_daos
+            + self.dangling_comment_refs
+            + self.miscounted_proposals
+            + self.orphaned_proposals
+            + self.orphaned_comments
+            + self.unlisted_proposals
+            + self.unlisted_comments
+            > 0
+    }
+}
+
+impl MaintenanceReport {
+    fn removed_anything(&self) -> bool {
+        self.expired_invites
+            + self.trimmed_notifications
+            + self.pruned_rate_limits
+            + self.expired_drafts
+            + self.orphaned_proposals
+            + self.orphaned_comments
+            > 0
+    }
+}
+
+// (Re)register the periodic run with the configured interval. Timers don't survive upgrades,
+// so this has to be called from init and post_upgrade
+pub(crate) fn schedule() {
+    let interval = CONFIG
+        .with(|config| config.borrow().get().maintenance_interval_seconds)
+        .unwrap_or(DEFAULT_INTERVAL_SECONDS);
+
+    TIMER.with(|timer| {
+        if let Some(previous) = timer.borrow_mut().take() {
+            clear_timer(previous);
+        }
+        let id = set_timer_interval(Duration::from_secs(interval), || {
+            run();
+        });
+        *timer.borrow_mut() = Some(id);
+    });
+}
+
+// Open a scheduled proposal for voting at `starts_at`. Like the periodic run these timers don't
+// survive upgrades, post_upgrade registers them again with reschedule_openings
+pub(crate) fn schedule_opening(proposal_id: u64, starts_at: u64) {
+    let delay = starts_at.saturating_sub(time());
+    set_timer(Duration::from_nanos(delay), move || {
+        open_scheduled(proposal_id);
+    });
+}
+
+// Register the opening of every scheduled proposal again. Those whose start passed during the
+// upgrade open right away
+pub(crate) fn reschedule_openings() {
+    let scheduled: Vec<(u64, u64)> = PROPOSAL_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, proposal)| proposal.status == ProposalStatus::Scheduled)
+            .map(|(id, proposal)| (id, proposal.voting_starts_at.unwrap_or_default()))
+            .collect()
+    });
+    scheduled
+        .into_iter()
+        .for_each(|(id, starts_at)| schedule_opening(id, starts_at));
+}
+
+// Remind the members who haven't voted on a proposal a day before its `deadline`, or right away
+// when that is already less than a day off. Scheduling it again replaces the pending reminder
+pub(crate) fn schedule_reminder(proposal_id: u64, deadline: u64) {
+    let delay = deadline
+        .saturating_sub(REMINDER_LEAD)
+        .saturating_sub(time());
+    set_reminder_timer(proposal_id, Duration::from_nanos(delay));
+}
+
+// Register the reminders of open and scheduled proposals again, including those an upgrade
+// interrupted part way through. Reminders already sent aren't repeated
+pub(crate) fn reschedule_reminders() {
+    let now = time();
+    let pending: Vec<(u64, u64)> = PROPOSAL_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, proposal)| {
+                matches!(
+                    proposal.status,
+                    ProposalStatus::Open | ProposalStatus::Scheduled
+                ) && proposal.deadline > now
+            })
+            .map(|(id, proposal)| (id, proposal.deadline))
+            .collect()
+    });
+    pending
+        .into_iter()
+        .filter(|(id, _)| reminder_progress(id) != REMINDER_DONE)
+        .for_each(|(id, deadline)| schedule_reminder(id, deadline));
+}
+
+fn set_reminder_timer(proposal_id: u64, delay: Duration) {
+    REMINDER_TIMERS.with(|timers| {
+        if let Some(previous) = timers.borrow_mut().remove(&proposal_id) {
+            clear_timer(previous);
+        }
+        let id = set_timer(delay, move || {
+            REMINDER_TIMERS.with(|timers| timers.borrow_mut().remove(&proposal_id));
+            send_reminders(proposal_id);
+        });
+        timers.borrow_mut().insert(proposal_id, id);
+    });
+}
+
+fn reminder_progress(proposal_id: &u64) -> u64 {
+    VOTE_REMINDERS
+        .with(|service| service.borrow().get(proposal_id))
+        .unwrap_or_default()
+}
+
+// Notify the next REMINDER_BATCH_SIZE members that can still vote on the proposal, and continue
+// in a new slice while there are more. Nothing is sent once voting ended, the proposal was
+// finalized, cancelled or deleted, or for members that opted out of reminders
+fn send_reminders(proposal_id: u64) {
+    let proposal = match _get_proposal(&proposal_id)
+        .filter(|p| p.status == ProposalStatus::Open && p.deadline > time())
+    {
+        Some(proposal) => proposal,
+        None => return,
+    };
+    let dao = match _get_dao(&proposal.dao_id) {
+        Some(dao) if !dao.archived => dao,
+        _ => return,
+    };
+    let progress = reminder_progress(&proposal_id);
+    if progress == REMINDER_DONE {
+        return;
+    }
+
+    let voters: Vec<Principal> = _dao_participants(&dao);
+    let end = (progress + REMINDER_BATCH_SIZE).min(voters.len() as u64);
+    voters[progress.min(end) as usize..end as usize]
+        .iter()
+        .filter(|voter| proposal.owner != Some(**voter))
+        .filter(|voter| _get_vote(&proposal_id, voter).is_none())
+        .filter(|voter| {
+            !BALLOT_COMMITMENTS.with(|service| {
+                service
+                    .borrow()
+                    .contains_key(&(proposal_id, principal_key(voter)))
+            })
+        })
+        .filter(|voter| _get_preferences(voter).vote_reminders)
+        .for_each(|voter| {
+            notify(
+                voter,
+                NotificationKind::VoteReminder,
+                proposal.dao_id,
+                Some(proposal_id),
+                None,
+            )
+        });
+
+    let more = end < voters.len() as u64;
+    VOTE_REMINDERS.with(|service| {
+        service
+            .borrow_mut()
+            .insert(proposal_id, if more { end } else { REMINDER_DONE })
+    });
+    if more {
+        set_reminder_timer(proposal_id, Duration::ZERO);
+    }
+}
+
+// The owner may have opened the proposal early or deleted it since, then there is nothing to do
+fn open_scheduled(proposal_id: u64) {
+    if let Some(mut proposal) =
+        _get_proposal(&proposal_id).filter(|p| p.status == ProposalStatus::Scheduled)
+    {
+        proposal.status = ProposalStatus::Open;
+        proposal.updated_at = Some(time());
+
+        do_insert_proposal(&mut proposal);
+        log_event(
+            proposal.dao_id,
+            AuditAction::ProposalOpened,
+            AuditTarget::Proposal(proposal_id),
+        );
+    }
+}
+
+pub(crate) fn run() -> MaintenanceReport {
+    let now = time();
+    let report = MaintenanceReport {
+        expired_invites: expire_invites(now),
+        trimmed_notifications: trim_notifications(),
+        pruned_rate_limits: rate_limit::prune(now),
+        expired_drafts: remove_expired_drafts(now),
+        // proposals go first so the comments they leave behind are counted with them
+        orphaned_proposals: remove_orphaned_proposals(),
+        orphaned_comments: remove_orphaned_comments(),
+        ran_at: now,
+    };
+
+    if report.removed_anything() {
+        log_event(
+            CANISTER_AUDIT_SCOPE,
+            AuditAction::MaintenanceRun,
+            AuditTarget::Maintenance(report.clone()),
+        );
+    }
+    report
+}
+
+fn expire_invites(now: u64) -> u64 {
+    let expired: Vec<(u64, PrincipalKey)> = INVITE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, invite)| invite.expires_at < now)
+            .map(|(key, _)| key)
+            .collect()
+    });
+
+    INVITE_STORAGE.with(|service| {
+        expired.iter().for_each(|key| {
+            service.borrow_mut().remove(key);
+        })
+    });
+    expired.len() as u64
+}
+
+// Evict the oldest notifications of users above the cap
+fn trim_notifications() -> u64 {
+    let mut by_recipient: BTreeMap<PrincipalKey, Vec<u64>> = BTreeMap::new();
+    NOTIFICATIONS_BY_RECIPIENT.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .for_each(|((recipient, id), _)| by_recipient.entry(recipient).or_default().push(id))
+    });
+
+    let mut trimmed = 0;
+    for (recipient, ids) in by_recipient {
+        if ids.len() <= MAX_NOTIFICATIONS_PER_USER {
+            continue;
+        }
+        for evicted in &ids[..ids.len() - MAX_NOTIFICATIONS_PER_USER] {
+            NOTIFICATION_STORAGE.with(|service| service.borrow_mut().remove(evicted));
+            NOTIFICATIONS_BY_RECIPIENT
+                .with(|service| service.borrow_mut().remove(&(recipient, *evicted)));
+            trimmed += 1;
+        }
+    }
+    trimmed
+}
+
+fn remove_expired_drafts(now: u64) -> u64 {
+    let expired: Vec<Proposal> = PROPOSAL_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, proposal)| {
+                proposal.status == ProposalStatus::Draft
+                    && now >= proposal.created_at.saturating_add(DRAFT_TTL)
+            })
+            .map(|(_, proposal)| proposal)
+            .collect()
+    });
+
+    expired.iter().for_each(_delete_proposal_records);
+    expired.len() as u64
+}
+
+fn remove_orphaned_proposals() -> u64 {
+    let orphaned: Vec<u64> = PROPOSAL_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, proposal)| _get_dao(&proposal.dao_id).is_none())
+            .map(|(id, _)| id)
+            .collect()
+    });
+
+    for id in orphaned.iter() {
+        if let Some(proposal) = _get_proposal(id) {
+            _delete_proposal_records(&proposal);
+        }
+    }
+    orphaned.len() as u64
+}
+
+fn remove_orphaned_comments() -> u64 {
+    let orphaned: Vec<Comment> = COMMENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, comment)| _get_proposal(&comment.proposal_id).is_none())
+            .map(|(_, comment)| comment)
+            .collect()
+    });
+
+    orphaned.iter().for_each(_delete_comment_records);
+    orphaned.len() as u64
+}
+
+// Count the votes on the proposals of a DAO again from their vote records, which hold one vote per
+// voter, so no one is counted twice. Memberships are keyed by principal and can't repeat either.
+// None if there is no such DAO
+pub(crate) fn dedupe_dao(dao_id: u64) -> Option<DedupeReport> {
+    let dao = _get_dao(&dao_id)?;
+    let mut report = DedupeReport {
+        dao_id,
+        proposals: Vec::new(),
+    };
+
+    for id in _dao_proposal_ids(&dao.id).iter() {
+        if let Some(mut proposal) = _get_proposal(id) {
+            let off = _recount_votes(&mut proposal);
+            if off > 0 {
+                do_insert_proposal(&mut proposal);
+                report.proposals.push((*id, off));
+            }
+        }
+    }
+
+    if !report.proposals.is_empty() {
+        log_event(
+            dao_id,
+            AuditAction::DuplicatesRemoved,
+            AuditTarget::Dao(dao_id),
+        );
+    }
+    Some(report)
+}
+
+// Go through the next INTEGRITY_BATCH_SIZE DAOs, proposals and comments from `cursor`, the start
+// when None, and count the references between them that are broken. Unless `dry_run` is set
+// they are repaired as well
+pub(crate) fn repair_integrity(dry_run: bool, cursor: Option<IntegrityCursor>) -> IntegrityReport {
+    let mut report = IntegrityReport {
+        dry_run,
+        ..Default::default()
+    };
+    let mut cursor = cursor.unwrap_or(IntegrityCursor {
+        stage: IntegrityStage::Daos,
+        after: None,
+    });
+
+    loop {
+        let budget = INTEGRITY_BATCH_SIZE - report.records_checked;
+        let ids = next_ids(cursor.stage, cursor.after, budget);
+        for id in ids.iter() {
+            match cursor.stage {
+                IntegrityStage::Daos => check_dao(*id, &mut report),
+                IntegrityStage::Proposals => check_proposal(*id, &mut report),
+                IntegrityStage::Comments => check_comment(*id, &mut report),
+            }
+        }
+        report.records_checked += ids.len() as u64;
+
+        // a stage that filled the batch may have more left
+        if ids.len() as u64 == budget {
+            cursor.after = ids.last().copied();
+            report.next_cursor = Some(cursor);
+            break;
+        }
+        cursor = match cursor.stage {
+            IntegrityStage::Daos => IntegrityCursor {
+                stage: IntegrityStage::Proposals,
+                after: None,
+            },
+            IntegrityStage::Proposals => IntegrityCursor {
+                stage: IntegrityStage::Comments,
+                after: None,
+            },
+            IntegrityStage::Comments => break,
+        };
+    }
+
+    if !dry_run && report.found_anything() {
+        log_event(
+            CANISTER_AUDIT_SCOPE,
+            AuditAction::IntegrityRepaired,
+            AuditTarget::Integrity(report.clone()),
+        );
+    }
+    report
+}
+
+// The next ids of a stage's map after `after`, at most `limit`
+fn next_ids(stage: IntegrityStage, after: Option<u64>, limit: u64) -> Vec<u64> {
+    let start = match after {
+        Some(id) => Bound::Excluded(id),
+        None => Bound::Unbounded,
+    };
+    let range = (start, Bound::Unbounded);
+    match stage {
+        IntegrityStage::Daos => DAO_STORAGE.with(|service| {
+            service
+                .borrow()
+                .range(range)
+                .take(limit as usize)
+                .map(|(id, _)| id)
+                .collect()
+        }),
+        IntegrityStage::Proposals => PROPOSAL_STORAGE.with(|service| {
+            service
+                .borrow()
+                .range(range)
+                .take(limit as usize)
+                .map(|(id, _)| id)
+                .collect()
+        }),
+        IntegrityStage::Comments => COMMENT_STORAGE.with(|service| {
+            service
+                .borrow()
+                .range(range)
+                .take(limit as usize)
+                .map(|(id, _)| id)
+                .collect()
+        }),
+    }
+}
+
+fn check_dao(dao_id: u64, report: &mut IntegrityReport) {
+    let mut dao = match _get_dao(&dao_id) {
+        Some(dao) => dao,
+        None => return,
+    };
+    let (listed, dangling): (Vec<u64>, Vec<u64>) =
+        _dao_proposal_ids(&dao_id).into_iter().partition(|id| {
+            _get_proposal(id)
+                .map(|proposal| proposal.dao_id == dao_id)
+                .unwrap_or_default()
+        });
+    let miscounted = dao.proposal_count != listed.len() as u64;
+
+    report.dangling_proposal_refs += dangling.len() as u64;
+    report.miscounted_daos += miscounted as u64;
+    if !report.dry_run && (!dangling.is_empty() || miscounted) {
+        for id in dangling {
+            _unlist_dao_proposal(&mut dao, id);
+        }
+        dao.proposal_count = listed.len() as u64;
+        do_insert_dao(&mut dao);
+    }
+}
+
+fn check_proposal(proposal_id: u64, report: &mut IntegrityReport) {
+    let mut proposal = match _get_proposal(&proposal_id) {
+        Some(proposal) => proposal,
+        None => return,
+    };
+    match _get_dao(&proposal.dao_id) {
+        None => {
+            report.orphaned_proposals += 1;
+            if !report.dry_run {
+                _delete_proposal_records(&proposal);
+            }
+            return;
+        }
+        Some(mut dao)
+            if !DAO_PROPOSALS
+                .with(|service| service.borrow().contains_key(&(dao.id, proposal_id))) =>
+        {
+            report.unlisted_proposals += 1;
+            if !report.dry_run {
+                _list_dao_proposal(&mut dao, proposal_id);
+                do_insert_dao(&mut dao);
+            }
+        }
+        Some(_) => (),
+    }
+
+    let (listed, dangling): (Vec<u64>, Vec<u64>) =
+        _comment_ids(&proposal_id).into_iter().partition(|id| {
+            _get_comment(id)
+                .map(|comment| comment.proposal_id == proposal_id)
+                .unwrap_or_default()
+        });
+    let votes_off = _recount_votes(&mut proposal) > 0;
+    let miscounted = votes_off || proposal.comment_count != listed.len() as u64;
+
+    report.dangling_comment_refs += dangling.len() as u64;
+    report.miscounted_proposals += miscounted as u64;
+    if !report.dry_run && (!dangling.is_empty() || miscounted) {
+        for id in dangling {
+            _unlist_comment(&mut proposal, id);
+        }
+        proposal.comment_count = listed.len() as u64;
+        do_insert_proposal(&mut proposal);
+    }
+}
+
+fn check_comment(comment_id: u64, report: &mut IntegrityReport) {
+    let comment = match _get_comment(&comment_id) {
+        Some(comment) => comment,
+        None => return,
+    };
+    match _get_proposal(&comment.proposal_id) {
+        None => {
+            report.orphaned_comments += 1;
+            if !report.dry_run {
+                _delete_comment_records(&comment);
+            }
+        }
+        Some(mut proposal)
+            if !PROPOSAL_COMMENTS
+                .with(|service| service.borrow().contains_key(&(proposal.id, comment_id))) =>
+        {
+            report.unlisted_comments += 1;
+            if !report.dry_run {
+                _list_comment(&mut proposal, comment_id);
+                do_insert_proposal(&mut proposal);
+            }
+        }
+        Some(_) => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{set_caller, set_time};
+    use crate::{
+        _record_vote, _set_membership, do_insert_comment, get_my_notifications, set_my_preferences,
+        AuditEvent, Dao, Error, Invite, Membership, Notification, UserPreferences, Vote,
+        VoteDirection, AUDIT_STORAGE, MAX_PAGE_SIZE,
+    };
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    // proposal 10 of DAO 1 upvoted by user 2, with comment 11. Its counts are then spoiled
+    fn miscounted_proposal() {
+        let mut dao = Dao {
+            id: 1,
+            owner: Some(user(1)),
+            ..Default::default()
+        };
+        let mut proposal = Proposal {
+            id: 10,
+            dao_id: 1,
+            ..Default::default()
+        };
+        _list_dao_proposal(&mut dao, 10);
+        do_insert_dao(&mut dao);
+        _record_vote(
+            &mut proposal,
+            &Vote {
+                proposal_id: 10,
+                voter: Some(user(2)),
+                direction: VoteDirection::Up,
+                ..Default::default()
+            },
+        );
+        do_insert_comment(&Comment {
+            id: 11,
+            proposal_id: 10,
+            ..Default::default()
+        });
+        _list_comment(&mut proposal, 11);
+        // a comment listed for the proposal that isn't there
+        PROPOSAL_COMMENTS.with(|service| service.borrow_mut().insert((10, 999), ()));
+        proposal.upvote_count = 5;
+        proposal.comment_count = 0;
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(10, proposal));
+    }
+
+    #[test]
+    fn integrity_repair_recounts_votes_and_comments() {
+        miscounted_proposal();
+
+        let report = repair_integrity(true, None);
+        assert_eq!(
+            (report.miscounted_proposals, report.dangling_comment_refs),
+            (1, 1)
+        );
+        assert_eq!(_get_proposal(&10).unwrap().upvote_count, 5);
+
+        let report = repair_integrity(false, None);
+        assert_eq!(
+            (report.miscounted_proposals, report.dangling_comment_refs),
+            (1, 1)
+        );
+        let proposal = _get_proposal(&10).unwrap();
+        assert_eq!((proposal.upvote_count, proposal.comment_count), (1, 1));
+        assert_eq!(_comment_ids(&10), vec![11]);
+        assert!(!repair_integrity(false, None).found_anything());
+    }
+
+    #[test]
+    fn dedupe_recounts_the_votes_of_a_dao() {
+        miscounted_proposal();
+
+        let report = dedupe_dao(1).unwrap();
+        assert!(report.proposals == vec![(10, 4)]);
+        assert_eq!(_get_proposal(&10).unwrap().upvote_count, 1);
+        assert!(dedupe_dao(1).unwrap().proposals.is_empty());
+        assert!(dedupe_dao(2).is_none());
+    }
+
+    #[test]
+    fn integrity_repair_fixes_dangling_orphaned_and_unlisted_records() {
+        let mut dao = Dao {
+            id: 2,
+            ..Default::default()
+        };
+        // a proposal listed for the DAO that isn't there
+        _list_dao_proposal(&mut dao, 50);
+        do_insert_dao(&mut dao);
+        // a proposal of a DAO that is gone, one missing from its DAO's list and a comment missing
+        // from its proposal's list
+        let of_a_gone_dao = Proposal {
+            dao_id: 3,
+            ..Default::default()
+        };
+        for (id, proposal) in [
+            (60, of_a_gone_dao),
+            (
+                80,
+                Proposal {
+                    dao_id: 2,
+                    ..Default::default()
+                },
+            ),
+        ] {
+            PROPOSAL_STORAGE
+                .with(|service| service.borrow_mut().insert(id, Proposal { id, ..proposal }));
+        }
+        for (id, proposal_id) in [(70, 999), (81, 80)] {
+            do_insert_comment(&Comment {
+                id,
+                proposal_id,
+                ..Default::default()
+            });
+        }
+        let counts = |report: &IntegrityReport| {
+            [
+                report.dangling_proposal_refs,
+                report.miscounted_daos,
+                report.orphaned_proposals,
+                report.unlisted_proposals,
+                report.orphaned_comments,
+                report.unlisted_comments,
+            ]
+        };
+
+        let report = repair_integrity(true, None);
+        assert_eq!(counts(&report), [1, 1, 1, 1, 1, 1]);
+        assert_eq!(report.records_checked, 5);
+        assert!(report.next_cursor.is_none());
+        // a dry run leaves everything as it was
+        assert!(_get_proposal(&60).is_some() && _get_comment(&70).is_some());
+        assert_eq!(_dao_proposal_ids(&2), vec![50]);
+        assert!(last_audit_event().is_none());
+
+        assert_eq!(counts(&repair_integrity(false, None)), [1, 1, 1, 1, 1, 1]);
+        assert!(_get_proposal(&60).is_none() && _get_comment(&70).is_none());
+        assert_eq!(_dao_proposal_ids(&2), vec![80]);
+        assert_eq!(_get_dao(&2).unwrap().proposal_count, 1);
+        assert_eq!(_comment_ids(&80), vec![81]);
+        assert!(
+            last_audit_event().is_some_and(|event| event.action == AuditAction::IntegrityRepaired)
+        );
+        assert!(!repair_integrity(false, None).found_anything());
+    }
+
+    #[test]
+    fn integrity_repair_continues_where_the_last_batch_stopped() {
+        for id in 1..=INTEGRITY_BATCH_SIZE + 1 {
+            DAO_STORAGE.with(|service| {
+                service.borrow_mut().insert(
+                    id,
+                    Dao {
+                        id,
+                        ..Default::default()
+                    },
+                )
+            });
+        }
+        let first = repair_integrity(true, None);
+        assert_eq!(first.records_checked, INTEGRITY_BATCH_SIZE);
+        assert!(
+            first.next_cursor
+                == Some(IntegrityCursor {
+                    stage: IntegrityStage::Daos,
+                    after: Some(INTEGRITY_BATCH_SIZE),
+                })
+        );
+        let second = repair_integrity(true, first.next_cursor);
+        assert_eq!(second.records_checked, 1);
+        assert!(second.next_cursor.is_none());
+
+        // only the admin can run it
+        set_caller(user(2));
+        assert!(matches!(
+            crate::repair_integrity(true, None),
+            Err(Error::Unauthorized { .. })
+        ));
+        make_admin(user(2));
+        assert!(crate::repair_integrity(true, None).is_ok());
+    }
+
+    fn make_admin(admin: Principal) {
+        let mut config = CONFIG.with(|config| config.borrow().get().clone());
+        config.admin = Some(admin);
+        CONFIG
+            .with(|cell| cell.borrow_mut().set(config))
+            .expect("cannot update the config");
+    }
+
+    fn last_audit_event() -> Option<AuditEvent> {
+        AUDIT_STORAGE.with(|service| service.borrow().iter().last().map(|(_, event)| event))
+    }
+
+    fn proposal(id: u64, dao_id: u64, status: ProposalStatus) -> Proposal {
+        let proposal = Proposal {
+            id,
+            dao_id,
+            status,
+            ..Default::default()
+        };
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal.clone()));
+        proposal
+    }
+
+    #[test]
+    fn maintenance_removes_what_expired_or_lost_its_parent() {
+        let now = DRAFT_TTL + 1;
+        let mut dao = Dao {
+            id: 1,
+            ..Default::default()
+        };
+        do_insert_dao(&mut dao);
+        for (invitee, expires_at) in [(user(2), 10), (user(3), u64::MAX)] {
+            let invite = Invite {
+                dao_id: 1,
+                invitee: Some(invitee),
+                expires_at,
+                ..Default::default()
+            };
+            INVITE_STORAGE.with(|service| {
+                service
+                    .borrow_mut()
+                    .insert((1, principal_key(&invitee)), invite)
+            });
+        }
+        // notifications from before the cap was enforced on insert
+        for id in 0..MAX_NOTIFICATIONS_PER_USER as u64 + 3 {
+            let notification = Notification {
+                id: 1_000 + id,
+                recipient: Some(user(2)),
+                ..Default::default()
+            };
+            NOTIFICATION_STORAGE
+                .with(|service| service.borrow_mut().insert(1_000 + id, notification));
+            NOTIFICATIONS_BY_RECIPIENT.with(|service| {
+                service
+                    .borrow_mut()
+                    .insert((principal_key(&user(2)), 1_000 + id), ())
+            });
+        }
+        let limit = rate_limit::MAX_PROPOSALS_PER_WINDOW;
+        assert!(
+            rate_limit::check_and_record(&user(2), 1, limit, rate_limit::PROPOSAL_WINDOW, 0)
+                .is_ok()
+        );
+        assert!(
+            rate_limit::check_and_record(&user(3), 1, limit, rate_limit::PROPOSAL_WINDOW, now)
+                .is_ok()
+        );
+        proposal(20, 1, ProposalStatus::Draft);
+        proposal(21, 1, ProposalStatus::Open);
+        // proposal 30 of a DAO that is gone with its comment 31, and comment 40 of a missing proposal
+        let mut orphan = proposal(30, 7, ProposalStatus::Open);
+        for (id, proposal_id) in [(31, 30), (40, 99)] {
+            do_insert_comment(&Comment {
+                id,
+                proposal_id,
+                ..Default::default()
+            });
+        }
+        _list_comment(&mut orphan, 31);
+
+        make_admin(user(1));
+        set_caller(user(2));
+        assert!(matches!(
+            crate::run_maintenance(),
+            Err(Error::Unauthorized { .. })
+        ));
+        set_caller(user(1));
+        set_time(now);
+        let report = crate::run_maintenance().ok().unwrap();
+        assert_eq!(
+            (
+                report.expired_invites,
+                report.trimmed_notifications,
+                report.pruned_rate_limits,
+                report.expired_drafts,
+                report.orphaned_proposals,
+                report.orphaned_comments
+            ),
+            (1, 3, 1, 1, 1, 1)
+        );
+        assert_eq!(INVITE_STORAGE.with(|service| service.borrow().len()), 1);
+        assert!(NOTIFICATION_STORAGE.with(|service| service.borrow().get(&1_002).is_none()));
+        assert!(NOTIFICATION_STORAGE.with(|service| service.borrow().get(&1_003).is_some()));
+        assert!(_get_proposal(&20).is_none() && _get_proposal(&30).is_none());
+        assert!(_get_proposal(&21).is_some());
+        assert!(_get_comment(&31).is_none() && _get_comment(&40).is_none());
+        let event = last_audit_event().unwrap();
+        assert!(
+            event.dao_id == CANISTER_AUDIT_SCOPE && event.action == AuditAction::MaintenanceRun
+        );
+        assert!(matches!(event.target, AuditTarget::Maintenance(logged) if logged == report));
+
+        // nothing left to remove, nothing logged
+        let report = crate::run_maintenance().ok().unwrap();
+        assert!(!report.removed_anything());
+        assert_eq!(last_audit_event().unwrap().id, event.id);
+    }
+
+    #[test]
+    fn only_the_admin_sets_a_nonzero_interval() {
+        make_admin(user(1));
+        set_caller(user(2));
+        assert!(matches!(
+            crate::set_maintenance_interval(60),
+            Err(Error::Unauthorized { .. })
+        ));
+        set_caller(user(1));
+        assert!(matches!(
+            crate::set_maintenance_interval(0),
+            Err(Error::InvalidInput { .. })
+        ));
+        assert!(crate::set_maintenance_interval(60).is_ok());
+        assert_eq!(
+            CONFIG.with(|config| config.borrow().get().maintenance_interval_seconds),
+            Some(60)
+        );
+    }
+
+    #[test]
+    fn scheduled_proposals_open_when_their_timer_fires() {
+        let scheduled = Proposal {
+            voting_starts_at: Some(5),
+            deadline: 5 + 1_000,
+            ..proposal(10, 1, ProposalStatus::Scheduled)
+        };
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(10, scheduled));
+        proposal(11, 1, ProposalStatus::Open);
+
+        // what the timer set by schedule_opening runs
+        set_time(5);
+        open_scheduled(10);
+        open_scheduled(11);
+        let opened = _get_proposal(&10).unwrap();
+        assert!(opened.status == ProposalStatus::Open && opened.deadline == 1_005);
+        let event = last_audit_event().unwrap();
+        assert!(event.action == AuditAction::ProposalOpened);
+        assert!(matches!(event.target, AuditTarget::Proposal(10)));
+
+        // opened early by the owner, the timer has nothing left to do
+        open_scheduled(10);
+        assert_eq!(last_audit_event().unwrap().id, event.id);
+    }
+
+    fn reminders_of(recipient: Principal) -> usize {
+        set_caller(recipient);
+        get_my_notifications(false, 0, MAX_PAGE_SIZE)
+            .iter()
+            .filter(|notification| notification.kind == NotificationKind::VoteReminder)
+            .count()
+    }
+
+    #[test]
+    fn reminders_reach_members_who_havent_voted_in_slices() {
+        // more members than fit in one slice
+        let mut dao = Dao {
+            id: 1,
+            owner: Some(user(1)),
+            ..Default::default()
+        };
+        for n in 2..=160 {
+            _set_membership(&mut dao, &user(n), Some(Membership::Member));
+        }
+        do_insert_dao(&mut dao);
+        let deadline = 10 * REMINDER_LEAD;
+        let mut open = Proposal {
+            owner: Some(user(1)),
+            deadline,
+            ..proposal(10, 1, ProposalStatus::Open)
+        };
+        _record_vote(
+            &mut open,
+            &Vote {
+                proposal_id: 10,
+                voter: Some(user(2)),
+                direction: VoteDirection::Up,
+                voted_at: 0,
+                weight: None,
+                ranking: None,
+            },
+        );
+        do_insert_proposal(&mut open);
+        let approved = Proposal {
+            deadline,
+            ..proposal(11, 1, ProposalStatus::Approved)
+        };
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(11, approved));
+        set_caller(user(3));
+        set_my_preferences(UserPreferences {
+            vote_reminders: false,
+            ..Default::default()
+        });
+
+        send_reminders(10);
+        assert_eq!(reminder_progress(&10), REMINDER_BATCH_SIZE);
+        send_reminders(10);
+        assert_eq!(reminder_progress(&10), REMINDER_DONE);
+        // slices that run again after the last one send nothing twice
+        send_reminders(10);
+        send_reminders(11);
+        assert_eq!(reminder_progress(&11), 0);
+
+        for (member, reminders) in [
+            (user(1), 0),
+            (user(2), 0),
+            (user(3), 0),
+            (user(4), 1),
+            (user(160), 1),
+        ] {
+            assert_eq!(reminders_of(member), reminders);
+        }
+
+        // upgrades drop the timers and register the reminders that weren't sent yet, only those
+        let pending = Proposal {
+            deadline,
+            ..proposal(12, 1, ProposalStatus::Open)
+        };
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(12, pending));
+        REMINDER_TIMERS.with(|timers| timers.borrow_mut().clear());
+        reschedule_reminders();
+        let registered: Vec<u64> =
+            REMINDER_TIMERS.with(|timers| timers.borrow().keys().copied().collect());
+        assert_eq!(registered, vec![12]);
+    }
+}
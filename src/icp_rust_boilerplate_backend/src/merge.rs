@@ -0,0 +1,638 @@
+use crate::env::{canister_id, set_timer, time};
+use crate::{
+    _admit_member, _assign_proposal_slug, _check_not_archived, _clear_proposal_number,
+    _clear_proposal_slugs, _dao_participants, _dao_proposal_ids, _dequeue_proposal, _get_dao,
+    _get_proposal, _get_settings, _index_proposal_number, _is_banned, _is_dao_admin, _is_member,
+    _list_dao_proposal, _next_proposal_number, _save_settings, _unlist_dao_proposal, caller,
+    do_insert_dao, do_insert_proposal, ledger, log_event, notify, registry, treasury,
+};
+use crate::{
+    AuditAction, AuditTarget, CancelReason, Dao, DepositStatus, Error, ErrorContext,
+    NotificationKind, ProposalStatus, ID_COUNTER, MERGE_STORAGE,
+};
+use candid::{Decode, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, Storable};
+use std::{borrow::Cow, time::Duration};
+
+// Members and proposals a single merge step goes through, the rest are left to the next step so
+// that no step runs into the instruction limit however large the source is
+const MEMBER_BATCH_SIZE: usize = 200;
+const PROPOSAL_BATCH_SIZE: usize = 50;
+// How long a merge waits before moving the treasury again when the ledger failed
+const TREASURY_RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum MergeStatus {
+    // waiting for the owner of the target to accept it
+    Proposed,
+    // accepted, the owner and members of the source are being added to the target
+    AddingMembers,
+    // open proposals of the source are being cancelled and every proposal moved to the target
+    MovingProposals,
+    // what is in the source's treasury is being transferred to the target's
+    MovingTreasury,
+    Completed,
+    // called off by either owner before it was accepted
+    Cancelled,
+}
+
+// A merge of one DAO into another. Once accepted it is carried out in steps, and this record is
+// what the next step, or the first one after an upgrade, continues from
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DaoMerge {
+    id: u64,
+    source_dao_id: u64,
+    target_dao_id: u64,
+    proposed_by: Principal,
+    proposed_at: u64,
+    accepted_at: Option<u64>,
+    completed_at: Option<u64>,
+    status: MergeStatus,
+    // participants of the source gone through so far, added to the target unless they already
+    // were members or are banned there
+    members_checked: u64,
+    members_added: u64,
+    proposals_moved: u64,
+    // proposals still open in the source, moved to the target cancelled
+    proposals_cancelled: u64,
+    // transferred to the target's treasury. None before that step and when no ledger is configured
+    treasury_moved: Option<u64>,
+    // why the last transfer of the treasury failed, it is tried again after TREASURY_RETRY_DELAY
+    last_error: Option<Error>,
+}
+
+impl DaoMerge {
+    fn is_active(&self) -> bool {
+        !matches!(self.status, MergeStatus::Completed | MergeStatus::Cancelled)
+    }
+
+    fn involves(&self, dao_id: u64) -> bool {
+        self.source_dao_id == dao_id || self.target_dao_id == dao_id
+    }
+}
+
+impl Storable for DaoMerge {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for DaoMerge {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Propose merging `source_dao_id`, which the caller owns, into `target_dao_id`. Nothing changes
+// until the owner of the target accepts it
+pub(crate) fn propose(source_dao_id: u64, target_dao_id: u64) -> Result<DaoMerge, Error> {
+    let source = get_dao(source_dao_id)?;
+    let target = get_dao(target_dao_id)?;
+    if !crate::_is_owner(&source.owner, &caller()) {
+        return Err(Error::permission_error(
+            ErrorContext::Dao(source.id),
+            format!(
+                "Couldn't merge dao with id={}. You are not the owner",
+                source.id
+            ),
+        ));
+    }
+    if source.id == target.id {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(source.id),
+            "a dao can't be merged into itself".to_string(),
+        ));
+    }
+    _check_not_archived(&source)?;
+    _check_not_archived(&target)?;
+    check_not_merging(source.id)?;
+    check_not_merging(target.id)?;
+
+    let merge = DaoMerge {
+        id: next_id(),
+        source_dao_id: source.id,
+        target_dao_id: target.id,
+        proposed_by: caller(),
+        proposed_at: time(),
+        accepted_at: None,
+        completed_at: None,
+        status: MergeStatus::Proposed,
+        members_checked: 0,
+        members_added: 0,
+        proposals_moved: 0,
+        proposals_cancelled: 0,
+        treasury_moved: None,
+        last_error: None,
+    };
+    save(&merge);
+    log_both(&merge, AuditAction::MergeProposed);
+    if let Some(owner) = target.owner {
+        notify(
+            &owner,
+            NotificationKind::MergeProposed,
+            target.id,
+            None,
+            None,
+        );
+    }
+    Ok(merge)
+}
+
+// Accept a proposed merge as the owner of its target. The source is archived right away and
+// points at the target from then on, the rest happens in steps run by timers
+pub(crate) fn accept(merge_id: u64) -> Result<DaoMerge, Error> {
+    let mut merge = get_merge(merge_id)?;
+    let mut source = get_dao(merge.source_dao_id)?;
+    let target = get_dao(merge.target_dao_id)?;
+    if !crate::_is_owner(&target.owner, &caller()) {
+        return Err(Error::permission_error(
+            ErrorContext::Dao(target.id),
+            format!(
+                "Couldn't accept merge with id={}. You are not the owner of dao with id={}",
+                merge_id, target.id
+            ),
+        ));
+    }
+    if merge.status != MergeStatus::Proposed {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(target.id),
+            format!(
+                "Couldn't accept merge with id={}. It isn't waiting to be accepted",
+                merge_id
+            ),
+        ));
+    }
+    _check_not_archived(&source)?;
+    _check_not_archived(&target)?;
+
+    source.archived = true;
+    let mut settings = _get_settings(&source.id);
+    settings.merged_into = Some(target.id);
+    _save_settings(&mut source, settings);
+    log_event(
+        source.id,
+        AuditAction::DaoArchived,
+        AuditTarget::Dao(source.id),
+    );
+    registry::announce(&source, registry::RegistryEvent::Archived);
+
+    merge.status = MergeStatus::AddingMembers;
+    merge.accepted_at = Some(time());
+    save(&merge);
+    log_both(&merge, AuditAction::MergeAccepted);
+    schedule(merge.id, Duration::ZERO);
+    Ok(merge)
+}
+
+// Call off a merge that wasn't accepted yet, as the owner of either DAO
+pub(crate) fn cancel(merge_id: u64) -> Result<DaoMerge, Error> {
+    let mut merge = get_merge(merge_id)?;
+    let is_owner = [merge.source_dao_id, merge.target_dao_id]
+        .iter()
+        .filter_map(_get_dao)
+        .any(|dao| crate::_is_owner(&dao.owner, &caller()));
+    if !is_owner {
+        return Err(Error::permission_error(
+            ErrorContext::Dao(merge.source_dao_id),
+            format!(
+                "Couldn't cancel merge with id={}. You don't own either dao",
+                merge_id
+            ),
+        ));
+    }
+    if merge.status != MergeStatus::Proposed {
+        return Err(Error::invalid_input(
+            ErrorContext::Dao(merge.source_dao_id),
+            format!(
+                "Couldn't cancel merge with id={}. It was already accepted or called off",
+                merge_id
+            ),
+        ));
+    }
+
+    merge.status = MergeStatus::Cancelled;
+    save(&merge);
+    Ok(merge)
+}
+
+// Every merge `dao_id` took part in, as source or target, oldest first
+pub(crate) fn list(dao_id: u64) -> Result<Vec<DaoMerge>, Error> {
+    let dao = get_dao(dao_id)?;
+    if !_is_dao_admin(&dao, &caller()) {
+        return Err(Error::permission_error(
+            ErrorContext::Dao(dao_id),
+            format!(
+                "Couldn't list the merges of dao with id={}. You are not an admin",
+                dao_id
+            ),
+        ));
+    }
+    Ok(MERGE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, merge)| merge)
+            .filter(|merge| merge.involves(dao_id))
+            .collect()
+    }))
+}
+
+// Refuse to change what a merge that is proposed or running depends on, e.g. deleting either DAO
+pub(crate) fn check_not_merging(dao_id: u64) -> Result<(), Error> {
+    let active = MERGE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .find(|(_, merge)| merge.is_active() && merge.involves(dao_id))
+            .map(|(id, _)| id)
+    });
+    match active {
+        Some(merge_id) => Err(Error::invalid_input(
+            ErrorContext::Dao(dao_id),
+            format!(
+                "dao with id={} is part of merge with id={}, wait for it to complete or cancel it",
+                dao_id, merge_id
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+// Continue the merges an upgrade interrupted. Timers don't survive upgrades, so this has to be
+// called from post_upgrade
+pub(crate) fn resume() {
+    let running: Vec<u64> = MERGE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, merge)| merge.is_active() && merge.status != MergeStatus::Proposed)
+            .map(|(id, _)| id)
+            .collect()
+    });
+    running
+        .into_iter()
+        .for_each(|id| schedule(id, Duration::ZERO));
+}
+
+fn schedule(merge_id: u64, delay: Duration) {
+    set_timer(delay, move || ic_cdk::spawn(step(merge_id)));
+}
+
+// Carry out the next batch of a merge and schedule the one after it
+async fn step(merge_id: u64) {
+    let mut merge = match MERGE_STORAGE.with(|service| service.borrow().get(&merge_id)) {
+        Some(merge) if merge.is_active() && merge.status != MergeStatus::Proposed => merge,
+        _ => return,
+    };
+    let mut delay = Duration::ZERO;
+    match merge.status {
+        MergeStatus::AddingMembers => add_members(&mut merge),
+        MergeStatus::MovingProposals => move_proposals(&mut merge),
+        MergeStatus::MovingTreasury => {
+            if !move_treasury(&mut merge).await {
+                delay = TREASURY_RETRY_DELAY;
+            }
+        }
+        MergeStatus::Proposed | MergeStatus::Completed | MergeStatus::Cancelled => {}
+    }
+
+    if merge.status == MergeStatus::Completed {
+        merge.completed_at = Some(time());
+        log_both(&merge, AuditAction::MergeCompleted);
+        if let Some(target) = _get_dao(&merge.target_dao_id) {
+            registry::announce(&target, registry::RegistryEvent::Updated);
+        }
+    }
+    save(&merge);
+    if merge.is_active() {
+        schedule(merge_id, delay);
+    }
+}
+
+// Add the next batch of the source's owner and members to the target
+fn add_members(merge: &mut DaoMerge) {
+    let (source, mut target) = match daos(merge) {
+        Some(daos) => daos,
+        None => return,
+    };
+    let participants = _dao_participants(&source);
+    let batch: Vec<Principal> = participants
+        .iter()
+        .skip(merge.members_checked as usize)
+        .take(MEMBER_BATCH_SIZE)
+        .cloned()
+        .collect();
+
+    let mut added = Vec::new();
+    for member in batch.iter() {
+        if !_is_member(&target, member) && !_is_banned(target.id, member) {
+            _admit_member(&mut target, member);
+            added.push(*member);
+        }
+    }
+    if !added.is_empty() {
+        do_insert_dao(&mut target);
+        added
+            .iter()
+            .for_each(|member| notify(member, NotificationKind::AddedToDao, target.id, None, None));
+    }
+
+    merge.members_checked += batch.len() as u64;
+    merge.members_added += added.len() as u64;
+    if merge.members_checked >= participants.len() as u64 {
+        merge.status = MergeStatus::MovingProposals;
+    }
+}
+
+// Move the next batch of the source's proposals, along with their comments and votes, to the
+// target. Proposals still open are cancelled first, those already finalized keep their outcome
+fn move_proposals(merge: &mut DaoMerge) {
+    let (mut source, mut target) = match daos(merge) {
+        Some(daos) => daos,
+        None => return,
+    };
+    let batch: Vec<u64> = _dao_proposal_ids(&source.id)
+        .into_iter()
+        .take(PROPOSAL_BATCH_SIZE)
+        .collect();
+
+    for id in batch.iter() {
+        _unlist_dao_proposal(&mut source, *id);
+        let mut proposal = match _get_proposal(id).filter(|proposal| proposal.dao_id == source.id) {
+            Some(proposal) => proposal,
+            None => continue,
+        };
+        if matches!(
+            proposal.status,
+            ProposalStatus::Open
+                | ProposalStatus::Scheduled
+                | ProposalStatus::Blocked
+                | ProposalStatus::Queued
+        ) {
+            proposal.status = ProposalStatus::Cancelled;
+            proposal.cancel_reason = Some(CancelReason::Merge {
+                target_dao_id: target.id,
+            });
+            // the proposer never got to reach quorum, so their deposit is owed back. It is paid
+            // out of the target's treasury, which the source's is moved into
+            if let Some(deposit) = proposal
+                .deposit
+                .as_mut()
+                .filter(|deposit| deposit.status == DepositStatus::Held)
+            {
+                deposit.status = DepositStatus::RefundPending;
+            }
+            log_event(
+                source.id,
+                AuditAction::ProposalCancelled,
+                AuditTarget::Proposal(*id),
+            );
+            merge.proposals_cancelled += 1;
+        }
+
+        // comments and votes point at the proposal, only its slug and number are kept per DAO
+        _clear_proposal_slugs(&proposal);
+        _clear_proposal_number(&proposal);
+        _dequeue_proposal(&proposal);
+        proposal.dao_id = target.id;
+        _assign_proposal_slug(&mut proposal);
+        proposal.proposal_number = _next_proposal_number(&mut target);
+        _index_proposal_number(&proposal);
+        proposal.updated_at = Some(time());
+        do_insert_proposal(&mut proposal);
+        _list_dao_proposal(&mut target, *id);
+        merge.proposals_moved += 1;
+    }
+
+    do_insert_dao(&mut source);
+    do_insert_dao(&mut target);
+    if batch.len() < PROPOSAL_BATCH_SIZE {
+        merge.status = MergeStatus::MovingTreasury;
+    }
+}
+
+// Transfer the source's treasury to the target's and complete the merge. False when the ledger
+// failed, the transfer is then tried again later
+async fn move_treasury(merge: &mut DaoMerge) -> bool {
+    if !ledger::is_configured() {
+        merge.status = MergeStatus::Completed;
+        return true;
+    }
+    match ledger::transfer_treasury(
+        ErrorContext::Dao(merge.source_dao_id),
+        merge.source_dao_id,
+        merge.target_dao_id,
+    )
+    .await
+    {
+        Ok(moved) => {
+            merge.treasury_moved = Some(moved.as_ref().map_or(0, |(amount, _)| *amount));
+            if let Some((amount, block_index)) = moved {
+                let canister = Some(canister_id());
+                treasury::record(
+                    merge.source_dao_id,
+                    treasury::TreasuryTxKind::MergeOut,
+                    amount,
+                    canister,
+                    None,
+                    Some(block_index.clone()),
+                );
+                treasury::record(
+                    merge.target_dao_id,
+                    treasury::TreasuryTxKind::MergeIn,
+                    amount,
+                    canister,
+                    None,
+                    Some(block_index),
+                );
+            }
+            merge.last_error = None;
+            merge.status = MergeStatus::Completed;
+            true
+        }
+        Err(error) => {
+            merge.last_error = Some(error);
+            false
+        }
+    }
+}
+
+// Both DAOs of a merge. They can't be deleted while it runs, should either be gone anyway the
+// merge is completed with what was done so far
+fn daos(merge: &mut DaoMerge) -> Option<(Dao, Dao)> {
+    match (
+        _get_dao(&merge.source_dao_id),
+        _get_dao(&merge.target_dao_id),
+    ) {
+        (Some(source), Some(target)) => Some((source, target)),
+        _ => {
+            merge.status = MergeStatus::Completed;
+            None
+        }
+    }
+}
+
+// Log a merge event in the audit log of both DAOs, each pointing at the other one
+fn log_both(merge: &DaoMerge, action: AuditAction) {
+    log_event(
+        merge.source_dao_id,
+        action,
+        AuditTarget::Dao(merge.target_dao_id),
+    );
+    log_event(
+        merge.target_dao_id,
+        action,
+        AuditTarget::Dao(merge.source_dao_id),
+    );
+}
+
+fn get_dao(dao_id: u64) -> Result<Dao, Error> {
+    _get_dao(&dao_id).ok_or_else(|| {
+        Error::not_found(
+            ErrorContext::Dao(dao_id),
+            format!("a dao with id={} not found", dao_id),
+        )
+    })
+}
+
+fn get_merge(merge_id: u64) -> Result<DaoMerge, Error> {
+    MERGE_STORAGE
+        .with(|service| service.borrow().get(&merge_id))
+        .ok_or_else(|| {
+            Error::not_found(
+                ErrorContext::None,
+                format!("a merge with id={} not found", merge_id),
+            )
+        })
+}
+
+fn save(merge: &DaoMerge) {
+    MERGE_STORAGE.with(|service| service.borrow_mut().insert(merge.id, merge.clone()));
+}
+
+fn next_id() -> u64 {
+    ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("cannot increment id counter")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{set_caller, set_time};
+    use crate::{
+        accept_merge, add_members_bulk, add_proposal, comment_on_post, create_dao,
+        end_proposal_vote, get_all_comments_on_proposal, get_all_proposals, propose_merge, upvote,
+        CommentPayload, DaoPayload, ProposalPayload, VOTING_PERIOD,
+    };
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    // Runs a future that never awaits another canister, like the steps of a merge without a
+    // ledger configured
+    fn run<F: std::future::Future>(future: F) -> F::Output {
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        match std::pin::pin!(future).as_mut().poll(&mut context) {
+            std::task::Poll::Ready(output) => output,
+            std::task::Poll::Pending => panic!("awaited a call to another canister"),
+        }
+    }
+
+    // a DAO owned by user `owner` with the members `members`
+    fn dao(name: &str, owner: u8, members: &[u8]) -> u64 {
+        set_caller(user(owner));
+        let dao = create_dao(DaoPayload {
+            name: name.to_string(),
+            description: "Small".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let members = members.iter().map(|n| user(*n)).collect();
+        assert!(add_members_bulk(dao.id, members).is_ok());
+        dao.id
+    }
+
+    fn proposal(dao_id: u64, title: &str) -> u64 {
+        let payload = ProposalPayload {
+            dao_id,
+            title: title.to_string(),
+            details: "Details".to_string(),
+            ..Default::default()
+        };
+        run(add_proposal(payload)).ok().unwrap().id
+    }
+
+    #[test]
+    fn merges_join_members_move_proposals_and_archive_the_source() {
+        let source = dao("Source", 1, &[2, 3]);
+        let target = dao("Target", 4, &[2, 5]);
+        set_caller(user(2));
+        let open = proposal(source, "Still open");
+        let finished = proposal(source, "Finished");
+        let discussed = CommentPayload {
+            content: "Agreed".to_string(),
+            proposal_id: finished,
+            reply_to: None,
+            stance: None,
+        };
+        assert!(comment_on_post(discussed).is_ok());
+        set_caller(user(3));
+        assert!(upvote(finished).is_ok());
+        set_time(VOTING_PERIOD + 1);
+        set_caller(user(2));
+        assert!(end_proposal_vote(finished).is_ok());
+
+        set_caller(user(1));
+        let merge_id = propose_merge(source, target).ok().unwrap().id;
+        assert!(matches!(
+            propose_merge(source, target),
+            Err(Error::InvalidInput { .. })
+        ));
+        for n in [1, 2] {
+            set_caller(user(n));
+            assert!(matches!(
+                accept_merge(merge_id),
+                Err(Error::PermissionError { .. })
+            ));
+        }
+        set_caller(user(4));
+        assert!(accept_merge(merge_id).is_ok());
+        assert_eq!(_get_settings(&source).merged_into, Some(target));
+        assert!(_get_dao(&source).unwrap().archived);
+
+        // what the timers would run, one step at a time
+        let mut steps = 0;
+        while get_merge(merge_id).ok().unwrap().is_active() {
+            run(step(merge_id));
+            steps += 1;
+        }
+        let merge = get_merge(merge_id).ok().unwrap();
+        assert!(merge.status == MergeStatus::Completed);
+        assert_eq!(steps, 3);
+        assert_eq!((merge.members_checked, merge.members_added), (3, 2));
+        assert_eq!((merge.proposals_moved, merge.proposals_cancelled), (2, 1));
+
+        let target_dao = _get_dao(&target).unwrap();
+        assert!((1..=5).all(|n| _is_member(&target_dao, &user(n))));
+        assert!(_dao_proposal_ids(&source).is_empty());
+        set_caller(user(5));
+        // the cancelled one is moved as well, but lists leave cancelled proposals out
+        let views = get_all_proposals(target).ok().unwrap();
+        assert!(views.len() == 1 && views[0].id == finished);
+        assert!(views[0].status == ProposalStatus::Approved);
+        assert_eq!(_get_proposal(&open).unwrap().dao_id, target);
+        assert!(matches!(
+            _get_proposal(&open).unwrap().cancel_reason,
+            Some(CancelReason::Merge { target_dao_id }) if target_dao_id == target
+        ));
+        assert!(get_all_comments_on_proposal(finished).is_ok_and(|comments| comments.len() == 1));
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{Error, ErrorContext, MESSAGE_CATALOG};
+use ic_stable_structures::{storable::Blob, BoundedStorable, Storable};
+use std::borrow::Cow;
+
+// Locale of the messages written next to the errors in the code
+const DEFAULT_LOCALE: &str = "en";
+const MAX_LOCALE_LEN: usize = 16;
+const MAX_TEMPLATE_LEN: usize = 300;
+// Replaced by the id of the entity an error is about
+const ID_PLACEHOLDER: &str = "{id}";
+
+pub(crate) type LocaleKey = Blob<16>;
+
+// Messages shipped with the canister for locales other than English, by error code. The admin
+// can override them and add locales with set_error_message
+const SPANISH: [(u16, &str); 30] = [
+    (1001, "No se encontró el elemento {id}"),
+    (1002, "No eres miembro de esta DAO"),
+    (1003, "Ya eres miembro de esta DAO"),
+    (1004, "Ya enviaste una solicitud para unirte a esta DAO"),
+    (1005, "Tienes prohibida la participación"),
+    (1006, "Los datos enviados no son válidos"),
+    (1007, "Ese nombre visible ya está en uso"),
+    (1008, "No tienes autorización para esta acción"),
+    (1009, "No cumples los requisitos para esta acción"),
+    (1010, "Demasiadas solicitudes, inténtalo de nuevo más tarde"),
+    (1011, "El hilo de comentarios está bloqueado"),
+    (1012, "Ya votaste en esta propuesta"),
+    (1013, "No puedes votar en tu propia propuesta"),
+    (1014, "No puedes dar me gusta a tu propio comentario"),
+    (1016, "No tienes permiso para modificar el elemento {id}"),
+    (1017, "El plazo ya terminó"),
+    (1018, "El plazo todavía no ha terminado"),
+    (1019, "Ya diste me gusta a este comentario"),
+    (1020, "Fondos insuficientes"),
+    (
+        1021,
+        "El elemento cambió desde que lo leíste, vuelve a cargarlo",
+    ),
+    (1022, "Primero debes aceptar la constitución de la DAO"),
+    (1023, "El ledger no pudo completar la transferencia"),
+    (1024, "La DAO está archivada"),
+    (1025, "La votación de esta propuesta está cerrada"),
+    (1026, "El periodo de comentarios de esta propuesta terminó"),
+    (1027, "Esta función está desactivada"),
+    (1028, "La DAO ya tiene el máximo de propuestas abiertas"),
+    (1029, "El canister alcanzó su límite de almacenamiento"),
+    (1030, "Otro pago de esta propuesta todavía espera al ledger"),
+    (1031, "El canister que guarda esta DAO no está disponible"),
+];
+
+// A catalog entry set by the admin
+#[derive(Clone)]
+pub(crate) struct MessageTemplate(String);
+
+impl Storable for MessageTemplate {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Borrowed(self.0.as_bytes())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        MessageTemplate(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+impl BoundedStorable for MessageTemplate {
+    const MAX_SIZE: u32 = MAX_TEMPLATE_LEN as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Lowercase a locale tag such as "es-MX". None unless it is made of letters, digits and hyphens
+pub(crate) fn normalize_locale(locale: &str) -> Option<String> {
+    let locale = locale.trim().to_ascii_lowercase();
+    let valid = !locale.is_empty()
+        && locale.len() <= MAX_LOCALE_LEN
+        && locale
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-');
+    Some(locale).filter(|_| valid)
+}
+
+// The message of an error with `code` for the caller. Their locale's template is used when there
+// is one, then its language without a region, then an English override, and otherwise `msg`
+// itself. The code and context of the error are never changed, only the message
+pub(crate) fn localize(code: u16, context: &ErrorContext, msg: String) -> String {
+    let locale = crate::_get_preferences(&crate::env::caller())
+        .locale
+        .unwrap_or_else(|| DEFAULT_LOCALE.to_string());
+    let language = locale.split('-').next().unwrap_or_default();
+    let localized = [locale.as_str(), language, DEFAULT_LOCALE]
+        .into_iter()
+        .find_map(|candidate| template(code, candidate))
+        .map(|template| {
+            template
+                .replace(ID_PLACEHOLDER, &context_id(context))
+                .trim()
+                .to_string()
+        })
+        .unwrap_or(msg);
+    localized
+}
+
+fn template(code: u16, locale: &str) -> Option<String> {
+    let key = LocaleKey::try_from(locale.as_bytes()).ok()?;
+    MESSAGE_CATALOG
+        .with(|service| service.borrow().get(&(code, key)))
+        .map(|template| template.0)
+        .or_else(|| builtin(code, locale).map(str::to_string))
+}
+
+fn builtin(code: u16, locale: &str) -> Option<&'static str> {
+    let messages: &[(u16, &str)] = match locale {
+        "es" => &SPANISH,
+        _ => return None,
+    };
+    messages
+        .iter()
+        .find(|(message_code, _)| *message_code == code)
+        .map(|(_, message)| *message)
+}
+
+fn context_id(context: &ErrorContext) -> String {
+    match context {
+        ErrorContext::None => String::new(),
+        ErrorContext::Dao(id)
+        | ErrorContext::Proposal(id)
+        | ErrorContext::Comment(id)
+        | ErrorContext::JoinRequest(id)
+        | ErrorContext::Notification(id)
+        | ErrorContext::Image(id)
+        | ErrorContext::Upload(id) => id.to_string(),
+        ErrorContext::Attachment { proposal_id, index } => format!("{}/{}", proposal_id, index),
+        ErrorContext::Member(principal) => principal.to_text(),
+    }
+}
+
+// Set the template of `code` in `locale`, or remove it to go back to the shipped message
+pub(crate) fn set_template(code: u16, locale: &str, template: Option<String>) -> Result<(), Error> {
+    if !(1000..2000).contains(&code) {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            format!("{} is not an error code", code),
+        ));
+    }
+    let key = normalize_locale(locale)
+        .and_then(|locale| LocaleKey::try_from(locale.as_bytes()).ok())
+        .ok_or_else(|| {
+            Error::invalid_input(
+                ErrorContext::None,
+                format!(
+                    "locale must be at most {} letters, digits or hyphens",
+                    MAX_LOCALE_LEN
+                ),
+            )
+        })?;
+
+    match template {
+        Some(template) => {
+            let template = template.trim().to_string();
+            if template.is_empty() || template.len() > MAX_TEMPLATE_LEN {
+                return Err(Error::invalid_input(
+                    ErrorContext::None,
+                    format!("template must be between 1 and {} bytes", MAX_TEMPLATE_LEN),
+                ));
+            }
+            MESSAGE_CATALOG.with(|service| {
+                service
+                    .borrow_mut()
+                    .insert((code, key), MessageTemplate(template))
+            });
+        }
+        None => {
+            MESSAGE_CATALOG.with(|service| service.borrow_mut().remove(&(code, key)));
+        }
+    }
+    Ok(())
+}
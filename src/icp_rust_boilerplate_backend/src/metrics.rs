@@ -0,0 +1,202 @@
+use crate::{
+    env::{cycle_balance, instruction_counter, stable_pages},
+    storage, COMMENT_STORAGE, DAO_STORAGE, ID_COUNTER, PROPOSAL_STORAGE,
+};
+use std::{cell::RefCell, collections::BTreeMap};
+
+// Size of a WebAssembly memory page in bytes
+pub(crate) const WASM_PAGE_SIZE: u64 = 65536;
+// Upper bounds of the instruction histogram buckets, calls above the last one land in an extra
+// bucket at the end
+const INSTRUCTION_BUCKETS: [u64; 4] = [1_000_000, 10_000_000, 100_000_000, 1_000_000_000];
+
+thread_local! {
+    // endpoint -> instructions its calls used. Kept on the heap only, upgrades start over
+    static PERF_STATS: RefCell<BTreeMap<&'static str, EndpointStats>> =
+        const { RefCell::new(BTreeMap::new()) };
+}
+
+// Operator facing numbers describing the state of the whole canister
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct CanisterMetrics {
+    total_daos: u64,
+    total_proposals: u64,
+    total_comments: u64,
+    id_counter: u64,
+    heap_memory_bytes: u64,
+    stable_memory_bytes: u64,
+    // past it writes that add records are refused, see storage::is_full
+    stable_memory_soft_limit_bytes: u64,
+    storage_full: bool,
+    cycle_balance: u64,
+    instructions_used: u64,
+}
+
+// Gather the metrics. Map lengths are tracked by the stable structures so this stays cheap
+pub(crate) fn collect() -> CanisterMetrics {
+    CanisterMetrics {
+        total_daos: DAO_STORAGE.with(|service| service.borrow().len()),
+        total_proposals: PROPOSAL_STORAGE.with(|service| service.borrow().len()),
+        total_comments: COMMENT_STORAGE.with(|service| service.borrow().len()),
+        id_counter: ID_COUNTER.with(|counter| *counter.borrow().get()),
+        heap_memory_bytes: heap_memory_bytes(),
+        stable_memory_bytes: stable_pages() * WASM_PAGE_SIZE,
+        stable_memory_soft_limit_bytes: storage::soft_limit_bytes(),
+        storage_full: storage::used_now() >= storage::soft_limit_bytes(),
+        cycle_balance: cycle_balance(),
+        instructions_used: instruction_counter(),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn heap_memory_bytes() -> u64 {
+    core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_SIZE
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn heap_memory_bytes() -> u64 {
+    0
+}
+
+// Instructions used by the calls of an endpoint since the canister started or the stats were reset
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct EndpointStats {
+    endpoint: String,
+    count: u64,
+    sum_instructions: u128,
+    max_instructions: u64,
+    // calls per INSTRUCTION_BUCKETS bound, plus the calls above the last one
+    histogram: Vec<u64>,
+}
+
+// Counts the instructions from its creation until it is dropped towards its endpoint, e.g.
+// `let _profile = metrics::profile("upvote");` at the top of the endpoint. Only updates can be
+// profiled, whatever a query records is thrown away with the rest of its state changes
+pub(crate) struct Profile {
+    endpoint: &'static str,
+    started_at: u64,
+}
+
+pub(crate) fn profile(endpoint: &'static str) -> Profile {
+    Profile {
+        endpoint,
+        started_at: instruction_counter(),
+    }
+}
+
+impl Drop for Profile {
+    fn drop(&mut self) {
+        let instructions = instruction_counter().saturating_sub(self.started_at);
+        let bucket = INSTRUCTION_BUCKETS
+            .iter()
+            .position(|bound| instructions <= *bound)
+            .unwrap_or(INSTRUCTION_BUCKETS.len());
+        PERF_STATS.with(|stats| {
+            let mut stats = stats.borrow_mut();
+            let entry = stats.entry(self.endpoint).or_insert_with(|| EndpointStats {
+                endpoint: self.endpoint.to_string(),
+                histogram: vec![0; INSTRUCTION_BUCKETS.len() + 1],
+                ..Default::default()
+            });
+            entry.count += 1;
+            entry.sum_instructions += instructions as u128;
+            entry.max_instructions = entry.max_instructions.max(instructions);
+            entry.histogram[bucket] += 1;
+        });
+    }
+}
+
+pub(crate) fn perf_stats() -> Vec<EndpointStats> {
+    PERF_STATS.with(|stats| stats.borrow().values().cloned().collect())
+}
+
+pub(crate) fn reset_perf_stats() {
+    PERF_STATS.with(|stats| stats.borrow_mut().clear());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{set_caller, set_stable_pages};
+    use crate::{get_canister_metrics, get_perf_stats, upvote, Dao, Error, Proposal, CONFIG};
+    use candid::Principal;
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    fn make_admin(admin: Principal) {
+        let mut config = CONFIG.with(|config| config.borrow().get().clone());
+        config.admin = Some(admin);
+        CONFIG.with(|cell| cell.borrow_mut().set(config).ok());
+    }
+
+    #[test]
+    fn only_the_admin_sees_the_metrics() {
+        make_admin(user(1));
+        DAO_STORAGE.with(|service| service.borrow_mut().insert(1, Dao::default()));
+        for id in [10, 11] {
+            PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, Proposal::default()));
+        }
+
+        set_caller(user(2));
+        assert!(matches!(
+            get_canister_metrics(),
+            Err(Error::Unauthorized { .. })
+        ));
+        set_caller(user(1));
+        let metrics = get_canister_metrics().ok().unwrap();
+        assert_eq!(
+            (
+                metrics.total_daos,
+                metrics.total_proposals,
+                metrics.total_comments
+            ),
+            (1, 2, 0)
+        );
+        assert!(!metrics.storage_full && metrics.stable_memory_soft_limit_bytes > 0);
+
+        set_stable_pages(metrics.stable_memory_soft_limit_bytes / WASM_PAGE_SIZE);
+        let metrics = get_canister_metrics().ok().unwrap();
+        assert!(metrics.storage_full);
+        assert_eq!(
+            metrics.stable_memory_bytes,
+            metrics.stable_memory_soft_limit_bytes
+        );
+    }
+
+    #[test]
+    fn profiled_calls_are_counted_until_reset() {
+        make_admin(user(1));
+        set_caller(user(2));
+        // calls that fail are profiled too
+        for _ in 0..3 {
+            assert!(upvote(99).is_err());
+        }
+        {
+            let _profile = profile("comment_on_post");
+        }
+
+        assert!(matches!(get_perf_stats(), Err(Error::Unauthorized { .. })));
+        assert!(matches!(
+            crate::reset_perf_stats(),
+            Err(Error::Unauthorized { .. })
+        ));
+        set_caller(user(1));
+        let stats = get_perf_stats().ok().unwrap();
+        let counts: Vec<(&str, u64)> = stats
+            .iter()
+            .map(|stats| (stats.endpoint.as_str(), stats.count))
+            .collect();
+        assert_eq!(counts, vec![("comment_on_post", 1), ("upvote", 3)]);
+        // natively no instructions are counted, every call lands in the first bucket
+        assert_eq!(stats[1].histogram, vec![3, 0, 0, 0, 0]);
+        assert_eq!(
+            (stats[1].sum_instructions, stats[1].max_instructions),
+            (0, 0)
+        );
+
+        assert!(crate::reset_perf_stats().is_ok());
+        assert!(get_perf_stats().is_ok_and(|stats| stats.is_empty()));
+    }
+}
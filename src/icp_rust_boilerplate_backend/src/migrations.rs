@@ -0,0 +1,574 @@
+use crate::env::{set_timer, time};
+use crate::{
+    _assign_dao_slug, _assign_proposal_slug, _backfill_discussion, _backfill_likes, _comment_ids,
+    _count_activity, _index_dao_members, _list_comment, _number_proposals, _record_participation,
+    _recount_votes, _votes, certification, do_insert_vote, principal_key, ActivityKind, Comment,
+    Memory, Proposal, Vote, VoteDirection, COMMENT_STORAGE, DAO_MEMBERSHIPS, DAO_PROPOSALS,
+    DAO_STORAGE, LEGACY_COMMENT_STORAGE, LEGACY_DAO_STORAGE, LEGACY_PROPOSAL_STORAGE,
+    MIGRATION_STATE, PROPOSAL_STORAGE,
+};
+use candid::{Decode, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, Storable};
+use std::{borrow::Cow, collections::BTreeMap, ops::Bound, time::Duration};
+
+// Layout version written with every Proposal, Dao and Comment. Records of the first release are
+// version 0 and are moved to the current layout by a single migration, see start
+pub(crate) const SCHEMA_VERSION: u8 = 1;
+
+// Records a single migration step goes through, the rest is left to the next step. Small enough
+// that a step stays far below the instruction limit of a message whatever it has to rewrite
+const MIGRATION_BATCH_SIZE: u64 = 500;
+
+// Number of records moved to the current layout, per map
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct MigrationReport {
+    proposals: u64,
+    daos: u64,
+    comments: u64,
+    // member and admin entries written to DAO_MEMBERSHIPS
+    memberships: u64,
+    // proposals, votes and comments counted towards the activity of their members
+    member_activity: u64,
+}
+
+// The step a migration is at, in the order they are gone through. Comments move first, the
+// discussion summary of a proposal is computed from them
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum MigrationStage {
+    Comments,
+    Proposals,
+    Daos,
+    MemberActivity,
+}
+
+// Where a migration stopped, the next step continues from there
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) struct MigrationCursor {
+    stage: MigrationStage,
+    // the last id gone through in that stage, None before the first one
+    after: Option<u64>,
+}
+
+// Progress of the storage migration, kept in MIGRATION_STATE so it survives upgrades. This is what
+// get_migration_status reports
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct MigrationStatus {
+    // schema version stored records were brought up to, 0 before the migration
+    completed_version: u8,
+    // where the running migration continues, None while none is running
+    cursor: Option<MigrationCursor>,
+    // what the migration did so far, or in total once it is done
+    report: MigrationReport,
+    // when the last step of the migration ran
+    completed_at: Option<u64>,
+}
+
+impl Storable for MigrationStatus {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+pub(crate) fn status() -> MigrationStatus {
+    MIGRATION_STATE.with(|state| state.borrow().get().clone())
+}
+
+fn save(status: MigrationStatus) {
+    MIGRATION_STATE
+        .with(|state| state.borrow_mut().set(status))
+        .expect("cannot save the migration status");
+}
+
+pub(crate) fn is_running() -> bool {
+    status().cursor.is_some()
+}
+
+// A fresh install stores every record in the current layout from the start, so upgrades of it
+// have nothing to migrate
+pub(crate) fn mark_done() {
+    save(MigrationStatus {
+        completed_version: SCHEMA_VERSION,
+        completed_at: Some(time()),
+        ..Default::default()
+    });
+}
+
+// Start moving the records of the first release to the current layout unless that was done
+// already, or continue the migration an earlier upgrade started. The first step runs right away,
+// the rest on timers. Returns whether the migration is done
+pub(crate) fn start() -> bool {
+    !begin() || run()
+}
+
+// Set the cursor of the migration unless it is running already. Returns whether there is a
+// migration to run
+fn begin() -> bool {
+    let mut status = status();
+    if status.cursor.is_some() {
+        return true;
+    }
+    if status.completed_version >= SCHEMA_VERSION {
+        return false;
+    }
+    status.cursor = Some(MigrationCursor {
+        stage: MigrationStage::Comments,
+        after: None,
+    });
+    status.report = MigrationReport::default();
+    save(status);
+    true
+}
+
+// Run a step and schedule the next one, or restart the background work once the last step is done.
+// Progress is left in the migration status
+fn run() -> bool {
+    let done = step();
+    if !done {
+        set_timer(Duration::ZERO, || {
+            if run() {
+                crate::resume_background_work();
+            }
+        });
+    }
+    done
+}
+
+// Migrate the next batch of records. Returns whether the migration is done
+pub(crate) fn step() -> bool {
+    let mut status = status();
+    let cursor = match status.cursor {
+        Some(cursor) => cursor,
+        None => return true,
+    };
+    status.cursor = advance(cursor, &mut status.report);
+    let done = status.cursor.is_none();
+    if done {
+        status.completed_version = SCHEMA_VERSION;
+        status.completed_at = Some(time());
+    }
+    save(status);
+    done
+}
+
+// Go through the next batch of the cursor's stage. Returns where the next step continues
+fn advance(cursor: MigrationCursor, report: &mut MigrationReport) -> Option<MigrationCursor> {
+    let after = match cursor.stage {
+        MigrationStage::Comments => move_comments(cursor.after, report),
+        MigrationStage::Proposals => move_proposals(cursor.after, report),
+        MigrationStage::Daos => move_daos(cursor.after, report),
+        MigrationStage::MemberActivity => backfill_member_activity(cursor.after, report),
+    };
+    if after.is_some() {
+        return Some(MigrationCursor {
+            stage: cursor.stage,
+            after,
+        });
+    }
+    let next = match cursor.stage {
+        MigrationStage::Comments => MigrationStage::Proposals,
+        MigrationStage::Proposals => MigrationStage::Daos,
+        MigrationStage::Daos => MigrationStage::MemberActivity,
+        MigrationStage::MemberActivity => return None,
+    };
+    Some(MigrationCursor {
+        stage: next,
+        after: None,
+    })
+}
+
+// The next batch of a map after `after`, along with the id the batch after it starts after. That
+// id is None once the map was gone through
+fn batch<V: BoundedStorable>(
+    map: &StableBTreeMap<u64, V, Memory>,
+    after: Option<u64>,
+) -> (Vec<(u64, V)>, Option<u64>) {
+    let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+    let records: Vec<(u64, V)> = map
+        .range((start, Bound::Unbounded))
+        .take(MIGRATION_BATCH_SIZE as usize)
+        .collect();
+    let next = if records.len() as u64 == MIGRATION_BATCH_SIZE {
+        records.last().map(|(id, _)| *id)
+    } else {
+        None
+    };
+    (records, next)
+}
+
+// Comments of the first release kept their likes on the record, in a map whose bound the current
+// layout doesn't fit. Each of them moves from LEGACY_COMMENT_STORAGE to COMMENT_STORAGE with its
+// likes written to COMMENT_LIKES
+fn move_comments(after: Option<u64>, report: &mut MigrationReport) -> Option<u64> {
+    let (comments, next) = LEGACY_COMMENT_STORAGE.with(|service| batch(&service.borrow(), after));
+
+    for (id, legacy) in comments {
+        let mut comment: Comment = legacy.into();
+        _backfill_likes(&mut comment);
+        if let Some(author) = comment.author {
+            _record_participation(&author, &comment.proposal_id);
+        }
+        comment.schema_version = SCHEMA_VERSION;
+        COMMENT_STORAGE.with(|service| service.borrow_mut().insert(id, comment));
+        LEGACY_COMMENT_STORAGE.with(|service| service.borrow_mut().remove(&id));
+        report.comments += 1;
+    }
+    next
+}
+
+// Proposals of the first release kept their voters and comments on the record, in a map whose
+// bound the current layout doesn't fit. Each of them moves from LEGACY_PROPOSAL_STORAGE to
+// PROPOSAL_STORAGE with its votes written to VOTE_STORAGE and its comments listed in
+// PROPOSAL_COMMENTS
+fn move_proposals(after: Option<u64>, report: &mut MigrationReport) -> Option<u64> {
+    let (proposals, next) = LEGACY_PROPOSAL_STORAGE.with(|service| batch(&service.borrow(), after));
+
+    for (id, legacy) in proposals {
+        let (mut proposal, upvoters, downvoters, comments) = legacy.split();
+        settle_votes(&mut proposal, upvoters, downvoters);
+        for comment_id in comments {
+            _list_comment(&mut proposal, comment_id);
+        }
+        _backfill_discussion(&mut proposal);
+        _assign_proposal_slug(&mut proposal);
+        proposal.schema_version = SCHEMA_VERSION;
+        // the certified tree was rebuilt before the proposal got here
+        certification::certify(&proposal);
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal));
+        LEGACY_PROPOSAL_STORAGE.with(|service| service.borrow_mut().remove(&id));
+        report.proposals += 1;
+    }
+    next
+}
+
+// Turn the voter lists of a proposal into its vote records and count them. Nothing stopped a voter
+// from voting more than once, their votes are counted once and a voter found in both lists keeps
+// their upvote. The votes are dated to the creation of the proposal
+fn settle_votes(proposal: &mut Proposal, upvoters: Vec<Principal>, downvoters: Vec<Principal>) {
+    let mut directions: BTreeMap<Principal, VoteDirection> = downvoters
+        .into_iter()
+        .map(|voter| (voter, VoteDirection::Down))
+        .collect();
+    for voter in upvoters {
+        directions.insert(voter, VoteDirection::Up);
+    }
+
+    for (voter, direction) in directions {
+        do_insert_vote(&Vote {
+            proposal_id: proposal.id,
+            voter: Some(voter),
+            direction,
+            voted_at: proposal.created_at,
+            weight: None,
+            ranking: None,
+        });
+    }
+    _recount_votes(proposal);
+}
+
+// DAOs of the first release kept their members and proposals on the record, in a map whose bound
+// the current layout doesn't fit. Each of them moves from LEGACY_DAO_STORAGE to DAO_STORAGE with
+// its memberships written to DAO_MEMBERSHIPS and the member index, and its proposals to
+// DAO_PROPOSALS
+fn move_daos(after: Option<u64>, report: &mut MigrationReport) -> Option<u64> {
+    let (daos, next) = LEGACY_DAO_STORAGE.with(|service| batch(&service.borrow(), after));
+
+    for (id, legacy) in daos {
+        let (mut dao, memberships, proposals) = legacy.split();
+        for (user, membership) in memberships {
+            DAO_MEMBERSHIPS.with(|service| {
+                service
+                    .borrow_mut()
+                    .insert((id, principal_key(&user)), membership)
+            });
+            report.memberships += 1;
+        }
+        for proposal_id in proposals {
+            DAO_PROPOSALS.with(|service| service.borrow_mut().insert((id, proposal_id), ()));
+        }
+        _index_dao_members(&dao);
+        _assign_dao_slug(&mut dao);
+        // runs after the proposals stage, so the proposals are in the current layout already
+        _number_proposals(&mut dao);
+        dao.schema_version = SCHEMA_VERSION;
+        DAO_STORAGE.with(|service| service.borrow_mut().insert(id, dao));
+        LEGACY_DAO_STORAGE.with(|service| service.borrow_mut().remove(&id));
+        report.daos += 1;
+    }
+    next
+}
+
+// The first release kept no activity counters, so the migration counts the proposals, votes and
+// comments of each member once everything else moved. Calls are refused while a migration runs, so
+// nothing else counts them in between two steps
+fn backfill_member_activity(after: Option<u64>, report: &mut MigrationReport) -> Option<u64> {
+    let (proposals, next) = PROPOSAL_STORAGE.with(|service| batch(&service.borrow(), after));
+
+    for (_, proposal) in proposals {
+        let comment_authors = _comment_ids(&proposal.id)
+            .into_iter()
+            .filter_map(|id| COMMENT_STORAGE.with(|comments| comments.borrow().get(&id)))
+            .filter_map(|comment| comment.author);
+        let voters = _votes(&proposal.id)
+            .into_iter()
+            .filter_map(|vote| vote.voter);
+        let activities = proposal
+            .owner
+            .iter()
+            .map(|owner| (*owner, ActivityKind::Proposal))
+            .chain(voters.map(|voter| (voter, ActivityKind::Vote)))
+            .chain(comment_authors.map(|author| (author, ActivityKind::Comment)));
+        for (member, kind) in activities {
+            _count_activity(proposal.dao_id, &member, kind);
+            report.member_activity += 1;
+        }
+    }
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        BaselineComment, BaselineDao, BaselineProposal, Dao, ImageRef, Membership, ProposalStatus,
+        COMMENT_LIKES, MEMBER_ACTIVITY, MEMBER_DAOS, PROPOSAL_COMMENTS, PROPOSAL_SLUGS,
+        THREAD_PARTICIPATION,
+    };
+    use candid::Principal;
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    fn decode<T: Storable>(hex: &str) -> T {
+        let bytes: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        T::from_bytes(Cow::Owned(bytes))
+    }
+
+    // Proposal 7 of DAO 3 as the first release wrote it: a request of 50 by user 1, upvoted by
+    // user 2, downvoted by user 3, approved, with comment 8, created at 10 and due at 20
+    const FIRST_PROPOSAL: &str = "4449444c056c0ddbb701788ee09c220198abec810171b7fff5810102acb990b9017ee8f5d5f90178b3b0dac3030387ea98d104789891bea00678aaacd9d00678c2b9dbda0a71a7c99fef0b01b4b2ea870c046d686e786e686d7801000700000000000000010101020446756e640001030000000000000001010101320000000000000014000000000000000a00000000000000016401010103010800000000000000";
+    // DAO 3 as the first release wrote it: "Builders" owned by user 1 with members 1 and 2,
+    // proposal 7, an avatar url and created at 5
+    const FIRST_DAO: &str = "4449444c056c09dbb70178b7fff5810101d9d9c0d30102b3b0dac30303cbe4fdc70471fc91f4f80571aaacd9d0067881a08ebd0c049980f2d20f716e786d686e686d7801000300000000000000000201010101010201010101084275696c64657273016405000000000000000107000000000000000f68747470733a2f2f612f622e706e67";
+    // Comment 8 on proposal 7 as the first release wrote it: "hi" by user 2, liked by users 1
+    // and 3, created at 12
+    const FIRST_COMMENT: &str = "4449444c046c07dbb70178b7fff5810101b99adecb0171aaacd9d006788bd5ef9b0702bcaefea707038882e69c0a786e786e686d6801000800000000000000000268690c0000000000000001010102020101010101030700000000000000";
+
+    // The three records above in the maps of the first release
+    fn store_first_release() {
+        LEGACY_PROPOSAL_STORAGE.with(|service| {
+            service.borrow_mut().insert(7, decode(FIRST_PROPOSAL));
+        });
+        LEGACY_DAO_STORAGE.with(|service| {
+            service.borrow_mut().insert(3, decode(FIRST_DAO));
+        });
+        LEGACY_COMMENT_STORAGE.with(|service| {
+            service.borrow_mut().insert(8, decode(FIRST_COMMENT));
+        });
+    }
+
+    fn migrate() {
+        assert!(begin());
+        while !step() {}
+    }
+
+    // how user `n` voted on proposal 7
+    fn direction(n: u8) -> Option<VoteDirection> {
+        crate::_get_vote(&7, &user(n)).map(|vote| vote.direction)
+    }
+
+    #[test]
+    fn decodes_the_records_of_the_first_release() {
+        let proposal: BaselineProposal = decode(FIRST_PROPOSAL);
+        assert_eq!((proposal.id, proposal.dao_id), (7, 3));
+        assert_eq!(proposal.title, "Fund");
+        assert_eq!(proposal.amount_requested, 50);
+        assert_eq!(proposal.owner, Some(user(1)));
+        assert_eq!(proposal.upvotes, vec![user(2)]);
+        assert_eq!(proposal.downvotes, vec![user(3)]);
+        assert_eq!(proposal.comments, vec![8]);
+        assert!(proposal.is_approved);
+        assert_eq!((proposal.created_at, proposal.deadline), (10, 20));
+
+        let dao: BaselineDao = decode(FIRST_DAO);
+        assert_eq!(dao.id, 3);
+        assert_eq!(dao.name, "Builders");
+        assert_eq!(dao.avatar, "https://a/b.png");
+        assert_eq!(dao.owner, Some(user(1)));
+        assert_eq!(dao.members, vec![user(1), user(2)]);
+        assert_eq!(dao.proposals, vec![7]);
+
+        let comment: BaselineComment = decode(FIRST_COMMENT);
+        assert_eq!((comment.id, comment.proposal_id), (8, 7));
+        assert_eq!(comment.content, "hi");
+        assert_eq!(comment.author, Some(user(2)));
+        assert_eq!(comment.likes, vec![user(1), user(3)]);
+    }
+
+    #[test]
+    fn moves_the_first_release_to_the_current_layout() {
+        store_first_release();
+
+        migrate();
+        let report = status().report;
+        assert_eq!((report.proposals, report.daos, report.comments), (1, 1, 1));
+        assert!(LEGACY_PROPOSAL_STORAGE.with(|service| service.borrow().is_empty()));
+        assert!(LEGACY_DAO_STORAGE.with(|service| service.borrow().is_empty()));
+        assert!(LEGACY_COMMENT_STORAGE.with(|service| service.borrow().is_empty()));
+        assert_eq!(status().completed_version, SCHEMA_VERSION);
+        assert!(status().completed_at.is_some());
+
+        let comment = COMMENT_STORAGE
+            .with(|service| service.borrow().get(&8))
+            .unwrap();
+        assert_eq!(comment.schema_version, SCHEMA_VERSION);
+        assert_eq!(comment.content, "hi");
+        assert!(comment.likes.is_empty());
+        assert_eq!(comment.like_count, 2);
+        for liker in [1, 3] {
+            assert!(COMMENT_LIKES.with(|service| service
+                .borrow()
+                .contains_key(&(8, principal_key(&user(liker))))));
+        }
+        assert!(THREAD_PARTICIPATION
+            .with(|service| service.borrow().contains_key(&(principal_key(&user(2)), 7))));
+
+        let proposal = PROPOSAL_STORAGE
+            .with(|service| service.borrow().get(&7))
+            .unwrap();
+        assert_eq!(proposal.schema_version, SCHEMA_VERSION);
+        assert_eq!(proposal.kind.amount(), 50);
+        assert!(matches!(proposal.status, ProposalStatus::Approved));
+        // votes were only ended after the deadline
+        assert_eq!(proposal.finalized_at, Some(20));
+        assert_eq!((proposal.upvote_count, proposal.downvote_count), (1, 1));
+        assert_eq!(proposal.comment_count, 1);
+        assert!(PROPOSAL_COMMENTS.with(|service| service.borrow().contains_key(&(7, 8))));
+        // the comment was moved first, so it counts towards the discussion
+        assert_eq!(proposal.unique_participants, 2);
+        assert_eq!(proposal.last_activity_at, 12);
+        assert_eq!(proposal.slug, "fund");
+        assert!(direction(2) == Some(VoteDirection::Up));
+        assert!(direction(3) == Some(VoteDirection::Down));
+
+        let dao: Dao = DAO_STORAGE
+            .with(|service| service.borrow().get(&3))
+            .unwrap();
+        assert_eq!(dao.schema_version, SCHEMA_VERSION);
+        assert_eq!(dao.slug, "builders");
+        assert!(matches!(&dao.avatar, ImageRef::Url(url) if url == "https://a/b.png"));
+        // the owner was listed as a member too, but gets no membership
+        assert!(crate::_memberships(3) == vec![(user(2), Membership::Member)]);
+        assert_eq!(dao.member_count, 1);
+        for member in [1, 2] {
+            assert!(MEMBER_DAOS.with(|service| service
+                .borrow()
+                .contains_key(&(principal_key(&user(member)), 3))));
+        }
+        assert_eq!(crate::_dao_proposal_ids(&3), vec![7]);
+        assert_eq!(dao.proposal_count, 1);
+        assert_eq!(dao.last_proposal_number, 1);
+        assert_eq!(
+            crate::_get_proposal(&7).map(|proposal| proposal.proposal_number),
+            Some(1)
+        );
+
+        // the proposal of user 1, the votes of users 2 and 3 and the comment of user 2
+        assert_eq!(report.member_activity, 4);
+        let activity = |n: u8| {
+            MEMBER_ACTIVITY
+                .with(|service| service.borrow().get(&(3, principal_key(&user(n)))))
+                .map(|activity| {
+                    (
+                        activity.proposals_created,
+                        activity.votes_cast,
+                        activity.comments_made,
+                    )
+                })
+        };
+        assert_eq!(activity(1), Some((1, 0, 0)));
+        assert_eq!(activity(2), Some((0, 1, 1)));
+        assert!(!begin());
+    }
+
+    #[test]
+    fn voters_are_counted_once_and_keep_their_upvote() {
+        let mut legacy: BaselineProposal = decode(FIRST_PROPOSAL);
+        // user 2 upvoted twice, user 4 both ways
+        legacy.upvotes.extend([user(2), user(4)]);
+        legacy.downvotes.push(user(4));
+        let (mut proposal, upvoters, downvoters, _) = legacy.split();
+        settle_votes(&mut proposal, upvoters, downvoters);
+
+        assert_eq!((proposal.upvote_count, proposal.downvote_count), (2, 1));
+        assert!(direction(4) == Some(VoteDirection::Up));
+        // the votes are dated to the creation of the proposal
+        assert_eq!(crate::_get_vote(&7, &user(2)).unwrap().voted_at, 10);
+    }
+
+    #[test]
+    fn migrates_in_batches_and_refuses_updates_meanwhile() {
+        let count = MIGRATION_BATCH_SIZE + 1;
+        for id in 1..=count {
+            let mut proposal: BaselineProposal = decode(FIRST_PROPOSAL);
+            proposal.id = id;
+            proposal.dao_id = 1;
+            proposal.title = "Budget".to_string();
+            proposal.created_at = id;
+            proposal.upvotes.clear();
+            proposal.downvotes.clear();
+            proposal.comments.clear();
+            LEGACY_PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal));
+        }
+
+        assert!(begin());
+        // no comments to move, then the first batch of proposals
+        assert!(!step());
+        assert!(!step());
+        assert!(
+            status().cursor
+                == Some(MigrationCursor {
+                    stage: MigrationStage::Proposals,
+                    after: Some(MIGRATION_BATCH_SIZE),
+                })
+        );
+        assert!(crate::_check_can_update().is_err());
+        assert!(crate::_check_can_read().is_err());
+        assert_eq!(
+            LEGACY_PROPOSAL_STORAGE.with(|service| service.borrow().len()),
+            1
+        );
+
+        while !step() {}
+        assert!(!is_running());
+        assert!(crate::_check_can_update().is_ok());
+        assert_eq!(status().report.proposals, count);
+        assert!(LEGACY_PROPOSAL_STORAGE.with(|service| service.borrow().is_empty()));
+        PROPOSAL_STORAGE.with(|service| {
+            assert!(service
+                .borrow()
+                .iter()
+                .all(|(_, proposal)| proposal.schema_version == SCHEMA_VERSION
+                    && !proposal.slug.is_empty()))
+        });
+        // every proposal got its own slug, even across batches
+        assert_eq!(PROPOSAL_SLUGS.with(|service| service.borrow().len()), count);
+        assert!(!begin());
+    }
+
+    #[test]
+    fn fresh_installs_have_nothing_to_migrate() {
+        mark_done();
+        assert!(!begin());
+        assert!(start());
+        assert_eq!(status().completed_version, SCHEMA_VERSION);
+        assert!(!is_running());
+    }
+}
@@ -0,0 +1,431 @@
+use crate::env::{set_timer, time};
+use crate::{LINK_PREVIEWS, PREVIEW_FETCHES};
+use candid::{Decode, Encode};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext,
+};
+use ic_stable_structures::{BoundedStorable, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, time::Duration};
+
+// Links of a comment that get a preview, the ones after them are left as they are
+const MAX_LINKS_PER_COMMENT: usize = 3;
+const MAX_URL_LEN: usize = 300;
+// Bounds on what is kept of a page
+const MAX_TITLE_LEN: usize = 200;
+const MAX_DESCRIPTION_LEN: usize = 500;
+// Pages fetched for the comments of a single DAO per day, links past it are left unavailable
+const MAX_FETCHES_PER_DAO_PER_DAY: u64 = 200;
+const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+// How long a preview is used before its page is fetched again. Previews that couldn't be fetched
+// are tried again sooner
+const PREVIEW_TTL: u64 = 7 * DAY;
+const UNAVAILABLE_TTL: u64 = 60 * 60 * 1_000_000_000;
+// Only the start of a page is read, that's where its title and meta tags are
+const MAX_PAGE_BYTES: usize = 16 * 1024;
+// Servers can ignore the Range header asking for MAX_PAGE_BYTES, pages longer than this fail
+const MAX_RESPONSE_BYTES: u64 = 64 * 1024;
+// Attached to every outcall. Covers a small request and MAX_RESPONSE_BYTES on a 13 node subnet,
+// whatever isn't used is refunded
+const OUTCALL_CYCLES: u128 = 1_000_000_000;
+// Name of the query replicas run responses through, see link_preview_transform
+const TRANSFORM_METHOD: &str = "link_preview_transform";
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum PreviewStatus {
+    // the page is being fetched
+    Pending,
+    Ready,
+    // the page couldn't be fetched or had nothing to show, or the DAO ran out of fetches
+    Unavailable,
+}
+
+// What a link in a comment points at, shared by every comment with the same link
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct LinkPreview {
+    id: u64,
+    url: String,
+    status: PreviewStatus,
+    title: Option<String>,
+    description: Option<String>,
+    image_url: Option<String>,
+    // when it was requested, or fetched once it isn't pending anymore
+    updated_at: u64,
+}
+
+impl LinkPreview {
+    fn is_stale(&self, now: u64) -> bool {
+        let ttl = match self.status {
+            PreviewStatus::Ready => PREVIEW_TTL,
+            PreviewStatus::Pending | PreviewStatus::Unavailable => UNAVAILABLE_TTL,
+        };
+        now >= self.updated_at.saturating_add(ttl)
+    }
+}
+
+impl Storable for LinkPreview {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for LinkPreview {
+    const MAX_SIZE: u32 = 4096;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// What the transform keeps of a page, so replicas agree on it whatever else the page contains
+#[derive(Serialize, Deserialize, Default)]
+struct PageMeta {
+    title: Option<String>,
+    description: Option<String>,
+    image_url: Option<String>,
+}
+
+// The http(s) links in a comment, each once and at most MAX_LINKS_PER_COMMENT
+fn extract_urls(content: &str) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    for word in content.split_whitespace() {
+        let word = word.trim_start_matches(['(', '<', '[', '"', '\'']);
+        if !word.starts_with("https://") && !word.starts_with("http://") {
+            continue;
+        }
+        let url = word.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '>', ']', '"', '\'']);
+        let has_host = url.split("://").nth(1).is_some_and(|rest| !rest.is_empty());
+        if has_host && url.len() <= MAX_URL_LEN && !urls.iter().any(|known| known == url) {
+            urls.push(url.to_string());
+        }
+        if urls.len() == MAX_LINKS_PER_COMMENT {
+            break;
+        }
+    }
+    urls
+}
+
+// The previews of the links in a comment of `dao_id`. Links without a fresh preview are fetched
+// in a timer, so posting a comment never waits for, or fails because of, the linked page
+pub(crate) fn attach(content: &str, dao_id: u64) -> Vec<u64> {
+    extract_urls(content)
+        .into_iter()
+        .map(|url| {
+            let id = preview_id(&url);
+            refresh(id, url, dao_id);
+            id
+        })
+        .collect()
+}
+
+// The stored previews among `ids`, in the same order
+pub(crate) fn resolve(ids: &[u64]) -> Vec<LinkPreview> {
+    LINK_PREVIEWS.with(|service| {
+        let service = service.borrow();
+        ids.iter().filter_map(|id| service.get(id)).collect()
+    })
+}
+
+// Keep only the title, description and image of a page so replicas can agree on the response
+pub(crate) fn transform(args: TransformArgs) -> HttpResponse {
+    let succeeded = args.response.status >= 200u64 && args.response.status < 300u64;
+    let meta = if succeeded {
+        let end = args.response.body.len().min(MAX_PAGE_BYTES);
+        parse_page(&String::from_utf8_lossy(&args.response.body[..end]))
+    } else {
+        PageMeta::default()
+    };
+    HttpResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: serde_json::to_vec(&meta).unwrap_or_default(),
+    }
+}
+
+// Links are told apart by the start of their SHA-256
+fn preview_id(url: &str) -> u64 {
+    let digest = Sha256::digest(url.as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes"))
+}
+
+fn refresh(id: u64, url: String, dao_id: u64) {
+    let now = time();
+    let cached = LINK_PREVIEWS.with(|service| service.borrow().get(&id));
+    if cached.is_some_and(|preview| preview.url == url && !preview.is_stale(now)) {
+        return;
+    }
+
+    // outcalls can only reach https urls
+    let fetch = url.starts_with("https://") && record_fetch(dao_id, now);
+    let preview = LinkPreview {
+        id,
+        url: url.clone(),
+        status: if fetch {
+            PreviewStatus::Pending
+        } else {
+            PreviewStatus::Unavailable
+        },
+        title: None,
+        description: None,
+        image_url: None,
+        updated_at: now,
+    };
+    LINK_PREVIEWS.with(|service| service.borrow_mut().insert(id, preview));
+    if fetch {
+        set_timer(Duration::ZERO, move || {
+            ic_cdk::spawn(fetch_page(id, url));
+        });
+    }
+}
+
+// Count a fetch against the DAO's daily budget. False when the budget is used up
+fn record_fetch(dao_id: u64, now: u64) -> bool {
+    let day = now / DAY;
+    PREVIEW_FETCHES.with(|service| {
+        let mut service = service.borrow_mut();
+        let past_days: Vec<(u64, u64)> = service
+            .range((dao_id, 0)..(dao_id, day))
+            .map(|(key, _)| key)
+            .collect();
+        for key in past_days {
+            service.remove(&key);
+        }
+
+        let fetches = service.get(&(dao_id, day)).unwrap_or_default();
+        if fetches >= MAX_FETCHES_PER_DAO_PER_DAY {
+            return false;
+        }
+        service.insert((dao_id, day), fetches + 1);
+        true
+    })
+}
+
+async fn fetch_page(id: u64, url: String) {
+    let request = CanisterHttpRequestArgument {
+        url: url.clone(),
+        max_response_bytes: Some(MAX_RESPONSE_BYTES),
+        method: HttpMethod::GET,
+        headers: vec![HttpHeader {
+            name: "Range".to_string(),
+            value: format!("bytes=0-{}", MAX_PAGE_BYTES - 1),
+        }],
+        body: None,
+        transform: Some(TransformContext::from_name(
+            TRANSFORM_METHOD.to_string(),
+            Vec::new(),
+        )),
+    };
+    let meta = match http_request(request, OUTCALL_CYCLES).await {
+        Ok((response,)) => serde_json::from_slice::<PageMeta>(&response.body).ok(),
+        Err(_) => None,
+    }
+    .filter(|meta| meta.title.is_some() || meta.description.is_some());
+
+    let preview = match meta {
+        Some(meta) => LinkPreview {
+            id,
+            url,
+            status: PreviewStatus::Ready,
+            title: meta.title,
+            description: meta.description,
+            image_url: meta.image_url,
+            updated_at: time(),
+        },
+        None => LinkPreview {
+            id,
+            url,
+            status: PreviewStatus::Unavailable,
+            title: None,
+            description: None,
+            image_url: None,
+            updated_at: time(),
+        },
+    };
+    LINK_PREVIEWS.with(|service| service.borrow_mut().insert(id, preview));
+}
+
+// The og: meta tags of a page, falling back to its <title> and description meta tag
+fn parse_page(html: &str) -> PageMeta {
+    let lower = html.to_ascii_lowercase();
+    let mut meta = PageMeta::default();
+    let mut title = None;
+    let mut description = None;
+
+    let mut position = 0;
+    while let Some(start) = lower[position..].find("<meta").map(|i| position + i) {
+        let end = match lower[start..].find('>') {
+            Some(i) => start + i,
+            None => break,
+        };
+        let (tag, tag_lower) = (&html[start..end], &lower[start..end]);
+        let key =
+            attribute(tag, tag_lower, "property").or_else(|| attribute(tag, tag_lower, "name"));
+        let content = attribute(tag, tag_lower, "content");
+        match (key.map(|key| key.to_ascii_lowercase()).as_deref(), content) {
+            (Some("og:title"), Some(content)) => meta.title = clean(content, MAX_TITLE_LEN),
+            (Some("og:description"), Some(content)) => {
+                meta.description = clean(content, MAX_DESCRIPTION_LEN)
+            }
+            (Some("description"), Some(content)) => {
+                description = clean(content, MAX_DESCRIPTION_LEN)
+            }
+            (Some("og:image"), Some(content)) => {
+                meta.image_url = Some(content.trim().to_string())
+                    .filter(|url| url.starts_with("https://") && url.len() <= MAX_URL_LEN)
+            }
+            _ => (),
+        }
+        position = end;
+    }
+
+    if let Some(start) = lower.find("<title") {
+        if let Some(text_start) = lower[start..].find('>').map(|i| start + i + 1) {
+            if let Some(text_end) = lower[text_start..].find("</title") {
+                title = clean(&html[text_start..text_start + text_end], MAX_TITLE_LEN);
+            }
+        }
+    }
+
+    meta.title = meta.title.or(title);
+    meta.description = meta.description.or(description);
+    meta
+}
+
+// The value of a quoted attribute of a tag, `name` has to be lowercase
+fn attribute<'a>(tag: &'a str, tag_lower: &str, name: &str) -> Option<&'a str> {
+    let pattern = format!("{}=", name);
+    let mut position = 0;
+    while let Some(i) = tag_lower[position..].find(&pattern).map(|i| position + i) {
+        position = i + pattern.len();
+        // skip e.g. data-content= when looking for content=
+        if !tag_lower[..i].ends_with(char::is_whitespace) {
+            continue;
+        }
+        let quote = tag[position..].chars().next()?;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+        let value_start = position + 1;
+        let value_end = tag[value_start..].find(quote)? + value_start;
+        return Some(&tag[value_start..value_end]);
+    }
+    None
+}
+
+// Decode the common entities, collapse whitespace and cut to `max_len` characters. None if
+// nothing is left
+fn clean(text: &str, max_len: usize) -> Option<String> {
+    let decoded = text
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&");
+    let collapsed: String = decoded
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(max_len)
+        .collect();
+    Some(collapsed).filter(|text| !text.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::set_time;
+
+    fn fetches_today(dao_id: u64) -> u64 {
+        PREVIEW_FETCHES
+            .with(|service| service.borrow().get(&(dao_id, time() / DAY)))
+            .unwrap_or_default()
+    }
+
+    fn status(id: u64) -> Option<PreviewStatus> {
+        resolve(&[id]).first().map(|preview| preview.status)
+    }
+
+    // what the replicas agree on for a page, as fetch_page reads it
+    fn transformed(status: u64, body: &str) -> PageMeta {
+        let response = transform(TransformArgs {
+            response: HttpResponse {
+                status: status.into(),
+                headers: vec![HttpHeader {
+                    name: "Set-Cookie".to_string(),
+                    value: "session=1".to_string(),
+                }],
+                body: body.as_bytes().to_vec(),
+            },
+            context: Vec::new(),
+        });
+        assert!(response.headers.is_empty());
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn up_to_three_distinct_links_are_taken_from_a_comment() {
+        assert_eq!(
+            extract_urls(
+                "See (https://a.example/x), https://a.example/x and <http://b.example>. \
+                 Not ftp://c.example or https:// but https://d.example! https://e.example"
+            ),
+            vec![
+                "https://a.example/x",
+                "http://b.example",
+                "https://d.example"
+            ]
+        );
+        let long = format!("https://{}", "a".repeat(MAX_URL_LEN));
+        assert!(extract_urls(&long).is_empty());
+    }
+
+    #[test]
+    fn transforms_keep_only_title_description_and_image() {
+        let page = r#"<html><head><title>Fallback</title>
+            <meta property="og:title" content="Grant &amp; plan">
+            <meta name="description" data-content="x" content="  The   plan ">
+            <meta property="og:image" content="http://insecure.example/i.png">
+            </head><body>secret</body></html>"#;
+        let meta = transformed(200, page);
+        assert_eq!(meta.title.as_deref(), Some("Grant & plan"));
+        assert_eq!(meta.description.as_deref(), Some("The plan"));
+        assert_eq!(meta.image_url, None);
+
+        // only the start of a page is read and failed responses keep nothing
+        let late = format!("{}<title>Too far</title>", " ".repeat(MAX_PAGE_BYTES));
+        assert!(transformed(200, &late).title.is_none());
+        assert!(transformed(404, page).title.is_none());
+    }
+
+    #[test]
+    fn previews_are_cached_until_stale_and_fetches_are_bounded_per_dao() {
+        set_time(DAY);
+        let ids = attach("https://a.example and http://b.example", 1);
+        assert_eq!(ids.len(), 2);
+        assert!(status(ids[0]) == Some(PreviewStatus::Pending));
+        // outcalls can't reach plain http, the comment posts with the preview unavailable
+        assert!(status(ids[1]) == Some(PreviewStatus::Unavailable));
+        assert_eq!(fetches_today(1), 1);
+
+        attach("https://a.example again", 1);
+        assert_eq!(fetches_today(1), 1);
+        set_time(DAY + UNAVAILABLE_TTL);
+        attach("https://a.example again", 1);
+        assert_eq!(fetches_today(1), 2);
+
+        for n in fetches_today(1)..MAX_FETCHES_PER_DAO_PER_DAY {
+            attach(&format!("https://{}.example", n), 1);
+        }
+        let over = attach("https://one-too-many.example", 1);
+        assert!(status(over[0]) == Some(PreviewStatus::Unavailable));
+        assert_eq!(fetches_today(1), MAX_FETCHES_PER_DAO_PER_DAY);
+        // other DAOs and the next day have their own budget
+        assert!(status(attach("https://other.example", 2)[0]) == Some(PreviewStatus::Pending));
+        set_time(2 * DAY);
+        let next_day = attach("https://next-day.example", 1);
+        assert!(status(next_day[0]) == Some(PreviewStatus::Pending));
+    }
+}
@@ -0,0 +1,300 @@
+// Ranked-choice proposals. Members rank some or all of the options with vote_option and the
+// ballots are counted by instant runoff when the vote is finalized
+
+// Bounds on the options of a ranked-choice proposal
+pub(crate) const MIN_OPTIONS: usize = 2;
+pub(crate) const MAX_OPTIONS: usize = 6;
+pub(crate) const MAX_OPTION_LEN: usize = 50;
+
+// How a tie for last place was settled
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum TieBreak {
+    // the tied option ranked last on the most ballots was dropped
+    LastPlaceRankings,
+    // the proposal owner picked the tied option that stays, see resolve_ranked_tie
+    OwnerDecision,
+}
+
+// One counting round of an instant runoff
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct RankedRound {
+    // first preferences among the options still in the count, by option index. Options that
+    // were dropped in earlier rounds have 0
+    counts: Vec<u64>,
+    // ballots none of whose options are left in the count
+    exhausted: u64,
+    // options dropped after this round, none in the round that found the winner
+    eliminated: Vec<u8>,
+    tie_break: Option<TieBreak>,
+}
+
+// The outcome of counting a ranked-choice proposal, kept on the proposal so anyone can follow
+// the rounds
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct RankedResult {
+    rounds: Vec<RankedRound>,
+    // index of the winning option. None when nothing was ranked or a tie waits for the owner
+    winner: Option<u8>,
+    // options still tied for last place after comparing last-place rankings. The count stops
+    // there until the owner picks the one that stays with resolve_ranked_tie
+    pending_tie: Vec<u8>,
+    // the option the owner kept in each tie they settled, in counting order
+    owner_decisions: Vec<u8>,
+}
+
+impl RankedResult {
+    pub(crate) fn winner(&self) -> Option<u8> {
+        self.winner
+    }
+
+    pub(crate) fn pending_tie(&self) -> &[u8] {
+        &self.pending_tie
+    }
+
+    pub(crate) fn owner_decisions(&self) -> &[u8] {
+        &self.owner_decisions
+    }
+}
+
+// Check the options of a new or amended ranked-choice proposal
+pub(crate) fn validate_options(options: &[String]) -> Result<(), String> {
+    if !(MIN_OPTIONS..=MAX_OPTIONS).contains(&options.len()) {
+        return Err(format!(
+            "a ranked-choice proposal needs between {} and {} options",
+            MIN_OPTIONS, MAX_OPTIONS
+        ));
+    }
+    for (index, option) in options.iter().enumerate() {
+        let length = option.trim().chars().count();
+        if length == 0 || length > MAX_OPTION_LEN {
+            return Err(format!(
+                "option {} must be between 1 and {} characters",
+                index, MAX_OPTION_LEN
+            ));
+        }
+        if options[..index]
+            .iter()
+            .any(|other| other.trim().eq_ignore_ascii_case(option.trim()))
+        {
+            return Err(format!("option {} repeats an earlier option", index));
+        }
+    }
+    Ok(())
+}
+
+// Check a ballot for a proposal with `option_count` options. It lists option indexes from most
+// to least preferred, at least one and each at most once. Options left out aren't ranked at all
+pub(crate) fn validate_ranking(ranking: &[u8], option_count: usize) -> Result<(), String> {
+    if ranking.is_empty() {
+        return Err("a ranking has to list at least one option".to_string());
+    }
+    for (position, option) in ranking.iter().enumerate() {
+        if *option as usize >= option_count {
+            return Err(format!(
+                "option {} doesn't exist, the proposal has {} options",
+                option, option_count
+            ));
+        }
+        if ranking[..position].contains(option) {
+            return Err(format!("option {} is ranked more than once", option));
+        }
+    }
+    Ok(())
+}
+
+// Count `ballots`, each a ranking and what it weighs, by instant runoff. Every round gives each
+// ballot to its highest ranked option still in the count. An option holding more than half of
+// those ballots wins, otherwise the option with the fewest is dropped and the count goes again.
+// A tie for fewest drops the tied option ranked last on the most ballots. When that is still a
+// tie the next of `owner_decisions` names the option that stays and the others are dropped
+// together. Without one the count stops with the tie pending
+pub(crate) fn instant_runoff(
+    option_count: usize,
+    ballots: &[(Vec<u8>, u64)],
+    owner_decisions: &[u8],
+) -> RankedResult {
+    let mut result = RankedResult::default();
+    let mut decisions = owner_decisions.iter().copied();
+    let mut remaining: Vec<u8> = (0..option_count as u8).collect();
+
+    loop {
+        let mut round = RankedRound {
+            counts: vec![0; option_count],
+            ..Default::default()
+        };
+        for (ranking, weight) in ballots {
+            match ranking.iter().find(|option| remaining.contains(option)) {
+                Some(option) => round.counts[*option as usize] += weight,
+                None => round.exhausted += weight,
+            }
+        }
+        let total: u64 = round.counts.iter().sum();
+        if total == 0 {
+            result.rounds.push(round);
+            return result;
+        }
+        let majority = remaining
+            .iter()
+            .copied()
+            .find(|option| round.counts[*option as usize] * 2 > total);
+        if let Some(winner) =
+            majority.or(remaining.first().copied().filter(|_| remaining.len() == 1))
+        {
+            result.winner = Some(winner);
+            result.rounds.push(round);
+            return result;
+        }
+
+        let fewest = remaining
+            .iter()
+            .map(|option| round.counts[*option as usize])
+            .min()
+            .unwrap_or_default();
+        let mut dropped: Vec<u8> = remaining
+            .iter()
+            .copied()
+            .filter(|option| round.counts[*option as usize] == fewest)
+            .collect();
+        if dropped.len() > 1 {
+            let last_places: Vec<u64> = dropped
+                .iter()
+                .map(|option| last_place_rankings(*option, ballots, &remaining))
+                .collect();
+            let most = last_places.iter().copied().max().unwrap_or_default();
+            dropped = dropped
+                .into_iter()
+                .zip(last_places)
+                .filter(|(_, count)| *count == most)
+                .map(|(option, _)| option)
+                .collect();
+            round.tie_break = Some(TieBreak::LastPlaceRankings);
+        }
+        if dropped.len() > 1 {
+            match decisions.next().filter(|kept| dropped.contains(kept)) {
+                Some(kept) => {
+                    dropped.retain(|option| *option != kept);
+                    result.owner_decisions.push(kept);
+                    round.tie_break = Some(TieBreak::OwnerDecision);
+                }
+                None => {
+                    round.tie_break = None;
+                    result.pending_tie = dropped;
+                    result.rounds.push(round);
+                    return result;
+                }
+            }
+        }
+
+        remaining.retain(|option| !dropped.contains(option));
+        round.eliminated = dropped;
+        result.rounds.push(round);
+    }
+}
+
+// the weight of the ballots that rank `option` below every other option still in the count.
+// Ballots ranking fewer than two of those options have no last place
+fn last_place_rankings(option: u8, ballots: &[(Vec<u8>, u64)], remaining: &[u8]) -> u64 {
+    ballots
+        .iter()
+        .filter(|(ranking, _)| {
+            let ranked: Vec<&u8> = ranking
+                .iter()
+                .filter(|ranked| remaining.contains(ranked))
+                .collect();
+            ranked.len() > 1 && ranked.last() == Some(&&option)
+        })
+        .map(|(_, weight)| weight)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `count` ballots ranking `ranking`, each weighing 1
+    fn ballots(groups: &[(&[u8], usize)]) -> Vec<(Vec<u8>, u64)> {
+        groups
+            .iter()
+            .flat_map(|(ranking, count)| vec![(ranking.to_vec(), 1); *count])
+            .collect()
+    }
+
+    #[test]
+    fn the_weakest_option_is_dropped_until_one_holds_a_majority() {
+        // A 4, B 3, C 2: nobody has a majority, C goes and its ballots move on to B
+        let result = instant_runoff(
+            3,
+            &ballots(&[(&[0, 1], 4), (&[1, 0], 3), (&[2, 1], 2)]),
+            &[],
+        );
+        assert_eq!(result.winner(), Some(1));
+        assert_eq!(result.rounds.len(), 2);
+        assert_eq!(result.rounds[0].counts, vec![4, 3, 2]);
+        assert_eq!(result.rounds[0].eliminated, vec![2]);
+        assert!(result.rounds[0].tie_break.is_none());
+        assert_eq!(result.rounds[1].counts, vec![4, 5, 0]);
+        assert!(result.rounds[1].eliminated.is_empty());
+
+        // ballots with nothing left to give are exhausted
+        let result = instant_runoff(3, &ballots(&[(&[0], 4), (&[1], 3), (&[2], 2)]), &[]);
+        assert_eq!(result.rounds[1].exhausted, 2);
+        assert_eq!(result.winner(), Some(0));
+    }
+
+    #[test]
+    fn ties_for_fewest_drop_the_option_ranked_last_most_often() {
+        // A and B both have 2, A is ranked last on 5 ballots and B on none
+        let result = instant_runoff(
+            3,
+            &ballots(&[(&[0, 1, 2], 2), (&[1, 2, 0], 2), (&[2, 1, 0], 3)]),
+            &[],
+        );
+        assert!(result.rounds[0].tie_break == Some(TieBreak::LastPlaceRankings));
+        assert_eq!(result.rounds[0].eliminated, vec![0]);
+        assert_eq!(result.winner(), Some(1));
+    }
+
+    #[test]
+    fn ties_left_after_last_places_wait_for_the_owner() {
+        // A and B both have 1 and ballots ranking a single option have no last place
+        let tied = ballots(&[(&[0], 1), (&[1], 1), (&[2], 2)]);
+        let pending = instant_runoff(3, &tied, &[]);
+        assert_eq!(pending.winner(), None);
+        assert_eq!(pending.pending_tie(), &[0, 1]);
+        assert!(pending.rounds[0].tie_break.is_none());
+
+        // the owner keeping B drops A, after which C holds a majority
+        let decided = instant_runoff(3, &tied, &[1]);
+        assert!(decided.pending_tie().is_empty());
+        assert_eq!(decided.owner_decisions(), &[1]);
+        assert!(decided.rounds[0].tie_break == Some(TieBreak::OwnerDecision));
+        assert_eq!(decided.rounds[0].eliminated, vec![0]);
+        assert_eq!(decided.winner(), Some(2));
+
+        // a decision naming an option that isn't tied leaves the tie pending
+        assert_eq!(instant_runoff(3, &tied, &[2]).pending_tie(), &[0, 1]);
+    }
+
+    #[test]
+    fn rankings_list_existing_options_once() {
+        assert!(validate_ranking(&[2, 0], 3).is_ok());
+        for invalid in [&[][..], &[3], &[1, 1]] {
+            assert!(validate_ranking(invalid, 3).is_err());
+        }
+        let options = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert!(validate_options(&options(&["Blue", "Green"])).is_ok());
+        for invalid in [
+            options(&["Blue"]),
+            options(&["Blue", " blue "]),
+            options(&["Blue", ""]),
+            options(&["a"; MAX_OPTIONS + 1]),
+        ] {
+            assert!(validate_options(&invalid).is_err());
+        }
+    }
+}
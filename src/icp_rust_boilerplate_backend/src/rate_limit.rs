@@ -0,0 +1,163 @@
+use crate::{principal_key, Error, PrincipalKey, RATE_LIMIT_STORAGE};
+use candid::{Decode, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, Storable};
+use std::borrow::Cow;
+
+// Proposals a member can create in a single DAO within PROPOSAL_WINDOW
+pub(crate) const MAX_PROPOSALS_PER_WINDOW: usize = 5;
+pub(crate) const PROPOSAL_WINDOW: u64 = 24 * 60 * 60 * 1_000_000_000;
+// Comments a member can post on a single proposal within COMMENT_WINDOW
+pub(crate) const MAX_COMMENTS_PER_WINDOW: usize = 20;
+pub(crate) const COMMENT_WINDOW: u64 = 60 * 60 * 1_000_000_000;
+
+// When a member performed a rate limited action, oldest first. Never holds more than the limit
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
+pub(crate) struct RateWindow {
+    timestamps: Vec<u64>,
+}
+
+impl Storable for RateWindow {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for RateWindow {
+    const MAX_SIZE: u32 = 1024;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Record an action by a member in a scope (a DAO for proposals, a proposal for comments) at `now`.
+// Fails with the time left until the oldest action leaves the window if the limit is reached
+pub(crate) fn check_and_record(
+    member: &Principal,
+    scope_id: u64,
+    limit: usize,
+    window: u64,
+    now: u64,
+) -> Result<(), Error> {
+    let key: (PrincipalKey, u64) = (principal_key(member), scope_id);
+    let mut rate_window = current_window(&key, limit, window, now)?;
+    rate_window.timestamps.push(now);
+    RATE_LIMIT_STORAGE.with(|service| service.borrow_mut().insert(key, rate_window));
+    Ok(())
+}
+
+// Fail like check_and_record would, without recording anything
+pub(crate) fn check(
+    member: &Principal,
+    scope_id: u64,
+    limit: usize,
+    window: u64,
+    now: u64,
+) -> Result<(), Error> {
+    current_window(&(principal_key(member), scope_id), limit, window, now).map(|_| ())
+}
+
+// The actions of a window still inside it at `now`, or the error when there's no room for another
+fn current_window(
+    key: &(PrincipalKey, u64),
+    limit: usize,
+    window: u64,
+    now: u64,
+) -> Result<RateWindow, Error> {
+    let mut rate_window = RATE_LIMIT_STORAGE
+        .with(|service| service.borrow().get(key))
+        .unwrap_or_default();
+
+    rate_window
+        .timestamps
+        .retain(|timestamp| now < timestamp.saturating_add(window));
+
+    if rate_window.timestamps.len() >= limit {
+        let oldest = rate_window.timestamps.first().copied().unwrap_or(now);
+        let retry_after = oldest.saturating_add(window).saturating_sub(now);
+        return Err(Error::rate_limited(retry_after.div_ceil(1_000_000_000)));
+    }
+    Ok(rate_window)
+}
+
+// Drop windows whose actions have all left the longest window. Returns how many were dropped
+pub(crate) fn prune(now: u64) -> u64 {
+    let longest_window = PROPOSAL_WINDOW.max(COMMENT_WINDOW);
+    let stale: Vec<(PrincipalKey, u64)> = RATE_LIMIT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .filter(|(_, rate_window)| {
+                rate_window
+                    .timestamps
+                    .iter()
+                    .all(|timestamp| now >= timestamp.saturating_add(longest_window))
+            })
+            .map(|(key, _)| key)
+            .collect()
+    });
+
+    RATE_LIMIT_STORAGE.with(|service| {
+        stale.iter().for_each(|key| {
+            service.borrow_mut().remove(key);
+        })
+    });
+    stale.len() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    fn retry_after(result: Result<(), Error>) -> Option<u64> {
+        match result {
+            Err(Error::RateLimited {
+                retry_after_seconds,
+                ..
+            }) => Some(retry_after_seconds),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn bursts_are_limited_until_the_oldest_action_leaves_the_window() {
+        let member = Principal::from_slice(&[1]);
+        let record = |now: u64| check_and_record(&member, 7, 3, 60 * SECOND, now);
+        for now in [0, 10 * SECOND, 20 * SECOND] {
+            assert!(record(now).is_ok());
+        }
+        // the action at 0 leaves the window at 60s, partial seconds are rounded up
+        assert_eq!(retry_after(record(30 * SECOND)), Some(30));
+        assert_eq!(retry_after(record(59 * SECOND + 1)), Some(1));
+        assert!(retry_after(check(&member, 7, 3, 60 * SECOND, 59 * SECOND)).is_some());
+        assert!(record(60 * SECOND).is_ok());
+        assert_eq!(retry_after(record(61 * SECOND)), Some(9));
+
+        // other scopes and members have windows of their own
+        assert!(check_and_record(&member, 8, 3, 60 * SECOND, 61 * SECOND).is_ok());
+        let other = Principal::from_slice(&[2]);
+        assert!(check_and_record(&other, 7, 3, 60 * SECOND, 61 * SECOND).is_ok());
+
+        // windows only keep the actions still inside them
+        let stored = RATE_LIMIT_STORAGE
+            .with(|service| service.borrow().get(&(principal_key(&member), 7)))
+            .unwrap();
+        assert_eq!(
+            stored.timestamps,
+            vec![10 * SECOND, 20 * SECOND, 60 * SECOND]
+        );
+    }
+
+    #[test]
+    fn prune_drops_windows_past_the_longest_window() {
+        let member = Principal::from_slice(&[1]);
+        assert!(check_and_record(&member, 1, 5, PROPOSAL_WINDOW, 0).is_ok());
+        assert!(check_and_record(&member, 2, 5, COMMENT_WINDOW, 10).is_ok());
+        assert_eq!(prune(PROPOSAL_WINDOW), 1);
+        assert_eq!(prune(PROPOSAL_WINDOW + 10), 1);
+        assert!(RATE_LIMIT_STORAGE.with(|service| service.borrow().is_empty()));
+    }
+}
@@ -0,0 +1,342 @@
+use crate::env::time;
+use crate::{_get_settings, Dao, DaoSummary, DaoVisibility, CONFIG, REGISTRY_OUTBOX};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::call::CallResult;
+use ic_stable_structures::{BoundedStorable, Storable};
+use std::{borrow::Cow, cell::Cell, time::Duration};
+
+// Method of the registry canister announcements are delivered to
+#[cfg(not(test))]
+const REGISTER_METHOD: &str = "register_dao";
+// Failed deliveries are tried again after RETRY_BASE_DELAY, doubling with every attempt up to
+// MAX_RETRY_DELAY. Announcements are kept until they are delivered
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+// Bounds on what get_outbox_status returns and on the error kept per entry
+const MAX_LISTED_ENTRIES: usize = 50;
+const MAX_ERROR_LEN: usize = 200;
+
+thread_local! {
+    // set while a flush is delivering, so timers and flush_outbox don't deliver twice
+    static FLUSHING: Cell<bool> = const { Cell::new(false) };
+}
+
+// What happened to a public DAO
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum RegistryEvent {
+    // the DAO was made public
+    Listed,
+    Updated,
+    Archived,
+    Unarchived,
+    // the DAO was made private or deleted
+    Delisted,
+}
+
+// The argument of register_dao on the registry
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DaoAnnouncement {
+    event: RegistryEvent,
+    dao: DaoSummary,
+    announced_at: u64,
+}
+
+// An announcement waiting to be delivered. Only the latest one of each DAO is kept, the registry
+// only needs to know where a DAO is at now
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct OutboxEntry {
+    announcement: DaoAnnouncement,
+    // bumped whenever a newer announcement replaces this one
+    version: u64,
+    attempts: u32,
+    last_attempt_at: Option<u64>,
+    last_error: Option<String>,
+}
+
+impl Storable for OutboxEntry {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for OutboxEntry {
+    const MAX_SIZE: u32 = 2048;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct OutboxStatus {
+    registry_canister: Option<Principal>,
+    pending: u64,
+    flushing: bool,
+    // (dao_id, entry) of the first MAX_LISTED_ENTRIES DAOs waiting for delivery
+    entries: Vec<(u64, OutboxEntry)>,
+}
+
+// Queue an announcement about a public DAO for the registry, if one is configured. Private DAOs
+// are never announced, so callers announce a DAO being made private before the change. Delivery
+// runs in a timer, the calling endpoint never waits for or fails because of the registry
+pub(crate) fn announce(dao: &Dao, event: RegistryEvent) {
+    if registry_canister().is_none() || _get_settings(&dao.id).visibility != DaoVisibility::Public {
+        return;
+    }
+
+    let version = REGISTRY_OUTBOX
+        .with(|service| service.borrow().get(&dao.id))
+        .map(|entry| entry.version + 1)
+        .unwrap_or_default();
+    let entry = OutboxEntry {
+        announcement: DaoAnnouncement {
+            event,
+            dao: DaoSummary::from(dao),
+            announced_at: time(),
+        },
+        version,
+        attempts: 0,
+        last_attempt_at: None,
+        last_error: None,
+    };
+    REGISTRY_OUTBOX.with(|service| service.borrow_mut().insert(dao.id, entry));
+    schedule_flush(Duration::ZERO);
+}
+
+// Deliver whatever was left in the outbox before an upgrade
+pub(crate) fn resume() {
+    if !REGISTRY_OUTBOX.with(|service| service.borrow().is_empty()) {
+        schedule_flush(Duration::ZERO);
+    }
+}
+
+pub(crate) fn status() -> OutboxStatus {
+    REGISTRY_OUTBOX.with(|service| {
+        let service = service.borrow();
+        OutboxStatus {
+            registry_canister: registry_canister(),
+            pending: service.len(),
+            flushing: FLUSHING.with(Cell::get),
+            entries: service.iter().take(MAX_LISTED_ENTRIES).collect(),
+        }
+    })
+}
+
+// Deliver every queued announcement, one DAO after the other. Announcements that fail stay in
+// the outbox and another flush is scheduled for the earliest retry
+pub(crate) async fn flush() {
+    if registry_canister().is_none() || FLUSHING.with(|flushing| flushing.replace(true)) {
+        return;
+    }
+
+    let mut retry_in: Option<Duration> = None;
+    let dao_ids: Vec<u64> =
+        REGISTRY_OUTBOX.with(|service| service.borrow().iter().map(|(id, _)| id).collect());
+    for dao_id in dao_ids {
+        let registry = match registry_canister() {
+            Some(registry) => registry,
+            None => break,
+        };
+        let entry = match REGISTRY_OUTBOX.with(|service| service.borrow().get(&dao_id)) {
+            Some(entry) => entry,
+            None => continue,
+        };
+
+        let result = deliver(registry, entry.announcement.clone()).await;
+        // a newer announcement may have replaced the entry while the call was running
+        let current = REGISTRY_OUTBOX
+            .with(|service| service.borrow().get(&dao_id))
+            .filter(|current| current.version == entry.version);
+        match (result, current) {
+            (Ok(()), Some(_)) => {
+                REGISTRY_OUTBOX.with(|service| service.borrow_mut().remove(&dao_id));
+            }
+            (Err((code, msg)), Some(mut current)) => {
+                current.attempts += 1;
+                current.last_attempt_at = Some(time());
+                current.last_error = Some(
+                    format!("{:?} {}", code, msg)
+                        .chars()
+                        .take(MAX_ERROR_LEN)
+                        .collect(),
+                );
+                let delay = retry_delay(current.attempts);
+                retry_in = Some(retry_in.map_or(delay, |retry_in| retry_in.min(delay)));
+                REGISTRY_OUTBOX.with(|service| service.borrow_mut().insert(dao_id, current));
+            }
+            // the newer announcement is left for the next flush
+            (_, None) => {}
+        }
+    }
+
+    FLUSHING.with(|flushing| flushing.set(false));
+    // announcements queued during this flush found it running and weren't delivered yet
+    let untried = REGISTRY_OUTBOX.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .any(|(_, entry)| entry.attempts == 0)
+    });
+    if untried && registry_canister().is_some() {
+        schedule_flush(Duration::ZERO);
+    } else if let Some(delay) = retry_in {
+        schedule_flush(delay);
+    }
+}
+
+#[cfg(not(test))]
+async fn deliver(registry: Principal, announcement: DaoAnnouncement) -> CallResult<()> {
+    ic_cdk::call(registry, REGISTER_METHOD, (announcement,)).await
+}
+
+#[cfg(not(test))]
+fn schedule_flush(delay: Duration) {
+    ic_cdk_timers::set_timer(delay, || ic_cdk::spawn(flush()));
+}
+
+#[cfg(test)]
+thread_local! {
+    static DELIVERED: std::cell::RefCell<Vec<DaoAnnouncement>> = const { std::cell::RefCell::new(Vec::new()) };
+    static REJECTING: Cell<bool> = const { Cell::new(false) };
+    static SCHEDULED: std::cell::RefCell<Vec<Duration>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+// Natively the registry is a stub keeping what it is sent, or rejecting it while REJECTING is set
+#[cfg(test)]
+async fn deliver(_registry: Principal, announcement: DaoAnnouncement) -> CallResult<()> {
+    if REJECTING.with(Cell::get) {
+        return Err((
+            ic_cdk::api::call::RejectionCode::CanisterReject,
+            "registry is down".to_string(),
+        ));
+    }
+    DELIVERED.with(|delivered| delivered.borrow_mut().push(announcement));
+    Ok(())
+}
+
+// Natively there are no timers, tests flush by hand and check when a flush was asked for
+#[cfg(test)]
+fn schedule_flush(delay: Duration) {
+    SCHEDULED.with(|scheduled| scheduled.borrow_mut().push(delay));
+}
+
+// waits 30 seconds after the first failure, then 60, 120, ... up to an hour
+fn retry_delay(attempts: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY)
+}
+
+fn registry_canister() -> Option<Principal> {
+    CONFIG.with(|config| config.borrow().get().registry_canister)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{set_caller, set_time};
+    use crate::{archive_dao, do_insert_dao, set_dao_visibility, DaoSettings, DAO_SETTINGS};
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    // the stub registry answers right away, so a flush is done after its first poll
+    fn flush_now() {
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        assert!(std::future::Future::poll(std::pin::pin!(flush()), &mut context).is_ready());
+    }
+
+    fn delivered() -> Vec<RegistryEvent> {
+        DELIVERED.with(|delivered| delivered.borrow().iter().map(|a| a.event).collect())
+    }
+
+    fn entry(dao_id: u64) -> Option<OutboxEntry> {
+        REGISTRY_OUTBOX.with(|service| service.borrow().get(&dao_id))
+    }
+
+    // public DAO 1 of user 1, with the registry configured
+    fn public_dao() {
+        let mut config = CONFIG.with(|config| config.borrow().get().clone());
+        config.registry_canister = Some(user(99));
+        CONFIG.with(|cell| cell.borrow_mut().set(config).ok());
+        let mut dao = Dao {
+            id: 1,
+            owner: Some(user(1)),
+            ..Default::default()
+        };
+        do_insert_dao(&mut dao);
+        let settings = DaoSettings {
+            dao_id: 1,
+            visibility: DaoVisibility::Public,
+            ..Default::default()
+        };
+        DAO_SETTINGS.with(|service| service.borrow_mut().insert(1, settings));
+    }
+
+    #[test]
+    fn delivers_announcements_and_retries_failures() {
+        public_dao();
+        set_caller(user(1));
+        set_time(5);
+        REJECTING.with(|rejecting| rejecting.set(true));
+
+        // the registry being down doesn't fail the change it hears about
+        assert!(archive_dao(1).is_ok());
+        assert!(SCHEDULED.with(|scheduled| scheduled.borrow().clone()) == vec![Duration::ZERO]);
+        flush_now();
+        let failed = entry(1).unwrap();
+        assert!(failed.announcement.event == RegistryEvent::Archived);
+        assert!(failed.attempts == 1 && failed.last_attempt_at == Some(5));
+        assert!(failed.last_error.unwrap().contains("registry is down"));
+        assert!(SCHEDULED.with(|scheduled| scheduled.borrow().last() == Some(&RETRY_BASE_DELAY)));
+        assert!(delivered().is_empty());
+
+        flush_now();
+        assert!(entry(1).unwrap().attempts == 2);
+        assert!(
+            SCHEDULED.with(|scheduled| scheduled.borrow().last().copied())
+                == Some(2 * RETRY_BASE_DELAY)
+        );
+
+        REJECTING.with(|rejecting| rejecting.set(false));
+        flush_now();
+        assert!(entry(1).is_none());
+        assert!(delivered() == vec![RegistryEvent::Archived]);
+        assert!(status().pending == 0 && !status().flushing);
+    }
+
+    #[test]
+    fn only_public_daos_are_announced() {
+        public_dao();
+        set_caller(user(1));
+
+        // a DAO made private is delisted while the registry can still hear about it, and newer
+        // announcements replace the waiting one
+        let dao = crate::_get_dao(&1).unwrap();
+        announce(&dao, RegistryEvent::Updated);
+        assert!(set_dao_visibility(1, DaoVisibility::Private).is_ok());
+        assert!(entry(1).unwrap().version == 1);
+        announce(&dao, RegistryEvent::Updated);
+        flush_now();
+        assert!(delivered() == vec![RegistryEvent::Delisted]);
+
+        assert!(set_dao_visibility(1, DaoVisibility::Public).is_ok());
+        flush_now();
+        assert!(delivered() == vec![RegistryEvent::Delisted, RegistryEvent::Listed]);
+    }
+
+    #[test]
+    fn waits_for_a_registry_to_be_configured() {
+        public_dao();
+        let mut config = CONFIG.with(|config| config.borrow().get().clone());
+        config.registry_canister = None;
+        CONFIG.with(|cell| cell.borrow_mut().set(config).ok());
+
+        announce(&crate::_get_dao(&1).unwrap(), RegistryEvent::Updated);
+        flush_now();
+        assert!(entry(1).is_none() && delivered().is_empty());
+        assert!(retry_delay(1) == RETRY_BASE_DELAY && retry_delay(40) == MAX_RETRY_DELAY);
+    }
+}
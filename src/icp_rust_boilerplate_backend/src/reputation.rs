@@ -0,0 +1,139 @@
+use crate::{principal_key, PrincipalKey, REPUTATION_STORAGE};
+use candid::Principal;
+
+// Points awarded to the owner of a proposal that gets approved
+pub(crate) const PROPOSAL_APPROVED_POINTS: u64 = 5;
+// Points awarded to the author of a comment for every like it receives
+pub(crate) const COMMENT_LIKED_POINTS: u64 = 1;
+// Points awarded to a member for every vote they cast
+pub(crate) const VOTE_CAST_POINTS: u64 = 1;
+
+// A member and the reputation they've accrued in a DAO
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ReputationEntry {
+    member: Principal,
+    points: u64,
+}
+
+// Add points to a member's reputation in a DAO. Points are never taken away
+pub(crate) fn award(dao_id: u64, member: &Principal, points: u64) {
+    let key = (dao_id, principal_key(member));
+    REPUTATION_STORAGE.with(|service| {
+        let current = service.borrow().get(&key).unwrap_or_default();
+        service
+            .borrow_mut()
+            .insert(key, current.saturating_add(points))
+    });
+}
+
+pub(crate) fn get(dao_id: u64, member: &Principal) -> u64 {
+    REPUTATION_STORAGE
+        .with(|service| service.borrow().get(&(dao_id, principal_key(member))))
+        .unwrap_or_default()
+}
+
+// The members of a DAO with the most reputation, highest first
+pub(crate) fn leaderboard(dao_id: u64, limit: usize) -> Vec<ReputationEntry> {
+    let mut entries: Vec<ReputationEntry> = REPUTATION_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((dao_id, PrincipalKey::default())..)
+            .take_while(|((id, _), _)| *id == dao_id)
+            .map(|((_, member), points)| ReputationEntry {
+                member: Principal::from_slice(member.as_slice()),
+                points,
+            })
+            .collect()
+    });
+
+    entries.sort_by(|a, b| b.points.cmp(&a.points).then(a.member.cmp(&b.member)));
+    entries.truncate(limit);
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{set_caller, set_time};
+    use crate::{
+        _admit_member, comment_on_post, delete_comment, do_insert_dao, downvote, end_proposal_vote,
+        get_dao_leaderboard, like_comment, upvote, CommentPayload, Dao, Proposal, ProposalStatus,
+        PROPOSAL_STORAGE,
+    };
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    // DAO 1 of user 1 with members 2 to 4, and open proposal 10 of user 2
+    fn dao_with_proposal() {
+        let mut dao = Dao {
+            id: 1,
+            owner: Some(user(1)),
+            ..Default::default()
+        };
+        (2..=4).for_each(|n| _admit_member(&mut dao, &user(n)));
+        do_insert_dao(&mut dao);
+        let proposal = Proposal {
+            id: 10,
+            dao_id: 1,
+            owner: Some(user(2)),
+            deadline: 1_000,
+            status: ProposalStatus::Open,
+            ..Default::default()
+        };
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(10, proposal));
+    }
+
+    #[test]
+    fn reputation_accrues_for_votes_likes_and_approvals() {
+        dao_with_proposal();
+        for voter in [user(1), user(3), user(4)] {
+            set_caller(voter);
+            assert!(upvote(10).is_ok());
+        }
+        set_caller(user(3));
+        let comment = comment_on_post(CommentPayload {
+            content: "agreed".to_string(),
+            proposal_id: 10,
+            reply_to: None,
+            stance: None,
+        })
+        .ok()
+        .unwrap();
+        for liker in [user(1), user(4)] {
+            set_caller(liker);
+            assert!(like_comment(comment.id).is_ok());
+        }
+        assert_eq!(
+            get(1, &user(3)),
+            VOTE_CAST_POINTS + 2 * COMMENT_LIKED_POINTS
+        );
+
+        set_caller(user(2));
+        set_time(1_001);
+        assert!(end_proposal_vote(10).is_ok());
+        assert_eq!(get(1, &user(2)), PROPOSAL_APPROVED_POINTS);
+
+        // deleting the liked comment keeps its points, and nothing is ever taken away
+        set_caller(user(3));
+        assert!(delete_comment(comment.id).is_ok());
+        assert!(downvote(10).is_err());
+        assert_eq!(
+            get(1, &user(3)),
+            VOTE_CAST_POINTS + 2 * COMMENT_LIKED_POINTS
+        );
+
+        let board: Vec<(Principal, u64)> = get_dao_leaderboard(1, 10)
+            .ok()
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.member, entry.points))
+            .collect();
+        assert_eq!(
+            board,
+            vec![(user(2), 5), (user(3), 3), (user(1), 1), (user(4), 1)]
+        );
+        assert_eq!(get_dao_leaderboard(1, 2).ok().unwrap().len(), 2);
+    }
+}
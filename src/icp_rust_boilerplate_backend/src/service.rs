@@ -0,0 +1,770 @@
+// Core governance rules and the endpoint logic of voting. Endpoints pass in caller() and time()
+// along with what they act on, nothing here reads them itself. The rules only depend on their
+// arguments, voting and ending a vote also read and write storage.
+//
+// upvote, downvote, cast_votes, can_vote and end_proposal_vote are shims over cast_vote,
+// check_can_cast_vote and end_vote. The audit log, notifications and treasury history these write
+// to still stamp their entries through env, and the other endpoints read caller() and time()
+// themselves, native tests drive those through env's stand-ins
+use crate::{
+    _check_can_vote_on, _check_dao_not_archived, _close_if_clearly_failing, _count_activity,
+    _finalize_vote, _follow, _get_dao, _get_member_role, _get_preferences, _get_proposal,
+    _get_settings, _is_canister_admin, _record_activity, _record_vote, do_insert_proposal,
+    log_event, notify, reputation, ActivityKind, AuditAction, AuditTarget, Dao, EarlyClose, Error,
+    ErrorContext, Membership, NotificationKind, Proposal, ProposalStatus, Vote, VoteDirection,
+};
+use candid::Principal;
+use std::collections::BTreeSet;
+
+// Records without an owner predate ownership tracking. Only canister admins can manage those
+pub(crate) fn is_owner(owner: &Option<Principal>, user: &Principal, is_admin: bool) -> bool {
+    match owner {
+        Some(owner) => owner == user,
+        None => is_admin,
+    }
+}
+
+// `membership` is how `user` belongs to `dao`, as kept in DAO_MEMBERSHIPS
+pub(crate) fn is_member(dao: &Dao, user: &Principal, membership: Option<Membership>) -> bool {
+    dao.owner == Some(*user) || membership.is_some_and(Membership::is_member)
+}
+
+// Members and observers can read a DAO, only members can take part in it
+pub(crate) fn can_read(dao: &Dao, user: &Principal, membership: Option<Membership>) -> bool {
+    is_member(dao, user, membership) || membership == Some(Membership::Observer)
+}
+
+// Lowercase the ASCII letters and digits of `text` and join each run of them with a hyphen, e.g.
+// "Builders' Guild!" becomes "builders-guild". At most `max_len` bytes are kept, `fallback` is
+// used when nothing is left
+pub(crate) fn slugify(text: &str, max_len: usize, fallback: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(max_len);
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        fallback.to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+// The `n`th candidate for a slug: the base itself, then base-2, base-3, ...
+pub(crate) fn numbered_slug(base: &str, n: u64) -> String {
+    if n <= 1 {
+        base.to_string()
+    } else {
+        format!("{}-{}", base, n)
+    }
+}
+
+// Drop repeated principals from a list, keeping the first occurrence of each. Returns how many
+// were dropped
+pub(crate) fn dedupe_principals(principals: &mut Vec<Principal>) -> u64 {
+    let before = principals.len();
+    let mut seen = BTreeSet::new();
+    principals.retain(|principal| seen.insert(*principal));
+    (before - principals.len()) as u64
+}
+
+pub(crate) fn is_deadline_passed(deadline: u64, now: u64) -> bool {
+    now > deadline
+}
+
+// Check that `voter`, who belongs to `dao` by `membership`, can vote on a proposal of it at `now`.
+// Neither the owner nor the `co_authors` of a proposal can vote on it, and `has_voted` voters
+// already did
+pub(crate) fn check_if_can_vote(
+    proposal: &Proposal,
+    dao: &Dao,
+    membership: Option<Membership>,
+    co_authors: &[Principal],
+    has_voted: bool,
+    voter: &Principal,
+    now: u64,
+) -> Result<(), Error> {
+    if proposal.status == ProposalStatus::Draft {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't vote on a proposal with id={}. It is a draft",
+                proposal.id
+            ),
+        ));
+    }
+
+    if proposal.status == ProposalStatus::Cancelled {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't vote on a proposal with id={}. It was cancelled",
+                proposal.id
+            ),
+        ));
+    }
+    if proposal.status == ProposalStatus::Scheduled {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't vote on a proposal with id={}. Voting starts at {}",
+                proposal.id,
+                proposal.voting_starts_at.unwrap_or_default()
+            ),
+        ));
+    }
+    if proposal.status == ProposalStatus::Queued {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't vote on a proposal with id={}. It is queued until its dao has room for another open proposal",
+                proposal.id
+            ),
+        ));
+    }
+    if proposal.status == ProposalStatus::Blocked {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't vote on a proposal with id={}. It is waiting for proposal with id={} to be approved",
+                proposal.id,
+                proposal.depends_on.unwrap_or_default()
+            ),
+        ));
+    }
+
+    if matches!(
+        proposal.status,
+        ProposalStatus::Approved | ProposalStatus::Rejected
+    ) {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't vote on a proposal with id={}. Its vote is over",
+                proposal.id
+            ),
+        ));
+    }
+
+    if !is_member(dao, voter, membership) {
+        return Err(Error::not_a_member(
+            ErrorContext::Dao(dao.id),
+            format!("unable to vote in a dao with id={}. Not a member", dao.id),
+        ));
+    }
+
+    if proposal.owner.is_some() && proposal.owner == Some(*voter) {
+        return Err(Error::cant_vote_yours(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't vote on a proposal with id={} because you created the proposal",
+                proposal.id
+            ),
+        ));
+    }
+    if co_authors.contains(voter) {
+        return Err(Error::cant_vote_yours(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't vote on a proposal with id={} because you co-authored the proposal",
+                proposal.id
+            ),
+        ));
+    }
+
+    if has_voted {
+        return Err(Error::has_voted(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't vote on a proposal with id={}. user voted already",
+                proposal.id
+            ),
+        ));
+    }
+
+    if is_deadline_passed(proposal.deadline, now) {
+        return Err(Error::deadline_exceeded(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't vote on a proposal with id={}. Deadline exceeded",
+                proposal.id
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+// Check that `user`, a canister admin when `is_admin`, can finalize a proposal at `now`.
+// Anonymous ballots can only be finalized once `reveal_window` has passed after their deadline
+pub(crate) fn check_if_can_end_vote(
+    proposal: &Proposal,
+    user: &Principal,
+    is_admin: bool,
+    now: u64,
+    reveal_window: u64,
+) -> Result<(), Error> {
+    if !is_owner(&proposal.owner, user, is_admin) {
+        return Err(Error::permission_error(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't update proposal with id={}. You are not the owner",
+                proposal.id
+            ),
+        ));
+    }
+    if proposal.status == ProposalStatus::Draft {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't end the vote on proposal with id={}. It is a draft",
+                proposal.id
+            ),
+        ));
+    }
+    if proposal.status == ProposalStatus::Cancelled {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't end the vote on proposal with id={}. It was cancelled",
+                proposal.id
+            ),
+        ));
+    }
+    if matches!(
+        proposal.status,
+        ProposalStatus::Blocked | ProposalStatus::Scheduled | ProposalStatus::Queued
+    ) {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't end the vote on proposal with id={}. Voting on it hasn't started",
+                proposal.id
+            ),
+        ));
+    }
+    if matches!(
+        proposal.status,
+        ProposalStatus::Approved | ProposalStatus::Rejected
+    ) {
+        return Err(Error::invalid_input(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Couldn't end the vote on proposal with id={}. Its vote is over",
+                proposal.id
+            ),
+        ));
+    }
+    if !is_deadline_passed(proposal.deadline, now) {
+        return Err(Error::deadline_not_exceeded(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Voting period for proposal with id={} isn't over.",
+                proposal.id
+            ),
+        ));
+    }
+    if proposal.anonymous_ballot
+        && !is_deadline_passed(proposal.deadline.saturating_add(reveal_window), now)
+    {
+        return Err(Error::deadline_not_exceeded(
+            ErrorContext::Proposal(proposal.id),
+            format!(
+                "Votes on proposal with id={} can still be revealed.",
+                proposal.id
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+// Whether enough of a DAO's members voted for the result to count. A quorum of 0 is always met
+pub(crate) fn meets_quorum(voter_count: u64, member_count: u64, quorum_percent: u8) -> bool {
+    voter_count as u128 * 100 >= quorum_percent as u128 * member_count as u128
+}
+
+// Whether a proposal with `voter_count` voters and these vote totals meets an early close rule.
+// Like tally the share is compared exactly, e.g. 2 downvotes of 3 meet 66 percent but not 67
+pub(crate) fn is_clearly_failing(
+    voter_count: u64,
+    upvotes: u64,
+    downvotes: u64,
+    rule: &EarlyClose,
+) -> bool {
+    let total_votes = upvotes as u128 + downvotes as u128;
+    voter_count >= rule.min_votes as u64
+        && total_votes > 0
+        && downvotes as u128 * 100 >= rule.reject_ratio_percent as u128 * total_votes
+}
+
+// The outcome of a proposal given its vote totals, weighted by role when the DAO weights votes.
+// It passes when upvotes make up at least `approval_threshold_percent` of the votes cast. A tie
+// never passes, so the default of 50 means strictly more upvotes than downvotes. The share is
+// compared exactly rather than rounded, e.g. 2 of 3 votes (66.6%) meets a threshold of 66 but
+// not 67
+pub(crate) fn tally(
+    upvotes: u64,
+    downvotes: u64,
+    approval_threshold_percent: u8,
+) -> ProposalStatus {
+    let upvotes = upvotes as u128;
+    let downvotes = downvotes as u128;
+    let total_votes = upvotes + downvotes;
+    // cross-multiplied in u128 so neither side can overflow or lose precision to division
+    let meets_threshold = upvotes * 100 >= approval_threshold_percent as u128 * total_votes;
+    if total_votes > 0 && upvotes != downvotes && meets_threshold {
+        ProposalStatus::Approved
+    } else {
+        ProposalStatus::Rejected
+    }
+}
+
+// Check that `voter` can upvote or downvote a proposal at `now`. Returns the proposal as it was
+// checked
+pub(crate) fn check_can_cast_vote(id: u64, voter: &Principal, now: u64) -> Result<Proposal, Error> {
+    match _get_proposal(&id) {
+        Some(proposal) => {
+            _check_can_vote_on(&proposal, voter, now)?;
+            Ok(proposal)
+        }
+        None => Err(Error::not_found(
+            ErrorContext::Proposal(id),
+            format!(
+                "couldn't vote on a proposal with id={}. proposal not found",
+                id
+            ),
+        )),
+    }
+}
+
+// Record the vote of `voter` on a proposal at `now`. A downvote that makes the proposal clearly
+// fail rejects it right away, see DaoSettings::early_close
+pub(crate) fn cast_vote(
+    id: u64,
+    direction: VoteDirection,
+    voter: &Principal,
+    now: u64,
+) -> Result<Proposal, Error> {
+    let proposal = check_can_cast_vote(id, voter, now)?;
+    // apply the vote to the latest copy so that nothing written since the checks is lost
+    let mut proposal = _get_proposal(&id).unwrap_or(proposal);
+    let weight = _get_dao(&proposal.dao_id)
+        .map(|dao| {
+            _get_settings(&dao.id)
+                .role_vote_weights
+                .unwrap_or_default()
+                .weight_of(_get_member_role(&dao, voter))
+        })
+        .unwrap_or(1);
+    _record_vote(
+        &mut proposal,
+        &Vote {
+            proposal_id: id,
+            voter: Some(*voter),
+            direction,
+            voted_at: now,
+            weight: Some(weight),
+            ranking: None,
+        },
+    );
+    _record_activity(&mut proposal, voter, now);
+
+    do_insert_proposal(&mut proposal);
+    reputation::award(proposal.dao_id, voter, reputation::VOTE_CAST_POINTS);
+    _count_activity(proposal.dao_id, voter, ActivityKind::Vote);
+    if _get_preferences(voter).auto_follow_on_vote {
+        _follow(&id, voter);
+    }
+    log_event(
+        proposal.dao_id,
+        AuditAction::VoteCast,
+        AuditTarget::Proposal(id),
+    );
+    if let Some(owner) = proposal.owner {
+        notify(
+            &owner,
+            NotificationKind::NewVote,
+            proposal.dao_id,
+            Some(id),
+            None,
+        );
+    }
+    if direction == VoteDirection::Down {
+        proposal = _close_if_clearly_failing(proposal, now);
+    }
+    Ok(proposal)
+}
+
+// End the vote on a proposal for `user` at `now` and record its outcome, see check_if_can_end_vote
+pub(crate) fn end_vote(id: u64, user: &Principal, now: u64) -> Result<Proposal, Error> {
+    let proposal = match _get_proposal(&id) {
+        Some(proposal) => proposal,
+        None => {
+            return Err(Error::not_found(
+                ErrorContext::Proposal(id),
+                format!(
+                    "couldn't update a proposal with id={}. proposal not found",
+                    id
+                ),
+            ))
+        }
+    };
+    check_if_can_end_vote(
+        &proposal,
+        user,
+        proposal.owner.is_none() && _is_canister_admin(user),
+        now,
+        _get_settings(&proposal.dao_id).reveal_window(),
+    )?;
+    _check_dao_not_archived(&proposal.dao_id)?;
+
+    Ok(_finalize_vote(proposal, None, now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEADLINE: u64 = 1_000;
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    // a DAO owned by user 1
+    fn dao() -> Dao {
+        Dao {
+            id: 7,
+            owner: Some(user(1)),
+            member_count: 3,
+            ..Default::default()
+        }
+    }
+
+    // users 2, 3 and 4 are members of dao() and user 5 an observer
+    fn membership(n: u8) -> Option<Membership> {
+        match n {
+            2..=4 => Some(Membership::Member),
+            5 => Some(Membership::Observer),
+            _ => None,
+        }
+    }
+
+    // an open proposal by user 2 in dao()
+    fn proposal() -> Proposal {
+        Proposal {
+            id: 11,
+            dao_id: 7,
+            owner: Some(user(2)),
+            deadline: DEADLINE,
+            status: ProposalStatus::Open,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tally_needs_a_strict_majority_by_default() {
+        assert!(tally(3, 2, 50) == ProposalStatus::Approved);
+        assert!(tally(2, 2, 50) == ProposalStatus::Rejected);
+        assert!(tally(2, 3, 50) == ProposalStatus::Rejected);
+        assert!(tally(1, 0, 50) == ProposalStatus::Approved);
+    }
+
+    #[test]
+    fn tally_rejects_ties_and_empty_votes_at_any_threshold() {
+        assert!(tally(0, 0, 0) == ProposalStatus::Rejected);
+        assert!(tally(0, 0, 50) == ProposalStatus::Rejected);
+        assert!(tally(5, 5, 0) == ProposalStatus::Rejected);
+        assert!(tally(u64::MAX, u64::MAX, 1) == ProposalStatus::Rejected);
+    }
+
+    #[test]
+    fn tally_compares_the_share_exactly() {
+        // 2 of 3 is 66.6%, so it meets 66 but not 67
+        assert!(tally(2, 1, 66) == ProposalStatus::Approved);
+        assert!(tally(2, 1, 67) == ProposalStatus::Rejected);
+        assert!(tally(67, 33, 67) == ProposalStatus::Approved);
+        assert!(tally(66, 34, 67) == ProposalStatus::Rejected);
+        assert!(tally(1, 0, 100) == ProposalStatus::Approved);
+        assert!(tally(99, 1, 100) == ProposalStatus::Rejected);
+    }
+
+    #[test]
+    fn tally_does_not_overflow_at_u64_extremes() {
+        assert!(tally(u64::MAX, 0, 100) == ProposalStatus::Approved);
+        assert!(tally(u64::MAX, u64::MAX - 1, 50) == ProposalStatus::Approved);
+        assert!(tally(u64::MAX - 1, u64::MAX, 50) == ProposalStatus::Rejected);
+        assert!(tally(0, u64::MAX, 1) == ProposalStatus::Rejected);
+        assert!(tally(u64::MAX, 1, u8::MAX) == ProposalStatus::Rejected);
+    }
+
+    #[test]
+    fn meets_quorum_compares_the_share_exactly() {
+        assert!(meets_quorum(0, 0, 0));
+        assert!(meets_quorum(0, 10, 0));
+        assert!(meets_quorum(5, 10, 50));
+        assert!(!meets_quorum(4, 10, 50));
+        assert!(meets_quorum(2, 3, 66));
+        assert!(!meets_quorum(2, 3, 67));
+        assert!(meets_quorum(3, 3, 100));
+        assert!(!meets_quorum(2, 3, 100));
+    }
+
+    #[test]
+    fn meets_quorum_does_not_overflow_at_u64_extremes() {
+        assert!(meets_quorum(u64::MAX, u64::MAX, 100));
+        assert!(!meets_quorum(u64::MAX - 1, u64::MAX, 100));
+        assert!(meets_quorum(u64::MAX / 2 + 1, u64::MAX, 50));
+        assert!(!meets_quorum(0, u64::MAX, 1));
+    }
+
+    #[test]
+    fn is_clearly_failing_needs_min_votes_and_the_ratio() {
+        let rule = EarlyClose {
+            min_votes: 3,
+            reject_ratio_percent: 67,
+        };
+        assert!(!is_clearly_failing(2, 0, 2, &rule));
+        assert!(is_clearly_failing(3, 0, 3, &rule));
+        // 2 downvotes of 3 is 66.6%
+        assert!(!is_clearly_failing(3, 1, 2, &rule));
+        let rule = EarlyClose {
+            min_votes: 3,
+            reject_ratio_percent: 66,
+        };
+        assert!(is_clearly_failing(3, 1, 2, &rule));
+        assert!(!is_clearly_failing(3, 2, 1, &rule));
+    }
+
+    #[test]
+    fn is_clearly_failing_ignores_proposals_without_votes() {
+        let rule = EarlyClose {
+            min_votes: 0,
+            reject_ratio_percent: 0,
+        };
+        assert!(!is_clearly_failing(0, 0, 0, &rule));
+    }
+
+    #[test]
+    fn is_clearly_failing_does_not_overflow_at_u64_extremes() {
+        let rule = EarlyClose {
+            min_votes: u32::MAX,
+            reject_ratio_percent: 100,
+        };
+        assert!(is_clearly_failing(u64::MAX, 0, u64::MAX, &rule));
+        assert!(!is_clearly_failing(u64::MAX, 1, u64::MAX, &rule));
+        assert!(!is_clearly_failing(u32::MAX as u64 - 1, 0, u64::MAX, &rule));
+    }
+
+    #[test]
+    fn slugify_joins_runs_of_letters_and_digits() {
+        assert_eq!(slugify("Builders' Guild!", 40, "dao"), "builders-guild");
+        assert_eq!(slugify("  Fund #42 -- now ", 40, "dao"), "fund-42-now");
+        assert_eq!(slugify("ÜBER café", 40, "dao"), "ber-caf");
+    }
+
+    #[test]
+    fn slugify_falls_back_when_nothing_is_left() {
+        assert_eq!(slugify("", 40, "dao"), "dao");
+        assert_eq!(slugify("!!! ???", 40, "proposal"), "proposal");
+        assert_eq!(slugify("日本語", 40, "dao"), "dao");
+    }
+
+    #[test]
+    fn slugify_truncates_without_a_trailing_hyphen() {
+        assert_eq!(slugify("abc def", 4, "dao"), "abc");
+        assert_eq!(slugify("abcdef", 3, "dao"), "abc");
+        assert_eq!(slugify("abc", 0, "dao"), "dao");
+    }
+
+    #[test]
+    fn numbered_slug_keeps_the_base_for_the_first_candidate() {
+        assert_eq!(numbered_slug("guild", 0), "guild");
+        assert_eq!(numbered_slug("guild", 1), "guild");
+        assert_eq!(numbered_slug("guild", 2), "guild-2");
+    }
+
+    #[test]
+    fn members_can_vote_before_the_deadline() {
+        assert!(check_if_can_vote(
+            &proposal(),
+            &dao(),
+            membership(3),
+            &[],
+            false,
+            &user(3),
+            DEADLINE
+        )
+        .is_ok());
+        assert!(
+            check_if_can_vote(&proposal(), &dao(), membership(1), &[], false, &user(1), 0).is_ok()
+        );
+    }
+
+    #[test]
+    fn voting_closes_after_the_deadline() {
+        let result = check_if_can_vote(
+            &proposal(),
+            &dao(),
+            membership(3),
+            &[],
+            false,
+            &user(3),
+            DEADLINE + 1,
+        );
+        assert!(matches!(result, Err(Error::DeadlineExceeded { .. })));
+        let result = check_if_can_vote(
+            &proposal(),
+            &dao(),
+            membership(3),
+            &[],
+            false,
+            &user(3),
+            u64::MAX,
+        );
+        assert!(matches!(result, Err(Error::DeadlineExceeded { .. })));
+    }
+
+    #[test]
+    fn outsiders_and_observers_cannot_vote() {
+        let result = check_if_can_vote(&proposal(), &dao(), membership(6), &[], false, &user(6), 0);
+        assert!(matches!(result, Err(Error::NotAMember { .. })));
+        let result = check_if_can_vote(&proposal(), &dao(), membership(5), &[], false, &user(5), 0);
+        assert!(matches!(result, Err(Error::NotAMember { .. })));
+    }
+
+    #[test]
+    fn authors_cannot_vote_on_their_proposal() {
+        let result = check_if_can_vote(&proposal(), &dao(), membership(2), &[], false, &user(2), 0);
+        assert!(matches!(result, Err(Error::CantVoteYours { .. })));
+        let result = check_if_can_vote(
+            &proposal(),
+            &dao(),
+            membership(4),
+            &[user(4)],
+            false,
+            &user(4),
+            0,
+        );
+        assert!(matches!(result, Err(Error::CantVoteYours { .. })));
+    }
+
+    #[test]
+    fn members_vote_once() {
+        let result = check_if_can_vote(&proposal(), &dao(), membership(3), &[], true, &user(3), 0);
+        assert!(matches!(result, Err(Error::HasVoted { .. })));
+    }
+
+    #[test]
+    fn only_open_proposals_take_votes() {
+        for status in [
+            ProposalStatus::Draft,
+            ProposalStatus::Cancelled,
+            ProposalStatus::Scheduled,
+            ProposalStatus::Queued,
+            ProposalStatus::Blocked,
+            ProposalStatus::Approved,
+            ProposalStatus::Rejected,
+        ] {
+            let mut proposal = proposal();
+            proposal.status = status;
+            let result =
+                check_if_can_vote(&proposal, &dao(), membership(3), &[], false, &user(3), 0);
+            assert!(matches!(result, Err(Error::InvalidInput { .. })));
+        }
+    }
+
+    #[test]
+    fn owners_end_the_vote_after_the_deadline() {
+        let result = check_if_can_end_vote(&proposal(), &user(2), false, DEADLINE, 0);
+        assert!(matches!(result, Err(Error::DeadlineNotExceeded { .. })));
+        assert!(check_if_can_end_vote(&proposal(), &user(2), false, DEADLINE + 1, 0).is_ok());
+        assert!(check_if_can_end_vote(&proposal(), &user(2), false, u64::MAX, u64::MAX).is_ok());
+    }
+
+    #[test]
+    fn only_the_owner_ends_the_vote() {
+        let result = check_if_can_end_vote(&proposal(), &user(3), false, DEADLINE + 1, 0);
+        assert!(matches!(result, Err(Error::PermissionError { .. })));
+    }
+
+    #[test]
+    fn anonymous_ballots_end_after_the_reveal_window() {
+        let mut proposal = proposal();
+        proposal.anonymous_ballot = true;
+        let result = check_if_can_end_vote(&proposal, &user(2), false, DEADLINE + 10, 10);
+        assert!(matches!(result, Err(Error::DeadlineNotExceeded { .. })));
+        assert!(check_if_can_end_vote(&proposal, &user(2), false, DEADLINE + 11, 10).is_ok());
+        // the window can't push the end past u64::MAX
+        proposal.deadline = u64::MAX - 1;
+        let result = check_if_can_end_vote(&proposal, &user(2), false, u64::MAX, u64::MAX);
+        assert!(matches!(result, Err(Error::DeadlineNotExceeded { .. })));
+    }
+
+    #[test]
+    fn votes_that_never_started_cannot_end() {
+        for status in [
+            ProposalStatus::Draft,
+            ProposalStatus::Cancelled,
+            ProposalStatus::Scheduled,
+            ProposalStatus::Queued,
+            ProposalStatus::Blocked,
+        ] {
+            let mut proposal = proposal();
+            proposal.status = status;
+            let result = check_if_can_end_vote(&proposal, &user(2), false, DEADLINE + 1, 0);
+            assert!(matches!(result, Err(Error::InvalidInput { .. })));
+        }
+    }
+
+    #[test]
+    fn finalized_votes_cannot_end_again() {
+        for status in [ProposalStatus::Approved, ProposalStatus::Rejected] {
+            let mut proposal = proposal();
+            proposal.status = status;
+            let result = check_if_can_end_vote(&proposal, &user(2), false, DEADLINE + 1, 0);
+            assert!(matches!(result, Err(Error::InvalidInput { .. })));
+        }
+    }
+
+    #[test]
+    fn owners_are_matched_exactly() {
+        assert!(is_owner(&Some(user(1)), &user(1), false));
+        assert!(!is_owner(&Some(user(1)), &user(2), false));
+        assert!(!is_owner(&Some(user(1)), &user(2), true));
+    }
+
+    #[test]
+    fn only_admins_manage_ownerless_records() {
+        assert!(!is_owner(&None, &user(1), false));
+        assert!(is_owner(&None, &user(1), true));
+
+        let mut proposal = proposal();
+        proposal.owner = None;
+        let result = check_if_can_end_vote(&proposal, &user(2), false, DEADLINE + 1, 0);
+        assert!(matches!(result, Err(Error::PermissionError { .. })));
+        assert!(check_if_can_end_vote(&proposal, &user(2), true, DEADLINE + 1, 0).is_ok());
+    }
+
+    #[test]
+    fn observers_read_but_are_not_members() {
+        assert!(is_member(&dao(), &user(1), membership(1)));
+        assert!(is_member(&dao(), &user(3), membership(3)));
+        assert!(!is_member(&dao(), &user(5), membership(5)));
+        assert!(can_read(&dao(), &user(5), membership(5)));
+        assert!(!can_read(&dao(), &user(6), membership(6)));
+    }
+
+    #[test]
+    fn dedupe_principals_keeps_the_first_occurrence() {
+        let mut principals = vec![user(3), user(1), user(3), user(2), user(1)];
+        assert_eq!(dedupe_principals(&mut principals), 2);
+        assert_eq!(principals, vec![user(3), user(1), user(2)]);
+    }
+}
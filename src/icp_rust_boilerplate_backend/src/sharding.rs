@@ -0,0 +1,786 @@
+// Spreads DAOs over bucket canisters. This canister, the main one, keeps every DAO and a routing
+// table from dao_id to the bucket each new DAO was placed in. The proposals, comments and votes
+// of a routed DAO live in its bucket and calls about them are forwarded there, clients keep
+// calling the main canister with the same candid interface. Buckets run the same wasm, installed
+// with InitArgs::bucket, and only take calls from their main canister
+//
+// The admin uploads the wasm with upload_bucket_wasm and spawns the first bucket with
+// spawn_bucket. From then on new DAOs fill the last bucket up to max_daos_per_bucket and the next
+// one is spawned in the background once it is full. DAOs created before the first bucket, or
+// while the next one is being spawned, stay here like every DAO did before
+//
+// add_proposal, upvote, downvote, end_proposal_vote and comment_on_post are forwarded, and so are
+// the composite queries get_proposal, get_all_proposals and get_all_comments_on_proposal. Left
+// for follow-ups:
+// - the other proposal and comment endpoints, which only see the DAOs kept here
+// - proposal deposits and treasury payments, which routed DAOs don't take
+// - moving existing DAOs to a bucket, and upgrading buckets
+// - the notifications, reputation and audit log of routed DAOs, which stay in their bucket, and
+//   mentions, which only resolve to the users of the call
+use crate::{
+    _get_dao, _get_settings, _membership, caller, do_insert_dao,
+    env::{act_for, canister_id, set_timer, stable_pages, time},
+    metrics::WASM_PAGE_SIZE,
+    principal_key, CanisterConfig, Comment, CommentPayload, CommentView, Dao, DaoSettings, Error,
+    ErrorContext, InitArgs, Membership, ProposalPayload, ProposalView, BUCKETS, BUCKET_WASM,
+    COMMENT_STORAGE, CONFIG, DAO_MEMBERSHIPS, DAO_ROUTES, DAO_SETTINGS, DAO_STORAGE,
+    DUES_PAID_UNTIL, ID_COUNTER, MEMBER_JOINED_AT, PROPOSAL_STORAGE, ROUTED_PROPOSALS,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::call::CallResult;
+use ic_stable_structures::{BoundedStorable, Storable};
+use std::{borrow::Cow, cell::Cell, time::Duration};
+
+// Stable memory a shard can use before new DAOs should be placed in another one
+const SHARD_CAPACITY_BYTES: u64 = 8 * 1024 * 1024 * 1024;
+// Number of DAOs listed as the largest ones of a shard
+const LARGEST_DAOS: usize = 10;
+// Ids of records created in the bucket with index i start at (i + 1) * ID_SPAN and the ones
+// created here stay below ID_SPAN, so an id is never taken twice across canisters
+const ID_SPAN: u64 = 1 << 40;
+// DAOs placed in a bucket before the next one is spawned, unless max_daos_per_bucket is set
+pub(crate) const DEFAULT_MAX_DAOS_PER_BUCKET: u64 = 10_000;
+// Cycles a bucket is created with
+#[cfg(not(test))]
+const BUCKET_CYCLES: u128 = 2_000_000_000_000;
+
+thread_local! {
+    // set while a bucket is being spawned, so a full bucket spawns the next one once
+    static SPAWNING: Cell<bool> = const { Cell::new(false) };
+}
+
+// How full a shard is
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ShardInfo {
+    shard: Principal,
+    dao_count: u64,
+    proposal_count: u64,
+    comment_count: u64,
+    stable_memory_bytes: u64,
+    capacity_bytes: u64,
+    // whether the shard has reached its capacity
+    full: bool,
+    // (dao_id, proposal count) of the DAOs with the most proposals, the first to move out
+    largest_daos: Vec<(u64, u64)>,
+    // the buckets this canister spawned, in the order they were, and how many DAOs each holds
+    buckets: Vec<Bucket>,
+    max_daos_per_bucket: u64,
+    bucket_wasm_bytes: u64,
+    spawning: bool,
+    // the main canister, when this canister is one of its buckets
+    main_canister: Option<Principal>,
+}
+
+// What a bucket is installed with
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct BucketInit {
+    main: Principal,
+    // where the ids of the bucket's records start
+    first_id: u64,
+}
+
+// A bucket canister spawned by this one
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Bucket {
+    canister_id: Principal,
+    dao_count: u64,
+    first_id: u64,
+    created_at: u64,
+}
+
+impl Storable for Bucket {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for Bucket {
+    const MAX_SIZE: u32 = 128;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// The wasm module buckets are installed with, kept as it was uploaded
+#[derive(Default)]
+pub(crate) struct BucketWasm(Vec<u8>);
+
+impl Storable for BucketWasm {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        BucketWasm(bytes.into_owned())
+    }
+}
+
+// How a user of a forwarded call belongs to its DAO. Members are kept here, the bucket gets the
+// standing of the users a call is about with every call
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Standing {
+    user: Principal,
+    membership: Option<Membership>,
+    joined_at: Option<u64>,
+    paid_until: Option<u64>,
+}
+
+// What a bucket needs to know about a DAO to take a call about it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DaoContext {
+    dao: Dao,
+    settings: DaoSettings,
+    standings: Vec<Standing>,
+}
+
+// The calls forwarded to a bucket, named after the endpoints they are made to
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) enum BucketCall {
+    AddProposal(ProposalPayload),
+    Upvote(u64),
+    Downvote(u64),
+    EndProposalVote(u64),
+    CommentOnPost(CommentPayload),
+    GetProposal(u64),
+    GetAllProposals(u64),
+    GetAllCommentsOnProposal(u64),
+}
+
+impl BucketCall {
+    fn is_query(&self) -> bool {
+        matches!(
+            self,
+            BucketCall::GetProposal(_)
+                | BucketCall::GetAllProposals(_)
+                | BucketCall::GetAllCommentsOnProposal(_)
+        )
+    }
+}
+
+// A call forwarded by the main canister on behalf of `caller`
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct BucketRequest {
+    caller: Principal,
+    context: DaoContext,
+    call: BucketCall,
+}
+
+// What a forwarded call returned, by the type its endpoint returns
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) enum BucketReply {
+    Proposal(ProposalView),
+    Proposals(Vec<ProposalView>),
+    Comment(Comment),
+    Comments(Vec<CommentView>),
+}
+
+impl BucketReply {
+    pub(crate) fn into_proposal(self) -> Result<ProposalView, Error> {
+        match self {
+            BucketReply::Proposal(proposal) => Ok(proposal),
+            _ => Err(unexpected_reply()),
+        }
+    }
+
+    pub(crate) fn into_proposals(self) -> Result<Vec<ProposalView>, Error> {
+        match self {
+            BucketReply::Proposals(proposals) => Ok(proposals),
+            _ => Err(unexpected_reply()),
+        }
+    }
+
+    pub(crate) fn into_comment(self) -> Result<Comment, Error> {
+        match self {
+            BucketReply::Comment(comment) => Ok(comment),
+            _ => Err(unexpected_reply()),
+        }
+    }
+
+    pub(crate) fn into_comments(self) -> Result<Vec<CommentView>, Error> {
+        match self {
+            BucketReply::Comments(comments) => Ok(comments),
+            _ => Err(unexpected_reply()),
+        }
+    }
+}
+
+fn unexpected_reply() -> Error {
+    Error::bucket_unavailable(
+        ErrorContext::None,
+        "the bucket answered with a reply of another call".to_string(),
+    )
+}
+
+pub(crate) fn shard_info() -> ShardInfo {
+    let stable_memory_bytes = stable_pages() * WASM_PAGE_SIZE;
+    let mut largest_daos: Vec<(u64, u64)> = DAO_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(id, dao)| (id, dao.proposal_count))
+            .collect()
+    });
+    largest_daos.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    largest_daos.truncate(LARGEST_DAOS);
+
+    ShardInfo {
+        shard: canister_id(),
+        dao_count: DAO_STORAGE.with(|service| service.borrow().len()),
+        proposal_count: PROPOSAL_STORAGE.with(|service| service.borrow().len()),
+        comment_count: COMMENT_STORAGE.with(|service| service.borrow().len()),
+        stable_memory_bytes,
+        capacity_bytes: SHARD_CAPACITY_BYTES,
+        full: stable_memory_bytes >= SHARD_CAPACITY_BYTES,
+        largest_daos,
+        buckets: BUCKETS
+            .with(|service| service.borrow().iter().map(|(_, bucket)| bucket).collect()),
+        max_daos_per_bucket: max_daos_per_bucket(),
+        bucket_wasm_bytes: BUCKET_WASM.with(|wasm| wasm.borrow().get().0.len() as u64),
+        spawning: SPAWNING.with(Cell::get),
+        main_canister: main_canister(),
+    }
+}
+
+// Make this canister a bucket of `init.main`, taking ids from `init.first_id` on
+pub(crate) fn become_bucket(init: BucketInit, config: &mut CanisterConfig) {
+    config.main_canister = Some(init.main);
+    ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value.max(init.first_id))
+        })
+        .expect("cannot set the id counter");
+}
+
+fn main_canister() -> Option<Principal> {
+    CONFIG.with(|config| config.borrow().get().main_canister)
+}
+
+fn max_daos_per_bucket() -> u64 {
+    CONFIG
+        .with(|config| config.borrow().get().max_daos_per_bucket)
+        .unwrap_or(DEFAULT_MAX_DAOS_PER_BUCKET)
+}
+
+// Refuses calls a bucket takes from anyone but its main canister
+pub(crate) fn check_direct_call() -> Result<(), String> {
+    match main_canister() {
+        Some(main) if crate::env::caller() != main => Err(format!(
+            "Unauthorized: this canister is a bucket of {}, call it instead",
+            main
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Refuses forwarded calls unless this canister is a bucket and they come from its main canister
+pub(crate) fn check_forwarded() -> Result<(), String> {
+    match main_canister() {
+        Some(main) if crate::env::caller() == main => Ok(()),
+        _ => {
+            Err("Unauthorized: only the main canister of a bucket forwards calls to it".to_string())
+        }
+    }
+}
+
+// Add a chunk to the bucket wasm, or start it over with the chunk. Returns its size so far
+pub(crate) fn upload_wasm(chunk: Vec<u8>, append: bool) -> u64 {
+    BUCKET_WASM.with(|wasm| {
+        let mut module = if append {
+            wasm.borrow().get().0.clone()
+        } else {
+            Vec::new()
+        };
+        module.extend(chunk);
+        let len = module.len() as u64;
+        wasm.borrow_mut()
+            .set(BucketWasm(module))
+            .expect("cannot store the bucket wasm");
+        len
+    })
+}
+
+// Place a new DAO in the last bucket while it has room, and spawn the next bucket in the
+// background once it is full. Nothing is placed until the admin spawned a first bucket
+pub(crate) fn place(dao_id: u64) {
+    let Some((index, mut bucket)) = BUCKETS.with(|service| service.borrow().iter().last()) else {
+        return;
+    };
+    let max_daos = max_daos_per_bucket();
+    if bucket.dao_count < max_daos {
+        bucket.dao_count += 1;
+        BUCKETS.with(|service| service.borrow_mut().insert(index, bucket.clone()));
+        DAO_ROUTES.with(|service| service.borrow_mut().insert(dao_id, index));
+    }
+    if bucket.dao_count >= max_daos {
+        schedule_spawn();
+    }
+}
+
+fn schedule_spawn() {
+    if SPAWNING.with(|spawning| spawning.replace(true)) {
+        return;
+    }
+    set_timer(Duration::ZERO, || {
+        ic_cdk::spawn(async {
+            // a failed spawn is tried again by the next DAO finding the bucket full
+            let _ = spawn().await;
+            SPAWNING.with(|spawning| spawning.set(false));
+        })
+    });
+}
+
+// Spawn a bucket now, unless one is being spawned already
+pub(crate) async fn spawn_bucket() -> Result<Bucket, Error> {
+    if SPAWNING.with(|spawning| spawning.replace(true)) {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            "a bucket is being spawned already, try again shortly".to_string(),
+        ));
+    }
+    let result = spawn().await;
+    SPAWNING.with(|spawning| spawning.set(false));
+    result
+}
+
+// Create a canister and install the bucket wasm in it. A canister the wasm couldn't be installed
+// in is left behind, the next spawn creates another one
+async fn spawn() -> Result<Bucket, Error> {
+    let wasm = BUCKET_WASM.with(|wasm| wasm.borrow().get().0.clone());
+    if wasm.is_empty() {
+        return Err(Error::invalid_input(
+            ErrorContext::None,
+            "Couldn't spawn a bucket. Upload its wasm with upload_bucket_wasm first".to_string(),
+        ));
+    }
+    let index = BUCKETS.with(|service| service.borrow().len());
+    let first_id = (index + 1) * ID_SPAN;
+    let args = InitArgs {
+        bucket: Some(BucketInit {
+            main: canister_id(),
+            first_id,
+        }),
+        ..Default::default()
+    };
+    let canister_id =
+        install(wasm, Encode!(&Some(args)).unwrap())
+            .await
+            .map_err(|(code, msg)| {
+                Error::bucket_unavailable(
+                    ErrorContext::None,
+                    format!("Couldn't spawn a bucket: {:?} {}", code, msg),
+                )
+            })?;
+
+    let bucket = Bucket {
+        canister_id,
+        dao_count: 0,
+        first_id,
+        created_at: time(),
+    };
+    BUCKETS.with(|service| service.borrow_mut().insert(index, bucket.clone()));
+    Ok(bucket)
+}
+
+#[cfg(not(test))]
+async fn install(wasm_module: Vec<u8>, arg: Vec<u8>) -> CallResult<Principal> {
+    use ic_cdk::api::management_canister::main::{
+        create_canister, install_code, CanisterInstallMode, CreateCanisterArgument,
+        InstallCodeArgument,
+    };
+
+    let (created,) = create_canister(CreateCanisterArgument::default(), BUCKET_CYCLES).await?;
+    install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Install,
+        canister_id: created.canister_id,
+        wasm_module,
+        arg,
+    })
+    .await?;
+    Ok(created.canister_id)
+}
+
+// Natively nothing is installed, each bucket gets a principal of its own
+#[cfg(test)]
+async fn install(_wasm_module: Vec<u8>, _arg: Vec<u8>) -> CallResult<Principal> {
+    let index = BUCKETS.with(|service| service.borrow().len()) as u8;
+    Ok(Principal::from_slice(&[0xb0, index]))
+}
+
+pub(crate) fn is_routed(dao_id: u64) -> bool {
+    DAO_ROUTES.with(|service| service.borrow().contains_key(&dao_id))
+}
+
+// The DAO of a proposal created in a bucket, None for proposals kept here
+pub(crate) fn routed_proposal(proposal_id: u64) -> Option<u64> {
+    ROUTED_PROPOSALS.with(|service| service.borrow().get(&proposal_id))
+}
+
+// Note a proposal a bucket created, and count it in the DAO kept here the way the bucket did
+pub(crate) fn record_proposal(proposal: &ProposalView) {
+    ROUTED_PROPOSALS.with(|service| service.borrow_mut().insert(proposal.id, proposal.dao_id));
+    if let Some(mut dao) = _get_dao(&proposal.dao_id) {
+        dao.proposal_count += 1;
+        dao.last_proposal_number = dao.last_proposal_number.max(proposal.proposal_number);
+        dao.updated_at = Some(time());
+        do_insert_dao(&mut dao);
+    }
+}
+
+// Forward a call about a routed DAO to its bucket on behalf of the caller. The standing of the
+// caller and of the co-authors a proposal names goes along
+pub(crate) async fn forward(dao_id: u64, call: BucketCall) -> Result<BucketReply, Error> {
+    let bucket = DAO_ROUTES
+        .with(|service| service.borrow().get(&dao_id))
+        .and_then(|index| BUCKETS.with(|service| service.borrow().get(&index)));
+    let (Some(bucket), Some(dao)) = (bucket, _get_dao(&dao_id)) else {
+        return Err(crate::_dao_not_found_error(&dao_id));
+    };
+
+    let mut users = vec![caller()];
+    if let BucketCall::AddProposal(payload) = &call {
+        users.extend(payload.co_authors.iter().flatten());
+    }
+    let method = if call.is_query() {
+        "bucket_query"
+    } else {
+        "bucket_update"
+    };
+    let request = BucketRequest {
+        caller: caller(),
+        context: DaoContext {
+            standings: users.iter().map(|user| standing(dao_id, user)).collect(),
+            settings: _get_settings(&dao_id),
+            dao,
+        },
+        call,
+    };
+    match call_bucket(bucket.canister_id, method, request).await {
+        Ok((reply,)) => reply,
+        Err((code, msg)) => Err(Error::bucket_unavailable(
+            ErrorContext::Dao(dao_id),
+            format!(
+                "the bucket of dao with id={} didn't take the call: {:?} {}",
+                dao_id, code, msg
+            ),
+        )),
+    }
+}
+
+fn standing(dao_id: u64, user: &Principal) -> Standing {
+    let key = (dao_id, principal_key(user));
+    Standing {
+        user: *user,
+        membership: _membership(dao_id, user),
+        joined_at: MEMBER_JOINED_AT.with(|service| service.borrow().get(&key)),
+        paid_until: DUES_PAID_UNTIL.with(|service| service.borrow().get(&key)),
+    }
+}
+
+#[cfg(not(test))]
+async fn call_bucket(
+    bucket: Principal,
+    method: &str,
+    request: BucketRequest,
+) -> CallResult<(Result<BucketReply, Error>,)> {
+    ic_cdk::call(bucket, method, (request,)).await
+}
+
+// Natively there are no buckets to call
+#[cfg(test)]
+async fn call_bucket(
+    _bucket: Principal,
+    _method: &str,
+    _request: BucketRequest,
+) -> CallResult<(Result<BucketReply, Error>,)> {
+    Err((
+        ic_cdk::api::call::RejectionCode::DestinationInvalid,
+        "natively there are no buckets".to_string(),
+    ))
+}
+
+// Run a forwarded call in this bucket as the user who made it, with the DAO as the main canister
+// sent it
+pub(crate) async fn handle_update(request: BucketRequest) -> Result<BucketReply, Error> {
+    adopt(request.context);
+    act_for(Some(request.caller));
+    // add_proposal only awaits a deposit, which adopt leaves out, so nothing else runs as the
+    // user before the override is cleared
+    let reply = match request.call {
+        BucketCall::AddProposal(payload) => crate::add_proposal(payload)
+            .await
+            .map(BucketReply::Proposal),
+        BucketCall::Upvote(id) => crate::upvote(id).map(BucketReply::Proposal),
+        BucketCall::Downvote(id) => crate::downvote(id).map(BucketReply::Proposal),
+        BucketCall::EndProposalVote(id) => crate::end_proposal_vote(id).map(BucketReply::Proposal),
+        BucketCall::CommentOnPost(payload) => {
+            crate::comment_on_post(payload).map(BucketReply::Comment)
+        }
+        call => run_query(call),
+    };
+    act_for(None);
+    reply
+}
+
+// Like handle_update for the calls of composite queries
+pub(crate) fn handle_query(request: BucketRequest) -> Result<BucketReply, Error> {
+    adopt(request.context);
+    act_for(Some(request.caller));
+    let reply = run_query(request.call);
+    act_for(None);
+    reply
+}
+
+fn run_query(call: BucketCall) -> Result<BucketReply, Error> {
+    match call {
+        BucketCall::GetProposal(id) => crate::get_proposal(id).map(BucketReply::Proposal),
+        BucketCall::GetAllProposals(dao_id) => {
+            crate::get_all_proposals(dao_id).map(BucketReply::Proposals)
+        }
+        BucketCall::GetAllCommentsOnProposal(proposal_id) => {
+            crate::get_all_comments_on_proposal(proposal_id).map(BucketReply::Comments)
+        }
+        _ => Err(Error::invalid_input(
+            ErrorContext::None,
+            "bucket_query only takes the calls of queries".to_string(),
+        )),
+    }
+}
+
+// Keep what the main canister sent about a DAO and the users of a call. The bucket counts and
+// numbers the DAO's proposals itself
+fn adopt(context: DaoContext) {
+    let DaoContext {
+        mut dao,
+        mut settings,
+        standings,
+    } = context;
+    let kept = _get_dao(&dao.id).unwrap_or_default();
+    dao.proposal_count = kept.proposal_count;
+    dao.last_proposal_number = kept.last_proposal_number;
+    // deposits go to the treasury of the main canister, which doesn't take them for routed DAOs
+    settings.proposal_deposit = None;
+    for standing in standings {
+        let key = (dao.id, principal_key(&standing.user));
+        DAO_MEMBERSHIPS.with(|service| match standing.membership {
+            Some(membership) => service.borrow_mut().insert(key, membership),
+            None => service.borrow_mut().remove(&key),
+        });
+        MEMBER_JOINED_AT.with(|service| match standing.joined_at {
+            Some(joined_at) => service.borrow_mut().insert(key, joined_at),
+            None => service.borrow_mut().remove(&key),
+        });
+        DUES_PAID_UNTIL.with(|service| match standing.paid_until {
+            Some(paid_until) => service.borrow_mut().insert(key, paid_until),
+            None => service.borrow_mut().remove(&key),
+        });
+    }
+    DAO_SETTINGS.with(|service| service.borrow_mut().insert(dao.id, settings));
+    DAO_STORAGE.with(|service| service.borrow_mut().insert(dao.id, dao));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{caller as message_caller, set_caller};
+    use crate::{Proposal, ProposalStatus};
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[n, 0x5a])
+    }
+
+    // natively installs and bucket calls answer right away, so a spawn or a forwarded call is
+    // done after its first poll
+    fn run<F: std::future::Future>(future: F) -> F::Output {
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        match std::pin::pin!(future).as_mut().poll(&mut context) {
+            std::task::Poll::Ready(output) => output,
+            std::task::Poll::Pending => panic!("awaited a call to another canister"),
+        }
+    }
+
+    fn set_max_daos_per_bucket(max: u64) {
+        CONFIG.with(|config| {
+            let mut updated = config.borrow().get().clone();
+            updated.max_daos_per_bucket = Some(max);
+            config.borrow_mut().set(updated).unwrap();
+        });
+    }
+
+    #[test]
+    fn lists_the_daos_with_the_most_proposals_first() {
+        for (id, proposal_count) in (1..=12).map(|id| (id, id % 4)) {
+            let dao = Dao {
+                id,
+                proposal_count,
+                ..Default::default()
+            };
+            DAO_STORAGE.with(|service| service.borrow_mut().insert(id, dao));
+        }
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(1, Proposal::default()));
+
+        let info = shard_info();
+        assert_eq!(
+            (info.dao_count, info.proposal_count, info.comment_count),
+            (12, 1, 0)
+        );
+        assert_eq!(info.largest_daos.len(), LARGEST_DAOS);
+        // ties on the proposal count go to the oldest DAO
+        assert_eq!(&info.largest_daos[..4], &[(3, 3), (7, 3), (11, 3), (2, 2)]);
+        assert!(!info.full && info.capacity_bytes == SHARD_CAPACITY_BYTES);
+    }
+
+    #[test]
+    fn fills_a_bucket_and_asks_for_the_next() {
+        // nothing is placed before the first bucket
+        place(1);
+        assert!(!is_routed(1));
+        assert!(run(spawn_bucket()).is_err());
+
+        upload_wasm(vec![0, 0x61], false);
+        assert_eq!(upload_wasm(vec![0x73, 0x6d], true), 4);
+        let first = run(spawn_bucket()).ok().unwrap();
+        assert_eq!(first.first_id, ID_SPAN);
+        set_max_daos_per_bucket(2);
+
+        place(2);
+        assert!(is_routed(2) && !SPAWNING.with(Cell::get));
+        // the second DAO fills the bucket, the next one is spawned in the background
+        place(3);
+        assert!(is_routed(3) && SPAWNING.with(Cell::get));
+        // and DAOs created meanwhile stay here
+        place(4);
+        assert!(!is_routed(4));
+        assert!(run(spawn_bucket()).is_err());
+
+        SPAWNING.with(|spawning| spawning.set(false));
+        let second = run(spawn_bucket()).ok().unwrap();
+        assert_eq!(second.first_id, 2 * ID_SPAN);
+        assert_ne!(second.canister_id, first.canister_id);
+        place(5);
+        assert_eq!(DAO_ROUTES.with(|service| service.borrow().get(&5)), Some(1));
+        let info = shard_info();
+        let counts: Vec<u64> = info.buckets.iter().map(|bucket| bucket.dao_count).collect();
+        assert_eq!(counts, [2, 1]);
+    }
+
+    #[test]
+    fn forwarding_fails_without_a_bucket_to_take_the_call() {
+        upload_wasm(vec![1], false);
+        run(spawn_bucket()).ok().unwrap();
+        let dao = Dao {
+            id: 1,
+            owner: Some(user(1)),
+            ..Default::default()
+        };
+        DAO_STORAGE.with(|service| service.borrow_mut().insert(1, dao));
+        place(1);
+
+        assert!(matches!(
+            run(forward(1, BucketCall::GetAllProposals(1))),
+            Err(Error::BucketUnavailable { .. })
+        ));
+        // a DAO that isn't routed has no bucket to forward to
+        assert!(matches!(
+            run(forward(2, BucketCall::GetAllProposals(2))),
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn runs_forwarded_calls_as_their_user() {
+        let main = user(9);
+        let mut config = CanisterConfig::default();
+        become_bucket(
+            BucketInit {
+                main,
+                first_id: ID_SPAN,
+            },
+            &mut config,
+        );
+        CONFIG.with(|cell| cell.borrow_mut().set(config).unwrap());
+        set_caller(user(2));
+        assert!(check_direct_call().is_err() && check_forwarded().is_err());
+        set_caller(main);
+        assert!(check_direct_call().is_ok() && check_forwarded().is_ok());
+
+        let (owner, member) = (user(1), user(2));
+        let context = |standing_of: Principal, membership: Option<Membership>| DaoContext {
+            dao: Dao {
+                id: 7,
+                owner: Some(owner),
+                name: "Routed".to_string(),
+                proposal_count: 5,
+                ..Default::default()
+            },
+            settings: DaoSettings {
+                dao_id: 7,
+                proposal_deposit: Some(100),
+                ..Default::default()
+            },
+            standings: vec![Standing {
+                user: standing_of,
+                membership,
+                joined_at: None,
+                paid_until: None,
+            }],
+        };
+        let proposal = run(handle_update(BucketRequest {
+            caller: member,
+            context: context(member, Some(Membership::Member)),
+            call: BucketCall::AddProposal(ProposalPayload {
+                dao_id: 7,
+                title: "Forwarded".to_string(),
+                details: "Details".to_string(),
+                ..Default::default()
+            }),
+        }))
+        .ok()
+        .unwrap()
+        .into_proposal()
+        .ok()
+        .unwrap();
+        // made by the user, with an id of the bucket and no deposit
+        assert!(proposal.owner == Some(member) && proposal.id >= ID_SPAN);
+        assert!(proposal.deposit.is_none() && proposal.status == ProposalStatus::Open);
+        assert_eq!(message_caller(), main);
+        // the bucket counts the DAO's proposals itself
+        let dao = _get_dao(&7).unwrap();
+        assert_eq!((dao.name.as_str(), dao.proposal_count), ("Routed", 1));
+
+        // the owner votes, and a user the main canister says isn't a member doesn't even see
+        // the proposal of the private DAO
+        let vote = |voter: Principal, membership: Option<Membership>| {
+            run(handle_update(BucketRequest {
+                caller: voter,
+                context: context(voter, membership),
+                call: BucketCall::Upvote(proposal.id),
+            }))
+        };
+        assert!(vote(owner, None).ok().unwrap().into_proposal().is_ok());
+        assert!(matches!(vote(user(3), None), Err(Error::NotFound { .. })));
+        // a member the main canister banned since is refused too
+        assert!(vote(member, Some(Membership::Banned)).is_err());
+
+        let listed = handle_query(BucketRequest {
+            caller: owner,
+            context: context(owner, None),
+            call: BucketCall::GetAllProposals(7),
+        })
+        .ok()
+        .unwrap()
+        .into_proposals()
+        .ok()
+        .unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].upvote_count, Some(1));
+        assert!(handle_query(BucketRequest {
+            caller: owner,
+            context: context(owner, None),
+            call: BucketCall::Upvote(proposal.id),
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{
+    env::stable_pages, log_event, maintenance::CANISTER_AUDIT_SCOPE, metrics::WASM_PAGE_SIZE,
+    AuditAction, AuditTarget, CONFIG,
+};
+use std::cell::Cell;
+
+// Stable memory the canister fills before refusing new records, unless the admin set
+// storage_soft_limit_bytes. Kept below sharding::SHARD_CAPACITY_BYTES so there is room left to
+// finish the writes already under way
+pub(crate) const DEFAULT_SOFT_LIMIT_BYTES: u64 = 7 * 1024 * 1024 * 1024;
+// How many checks reuse the cached page count before stable memory is measured again
+const REFRESH_EVERY: u64 = 100;
+// Usage, in percent of the soft limit, whose crossing is logged for the admin
+const ALERT_PERCENTS: [u8; 2] = [80, 95];
+
+thread_local! {
+    // the stable memory size in pages when it was last measured, and the checks made since
+    static CACHED_PAGES: Cell<Option<(u64, u64)>> = const { Cell::new(None) };
+    // the highest of ALERT_PERCENTS usage has crossed, 0 while below all of them
+    static ALERT_LEVEL: Cell<u8> = const { Cell::new(0) };
+}
+
+// Whether stable memory reached the soft limit. Endpoints that add records check this first and
+// refuse to run when it did. Reads, votes on existing proposals and deletions don't, so they keep
+// working until the admin makes room or raises the limit
+pub(crate) fn is_full() -> bool {
+    used_bytes(cached_pages()) >= soft_limit_bytes()
+}
+
+// Stable memory in use, measured now rather than taken from the cache
+pub(crate) fn used_now() -> u64 {
+    used_bytes(stable_pages())
+}
+
+pub(crate) fn soft_limit_bytes() -> u64 {
+    CONFIG
+        .with(|config| config.borrow().get().storage_soft_limit_bytes)
+        .unwrap_or(DEFAULT_SOFT_LIMIT_BYTES)
+}
+
+// Measure stable memory again, e.g. after the limit changed, logging a newly crossed alert level
+pub(crate) fn refresh() {
+    CACHED_PAGES.with(|cached| cached.set(None));
+    cached_pages();
+}
+
+// Pick up the alert level usage is at after an upgrade without logging it again, it was logged
+// when it was crossed
+pub(crate) fn resume() {
+    let pages = stable_pages();
+    CACHED_PAGES.with(|cached| cached.set(Some((pages, 0))));
+    ALERT_LEVEL.with(|level| level.set(alert_level(used_bytes(pages))));
+}
+
+fn cached_pages() -> u64 {
+    let (pages, checks) = match CACHED_PAGES.with(Cell::get) {
+        Some((pages, checks)) if checks < REFRESH_EVERY => (pages, checks + 1),
+        _ => {
+            let pages = stable_pages();
+            alert(used_bytes(pages));
+            (pages, 0)
+        }
+    };
+    CACHED_PAGES.with(|cached| cached.set(Some((pages, checks))));
+    pages
+}
+
+// Log an audit event when usage went past one of ALERT_PERCENTS. Falling back below a level, once
+// the limit was raised, lets it be logged again when it is crossed the next time
+fn alert(used_bytes: u64) {
+    let level = alert_level(used_bytes);
+    let previous = ALERT_LEVEL.with(|alerted| alerted.replace(level));
+    if level > previous {
+        log_event(
+            CANISTER_AUDIT_SCOPE,
+            AuditAction::StorageAlert,
+            AuditTarget::Storage {
+                percent: level,
+                used_bytes,
+                soft_limit_bytes: soft_limit_bytes(),
+            },
+        );
+    }
+}
+
+fn alert_level(used_bytes: u64) -> u8 {
+    let limit = soft_limit_bytes().max(1) as u128;
+    ALERT_PERCENTS
+        .iter()
+        .copied()
+        .filter(|percent| used_bytes as u128 * 100 >= limit * *percent as u128)
+        .max()
+        .unwrap_or_default()
+}
+
+fn used_bytes(pages: u64) -> u64 {
+    pages.saturating_mul(WASM_PAGE_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        env::{set_caller, set_stable_pages},
+        init, update_config, AuditEvent, InitArgs, AUDIT_STORAGE,
+    };
+    use candid::Principal;
+
+    // a soft limit of 100 pages set by the admin, so a page is a percent
+    fn limit_to_100_pages() {
+        let admin = Principal::from_slice(&[1]);
+        init(Some(InitArgs {
+            admins: vec![admin],
+            ..Default::default()
+        }));
+        set_caller(admin);
+        assert!(update_config(InitArgs {
+            storage_soft_limit_bytes: Some(100 * WASM_PAGE_SIZE),
+            ..Default::default()
+        })
+        .is_ok());
+    }
+
+    fn alerted_percents() -> Vec<u8> {
+        AUDIT_STORAGE.with(|service| {
+            service
+                .borrow()
+                .iter()
+                .filter_map(|(_, event): (u64, AuditEvent)| match event.target {
+                    AuditTarget::Storage { percent, .. } => Some(percent),
+                    _ => None,
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn usage_is_measured_again_every_hundred_checks() {
+        limit_to_100_pages();
+        refresh();
+        assert!(!is_full());
+
+        set_stable_pages(100);
+        // the cached page count is used until it is due to be measured again
+        for _ in 0..REFRESH_EVERY - 1 {
+            assert!(!is_full());
+        }
+        assert!(is_full());
+        assert_eq!(used_now(), 100 * WASM_PAGE_SIZE);
+
+        set_stable_pages(99);
+        refresh();
+        assert!(!is_full());
+    }
+
+    #[test]
+    fn crossing_80_and_95_percent_is_logged_once() {
+        limit_to_100_pages();
+        for pages in [50, 80, 90, 96, 99] {
+            set_stable_pages(pages);
+            refresh();
+        }
+        assert_eq!(alerted_percents(), vec![80, 95]);
+
+        // after dropping below the levels they are logged again when crossed
+        set_stable_pages(10);
+        refresh();
+        set_stable_pages(97);
+        refresh();
+        assert_eq!(alerted_percents(), vec![80, 95, 95]);
+
+        // upgrades pick up the level without logging it again
+        set_stable_pages(85);
+        resume();
+        set_stable_pages(86);
+        refresh();
+        assert_eq!(alerted_percents().len(), 3);
+    }
+}
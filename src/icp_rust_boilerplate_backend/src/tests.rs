@@ -0,0 +1,5235 @@
+// Endpoint level tests. They run natively against the in-memory stable structures, with the system
+// calls stubbed out by env. DAOs and proposals are written to the maps directly
+use super::*;
+use crate::env::{certified_data, set_caller, set_stable_pages, set_time};
+
+fn user(n: u8) -> Principal {
+    Principal::from_slice(&[n])
+}
+
+// members added in bulk, apart from the users above
+fn member(n: u32) -> Principal {
+    Principal::from_slice(&(1_000 + n).to_be_bytes())
+}
+
+fn comment(proposal_id: u64, content: &str) -> CommentPayload {
+    CommentPayload {
+        content: content.to_string(),
+        proposal_id,
+        reply_to: None,
+        stance: None,
+    }
+}
+
+// user 1 owns DAO 1, user 2 is a member and user 3 an observer
+fn insert_dao(visibility: DaoVisibility) -> Dao {
+    let mut dao = Dao {
+        id: 1,
+        name: "Builders".to_string(),
+        owner: Some(user(1)),
+        ..Default::default()
+    };
+    _set_membership(&mut dao, &user(2), Some(Membership::Member));
+    _set_membership(&mut dao, &user(3), Some(Membership::Observer));
+    DAO_STORAGE.with(|service| service.borrow_mut().insert(dao.id, dao.clone()));
+    DAO_SETTINGS.with(|service| {
+        service.borrow_mut().insert(
+            dao.id,
+            DaoSettings {
+                dao_id: dao.id,
+                visibility,
+                ..Default::default()
+            },
+        )
+    });
+    dao
+}
+
+// an open proposal of user 1 in DAO 1
+fn insert_proposal(id: u64) -> Proposal {
+    let proposal = Proposal {
+        id,
+        dao_id: 1,
+        owner: Some(user(1)),
+        deadline: 1_000,
+        status: ProposalStatus::Open,
+        ..Default::default()
+    };
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(proposal.id, proposal.clone()));
+    proposal
+}
+
+// Runs an async endpoint natively. Without a ledger configured their ledger calls fail before
+// awaiting anything, so they are done after the first poll
+fn run<F: std::future::Future>(future: F) -> F::Output {
+    let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+    match std::pin::pin!(future).as_mut().poll(&mut context) {
+        std::task::Poll::Ready(output) => output,
+        std::task::Poll::Pending => panic!("awaited a call to another canister"),
+    }
+}
+
+// an open proposal of user 1 listed in DAO 1, with its title and details
+fn insert_listed_proposal(id: u64, title: &str, details: &str) -> Proposal {
+    let proposal = Proposal {
+        title: title.to_string(),
+        details: details.to_string(),
+        created_at: id,
+        ..insert_proposal(id)
+    };
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal.clone()));
+    let mut dao = _get_dao(&1).unwrap();
+    _list_dao_proposal(&mut dao, id);
+    do_insert_dao(&mut dao);
+    proposal
+}
+
+// moves the deadline of a proposal out of reach, for tests that let time pass
+fn keep_open(id: u64) {
+    let proposal = Proposal {
+        deadline: u64::MAX,
+        .._get_proposal(&id).unwrap()
+    };
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal));
+}
+
+fn ids(proposals: Result<Vec<ProposalView>, Error>) -> Vec<u64> {
+    proposals.ok().unwrap().iter().map(|view| view.id).collect()
+}
+
+fn is_proposal_not_found<T>(result: &Result<T, Error>, id: u64) -> bool {
+    matches!(
+        result,
+        Err(Error::NotFound { context, .. }) if *context == ErrorContext::Proposal(id)
+    )
+}
+
+#[test]
+fn private_proposals_look_missing_to_outsiders() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+    set_caller(user(9));
+
+    assert!(is_proposal_not_found(&get_my_vote(10), 10));
+    assert!(is_proposal_not_found(&get_my_vote(11), 11));
+    assert!(is_proposal_not_found(&get_ballots(10), 10));
+    assert!(is_proposal_not_found(&get_vote_receipt(10), 10));
+    assert!(is_proposal_not_found(&get_vote_breakdown(10), 10));
+    assert!(is_proposal_not_found(&get_proposal_attachments(10), 10));
+    assert!(is_proposal_not_found(
+        &service::check_can_cast_vote(10, &user(9), 0),
+        10
+    ));
+}
+
+#[test]
+fn outsiders_learn_nothing_from_the_status_of_private_proposals() {
+    insert_dao(DaoVisibility::Private);
+    let mut proposal = insert_proposal(10);
+    set_caller(user(9));
+
+    for status in [
+        ProposalStatus::Cancelled,
+        ProposalStatus::Approved,
+        ProposalStatus::Scheduled,
+    ] {
+        proposal.status = status;
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(10, proposal.clone()));
+        assert!(is_proposal_not_found(
+            &_check_can_vote_on(&proposal, &user(9), 0),
+            10
+        ));
+    }
+}
+
+#[test]
+fn members_and_observers_read_private_proposals() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+
+    set_caller(user(2));
+    assert!(matches!(get_my_vote(10), Ok(None)));
+    // voting goes by the caller and time it is given
+    assert!(service::check_can_cast_vote(10, &user(2), 1_000).is_ok());
+    assert!(matches!(
+        service::check_can_cast_vote(10, &user(2), 1_001),
+        Err(Error::DeadlineExceeded { .. })
+    ));
+
+    set_caller(user(3));
+    assert!(matches!(get_my_vote(10), Ok(None)));
+    assert!(matches!(
+        service::check_can_cast_vote(10, &user(3), 0),
+        Err(Error::NotAMember { .. })
+    ));
+}
+
+#[test]
+fn votes_are_cast_and_ended_by_the_caller_and_time_given() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+    // the message's own caller and clock are left alone, the service goes by its arguments
+    set_caller(user(9));
+
+    let proposal = service::cast_vote(10, VoteDirection::Up, &user(2), 500)
+        .ok()
+        .unwrap();
+    assert_eq!(proposal.upvote_count, 1);
+    assert_eq!(proposal.last_activity_at, 500);
+    assert_eq!(_get_vote(&10, &user(2)).unwrap().voted_at, 500);
+    assert!(matches!(
+        service::cast_vote(10, VoteDirection::Down, &user(2), 500),
+        Err(Error::HasVoted { .. })
+    ));
+    assert!(matches!(
+        service::cast_vote(10, VoteDirection::Up, &user(1), 500),
+        Err(Error::CantVoteYours { .. })
+    ));
+
+    // only the owner ends the vote, once the deadline has passed
+    assert!(matches!(
+        service::end_vote(10, &user(1), 1_000),
+        Err(Error::DeadlineNotExceeded { .. })
+    ));
+    assert!(matches!(
+        service::end_vote(10, &user(2), 1_001),
+        Err(Error::PermissionError { .. })
+    ));
+    let ended = service::end_vote(10, &user(1), 1_001).ok().unwrap();
+    assert!(ended.status == ProposalStatus::Approved);
+    assert_eq!(ended.finalized_at, Some(1_001));
+}
+
+#[test]
+fn public_proposals_tell_outsiders_they_are_not_members() {
+    insert_dao(DaoVisibility::Public);
+    insert_proposal(10);
+    set_caller(user(9));
+
+    assert!(matches!(get_my_vote(10), Err(Error::NotAMember { .. })));
+    assert!(matches!(
+        service::check_can_cast_vote(10, &user(9), 0),
+        Err(Error::NotAMember { .. })
+    ));
+}
+
+#[test]
+fn audit_events_fit_their_map() {
+    set_caller(Principal::from_slice(&[7; 29]));
+    // the largest event: a departure of the longest principal with a note of the most
+    // characters, each taking up four bytes
+    let note = "𝄞".repeat(MAX_EXIT_NOTE_LEN);
+    let largest = AuditEvent {
+        id: u64::MAX,
+        timestamp: u64::MAX,
+        actor: Some(caller()),
+        dao_id: u64::MAX,
+        action: AuditAction::MemberLeft,
+        target: AuditTarget::Departure {
+            member: caller(),
+            note: Some(note.clone()),
+        },
+    };
+    assert!(largest.to_bytes().len() <= AuditEvent::MAX_SIZE as usize);
+    log_event(
+        1,
+        AuditAction::MemberLeft,
+        AuditTarget::Departure {
+            member: caller(),
+            note: Some(note.clone()),
+        },
+    );
+    log_event(
+        1,
+        AuditAction::IntegrityRepaired,
+        AuditTarget::Integrity(Default::default()),
+    );
+
+    let event = AUDIT_STORAGE
+        .with(|service| service.borrow().get(&0))
+        .unwrap();
+    assert!(matches!(
+        event.target,
+        AuditTarget::Departure { note: Some(logged), .. } if logged == note
+    ));
+    assert_eq!(AUDIT_STORAGE.with(|service| service.borrow().len()), 2);
+}
+
+#[test]
+fn daos_take_any_number_of_members() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    for batch in 0..3u32 {
+        let members: Vec<Principal> = (0..MAX_MEMBERS_PER_BATCH as u32)
+            .map(|n| Principal::from_slice(&(1_000 + batch * 1_000 + n).to_be_bytes()))
+            .collect();
+        let result = add_members_bulk(1, members).ok().unwrap();
+        assert_eq!(result.added.len(), MAX_MEMBERS_PER_BATCH);
+    }
+
+    let dao = _get_dao(&1).unwrap();
+    assert_eq!(dao.member_count, 1 + 3 * MAX_MEMBERS_PER_BATCH as u64);
+    assert!(dao.to_bytes().len() < Dao::MAX_SIZE as usize);
+    assert_eq!(_dao_participants(&dao).len(), 2 + 3 * MAX_MEMBERS_PER_BATCH);
+}
+
+#[test]
+fn daos_take_any_number_of_proposals() {
+    let mut dao = insert_dao(DaoVisibility::Private);
+    for id in 100..1_100 {
+        insert_proposal(id);
+        _list_dao_proposal(&mut dao, id);
+    }
+    do_insert_dao(&mut dao);
+    assert!(dao.to_bytes().len() < Dao::MAX_SIZE as usize);
+
+    _delete_proposal_records(&_get_proposal(&100).unwrap());
+    let dao = _get_dao(&1).unwrap();
+    assert_eq!(dao.proposal_count, 999);
+    assert_eq!(_dao_proposal_ids(&1).first(), Some(&101));
+    set_caller(user(2));
+    assert_eq!(
+        get_all_proposals(1).ok().map(|proposals| proposals.len()),
+        Some(999)
+    );
+}
+
+#[test]
+fn memberships_follow_bans_and_promotions() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+
+    assert!(set_dao_admin(1, user(2), true).is_ok());
+    assert!(_is_dao_admin(&_get_dao(&1).unwrap(), &user(2)));
+    assert!(matches!(
+        set_dao_admin(1, user(3), true),
+        Err(Error::NotAMember { .. })
+    ));
+
+    // an admin can't ban another admin, the owner can
+    set_caller(user(2));
+    assert!(matches!(
+        ban_member(1, user(2)),
+        Err(Error::PermissionError { .. })
+    ));
+    set_caller(user(1));
+    let dao = ban_member(1, user(2)).ok().unwrap();
+    assert_eq!(dao.member_count, 0);
+    assert!(_is_banned(1, &user(2)));
+    assert!(!_get_member_dao_ids(&user(2)).contains(&1));
+    assert!(matches!(
+        add_members_bulk(1, vec![user(2)]).ok().map(|result| result.banned),
+        Some(banned) if banned == vec![user(2)]
+    ));
+
+    assert!(unban_member(1, user(2)).is_ok());
+    assert!(_membership(1, &user(2)).is_none());
+    let page = get_dao_memberships(1, Membership::Observer, 0, 10)
+        .ok()
+        .unwrap();
+    assert_eq!(page, vec![user(3)]);
+}
+
+#[test]
+fn member_directory_pages_through_a_large_dao() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+    set_caller(user(1));
+    set_time(100);
+    assert!(add_members_bulk(1, (0..100).map(member).collect()).is_ok());
+    set_time(200);
+    assert!(add_members_bulk(1, (100..148).map(member).collect()).is_ok());
+
+    set_caller(member(120));
+    assert!(comment_on_post(comment(10, "first")).is_ok());
+    assert!(comment_on_post(comment(10, "second")).is_ok());
+    assert!(upvote(10).is_ok());
+    set_caller(user(2));
+    assert!(downvote(10).is_ok());
+
+    let mut seen = Vec::new();
+    for offset in [0, 100] {
+        let page = get_dao_members(1, offset, 100, MemberSort::ByJoined)
+            .ok()
+            .unwrap();
+        assert_eq!(page.total_count, 150);
+        seen.extend(page.items);
+    }
+    assert_eq!(seen.len(), 150);
+    assert!(
+        seen.windows(2)
+            .all(|pair| pair[0].joined_at.unwrap_or_default()
+                <= pair[1].joined_at.unwrap_or_default())
+    );
+    assert!(seen[149].joined_at == Some(200));
+    let mut principals: Vec<Principal> = seen.iter().map(|entry| entry.principal).collect();
+    principals.sort();
+    principals.dedup();
+    assert_eq!(principals.len(), 150);
+
+    let page = get_dao_members(1, 0, 2, MemberSort::ByActivity)
+        .ok()
+        .unwrap();
+    assert!(page.items[0].principal == member(120));
+    assert_eq!(
+        (page.items[0].comments_made, page.items[0].votes_cast),
+        (2, 1)
+    );
+    assert!(page.items[1].principal == user(2));
+    assert_eq!(page.items[1].votes_cast, 1);
+
+    set_caller(user(9));
+    assert!(get_dao_members(1, 0, 10, MemberSort::ByJoined).is_err());
+}
+
+// a proposal with every field at its largest
+fn largest_proposal() -> Proposal {
+    // four bytes each, the most a character takes in UTF-8
+    let text = |len: usize| "𝄞".repeat(len);
+    let principal = Principal::from_slice(&[7; 29]);
+    let options: Vec<String> = (0..ranked::MAX_OPTIONS)
+        .map(|_| text(ranked::MAX_OPTION_LEN))
+        .collect();
+    let ballots: Vec<(Vec<u8>, u64)> = (0..ranked::MAX_OPTIONS as u8)
+        .map(|option| {
+            (
+                (0..ranked::MAX_OPTIONS as u8).rev().collect(),
+                option as u64,
+            )
+        })
+        .collect();
+    Proposal {
+        id: u64::MAX,
+        dao_id: u64::MAX,
+        title: text(MAX_PROPOSAL_TITLE_LEN),
+        details: text(MAX_PROPOSAL_DETAILS_LEN),
+        kind: ProposalKind::RankedChoice { options },
+        owner: Some(principal),
+        upvote_count: u64::MAX,
+        downvote_count: u64::MAX,
+        comment_count: u64::MAX,
+        updated_at: Some(u64::MAX),
+        depends_on: Some(u64::MAX),
+        voting_starts_at: Some(u64::MAX),
+        result_digest: vec![u8::MAX; 32],
+        final_tally: Some(VoteBreakdown {
+            proposal_id: u64::MAX,
+            upvotes: u64::MAX,
+            downvotes: u64::MAX,
+            weighted_upvotes: u64::MAX,
+            weighted_downvotes: u64::MAX,
+        }),
+        executed_at: Some(u64::MAX),
+        removal: Some(Removal {
+            removed_by: principal,
+            reason: text(MAX_REMOVAL_REASON_LEN),
+            removed_at: u64::MAX,
+        }),
+        slug: "a".repeat(MAX_SLUG_BASE_LEN + 21),
+        comments_close_at: Some(u64::MAX),
+        deposit: Some(ProposalDeposit {
+            amount: u64::MAX,
+            status: DepositStatus::RefundPending,
+            settled_at: Some(u64::MAX),
+        }),
+        finalized_at: Some(u64::MAX),
+        execution_block: Some(candid::Nat::from(u64::MAX)),
+        cancel_reason: Some(CancelReason::Merge {
+            target_dao_id: u64::MAX,
+        }),
+        closed_early: Some(EarlyClosure {
+            rule: EarlyClose {
+                min_votes: u32::MAX,
+                reject_ratio_percent: u8::MAX,
+            },
+            upvotes: u64::MAX,
+            downvotes: u64::MAX,
+            original_deadline: u64::MAX,
+        }),
+        ranked_result: Some(ranked::instant_runoff(ranked::MAX_OPTIONS, &ballots, &[])),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn proposals_fit_their_bound_at_their_largest() {
+    let proposal = largest_proposal();
+    assert!(proposal.to_bytes().len() <= Proposal::MAX_SIZE as usize);
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(proposal.id, proposal.clone()));
+
+    let too_long = |title: usize, details: usize| ProposalPayload {
+        title: "a".repeat(title),
+        details: "a".repeat(details),
+        ..Default::default()
+    };
+    assert!(_validate_proposal_text(&too_long(MAX_PROPOSAL_TITLE_LEN, 0)).is_ok());
+    assert!(_validate_proposal_text(&too_long(MAX_PROPOSAL_TITLE_LEN + 1, 0)).is_err());
+    assert!(_validate_proposal_text(&too_long(0, MAX_PROPOSAL_DETAILS_LEN + 1)).is_err());
+}
+
+#[test]
+fn proposals_take_any_number_of_votes_and_comments() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+    set_caller(user(1));
+    for batch in 0..3u32 {
+        let members = (batch * 100..(batch + 1) * 100).map(member).collect();
+        assert!(add_members_bulk(1, members).is_ok());
+    }
+
+    for n in 0..300 {
+        set_caller(member(n));
+        let voted = if n % 3 == 0 { downvote(10) } else { upvote(10) };
+        assert!(voted.is_ok());
+        if n < 100 {
+            assert!(comment_on_post(comment(10, "agreed")).is_ok());
+        }
+    }
+    assert!(matches!(upvote(10), Err(Error::HasVoted { .. })));
+
+    let proposal = _get_proposal(&10).unwrap();
+    assert_eq!(
+        (
+            proposal.upvote_count,
+            proposal.downvote_count,
+            proposal.comment_count
+        ),
+        (200, 100, 100)
+    );
+    assert!(proposal.to_bytes().len() < 1024);
+    let view = get_proposal(10).ok().unwrap();
+    assert!(view.upvote_count == Some(200) && view.downvote_count == Some(100));
+    assert_eq!(view.comments.len(), 100);
+    assert_eq!(_votes(&10).len(), 300);
+
+    // deleted comments leave a tombstone, which stays listed
+    let first = view.comments[0];
+    set_caller(member(0));
+    assert!(delete_comment(first).is_ok());
+    assert_eq!(_get_proposal(&10).unwrap().comment_count, 100);
+    _delete_comment_records(&_get_comment(&first).unwrap());
+    assert_eq!(_get_proposal(&10).unwrap().comment_count, 99);
+    assert!(!_comment_ids(&10).contains(&first));
+}
+
+#[test]
+fn discussion_counters_follow_comments_and_votes() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    assert!(add_members_bulk(1, (0..3).map(member).collect()).is_ok());
+    let mut dao = _get_dao(&1).unwrap();
+    for id in [10, 11, 12] {
+        insert_proposal(id);
+        _list_dao_proposal(&mut dao, id);
+    }
+    do_insert_dao(&mut dao);
+
+    set_caller(member(0));
+    set_time(100);
+    assert!(comment_on_post(comment(11, "first")).is_ok());
+    set_time(200);
+    let second = comment_on_post(comment(11, "second")).ok().unwrap();
+    set_caller(member(1));
+    set_time(300);
+    assert!(comment_on_post(comment(11, "third")).is_ok());
+    assert!(upvote(11).is_ok());
+    set_time(400);
+    assert!(comment_on_post(comment(12, "only")).is_ok());
+    set_caller(member(2));
+    set_time(500);
+    assert!(downvote(10).is_ok());
+
+    let proposal = _get_proposal(&11).unwrap();
+    assert_eq!(proposal.comment_count, 3);
+    assert_eq!(proposal.last_activity_at, 300);
+    assert_eq!(proposal.unique_participants, 2);
+    assert_eq!(_get_proposal(&10).unwrap().last_activity_at, 500);
+
+    // a deleted comment stays as a tombstone, so it is still counted
+    set_caller(member(0));
+    set_time(600);
+    assert!(delete_comment(second.id).is_ok());
+    let proposal = _get_proposal(&11).unwrap();
+    assert_eq!(proposal.comment_count, 3);
+    assert_eq!(proposal.unique_participants, 2);
+
+    let order = |sort: SortBy| -> Vec<u64> {
+        list_proposals(1, sort, 0, 10)
+            .ok()
+            .unwrap()
+            .iter()
+            .map(|view| view.id)
+            .collect()
+    };
+    assert_eq!(order(SortBy::MostCommented), vec![11, 12, 10]);
+    assert_eq!(order(SortBy::RecentlyActive), vec![10, 12, 11]);
+    assert_eq!(order(SortBy::Newest), vec![12, 11, 10]);
+    assert_eq!(
+        list_proposals(1, SortBy::MostCommented, 1, 1)
+            .ok()
+            .map(|page| page[0].id),
+        Some(12)
+    );
+}
+
+// The records of the maps a DAO, its proposals and their discussion are kept in, encoded
+fn stored_records() -> Vec<Vec<(Vec<u8>, Vec<u8>)>> {
+    fn encoded<K, V>(map: &StableBTreeMap<K, V, Memory>) -> Vec<(Vec<u8>, Vec<u8>)>
+    where
+        K: BoundedStorable + Ord + Clone,
+        V: BoundedStorable,
+    {
+        map.iter()
+            .map(|(key, value)| (key.to_bytes().to_vec(), value.to_bytes().to_vec()))
+            .collect()
+    }
+    vec![
+        PROPOSAL_STORAGE.with(|service| encoded(&service.borrow())),
+        DAO_STORAGE.with(|service| encoded(&service.borrow())),
+        DAO_MEMBERSHIPS.with(|service| encoded(&service.borrow())),
+        DAO_PROPOSALS.with(|service| encoded(&service.borrow())),
+        COMMENT_STORAGE.with(|service| encoded(&service.borrow())),
+        PROPOSAL_COMMENTS.with(|service| encoded(&service.borrow())),
+        VOTE_STORAGE.with(|service| encoded(&service.borrow())),
+    ]
+}
+
+#[test]
+fn records_survive_an_upgrade() {
+    init(None);
+    let mut dao = insert_dao(DaoVisibility::Private);
+    for id in [10, 11] {
+        insert_proposal(id);
+        _list_dao_proposal(&mut dao, id);
+    }
+    // a proposal as large as its bound allows
+    let largest = Proposal {
+        id: 12,
+        dao_id: 1,
+        ..largest_proposal()
+    };
+    assert!(largest.to_bytes().len() > Proposal::MAX_SIZE as usize * 3 / 4);
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(12, largest.clone()));
+    _list_dao_proposal(&mut dao, 12);
+    do_insert_dao(&mut dao);
+    set_caller(user(2));
+    assert!(upvote(10).is_ok());
+    let first = comment_on_post(comment(10, "first")).ok().unwrap();
+    let reply = comment_on_post(CommentPayload {
+        reply_to: Some(first.id),
+        ..comment(10, "reply")
+    })
+    .ok()
+    .unwrap();
+    let stored = stored_records();
+    let last_id = ID_COUNTER.with(|counter| *counter.borrow().get());
+
+    env::upgrade(move || {
+        post_upgrade(None);
+        while migrations::is_running() {
+            migrations::step();
+        }
+        assert!(stored_records() == stored);
+
+        set_caller(user(2));
+        assert!(get_proposal(10).is_ok());
+        assert_eq!(_get_proposal(&10).unwrap().upvote_count, 1);
+        assert_eq!(_comment_ids(&10), vec![first.id, reply.id]);
+        assert!(_get_comment(&reply.id).is_some_and(|comment| comment.reply_to == Some(first.id)));
+        assert!(_get_proposal(&12).unwrap().to_bytes() == largest.to_bytes());
+        assert_eq!(_dao_proposal_ids(&1), vec![10, 11, 12]);
+
+        // ids keep counting from where they were, so new records don't take the id of an old one
+        assert_eq!(ID_COUNTER.with(|counter| *counter.borrow().get()), last_id);
+        let next = comment_on_post(comment(11, "after")).ok().unwrap();
+        assert!(next.id >= last_id);
+        assert!(COMMENT_STORAGE.with(|service| service.borrow().len()) == 3);
+    });
+}
+
+#[test]
+fn proposals_too_large_for_their_bound_are_refused() {
+    insert_dao(DaoVisibility::Private);
+    let before = insert_proposal(10);
+    set_caller(user(1));
+
+    let payload = ProposalPayload {
+        dao_id: 1,
+        title: "a".repeat(MAX_PROPOSAL_TITLE_LEN + 1),
+        details: "𝄞".repeat(MAX_PROPOSAL_DETAILS_LEN),
+        ..Default::default()
+    };
+    assert!(matches!(
+        update_proposal(10, payload, None),
+        Err(Error::InvalidInput { msg, .. }) if msg.contains("title")
+    ));
+    assert!(_get_proposal(&10).unwrap().to_bytes() == before.to_bytes());
+}
+
+// What writing a record past its bound does. The message traps, so nothing it wrote is kept
+#[test]
+#[should_panic(expected = "Value is too large")]
+fn writes_past_the_bound_trap() {
+    let proposal = Proposal {
+        details: "a".repeat(Proposal::MAX_SIZE as usize),
+        ..Default::default()
+    };
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(1, proposal));
+}
+
+#[test]
+fn lapsed_dues_block_voting_but_not_reading() {
+    insert_dao(DaoVisibility::Private);
+    for id in [10, 11] {
+        let proposal = Proposal {
+            deadline: u64::MAX,
+            ..insert_proposal(id)
+        };
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal));
+    }
+    DAO_SETTINGS.with(|service| {
+        let mut settings = _get_settings(&1);
+        settings.dues_amount = 100;
+        settings.dues_period_seconds = 60;
+        service.borrow_mut().insert(1, settings)
+    });
+    set_caller(user(2));
+    set_time(10);
+
+    assert!(matches!(upvote(10), Err(Error::NotEligible { .. })));
+    assert!(get_proposal(10).is_ok());
+    assert!(matches!(get_my_paid_until(1), Ok(0)));
+
+    // what pay_dues does once the ledger has taken the payment
+    assert_eq!(_extend_paid_until(&1, &user(2)), 10 + 60_000_000_000);
+    assert!(upvote(10).is_ok());
+    // paying early adds to the period already paid for
+    assert_eq!(_extend_paid_until(&1, &user(2)), 10 + 120_000_000_000);
+
+    set_time(10 + 120_000_000_000);
+    assert!(matches!(downvote(11), Err(Error::NotEligible { .. })));
+    assert!(get_proposal(11).is_ok());
+
+    // dues of 0 turn the whole mechanism off
+    set_caller(user(1));
+    assert!(set_dues(1, 0, 0).is_ok());
+    set_caller(user(2));
+    assert!(downvote(11).is_ok());
+}
+
+#[test]
+fn deposits_are_refunded_or_forfeited_at_finalization() {
+    insert_dao(DaoVisibility::Private);
+    DAO_SETTINGS.with(|service| {
+        let mut settings = _get_settings(&1);
+        settings.proposal_deposit = Some(50);
+        service.borrow_mut().insert(1, settings)
+    });
+    set_caller(user(1));
+
+    // nothing is created when the deposit can't be taken
+    let payload = ProposalPayload {
+        dao_id: 1,
+        title: "Fund the meetup".to_string(),
+        details: "Venue and snacks".to_string(),
+        ..Default::default()
+    };
+    assert!(matches!(
+        run(add_proposal(payload)),
+        Err(Error::LedgerError { .. })
+    ));
+    assert_eq!(PROPOSAL_STORAGE.with(|service| service.borrow().len()), 0);
+
+    let held = |id: u64| Proposal {
+        deposit: Some(ProposalDeposit {
+            amount: 50,
+            status: DepositStatus::Held,
+            settled_at: None,
+        }),
+        ..insert_proposal(id)
+    };
+    let status = |proposal: &Proposal| proposal.deposit.as_ref().map(|deposit| deposit.status);
+
+    set_time(2_000);
+    let mut missed = held(10);
+    assert!(!_settle_deposit(&mut missed, false, 2_000));
+    assert!(status(&missed) == Some(DepositStatus::Forfeited));
+    assert!(missed.deposit.as_ref().unwrap().settled_at == Some(2_000));
+    let history = get_treasury_history(1, 0, 10).ok().unwrap();
+    assert_eq!(history.len(), 1);
+    // the forfeited deposit already is in the treasury, so the balance doesn't move
+    assert_eq!(treasury::balance(1), 0);
+
+    let mut reached = held(11);
+    assert!(_settle_deposit(&mut reached, true, 2_000));
+    assert!(status(&reached) == Some(DepositStatus::RefundPending));
+    // finalizing again leaves a settled deposit as it is
+    assert!(!_settle_deposit(&mut reached, true, 2_000));
+    assert!(!_settle_deposit(&mut missed, true, 2_000));
+    do_insert_proposal(&mut reached);
+
+    // a refund that fails stays pending, so the proposer can claim it again
+    for _ in 0..2 {
+        assert!(matches!(
+            run(claim_deposit_refund(11)),
+            Err(Error::LedgerError { .. })
+        ));
+        assert!(status(&_get_proposal(&11).unwrap()) == Some(DepositStatus::RefundPending));
+    }
+    set_caller(user(2));
+    assert!(matches!(
+        run(claim_deposit_refund(11)),
+        Err(Error::PermissionError { .. })
+    ));
+    set_caller(user(1));
+    assert!(matches!(
+        run(claim_deposit_refund(10)),
+        Err(Error::InvalidInput { .. })
+    ));
+}
+
+#[test]
+fn payments_of_a_proposal_wait_for_the_one_in_flight() {
+    insert_dao(DaoVisibility::Private);
+    let proposal = Proposal {
+        status: ProposalStatus::Approved,
+        kind: ProposalKind::Funding {
+            amount: 100,
+            recipient: None,
+        },
+        ..insert_proposal(10)
+    };
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(10, proposal));
+    set_caller(user(1));
+    set_time(2_000);
+
+    // what the first of two back-to-back executions holds while it waits for the ledger
+    let in_flight = locks::acquire(10, locks::LockedOperation::Execution, &user(1)).ok();
+    assert!(matches!(
+        run(execute_proposal(10)),
+        Err(Error::ExecutionInProgress { .. })
+    ));
+    assert!(matches!(
+        run(claim_deposit_refund(10)),
+        Err(Error::ExecutionInProgress { .. })
+    ));
+    assert!(_get_proposal(&10).unwrap().executed_at.is_none());
+    assert!(get_treasury_history(1, 0, 10).ok().unwrap().is_empty());
+    assert!(matches!(
+        clear_stuck_execution(10),
+        Err(Error::ExecutionInProgress { .. })
+    ));
+    drop(in_flight);
+    assert!(locks::get(&10).is_none());
+
+    // a payment that fails releases the lock too
+    assert!(matches!(
+        run(execute_proposal(10)),
+        Err(Error::LedgerError { .. })
+    ));
+    assert!(locks::get(&10).is_none());
+
+    // a lock whose ledger call never came back can be cleared once it is old enough
+    let stuck = locks::acquire(10, locks::LockedOperation::Execution, &user(1)).ok();
+    std::mem::forget(stuck);
+    set_caller(user(2));
+    assert!(matches!(
+        clear_stuck_execution(10),
+        Err(Error::PermissionError { .. })
+    ));
+    set_caller(user(1));
+    set_time(2_000 + locks::STUCK_AFTER);
+    assert!(clear_stuck_execution(10).is_ok());
+    assert!(locks::get(&10).is_none());
+}
+
+#[test]
+fn join_requests_are_approved_once_and_refused_to_the_banned() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(5));
+    set_time(10);
+    let request = request_to_join(1, "let me in".to_string()).ok().unwrap();
+    assert!(request.status == JoinRequestStatus::Pending && request.created_at == 10);
+    assert!(matches!(
+        request_to_join(1, "again".to_string()),
+        Err(Error::AlreadyRequested { .. })
+    ));
+    assert!(matches!(
+        approve_join_request(request.id),
+        Err(Error::PermissionError { .. })
+    ));
+
+    set_caller(user(1));
+    let pending = get_pending_requests(1).ok().unwrap();
+    assert!(pending.len() == 1 && pending[0].id == request.id);
+    let approved = approve_join_request(request.id).ok().unwrap();
+    assert!(approved.status == JoinRequestStatus::Approved);
+    assert!(_membership(1, &user(5)) == Some(Membership::Member));
+    assert!(get_pending_requests(1).ok().unwrap().is_empty());
+    assert!(matches!(
+        reject_join_request(request.id),
+        Err(Error::InvalidInput { .. })
+    ));
+
+    set_caller(user(5));
+    assert!(matches!(
+        request_to_join(1, "and again".to_string()),
+        Err(Error::AlreadyMember { .. })
+    ));
+
+    // a banned user's request is rejected as it is made
+    set_caller(user(1));
+    assert!(ban_member(1, user(2)).is_ok());
+    set_caller(user(2));
+    let banned = request_to_join(1, "sorry".to_string()).ok().unwrap();
+    assert!(banned.status == JoinRequestStatus::Rejected);
+    set_caller(user(1));
+    assert!(get_pending_requests(1).ok().unwrap().is_empty());
+    assert!(!_is_member(&_get_dao(&1).unwrap(), &user(2)));
+}
+
+#[test]
+fn proposal_search_matches_titles_and_details_in_any_case() {
+    insert_dao(DaoVisibility::Private);
+    insert_listed_proposal(10, "Fund the Garden", "Seeds and tools");
+    insert_listed_proposal(11, "New logo", "A garden themed logo");
+    insert_listed_proposal(12, "Budget", "Quarterly numbers");
+    set_caller(user(2));
+
+    assert_eq!(
+        ids(search_proposals(1, "fund".to_string(), 0, 10)),
+        vec![10]
+    );
+    assert_eq!(
+        ids(search_proposals(1, "themed".to_string(), 0, 10)),
+        vec![11]
+    );
+    assert_eq!(
+        ids(search_proposals(1, "  GARDEN ".to_string(), 0, 10)),
+        vec![11, 10]
+    );
+    assert_eq!(
+        ids(search_proposals(1, "garden".to_string(), 1, 10)),
+        vec![10]
+    );
+    assert!(ids(search_proposals(1, "treasury".to_string(), 0, 10)).is_empty());
+    for query in ["", "   "] {
+        assert!(matches!(
+            search_proposals(1, query.to_string(), 0, 10),
+            Err(Error::InvalidInput { .. })
+        ));
+    }
+    set_caller(user(9));
+    assert!(search_proposals(1, "garden".to_string(), 0, 10).is_err());
+}
+
+#[test]
+fn my_vote_tells_up_down_and_not_voted_apart() {
+    insert_dao(DaoVisibility::Public);
+    set_caller(user(1));
+    assert!(add_members_bulk(1, vec![member(0)]).is_ok());
+    insert_proposal(10);
+
+    set_caller(user(2));
+    assert!(matches!(get_my_vote(10), Ok(None)));
+    assert!(upvote(10).is_ok());
+    assert!(matches!(get_my_vote(10), Ok(Some(VoteDirection::Up))));
+
+    set_caller(member(0));
+    assert!(downvote(10).is_ok());
+    assert!(matches!(get_my_vote(10), Ok(Some(VoteDirection::Down))));
+
+    // the owner of the DAO wrote the proposal and never voted on it
+    set_caller(user(1));
+    assert!(matches!(get_my_vote(10), Ok(None)));
+    set_caller(user(9));
+    assert!(matches!(get_my_vote(10), Err(Error::NotAMember { .. })));
+}
+
+#[test]
+fn proposal_views_count_votes_and_ballots_stay_with_the_owner() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    assert!(add_members_bulk(1, vec![member(0)]).is_ok());
+    insert_proposal(10);
+    set_caller(user(2));
+    assert!(upvote(10).is_ok());
+    set_caller(member(0));
+    assert!(downvote(10).is_ok());
+
+    let view = get_proposal(10).ok().unwrap();
+    assert!(view.upvote_count == Some(1) && view.downvote_count == Some(1));
+    assert!(view.caller_has_voted);
+    set_caller(user(1));
+    assert!(!get_proposal(10).ok().unwrap().caller_has_voted);
+
+    // ballots are private until the owner makes them public, and only the owner sees them
+    assert!(matches!(
+        get_ballots(10),
+        Err(Error::PermissionError { .. })
+    ));
+    assert!(set_public_ballots(1, true).is_ok());
+    let ballots = get_ballots(10).ok().unwrap();
+    assert_eq!(ballots.upvotes, vec![user(2)]);
+    assert_eq!(ballots.downvotes, vec![member(0)]);
+    set_caller(user(2));
+    assert!(matches!(
+        get_ballots(10),
+        Err(Error::PermissionError { .. })
+    ));
+}
+
+#[test]
+fn dao_stats_add_up_a_seeded_dao() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    assert!(add_members_bulk(1, vec![member(0), member(1)]).is_ok());
+    let seed = |id: u64, status: ProposalStatus, deadline: u64| {
+        let proposal = Proposal {
+            status,
+            deadline,
+            ..insert_listed_proposal(id, "seeded", "")
+        };
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal));
+    };
+    seed(10, ProposalStatus::Open, 1_000);
+    // past its deadline but not finalized yet, so still open
+    seed(11, ProposalStatus::Open, 50);
+    seed(12, ProposalStatus::Approved, 50);
+    seed(13, ProposalStatus::Rejected, 50);
+    seed(14, ProposalStatus::Draft, 1_000);
+    seed(15, ProposalStatus::Cancelled, 1_000);
+
+    set_time(100);
+    for voter in [user(2), member(0), member(1)] {
+        set_caller(voter);
+        assert!(upvote(10).is_ok());
+    }
+    set_caller(member(0));
+    assert!(comment_on_post(comment(10, "yes")).is_ok());
+    assert!(comment_on_post(comment(10, "really")).is_ok());
+
+    set_caller(user(2));
+    let stats = get_dao_stats(1).ok().unwrap();
+    assert_eq!(
+        (
+            stats.member_count,
+            stats.total_proposals,
+            stats.open_proposals,
+            stats.approved_proposals,
+            stats.rejected_proposals,
+            stats.draft_proposals,
+            stats.cancelled_proposals
+        ),
+        (3, 6, 2, 1, 1, 1, 1)
+    );
+    assert_eq!((stats.total_comments, stats.total_votes_cast), (2, 3));
+    // member 0 voted once and commented twice
+    assert!(stats.most_active_member == Some(member(0)));
+    assert!(stats.treasury_balance.is_none() && stats.computed_at == 100);
+
+    set_caller(user(9));
+    assert!(get_dao_stats(1).is_err());
+}
+
+#[test]
+fn creating_voting_and_ending_leaves_an_audit_trail_in_order() {
+    set_caller(user(1));
+    let dao = create_dao(DaoPayload {
+        name: "Audited".to_string(),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(add_members_bulk(dao.id, vec![user(2)]).is_ok());
+    let proposal = run(add_proposal(ProposalPayload {
+        dao_id: dao.id,
+        title: "Paint the hall".to_string(),
+        details: "Blue".to_string(),
+        ..Default::default()
+    }))
+    .ok()
+    .unwrap();
+    set_caller(user(2));
+    assert!(upvote(proposal.id).is_ok());
+    set_caller(user(1));
+    set_time(proposal.deadline + 1);
+    assert!(end_proposal_vote(proposal.id).is_ok());
+
+    let actions = |events: Vec<AuditEvent>| -> Vec<(AuditAction, Option<Principal>)> {
+        events
+            .iter()
+            .map(|event| (event.action, event.actor))
+            .collect()
+    };
+    let log = actions(get_dao_audit_log(dao.id, 0, 50).ok().unwrap());
+    assert!(
+        log == vec![
+            (AuditAction::DaoCreated, Some(user(1))),
+            (AuditAction::MemberJoined, Some(user(1))),
+            (AuditAction::ProposalCreated, Some(user(1))),
+            (AuditAction::VoteCast, Some(user(2))),
+            (AuditAction::ProposalFinalized, Some(user(1))),
+        ]
+    );
+    let log = actions(get_proposal_audit_log(proposal.id).ok().unwrap());
+    assert!(
+        log == vec![
+            (AuditAction::ProposalCreated, Some(user(1))),
+            (AuditAction::VoteCast, Some(user(2))),
+            (AuditAction::ProposalFinalized, Some(user(1))),
+        ]
+    );
+}
+
+fn notification_kinds(only_unread: bool) -> Vec<NotificationKind> {
+    get_my_notifications(only_unread, 0, MAX_PAGE_SIZE)
+        .iter()
+        .map(|notification| notification.kind)
+        .collect()
+}
+
+#[test]
+fn comments_notify_the_proposal_owner_but_not_the_commenter() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+    set_caller(user(1));
+    assert!(comment_on_post(comment(10, "my own proposal")).is_ok());
+    assert!(notification_kinds(false).is_empty());
+
+    set_caller(user(2));
+    assert!(comment_on_post(comment(10, "nice")).is_ok());
+    assert!(upvote(10).is_ok());
+    assert!(notification_kinds(false).is_empty());
+
+    set_caller(user(1));
+    assert!(
+        notification_kinds(false) == vec![NotificationKind::NewVote, NotificationKind::NewComment]
+    );
+    let newest = get_my_notifications(false, 0, 1)[0].clone();
+    assert!(newest.proposal_id == Some(10) && !newest.read);
+    assert!(mark_notification_read(newest.id).is_ok());
+    assert!(notification_kinds(true) == vec![NotificationKind::NewComment]);
+    assert_eq!(mark_all_read(), 1);
+    assert!(notification_kinds(true).is_empty());
+
+    // nobody else can read them
+    set_caller(user(2));
+    assert!(matches!(
+        mark_notification_read(newest.id),
+        Err(Error::NotFound { .. })
+    ));
+}
+
+#[test]
+fn notifications_keep_the_newest_of_each_user() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+    keep_open(10);
+    set_caller(user(2));
+    for n in 0..MAX_NOTIFICATIONS_PER_USER + 5 {
+        // slow enough for the rate limit
+        set_time(n as u64 * rate_limit::COMMENT_WINDOW / 10);
+        assert!(comment_on_post(comment(10, &n.to_string())).is_ok());
+    }
+    set_caller(user(1));
+    assert_eq!(
+        _get_notification_ids(&user(1)).len(),
+        MAX_NOTIFICATIONS_PER_USER
+    );
+    let oldest = _get_notification(&_get_notification_ids(&user(1))[0]).unwrap();
+    let comment_ids = _comment_ids(&10);
+    assert!(oldest.comment_id == Some(comment_ids[5]));
+}
+
+#[test]
+fn mentions_keep_members_only_and_stop_at_the_cap() {
+    insert_dao(DaoVisibility::Private);
+    insert_listed_proposal(10, "Thanks", "");
+    set_caller(user(1));
+    assert!(add_members_bulk(1, (0..12).map(member).collect()).is_ok());
+
+    set_caller(user(2));
+    let content = format!(
+        "thanks @{}, @{} and @not-a-principal! Also @{} and @{}",
+        member(0).to_text(),
+        user(9).to_text(),
+        member(0).to_text(),
+        user(3).to_text(),
+    );
+    let mentioned = comment_on_post(comment(10, &content)).ok().unwrap();
+    // user 9 isn't in the DAO, user 3 only observes it
+    assert_eq!(mentioned.mentions, vec![member(0)]);
+
+    set_time(1);
+    let everyone: Vec<String> = (0..12)
+        .map(|n| format!("@{}", member(n).to_text()))
+        .collect();
+    let crowded = comment_on_post(comment(10, &everyone.join(" ")))
+        .ok()
+        .unwrap();
+    assert_eq!(crowded.mentions.len(), MAX_MENTIONS_PER_COMMENT);
+    assert_eq!(crowded.mentions, (0..10).map(member).collect::<Vec<_>>());
+
+    set_caller(member(0));
+    let mentioning: Vec<u64> = get_comments_mentioning_me(1, 0, 10)
+        .ok()
+        .unwrap()
+        .iter()
+        .map(|comment| comment.id)
+        .collect();
+    assert_eq!(mentioning, vec![crowded.id, mentioned.id]);
+    let kinds = notification_kinds(false);
+    assert!(kinds[..2] == [NotificationKind::Mention; 2]);
+    assert!(kinds[2..] == [NotificationKind::AddedToDao]);
+    set_caller(member(11));
+    assert!(get_comments_mentioning_me(1, 0, 10)
+        .ok()
+        .unwrap()
+        .is_empty());
+}
+
+fn profile(display_name: &str) -> ProfilePayload {
+    ProfilePayload {
+        display_name: display_name.to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn display_names_are_unique_in_any_case_and_freed_by_renames() {
+    set_caller(user(1));
+    assert!(set_my_profile(profile("Alice")).is_ok());
+    set_caller(user(2));
+    assert!(matches!(
+        set_my_profile(profile("alice")),
+        Err(Error::DisplayNameTaken { .. })
+    ));
+    for invalid in [
+        "al",
+        "alice smith",
+        "ålice",
+        &"a".repeat(MAX_DISPLAY_NAME_LEN + 1),
+    ] {
+        assert!(matches!(
+            set_my_profile(profile(invalid)),
+            Err(Error::InvalidInput { .. })
+        ));
+    }
+
+    // renaming frees the old name, and keeping your own name is no collision
+    set_caller(user(1));
+    assert!(set_my_profile(profile("Alice")).is_ok());
+    assert!(set_my_profile(profile("Alicia")).is_ok());
+    set_caller(user(2));
+    assert!(set_my_profile(profile("ALICE")).is_ok());
+    assert!(get_my_profile().ok().unwrap().display_name == "ALICE");
+
+    let resolved = resolve_principals(vec![user(1), user(3), user(2)]);
+    let names: Vec<(Principal, Option<String>)> = resolved
+        .into_iter()
+        .map(|(user, profile)| (user, profile.map(|profile| profile.display_name)))
+        .collect();
+    assert!(
+        names
+            == vec![
+                (user(1), Some("Alicia".to_string())),
+                (user(3), None),
+                (user(2), Some("ALICE".to_string())),
+            ]
+    );
+    assert!(matches!(get_profile(user(3)), Err(Error::NotFound { .. })));
+}
+
+fn proposal_payload(title: &str) -> ProposalPayload {
+    ProposalPayload {
+        dao_id: 1,
+        title: title.to_string(),
+        details: "Details".to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn new_members_wait_before_proposing_and_roles_gate_it() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    assert!(set_proposal_rules(1, 3_600, Role::Member).is_ok());
+    set_time(1_000_000_000);
+    assert!(add_members_bulk(1, vec![member(0)]).is_ok());
+
+    set_caller(member(0));
+    set_time(11_000_000_000);
+    assert!(matches!(
+        run(add_proposal(proposal_payload("Too soon"))),
+        Err(Error::NotEligible { msg, .. }) if msg.contains("in 3590 seconds")
+    ));
+    set_time(3_601_000_000_000);
+    assert!(run(add_proposal(proposal_payload("Just in time"))).is_ok());
+
+    // user 2 joined before join dates were recorded, so is old enough
+    set_caller(user(2));
+    assert!(run(add_proposal(proposal_payload("Old hand"))).is_ok());
+
+    set_caller(user(1));
+    assert!(set_proposal_rules(1, 3_600, Role::Admin).is_ok());
+    set_caller(member(0));
+    assert!(matches!(
+        run(add_proposal(proposal_payload("Not an admin"))),
+        Err(Error::NotEligible { msg, .. }) if msg.contains("role is too low")
+    ));
+    set_caller(user(1));
+    assert!(set_dao_admin(1, member(0), true).is_ok());
+    assert!(run(add_proposal(proposal_payload("Owner"))).is_ok());
+    set_caller(member(0));
+    assert!(run(add_proposal(proposal_payload("Now an admin"))).is_ok());
+}
+
+#[test]
+fn proposals_and_comments_are_rate_limited() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(2));
+    for n in 0..rate_limit::MAX_PROPOSALS_PER_WINDOW {
+        assert!(run(add_proposal(proposal_payload(&format!("Idea {}", n)))).is_ok());
+    }
+    assert!(matches!(
+        run(add_proposal(proposal_payload("One too many"))),
+        Err(Error::RateLimited {
+            retry_after_seconds: 86_400,
+            ..
+        })
+    ));
+
+    insert_proposal(10);
+    keep_open(10);
+    for _ in 0..rate_limit::MAX_COMMENTS_PER_WINDOW {
+        assert!(comment_on_post(comment(10, "spam")).is_ok());
+    }
+    set_time(rate_limit::COMMENT_WINDOW / 2);
+    assert!(matches!(
+        comment_on_post(comment(10, "spam")),
+        Err(Error::RateLimited {
+            retry_after_seconds: 1_800,
+            ..
+        })
+    ));
+}
+
+fn visible_comments(proposal_id: u64) -> Vec<u64> {
+    get_comments_after(proposal_id, None, MAX_PAGE_SIZE, false)
+        .ok()
+        .unwrap()
+        .items
+        .iter()
+        .map(|comment| comment.id)
+        .collect()
+}
+
+#[test]
+fn reports_hide_content_until_an_admin_resolves_them() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    assert!(add_members_bulk(1, (0..3).map(member).collect()).is_ok());
+    assert!(set_report_threshold(1, DEFAULT_REPORT_THRESHOLD).is_ok());
+    insert_proposal(10);
+    keep_open(10);
+    set_caller(user(2));
+    let reported = comment_on_post(comment(10, "spam")).ok().unwrap();
+    let target = ReportTarget::Comment(reported.id);
+
+    // reporting twice counts once
+    for reporter in [member(0), member(0), member(1)] {
+        set_caller(reporter);
+        assert!(report_content(target, "spam".to_string()).is_ok());
+    }
+    assert_eq!(visible_comments(10), vec![reported.id]);
+    set_caller(member(2));
+    assert!(report_content(target, "spam".to_string()).is_ok());
+    assert!(visible_comments(10).is_empty());
+    assert!(matches!(
+        get_reported_content(1),
+        Err(Error::PermissionError { .. })
+    ));
+
+    set_caller(user(1));
+    assert_eq!(visible_comments(10), vec![reported.id]);
+    let reports = get_reported_content(1).ok().unwrap();
+    assert!(reports.len() == 1 && reports[0].hidden && reports[0].reports.len() == 3);
+    assert!(resolve_report(target, ReportAction::Dismiss).is_ok());
+    set_caller(member(2));
+    assert_eq!(visible_comments(10), vec![reported.id]);
+    assert!(matches!(
+        resolve_report(target, ReportAction::Remove),
+        Err(Error::PermissionError { .. })
+    ));
+
+    // an admin removes a comment someone else wrote
+    set_caller(user(1));
+    assert!(get_reported_content(1).ok().unwrap().is_empty());
+    assert!(set_dao_admin(1, member(1), true).is_ok());
+    set_caller(member(1));
+    assert!(resolve_report(target, ReportAction::Remove).is_ok());
+    assert!(_get_comment(&reported.id).is_none());
+    assert!(visible_comments(10).is_empty());
+}
+
+#[test]
+fn pins_come_first_up_to_the_cap_and_locks_stop_new_comments() {
+    insert_dao(DaoVisibility::Private);
+    for id in 10..15 {
+        insert_listed_proposal(id, "Proposal", "");
+        keep_open(id);
+    }
+    set_caller(user(2));
+    assert!(matches!(
+        pin_proposal(13),
+        Err(Error::PermissionError { .. })
+    ));
+
+    set_caller(user(1));
+    for id in [13, 11, 14] {
+        assert!(pin_proposal(id).ok().unwrap().pinned);
+    }
+    assert!(matches!(pin_proposal(12), Err(Error::InvalidInput { .. })));
+    assert_eq!(ids(get_all_proposals(1)), vec![11, 13, 14, 10, 12]);
+    assert!(!unpin_proposal(13).ok().unwrap().pinned);
+    assert!(pin_proposal(12).is_ok());
+    assert_eq!(ids(get_all_proposals(1)), vec![11, 12, 14, 10, 13]);
+
+    set_caller(user(2));
+    let first = comment_on_post(comment(10, "before the lock"))
+        .ok()
+        .unwrap();
+    set_caller(user(1));
+    assert!(lock_comments(10).ok().unwrap().comments_locked);
+    set_caller(user(2));
+    assert!(matches!(
+        comment_on_post(comment(10, "after the lock")),
+        Err(Error::ThreadLocked { .. })
+    ));
+    assert!(matches!(
+        update_comment(first.id, comment(10, "edited")),
+        Err(Error::ThreadLocked { .. })
+    ));
+    set_caller(user(1));
+    assert!(matches!(
+        like_comment(first.id),
+        Err(Error::ThreadLocked { .. })
+    ));
+    assert_eq!(visible_comments(10), vec![first.id]);
+    assert!(get_proposal(10).is_ok());
+
+    assert!(unlock_comments(10).is_ok());
+    assert!(like_comment(first.id).is_ok());
+}
+
+fn attachment(name: &str, url: &str) -> Attachment {
+    Attachment {
+        name: name.to_string(),
+        url: url.to_string(),
+        sha256: None,
+    }
+}
+
+#[test]
+fn attachments_are_capped_checked_kept_by_updates_and_deleted_with_their_proposal() {
+    insert_dao(DaoVisibility::Private);
+    insert_listed_proposal(10, "Proposal", "");
+    set_caller(user(1));
+    for n in 0..MAX_ATTACHMENTS_PER_PROPOSAL {
+        let url = format!("https://example.com/{}.pdf", n);
+        assert!(add_attachment(10, attachment("Budget", &url)).is_ok());
+    }
+    assert!(matches!(
+        add_attachment(10, attachment("Budget", "https://example.com/extra.pdf")),
+        Err(Error::InvalidInput { .. })
+    ));
+    assert!(remove_attachment(10, 0).is_ok());
+    for bad in [
+        attachment("Budget", "http://example.com/budget.pdf"),
+        attachment("Budget", "https://"),
+        attachment("", "https://example.com/budget.pdf"),
+        attachment(
+            &"x".repeat(MAX_ATTACHMENT_NAME_LEN + 1),
+            "https://example.com/budget.pdf",
+        ),
+        Attachment {
+            sha256: Some("not a hash".to_string()),
+            ..attachment("Budget", "https://example.com/budget.pdf")
+        },
+    ] {
+        assert!(matches!(
+            add_attachment(10, bad),
+            Err(Error::InvalidInput { .. })
+        ));
+    }
+    set_caller(user(2));
+    assert!(matches!(
+        add_attachment(10, attachment("Budget", "https://example.com/budget.pdf")),
+        Err(Error::PermissionError { .. })
+    ));
+
+    // updates without attachments leave them alone
+    set_caller(user(1));
+    assert!(update_proposal(10, proposal_payload("Renamed"), None).is_ok());
+    let urls: Vec<String> = get_proposal_attachments(10)
+        .ok()
+        .unwrap()
+        .into_iter()
+        .map(|attachment| attachment.url)
+        .collect();
+    assert_eq!(
+        urls,
+        (1..MAX_ATTACHMENTS_PER_PROPOSAL)
+            .map(|n| format!("https://example.com/{}.pdf", n))
+            .collect::<Vec<String>>()
+    );
+
+    assert!(delete_proposal(10).is_ok());
+    assert!(_get_attachments(&10).is_empty());
+}
+
+// uploads `bytes` as upload `upload_id` in chunks of the largest size and finalizes it
+fn upload_image(upload_id: u64, bytes: &[u8]) -> images::ImageId {
+    for (index, chunk) in bytes.chunks(images::MAX_CHUNK_SIZE).enumerate() {
+        assert!(upload_image_chunk(upload_id, index as u64, chunk.to_vec()).is_ok());
+    }
+    finalize_image(upload_id).ok().unwrap()
+}
+
+fn avatar_payload(avatar: ImageRef) -> DaoPayload {
+    DaoPayload {
+        name: "Builders".to_string(),
+        description: "A DAO".to_string(),
+        avatar,
+    }
+}
+
+#[test]
+fn images_round_trip_in_chunks_and_go_away_with_their_dao() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    let bytes: Vec<u8> = (0..images::MAX_CHUNK_SIZE + 10).map(|n| n as u8).collect();
+    let image_id = upload_image(1, &bytes);
+    assert!(get_image(image_id, 0).ok().unwrap() == bytes[..images::MAX_CHUNK_SIZE]);
+    assert!(get_image(image_id, 1).ok().unwrap() == bytes[images::MAX_CHUNK_SIZE..]);
+    assert!(matches!(
+        get_image(image_id, 2),
+        Err(Error::NotFound { .. })
+    ));
+    assert!(images::read(&images::get(&image_id).unwrap()) == bytes);
+
+    // chunks and whole images are capped, and uploads belong to whoever started them
+    let full_chunk = vec![0; images::MAX_CHUNK_SIZE];
+    assert!(matches!(
+        upload_image_chunk(2, 0, vec![0; images::MAX_CHUNK_SIZE + 1]),
+        Err(Error::InvalidInput { .. })
+    ));
+    let chunk_count = images::MAX_IMAGE_SIZE / images::MAX_CHUNK_SIZE as u64;
+    for index in 0..chunk_count {
+        assert!(upload_image_chunk(2, index, full_chunk.clone()).is_ok());
+    }
+    assert!(matches!(
+        upload_image_chunk(2, chunk_count, vec![0]),
+        Err(Error::InvalidInput { .. })
+    ));
+    set_caller(user(2));
+    assert!(matches!(
+        upload_image_chunk(2, 0, vec![0]),
+        Err(Error::PermissionError { .. })
+    ));
+    assert!(matches!(
+        finalize_image(2),
+        Err(Error::PermissionError { .. })
+    ));
+
+    // replacing the avatar deletes the previous image, deleting the DAO deletes the current one
+    set_caller(user(1));
+    assert!(update_dao(1, avatar_payload(ImageRef::Image(image_id)), None).is_ok());
+    let replacement = upload_image(3, &[1, 2, 3]);
+    assert!(update_dao(1, avatar_payload(ImageRef::Image(replacement)), None).is_ok());
+    assert!(images::get(&image_id).is_none());
+    assert!(images::get_chunk(image_id, 0).is_none());
+    assert!(archive_dao(1).is_ok());
+    assert!(delete_dao(1).is_ok());
+    assert!(matches!(
+        get_image(replacement, 0),
+        Err(Error::NotFound { .. })
+    ));
+    assert!(images::get_chunk(replacement, 0).is_none());
+}
+
+fn candid_bytes<T: candid::CandidType>(value: &T) -> Vec<u8> {
+    candid::encode_one(value).unwrap()
+}
+
+#[test]
+fn proposal_bundles_match_the_individual_endpoints() {
+    insert_dao(DaoVisibility::Private);
+    insert_listed_proposal(10, "Proposal", "");
+    keep_open(10);
+    set_caller(user(2));
+    assert!(upvote(10).is_ok());
+    for n in 0..DEFAULT_BUNDLE_COMMENTS + 2 {
+        set_caller(user(1 + n as u8 % 2));
+        assert!(comment_on_post(comment(10, &format!("comment {}", n))).is_ok());
+    }
+    // user 2 likes the second newest comment, which user 1 wrote
+    let liked = _comment_ids(&10)[DEFAULT_BUNDLE_COMMENTS as usize];
+    assert!(like_comment(liked).is_ok());
+
+    set_caller(user(1));
+    let bundle = get_proposal_bundle(10, None).ok().unwrap();
+    assert!(candid_bytes(&bundle.proposal) == candid_bytes(&get_proposal(10).ok().unwrap()));
+    assert!(bundle.my_vote.is_none() && get_my_vote(10).ok().unwrap().is_none());
+    assert!(bundle.dao.id == 1 && bundle.dao.member_count == 1);
+    assert_eq!(bundle.comment_count, DEFAULT_BUNDLE_COMMENTS + 2);
+    let newest: Vec<CommentView> = get_all_comments_on_proposal(10)
+        .ok()
+        .unwrap()
+        .into_iter()
+        .rev()
+        .take(DEFAULT_BUNDLE_COMMENTS as usize)
+        .collect();
+    let comments: Vec<CommentView> = bundle
+        .comments
+        .into_iter()
+        .map(|entry| entry.comment)
+        .collect();
+    assert!(candid_bytes(&comments) == candid_bytes(&newest));
+    assert!(!comments[1].liked_by_caller && comments[1].like_count == 1);
+
+    set_caller(user(2));
+    let bundle = get_proposal_bundle(10, Some(3)).ok().unwrap();
+    assert!(bundle.my_vote == get_my_vote(10).ok().unwrap());
+    assert!(bundle.my_vote == Some(VoteDirection::Up));
+    assert_eq!(bundle.comments.len(), 3);
+    assert!(bundle.comments[1].comment.liked_by_caller);
+
+    set_caller(user(9));
+    assert!(is_proposal_not_found(&get_proposal_bundle(10, None), 10));
+}
+
+#[test]
+fn batched_votes_keep_each_result_and_stop_at_the_cap() {
+    insert_dao(DaoVisibility::Private);
+    for id in 10..13 {
+        insert_proposal(id);
+    }
+    set_caller(user(2));
+    let results = cast_votes(vec![
+        (10, VoteDirection::Up),
+        (11, VoteDirection::Down),
+        (10, VoteDirection::Down),
+        (99, VoteDirection::Up),
+        (12, VoteDirection::Up),
+    ]);
+    assert_eq!(
+        results.iter().map(|(id, _)| *id).collect::<Vec<u64>>(),
+        vec![10, 11, 10, 99, 12]
+    );
+    assert!(results[0].1.is_ok() && results[1].1.is_ok() && results[4].1.is_ok());
+    assert!(matches!(results[2].1, Err(Error::HasVoted { .. })));
+    assert!(is_proposal_not_found(&results[3].1, 99));
+
+    // votes before and after a failure are kept
+    assert!(get_my_vote(10).ok().unwrap() == Some(VoteDirection::Up));
+    assert!(get_my_vote(11).ok().unwrap() == Some(VoteDirection::Down));
+    assert!(get_my_vote(12).ok().unwrap() == Some(VoteDirection::Up));
+    let proposal = _get_proposal(&10).unwrap();
+    assert_eq!((proposal.upvote_count, proposal.downvote_count), (1, 0));
+
+    set_caller(user(1));
+    assert!(add_members_bulk(1, vec![member(0)]).is_ok());
+    set_caller(member(0));
+    let mut votes = vec![(10, VoteDirection::Up); MAX_VOTES_PER_BATCH];
+    votes.push((11, VoteDirection::Up));
+    let results = cast_votes(votes);
+    assert!(results[0].1.is_ok());
+    assert!(results[1..MAX_VOTES_PER_BATCH]
+        .iter()
+        .all(|(_, result)| matches!(result, Err(Error::HasVoted { .. }))));
+    assert!(matches!(
+        results[MAX_VOTES_PER_BATCH].1,
+        Err(Error::InvalidInput { .. })
+    ));
+    assert!(get_my_vote(11).ok().unwrap().is_none());
+}
+
+fn my_daos() -> Vec<(u64, Role, u64, u64)> {
+    get_user_daos()
+        .ok()
+        .unwrap()
+        .iter()
+        .map(|view| {
+            (
+                view.dao.id,
+                view.role,
+                view.member_count,
+                view.proposal_count,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn user_daos_follow_joins_leaves_and_bans() {
+    set_caller(user(2));
+    assert!(my_daos().is_empty());
+
+    set_caller(user(1));
+    let ids: Vec<u64> = ["First", "Second", "Third"]
+        .iter()
+        .map(|name| {
+            create_dao(DaoPayload {
+                name: name.to_string(),
+                ..Default::default()
+            })
+            .unwrap()
+            .id
+        })
+        .collect();
+    assert!(add_members_bulk(ids[0], vec![user(2)]).is_ok());
+    assert!(run(add_proposal(ProposalPayload {
+        dao_id: ids[0],
+        ..proposal_payload("Paint the hall")
+    }))
+    .is_ok());
+    set_caller(user(2));
+    assert!(my_daos() == vec![(ids[0], Role::Member, 1, 1)]);
+
+    set_caller(user(1));
+    assert!(add_members_bulk(ids[1], vec![user(2)]).is_ok());
+    assert!(set_dao_admin(ids[1], user(2), true).is_ok());
+    assert!(
+        my_daos()
+            == vec![
+                (ids[0], Role::Owner, 1, 1),
+                (ids[1], Role::Owner, 1, 0),
+                (ids[2], Role::Owner, 0, 0),
+            ]
+    );
+    set_caller(user(2));
+    assert!(my_daos() == vec![(ids[0], Role::Member, 1, 1), (ids[1], Role::Admin, 1, 0)]);
+
+    assert!(leave_dao(ids[0], None).is_ok());
+    assert!(my_daos() == vec![(ids[1], Role::Admin, 1, 0)]);
+    set_caller(user(1));
+    assert!(ban_member(ids[1], user(2)).is_ok());
+    set_caller(user(2));
+    assert!(my_daos().is_empty());
+}
+
+// the variant, code and context of an error, as clients decode them
+fn error_shape(result: Result<(), Error>) -> (String, u16, ErrorContext) {
+    let Err(error) = result else {
+        panic!("expected an error")
+    };
+    let value = serde_json::to_value(error).unwrap();
+    let (variant, fields) = value.as_object().unwrap().iter().next().unwrap();
+    (
+        variant.clone(),
+        fields["code"].as_u64().unwrap() as u16,
+        serde_json::from_value(fields["context"].clone()).unwrap(),
+    )
+}
+
+#[test]
+fn endpoints_fail_with_the_variant_code_and_context_of_the_condition() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+    set_caller(user(2));
+    let own = comment_on_post(comment(10, "mine")).ok().unwrap().id;
+    assert!(upvote(10).is_ok());
+    set_caller(user(1));
+    assert!(like_comment(own).is_ok());
+    let stale = _get_proposal(&10).unwrap().version - 1;
+
+    type Call = Box<dyn Fn() -> Result<(), Error>>;
+    let cases: Vec<(u8, Call, &str, u16, ErrorContext)> = vec![
+        (
+            9,
+            Box::new(|| upvote(10).map(|_| ())),
+            "NotFound",
+            1001,
+            ErrorContext::Proposal(10),
+        ),
+        (
+            2,
+            Box::new(|| comment_on_post(comment(99, "lost")).map(|_| ())),
+            "NotFound",
+            1001,
+            ErrorContext::Proposal(99),
+        ),
+        (
+            2,
+            Box::new(|| join_dao(1).map(|_| ())),
+            "AlreadyMember",
+            1003,
+            ErrorContext::Dao(1),
+        ),
+        (
+            1,
+            Box::new(|| {
+                add_attachment(10, attachment("Budget", "http://example.com/budget.pdf"))
+                    .map(|_| ())
+            }),
+            "InvalidInput",
+            1006,
+            ErrorContext::None,
+        ),
+        (
+            2,
+            Box::new(|| set_admin(user(2))),
+            "Unauthorized",
+            1008,
+            ErrorContext::None,
+        ),
+        (
+            2,
+            Box::new(|| upvote(10).map(|_| ())),
+            "HasVoted",
+            1012,
+            ErrorContext::Proposal(10),
+        ),
+        (
+            1,
+            Box::new(|| upvote(10).map(|_| ())),
+            "CantVoteYours",
+            1013,
+            ErrorContext::Proposal(10),
+        ),
+        (
+            2,
+            Box::new(move || like_comment(own).map(|_| ())),
+            "CantLikeYours",
+            1014,
+            ErrorContext::Comment(own),
+        ),
+        (
+            2,
+            Box::new(|| update_dao(1, DaoPayload::default(), None).map(|_| ())),
+            "PermissionError",
+            1016,
+            ErrorContext::Dao(1),
+        ),
+        (
+            1,
+            Box::new(|| end_proposal_vote(10).map(|_| ())),
+            "DeadlineNotExceeded",
+            1018,
+            ErrorContext::Proposal(10),
+        ),
+        (
+            1,
+            Box::new(move || like_comment(own).map(|_| ())),
+            "AlreadyLiked",
+            1019,
+            ErrorContext::Comment(own),
+        ),
+        (
+            1,
+            Box::new(move || {
+                update_proposal(10, proposal_payload("Stale"), Some(stale)).map(|_| ())
+            }),
+            "VersionConflict",
+            1021,
+            ErrorContext::Proposal(10),
+        ),
+    ];
+    for (caller, call, variant, code, context) in cases {
+        set_caller(user(caller));
+        let (actual_variant, actual_code, actual_context) = error_shape(call());
+        assert_eq!((actual_variant.as_str(), actual_code), (variant, code));
+        assert!(actual_context == context, "context of {}", variant);
+    }
+}
+
+fn proposal_version(id: u64) -> u64 {
+    get_proposal(id).ok().unwrap().version
+}
+
+#[test]
+fn stale_writes_conflict_and_fresh_ones_go_through() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+    keep_open(10);
+    set_caller(user(1));
+    let co_authored = ProposalPayload {
+        co_authors: Some(vec![user(2)]),
+        ..proposal_payload("Original")
+    };
+    assert!(update_proposal(10, co_authored, None).is_ok());
+
+    // both authors read the proposal, the co-author writes first
+    let read = proposal_version(10);
+    set_caller(user(2));
+    assert!(update_proposal(10, proposal_payload("Co-author edit"), Some(read)).is_ok());
+    let current = proposal_version(10);
+    assert!(current > read);
+    set_caller(user(1));
+    assert!(matches!(
+        update_proposal(10, proposal_payload("Owner edit"), Some(read)),
+        Err(Error::VersionConflict { current_version, .. }) if current_version == current
+    ));
+    assert_eq!(_get_proposal(&10).unwrap().title, "Co-author edit");
+    assert!(update_proposal(10, proposal_payload("Owner edit"), Some(current)).is_ok());
+
+    // votes move the version too
+    let read = proposal_version(10);
+    assert!(add_members_bulk(1, vec![member(0)]).is_ok());
+    set_caller(member(0));
+    assert!(upvote(10).is_ok());
+    set_caller(user(1));
+    assert!(matches!(
+        update_proposal(10, proposal_payload("After the vote"), Some(read)),
+        Err(Error::VersionConflict { .. })
+    ));
+
+    let read = _get_dao(&1).unwrap().version;
+    let payload = |name: &str| DaoPayload {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    assert!(update_dao(1, payload("Renamed"), Some(read)).is_ok());
+    assert!(matches!(
+        update_dao(1, payload("Renamed again"), Some(read)),
+        Err(Error::VersionConflict { current_version, .. }) if current_version == read + 1
+    ));
+    assert!(update_dao(1, payload("Renamed again"), Some(read + 1)).is_ok());
+}
+
+#[test]
+fn drafts_stay_with_their_author_until_published() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    assert!(add_members_bulk(1, vec![member(0)]).is_ok());
+    set_caller(user(2));
+    set_time(1_000);
+    let draft = run(add_proposal(ProposalPayload {
+        publish: Some(false),
+        ..proposal_payload("Draft")
+    }))
+    .ok()
+    .unwrap();
+    assert!(draft.status == ProposalStatus::Draft);
+    assert!(update_proposal(draft.id, proposal_payload("Edited draft"), None).is_ok());
+
+    // members don't see it and can't vote or comment, its author and the DAO's owner see it
+    set_caller(member(0));
+    assert!(ids(get_all_proposals(1)).is_empty());
+    assert!(is_proposal_not_found(&upvote(draft.id), draft.id));
+    assert!(is_proposal_not_found(
+        &comment_on_post(comment(draft.id, "early")),
+        draft.id
+    ));
+    set_caller(user(2));
+    assert_eq!(ids(get_all_proposals(1)), vec![draft.id]);
+    set_caller(user(1));
+    assert_eq!(ids(get_all_proposals(1)), vec![draft.id]);
+    assert!(matches!(upvote(draft.id), Err(Error::InvalidInput { .. })));
+
+    // the voting period starts when it's published
+    set_time(5_000_000_000);
+    set_caller(user(2));
+    let published = publish_proposal(draft.id).ok().unwrap();
+    assert!(published.status == ProposalStatus::Open);
+    assert_eq!(published.deadline, 5_000_000_000 + _voting_period(&1));
+    assert!(matches!(
+        publish_proposal(draft.id),
+        Err(Error::InvalidInput { .. })
+    ));
+    set_caller(member(0));
+    assert_eq!(ids(get_all_proposals(1)), vec![draft.id]);
+    assert!(upvote(draft.id).is_ok());
+}
+
+fn tally(id: u64) -> (u64, u64) {
+    let proposal = _get_proposal(&id).unwrap();
+    (proposal.upvote_count, proposal.downvote_count)
+}
+
+#[test]
+fn amendments_reset_or_set_aside_the_votes_cast_before_them() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+    keep_open(10);
+    set_caller(user(1));
+    assert!(add_members_bulk(1, vec![member(0), member(1)]).is_ok());
+
+    // nobody voted yet, edits aren't amendments
+    assert!(update_proposal(10, proposal_payload("First"), None).is_ok());
+    assert!(get_proposal_amendments(10).ok().unwrap().is_empty());
+
+    set_caller(member(0));
+    assert!(upvote(10).is_ok());
+    set_caller(member(1));
+    assert!(downvote(10).is_ok());
+    set_caller(user(1));
+    assert!(update_proposal(10, proposal_payload("Second"), None).is_ok());
+    let amendments = get_proposal_amendments(10).ok().unwrap();
+    assert!(amendments.len() == 1 && amendments[0].amended_by == Some(user(1)));
+    assert!(amendments[0].previous_title == "First" && amendments[0].title == "Second");
+    assert_eq!(tally(10), (0, 0));
+    set_caller(member(0));
+    assert!(get_my_vote(10).ok().unwrap().is_none());
+    assert!(matches!(reconfirm_vote(10), Err(Error::NotFound { .. })));
+    assert!(upvote(10).is_ok());
+
+    set_caller(user(1));
+    assert!(set_amendment_policy(1, AmendmentPolicy::RequireReconfirmation).is_ok());
+    assert!(update_proposal(10, proposal_payload("Third"), None).is_ok());
+    assert_eq!(get_proposal_amendments(10).ok().unwrap().len(), 2);
+    assert_eq!(tally(10), (0, 0));
+    set_caller(member(0));
+    assert!(reconfirm_vote(10).is_ok());
+    assert_eq!(tally(10), (1, 0));
+    assert!(matches!(reconfirm_vote(10), Err(Error::HasVoted { .. })));
+    set_caller(member(1));
+    assert!(matches!(reconfirm_vote(10), Err(Error::NotFound { .. })));
+}
+
+#[test]
+fn proposals_end_against_the_threshold_of_their_dao() {
+    insert_dao(DaoVisibility::Public);
+    set_caller(user(1));
+    assert!(add_members_bulk(1, (0..3).map(member).collect()).is_ok());
+    for invalid in [0, 101] {
+        assert!(matches!(
+            set_approval_threshold(1, invalid),
+            Err(Error::InvalidInput { .. })
+        ));
+    }
+    set_caller(user(2));
+    assert!(matches!(
+        set_approval_threshold(1, 66),
+        Err(Error::PermissionError { .. })
+    ));
+
+    // two upvotes of three are 66.6%, enough for 66 but not for 67
+    for (id, threshold, status) in [
+        (10, 67, ProposalStatus::Rejected),
+        (11, 66, ProposalStatus::Approved),
+    ] {
+        set_time(0);
+        insert_proposal(id);
+        set_caller(user(1));
+        assert!(set_approval_threshold(1, threshold).is_ok());
+        for voter in 0..2 {
+            set_caller(member(voter));
+            assert!(upvote(id).is_ok());
+        }
+        set_caller(member(2));
+        assert!(downvote(id).is_ok());
+        set_caller(user(9));
+        assert!(matches!(
+            get_dao(1),
+            Ok(DaoResponse::Summary(summary)) if summary.approval_threshold_percent == threshold
+        ));
+        set_caller(user(1));
+        set_time(1_001);
+        assert!(end_proposal_vote(id).is_ok());
+        assert!(_get_proposal(&id).unwrap().status == status);
+    }
+}
+
+fn new_comment_notifications() -> usize {
+    notification_kinds(false)
+        .into_iter()
+        .filter(|kind| *kind == NotificationKind::NewComment)
+        .count()
+}
+
+fn followed() -> Vec<u64> {
+    get_followed_proposals(0, MAX_PAGE_SIZE)
+        .iter()
+        .map(|view| view.id)
+        .collect()
+}
+
+#[test]
+fn followers_hear_about_new_comments_until_they_unfollow() {
+    insert_dao(DaoVisibility::Private);
+    insert_listed_proposal(10, "Proposal", "");
+    keep_open(10);
+    set_caller(user(1));
+    assert!(add_members_bulk(1, (0..3).map(member).collect()).is_ok());
+
+    set_caller(member(0));
+    assert!(follow_proposal(10).is_ok());
+    assert_eq!(followed(), vec![10]);
+    set_caller(member(1));
+    assert!(comment_on_post(comment(10, "first")).is_ok());
+    set_caller(member(0));
+    assert_eq!(new_comment_notifications(), 1);
+    assert!(unfollow_proposal(10).is_ok());
+    assert!(followed().is_empty());
+    assert!(matches!(unfollow_proposal(10), Err(Error::NotFound { .. })));
+
+    // voting follows unless the voter turned it off
+    set_caller(user(2));
+    assert!(upvote(10).is_ok());
+    assert_eq!(followed(), vec![10]);
+    set_caller(member(2));
+    assert!(
+        !set_my_preferences(UserPreferences {
+            auto_follow_on_vote: false,
+            ..get_my_preferences()
+        })
+        .auto_follow_on_vote
+    );
+    assert!(upvote(10).is_ok());
+    assert!(followed().is_empty());
+
+    set_caller(member(1));
+    assert!(comment_on_post(comment(10, "second")).is_ok());
+    for (follower, notifications) in [(member(0), 1), (user(2), 1), (member(2), 0)] {
+        set_caller(follower);
+        assert_eq!(new_comment_notifications(), notifications);
+    }
+
+    set_caller(user(9));
+    assert!(is_proposal_not_found(&follow_proposal(10), 10));
+}
+
+const HOUR: u64 = 60 * 60 * 1_000_000_000;
+
+// an open proposal of user 1 listed in `dao_id`, whose voting ends `hours_left` after `now`
+fn insert_feed_proposal(id: u64, dao_id: u64, now: u64, hours_left: u64) {
+    let proposal = Proposal {
+        id,
+        dao_id,
+        owner: Some(user(1)),
+        deadline: now + hours_left * HOUR,
+        status: ProposalStatus::Open,
+        ..Default::default()
+    };
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal));
+    let mut dao = _get_dao(&dao_id).unwrap();
+    _list_dao_proposal(&mut dao, id);
+    do_insert_dao(&mut dao);
+}
+
+fn feed_ids(items: Vec<FeedItem>) -> Vec<u64> {
+    items.iter().map(|item| item.proposal.id).collect()
+}
+
+#[test]
+fn feeds_rank_recent_activity_and_the_closest_deadlines_across_daos() {
+    let now = 240 * HOUR;
+    set_caller(user(1));
+    let daos: Vec<u64> = ["North", "South"]
+        .iter()
+        .map(|name| {
+            let dao = create_dao(DaoPayload {
+                name: name.to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+            assert!(add_members_bulk(dao.id, (0..3).map(member).collect()).is_ok());
+            dao.id
+        })
+        .collect();
+    insert_feed_proposal(100, daos[0], now, 10);
+    insert_feed_proposal(101, daos[0], now, 80);
+    insert_feed_proposal(200, daos[1], now, 2);
+    insert_feed_proposal(201, daos[1], now, 30);
+    insert_feed_proposal(202, daos[1], now, 1);
+    let closed = Proposal {
+        status: ProposalStatus::Approved,
+        ..(_get_proposal(&202).unwrap())
+    };
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(202, closed));
+
+    // three votes on 101 before the activity window, two votes and a comment on 201 and a vote
+    // on 100 within it
+    set_time(now - 60 * HOUR);
+    for voter in 0..3 {
+        set_caller(member(voter));
+        assert!(upvote(101).is_ok());
+    }
+    set_time(now - HOUR);
+    for voter in 0..2 {
+        set_caller(member(voter));
+        assert!(upvote(201).is_ok());
+    }
+    assert!(comment_on_post(comment(201, "soon")).is_ok());
+    assert!(upvote(100).is_ok());
+
+    set_time(now);
+    let feed = get_home_feed(10);
+    assert!(feed[0].dao_name == "South" && feed[0].recent_activity == 3);
+    assert_eq!(feed_ids(feed)[..2], [201, 100]);
+    assert_eq!(get_home_feed(10).len(), 5);
+    assert_eq!(feed_ids(get_home_feed(1)), vec![201]);
+    let ending_soon = get_ending_soon(10);
+    assert!(ending_soon[1].dao_name == "North");
+    assert_eq!(feed_ids(ending_soon), vec![200, 100, 201]);
+
+    set_caller(user(9));
+    assert!(get_home_feed(10).is_empty() && get_ending_soon(10).is_empty());
+}
+
+fn comment_page(after_id: Option<u64>, newest_first: bool) -> (Vec<u64>, Option<u64>) {
+    let page = get_comments_after(10, after_id, 2, newest_first)
+        .ok()
+        .unwrap();
+    (
+        page.items.iter().map(|item| item.id).collect(),
+        page.next_cursor,
+    )
+}
+
+#[test]
+fn comment_cursors_neither_skip_nor_repeat_when_comments_arrive() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+    keep_open(10);
+    let mut posted = Vec::new();
+    let mut post = |at: u64| {
+        set_time(at);
+        set_caller(user(1 + at as u8 % 2));
+        posted.push(comment_on_post(comment(10, "hello")).ok().unwrap().id);
+    };
+    for at in 1..=5 {
+        post(at);
+    }
+
+    let (first, cursor) = comment_page(None, false);
+    post(6);
+    let (second, cursor) = comment_page(cursor, false);
+    let (third, cursor) = comment_page(cursor, false);
+    assert!(cursor.is_none());
+    assert_eq!([first, second, third].concat(), posted);
+
+    // a comment listed late but created earlier still takes its place by creation time
+    let earlier = Comment {
+        id: 99,
+        content: "imported".to_string(),
+        author: Some(user(2)),
+        proposal_id: 10,
+        created_at: 0,
+        ..Default::default()
+    };
+    do_insert_comment(&earlier);
+    let mut proposal = _get_proposal(&10).unwrap();
+    _list_comment(&mut proposal, 99);
+    do_insert_proposal(&mut proposal);
+    let (newest, cursor) = comment_page(None, true);
+    assert!(newest == vec![posted[5], posted[4]]);
+    let (_, cursor) = comment_page(cursor, true);
+    let (_, cursor) = comment_page(cursor, true);
+    assert_eq!(comment_page(cursor, true), (vec![99], None));
+    assert_eq!(
+        get_comments_after(10, None, 2, false)
+            .ok()
+            .unwrap()
+            .total_count,
+        7
+    );
+    assert!(matches!(
+        get_comments_after(10, Some(1_234), 2, false),
+        Err(Error::NotFound {
+            context: ErrorContext::Comment(1_234),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn deleted_comments_keep_their_place_until_purged() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+    keep_open(10);
+    set_caller(user(2));
+    let parent = comment_on_post(comment(10, "parent")).ok().unwrap().id;
+    set_caller(user(1));
+    let reply = comment_on_post(CommentPayload {
+        reply_to: Some(parent),
+        ..comment(10, "reply")
+    })
+    .ok()
+    .unwrap()
+    .id;
+
+    set_caller(user(1));
+    assert!(matches!(
+        delete_comment(parent),
+        Err(Error::PermissionError { .. })
+    ));
+    set_caller(user(2));
+    let deleted = delete_comment(parent).ok().unwrap();
+    assert!(deleted.deleted && deleted.content == DELETED_COMMENT_CONTENT);
+    assert!(matches!(
+        delete_comment(parent),
+        Err(Error::NotFound { .. })
+    ));
+    assert_eq!(visible_comments(10), vec![parent, reply]);
+    let thread = get_all_comments_on_proposal(10).ok().unwrap();
+    assert!(thread[0].content == DELETED_COMMENT_CONTENT && thread[1].reply_to == Some(parent));
+    set_caller(user(1));
+    assert!(matches!(
+        like_comment(parent),
+        Err(Error::InvalidInput { .. })
+    ));
+    assert_eq!(_get_comment(&parent).unwrap().like_count, 0);
+
+    // only the DAO's owner purges, which removes the record
+    set_caller(user(2));
+    assert!(matches!(
+        purge_comment(parent),
+        Err(Error::PermissionError { .. })
+    ));
+    set_caller(user(1));
+    assert!(purge_comment(parent).is_ok());
+    assert!(_get_comment(&parent).is_none());
+    assert_eq!(visible_comments(10), vec![reply]);
+    assert!(matches!(purge_comment(parent), Err(Error::NotFound { .. })));
+
+    // proposals with votes can't be deleted, only cancelled
+    set_caller(user(2));
+    assert!(upvote(10).is_ok());
+    set_caller(user(1));
+    assert!(matches!(
+        delete_proposal(10),
+        Err(Error::InvalidInput { .. })
+    ));
+    assert!(_get_proposal(&10).is_some());
+}
+
+#[test]
+fn new_constitution_versions_must_be_accepted_again_before_voting() {
+    insert_dao(DaoVisibility::Private);
+    for id in 10..13 {
+        insert_proposal(id);
+    }
+    set_caller(user(2));
+    assert!(matches!(
+        set_constitution(1, "Be kind".to_string()),
+        Err(Error::PermissionError { .. })
+    ));
+    set_caller(user(1));
+    for invalid in [" ".to_string(), "x".repeat(MAX_CONSTITUTION_LEN + 1)] {
+        assert!(matches!(
+            set_constitution(1, invalid),
+            Err(Error::InvalidInput { .. })
+        ));
+    }
+    assert_eq!(
+        set_constitution(1, "Be kind".to_string())
+            .ok()
+            .unwrap()
+            .version,
+        1
+    );
+    assert!(set_constitution_required(1, true).is_ok());
+
+    set_caller(user(2));
+    assert!(matches!(
+        upvote(10),
+        Err(Error::ConstitutionNotAccepted { .. })
+    ));
+    assert!(matches!(
+        accept_constitution(1, 2),
+        Err(Error::NotFound { .. })
+    ));
+    assert!(accept_constitution(1, 1).is_ok());
+    assert!(upvote(10).is_ok());
+
+    // a new version has to be accepted again, older versions stay readable
+    set_caller(user(1));
+    assert_eq!(
+        set_constitution(1, "Be kinder".to_string())
+            .ok()
+            .unwrap()
+            .version,
+        2
+    );
+    set_caller(user(2));
+    assert!(get_constitution(1, None).ok().unwrap().text == "Be kinder");
+    assert!(get_constitution(1, Some(1)).ok().unwrap().text == "Be kind");
+    assert!(matches!(
+        upvote(11),
+        Err(Error::ConstitutionNotAccepted { .. })
+    ));
+    assert!(accept_constitution(1, 2).is_ok());
+    assert!(accept_constitution(1, 1).is_ok());
+    assert!(upvote(11).is_ok());
+
+    // without the gate nobody has to accept anything
+    set_caller(user(1));
+    assert!(set_constitution(1, "Be kindest".to_string()).is_ok());
+    assert!(set_constitution_required(1, false).is_ok());
+    set_caller(user(2));
+    assert!(upvote(12).is_ok());
+}
+
+fn depending_on(title: &str, prerequisite: u64) -> u64 {
+    run(add_proposal(ProposalPayload {
+        depends_on: Some(prerequisite),
+        ..proposal_payload(title)
+    }))
+    .ok()
+    .unwrap()
+    .id
+}
+
+fn status(id: u64) -> ProposalStatus {
+    _get_proposal(&id).unwrap().status
+}
+
+#[test]
+fn dependents_open_or_fail_with_their_prerequisite() {
+    insert_dao(DaoVisibility::Private);
+    insert_listed_proposal(10, "Phase 1", "");
+    insert_listed_proposal(11, "Plan B", "");
+    set_caller(user(2));
+    let phase_2 = depending_on("Phase 2", 10);
+    let phase_3 = depending_on("Phase 3", phase_2);
+    let fallback = depending_on("Fallback", 11);
+    let fallback_2 = depending_on("Fallback 2", fallback);
+    assert!(
+        status(phase_2) == ProposalStatus::Blocked && status(fallback_2) == ProposalStatus::Blocked
+    );
+    assert_eq!(_get_proposal(&phase_2).unwrap().deadline, 0);
+    assert!(matches!(upvote(phase_2), Err(Error::InvalidInput { .. })));
+    assert!(matches!(
+        run(add_proposal(ProposalPayload {
+            depends_on: Some(99),
+            ..proposal_payload("Nowhere")
+        })),
+        Err(Error::NotFound { .. })
+    ));
+
+    // approving phase 1 opens phase 2 from then on, phase 3 waits for phase 2
+    assert!(upvote(10).is_ok());
+    set_caller(user(1));
+    set_time(1_001);
+    assert!(end_proposal_vote(10).is_ok());
+    assert!(status(phase_2) == ProposalStatus::Open);
+    assert_eq!(
+        _get_proposal(&phase_2).unwrap().deadline,
+        1_001 + _voting_period(&1)
+    );
+    assert!(status(phase_3) == ProposalStatus::Blocked);
+
+    // rejecting plan B rejects the whole chain behind it
+    assert!(end_proposal_vote(11).is_ok());
+    assert!(status(11) == ProposalStatus::Rejected);
+    assert!(status(fallback) == ProposalStatus::Rejected);
+    assert!(status(fallback_2) == ProposalStatus::Rejected);
+    set_caller(user(2));
+    assert!(matches!(
+        run(add_proposal(ProposalPayload {
+            depends_on: Some(11),
+            ..proposal_payload("Too late")
+        })),
+        Err(Error::InvalidInput { .. })
+    ));
+}
+
+#[test]
+fn dependency_chains_refuse_cycles_and_stop_at_the_depth_cap() {
+    insert_dao(DaoVisibility::Private);
+    // 100 depends on nothing, every next proposal on the one before it
+    for id in 100..100 + MAX_DEPENDENCY_DEPTH as u64 {
+        let proposal = Proposal {
+            depends_on: (id > 100).then(|| id - 1),
+            ..insert_listed_proposal(id, "Link", "")
+        };
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal));
+    }
+
+    // 100 can't be published depending on anything that already depends on it
+    assert!(matches!(
+        _validate_dependency(&1, &101, Some(100)),
+        Err(Error::InvalidInput { msg, .. }) if msg.contains("already depends")
+    ));
+    // the new proposal counts towards the depth
+    let deepest = 99 + MAX_DEPENDENCY_DEPTH as u64;
+    assert!(_validate_dependency(&1, &(deepest - 1), None).is_ok());
+    assert!(matches!(
+        _validate_dependency(&1, &deepest, None),
+        Err(Error::InvalidInput { msg, .. }) if msg.contains("more than")
+    ));
+}
+
+#[test]
+fn scheduled_proposals_take_votes_once_opened() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    set_time(1_000);
+    for starts_at in [1_000, 1_001 + MAX_SCHEDULE_AHEAD] {
+        assert!(matches!(
+            run(add_proposal(ProposalPayload {
+                voting_starts_at: Some(starts_at),
+                ..proposal_payload("Out of range")
+            })),
+            Err(Error::InvalidInput { .. })
+        ));
+    }
+    let starts_at = 1_000 + HOUR;
+    let scheduled = |title: &str| {
+        run(add_proposal(ProposalPayload {
+            voting_starts_at: Some(starts_at),
+            ..proposal_payload(title)
+        }))
+        .ok()
+        .unwrap()
+    };
+    let later = scheduled("Later");
+    let now = scheduled("Now");
+    assert!(later.status == ProposalStatus::Scheduled);
+    assert_eq!(later.deadline, starts_at + _voting_period(&1));
+
+    // members see it with its start but can't vote yet
+    set_caller(user(2));
+    let listed = get_all_proposals(1).ok().unwrap();
+    assert!(listed
+        .iter()
+        .all(|view| view.voting_starts_at == Some(starts_at)));
+    assert!(matches!(upvote(later.id), Err(Error::InvalidInput { .. })));
+    assert!(matches!(
+        open_proposal_now(now.id),
+        Err(Error::PermissionError { .. })
+    ));
+
+    // the owner can open it early, its voting period starts then
+    set_caller(user(1));
+    set_time(2_000);
+    let opened = open_proposal_now(now.id).ok().unwrap();
+    assert!(opened.status == ProposalStatus::Open);
+    assert_eq!(opened.deadline, 2_000 + _voting_period(&1));
+    assert!(matches!(
+        open_proposal_now(now.id),
+        Err(Error::InvalidInput { .. })
+    ));
+    set_caller(user(2));
+    assert!(upvote(now.id).is_ok());
+    assert!(matches!(upvote(later.id), Err(Error::InvalidInput { .. })));
+}
+
+fn weighted(id: u64) -> (u64, u64, u64, u64) {
+    let breakdown = get_vote_breakdown(id).ok().unwrap();
+    (
+        breakdown.upvotes,
+        breakdown.downvotes,
+        breakdown.weighted_upvotes,
+        breakdown.weighted_downvotes,
+    )
+}
+
+#[test]
+fn weighted_votes_keep_the_weight_of_the_role_they_were_cast_with() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    assert!(add_members_bulk(1, (0..3).map(member).collect()).is_ok());
+    for invalid in [0, MAX_ROLE_VOTE_WEIGHT + 1] {
+        assert!(matches!(
+            set_role_vote_weights(
+                1,
+                Some(RoleVoteWeights {
+                    owner: invalid,
+                    ..Default::default()
+                })
+            ),
+            Err(Error::InvalidInput { .. })
+        ));
+    }
+    let weights = RoleVoteWeights {
+        owner: 3,
+        admin: 2,
+        member: 1,
+    };
+    assert!(set_role_vote_weights(1, Some(weights)).is_ok());
+    assert!(set_dao_admin(1, member(2), true).is_ok());
+    for id in [10, 11] {
+        let proposal = Proposal {
+            owner: Some(member(0)),
+            ..insert_listed_proposal(id, "Proposal", "")
+        };
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal));
+    }
+
+    // the owner's upvote outweighs two downvotes of members
+    assert!(upvote(10).is_ok());
+    for voter in [user(2), member(1)] {
+        set_caller(voter);
+        assert!(downvote(10).is_ok());
+    }
+    assert_eq!(weighted(10), (1, 2, 3, 2));
+
+    // an admin demoted after voting keeps the weight they voted with
+    set_caller(member(2));
+    assert!(upvote(11).is_ok());
+    set_caller(member(1));
+    assert!(downvote(11).is_ok());
+    set_caller(user(1));
+    assert!(set_dao_admin(1, member(2), false).is_ok());
+    assert_eq!(weighted(11), (1, 1, 2, 1));
+
+    set_caller(member(0));
+    set_time(1_001);
+    for id in [10, 11] {
+        assert!(end_proposal_vote(id).is_ok());
+        assert!(status(id) == ProposalStatus::Approved);
+    }
+}
+
+#[test]
+fn comments_check_membership_of_the_dao_they_belong_to() {
+    // user 2 is a member of DAO 1, DAO 2 belongs to member 9 alone
+    insert_dao(DaoVisibility::Public);
+    let mut other = Dao {
+        id: 2,
+        name: "Other".to_string(),
+        owner: Some(member(9)),
+        ..Default::default()
+    };
+    _set_membership(&mut other, &member(9), Some(Membership::Member));
+    do_insert_dao(&mut other);
+    let mut settings = DaoSettings {
+        dao_id: 2,
+        visibility: DaoVisibility::Public,
+        ..Default::default()
+    };
+    DAO_SETTINGS.with(|service| service.borrow_mut().insert(2, settings.clone()));
+    insert_listed_proposal(10, "Ours", "");
+    let theirs = Proposal {
+        dao_id: 2,
+        owner: Some(member(9)),
+        ..insert_proposal(20)
+    };
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(20, theirs));
+
+    set_caller(user(1));
+    let ours = comment_on_post(comment(10, "ours")).ok().unwrap();
+    set_caller(member(9));
+    let theirs = comment_on_post(comment(20, "theirs")).ok().unwrap();
+
+    set_caller(user(2));
+    assert!(like_comment(ours.id).is_ok());
+    assert!(matches!(
+        like_comment(theirs.id),
+        Err(Error::NotAMember { context, .. }) if context == ErrorContext::Dao(2)
+    ));
+    assert!(matches!(
+        get_all_comments_on_proposal(20),
+        Err(Error::NotAMember { .. })
+    ));
+    assert_eq!(visible_comments(10), vec![ours.id]);
+
+    // once DAO 2 is private its comments don't exist for outsiders
+    settings.visibility = DaoVisibility::Private;
+    DAO_SETTINGS.with(|service| service.borrow_mut().insert(2, settings));
+    assert!(matches!(
+        like_comment(theirs.id),
+        Err(Error::NotFound { .. })
+    ));
+    assert!(is_proposal_not_found(&get_all_comments_on_proposal(20), 20));
+    assert_eq!(_get_comment(&theirs.id).unwrap().like_count, 0);
+}
+
+fn config_values(config: &CanisterConfig) -> (Option<Principal>, Option<u64>, Option<u64>) {
+    (
+        config.admin,
+        config.voting_period_seconds,
+        config.max_daos_per_user,
+    )
+}
+
+#[test]
+fn init_args_survive_upgrades_that_override_parts_of_them() {
+    init(Some(InitArgs {
+        admins: vec![user(1), user(2)],
+        voting_period_seconds: Some(3_600),
+        max_daos_per_user: Some(2),
+        ledger_canister_id: Some(member(7)),
+        ..Default::default()
+    }));
+    let installed = get_config();
+    assert_eq!(
+        config_values(&installed),
+        (Some(user(1)), Some(3_600), Some(2))
+    );
+    assert_eq!(installed.additional_admins, Some(vec![user(2)]));
+    assert_eq!(installed.ledger_canister_id, Some(member(7)));
+
+    // an upgrade without args keeps everything, one with args only what they set
+    post_upgrade(None);
+    assert_eq!(
+        config_values(&get_config()),
+        (Some(user(1)), Some(3_600), Some(2))
+    );
+    post_upgrade(Some(InitArgs {
+        max_daos_per_user: Some(5),
+        ..Default::default()
+    }));
+    let upgraded = get_config();
+    assert_eq!(
+        config_values(&upgraded),
+        (Some(user(1)), Some(3_600), Some(5))
+    );
+    assert_eq!(upgraded.ledger_canister_id, Some(member(7)));
+
+    // only admins update it, and not to zero
+    let args = InitArgs {
+        voting_period_seconds: Some(60),
+        ..Default::default()
+    };
+    set_caller(user(3));
+    assert!(matches!(
+        update_config(args.clone()),
+        Err(Error::Unauthorized { .. })
+    ));
+    set_caller(user(2));
+    assert!(matches!(
+        update_config(InitArgs {
+            voting_period_seconds: Some(0),
+            ..Default::default()
+        }),
+        Err(Error::InvalidInput { .. })
+    ));
+    assert!(update_config(args).is_ok());
+    assert_eq!(
+        config_values(&get_config()),
+        (Some(user(1)), Some(60), Some(5))
+    );
+}
+
+fn whoami_memberships() -> Vec<(u64, Role, bool)> {
+    whoami()
+        .memberships
+        .iter()
+        .map(|membership| (membership.dao_id, membership.role, membership.is_active))
+        .collect()
+}
+
+#[test]
+fn whoami_describes_any_caller_without_failing() {
+    insert_dao(DaoVisibility::Public);
+    let mut other = Dao {
+        id: 2,
+        name: "Other".to_string(),
+        owner: Some(user(1)),
+        ..Default::default()
+    };
+    _set_membership(&mut other, &user(2), Some(Membership::Admin));
+    do_insert_dao(&mut other);
+
+    set_caller(member(5));
+    let fresh = whoami();
+    assert!(fresh.principal == member(5));
+    assert!(!fresh.is_anonymous);
+    assert!(fresh.profile.is_none());
+    assert!(fresh.memberships.is_empty());
+    assert_eq!(fresh.unread_notifications, 0);
+
+    set_caller(Principal::anonymous());
+    let anonymous = whoami();
+    assert!(anonymous.is_anonymous);
+    assert!(anonymous.profile.is_none() && anonymous.memberships.is_empty());
+
+    set_caller(user(2));
+    assert!(set_my_profile(profile("Bob")).is_ok());
+    notify(&user(2), NotificationKind::Mention, 1, None, None);
+    notify(&user(2), NotificationKind::Invited, 2, None, None);
+    let mut memberships = whoami_memberships();
+    memberships.sort_by_key(|(dao_id, ..)| *dao_id);
+    assert!(memberships == vec![(1, Role::Member, true), (2, Role::Admin, true)]);
+    let known = whoami();
+    assert!(known
+        .profile
+        .is_some_and(|profile| profile.display_name == "Bob"));
+    assert_eq!(known.unread_notifications, 2);
+    assert_eq!(mark_all_read(), 2);
+    assert_eq!(whoami().unread_notifications, 0);
+}
+
+#[test]
+fn vote_receipts_digest_the_ballots_of_finalized_proposals() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    assert!(add_members_bulk(1, vec![member(1)]).is_ok());
+    for id in [10, 11, 12] {
+        insert_listed_proposal(id, "Proposal", "");
+    }
+    // 10 and 11 get the same ballots, member 1 upvotes 12 instead
+    set_caller(user(2));
+    for id in [10, 11, 12] {
+        assert!(upvote(id).is_ok());
+    }
+    set_caller(member(1));
+    assert!(downvote(10).is_ok() && downvote(11).is_ok());
+    assert!(upvote(12).is_ok());
+    assert!(matches!(
+        get_vote_receipt(10),
+        Err(Error::InvalidInput { .. })
+    ));
+
+    set_caller(user(1));
+    set_time(1_001);
+    for id in [10, 11, 12] {
+        assert!(end_proposal_vote(id).is_ok());
+    }
+    set_caller(member(1));
+    let receipt = get_vote_receipt(10).ok().unwrap();
+    assert!(receipt
+        .vote
+        .is_some_and(|vote| vote.direction == VoteDirection::Down));
+    assert_eq!((receipt.tally.upvotes, receipt.tally.downvotes), (1, 1));
+    assert_eq!(receipt.result_digest.len(), 32);
+    assert_eq!(
+        get_vote_receipt(11).ok().unwrap().result_digest,
+        receipt.result_digest
+    );
+    assert!(get_vote_receipt(12).ok().unwrap().result_digest != receipt.result_digest);
+
+    // members who didn't vote get the digest without a vote
+    set_caller(user(1));
+    assert!(get_vote_receipt(10).ok().unwrap().vote.is_none());
+}
+
+#[test]
+fn bulk_imports_add_new_members_and_report_the_rest() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    assert!(ban_member(1, user(3)).is_ok());
+    set_time(50);
+
+    let result = add_members_bulk(1, vec![member(1), member(1), user(2), user(3), member(2)])
+        .ok()
+        .unwrap();
+    assert_eq!(result.added, vec![member(1), member(2)]);
+    assert_eq!(result.already_members, vec![member(1), user(2)]);
+    assert_eq!(result.banned, vec![user(3)]);
+
+    let dao = _get_dao(&1).unwrap();
+    for added in [member(1), member(2)] {
+        assert!(_is_member(&dao, &added));
+        assert_eq!(_joined_at(&dao, &added), Some(50));
+        set_caller(added);
+        assert!(notification_kinds(true) == vec![NotificationKind::AddedToDao]);
+        assert!(whoami_memberships() == vec![(1, Role::Member, true)]);
+    }
+    assert!(!_is_member(&dao, &user(3)));
+
+    // only the owner imports, at most a batch at a time
+    set_caller(user(2));
+    assert!(matches!(
+        add_members_bulk(1, vec![member(3)]),
+        Err(Error::PermissionError { .. })
+    ));
+    set_caller(user(1));
+    assert!(matches!(
+        add_members_bulk(1, (0..=MAX_MEMBERS_PER_BATCH as u32).map(member).collect()),
+        Err(Error::InvalidInput { .. })
+    ));
+    assert!(!_is_member(&_get_dao(&1).unwrap(), &member(0)));
+}
+
+#[test]
+fn deleting_a_dao_leaves_no_comments_behind() {
+    insert_dao(DaoVisibility::Private);
+    let mut comment_ids = Vec::new();
+    for id in [10, 11] {
+        insert_listed_proposal(id, "Proposal", "");
+        for author in [user(1), user(2)] {
+            set_caller(author);
+            comment_ids.push(comment_on_post(comment(id, "Comment")).ok().unwrap().id);
+        }
+    }
+    set_caller(user(1));
+    set_time(1_001);
+    for id in [10, 11] {
+        assert!(end_proposal_vote(id).is_ok());
+    }
+    assert!(archive_dao(1).is_ok());
+    assert!(delete_dao(1).is_ok());
+
+    for id in [10, 11] {
+        assert!(_comment_ids(&id).is_empty());
+        assert!(is_proposal_not_found(&get_all_comments_on_proposal(id), id));
+    }
+    set_caller(user(2));
+    for id in comment_ids {
+        assert!(_get_comment(&id).is_none());
+        assert!(matches!(
+            like_comment(id),
+            Err(Error::NotFound { context, .. }) if context == ErrorContext::Comment(id)
+        ));
+    }
+    // a DAO that is gone isn't mistaken for one the caller isn't part of
+    assert!(matches!(
+        _is_user_part_of_dao(&1),
+        Err(Error::NotFound { context, .. }) if context == ErrorContext::Dao(1)
+    ));
+}
+
+#[test]
+fn archived_daos_are_read_only_and_deleted_only_once_archived() {
+    insert_dao(DaoVisibility::Public);
+    insert_listed_proposal(10, "Proposal", "");
+    set_caller(user(2));
+    assert!(comment_on_post(comment(10, "Before")).is_ok());
+
+    set_caller(user(1));
+    assert!(matches!(delete_dao(1), Err(Error::InvalidInput { .. })));
+    set_caller(user(2));
+    assert!(matches!(archive_dao(1), Err(Error::PermissionError { .. })));
+    set_caller(user(1));
+    assert!(archive_dao(1).is_ok());
+    assert!(list_public_daos(0, MAX_PAGE_SIZE).is_empty());
+
+    set_caller(user(2));
+    assert!(matches!(
+        run(add_proposal(proposal_payload("New"))),
+        Err(Error::DaoArchived { .. })
+    ));
+    assert!(matches!(upvote(10), Err(Error::DaoArchived { .. })));
+    assert!(matches!(
+        comment_on_post(comment(10, "After")),
+        Err(Error::DaoArchived { .. })
+    ));
+    assert!(get_dao(1).is_ok());
+    assert!(get_proposal(10).is_ok());
+    assert!(get_all_comments_on_proposal(10).is_ok_and(|comments| comments.len() == 1));
+
+    // archived but with a proposal still open for voting
+    set_caller(user(1));
+    assert!(export_dao(1).is_ok());
+    assert!(matches!(delete_dao(1), Err(Error::InvalidInput { .. })));
+
+    assert!(unarchive_dao(1).is_ok());
+    assert_eq!(list_public_daos(0, MAX_PAGE_SIZE).len(), 1);
+    set_caller(user(2));
+    assert!(upvote(10).is_ok());
+    set_caller(user(1));
+    set_time(1_001);
+    assert!(end_proposal_vote(10).is_ok());
+    assert!(archive_dao(1).is_ok());
+    assert!(delete_dao(1).is_ok());
+    assert!(_get_dao(&1).is_none());
+}
+
+fn kind_payload(title: &str, kind: ProposalKind) -> ProposalPayload {
+    ProposalPayload {
+        kind: Some(kind),
+        ..proposal_payload(title)
+    }
+}
+
+#[test]
+fn proposal_kinds_are_validated_and_membership_changes_executed() {
+    insert_dao(DaoVisibility::Private);
+    let change = |target, action| ProposalKind::MembershipChange { target, action };
+
+    set_caller(user(2));
+    for (kind, variant) in [
+        (
+            ProposalKind::Funding {
+                amount: 0,
+                recipient: None,
+            },
+            "InvalidInput",
+        ),
+        (
+            ProposalKind::Funding {
+                amount: 10,
+                recipient: Some(Principal::anonymous()),
+            },
+            "InvalidInput",
+        ),
+        (change(user(2), MembershipAction::Add), "AlreadyMember"),
+        (change(user(1), MembershipAction::Remove), "PermissionError"),
+        (change(member(4), MembershipAction::Remove), "NotAMember"),
+    ] {
+        let result = run(add_proposal(kind_payload("Invalid", kind)));
+        assert_eq!(error_shape(result.map(|_| ())).0, variant);
+    }
+
+    let funding = run(add_proposal(kind_payload(
+        "Funding",
+        ProposalKind::Funding {
+            amount: 10,
+            recipient: None,
+        },
+    )))
+    .ok()
+    .unwrap();
+    assert_eq!(funding.amount_requested, 10);
+    let signal = run(add_proposal(kind_payload("Signal", ProposalKind::Signal)))
+        .ok()
+        .unwrap();
+    assert_eq!(signal.amount_requested, 0);
+    let add = run(add_proposal(kind_payload(
+        "Add",
+        change(member(4), MembershipAction::Add),
+    )))
+    .ok()
+    .unwrap();
+
+    // approved and executed, membership changes take effect and signals have nothing to do
+    set_caller(user(1));
+    assert!(upvote(signal.id).is_ok() && upvote(add.id).is_ok());
+    set_time(VOTING_PERIOD + 1);
+    set_caller(user(2));
+    assert!(end_proposal_vote(signal.id).is_ok() && end_proposal_vote(add.id).is_ok());
+    set_caller(user(1));
+    assert!(matches!(
+        run(execute_proposal(signal.id)),
+        Err(Error::InvalidInput { .. })
+    ));
+    assert!(run(execute_proposal(add.id)).is_ok());
+    assert!(_is_member(&_get_dao(&1).unwrap(), &member(4)));
+
+    set_caller(user(2));
+    let remove = run(add_proposal(kind_payload(
+        "Remove",
+        change(member(4), MembershipAction::Remove),
+    )))
+    .ok()
+    .unwrap();
+    set_caller(user(1));
+    assert!(upvote(remove.id).is_ok());
+    set_time(2 * VOTING_PERIOD + 2);
+    set_caller(user(2));
+    assert!(end_proposal_vote(remove.id).is_ok());
+    set_caller(user(1));
+    assert!(run(execute_proposal(remove.id)).is_ok());
+    assert!(!_is_member(&_get_dao(&1).unwrap(), &member(4)));
+}
+
+#[test]
+fn dao_settings_default_for_legacy_daos_and_patch_only_what_is_set() {
+    // a DAO moved from the first release, which had no settings
+    let mut dao = Dao {
+        id: 1,
+        owner: Some(user(1)),
+        ..Default::default()
+    };
+    _set_membership(&mut dao, &user(2), Some(Membership::Member));
+    DAO_STORAGE.with(|service| service.borrow_mut().insert(1, dao));
+    set_caller(user(1));
+    let legacy = get_dao_settings(1).ok().unwrap();
+    assert_eq!(legacy.dao_id, 1);
+    assert_eq!(
+        legacy.approval_threshold_percent,
+        DEFAULT_APPROVAL_THRESHOLD_PERCENT
+    );
+    assert_eq!(legacy.report_threshold, DEFAULT_REPORT_THRESHOLD);
+    assert!(legacy.auto_lock_comments && !legacy.public_ballots);
+    assert_eq!(legacy.quorum_percent, 0);
+    assert_eq!(legacy.timelock_seconds, 0);
+    assert!(legacy.voting_period_seconds.is_none());
+    assert!(set_approval_threshold(1, 60).is_ok());
+
+    let patched = update_dao_settings(
+        1,
+        DaoSettingsPatch {
+            quorum_percent: Some(30),
+            voting_period_seconds: Some(MIN_VOTING_PERIOD_SECONDS),
+            ..Default::default()
+        },
+    )
+    .ok()
+    .unwrap();
+    assert_eq!(patched.quorum_percent, 30);
+    let patched = update_dao_settings(
+        1,
+        DaoSettingsPatch {
+            timelock_seconds: Some(60),
+            ..Default::default()
+        },
+    )
+    .ok()
+    .unwrap();
+    assert_eq!(
+        (
+            patched.quorum_percent,
+            patched.timelock_seconds,
+            patched.approval_threshold_percent
+        ),
+        (30, 60, 60)
+    );
+    assert_eq!(
+        patched.voting_period_seconds,
+        Some(MIN_VOTING_PERIOD_SECONDS)
+    );
+    assert_eq!(
+        _voting_period(&1),
+        MIN_VOTING_PERIOD_SECONDS * 1_000_000_000
+    );
+
+    for invalid in [
+        DaoSettingsPatch {
+            voting_period_seconds: Some(MIN_VOTING_PERIOD_SECONDS - 1),
+            ..Default::default()
+        },
+        DaoSettingsPatch {
+            voting_period_seconds: Some(MAX_VOTING_PERIOD_SECONDS + 1),
+            ..Default::default()
+        },
+        DaoSettingsPatch {
+            quorum_percent: Some(101),
+            ..Default::default()
+        },
+        DaoSettingsPatch {
+            approval_threshold_percent: Some(0),
+            ..Default::default()
+        },
+        DaoSettingsPatch {
+            timelock_seconds: Some(MAX_TIMELOCK_SECONDS + 1),
+            ..Default::default()
+        },
+        DaoSettingsPatch {
+            dues_amount: Some(5),
+            ..Default::default()
+        },
+        DaoSettingsPatch {
+            max_comments_per_window: Some(MAX_ACTIONS_PER_WINDOW + 1),
+            ..Default::default()
+        },
+        // the valid quorum isn't applied when the rest of the patch is invalid
+        DaoSettingsPatch {
+            quorum_percent: Some(50),
+            max_proposals_per_window: Some(0),
+            ..Default::default()
+        },
+    ] {
+        assert!(matches!(
+            update_dao_settings(1, invalid),
+            Err(Error::InvalidInput { .. })
+        ));
+    }
+    assert_eq!(get_dao_settings(1).ok().unwrap().quorum_percent, 30);
+
+    set_caller(user(2));
+    assert!(matches!(
+        update_dao_settings(1, DaoSettingsPatch::default()),
+        Err(Error::PermissionError { .. })
+    ));
+}
+
+#[test]
+fn dao_settings_fit_their_bound_with_every_setting_at_its_largest() {
+    let largest = DaoSettings {
+        dao_id: u64::MAX,
+        voting_period_seconds: Some(u64::MAX),
+        quorum_percent: 100,
+        approval_threshold_percent: 100,
+        visibility: DaoVisibility::Private,
+        timelock_seconds: u64::MAX,
+        dues_amount: u64::MAX,
+        dues_period_seconds: u64::MAX,
+        max_proposals_per_window: Some(u64::MAX),
+        max_comments_per_window: Some(u64::MAX),
+        updated_at: Some(u64::MAX),
+        webhook: Some(webhooks::Webhook {
+            url: "a".repeat(webhooks::MAX_URL_LEN),
+            secret: "s".repeat(webhooks::MAX_SECRET_LEN),
+        }),
+        reveal_window_seconds: Some(u64::MAX),
+        proposal_deposit: Some(u64::MAX),
+        merged_into: Some(u64::MAX),
+        early_close: Some(EarlyClose {
+            min_votes: u32::MAX,
+            reject_ratio_percent: 100,
+        }),
+        max_open_proposals: Some(u32::MAX),
+        hide_tally_until_closed: Some(true),
+        hide_tally_from_admins: Some(true),
+        public_ballots: true,
+        min_membership_age_seconds: u64::MAX,
+        proposal_creation_role: Role::Admin,
+        report_threshold: u64::MAX,
+        auto_lock_comments: true,
+        amendment_policy: AmendmentPolicy::RequireReconfirmation,
+        constitution_required: true,
+        role_vote_weights: Some(RoleVoteWeights {
+            owner: u32::MAX,
+            admin: u32::MAX,
+            member: u32::MAX,
+        }),
+    };
+    assert!(largest.to_bytes().len() <= DaoSettings::MAX_SIZE as usize);
+}
+
+fn like_view(proposal_id: u64) -> (u64, bool) {
+    let comments = get_all_comments_on_proposal(proposal_id).ok().unwrap();
+    (comments[0].like_count, comments[0].liked_by_caller)
+}
+
+#[test]
+fn a_hundred_members_like_one_comment() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    assert!(add_members_bulk(1, (0..100).map(member).collect()).is_ok());
+    insert_listed_proposal(10, "Proposal", "");
+    set_caller(user(2));
+    let id = comment_on_post(comment(10, "Popular")).ok().unwrap().id;
+
+    for n in 0..100 {
+        set_caller(member(n));
+        assert!(like_comment(id).is_ok());
+    }
+    assert!(matches!(like_comment(id), Err(Error::AlreadyLiked { .. })));
+    assert_eq!(like_view(10), (100, true));
+    set_caller(user(1));
+    assert_eq!(like_view(10), (100, false));
+
+    set_caller(member(0));
+    assert!(unlike_comment(id).is_ok());
+    assert_eq!(like_view(10), (99, false));
+    assert!(!_has_liked(&id, &member(0)) && _has_liked(&id, &member(99)));
+}
+
+fn activity_counts(since: u64, until: u64) -> (u64, u64, u64, u64) {
+    let activity = get_dao_activity(1, since, until).ok().unwrap();
+    (
+        activity.new_proposals,
+        activity.new_members,
+        activity.new_comments,
+        activity.votes_cast,
+    )
+}
+
+#[test]
+fn dao_activity_counts_what_happened_from_since_up_to_until() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(2));
+    set_time(100);
+    let first = run(add_proposal(proposal_payload("First")))
+        .ok()
+        .unwrap()
+        .id;
+    set_caller(user(1));
+    set_time(200);
+    assert!(add_members_bulk(1, vec![member(1)]).is_ok());
+    set_time(300);
+    assert!(upvote(first).is_ok());
+    assert!(comment_on_post(comment(first, "Comment")).is_ok());
+    set_caller(member(1));
+    assert!(upvote(first).is_ok());
+    set_caller(user(2));
+    set_time(400);
+    let second = run(add_proposal(proposal_payload("Second")))
+        .ok()
+        .unwrap()
+        .id;
+    set_caller(user(1));
+    assert!(upvote(second).is_ok());
+
+    // since is included and until is not
+    assert_eq!(activity_counts(100, 400), (1, 1, 1, 2));
+    assert_eq!(activity_counts(101, 401), (1, 1, 1, 3));
+    assert_eq!(activity_counts(300, 300), (0, 0, 0, 0));
+    let activity = get_dao_activity(1, 0, 1_000).ok().unwrap();
+    let top: Vec<(u64, u64, u64)> = activity
+        .top_proposals
+        .iter()
+        .map(|proposal| (proposal.proposal_id, proposal.votes, proposal.comments))
+        .collect();
+    assert_eq!(top, vec![(first, 2, 1), (second, 1, 0)]);
+    assert!(activity.finalized_proposals.is_empty());
+
+    let finalized_at = VOTING_PERIOD + 101;
+    set_caller(user(2));
+    set_time(finalized_at);
+    assert!(end_proposal_vote(first).is_ok());
+    let activity = get_dao_activity(1, finalized_at, finalized_at + 1)
+        .ok()
+        .unwrap();
+    assert_eq!(activity.finalized_proposals.len(), 1);
+    assert_eq!(activity.finalized_proposals[0].proposal_id, first);
+    assert!(activity.finalized_proposals[0].status == ProposalStatus::Approved);
+
+    // long windows only cover their last 90 days
+    let clamped = get_dao_activity(1, 0, MAX_ACTIVITY_WINDOW + 150)
+        .ok()
+        .unwrap();
+    assert_eq!(clamped.since, 150);
+    assert_eq!(clamped.new_proposals, 1);
+    assert!(matches!(
+        get_dao_activity(1, 2, 1),
+        Err(Error::InvalidInput { .. })
+    ));
+    set_caller(member(5));
+    assert!(matches!(
+        get_dao_activity(1, 0, 1),
+        Err(Error::NotFound { .. })
+    ));
+}
+
+#[test]
+fn finished_proposals_take_comments_only_when_their_dao_allows_it() {
+    insert_dao(DaoVisibility::Private);
+    insert_listed_proposal(10, "Proposal", "");
+    set_caller(user(1));
+    let liked = comment_on_post(comment(10, "Liked later")).ok().unwrap().id;
+    set_caller(user(2));
+    let edited = comment_on_post(comment(10, "Edited later"))
+        .ok()
+        .unwrap()
+        .id;
+    assert!(matches!(
+        comment_on_post(comment(99, "Nowhere")),
+        Err(Error::NotFound { context, .. }) if context == ErrorContext::Proposal(99)
+    ));
+
+    // expired but not yet finalized is just as closed as finalized
+    set_time(1_001);
+    for finalize in [false, true] {
+        if finalize {
+            set_caller(user(1));
+            assert!(end_proposal_vote(10).is_ok());
+            set_caller(user(2));
+        }
+        assert!(matches!(
+            comment_on_post(comment(10, "Late")),
+            Err(Error::ProposalClosed { .. })
+        ));
+        assert!(matches!(
+            update_comment(edited, comment(10, "Late edit")),
+            Err(Error::ProposalClosed { .. })
+        ));
+        assert!(matches!(
+            like_comment(liked),
+            Err(Error::ProposalClosed { .. })
+        ));
+        assert_eq!(visible_comments(10), vec![liked, edited]);
+    }
+
+    set_caller(user(1));
+    assert!(set_auto_lock_comments(1, false).is_ok());
+    set_caller(user(2));
+    assert!(comment_on_post(comment(10, "Late")).is_ok());
+    assert!(update_comment(edited, comment(10, "Late edit")).is_ok());
+    assert!(like_comment(liked).is_ok());
+}
+
+// a code for linking to `primary`, what start_link hands out once the management canister
+// answered with randomness
+fn link_code(code: u64, primary: Principal, secondary_hint: Option<Principal>) -> String {
+    LINK_REQUESTS.with(|service| {
+        service.borrow_mut().insert(
+            code,
+            LinkRequest {
+                primary,
+                secondary_hint,
+                expires_at: time() + LINK_CODE_TTL,
+            },
+        )
+    });
+    format!("{:016x}", code)
+}
+
+#[test]
+fn linked_principals_act_as_their_primary() {
+    insert_dao(DaoVisibility::Private);
+    for id in [10, 11] {
+        insert_listed_proposal(id, "Proposal", "");
+        keep_open(id);
+    }
+    set_caller(user(1));
+    assert!(add_members_bulk(1, vec![member(3)]).is_ok());
+
+    // codes are single use, expire and can be meant for one principal only
+    let hinted = link_code(1, user(2), Some(member(2)));
+    set_caller(member(1));
+    assert!(matches!(complete_link(hinted), Err(Error::NotFound { .. })));
+    let expired = link_code(2, user(2), None);
+    set_time(LINK_CODE_TTL + 1);
+    assert!(matches!(
+        complete_link(expired),
+        Err(Error::NotFound { .. })
+    ));
+    let code = link_code(3, user(2), None);
+    assert!(complete_link(code.clone()).is_ok_and(|aliases| aliases == vec![member(1)]));
+    assert!(matches!(complete_link(code), Err(Error::NotFound { .. })));
+
+    // the alias votes as user 2, who can't vote again
+    assert!(upvote(10).is_ok());
+    set_caller(user(2));
+    assert!(get_my_vote(10).is_ok_and(|vote| vote == Some(VoteDirection::Up)));
+    assert!(upvote(10).is_err());
+    assert_eq!(tally(10), (1, 0));
+
+    // no chains, no members of a DAO as aliases and at most 5 aliases
+    let from_alias = link_code(4, member(1), None);
+    set_caller(member(2));
+    assert!(matches!(
+        complete_link(from_alias),
+        Err(Error::InvalidInput { .. })
+    ));
+    let to_member = link_code(5, user(2), None);
+    set_caller(member(3));
+    assert!(matches!(
+        complete_link(to_member),
+        Err(Error::InvalidInput { .. })
+    ));
+    for n in 10..15 {
+        let code = link_code(n as u64, user(2), None);
+        set_caller(member(n));
+        let linked = complete_link(code);
+        if n < 14 {
+            assert!(linked.is_ok());
+        } else {
+            assert!(matches!(linked, Err(Error::InvalidInput { .. })));
+        }
+    }
+
+    // only the primary unlinks
+    set_caller(member(1));
+    assert!(matches!(unlink(member(1)), Err(Error::NotFound { .. })));
+    set_caller(user(2));
+    assert!(unlink(member(1)).is_ok_and(|aliases| !aliases.contains(&member(1))));
+    set_caller(member(1));
+    assert!(upvote(11).is_err());
+    assert_eq!(tally(11), (0, 0));
+}
+
+#[test]
+fn listings_are_sorted_by_time_then_id_and_empty_when_nothing_matches() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(2));
+    assert!(get_all_proposals(1).is_ok_and(|proposals| proposals.is_empty()));
+    assert!(get_final_approved_proposals(1).is_ok_and(|proposals| proposals.is_empty()));
+    insert_listed_proposal(9, "Quiet", "");
+    assert!(get_all_comments_on_proposal(9).is_ok_and(|comments| comments.is_empty()));
+
+    // created_at ties are broken by id, so are finalized_at ties
+    for (id, created_at, finalized_at) in [(10, 5, 1), (11, 3, 2), (12, 3, 1), (13, 1, 3)] {
+        let proposal = Proposal {
+            created_at,
+            status: ProposalStatus::Approved,
+            finalized_at: Some(finalized_at),
+            ..insert_listed_proposal(id, "Proposal", "")
+        };
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal));
+    }
+    assert_eq!(ids(get_all_proposals(1)), vec![13, 11, 12, 10, 9]);
+    assert_eq!(ids(get_final_approved_proposals(1)), vec![10, 12, 11, 13]);
+
+    keep_open(9);
+    let mut posted = Vec::new();
+    for (at, author) in [(7, user(2)), (5, user(1)), (5, user(2))] {
+        set_time(at);
+        set_caller(author);
+        posted.push(comment_on_post(comment(9, "Comment")).ok().unwrap().id);
+    }
+    let listed: Vec<u64> = get_all_comments_on_proposal(9)
+        .ok()
+        .unwrap()
+        .iter()
+        .map(|comment| comment.id)
+        .collect();
+    assert_eq!(listed, vec![posted[1], posted[2], posted[0]]);
+}
+
+fn reply(proposal_id: u64, parent: u64) -> CommentPayload {
+    CommentPayload {
+        reply_to: Some(parent),
+        ..comment(proposal_id, "Reply")
+    }
+}
+
+fn threads() -> Vec<u64> {
+    ids(get_threads_i_participated_in(1, 0, MAX_PAGE_SIZE))
+}
+
+fn participations(user: &Principal) -> usize {
+    let key = principal_key(user);
+    THREAD_PARTICIPATION.with(|service| {
+        service
+            .borrow()
+            .range((key, 0)..)
+            .take_while(|((participant, _), _)| *participant == key)
+            .count()
+    })
+}
+
+#[test]
+fn replies_notify_the_parent_author_and_threads_follow_participation() {
+    insert_dao(DaoVisibility::Private);
+    for id in [10, 11] {
+        insert_listed_proposal(id, "Proposal", "");
+        keep_open(id);
+    }
+    set_caller(user(2));
+    assert!(threads().is_empty());
+    set_time(1);
+    let parent = comment_on_post(comment(10, "Parent")).ok().unwrap().id;
+    assert_eq!(threads(), vec![10]);
+
+    set_caller(user(1));
+    set_time(2);
+    let answer = comment_on_post(reply(10, parent)).ok().unwrap().id;
+    set_caller(user(2));
+    let replies: Vec<Option<u64>> = get_my_notifications(true, 0, MAX_PAGE_SIZE)
+        .iter()
+        .filter(|notification| notification.kind == NotificationKind::Reply)
+        .map(|notification| notification.comment_id)
+        .collect();
+    assert_eq!(replies, vec![Some(answer)]);
+
+    // replying to yourself notifies no one
+    assert!(mark_all_read() > 0);
+    set_time(3);
+    assert!(comment_on_post(reply(10, parent)).is_ok());
+    assert!(notification_kinds(true).is_empty());
+
+    // only the first comment on a proposal adds it, later ones move it up
+    set_time(4);
+    assert!(comment_on_post(comment(11, "Elsewhere")).is_ok());
+    assert_eq!(threads(), vec![11, 10]);
+    set_time(5);
+    assert!(comment_on_post(comment(10, "Back again")).is_ok());
+    assert_eq!(threads(), vec![10, 11]);
+    assert_eq!(participations(&user(2)), 2);
+    assert_eq!(participations(&user(1)), 1);
+}
+
+fn last_audit_action() -> Option<AuditAction> {
+    AUDIT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .last()
+            .map(|(_, event)| event.action)
+    })
+}
+
+#[test]
+fn comments_are_taken_up_to_their_longest() {
+    insert_dao(DaoVisibility::Private);
+    insert_listed_proposal(10, "Thanks", "");
+    set_caller(user(1));
+    assert!(add_members_bulk(1, (0..10).map(member).collect()).is_ok());
+
+    // every member mentioned, padded to the limit with characters of four bytes
+    set_caller(user(2));
+    let mentions: Vec<String> = (0..10)
+        .map(|n| format!("@{}", member(n).to_text()))
+        .collect();
+    let mentions = mentions.join(" ") + " ";
+    let padding = MAX_COMMENT_LEN - mentions.chars().count();
+    let longest = mentions + &"𝄞".repeat(padding);
+    let first = comment_on_post(comment(10, "first")).ok().unwrap();
+    let reply = comment_on_post(CommentPayload {
+        reply_to: Some(first.id),
+        stance: Some(Stance::Against),
+        ..comment(10, &longest)
+    })
+    .ok()
+    .unwrap();
+    assert_eq!(reply.mentions.len(), MAX_MENTIONS_PER_COMMENT);
+    assert!(_get_comment(&reply.id).is_some_and(|stored| stored.content == longest));
+
+    let too_long = longest.clone() + "!";
+    assert!(matches!(
+        comment_on_post(comment(10, &too_long)),
+        Err(Error::InvalidInput { .. })
+    ));
+    assert!(matches!(
+        update_comment(reply.id, comment(10, &too_long)),
+        Err(Error::InvalidInput { .. })
+    ));
+    assert!(_get_comment(&reply.id).is_some_and(|stored| stored.content == longest));
+    assert!(update_comment(first.id, comment(10, &longest)).is_ok());
+
+    // the largest record: every field at its longest, with principals of 29 bytes
+    let principal = Principal::from_slice(&[7; 29]);
+    let largest = Comment {
+        id: u64::MAX,
+        content: "𝄞".repeat(MAX_COMMENT_LEN),
+        author: Some(principal),
+        like_count: u64::MAX,
+        proposal_id: u64::MAX,
+        created_at: u64::MAX,
+        updated_at: Some(u64::MAX),
+        mentions: vec![principal; MAX_MENTIONS_PER_COMMENT],
+        schema_version: u8::MAX,
+        reply_to: Some(u64::MAX),
+        removal: Some(Removal {
+            removed_by: principal,
+            reason: "𝄞".repeat(MAX_REMOVAL_REASON_LEN),
+            removed_at: u64::MAX,
+        }),
+        stance: Some(Stance::Neutral),
+        link_previews: vec![u64::MAX; 3],
+        ..Default::default()
+    };
+    assert!(largest.to_bytes().len() <= Comment::MAX_SIZE as usize);
+    do_insert_comment(&largest);
+}
+
+#[test]
+fn admins_take_down_content_for_good_with_a_reason() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    assert!(add_members_bulk(1, vec![member(1)]).is_ok());
+    insert_listed_proposal(10, "Proposal", "");
+    let spam = Proposal {
+        owner: Some(user(2)),
+        ..insert_listed_proposal(11, "Spam", "Buy now")
+    };
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(11, spam));
+    set_caller(user(2));
+    let doxxing = comment_on_post(comment(10, "Their address is"))
+        .ok()
+        .unwrap()
+        .id;
+    set_caller(user(1));
+    assert!(upvote(11).is_ok());
+
+    // members other than admins can't, and a reason is required
+    set_caller(member(1));
+    assert!(matches!(
+        admin_remove_comment(doxxing, "doxxing".to_string()),
+        Err(Error::PermissionError { .. })
+    ));
+    set_caller(user(1));
+    assert!(matches!(
+        admin_remove_comment(doxxing, " ".to_string()),
+        Err(Error::InvalidInput { .. })
+    ));
+
+    set_time(5);
+    let removed = admin_remove_comment(doxxing, "doxxing".to_string())
+        .ok()
+        .unwrap();
+    assert_eq!(removed.content, REMOVED_CONTENT);
+    assert!(removed
+        .removal
+        .is_some_and(|removal| removal.removed_by == user(1)
+            && removal.reason == "doxxing"
+            && removal.removed_at == 5));
+    assert!(last_audit_action() == Some(AuditAction::CommentRemoved));
+    assert!(matches!(
+        admin_remove_comment(doxxing, "again".to_string()),
+        Err(Error::NotFound { .. })
+    ));
+
+    // the author is told and can't bring the comment back
+    set_caller(user(2));
+    assert!(notification_kinds(true).contains(&NotificationKind::ContentRemoved));
+    assert!(update_comment(doxxing, comment(10, "Their address is")).is_err());
+    assert_eq!(_get_comment(&doxxing).unwrap().content, REMOVED_CONTENT);
+
+    // a removed open proposal is cancelled and its votes are discarded
+    set_caller(user(1));
+    let proposal = admin_remove_proposal(11, "spam".to_string()).ok().unwrap();
+    assert!(proposal.status == ProposalStatus::Cancelled);
+    assert_eq!(proposal.title, REMOVED_CONTENT);
+    assert_eq!(tally(11), (0, 0));
+    assert!(last_audit_action() == Some(AuditAction::ProposalRemoved));
+    set_caller(member(1));
+    assert!(upvote(11).is_err());
+    assert_eq!(tally(11), (0, 0));
+}
+
+fn commit(proposal_id: u64, direction: VoteDirection, salt: &[u8]) -> Result<ProposalView, Error> {
+    commit_vote(
+        proposal_id,
+        certification::ballot_commitment(direction, salt, &caller()),
+    )
+}
+
+#[test]
+fn anonymous_ballots_count_revealed_commitments_only() {
+    insert_dao(DaoVisibility::Private);
+    init(Some(InitArgs {
+        admins: vec![user(1)],
+        ..Default::default()
+    }));
+    set_caller(user(1));
+    assert!(add_members_bulk(1, vec![member(1), member(2)]).is_ok());
+    let patch = DaoSettingsPatch {
+        reveal_window_seconds: Some(60),
+        ..Default::default()
+    };
+    assert!(update_dao_settings(1, patch).is_ok());
+    let ballot = Proposal {
+        anonymous_ballot: true,
+        ..insert_listed_proposal(10, "Remove a member", "")
+    };
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(10, ballot));
+    let (salt, other_salt) = ([1u8; 16], [2u8; 16]);
+
+    set_caller(user(2));
+    assert!(matches!(
+        commit(10, VoteDirection::Up, &salt),
+        Err(Error::FeatureDisabled { .. })
+    ));
+    set_caller(user(1));
+    assert!(set_feature_flag(features::Feature::AnonymousBallots, true).is_ok());
+
+    // member 2 commits but never reveals
+    for (voter, direction) in [
+        (user(2), VoteDirection::Up),
+        (member(1), VoteDirection::Down),
+        (member(2), VoteDirection::Up),
+    ] {
+        set_caller(voter);
+        assert!(commit(10, direction, &salt).is_ok());
+    }
+    assert!(matches!(
+        commit(10, VoteDirection::Down, &other_salt),
+        Err(Error::HasVoted { .. })
+    ));
+    assert!(matches!(
+        reveal_vote(10, VoteDirection::Up, salt.to_vec()),
+        Err(Error::DeadlineNotExceeded { .. })
+    ));
+    assert_eq!(tally(10), (0, 0));
+
+    set_time(1_001);
+    set_caller(user(2));
+    assert!(matches!(
+        reveal_vote(10, VoteDirection::Up, other_salt.to_vec()),
+        Err(Error::InvalidInput { .. })
+    ));
+    assert!(reveal_vote(10, VoteDirection::Up, salt.to_vec()).is_ok());
+    assert!(matches!(
+        reveal_vote(10, VoteDirection::Up, salt.to_vec()),
+        Err(Error::HasVoted { .. })
+    ));
+    set_caller(member(1));
+    assert!(matches!(
+        reveal_vote(10, VoteDirection::Up, salt.to_vec()),
+        Err(Error::InvalidInput { .. })
+    ));
+    assert!(reveal_vote(10, VoteDirection::Down, salt.to_vec()).is_ok());
+    assert_eq!(tally(10), (1, 1));
+
+    // the vote ends once the reveal window is over, and too late reveals don't count
+    set_caller(user(1));
+    assert!(end_proposal_vote(10).is_err());
+    set_time(1_001 + 60_000_000_000);
+    set_caller(member(2));
+    assert!(matches!(
+        reveal_vote(10, VoteDirection::Up, salt.to_vec()),
+        Err(Error::DeadlineExceeded { .. })
+    ));
+    set_caller(user(1));
+    assert!(end_proposal_vote(10).is_ok());
+    assert_eq!(tally(10), (1, 1));
+    assert!(status(10) == ProposalStatus::Rejected);
+}
+
+// how the canister runs an update call: its guard first, the endpoint only if the guard passes
+fn guarded<T>(update: impl FnOnce() -> T) -> Result<T, String> {
+    _check_can_update().map(|_| update())
+}
+
+#[test]
+fn globally_banned_users_change_nothing_until_unbanned() {
+    insert_dao(DaoVisibility::Private);
+    insert_listed_proposal(10, "Proposal", "");
+    keep_open(10);
+    init(Some(InitArgs {
+        admins: vec![user(1)],
+        ..Default::default()
+    }));
+    set_caller(user(2));
+    let before = comment_on_post(comment(10, "Before the ban"))
+        .ok()
+        .unwrap()
+        .id;
+    assert!(matches!(
+        global_ban(user(3), "spam".to_string()),
+        Err(Error::Unauthorized { .. })
+    ));
+    assert!(matches!(get_global_bans(), Err(Error::Unauthorized { .. })));
+
+    set_caller(user(1));
+    assert!(matches!(
+        global_ban(user(1), "spam".to_string()),
+        Err(Error::InvalidInput { .. })
+    ));
+    assert!(global_ban(user(2), "spam ring".to_string()).is_ok());
+    assert!(get_global_bans().is_ok_and(|bans| bans.len() == 1 && bans[0].user == user(2)));
+    assert!(guarded(|| run(add_proposal(proposal_payload("Allowed"))))
+        .is_ok_and(|result| result.is_ok()));
+
+    // linked principals are banned along with their primary
+    let code = link_code(1, user(2), None);
+    set_caller(member(1));
+    assert!(complete_link(code).is_ok());
+    for banned in [user(2), member(1)] {
+        set_caller(banned);
+        let Err(rejection) = guarded(|| run(add_proposal(proposal_payload("Spam")))) else {
+            panic!("a banned user added a proposal");
+        };
+        assert!(rejection.starts_with("Unauthorized") && rejection.ends_with("spam ring"));
+        assert!(guarded(|| comment_on_post(comment(10, "Spam"))).is_err());
+        assert!(guarded(|| delete_comment(before)).is_err());
+    }
+    assert!(visible_comments(10) == vec![before]);
+
+    set_caller(user(1));
+    assert!(global_unban(user(2)).is_ok());
+    assert!(matches!(global_unban(user(2)), Err(Error::NotFound { .. })));
+    set_caller(user(2));
+    assert!(guarded(|| comment_on_post(comment(10, "After the ban")))
+        .is_ok_and(|result| result.is_ok()));
+}
+
+fn milestones(amounts: &[u64]) -> Option<Vec<MilestonePayload>> {
+    Some(
+        amounts
+            .iter()
+            .map(|amount| MilestonePayload {
+                description: format!("Tranche of {}", amount),
+                amount: *amount,
+            })
+            .collect(),
+    )
+}
+
+fn released(proposal_id: u64) -> Vec<bool> {
+    get_milestone_status(proposal_id)
+        .ok()
+        .unwrap()
+        .iter()
+        .map(|milestone| milestone.released)
+        .collect()
+}
+
+#[test]
+fn milestones_add_up_and_are_released_once_in_order() {
+    insert_dao(DaoVisibility::Private);
+    let funding = |amounts: &[u64]| ProposalPayload {
+        kind: Some(ProposalKind::Funding {
+            amount: 100,
+            recipient: None,
+        }),
+        milestones: milestones(amounts),
+        ..proposal_payload("Grant")
+    };
+    set_caller(user(2));
+    for invalid in [
+        funding(&[60, 30]),
+        funding(&[60, 50]),
+        ProposalPayload {
+            kind: Some(ProposalKind::Signal),
+            ..funding(&[60, 40])
+        },
+    ] {
+        assert!(matches!(
+            run(add_proposal(invalid)),
+            Err(Error::InvalidInput { .. })
+        ));
+    }
+    let id = run(add_proposal(funding(&[60, 40]))).ok().unwrap().id;
+    set_caller(user(1));
+    assert!(upvote(id).is_ok());
+    set_caller(user(2));
+    set_time(VOTING_PERIOD + 1);
+    assert!(end_proposal_vote(id).is_ok());
+    assert!(submit_milestone_report(id, 0, "Half way".to_string()).is_ok());
+
+    set_caller(user(1));
+    assert!(matches!(
+        run(release_milestone(id, 1)),
+        Err(Error::InvalidInput { msg, .. }) if msg.contains("in order")
+    ));
+    // without a ledger the payment fails and the milestone stays unreleased
+    assert!(matches!(
+        run(release_milestone(id, 0)),
+        Err(Error::LedgerError { .. })
+    ));
+    assert_eq!(released(id), vec![false, false]);
+
+    // as if the ledger had paid it out
+    let paid = Milestone {
+        released: true,
+        ..get_milestone_status(id).ok().unwrap()[0].clone()
+    };
+    MILESTONE_STORAGE.with(|service| service.borrow_mut().insert((id, 0), paid));
+    assert!(matches!(
+        run(release_milestone(id, 0)),
+        Err(Error::InvalidInput { msg, .. }) if msg.contains("already released")
+    ));
+    assert!(matches!(
+        run(release_milestone(id, 1)),
+        Err(Error::InvalidInput { msg, .. }) if msg.contains("hasn't reported")
+    ));
+    set_caller(user(2));
+    assert!(matches!(
+        run(release_milestone(id, 1)),
+        Err(Error::PermissionError { .. })
+    ));
+    assert_eq!(released(id), vec![true, false]);
+}
+
+// a share link to a proposal, what create_share_link hands out once the management canister
+// answered with randomness
+fn share_link(proposal_id: u64, random: u8, ttl: u64) -> String {
+    let token = format!(
+        "{:016x}{}",
+        proposal_id,
+        format!("{:02x}", random).repeat(16)
+    );
+    SHARE_LINKS.with(|service| {
+        service.borrow_mut().insert(
+            (
+                proposal_id,
+                ShareTokenKey::try_from(&[random; 16][..]).unwrap(),
+            ),
+            ShareLink {
+                token: token.clone(),
+                proposal_id,
+                created_by: user(1),
+                created_at: time(),
+                expires_at: time() + ttl,
+            },
+        )
+    });
+    token
+}
+
+#[test]
+fn observers_and_share_links_read_without_taking_part() {
+    insert_dao(DaoVisibility::Private);
+    insert_listed_proposal(10, "Proposal", "");
+    keep_open(10);
+
+    set_caller(user(3));
+    assert!(get_proposal(10).is_ok());
+    assert!(get_all_comments_on_proposal(10).is_ok());
+    assert!(matches!(upvote(10), Err(Error::NotAMember { .. })));
+    assert!(matches!(
+        comment_on_post(comment(10, "Observing")),
+        Err(Error::NotAMember { .. })
+    ));
+    assert!(run(add_proposal(proposal_payload("Observed"))).is_err());
+    assert_eq!(tally(10), (0, 0));
+
+    let token = share_link(10, 1, 60);
+    let revoked = share_link(10, 2, 1_000);
+    set_caller(member(5));
+    assert!(is_proposal_not_found(&get_proposal(10), 10));
+    assert!(matches!(
+        get_proposal_by_token(token.clone()),
+        Err(Error::FeatureDisabled { .. })
+    ));
+    init(Some(InitArgs {
+        admins: vec![user(1)],
+        ..Default::default()
+    }));
+    set_caller(user(1));
+    assert!(set_feature_flag(features::Feature::ShareLinks, true).is_ok());
+
+    set_caller(member(5));
+    assert!(get_proposal_by_token(token.clone()).is_ok_and(|proposal| proposal.id == 10));
+    assert!(revoke_share_link(revoked.clone()).is_err());
+    set_caller(user(1));
+    assert!(revoke_share_link(revoked.clone()).is_ok());
+    set_caller(member(5));
+    set_time(60);
+    for gone in [revoked, token, "not a token".to_string()] {
+        assert!(matches!(
+            get_proposal_by_token(gone),
+            Err(Error::NotFound { .. })
+        ));
+    }
+}
+
+fn dao_by_slug(slug: &str) -> Option<u64> {
+    match get_dao_by_slug(slug.to_string()) {
+        Ok(DaoResponse::Full(dao)) => Some(dao.id),
+        Ok(DaoResponse::Summary(summary)) => Some(summary.id),
+        Err(_) => None,
+    }
+}
+
+#[test]
+fn slugs_resolve_case_insensitively_count_up_on_collisions_and_survive_renames() {
+    let guild = || DaoPayload {
+        name: "Builders Guild".to_string(),
+        description: "We build".to_string(),
+        ..Default::default()
+    };
+    let mut daos = Vec::new();
+    for owner in [user(1), user(2), user(3)] {
+        set_caller(owner);
+        let dao = create_dao(guild()).unwrap();
+        daos.push((dao.id, dao.slug));
+    }
+    let slugs: Vec<&str> = daos.iter().map(|(_, slug)| slug.as_str()).collect();
+    assert_eq!(
+        slugs,
+        vec!["builders-guild", "builders-guild-2", "builders-guild-3"]
+    );
+    let first = daos[0].0;
+
+    set_caller(user(1));
+    let renamed = DaoPayload {
+        name: "Makers".to_string(),
+        ..guild()
+    };
+    assert!(update_dao(first, renamed, None).is_ok_and(|dao| dao.slug == "makers"));
+    for slug in ["makers", "builders-guild", "Builders-Guild"] {
+        assert_eq!(dao_by_slug(slug), Some(first));
+    }
+
+    let mut proposals = Vec::new();
+    for _ in 0..2 {
+        let payload = ProposalPayload {
+            dao_id: first,
+            ..proposal_payload("Fund it!")
+        };
+        let proposal = run(add_proposal(payload)).ok().unwrap();
+        proposals.push((proposal.id, proposal.slug));
+    }
+    assert!(proposals[0].1 == "fund-it" && proposals[1].1 == "fund-it-2");
+    assert!(get_proposal_by_slug(first, "fund-it-2".to_string())
+        .is_ok_and(|proposal| proposal.id == proposals[1].0));
+    // slugs are per DAO
+    assert!(get_proposal_by_slug(daos[1].0, "fund-it".to_string()).is_err());
+
+    // outsiders of a private DAO can't tell its slugs from unused ones
+    set_caller(user(9));
+    assert_eq!(dao_by_slug("makers"), None);
+    assert!(matches!(
+        get_dao_by_slug("makers".to_string()),
+        Err(Error::NotFound { .. })
+    ));
+    assert!(matches!(
+        get_proposal_by_slug(first, "fund-it".to_string()),
+        Err(Error::NotFound { .. })
+    ));
+}
+
+#[test]
+fn comment_windows_close_new_comments_but_not_likes() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    set_time(1_000);
+    let voting_period_seconds = VOTING_PERIOD / 1_000_000_000;
+    for window in [0, voting_period_seconds + 1] {
+        let payload = ProposalPayload {
+            comment_window_seconds: Some(window),
+            ..proposal_payload("Out of bounds")
+        };
+        assert!(matches!(
+            run(add_proposal(payload)),
+            Err(Error::InvalidInput { .. })
+        ));
+    }
+    let payload = ProposalPayload {
+        comment_window_seconds: Some(60),
+        ..proposal_payload("Office hours")
+    };
+    let id = run(add_proposal(payload)).ok().unwrap().id;
+    let closes_at = 1_000 + 60_000_000_000;
+    assert!(get_proposal(id).is_ok_and(|view| view.comments_close_at == Some(closes_at)));
+    let unlimited = run(add_proposal(proposal_payload("Open floor")))
+        .ok()
+        .unwrap()
+        .id;
+    assert!(get_proposal(unlimited).is_ok_and(|view| view.comments_close_at.is_none()));
+
+    set_caller(user(2));
+    set_time(closes_at - 1);
+    let early = comment_on_post(comment(id, "Just in time"))
+        .ok()
+        .unwrap()
+        .id;
+
+    set_time(closes_at);
+    assert!(matches!(
+        comment_on_post(comment(id, "Too late")),
+        Err(Error::CommentWindowClosed { context, .. }) if context == ErrorContext::Proposal(id)
+    ));
+    assert!(comment_on_post(comment(unlimited, "Any time")).is_ok());
+    assert!(update_comment(early, comment(id, "Just in time, edited")).is_ok());
+    set_caller(user(1));
+    assert!(like_comment(early).is_ok());
+
+    // past the deadline the lock after finalization comes first, without it the window still
+    // keeps new comments out
+    set_time(1_001 + VOTING_PERIOD);
+    set_caller(user(2));
+    for closed in [
+        comment_on_post(comment(id, "After the vote")).map(|_| ()),
+        update_comment(early, comment(id, "Edited after the vote")).map(|_| ()),
+    ] {
+        assert!(matches!(closed, Err(Error::ProposalClosed { .. })));
+    }
+    set_caller(user(1));
+    assert!(set_auto_lock_comments(1, false).is_ok());
+    set_caller(user(2));
+    assert!(matches!(
+        comment_on_post(comment(id, "After the vote")),
+        Err(Error::CommentWindowClosed { .. })
+    ));
+    assert!(update_comment(early, comment(id, "Edited after the vote")).is_ok());
+    assert!(comment_on_post(comment(unlimited, "After the vote")).is_ok());
+}
+
+#[test]
+fn outsiders_cant_tell_private_daos_from_missing_ones() {
+    insert_dao(DaoVisibility::Private);
+    insert_listed_proposal(10, "Proposal", "");
+    set_caller(user(2));
+    let comment_id = comment_on_post(comment(10, "Hidden")).ok().unwrap().id;
+    set_caller(user(9));
+
+    // each call with the id of what exists in the private DAO, then with an unused id
+    type Call = fn(u64) -> Result<(), Error>;
+    type Context = fn(u64) -> ErrorContext;
+    let calls: Vec<(Call, u64, Context)> = vec![
+        (|id| get_dao(id).map(|_| ()), 1, ErrorContext::Dao),
+        (|id| get_all_proposals(id).map(|_| ()), 1, ErrorContext::Dao),
+        (
+            |id| get_proposal(id).map(|_| ()),
+            10,
+            ErrorContext::Proposal,
+        ),
+        (
+            |id| get_all_comments_on_proposal(id).map(|_| ()),
+            10,
+            ErrorContext::Proposal,
+        ),
+        (
+            |id| like_comment(id).map(|_| ()),
+            comment_id,
+            ErrorContext::Comment,
+        ),
+    ];
+    for (call, existing, context) in calls {
+        let (variant, code, found) = error_shape(call(existing));
+        let (missing_variant, missing_code, missing) = error_shape(call(999));
+        assert_eq!(
+            (variant.as_str(), code),
+            (missing_variant.as_str(), missing_code)
+        );
+        assert_eq!(variant, "NotFound");
+        assert!(found == context(existing) && missing == context(999));
+    }
+}
+
+#[test]
+fn votes_count_once_per_voter_even_when_the_counts_drifted() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+    set_caller(user(2));
+    assert!(upvote(10).is_ok());
+    for again in [upvote(10), downvote(10)] {
+        assert!(matches!(again, Err(Error::HasVoted { .. })));
+    }
+    assert_eq!(tally(10), (1, 0));
+
+    // counts written as if the same vote had landed three times
+    let mut proposal = _get_proposal(&10).unwrap();
+    proposal.upvote_count = 3;
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(10, proposal));
+    set_time(1_001);
+    set_caller(user(1));
+    assert!(end_proposal_vote(10).is_ok());
+    assert_eq!(tally(10), (1, 0));
+}
+
+fn stance_comment(proposal_id: u64, content: &str, stance: Option<Stance>) -> CommentPayload {
+    CommentPayload {
+        stance,
+        ..comment(proposal_id, content)
+    }
+}
+
+fn stance_counts(proposal_id: u64) -> (u64, u64, u64) {
+    let counts = get_proposal(proposal_id).ok().unwrap().stance_counts;
+    (counts.for_count, counts.against_count, counts.neutral_count)
+}
+
+#[test]
+fn stance_counters_follow_comments_through_edits_and_deletes() {
+    insert_dao(DaoVisibility::Private);
+    insert_listed_proposal(10, "Proposal", "");
+    set_caller(user(2));
+    let mut backers = Vec::new();
+    for n in 0..4 {
+        let payload = stance_comment(10, &format!("For {}", n), Some(Stance::For));
+        backers.push(comment_on_post(payload).ok().unwrap().id);
+    }
+    let against = comment_on_post(stance_comment(10, "Against", Some(Stance::Against)))
+        .ok()
+        .unwrap()
+        .id;
+    let undecided = comment_on_post(stance_comment(10, "No stance", None))
+        .ok()
+        .unwrap()
+        .id;
+    assert_eq!(stance_counts(10), (4, 1, 0));
+
+    // a stance left out of an update stays, one that is set moves the comment between counts
+    assert!(update_comment(against, stance_comment(10, "Edited", None)).is_ok());
+    assert_eq!(stance_counts(10), (4, 1, 0));
+    assert!(update_comment(against, stance_comment(10, "Edited", Some(Stance::Neutral))).is_ok());
+    assert!(update_comment(
+        undecided,
+        stance_comment(10, "Now against", Some(Stance::Against))
+    )
+    .is_ok());
+    assert_eq!(stance_counts(10), (4, 1, 1));
+    assert!(delete_comment(backers[0]).is_ok());
+    assert_eq!(stance_counts(10), (3, 1, 1));
+    assert!(get_all_proposals(1).is_ok_and(|views| views[0].stance_counts.for_count == 3));
+
+    set_caller(user(1));
+    assert!(like_comment(backers[3]).is_ok());
+    let summary = get_comment_stance_summary(10).ok().unwrap();
+    let ids = |comments: &[CommentView]| {
+        comments
+            .iter()
+            .map(|comment| comment.id)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        ids(&summary.top_for),
+        vec![backers[3], backers[1], backers[2]]
+    );
+    assert_eq!(ids(&summary.top_against), vec![undecided]);
+    assert_eq!(ids(&summary.top_neutral), vec![against]);
+    assert_eq!(summary.counts.for_count, 3);
+
+    set_caller(user(9));
+    assert!(is_proposal_not_found(&get_comment_stance_summary(10), 10));
+}
+
+#[test]
+fn approved_proposals_are_listed_in_finalization_order_with_or_without_executed_ones() {
+    insert_dao(DaoVisibility::Private);
+    for id in 10..=14 {
+        insert_listed_proposal(id, "Proposal", "");
+        if id != 13 {
+            set_caller(user(2));
+            assert!(upvote(id).is_ok());
+        }
+    }
+    // finalized out of id order, 13 is rejected and 14 still open
+    set_caller(user(1));
+    for (id, now) in [(12, 2_000), (10, 3_000), (13, 3_000), (11, 3_000)] {
+        set_time(now);
+        assert!(end_proposal_vote(id).is_ok());
+    }
+    assert!(status(13) == ProposalStatus::Rejected);
+    let mut paid = _get_proposal(&10).unwrap();
+    paid.executed_at = Some(4_000);
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(10, paid));
+
+    let listed = |include_executed, offset, limit| {
+        list_approved_proposals(1, include_executed, offset, limit)
+            .ok()
+            .unwrap()
+            .iter()
+            .map(|view| view.id)
+            .collect::<Vec<u64>>()
+    };
+    assert_eq!(listed(true, 0, 10), vec![12, 10, 11]);
+    assert_eq!(listed(false, 0, 10), vec![12, 11]);
+    assert_eq!(listed(true, 1, 1), vec![10]);
+    assert!(get_final_approved_proposals(1).is_ok_and(|views| views
+        .iter()
+        .map(|view| (view.id, view.finalized_at))
+        .eq([(12, Some(2_000)), (10, Some(3_000)), (11, Some(3_000))])));
+
+    set_caller(user(9));
+    assert!(list_approved_proposals(1, true, 0, 10).is_err());
+}
+
+// the message and code of a failed call
+fn error_message<T>(result: Result<T, Error>) -> (String, u64) {
+    let Err(error) = result else {
+        panic!("expected an error")
+    };
+    let value = serde_json::to_value(error).unwrap();
+    let fields = value.as_object().unwrap().values().next().unwrap();
+    (
+        fields["msg"].as_str().unwrap().to_string(),
+        fields["code"].as_u64().unwrap(),
+    )
+}
+
+#[test]
+fn error_messages_follow_the_callers_locale_and_keep_their_code() {
+    init(Some(InitArgs {
+        admins: vec![user(1)],
+        ..Default::default()
+    }));
+    for (n, locale) in [
+        (2, None),
+        (3, Some("es-MX")),
+        (4, Some("fr")),
+        (5, Some("no locale!")),
+    ] {
+        set_caller(user(n));
+        let preferences = set_my_preferences(UserPreferences {
+            locale: locale.map(str::to_string),
+            ..Default::default()
+        });
+        // invalid tags are dropped, valid ones lowercased
+        assert_eq!(
+            preferences.locale,
+            locale.filter(|_| n != 5).map(str::to_lowercase)
+        );
+    }
+    let message_of = |n: u8| {
+        set_caller(user(n));
+        error_message(get_proposal(99))
+    };
+    let (english, code) = message_of(2);
+    assert_eq!(
+        message_of(3),
+        ("No se encontró el elemento 99".to_string(), code)
+    );
+    assert_eq!(message_of(4), (english.clone(), code));
+    assert_eq!(message_of(5), (english.clone(), code));
+
+    set_caller(user(1));
+    let template = || Some("Élément {id} introuvable".to_string());
+    assert!(set_error_message(code as u16, "FR".to_string(), template()).is_ok());
+    assert_eq!(message_of(4).0, "Élément 99 introuvable");
+    set_caller(user(1));
+    for invalid in [
+        set_error_message(99, "fr".to_string(), template()),
+        set_error_message(code as u16, "f r".to_string(), template()),
+        set_error_message(code as u16, "fr".to_string(), Some(" ".to_string())),
+    ] {
+        assert!(matches!(invalid, Err(Error::InvalidInput { .. })));
+    }
+    set_caller(user(2));
+    assert!(matches!(
+        set_error_message(code as u16, "fr".to_string(), None),
+        Err(Error::Unauthorized { .. })
+    ));
+
+    set_caller(user(1));
+    assert!(set_error_message(code as u16, "fr".to_string(), None).is_ok());
+    assert_eq!(message_of(4).0, english);
+}
+
+// the same outcome, down to the message of an error
+fn same_outcome<T, U>(dry_run: Result<T, Error>, real: Result<U, Error>) -> bool {
+    match (dry_run, real) {
+        (Ok(_), Ok(_)) => true,
+        (Err(a), Err(b)) => serde_json::to_value(a).unwrap() == serde_json::to_value(b).unwrap(),
+        _ => false,
+    }
+}
+
+#[test]
+fn dry_runs_answer_what_the_real_call_would() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+    insert_proposal(11);
+    keep_open(11);
+    // deadline passed, owner, observer, outsider, missing proposal, then a vote and a repeat
+    for (n, id) in [
+        (2, 10),
+        (1, 11),
+        (3, 11),
+        (9, 11),
+        (2, 99),
+        (2, 11),
+        (2, 11),
+    ] {
+        set_caller(user(n));
+        set_time(if id == 10 { 1_001 } else { 0 });
+        assert!(same_outcome(can_vote(id), upvote(id)));
+    }
+    assert_eq!(tally(11), (1, 0));
+    set_time(0);
+
+    let payload = |dao_id: u64, title: &str| ProposalPayload {
+        dao_id,
+        ..proposal_payload(title)
+    };
+    let cases = [
+        (9, 1, "Outsider"),
+        (3, 1, "Observer"),
+        (2, 1, ""),
+        (2, 99, "No DAO"),
+        (2, 1, "Fine"),
+    ];
+    for (n, dao_id, title) in cases {
+        set_caller(user(n));
+        let dry_run = run(can_create_proposal(payload(dao_id, title)));
+        assert!(same_outcome(
+            dry_run,
+            run(add_proposal(payload(dao_id, title)))
+        ));
+    }
+    // the rate limit is checked without counting the dry runs against it
+    set_caller(user(2));
+    for _ in 0..10 {
+        let dry_run = run(can_create_proposal(payload(1, "Again")));
+        assert!(same_outcome(
+            dry_run,
+            run(add_proposal(payload(1, "Again")))
+        ));
+    }
+
+    // a deposit that can't be paid fails both the same way
+    DAO_SETTINGS.with(|service| {
+        let mut settings = _get_settings(&1);
+        settings.proposal_deposit = Some(50);
+        service.borrow_mut().insert(1, settings)
+    });
+    set_time(2 * rate_limit::PROPOSAL_WINDOW);
+    let dry_run = run(can_create_proposal(payload(1, "Deposit")));
+    assert!(matches!(dry_run, Err(Error::LedgerError { .. })));
+    assert!(same_outcome(
+        dry_run,
+        run(add_proposal(payload(1, "Deposit")))
+    ));
+}
+
+#[test]
+fn co_authors_edit_with_the_owner_but_neither_vote_delete_nor_end_it() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    let members: Vec<Principal> = (0..6).map(member).collect();
+    assert!(add_members_bulk(1, members.clone()).is_ok());
+    let with_co_authors = |co_authors: Vec<Principal>| ProposalPayload {
+        co_authors: Some(co_authors),
+        publish: Some(false),
+        ..proposal_payload("Written together")
+    };
+    for (co_authors, rejected) in [
+        (members.clone(), "InvalidInput"),
+        (vec![user(1)], "InvalidInput"),
+        (vec![user(2), user(9)], "NotAMember"),
+    ] {
+        let result = run(add_proposal(with_co_authors(co_authors))).map(|_| ());
+        assert_eq!(error_shape(result).0, rejected);
+    }
+    let view = run(add_proposal(with_co_authors(vec![user(2), user(2)])))
+        .ok()
+        .unwrap();
+    let id = view.id;
+    assert_eq!(view.co_authors, vec![user(2)]);
+
+    set_caller(user(2));
+    assert!(notification_kinds(true) == vec![NotificationKind::CoAuthorAdded]);
+    let edit = ProposalPayload {
+        details: "Edited by a co-author".to_string(),
+        ..proposal_payload("Written together")
+    };
+    assert!(update_proposal(id, edit, None).is_ok());
+    let attachments = add_attachment(id, attachment("Budget", "https://example.com/budget.pdf"));
+    assert!(attachments.is_ok_and(|attachments| attachments.len() == 1));
+    assert!(remove_attachment(id, 0).is_ok());
+    assert!(publish_proposal(id).is_ok());
+    // only the owner changes who co-authors
+    let handover = ProposalPayload {
+        co_authors: Some(vec![member(0)]),
+        ..proposal_payload("Written together")
+    };
+    assert!(matches!(
+        update_proposal(id, handover, None),
+        Err(Error::PermissionError { .. })
+    ));
+
+    for vote in [upvote(id), downvote(id)] {
+        assert!(matches!(vote, Err(Error::CantVoteYours { .. })));
+    }
+    assert!(matches!(can_vote(id), Err(Error::CantVoteYours { .. })));
+    assert!(matches!(
+        delete_proposal(id),
+        Err(Error::PermissionError { .. })
+    ));
+    set_time(VOTING_PERIOD + 1);
+    assert!(matches!(
+        end_proposal_vote(id),
+        Err(Error::PermissionError { .. })
+    ));
+    assert!(status(id) == ProposalStatus::Open);
+
+    set_caller(user(1));
+    assert!(end_proposal_vote(id).is_ok());
+}
+
+#[test]
+fn votable_proposals_are_exactly_the_ones_a_vote_is_accepted_on() {
+    insert_dao(DaoVisibility::Public);
+    let statuses = [
+        ProposalStatus::Open,
+        ProposalStatus::Draft,
+        ProposalStatus::Cancelled,
+        ProposalStatus::Approved,
+    ];
+    let mut id = 10;
+    for status in statuses {
+        for deadline in [1_000, 4_000_000_000] {
+            // the owner, a member who voted already, a member, an observer and an outsider
+            for (n, voted) in [(1, false), (2, true), (2, false), (3, false), (9, false)] {
+                id += 1;
+                insert_proposal(id);
+                if voted {
+                    set_time(0);
+                    set_caller(user(n));
+                    assert!(downvote(id).is_ok());
+                }
+                let proposal = Proposal {
+                    status,
+                    deadline,
+                    .._get_proposal(&id).unwrap()
+                };
+                PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal));
+
+                set_time(1_001);
+                set_caller(user(n));
+                // outsiders and, for drafts, everyone but the owner can't see it at all
+                let Ok(view) = get_proposal(id) else {
+                    assert!(n == 9 || status == ProposalStatus::Draft);
+                    assert!(upvote(id).is_err());
+                    continue;
+                };
+                assert_eq!(view.caller_is_owner, n == 1);
+                assert_eq!(view.time_remaining_seconds, (deadline > 1_001).then_some(3));
+                assert_eq!(view.is_votable_by_caller, upvote(id).is_ok());
+            }
+        }
+    }
+    // only open proposals before their deadline, voted on by a member for the first time
+    let votable = (11..=id)
+        .filter(|id| {
+            _get_vote(id, &user(2)).is_some_and(|vote| vote.direction == VoteDirection::Up)
+        })
+        .count();
+    assert_eq!(votable, 1);
+}
+
+#[test]
+fn clearly_failing_proposals_close_on_the_downvote_that_reaches_the_rule() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    let voters: Vec<Principal> = (0..4).map(member).collect();
+    assert!(add_members_bulk(1, voters.clone()).is_ok());
+    let rule = |reject_ratio_percent| DaoSettingsPatch {
+        early_close: Some(EarlyClose {
+            min_votes: 3,
+            reject_ratio_percent,
+        }),
+        ..Default::default()
+    };
+    assert!(matches!(
+        update_dao_settings(1, rule(50)),
+        Err(Error::InvalidInput { .. })
+    ));
+    assert!(update_dao_settings(1, rule(67)).is_ok());
+    insert_proposal(10);
+    insert_proposal(11);
+    keep_open(10);
+    keep_open(11);
+
+    // 2 downvotes of 3 is 66.6%, short of the rule
+    for (voter, vote) in [
+        (
+            voters[0],
+            downvote as fn(u64) -> Result<ProposalView, Error>,
+        ),
+        (voters[1], upvote),
+        (voters[2], downvote),
+    ] {
+        set_caller(voter);
+        assert!(vote(10).is_ok());
+    }
+    assert!(status(10) == ProposalStatus::Open);
+    set_caller(voters[3]);
+    assert!(downvote(10).is_ok_and(|view| view.status == ProposalStatus::Rejected));
+    let closure = _get_proposal(&10).unwrap().closed_early.unwrap();
+    assert_eq!((closure.upvotes, closure.downvotes), (1, 3));
+    assert_eq!(closure.original_deadline, u64::MAX);
+    set_caller(user(2));
+    assert!(matches!(
+        upvote(10),
+        Err(Error::InvalidInput { context, .. }) if context == ErrorContext::Proposal(10)
+    ));
+    set_caller(user(1));
+    assert!(notification_kinds(true).contains(&NotificationKind::ProposalClosedEarly));
+
+    // approvals always get the full window
+    for voter in voters {
+        set_caller(voter);
+        assert!(upvote(11).is_ok());
+    }
+    assert!(status(11) == ProposalStatus::Open);
+}
+
+// the like counts user 2 was told about, oldest first
+fn like_notifications() -> Vec<u64> {
+    set_caller(user(2));
+    let mut counts: Vec<(u64, u64)> = get_my_notifications(false, 0, MAX_PAGE_SIZE)
+        .iter()
+        .filter(|notification| notification.kind == NotificationKind::CommentLiked)
+        .map(|notification| (notification.id, notification.count.unwrap_or_default()))
+        .collect();
+    counts.sort();
+    counts.into_iter().map(|(_, count)| count).collect()
+}
+
+#[test]
+fn likes_reach_their_author_in_batches() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+    keep_open(10);
+    set_caller(user(1));
+    let likers: Vec<Principal> = (0..7).map(member).collect();
+    assert!(add_members_bulk(1, likers.clone()).is_ok());
+    set_caller(user(2));
+    let quiet = comment_on_post(comment(10, "Quiet")).ok().unwrap().id;
+    let popular = comment_on_post(comment(10, "Popular")).ok().unwrap().id;
+    let window = 15 * 60 * 1_000_000_000;
+
+    // a like taken back before its batch is delivered leaves nothing to tell
+    set_time(1_000);
+    set_caller(likers[0]);
+    assert!(like_comment(quiet).is_ok());
+    assert!(unlike_comment(quiet).is_ok());
+    // the 5th like is delivered right away, the rest with the batch at the end of the window
+    for liker in likers.iter() {
+        set_caller(*liker);
+        assert!(like_comment(popular).is_ok());
+    }
+    assert_eq!(like_notifications(), vec![5]);
+    set_time(1_000 + window - 1);
+    likes::resume();
+    assert_eq!(like_notifications(), vec![5]);
+    set_time(1_000 + window);
+    likes::resume();
+    assert_eq!(like_notifications(), vec![5, 2]);
+
+    // a batch whose timer was lost in an upgrade goes out when the timers are set up again
+    set_caller(likers[1]);
+    assert!(like_comment(quiet).is_ok());
+    set_time(10 * window);
+    likes::resume();
+    assert_eq!(like_notifications(), vec![5, 2, 1]);
+}
+
+#[test]
+fn proposals_are_numbered_densely_per_dao_and_numbers_never_come_back() {
+    insert_dao(DaoVisibility::Private);
+    // proposals from before numbering, the newest id created first
+    for (id, created_at) in [(10, 30), (11, 20), (12, 10)] {
+        let proposal = Proposal {
+            created_at,
+            ..insert_listed_proposal(id, "Legacy", "")
+        };
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal));
+    }
+    let mut dao = _get_dao(&1).unwrap();
+    _number_proposals(&mut dao);
+    DAO_STORAGE.with(|service| service.borrow_mut().insert(1, dao));
+
+    set_caller(user(2));
+    let number_of = |id: u64| get_proposal(id).ok().unwrap().proposal_number;
+    assert_eq!([12, 11, 10].map(number_of), [1, 2, 3]);
+    assert!(get_proposal_by_number(1, 1).is_ok_and(|view| view.id == 12));
+
+    set_caller(user(1));
+    let mut added = Vec::new();
+    for title in ["Fourth", "Fifth"] {
+        added.push(run(add_proposal(proposal_payload(title))).ok().unwrap());
+    }
+    assert_eq!(added[0].proposal_number, 4);
+    assert_eq!(added[1].proposal_number, 5);
+    assert!(delete_proposal(added[1].id).is_ok());
+    assert!(matches!(
+        get_proposal_by_number(1, 5),
+        Err(Error::NotFound { .. })
+    ));
+    let sixth = run(add_proposal(proposal_payload("Sixth"))).ok().unwrap();
+    assert_eq!(sixth.proposal_number, 6);
+
+    // other DAOs count from 1
+    let other = create_dao(DaoPayload {
+        name: "Other".to_string(),
+        description: "Another one".to_string(),
+        ..Default::default()
+    })
+    .unwrap();
+    let first = ProposalPayload {
+        dao_id: other.id,
+        ..proposal_payload("First")
+    };
+    assert_eq!(run(add_proposal(first)).ok().unwrap().proposal_number, 1);
+    assert!(get_proposal_by_number(other.id, 1).is_ok());
+    set_caller(user(2));
+    assert!(get_proposal_by_number(other.id, 1).is_err());
+}
+
+#[test]
+fn comment_views_resolve_authors_likes_and_replies_for_the_caller() {
+    insert_dao(DaoVisibility::Private);
+    insert_proposal(10);
+    set_caller(user(2));
+    assert!(set_my_profile(profile("Bea")).is_ok());
+    let named = comment_on_post(comment(10, "With a profile"))
+        .ok()
+        .unwrap()
+        .id;
+    assert!(comment_on_post(comment(10, "Second")).is_ok());
+    set_caller(user(1));
+    let anonymous = comment_on_post(comment(10, "Without one")).ok().unwrap().id;
+    assert!(like_comment(named).is_ok());
+    assert!(comment_on_post(reply(10, named)).is_ok());
+    assert!(update_comment(anonymous, comment(10, "Without one, edited")).is_ok());
+
+    let view = |id: u64| {
+        get_all_comments_on_proposal(10)
+            .ok()
+            .unwrap()
+            .into_iter()
+            .find(|view| view.id == id)
+            .unwrap()
+    };
+    let named_view = view(named);
+    assert_eq!(named_view.author, Some(user(2)));
+    assert_eq!(named_view.author_display_name.as_deref(), Some("Bea"));
+    assert_eq!((named_view.like_count, named_view.reply_count), (1, 1));
+    assert!(named_view.liked_by_caller && !named_view.edited);
+    let anonymous_view = view(anonymous);
+    assert_eq!(anonymous_view.author_display_name, None);
+    assert!(anonymous_view.edited && !anonymous_view.liked_by_caller);
+    assert_eq!(anonymous_view.reply_count, 0);
+
+    // liked_by_caller is about whoever asks
+    set_caller(user(2));
+    assert!(!view(named).liked_by_caller);
+    // a profile set later shows on comments posted before it
+    set_caller(user(1));
+    assert!(set_my_profile(profile("Ann")).is_ok());
+    assert_eq!(view(anonymous).author_display_name.as_deref(), Some("Ann"));
+}
+
+#[test]
+fn full_daos_queue_proposals_and_open_them_in_order_as_slots_free_up() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    let capped = DaoSettingsPatch {
+        max_open_proposals: Some(2),
+        ..Default::default()
+    };
+    assert!(update_dao_settings(1, capped).is_ok());
+    let queued = |title: &str| ProposalPayload {
+        queue_if_full: Some(true),
+        ..proposal_payload(title)
+    };
+    let first = run(add_proposal(proposal_payload("First")))
+        .ok()
+        .unwrap()
+        .id;
+    let second = run(add_proposal(proposal_payload("Second")))
+        .ok()
+        .unwrap()
+        .id;
+    assert!(matches!(
+        run(add_proposal(proposal_payload("Third"))),
+        Err(Error::TooManyOpenProposals { .. })
+    ));
+    set_time(5);
+    let third = run(add_proposal(queued("Third"))).ok().unwrap();
+    let fourth = run(add_proposal(queued("Fourth"))).ok().unwrap();
+    for (view, position) in [(&third, 1), (&fourth, 2)] {
+        assert!(view.status == ProposalStatus::Queued);
+        assert_eq!(view.queue_position, Some(position));
+    }
+
+    // the oldest queued proposal takes the first free slot, its vote starting then
+    let finalized_at = VOTING_PERIOD + 1;
+    set_time(finalized_at);
+    assert!(end_proposal_vote(first).is_ok());
+    let opened = get_proposal(third.id).ok().unwrap();
+    assert!(opened.status == ProposalStatus::Open);
+    assert_eq!(opened.deadline, finalized_at + VOTING_PERIOD);
+    assert_eq!(opened.queue_position, None);
+    assert!(get_proposal(fourth.id).is_ok_and(|view| view.queue_position == Some(1)));
+
+    assert!(cancel_proposal(second).is_ok());
+    assert!(status(fourth.id) == ProposalStatus::Open);
+}
+
+#[test]
+fn hidden_tallies_show_once_the_vote_is_finalized() {
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    let hidden = DaoSettingsPatch {
+        hide_tally_until_closed: Some(true),
+        ..Default::default()
+    };
+    assert!(update_dao_settings(1, hidden).is_ok());
+    let mut dao = _get_dao(&1).unwrap();
+    _set_membership(&mut dao, &member(1), Some(Membership::Member));
+    DAO_STORAGE.with(|service| service.borrow_mut().insert(1, dao));
+    insert_proposal(10);
+    set_caller(member(1));
+    assert!(upvote(10).is_ok());
+
+    // members see that votes came in, and their own, but not which way they went
+    set_caller(user(2));
+    assert!(downvote(10).is_ok());
+    let view = get_proposal(10).ok().unwrap();
+    assert_eq!((view.upvote_count, view.downvote_count), (None, None));
+    assert_eq!(view.vote_count, 2);
+    assert!(view.caller_has_voted);
+    assert!(matches!(
+        get_vote_breakdown(10),
+        Err(Error::PermissionError { .. })
+    ));
+
+    // the owner still sees the tally unless the DAO hides it from admins too
+    set_caller(user(1));
+    let view = get_proposal(10).ok().unwrap();
+    assert_eq!((view.upvote_count, view.downvote_count), (Some(1), Some(1)));
+    assert!(get_vote_breakdown(10).is_ok());
+    let from_admins = DaoSettingsPatch {
+        hide_tally_from_admins: Some(true),
+        ..Default::default()
+    };
+    assert!(update_dao_settings(1, from_admins).is_ok());
+    assert_eq!(get_proposal(10).ok().unwrap().upvote_count, None);
+    assert!(get_vote_breakdown(10).is_err());
+
+    // everyone sees the full counts once it's closed
+    set_time(1_001);
+    assert!(end_proposal_vote(10).is_ok());
+    for caller in [user(1), user(2)] {
+        set_caller(caller);
+        let view = get_proposal(10).ok().unwrap();
+        assert_eq!((view.upvote_count, view.downvote_count), (Some(1), Some(1)));
+        assert!(get_vote_breakdown(10).is_ok_and(|breakdown| breakdown.upvotes == 1));
+    }
+}
+
+#[test]
+fn hidden_tallies_are_kept_out_of_certification() {
+    insert_dao(DaoVisibility::Private);
+    let mut dao = _get_dao(&1).unwrap();
+    _set_membership(&mut dao, &member(1), Some(Membership::Member));
+    DAO_STORAGE.with(|service| service.borrow_mut().insert(1, dao));
+    insert_listed_proposal(10, "Hidden", "");
+    set_caller(user(2));
+    assert!(upvote(10).is_ok());
+    let counted = certified_data();
+
+    // hiding the tally certifies the open proposal again, without its counts
+    set_caller(user(1));
+    let hidden = DaoSettingsPatch {
+        hide_tally_until_closed: Some(true),
+        ..Default::default()
+    };
+    assert!(update_dao_settings(1, hidden).is_ok());
+    let sealed = certified_data();
+    assert!(sealed != counted);
+    set_caller(member(1));
+    assert!(downvote(10).is_ok());
+    assert!(certified_data() == sealed);
+    // not even for the owner, who still sees the counts
+    for caller in [user(1), user(2)] {
+        set_caller(caller);
+        assert!(matches!(
+            get_proposal_certified(10),
+            Err(Error::PermissionError { .. })
+        ));
+    }
+
+    set_caller(user(1));
+    set_time(1_001);
+    assert!(end_proposal_vote(10).is_ok());
+    assert!(certified_data() != sealed);
+    set_caller(user(2));
+    let certified = get_proposal_certified(10).ok().unwrap();
+    assert_eq!(
+        (
+            certified.proposal.upvote_count,
+            certified.proposal.downvote_count
+        ),
+        (Some(1), Some(1))
+    );
+}
+
+#[test]
+fn leaving_cancels_unfinished_proposals_and_keeps_the_note_for_the_owner() {
+    insert_dao(DaoVisibility::Private);
+    let owned_by_user2 = |id: u64, status: ProposalStatus| {
+        let proposal = Proposal {
+            owner: Some(user(2)),
+            status,
+            ..insert_listed_proposal(id, "Mine", "")
+        };
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal));
+    };
+    owned_by_user2(10, ProposalStatus::Open);
+    owned_by_user2(11, ProposalStatus::Draft);
+    owned_by_user2(12, ProposalStatus::Scheduled);
+    owned_by_user2(13, ProposalStatus::Approved);
+    insert_listed_proposal(14, "Theirs", "");
+    set_caller(user(1));
+    assert!(follow_proposal(10).is_ok());
+
+    set_caller(user(2));
+    assert!(leave_dao(
+        1,
+        Some(format!("  {}  ", "x".repeat(MAX_EXIT_NOTE_LEN + 1)))
+    )
+    .is_err());
+    assert!(leave_dao(1, Some("  moving on  ".to_string())).is_ok());
+    for id in [10, 11, 12] {
+        let proposal = _get_proposal(&id).unwrap();
+        assert!(proposal.status == ProposalStatus::Cancelled);
+        assert!(proposal.cancel_reason == Some(CancelReason::AuthorLeft));
+    }
+    // finalized proposals and those of others stay as they were
+    assert!(status(13) == ProposalStatus::Approved);
+    assert!(_get_proposal(&13).unwrap().cancel_reason.is_none());
+    assert!(status(14) == ProposalStatus::Open);
+
+    set_caller(user(1));
+    assert!(notification_kinds(true).contains(&NotificationKind::ProposalCancelled));
+    let log = get_dao_audit_log(1, 0, MAX_PAGE_SIZE).ok().unwrap();
+    let departure = log.last().unwrap();
+    assert!(departure.action == AuditAction::MemberLeft);
+    assert!(
+        departure.target
+            == AuditTarget::Departure {
+                member: user(2),
+                note: Some("moving on".to_string()),
+            }
+    );
+    assert_eq!(departure.actor, Some(user(2)));
+    // the owner can't leave their own DAO
+    assert!(leave_dao(1, None).is_err());
+}
+
+#[test]
+fn ranked_choice_proposals_take_rankings_and_are_counted_by_instant_runoff() {
+    insert_dao(DaoVisibility::Private);
+    let mut dao = _get_dao(&1).unwrap();
+    for n in 1..=4 {
+        _set_membership(&mut dao, &member(n), Some(Membership::Member));
+    }
+    DAO_STORAGE.with(|service| service.borrow_mut().insert(1, dao));
+    let ranked = Proposal {
+        kind: ProposalKind::RankedChoice {
+            options: vec!["Fox".to_string(), "Owl".to_string(), "Elk".to_string()],
+        },
+        ..insert_proposal(10)
+    };
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(10, ranked));
+    insert_proposal(11);
+
+    // each kind of proposal only takes its own kind of vote
+    set_caller(user(2));
+    assert!(matches!(upvote(10), Err(Error::InvalidInput { .. })));
+    assert!(matches!(downvote(10), Err(Error::InvalidInput { .. })));
+    assert!(matches!(
+        vote_option(11, vec![0]),
+        Err(Error::InvalidInput { .. })
+    ));
+    assert!(matches!(
+        vote_option(10, vec![3]),
+        Err(Error::InvalidInput { .. })
+    ));
+
+    // Fox 2, Owl 2, Elk 1: Elk is dropped and its ballot goes to Owl
+    assert!(vote_option(10, vec![1, 0]).is_ok());
+    assert!(matches!(
+        vote_option(10, vec![0]),
+        Err(Error::HasVoted { .. })
+    ));
+    for (n, ranking) in [(1, vec![0, 1]), (2, vec![0]), (3, vec![1]), (4, vec![2, 1])] {
+        set_caller(member(n));
+        assert!(vote_option(10, ranking).is_ok());
+    }
+
+    set_caller(user(1));
+    set_time(1_001);
+    let view = end_proposal_vote(10).ok().unwrap();
+    assert!(view.status == ProposalStatus::Approved);
+    let result = view.ranked_result.unwrap();
+    assert_eq!(result.winner(), Some(1));
+    assert!(result.pending_tie().is_empty());
+    assert!(matches!(
+        resolve_ranked_tie(10, 0),
+        Err(Error::InvalidInput { .. })
+    ));
+}
+
+#[test]
+fn owners_settle_ties_the_count_stopped_at() {
+    insert_dao(DaoVisibility::Private);
+    let ranked = Proposal {
+        kind: ProposalKind::RankedChoice {
+            options: vec!["Fox".to_string(), "Owl".to_string()],
+        },
+        ..insert_proposal(10)
+    };
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(10, ranked));
+    let mut dao = _get_dao(&1).unwrap();
+    _set_membership(&mut dao, &member(1), Some(Membership::Member));
+    DAO_STORAGE.with(|service| service.borrow_mut().insert(1, dao));
+    set_caller(user(2));
+    assert!(vote_option(10, vec![0]).is_ok());
+    set_caller(member(1));
+    assert!(vote_option(10, vec![1]).is_ok());
+
+    set_caller(user(1));
+    set_time(1_001);
+    let view = end_proposal_vote(10).ok().unwrap();
+    assert!(view.status == ProposalStatus::Approved);
+    assert_eq!(view.ranked_result.unwrap().pending_tie(), &[0, 1]);
+
+    set_caller(user(2));
+    assert!(matches!(
+        resolve_ranked_tie(10, 1),
+        Err(Error::PermissionError { .. })
+    ));
+    set_caller(user(1));
+    assert!(resolve_ranked_tie(10, 2).is_err());
+    let result = resolve_ranked_tie(10, 1)
+        .ok()
+        .unwrap()
+        .ranked_result
+        .unwrap();
+    assert_eq!(result.winner(), Some(1));
+    assert_eq!(result.owner_decisions(), &[1]);
+}
+
+#[test]
+fn full_storage_refuses_new_records_but_keeps_reads_votes_and_deletes() {
+    init(Some(InitArgs {
+        admins: vec![user(1)],
+        ..Default::default()
+    }));
+    insert_dao(DaoVisibility::Private);
+    insert_listed_proposal(10, "Open", "");
+    insert_listed_proposal(11, "Old", "");
+    set_stable_pages(40);
+    set_caller(user(1));
+    let limit = |pages: u64| {
+        update_config(InitArgs {
+            storage_soft_limit_bytes: Some(pages * metrics::WASM_PAGE_SIZE),
+            ..Default::default()
+        })
+    };
+    assert!(limit(0).is_err());
+    assert!(limit(40).is_ok());
+
+    let is_storage_full =
+        |result: Result<(), Error>| matches!(result, Err(Error::StorageFull { .. }));
+    assert!(is_storage_full(
+        run(add_proposal(proposal_payload("New"))).map(|_| ())
+    ));
+    assert!(is_storage_full(
+        add_members_bulk(1, vec![member(1)]).map(|_| ())
+    ));
+    assert!(!_is_member(&_get_dao(&1).unwrap(), &member(1)));
+    set_caller(user(2));
+    assert!(is_storage_full(
+        comment_on_post(comment(10, "still here")).map(|_| ())
+    ));
+    assert!(upvote(10).is_ok());
+    assert!(get_proposal(10).is_ok());
+    assert!(get_dao(1).is_ok());
+    assert_eq!(ids(get_all_proposals(1)), vec![10, 11]);
+    assert!(get_all_comments_on_proposal(10).is_ok());
+    set_caller(user(1));
+    assert!(delete_proposal(11).is_ok());
+
+    // raising the limit again makes room right away
+    assert!(limit(41).is_ok());
+    assert!(run(add_proposal(proposal_payload("New"))).is_ok());
+}
+
+#[test]
+fn voting_timelines_bucket_votes_from_creation_to_deadline() {
+    const HOUR: u64 = 60 * 60 * 1_000_000_000;
+    insert_dao(DaoVisibility::Private);
+    let proposal = Proposal {
+        deadline: 4 * HOUR,
+        ..insert_proposal(10)
+    };
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(10, proposal));
+    for (n, direction, voted_at) in [
+        (1, VoteDirection::Up, 0),
+        (2, VoteDirection::Down, HOUR - 1),
+        (3, VoteDirection::Up, HOUR),
+        (4, VoteDirection::Up, 3 * HOUR + 5),
+    ] {
+        do_insert_vote(&Vote {
+            proposal_id: 10,
+            voter: Some(member(n)),
+            direction,
+            voted_at,
+            ..Default::default()
+        });
+    }
+    let counts = |timeline: Vec<TimelineBucket>| -> Vec<(u64, u64, u64)> {
+        timeline
+            .iter()
+            .map(|bucket| (bucket.start, bucket.upvotes, bucket.downvotes))
+            .collect()
+    };
+
+    set_caller(user(2));
+    // buckets under an hour are made an hour long, buckets without votes are kept
+    let hourly = counts(get_voting_timeline(10, 60).ok().unwrap());
+    assert_eq!(
+        hourly,
+        vec![(0, 1, 1), (HOUR, 1, 0), (2 * HOUR, 0, 0), (3 * HOUR, 1, 0)]
+    );
+    // and buckets longer than the vote cover all of it
+    assert_eq!(
+        counts(get_voting_timeline(10, 30 * 24 * 60 * 60).ok().unwrap()),
+        vec![(0, 3, 1)]
+    );
+
+    set_caller(user(9));
+    assert!(get_voting_timeline(10, 3_600).is_err());
+}
+
+#[test]
+fn comment_searches_stay_in_their_scope_and_skip_deleted_comments() {
+    insert_dao(DaoVisibility::Private);
+    insert_listed_proposal(10, "Budget", "");
+    insert_listed_proposal(11, "Logo", "");
+    let other = Dao {
+        id: 2,
+        owner: Some(user(4)),
+        ..Default::default()
+    };
+    DAO_STORAGE.with(|service| service.borrow_mut().insert(2, other));
+    let elsewhere = Proposal {
+        dao_id: 2,
+        owner: Some(user(4)),
+        ..insert_proposal(20)
+    };
+    PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(20, elsewhere));
+    let mut dao = _get_dao(&2).unwrap();
+    _list_dao_proposal(&mut dao, 20);
+    do_insert_dao(&mut dao);
+
+    let post = |caller: Principal, proposal_id: u64, content: &str| {
+        set_time(time() + 1);
+        set_caller(caller);
+        comment_on_post(comment(proposal_id, content))
+            .ok()
+            .unwrap()
+            .id
+    };
+    let first = post(user(2), 10, "Raise the BUDGET");
+    let deleted = post(user(2), 10, "budget is fine");
+    let logo = post(user(1), 11, "the logo eats our budget");
+    post(user(1), 11, "nothing to see");
+    post(user(4), 20, "budget in another dao");
+
+    set_caller(user(2));
+    assert!(delete_comment(deleted).is_ok());
+    let found = |scope: CommentScope, offset: u64, limit: u64| -> Vec<(u64, u64, String)> {
+        search_comments(scope, " Budget ".to_string(), offset, limit)
+            .ok()
+            .unwrap()
+            .into_iter()
+            .map(|found| (found.comment.id, found.proposal_id, found.proposal_title))
+            .collect()
+    };
+    // newest first, with the proposal each match is on
+    assert_eq!(
+        found(CommentScope::Dao(1), 0, 10),
+        vec![
+            (logo, 11, "Logo".to_string()),
+            (first, 10, "Budget".to_string())
+        ]
+    );
+    assert_eq!(found(CommentScope::Proposal(10), 0, 10).len(), 1);
+    assert_eq!(
+        found(CommentScope::Dao(1), 1, 1),
+        vec![(first, 10, "Budget".to_string())]
+    );
+    assert!(found(CommentScope::Dao(1), 2, 10).is_empty());
+
+    // other DAOs can't be searched by outsiders and the query is checked like proposal search
+    assert!(search_comments(CommentScope::Dao(2), "budget".to_string(), 0, 10).is_err());
+    assert!(search_comments(CommentScope::Proposal(20), "budget".to_string(), 0, 10).is_err());
+    assert!(search_comments(CommentScope::Dao(1), " ".to_string(), 0, 10).is_err());
+    set_caller(user(4));
+    assert_eq!(found(CommentScope::Dao(2), 0, 10).len(), 1);
+}
+
+#[test]
+fn owner_todos_list_each_waiting_item_once_and_count_past_the_first_twenty() {
+    const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+    insert_dao(DaoVisibility::Private);
+    set_caller(user(1));
+    assert!(set_report_threshold(1, 2).is_ok());
+    let mut dao = _get_dao(&1).unwrap();
+    _set_membership(&mut dao, &member(1), Some(Membership::Member));
+    DAO_STORAGE.with(|service| service.borrow_mut().insert(1, dao));
+    let seed = |id: u64, status: ProposalStatus, kind: ProposalKind, deadline: u64| {
+        let proposal = Proposal {
+            status,
+            kind,
+            deadline,
+            ..insert_listed_proposal(id, &format!("Proposal {}", id), "")
+        };
+        PROPOSAL_STORAGE.with(|service| service.borrow_mut().insert(id, proposal));
+    };
+    let funding = || ProposalKind::Funding {
+        amount: 5,
+        recipient: None,
+    };
+    seed(10, ProposalStatus::Open, ProposalKind::Signal, 1_000);
+    seed(11, ProposalStatus::Open, ProposalKind::Signal, u64::MAX);
+    seed(12, ProposalStatus::Approved, funding(), 1_000);
+    seed(13, ProposalStatus::Approved, ProposalKind::Signal, 1_000);
+    seed(14, ProposalStatus::Queued, ProposalKind::Signal, 1_000);
+    PROPOSAL_QUEUE.with(|service| service.borrow_mut().insert((1, 14), ()));
+
+    set_caller(user(1));
+    assert!(invite_to_dao(1, user(6)).is_ok());
+    set_time(6 * DAY);
+    assert!(invite_to_dao(1, user(7)).is_ok());
+    set_caller(user(5));
+    assert!(request_to_join(1, "let me in".to_string()).is_ok());
+    for reporter in [user(2), member(1)] {
+        set_caller(reporter);
+        assert!(report_content(ReportTarget::Proposal(12), String::new()).is_ok());
+    }
+    assert!(report_content(ReportTarget::Proposal(13), String::new()).is_ok());
+
+    set_caller(user(2));
+    assert!(matches!(
+        get_owner_todo(1),
+        Err(Error::PermissionError { .. })
+    ));
+    set_caller(user(1));
+    let todo = get_owner_todo(1).ok().unwrap();
+    let ids = |list: &TodoList| -> (u64, Vec<Option<u64>>) {
+        (list.total, list.items.iter().map(|item| item.id).collect())
+    };
+    assert_eq!(ids(&todo.awaiting_finalization), (1, vec![Some(10)]));
+    assert_eq!(ids(&todo.awaiting_execution), (1, vec![Some(12)]));
+    assert_eq!(ids(&todo.reported_content), (1, vec![Some(12)]));
+    assert_eq!(ids(&todo.queued_proposals), (1, vec![Some(14)]));
+    assert_eq!(todo.join_requests.total, 1);
+    assert_eq!(todo.join_requests.items[0].principal, Some(user(5)));
+    // only the invite running out within 48 hours
+    assert_eq!(todo.expiring_invites.total, 1);
+    assert_eq!(todo.expiring_invites.items[0].principal, Some(user(6)));
+
+    // long lists are cut to the first twenty but still counted in full
+    for id in 100..120 {
+        seed(id, ProposalStatus::Open, ProposalKind::Signal, 2_000);
+    }
+    let todo = get_owner_todo(1).ok().unwrap();
+    assert_eq!(todo.awaiting_finalization.total, 21);
+    assert_eq!(
+        todo.awaiting_finalization.items.len(),
+        TODO_ITEMS_PER_CATEGORY
+    );
+}
@@ -0,0 +1,272 @@
+use crate::env::time;
+use crate::{TREASURY_HISTORY, TREASURY_INDEX};
+use candid::{Decode, Encode, Nat, Principal};
+use ic_stable_structures::{BoundedStorable, Storable};
+use std::borrow::Cow;
+
+// What moved money in or out of a DAO's treasury
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub(crate) enum TreasuryTxKind {
+    // a proposer staked the DAO's proposal deposit
+    Deposit,
+    // an approved Funding proposal or one of its milestones was paid
+    Payout,
+    DuesPayment,
+    // a deposit was kept because its proposal missed quorum. The money was already in the
+    // treasury, so the balance doesn't change
+    DepositForfeit,
+    // a deposit was paid back to its proposer
+    Refund,
+    // the treasury was moved to the DAO this one was merged into, or received from the DAO merged
+    // into this one
+    MergeOut,
+    MergeIn,
+}
+
+// A change to a DAO's treasury. The balance kept here is what the DAO received through this
+// canister minus what it paid out. Ledger fees and transfers made straight to the treasury
+// account aren't part of it, so it can differ from what the ledger account holds
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct TreasuryTx {
+    id: u64,
+    dao_id: u64,
+    kind: TreasuryTxKind,
+    amount: u64,
+    // who paid or was paid, this canister for merges
+    counterparty: Option<Principal>,
+    proposal_id: Option<u64>,
+    timestamp: u64,
+    // the ledger block of the transfer, none for forfeits which don't move anything
+    block_index: Option<Nat>,
+    // the DAO's balance once this transaction is applied
+    balance_after: u64,
+}
+
+impl Storable for TreasuryTx {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+}
+
+impl BoundedStorable for TreasuryTx {
+    const MAX_SIZE: u32 = 512;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// The outcome of replaying a DAO's history
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct TreasuryCheck {
+    dao_id: u64,
+    transactions: u64,
+    // the balance the history adds up to
+    computed_balance: u64,
+    // the balance the last transaction recorded
+    recorded_balance: u64,
+    // the first transaction whose recorded balance doesn't follow from the ones before it
+    first_mismatch: Option<u64>,
+    consistent: bool,
+}
+
+// Append a transaction to the history of `dao_id`. Called once the transfer went through, so the
+// history only holds what actually happened
+pub(crate) fn record(
+    dao_id: u64,
+    kind: TreasuryTxKind,
+    amount: u64,
+    counterparty: Option<Principal>,
+    proposal_id: Option<u64>,
+    block_index: Option<Nat>,
+) {
+    let balance_after = apply(balance(dao_id), kind, amount);
+    TREASURY_HISTORY.with(|service| {
+        let id = service.borrow().len();
+        let tx = TreasuryTx {
+            id,
+            dao_id,
+            kind,
+            amount,
+            counterparty,
+            proposal_id,
+            timestamp: time(),
+            block_index,
+            balance_after,
+        };
+        service.borrow_mut().insert(id, tx);
+        TREASURY_INDEX.with(|index| index.borrow_mut().insert((dao_id, id), ()));
+    });
+}
+
+// The balance of a DAO according to its history, 0 before anything was recorded
+pub(crate) fn balance(dao_id: u64) -> u64 {
+    TREASURY_INDEX
+        .with(|index| {
+            index
+                .borrow()
+                .range((dao_id, 0)..=(dao_id, u64::MAX))
+                .last()
+        })
+        .and_then(|((_, id), _)| TREASURY_HISTORY.with(|service| service.borrow().get(&id)))
+        .map(|tx| tx.balance_after)
+        .unwrap_or_default()
+}
+
+// The transactions of a DAO, oldest first
+pub(crate) fn history(dao_id: u64, offset: usize, limit: usize) -> Vec<TreasuryTx> {
+    let ids: Vec<u64> = TREASURY_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((dao_id, 0)..=(dao_id, u64::MAX))
+            .skip(offset)
+            .take(limit)
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    TREASURY_HISTORY.with(|service| {
+        let service = service.borrow();
+        ids.iter().filter_map(|id| service.get(id)).collect()
+    })
+}
+
+// Replay the history of a DAO from a zero balance and compare every step with the balance that
+// was recorded for it
+pub(crate) fn verify(dao_id: u64) -> TreasuryCheck {
+    let mut check = TreasuryCheck {
+        dao_id,
+        transactions: 0,
+        computed_balance: 0,
+        recorded_balance: 0,
+        first_mismatch: None,
+        consistent: true,
+    };
+    let ids: Vec<u64> = TREASURY_INDEX.with(|index| {
+        index
+            .borrow()
+            .range((dao_id, 0)..=(dao_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    for id in ids {
+        let tx = match TREASURY_HISTORY.with(|service| service.borrow().get(&id)) {
+            Some(tx) if tx.dao_id == dao_id => tx,
+            // an index entry without its transaction, or pointing at another DAO's
+            _ => {
+                check.first_mismatch = check.first_mismatch.or(Some(id));
+                continue;
+            }
+        };
+        check.transactions += 1;
+        check.computed_balance = apply(check.computed_balance, tx.kind, tx.amount);
+        check.recorded_balance = tx.balance_after;
+        if tx.balance_after != check.computed_balance {
+            check.first_mismatch = check.first_mismatch.or(Some(id));
+        }
+    }
+    check.consistent = check.first_mismatch.is_none();
+    check
+}
+
+fn apply(balance: u64, kind: TreasuryTxKind, amount: u64) -> u64 {
+    match kind {
+        TreasuryTxKind::Deposit | TreasuryTxKind::DuesPayment | TreasuryTxKind::MergeIn => {
+            balance.saturating_add(amount)
+        }
+        TreasuryTxKind::Payout | TreasuryTxKind::Refund | TreasuryTxKind::MergeOut => {
+            balance.saturating_sub(amount)
+        }
+        TreasuryTxKind::DepositForfeit => balance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{set_caller, set_time};
+    use crate::{verify_treasury, Error, CONFIG};
+
+    fn user(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    // dues of 100 and a deposit of 30 in, a payout of 50 out, then the deposit refunded. Another
+    // DAO takes dues in between
+    fn scripted_history() {
+        for (now, dao_id, kind, amount, proposal_id) in [
+            (1, 1, TreasuryTxKind::DuesPayment, 100, None),
+            (2, 1, TreasuryTxKind::Deposit, 30, Some(10)),
+            (3, 2, TreasuryTxKind::DuesPayment, 7, None),
+            (4, 1, TreasuryTxKind::Payout, 50, Some(11)),
+            (5, 1, TreasuryTxKind::DepositForfeit, 30, Some(10)),
+            (6, 1, TreasuryTxKind::Refund, 30, Some(12)),
+        ] {
+            set_time(now);
+            record(
+                dao_id,
+                kind,
+                amount,
+                Some(user(2)),
+                proposal_id,
+                Some(Nat::from(now)),
+            );
+        }
+    }
+
+    #[test]
+    fn history_lists_every_change_with_the_balance_after_it() {
+        scripted_history();
+        let steps: Vec<(TreasuryTxKind, u64, u64)> = history(1, 0, 10)
+            .iter()
+            .map(|tx| (tx.kind, tx.amount, tx.balance_after))
+            .collect();
+        assert!(
+            steps
+                == vec![
+                    (TreasuryTxKind::DuesPayment, 100, 100),
+                    (TreasuryTxKind::Deposit, 30, 130),
+                    (TreasuryTxKind::Payout, 50, 80),
+                    (TreasuryTxKind::DepositForfeit, 30, 80),
+                    (TreasuryTxKind::Refund, 30, 50),
+                ]
+        );
+        assert_eq!((balance(1), balance(2), balance(3)), (50, 7, 0));
+        let page: Vec<u64> = history(1, 1, 2).iter().map(|tx| tx.timestamp).collect();
+        assert_eq!(page, vec![2, 4]);
+
+        let check = verify(1);
+        assert!(check.consistent && check.first_mismatch.is_none());
+        assert_eq!((check.transactions, check.computed_balance), (5, 50));
+    }
+
+    #[test]
+    fn verifying_finds_the_first_balance_that_doesnt_add_up() {
+        scripted_history();
+        let mut tampered = TREASURY_HISTORY
+            .with(|service| service.borrow().get(&3))
+            .unwrap();
+        tampered.balance_after += 1;
+        TREASURY_HISTORY.with(|service| service.borrow_mut().insert(3, tampered));
+        let check = verify(1);
+        assert!(!check.consistent);
+        assert_eq!(check.first_mismatch, Some(3));
+        assert_eq!((check.computed_balance, check.recorded_balance), (50, 50));
+
+        // the DAO's index pointing at a transaction of another DAO
+        TREASURY_INDEX.with(|index| index.borrow_mut().insert((1, 2), ()));
+        assert_eq!(verify(1).first_mismatch, Some(2));
+        assert!(verify(2).consistent);
+
+        set_caller(user(2));
+        assert!(matches!(
+            verify_treasury(1),
+            Err(Error::Unauthorized { .. })
+        ));
+        let mut config = CONFIG.with(|config| config.borrow().get().clone());
+        config.admin = Some(user(1));
+        CONFIG.with(|cell| cell.borrow_mut().set(config).ok());
+        set_caller(user(1));
+        assert!(verify_treasury(1).is_ok_and(|check| check.first_mismatch == Some(2)));
+    }
+}
@@ -0,0 +1,325 @@
+use crate::env::{canister_id, set_timer, time};
+use crate::{_get_settings, log_event, AuditAction, AuditTarget, Proposal, ProposalStatus};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext, TransformFunc,
+};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+// Bounds on the webhook a DAO can configure
+pub(crate) const MAX_URL_LEN: usize = 300;
+const MIN_SECRET_LEN: usize = 16;
+pub(crate) const MAX_SECRET_LEN: usize = 128;
+// Header carrying the hex encoded HMAC-SHA256 of the body, keyed with the webhook's secret
+const SIGNATURE_HEADER: &str = "X-Dao-Signature";
+// Receivers only have to acknowledge a delivery, anything past this is cut off
+const MAX_RESPONSE_BYTES: u64 = 2 * 1024;
+// Attached to every outcall. Covers a small request and MAX_RESPONSE_BYTES on a 13 node subnet,
+// whatever isn't used is refunded
+const OUTCALL_CYCLES: u128 = 200_000_000;
+// Failed deliveries are tried again this many times, waiting RETRY_BASE_DELAY longer each time
+const MAX_RETRIES: u8 = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(30);
+// Name of the query replicas run responses through, see webhook_transform
+const TRANSFORM_METHOD: &str = "webhook_transform";
+
+// Where a DAO wants the outcomes of its proposals posted
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) struct Webhook {
+    pub(crate) url: String,
+    pub(crate) secret: String,
+}
+
+// The JSON body posted when a proposal is finalized
+#[derive(Clone, Serialize)]
+struct OutcomePayload {
+    dao_id: u64,
+    proposal_id: u64,
+    title: String,
+    outcome: String,
+    upvotes: u64,
+    downvotes: u64,
+    timestamp: u64,
+}
+
+// Check that a webhook can be called. Fails with what's wrong with it
+pub(crate) fn validate(webhook: &Webhook) -> Result<(), String> {
+    if !webhook.url.starts_with("https://") || webhook.url.len() <= "https://".len() {
+        return Err("webhook url must be an https url".to_string());
+    }
+    if webhook.url.len() > MAX_URL_LEN {
+        return Err(format!(
+            "webhook url can be at most {} characters",
+            MAX_URL_LEN
+        ));
+    }
+    if !(MIN_SECRET_LEN..=MAX_SECRET_LEN).contains(&webhook.secret.len()) {
+        return Err(format!(
+            "webhook secret must be between {} and {} characters",
+            MIN_SECRET_LEN, MAX_SECRET_LEN
+        ));
+    }
+    Ok(())
+}
+
+// Post the outcome of a finalized proposal to its DAO's webhook, if it has one. The delivery runs
+// in a timer so finalizing never waits for, or fails because of, the receiver
+pub(crate) fn notify_finalized(proposal: &Proposal) {
+    if _get_settings(&proposal.dao_id).webhook.is_none() {
+        return;
+    }
+
+    let payload = OutcomePayload {
+        dao_id: proposal.dao_id,
+        proposal_id: proposal.id,
+        title: proposal.title.clone(),
+        outcome: match proposal.status {
+            ProposalStatus::Approved => "approved".to_string(),
+            _ => "rejected".to_string(),
+        },
+        upvotes: proposal.upvote_count,
+        downvotes: proposal.downvote_count,
+        timestamp: time(),
+    };
+    schedule(payload, 0, Duration::ZERO);
+}
+
+// Strip what differs between replicas from a receiver's response so they can agree on it
+pub(crate) fn transform(args: TransformArgs) -> HttpResponse {
+    HttpResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: Vec::new(),
+    }
+}
+
+fn schedule(payload: OutcomePayload, attempt: u8, delay: Duration) {
+    set_timer(delay, move || {
+        ic_cdk::spawn(deliver(payload, attempt));
+    });
+}
+
+async fn deliver(payload: OutcomePayload, attempt: u8) {
+    // read again on every attempt so retries follow a webhook that was changed or removed
+    let webhook = match _get_settings(&payload.dao_id).webhook {
+        Some(webhook) => webhook,
+        None => return,
+    };
+    let delivered = match http_request(request(&webhook, &payload), OUTCALL_CYCLES).await {
+        Ok((response,)) => response.status >= 200u64 && response.status < 300u64,
+        Err(_) => false,
+    };
+    record_attempt(payload, attempt, delivered);
+}
+
+// The signed POST of a payload to a webhook
+fn request(webhook: &Webhook, payload: &OutcomePayload) -> CanisterHttpRequestArgument {
+    let body = serde_json::to_vec(payload).expect("cannot encode webhook payload");
+    CanisterHttpRequestArgument {
+        url: webhook.url.clone(),
+        max_response_bytes: Some(MAX_RESPONSE_BYTES),
+        method: HttpMethod::POST,
+        headers: vec![
+            HttpHeader {
+                name: "Content-Type".to_string(),
+                value: "application/json".to_string(),
+            },
+            HttpHeader {
+                name: SIGNATURE_HEADER.to_string(),
+                value: format!(
+                    "sha256={}",
+                    to_hex(&hmac_sha256(webhook.secret.as_bytes(), &body))
+                ),
+            },
+        ],
+        body: Some(body),
+        // TransformContext::from_name asks ic_cdk for the canister id, which only exists in a
+        // canister
+        transform: Some(TransformContext {
+            function: TransformFunc(candid::Func {
+                principal: canister_id(),
+                method: TRANSFORM_METHOD.to_string(),
+            }),
+            context: Vec::new(),
+        }),
+    }
+}
+
+// Log how a delivery went and schedule the next attempt of one that failed, if it has any left
+fn record_attempt(payload: OutcomePayload, attempt: u8, delivered: bool) {
+    let target = AuditTarget::Proposal(payload.proposal_id);
+    if delivered {
+        log_event(payload.dao_id, AuditAction::WebhookDelivered, target);
+        return;
+    }
+
+    log_event(payload.dao_id, AuditAction::WebhookFailed, target);
+    if let Some(delay) = retry_delay(attempt) {
+        schedule(payload, attempt + 1, delay);
+    }
+}
+
+// How long to wait before trying a failed attempt again, None once the retries ran out. Waits 30
+// seconds, then 60, then 120
+fn retry_delay(attempt: u8) -> Option<Duration> {
+    (attempt < MAX_RETRIES).then(|| RETRY_BASE_DELAY * 2u32.pow(attempt as u32))
+}
+
+// HMAC-SHA256 as defined in RFC 2104
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.map(|byte| byte ^ 0x36));
+    inner.update(message);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|byte| byte ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AUDIT_STORAGE;
+
+    fn payload() -> OutcomePayload {
+        OutcomePayload {
+            dao_id: 1,
+            proposal_id: 10,
+            title: "Fund the meetup".to_string(),
+            outcome: "approved".to_string(),
+            upvotes: 3,
+            downvotes: 1,
+            timestamp: 1_000,
+        }
+    }
+
+    fn webhook() -> Webhook {
+        Webhook {
+            url: "https://bot.example.com/outcomes".to_string(),
+            secret: "a-shared-secret-of-some-length".to_string(),
+        }
+    }
+
+    fn logged_actions() -> Vec<AuditAction> {
+        AUDIT_STORAGE.with(|service| {
+            service
+                .borrow()
+                .iter()
+                .map(|(_, event)| event.action)
+                .collect()
+        })
+    }
+
+    #[test]
+    fn only_https_urls_and_bounded_secrets_are_accepted() {
+        assert!(validate(&webhook()).is_ok());
+        for invalid in [
+            Webhook {
+                url: "http://bot.example.com".to_string(),
+                ..webhook()
+            },
+            Webhook {
+                url: "https://".to_string(),
+                ..webhook()
+            },
+            Webhook {
+                url: format!("https://{}", "a".repeat(MAX_URL_LEN)),
+                ..webhook()
+            },
+            Webhook {
+                secret: "short".to_string(),
+                ..webhook()
+            },
+            Webhook {
+                secret: "s".repeat(MAX_SECRET_LEN + 1),
+                ..webhook()
+            },
+        ] {
+            assert!(validate(&invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn requests_post_the_outcome_signed_with_the_secret() {
+        let request = request(&webhook(), &payload());
+        assert_eq!(request.url, webhook().url);
+        assert!(request.method == HttpMethod::POST);
+        assert_eq!(request.max_response_bytes, Some(MAX_RESPONSE_BYTES));
+
+        let body = request.body.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["dao_id"], 1);
+        assert_eq!(json["proposal_id"], 10);
+        assert_eq!(json["title"], "Fund the meetup");
+        assert_eq!(json["outcome"], "approved");
+        assert_eq!(json["upvotes"], 3);
+        assert_eq!(json["downvotes"], 1);
+        assert_eq!(json["timestamp"], 1_000);
+
+        let signature = request
+            .headers
+            .iter()
+            .find(|header| header.name == SIGNATURE_HEADER)
+            .map(|header| header.value.clone());
+        let expected = to_hex(&hmac_sha256(webhook().secret.as_bytes(), &body));
+        assert_eq!(signature, Some(format!("sha256={}", expected)));
+        assert_eq!(
+            request.transform.unwrap().function.0.method,
+            TRANSFORM_METHOD
+        );
+    }
+
+    #[test]
+    fn signatures_match_the_rfc_4231_test_vector() {
+        assert_eq!(
+            to_hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn transformed_responses_only_keep_their_status() {
+        let response = transform(TransformArgs {
+            response: HttpResponse {
+                status: 202u64.into(),
+                headers: vec![HttpHeader {
+                    name: "Date".to_string(),
+                    value: "Fri, 16 Oct 2026 10:00:00 GMT".to_string(),
+                }],
+                body: b"queued".to_vec(),
+            },
+            context: Vec::new(),
+        });
+        assert!(response.status == 202u64);
+        assert!(response.headers.is_empty() && response.body.is_empty());
+    }
+
+    #[test]
+    fn failed_deliveries_are_logged_and_retried_three_times() {
+        let delays: Vec<Option<u64>> = (0..=MAX_RETRIES)
+            .map(|attempt| retry_delay(attempt).map(|delay| delay.as_secs()))
+            .collect();
+        assert_eq!(delays, vec![Some(30), Some(60), Some(120), None]);
+
+        for attempt in 0..=MAX_RETRIES {
+            record_attempt(payload(), attempt, false);
+        }
+        record_attempt(payload(), 0, true);
+        let mut expected = vec![AuditAction::WebhookFailed; MAX_RETRIES as usize + 1];
+        expected.push(AuditAction::WebhookDelivered);
+        assert!(logged_actions() == expected);
+    }
+}
@@ -0,0 +1,176 @@
+// Stands in for the canisters the backend calls in the pocket-ic tests. One wasm plays both roles,
+// tests install it once as the ledger and once as the registry:
+// - an ICRC-1/ICRC-2 ledger keeping balances in memory. Allowances aren't tracked, every account
+//   is taken to have approved the backend for everything
+// - the registry public DAOs are announced to, keeping every announcement it is sent
+// While failing is set every transfer and announcement traps, like a canister that is down
+#[macro_use]
+extern crate serde;
+use candid::{CandidType, Nat, Principal};
+use std::{cell::Cell, cell::RefCell, collections::BTreeMap};
+
+// Fee of every transfer, paid by the account the amount is taken from
+const FEE: u64 = 10;
+
+thread_local! {
+    static BALANCES: RefCell<BTreeMap<Account, u64>> = const { RefCell::new(BTreeMap::new()) };
+    static TRANSFERS: RefCell<Vec<Transfer>> = const { RefCell::new(Vec::new()) };
+    static ANNOUNCEMENTS: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+    static FAILING: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+struct Account {
+    owner: Principal,
+    subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+struct Transfer {
+    from: Account,
+    to: Account,
+    amount: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct TransferFromArgs {
+    from: Account,
+    to: Account,
+    amount: Nat,
+}
+
+#[derive(CandidType, Deserialize)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+}
+
+#[derive(CandidType, Deserialize)]
+struct AllowanceArgs {
+    account: Account,
+    spender: Account,
+}
+
+#[derive(CandidType)]
+struct Allowance {
+    allowance: Nat,
+    expires_at: Option<u64>,
+}
+
+#[derive(CandidType)]
+enum TransferError {
+    InsufficientFunds { balance: Nat },
+}
+
+// The default subaccount can be given as none or as 32 zeros, both are the same account
+fn normalized(account: Account) -> Account {
+    match account.subaccount {
+        Some(subaccount) if subaccount.iter().all(|byte| *byte == 0) => Account {
+            owner: account.owner,
+            subaccount: None,
+        },
+        _ => account,
+    }
+}
+
+fn balance(account: &Account) -> u64 {
+    BALANCES.with(|balances| balances.borrow().get(account).copied().unwrap_or_default())
+}
+
+fn check_up() {
+    if FAILING.with(Cell::get) {
+        ic_cdk::trap("the stub is failing on purpose");
+    }
+}
+
+fn transfer(from: Account, to: Account, amount: &Nat) -> Result<Nat, TransferError> {
+    check_up();
+    let (from, to) = (normalized(from), normalized(to));
+    let amount = u64::try_from(&amount.0).expect("amounts fit in 64 bits");
+    let available = balance(&from);
+    if available < amount + FEE {
+        return Err(TransferError::InsufficientFunds {
+            balance: Nat::from(available),
+        });
+    }
+    BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        balances.insert(from.clone(), available - amount - FEE);
+        *balances.entry(to.clone()).or_default() += amount;
+    });
+    TRANSFERS.with(|transfers| {
+        let mut transfers = transfers.borrow_mut();
+        transfers.push(Transfer { from, to, amount });
+        Ok(Nat::from(transfers.len() - 1))
+    })
+}
+
+#[ic_cdk::query]
+fn icrc1_fee() -> Nat {
+    Nat::from(FEE)
+}
+
+#[ic_cdk::query]
+fn icrc1_balance_of(account: Account) -> Nat {
+    Nat::from(balance(&normalized(account)))
+}
+
+#[ic_cdk::query]
+fn icrc2_allowance(_args: AllowanceArgs) -> Allowance {
+    Allowance {
+        allowance: Nat::from(u64::MAX),
+        expires_at: None,
+    }
+}
+
+#[ic_cdk::update]
+fn icrc2_transfer_from(args: TransferFromArgs) -> Result<Nat, TransferError> {
+    transfer(args.from, args.to, &args.amount)
+}
+
+#[ic_cdk::update]
+fn icrc1_transfer(arg: TransferArg) -> Result<Nat, TransferError> {
+    let from = Account {
+        owner: ic_cdk::caller(),
+        subaccount: arg.from_subaccount,
+    };
+    transfer(from, arg.to, &arg.amount)
+}
+
+// Give an account tokens out of thin air
+#[ic_cdk::update]
+fn mint(account: Account, amount: u64) {
+    BALANCES.with(|balances| {
+        *balances
+            .borrow_mut()
+            .entry(normalized(account))
+            .or_default() += amount
+    });
+}
+
+// Every transfer made, in order
+#[ic_cdk::query]
+fn transfers() -> Vec<Transfer> {
+    TRANSFERS.with(|transfers| transfers.borrow().clone())
+}
+
+#[ic_cdk::update]
+fn set_failing(failing: bool) {
+    FAILING.with(|cell| cell.set(failing));
+}
+
+// Keeps the announcement as it was sent, tests decode it with the backend's .did
+#[export_name = "canister_update register_dao"]
+fn register_dao() {
+    check_up();
+    let announcement = ic_cdk::api::call::arg_data_raw();
+    ANNOUNCEMENTS.with(|announcements| announcements.borrow_mut().push(announcement));
+    ic_cdk::api::call::reply(());
+}
+
+// Every announcement delivered, in order
+#[ic_cdk::query]
+fn announcements() -> Vec<Vec<u8>> {
+    ANNOUNCEMENTS.with(|announcements| announcements.borrow().clone())
+}
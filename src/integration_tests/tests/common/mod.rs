@@ -0,0 +1,505 @@
+// Setup shared by the pocket-ic tests: the backend installed from its wasm, the stub canister
+// standing in for the ledger and the registry, and calls written as candid text. Arguments are
+// typed and results decoded with the canisters' .did files, so a test only spells out the fields
+// it cares about and results are compared as candid values
+#![allow(dead_code)]
+
+use candid::types::{Label, Type};
+use candid::{IDLArgs, IDLValue, Principal, TypeEnv};
+use candid_parser::utils::CandidSource;
+use flate2::{write::GzEncoder, Compression};
+use pocket_ic::{PocketIc, WasmResult};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Enough cycles for the backend to run its timers and spawn canisters
+const INITIAL_CYCLES: u128 = 100_000_000_000_000;
+// Bytes of the bucket wasm uploaded per call, well within the size of an ingress message
+const WASM_CHUNK_BYTES: usize = 1024 * 1024;
+
+pub struct Canister {
+    pub id: Principal,
+    types: TypeEnv,
+    actor: Type,
+}
+
+impl Canister {
+    fn encode(&self, method: &str, args: &str) -> Vec<u8> {
+        let function = self.function(method);
+        candid_parser::parse_idl_args(args)
+            .unwrap_or_else(|error| panic!("invalid arguments of {}: {}", method, error))
+            .to_bytes_with_types(&self.types, &function.args)
+            .unwrap_or_else(|error| panic!("arguments don't fit {}: {}", method, error))
+    }
+
+    fn encode_init(&self, args: &str) -> Vec<u8> {
+        let types = match self.actor.as_ref() {
+            candid::types::TypeInner::Class(args, _) => args.clone(),
+            _ => Vec::new(),
+        };
+        candid_parser::parse_idl_args(args)
+            .and_then(|parsed| Ok(parsed.to_bytes_with_types(&self.types, &types)?))
+            .unwrap_or_else(|error| panic!("invalid init arguments: {}", error))
+    }
+
+    fn decode(&self, method: &str, reply: &[u8]) -> IDLValue {
+        let function = self.function(method);
+        IDLArgs::from_bytes_with_types(reply, &self.types, &function.rets)
+            .unwrap_or_else(|error| panic!("reply of {} doesn't decode: {}", method, error))
+            .args
+            .into_iter()
+            .next()
+            .unwrap_or(IDLValue::Null)
+    }
+
+    fn function<'a>(&'a self, method: &'a str) -> &'a candid::types::Function {
+        self.types
+            .get_method(&self.actor, method)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    // A record of one of the types of the .did, decoded from the bytes it was encoded to
+    pub fn decode_type(&self, type_name: &str, bytes: &[u8]) -> IDLValue {
+        let ty: Type = candid::types::TypeInner::Var(type_name.to_string()).into();
+        IDLArgs::from_bytes_with_types(bytes, &self.types, &[ty])
+            .unwrap_or_else(|error| panic!("not a {}: {}", type_name, error))
+            .args
+            .remove(0)
+    }
+}
+
+pub struct Harness {
+    pub pic: PocketIc,
+    pub backend: Canister,
+}
+
+impl Harness {
+    // A fresh replica with the backend installed, ADMIN being its admin. `config` are more fields
+    // of the InitArgs record, e.g. "storage_soft_limit_bytes = opt 1_000_000"
+    pub fn new(config: &str) -> Harness {
+        let pic = PocketIc::new();
+        let id = pic.create_canister();
+        pic.add_cycles(id, INITIAL_CYCLES);
+        let backend = Canister {
+            id,
+            ..load_did(&backend_did())
+        };
+        let init = backend.encode_init(&format!(
+            "(opt record {{ admins = vec {{ principal \"{}\" }}; {} }})",
+            admin(),
+            config
+        ));
+        pic.install_canister(id, read_wasm(&backend_wasm()), init, None);
+        Harness { pic, backend }
+    }
+
+    // Another canister running the stub, to be used as the ledger or the registry
+    pub fn install_stub(&self) -> Canister {
+        let id = self.pic.create_canister();
+        self.pic.add_cycles(id, INITIAL_CYCLES);
+        self.pic.install_canister(
+            id,
+            read_wasm(&stub_wasm()),
+            candid::encode_args(()).unwrap(),
+            None,
+        );
+        Canister {
+            id,
+            ..load_did(&stub_did())
+        }
+    }
+
+    // Upload the backend's wasm as the one its buckets are installed with. It is gzipped, which the
+    // IC takes as well, so it fits the call installing it in a bucket
+    pub fn upload_bucket_wasm(&self) {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&read_wasm(&backend_wasm())).unwrap();
+        let wasm = encoder.finish().unwrap();
+        for (index, chunk) in wasm.chunks(WASM_CHUNK_BYTES).enumerate() {
+            let result = self.pic.update_call(
+                self.backend.id,
+                admin(),
+                "upload_bucket_wasm",
+                candid::encode_args((chunk, index > 0)).unwrap(),
+            );
+            ok(reply(&self.backend, "upload_bucket_wasm", result));
+        }
+    }
+
+    // A bucket the backend spawned, called with the backend's interface
+    pub fn bucket(&self, id: Principal) -> Canister {
+        Canister {
+            id,
+            ..load_did(&backend_did())
+        }
+    }
+
+    // The message an update call was rejected with, failing the test if it was answered
+    pub fn rejection(
+        &self,
+        canister: &Canister,
+        caller: Principal,
+        method: &str,
+        args: &str,
+    ) -> String {
+        let result =
+            self.pic
+                .update_call(canister.id, caller, method, canister.encode(method, args));
+        match result {
+            Ok(WasmResult::Reject(message)) => message,
+            Ok(WasmResult::Reply(_)) => panic!("{} wasn't rejected", method),
+            Err(error) => format!("{:?}", error),
+        }
+    }
+
+    // Install the same wasm again, the way a release is deployed
+    pub fn upgrade(&self) {
+        self.pic
+            .upgrade_canister(
+                self.backend.id,
+                read_wasm(&backend_wasm()),
+                candid::encode_one(None::<()>).unwrap(),
+                None,
+            )
+            .unwrap_or_else(|error| panic!("upgrade failed: {:?}", error));
+    }
+
+    pub fn update(
+        &self,
+        canister: &Canister,
+        caller: Principal,
+        method: &str,
+        args: &str,
+    ) -> IDLValue {
+        let result =
+            self.pic
+                .update_call(canister.id, caller, method, canister.encode(method, args));
+        reply(canister, method, result)
+    }
+
+    pub fn query(
+        &self,
+        canister: &Canister,
+        caller: Principal,
+        method: &str,
+        args: &str,
+    ) -> IDLValue {
+        let result =
+            self.pic
+                .query_call(canister.id, caller, method, canister.encode(method, args));
+        reply(canister, method, result)
+    }
+
+    // Update calls of the backend submitted together before any of them is executed, so their
+    // awaits interleave. Returns their results in the order they were given
+    pub fn update_together(&self, caller: Principal, calls: &[(&str, &str)]) -> Vec<IDLValue> {
+        let submitted: Vec<_> = calls
+            .iter()
+            .map(|(method, args)| {
+                self.pic
+                    .submit_call(
+                        self.backend.id,
+                        caller,
+                        method,
+                        self.backend.encode(method, args),
+                    )
+                    .unwrap_or_else(|error| panic!("{} wasn't accepted: {:?}", method, error))
+            })
+            .collect();
+        submitted
+            .into_iter()
+            .zip(calls)
+            .map(|(message, (method, _))| {
+                reply(&self.backend, method, self.pic.await_call(message))
+            })
+            .collect()
+    }
+
+    pub fn call(&self, caller: Principal, method: &str, args: &str) -> IDLValue {
+        self.update(&self.backend, caller, method, args)
+    }
+
+    pub fn read(&self, caller: Principal, method: &str, args: &str) -> IDLValue {
+        self.query(&self.backend, caller, method, args)
+    }
+
+    // Let time pass and run the timers that became due, with enough rounds for them or calls
+    // spawned before to go through a few calls to other canisters
+    pub fn wait(&self, seconds: u64) {
+        self.pic
+            .advance_time(std::time::Duration::from_secs(seconds));
+        for _ in 0..10 {
+            self.pic.tick();
+        }
+    }
+
+    // The replica's clock, in nanoseconds like the canister's
+    pub fn now(&self) -> u64 {
+        self.pic
+            .get_time()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64
+    }
+
+    // A private DAO of `owner` with `members` added to it
+    pub fn create_dao(&self, owner: Principal, members: &[Principal]) -> u64 {
+        let dao = self.call(
+            owner,
+            "create_dao",
+            "(record { name = \"Tested\"; description = \"A DAO under test\"; avatar = variant { None } })",
+        );
+        let dao_id = nat64(field(opt(&dao).expect("the DAO was refused"), "id"));
+        let members: Vec<String> = members
+            .iter()
+            .map(|member| format!("principal \"{}\"", member))
+            .collect();
+        ok(self.call(
+            owner,
+            "add_members_bulk",
+            &format!("({}, vec {{ {} }})", dao_id, members.join("; ")),
+        ));
+        dao_id
+    }
+
+    // A proposal of `owner` in a DAO. `fields` are more fields of the ProposalPayload record
+    pub fn add_proposal(&self, owner: Principal, dao_id: u64, title: &str, fields: &str) -> u64 {
+        let proposal = ok(self.call(
+            owner,
+            "add_proposal",
+            &format!(
+                "(record {{ dao_id = {}; title = {}; details = \"Details\"; amount_requested = 0; {} }})",
+                dao_id,
+                quoted(title),
+                fields
+            ),
+        ));
+        nat64(field(&proposal, "id"))
+    }
+
+    // The stub installed as the ledger the backend is configured with
+    pub fn install_ledger(&self) -> Canister {
+        let ledger = self.install_stub();
+        ok(self.call(
+            admin(),
+            "set_ledger_canister",
+            &format!("(principal \"{}\")", ledger.id),
+        ));
+        ledger
+    }
+
+    // Give `account` tokens on the stub ledger
+    pub fn mint(&self, ledger: &Canister, account: &str, amount: u64) {
+        self.update(
+            ledger,
+            admin(),
+            "mint",
+            &format!("({}, {})", account, amount),
+        );
+    }
+
+    pub fn balance(&self, ledger: &Canister, account: &str) -> u64 {
+        nat64(&self.query(
+            ledger,
+            admin(),
+            "icrc1_balance_of",
+            &format!("({})", account),
+        ))
+    }
+
+    // Every transfer the stub ledger made, as (from, to, amount) in candid text
+    pub fn transfers(&self, ledger: &Canister) -> Vec<(String, String, u64)> {
+        vec(&self.query(ledger, admin(), "transfers", "()"))
+            .iter()
+            .map(|transfer| {
+                (
+                    field(transfer, "from").to_string(),
+                    field(transfer, "to").to_string(),
+                    nat64(field(transfer, "amount")),
+                )
+            })
+            .collect()
+    }
+
+    // The account of a DAO's treasury, the backend's principal and the DAO id, big endian, in the
+    // last 8 of 32 bytes
+    pub fn treasury(&self, dao_id: u64) -> String {
+        let subaccount: String = [0u8; 24]
+            .iter()
+            .chain(dao_id.to_be_bytes().iter())
+            .map(|byte| format!("\\{:02x}", byte))
+            .collect();
+        format!(
+            "record {{ owner = principal \"{}\"; subaccount = opt blob \"{}\" }}",
+            self.backend.id, subaccount
+        )
+    }
+}
+
+// The default account of a principal
+pub fn account(owner: Principal) -> String {
+    format!(
+        "record {{ owner = principal \"{}\"; subaccount = null }}",
+        owner
+    )
+}
+
+fn reply<E: std::fmt::Debug>(
+    canister: &Canister,
+    method: &str,
+    result: Result<WasmResult, E>,
+) -> IDLValue {
+    match result {
+        Ok(WasmResult::Reply(bytes)) => canister.decode(method, &bytes),
+        Ok(WasmResult::Reject(message)) => panic!("{} was rejected: {}", method, message),
+        Err(error) => panic!("{} failed: {:?}", method, error),
+    }
+}
+
+// The admin the backend is installed with
+pub fn admin() -> Principal {
+    user(0)
+}
+
+// Distinct callers, none of them anonymous
+pub fn user(n: u8) -> Principal {
+    Principal::from_slice(&[n, 0xda, 0x0])
+}
+
+// The value of an Ok result, failing the test with the error otherwise
+pub fn ok(result: IDLValue) -> IDLValue {
+    match result {
+        IDLValue::Variant(variant) if variant.0.id == Label::Named("Ok".to_string()) => {
+            variant.0.val
+        }
+        other => panic!("expected Ok, got {}", other),
+    }
+}
+
+// The kind of error of an Err result, e.g. "NotAMember"
+pub fn err(result: IDLValue) -> String {
+    match result {
+        IDLValue::Variant(variant) if variant.0.id == Label::Named("Err".to_string()) => {
+            case(&variant.0.val).to_string()
+        }
+        other => panic!("expected Err, got {}", other),
+    }
+}
+
+// The name of the case a variant holds
+pub fn case(value: &IDLValue) -> &str {
+    match value {
+        IDLValue::Variant(variant) => match &variant.0.id {
+            Label::Named(name) => name,
+            label => panic!("unnamed case {}", label),
+        },
+        other => panic!("not a variant: {}", other),
+    }
+}
+
+pub fn field<'a>(value: &'a IDLValue, name: &str) -> &'a IDLValue {
+    let label = Label::Named(name.to_string());
+    match value {
+        IDLValue::Record(fields) => fields
+            .iter()
+            .find(|field| field.id == label)
+            .map(|field| &field.val)
+            .unwrap_or_else(|| panic!("no field {} in {}", name, value)),
+        other => panic!("not a record: {}", other),
+    }
+}
+
+pub fn nat64(value: &IDLValue) -> u64 {
+    match value {
+        IDLValue::Nat64(n) => *n,
+        IDLValue::Nat(n) => u64::try_from(&n.0).unwrap(),
+        other => panic!("not a number: {}", other),
+    }
+}
+
+pub fn principal(value: &IDLValue) -> Principal {
+    match value {
+        IDLValue::Principal(principal) => *principal,
+        other => panic!("not a principal: {}", other),
+    }
+}
+
+pub fn text(value: &IDLValue) -> &str {
+    match value {
+        IDLValue::Text(text) => text,
+        other => panic!("not a text: {}", other),
+    }
+}
+
+pub fn opt(value: &IDLValue) -> Option<&IDLValue> {
+    match value {
+        IDLValue::Opt(inner) => Some(inner),
+        IDLValue::None | IDLValue::Null => None,
+        other => panic!("not an option: {}", other),
+    }
+}
+
+pub fn vec(value: &IDLValue) -> &[IDLValue] {
+    match value {
+        IDLValue::Vec(values) => values,
+        other => panic!("not a vector: {}", other),
+    }
+}
+
+// Text escaped for a candid text literal
+pub fn quoted(content: &str) -> String {
+    format!("{:?}", content)
+}
+
+fn load_did(path: &Path) -> Canister {
+    let (types, actor) = CandidSource::File(path)
+        .load()
+        .unwrap_or_else(|error| panic!("can't load {}: {}", path.display(), error));
+    Canister {
+        id: Principal::anonymous(),
+        types,
+        actor: actor.expect("the .did declares no service"),
+    }
+}
+
+fn read_wasm(path: &Path) -> Vec<u8> {
+    std::fs::read(path).unwrap_or_else(|error| {
+        panic!(
+            "can't read {}, see the README on building the wasm: {}",
+            path.display(),
+            error
+        )
+    })
+}
+
+fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+// Built with `cargo build-wasm`, or wherever BACKEND_WASM points
+fn backend_wasm() -> PathBuf {
+    std::env::var_os("BACKEND_WASM")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            manifest_dir().join(
+                "../../target/wasm32-unknown-unknown/release/icp_rust_boilerplate_backend.wasm",
+            )
+        })
+}
+
+// Built with `cargo build-stub`, or wherever STUB_WASM points
+fn stub_wasm() -> PathBuf {
+    std::env::var_os("STUB_WASM")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            manifest_dir().join("target/wasm32-unknown-unknown/release/stub_canister.wasm")
+        })
+}
+
+fn backend_did() -> PathBuf {
+    manifest_dir().join("../icp_rust_boilerplate_backend/icp_rust_boilerplate_backend.did")
+}
+
+fn stub_did() -> PathBuf {
+    manifest_dir().join("stub_canister/stub_canister.did")
+}
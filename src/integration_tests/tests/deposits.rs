@@ -0,0 +1,183 @@
+// Proposal deposits are taken from the proposer into the DAO's treasury, paid back when the
+// proposal reaches quorum and kept by the treasury when it doesn't. A refund the ledger fails
+// stays claimable with claim_deposit_refund
+mod common;
+
+use candid::Principal;
+use common::*;
+
+const DEPOSIT: u64 = 50;
+const FEE: u64 = 10;
+const VOTING_PERIOD_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+struct Setup {
+    h: Harness,
+    ledger: Canister,
+    dao_id: u64,
+    proposer: Principal,
+}
+
+// A DAO of three asking for a deposit and half of its members to vote, the proposer holding 1_000
+fn setup() -> Setup {
+    let h = Harness::new("");
+    let ledger = h.install_ledger();
+    let proposer = user(2);
+    let dao_id = h.create_dao(user(1), &[proposer, user(3)]);
+    ok(h.call(
+        user(1),
+        "update_dao_settings",
+        &format!(
+            "({}, record {{ proposal_deposit = opt {}; quorum_percent = opt 50 }})",
+            dao_id, DEPOSIT
+        ),
+    ));
+    h.mint(&ledger, &account(proposer), 1_000);
+    Setup {
+        h,
+        ledger,
+        dao_id,
+        proposer,
+    }
+}
+
+fn deposit_status(h: &Harness, proposal_id: u64) -> String {
+    let proposal = ok(h.read(user(1), "get_proposal", &format!("({})", proposal_id)));
+    let deposit = opt(field(&proposal, "deposit")).expect("the proposal has no deposit");
+    assert_eq!(nat64(field(deposit, "amount")), DEPOSIT);
+    case(field(deposit, "status")).to_string()
+}
+
+// Let the vote run out and end it, then let the refund it starts reach the ledger
+fn end_vote(h: &Harness, proposer: Principal, proposal_id: u64) {
+    h.wait(VOTING_PERIOD_SECONDS + 1);
+    ok(h.call(proposer, "end_proposal_vote", &format!("({})", proposal_id)));
+    h.wait(1);
+}
+
+#[test]
+fn deposits_are_taken_and_paid_back_on_quorum() {
+    let Setup {
+        h,
+        ledger,
+        dao_id,
+        proposer,
+    } = setup();
+    let proposal_id = h.add_proposal(proposer, dao_id, "Staked", "");
+    assert_eq!(h.balance(&ledger, &h.treasury(dao_id)), DEPOSIT);
+    assert_eq!(
+        h.balance(&ledger, &account(proposer)),
+        1_000 - DEPOSIT - FEE
+    );
+    assert_eq!(deposit_status(&h, proposal_id), "Held");
+
+    for voter in [user(1), user(3)] {
+        ok(h.call(voter, "upvote", &format!("({})", proposal_id)));
+    }
+    end_vote(&h, proposer, proposal_id);
+
+    // paid back less the fee of the refund, the treasury is left as it was
+    assert_eq!(deposit_status(&h, proposal_id), "Refunded");
+    assert_eq!(h.balance(&ledger, &h.treasury(dao_id)), 0);
+    assert_eq!(
+        h.balance(&ledger, &account(proposer)),
+        1_000 - DEPOSIT - FEE + DEPOSIT - FEE
+    );
+    assert_eq!(h.transfers(&ledger).len(), 2);
+}
+
+#[test]
+fn deposits_are_forfeited_without_quorum() {
+    let Setup {
+        h,
+        ledger,
+        dao_id,
+        proposer,
+    } = setup();
+    let proposal_id = h.add_proposal(proposer, dao_id, "Ignored", "");
+    end_vote(&h, proposer, proposal_id);
+
+    assert_eq!(deposit_status(&h, proposal_id), "Forfeited");
+    assert_eq!(h.balance(&ledger, &h.treasury(dao_id)), DEPOSIT);
+    assert_eq!(h.transfers(&ledger).len(), 1);
+    let history = ok(h.read(
+        user(1),
+        "get_treasury_history",
+        &format!("({}, 0, 10)", dao_id),
+    ));
+    let kinds: Vec<&str> = vec(&history)
+        .iter()
+        .map(|entry| case(field(entry, "kind")))
+        .collect();
+    assert_eq!(kinds, ["Deposit", "DepositForfeit"]);
+    // nothing is owed back
+    assert_eq!(
+        err(h.call(
+            proposer,
+            "claim_deposit_refund",
+            &format!("({})", proposal_id)
+        )),
+        "InvalidInput"
+    );
+}
+
+#[test]
+fn failed_refunds_are_claimed_later() {
+    let Setup {
+        h,
+        ledger,
+        dao_id,
+        proposer,
+    } = setup();
+    let proposal_id = h.add_proposal(proposer, dao_id, "Staked", "");
+    for voter in [user(1), user(3)] {
+        ok(h.call(voter, "upvote", &format!("({})", proposal_id)));
+    }
+
+    h.update(&ledger, admin(), "set_failing", "(true)");
+    end_vote(&h, proposer, proposal_id);
+    assert_eq!(deposit_status(&h, proposal_id), "RefundPending");
+    assert_eq!(h.balance(&ledger, &h.treasury(dao_id)), DEPOSIT);
+
+    // still down, the refund stays pending
+    assert_eq!(
+        err(h.call(
+            proposer,
+            "claim_deposit_refund",
+            &format!("({})", proposal_id)
+        )),
+        "LedgerError"
+    );
+    assert_eq!(deposit_status(&h, proposal_id), "RefundPending");
+
+    h.update(&ledger, admin(), "set_failing", "(false)");
+    // only the proposer claims it
+    assert_eq!(
+        err(h.call(
+            user(3),
+            "claim_deposit_refund",
+            &format!("({})", proposal_id)
+        )),
+        "PermissionError"
+    );
+    ok(h.call(
+        proposer,
+        "claim_deposit_refund",
+        &format!("({})", proposal_id),
+    ));
+    assert_eq!(deposit_status(&h, proposal_id), "Refunded");
+    assert_eq!(h.balance(&ledger, &h.treasury(dao_id)), 0);
+    assert_eq!(
+        h.balance(&ledger, &account(proposer)),
+        1_000 - DEPOSIT - FEE + DEPOSIT - FEE
+    );
+    // and only once
+    assert_eq!(
+        err(h.call(
+            proposer,
+            "claim_deposit_refund",
+            &format!("({})", proposal_id)
+        )),
+        "InvalidInput"
+    );
+    assert_eq!(h.transfers(&ledger).len(), 2);
+}
@@ -0,0 +1,93 @@
+// Dues are paid through the ledger into the DAO's treasury and keep a member able to vote for one
+// period, after which they can still read but not vote until they pay again
+mod common;
+
+use common::*;
+
+const DUES: u64 = 100;
+const PERIOD_SECONDS: u64 = 24 * 60 * 60;
+
+#[test]
+fn paying_dues_keeps_a_member_voting_until_they_lapse() {
+    let h = Harness::new("");
+    let ledger = h.install_ledger();
+    let (owner, member) = (user(1), user(2));
+    let dao_id = h.create_dao(owner, &[member]);
+    ok(h.call(
+        owner,
+        "set_dues",
+        &format!("({}, {}, {})", dao_id, DUES, PERIOD_SECONDS),
+    ));
+    // proposals stay open for a week, longer than a period of dues
+    let first = h.add_proposal(owner, dao_id, "First", "");
+    let second = h.add_proposal(owner, dao_id, "Second", "");
+
+    // members who never paid can't vote
+    assert_eq!(
+        err(h.call(member, "upvote", &format!("({})", first))),
+        "NotEligible"
+    );
+
+    h.mint(&ledger, &account(member), 1_000);
+    let paid_at = h.now();
+    let paid_until = nat64(&ok(h.call(member, "pay_dues", &format!("({})", dao_id))));
+    assert!(paid_until >= paid_at + PERIOD_SECONDS * 1_000_000_000);
+    assert!(paid_until < paid_at + (PERIOD_SECONDS + 60) * 1_000_000_000);
+    assert_eq!(
+        nat64(&ok(h.read(
+            member,
+            "get_my_paid_until",
+            &format!("({})", dao_id)
+        ))),
+        paid_until
+    );
+    // the dues went from the member into the treasury, the member paid the fee on top
+    assert_eq!(h.balance(&ledger, &h.treasury(dao_id)), DUES);
+    assert_eq!(h.balance(&ledger, &account(member)), 1_000 - DUES - 10);
+    ok(h.call(member, "upvote", &format!("({})", first)));
+
+    h.wait(PERIOD_SECONDS + 1);
+    assert_eq!(
+        err(h.call(member, "upvote", &format!("({})", second))),
+        "NotEligible"
+    );
+    // lapsed members still read the DAO
+    ok(h.read(member, "get_proposal", &format!("({})", second)));
+    ok(h.read(member, "get_all_proposals", &format!("({})", dao_id)));
+
+    // paying again counts the period from now, not from when the last one ended
+    let paid_again_at = h.now();
+    let paid_until = nat64(&ok(h.call(member, "pay_dues", &format!("({})", dao_id))));
+    assert!(paid_until >= paid_again_at + PERIOD_SECONDS * 1_000_000_000);
+    ok(h.call(member, "upvote", &format!("({})", second)));
+    assert_eq!(h.balance(&ledger, &h.treasury(dao_id)), 2 * DUES);
+}
+
+#[test]
+fn a_failed_payment_extends_nothing() {
+    let h = Harness::new("");
+    let ledger = h.install_ledger();
+    let (owner, member) = (user(1), user(2));
+    let dao_id = h.create_dao(owner, &[member]);
+    ok(h.call(
+        owner,
+        "set_dues",
+        &format!("({}, {}, {})", dao_id, DUES, PERIOD_SECONDS),
+    ));
+
+    // too little to pay the dues and the fee
+    h.mint(&ledger, &account(member), DUES);
+    assert_eq!(
+        err(h.call(member, "pay_dues", &format!("({})", dao_id))),
+        "InsufficientFunds"
+    );
+    assert_eq!(
+        nat64(&ok(h.read(
+            member,
+            "get_my_paid_until",
+            &format!("({})", dao_id)
+        ))),
+        0
+    );
+    assert!(h.transfers(&ledger).is_empty());
+}
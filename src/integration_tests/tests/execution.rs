@@ -0,0 +1,67 @@
+// A Funding proposal pays out of its DAO's treasury once, however many executions of it arrive
+// while the ledger call of the first one runs
+mod common;
+
+use common::*;
+
+const AMOUNT: u64 = 100;
+const FEE: u64 = 10;
+const VOTING_PERIOD_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+#[test]
+fn executions_racing_each_other_pay_once() {
+    let h = Harness::new("");
+    let ledger = h.install_ledger();
+    let (owner, proposer) = (user(1), user(2));
+    let dao_id = h.create_dao(owner, &[proposer, user(3)]);
+    h.mint(&ledger, &h.treasury(dao_id), 1_000);
+
+    let proposal_id = h.add_proposal(
+        proposer,
+        dao_id,
+        "Paid",
+        &format!(
+            "kind = opt variant {{ Funding = record {{ amount = {}; recipient = null }} }}",
+            AMOUNT
+        ),
+    );
+    for voter in [owner, user(3)] {
+        ok(h.call(voter, "upvote", &format!("({})", proposal_id)));
+    }
+    h.wait(VOTING_PERIOD_SECONDS + 1);
+    let approved = ok(h.call(proposer, "end_proposal_vote", &format!("({})", proposal_id)));
+    assert_eq!(case(field(&approved, "status")), "Approved");
+
+    // both are in before the first one's transfer comes back
+    let execute = format!("({})", proposal_id);
+    let results = h.update_together(
+        owner,
+        &[
+            ("execute_proposal", &execute),
+            ("execute_proposal", &execute),
+        ],
+    );
+    let mut outcomes: Vec<String> = results
+        .into_iter()
+        .map(|result| match case(&result) {
+            "Ok" => "Ok".to_string(),
+            _ => err(result),
+        })
+        .collect();
+    outcomes.sort();
+    assert_eq!(outcomes, ["ExecutionInProgress", "Ok"]);
+
+    // and once it's done the proposal stays executed
+    assert_eq!(
+        err(h.call(owner, "execute_proposal", &execute)),
+        "InvalidInput"
+    );
+    let transfers = h.transfers(&ledger);
+    assert_eq!(transfers.len(), 1);
+    assert_eq!(transfers[0].2, AMOUNT);
+    assert_eq!(h.balance(&ledger, &account(proposer)), AMOUNT);
+    assert_eq!(
+        h.balance(&ledger, &h.treasury(dao_id)),
+        1_000 - AMOUNT - FEE
+    );
+}
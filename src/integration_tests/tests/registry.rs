@@ -0,0 +1,152 @@
+// Public DAOs are announced to the registry canister from a timer. Announcements the registry
+// fails stay in the outbox and are delivered by a later retry or by flush_outbox, and the calls
+// that queued them never fail because of it
+mod common;
+
+use candid::IDLValue;
+use common::*;
+
+// Retries wait 30 seconds after a first failure, see registry::RETRY_BASE_DELAY
+const FIRST_RETRY_SECONDS: u64 = 30;
+
+// (event, dao id, dao name) of every announcement the registry received
+fn announcements(h: &Harness, registry: &Canister) -> Vec<(String, u64, String)> {
+    vec(&h.query(registry, admin(), "announcements", "()"))
+        .iter()
+        .map(|blob| {
+            let bytes: Vec<u8> = match blob {
+                IDLValue::Blob(bytes) => bytes.clone(),
+                IDLValue::Vec(bytes) => bytes
+                    .iter()
+                    .map(|byte| match byte {
+                        IDLValue::Nat8(byte) => *byte,
+                        other => panic!("not a byte: {}", other),
+                    })
+                    .collect(),
+                other => panic!("not a blob: {}", other),
+            };
+            let announcement = h.backend.decode_type("DaoAnnouncement", &bytes);
+            let dao = field(&announcement, "dao");
+            (
+                case(field(&announcement, "event")).to_string(),
+                nat64(field(dao, "id")),
+                text(field(dao, "name")).to_string(),
+            )
+        })
+        .collect()
+}
+
+// (dao id, attempts, whether an error was kept) of every announcement waiting in the outbox
+fn outbox(h: &Harness) -> Vec<(u64, u64, bool)> {
+    let status = ok(h.read(admin(), "get_outbox_status", "()"));
+    let entries: Vec<(u64, u64, bool)> = vec(field(&status, "entries"))
+        .iter()
+        .map(|entry| {
+            let IDLValue::Record(pair) = entry else {
+                panic!("not a tuple: {}", entry)
+            };
+            let attempts = match field(&pair[1].val, "attempts") {
+                IDLValue::Nat32(attempts) => *attempts as u64,
+                other => panic!("not a count: {}", other),
+            };
+            (
+                nat64(&pair[0].val),
+                attempts,
+                opt(field(&pair[1].val, "last_error")).is_some(),
+            )
+        })
+        .collect();
+    assert_eq!(nat64(field(&status, "pending")), entries.len() as u64);
+    entries
+}
+
+fn rename(h: &Harness, dao_id: u64, name: &str) {
+    ok(h.call(
+        user(1),
+        "update_dao",
+        &format!(
+            "({}, record {{ name = {}; description = \"A DAO under test\"; avatar = variant {{ None }} }}, null)",
+            dao_id,
+            quoted(name)
+        ),
+    ));
+}
+
+#[test]
+fn announcements_are_delivered_and_retried() {
+    let h = Harness::new("");
+    let registry = h.install_stub();
+    ok(h.call(
+        admin(),
+        "set_registry_canister",
+        &format!("(opt principal \"{}\")", registry.id),
+    ));
+
+    // private DAOs aren't announced
+    let dao_id = h.create_dao(user(1), &[]);
+    h.wait(0);
+    assert!(announcements(&h, &registry).is_empty());
+
+    ok(h.call(
+        user(1),
+        "set_dao_visibility",
+        &format!("({}, variant {{ Public }})", dao_id),
+    ));
+    h.wait(0);
+    assert_eq!(
+        announcements(&h, &registry),
+        [("Listed".to_string(), dao_id, "Tested".to_string())]
+    );
+    assert!(outbox(&h).is_empty());
+
+    // the registry is down, the rename goes through and its announcement waits
+    h.update(&registry, admin(), "set_failing", "(true)");
+    rename(&h, dao_id, "Renamed");
+    h.wait(0);
+    assert_eq!(outbox(&h), [(dao_id, 1, true)]);
+    assert_eq!(announcements(&h, &registry).len(), 1);
+
+    // retried once the delay is over
+    h.update(&registry, admin(), "set_failing", "(false)");
+    h.wait(FIRST_RETRY_SECONDS + 1);
+    assert!(outbox(&h).is_empty());
+    assert_eq!(
+        announcements(&h, &registry)[1],
+        ("Updated".to_string(), dao_id, "Renamed".to_string())
+    );
+}
+
+#[test]
+fn flushing_delivers_without_waiting_for_the_retry() {
+    let h = Harness::new("");
+    let registry = h.install_stub();
+    ok(h.call(
+        admin(),
+        "set_registry_canister",
+        &format!("(opt principal \"{}\")", registry.id),
+    ));
+    let dao_id = h.create_dao(user(1), &[]);
+
+    h.update(&registry, admin(), "set_failing", "(true)");
+    ok(h.call(
+        user(1),
+        "set_dao_visibility",
+        &format!("({}, variant {{ Public }})", dao_id),
+    ));
+    rename(&h, dao_id, "Renamed");
+    h.wait(0);
+    // only the latest announcement of a DAO is kept
+    assert_eq!(outbox(&h), [(dao_id, 1, true)]);
+
+    h.update(&registry, admin(), "set_failing", "(false)");
+    let status = ok(h.call(admin(), "flush_outbox", "()"));
+    assert_eq!(nat64(field(&status, "pending")), 0);
+    assert_eq!(
+        announcements(&h, &registry),
+        [("Updated".to_string(), dao_id, "Renamed".to_string())]
+    );
+
+    // the retry that was scheduled finds nothing left to deliver
+    h.wait(FIRST_RETRY_SECONDS + 1);
+    assert_eq!(announcements(&h, &registry).len(), 1);
+}
@@ -0,0 +1,134 @@
+// New DAOs are placed in bucket canisters the backend spawns from the wasm the admin uploaded,
+// the next bucket being spawned once the last one is full. Clients keep calling the backend about
+// the proposals, comments and votes of those DAOs and it forwards the calls to their bucket
+mod common;
+
+use candid::Principal;
+use common::*;
+
+const VOTING_PERIOD_SECONDS: u64 = 7 * 24 * 60 * 60;
+// Ids of the records of the bucket with index i start at (i + 1) * ID_SPAN, see sharding::ID_SPAN
+const ID_SPAN: u64 = 1 << 40;
+
+fn comment(h: &Harness, author: Principal, proposal_id: u64, content: &str) -> u64 {
+    let comment = ok(h.call(
+        author,
+        "comment_on_post",
+        &format!(
+            "(record {{ proposal_id = {}; content = {} }})",
+            proposal_id,
+            quoted(content)
+        ),
+    ));
+    nat64(field(&comment, "id"))
+}
+
+// (canister, DAO count, first id) of every bucket the backend spawned
+fn buckets(h: &Harness) -> Vec<(Principal, u64, u64)> {
+    let info = ok(h.read(admin(), "get_shard_info", "()"));
+    vec(field(&info, "buckets"))
+        .iter()
+        .map(|bucket| {
+            (
+                principal(field(bucket, "canister_id")),
+                nat64(field(bucket, "dao_count")),
+                nat64(field(bucket, "first_id")),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn daos_are_spread_over_buckets() {
+    let h = Harness::new("max_daos_per_bucket = opt 1");
+    let (owner, member) = (user(1), user(2));
+    // a DAO created before there is a bucket stays in the backend
+    let local = h.create_dao(owner, &[member]);
+
+    h.upload_bucket_wasm();
+    ok(h.call(admin(), "spawn_bucket", "()"));
+    let first = h.create_dao(owner, &[member]);
+    // the first bucket is full with it, the second one is spawned in the background
+    h.wait(0);
+    let second = h.create_dao(owner, &[member]);
+
+    let spawned = buckets(&h);
+    assert_eq!(spawned.len(), 2);
+    assert_ne!(spawned[0].0, spawned[1].0);
+    assert!(spawned.iter().all(|(_, dao_count, _)| *dao_count == 1));
+    assert_eq!(spawned[0].2, ID_SPAN);
+    assert_eq!(spawned[1].2, 2 * ID_SPAN);
+
+    let mut proposals = Vec::new();
+    for (dao_id, (_, _, first_id)) in [first, second].into_iter().zip(&spawned) {
+        let proposal_id = h.add_proposal(member, dao_id, "Routed", "");
+        let comment_id = comment(&h, member, proposal_id, "Forwarded");
+        // created in the DAO's bucket, with ids of its own
+        for id in [proposal_id, comment_id] {
+            assert!(id >= *first_id && id < first_id + ID_SPAN);
+        }
+        ok(h.call(owner, "upvote", &format!("({})", proposal_id)));
+        // a second vote is refused by the bucket like it would be by the backend
+        assert_eq!(
+            err(h.call(owner, "downvote", &format!("({})", proposal_id))),
+            "HasVoted"
+        );
+
+        let proposal = ok(h.read(owner, "get_proposal", &format!("({})", proposal_id)));
+        assert_eq!(nat64(field(&proposal, "proposal_number")), 1);
+        assert_eq!(opt(field(&proposal, "upvote_count")).map(nat64), Some(1));
+        let listed = ok(h.read(owner, "get_all_proposals", &format!("({})", dao_id)));
+        assert_eq!(vec(&listed).len(), 1);
+        let comments = ok(h.read(
+            owner,
+            "get_all_comments_on_proposal",
+            &format!("({})", proposal_id),
+        ));
+        assert_eq!(vec(&comments).len(), 1);
+        // the DAO kept by the backend counts the proposal
+        let dao = ok(h.read(owner, "get_dao", &format!("({})", dao_id)));
+        assert_eq!(nat64(field(&dao, "proposal_count")), 1);
+        proposals.push(proposal_id);
+    }
+
+    // users outside the DAO don't see its proposals through the backend
+    assert_eq!(
+        err(h.read(user(3), "get_proposal", &format!("({})", proposals[0]))),
+        "NotFound"
+    );
+    // and nobody calls a bucket but the backend
+    let bucket = h.bucket(spawned[0].0);
+    let rejection = h.rejection(&bucket, owner, "upvote", &format!("({})", proposals[0]));
+    assert!(rejection.contains("bucket"), "{}", rejection);
+
+    h.wait(VOTING_PERIOD_SECONDS + 1);
+    for proposal_id in &proposals {
+        let ended = ok(h.call(member, "end_proposal_vote", &format!("({})", proposal_id)));
+        assert_eq!(case(field(&ended, "status")), "Approved");
+    }
+
+    // the DAO kept in the backend works as it did, and holds the only proposal the backend does
+    let local_proposal = h.add_proposal(member, local, "Local", "");
+    assert!(local_proposal < ID_SPAN);
+    let info = ok(h.read(admin(), "get_shard_info", "()"));
+    assert_eq!(nat64(field(&info, "dao_count")), 3);
+    assert_eq!(nat64(field(&info, "proposal_count")), 1);
+
+    // deposits go to the backend's treasury, which routed DAOs don't take yet
+    ok(h.call(
+        owner,
+        "update_dao_settings",
+        &format!("({}, record {{ proposal_deposit = opt 50 }})", second),
+    ));
+    assert_eq!(
+        err(h.call(
+            member,
+            "add_proposal",
+            &format!(
+                "(record {{ dao_id = {}; title = \"Staked\"; details = \"\"; amount_requested = 0 }})",
+                second
+            ),
+        )),
+        "InvalidInput"
+    );
+}
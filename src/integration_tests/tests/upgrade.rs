@@ -0,0 +1,124 @@
+// An upgrade to the same wasm keeps every DAO, proposal, comment and vote as it was, and new
+// records keep taking ids after the ones taken before the upgrade
+mod common;
+
+use candid::types::value::{IDLField, VariantValue};
+use candid::types::Label;
+use candid::IDLValue;
+use common::*;
+
+fn comment(h: &Harness, proposal_id: u64, content: &str, reply_to: Option<u64>) -> u64 {
+    let reply_to = reply_to.map_or("null".to_string(), |id| format!("opt {}", id));
+    let comment = ok(h.call(
+        user(2),
+        "comment_on_post",
+        &format!(
+            "(record {{ proposal_id = {}; content = {}; reply_to = {} }})",
+            proposal_id,
+            quoted(content),
+            reply_to
+        ),
+    ));
+    nat64(field(&comment, "id"))
+}
+
+// The time proposals have left counts down while the test runs, everything else must match
+fn without_countdown(value: IDLValue) -> IDLValue {
+    match value {
+        IDLValue::Record(fields) => IDLValue::Record(
+            fields
+                .into_iter()
+                .filter(|field| field.id != Label::Named("time_remaining_seconds".to_string()))
+                .map(|field| IDLField {
+                    id: field.id,
+                    val: without_countdown(field.val),
+                })
+                .collect(),
+        ),
+        IDLValue::Variant(VariantValue(field, index)) => IDLValue::Variant(VariantValue(
+            Box::new(IDLField {
+                id: field.id,
+                val: without_countdown(field.val),
+            }),
+            index,
+        )),
+        IDLValue::Vec(values) => IDLValue::Vec(values.into_iter().map(without_countdown).collect()),
+        IDLValue::Opt(value) => IDLValue::Opt(Box::new(without_countdown(*value))),
+        value => value,
+    }
+}
+
+#[test]
+fn records_survive_an_upgrade() {
+    let h = Harness::new("");
+    let (owner, member) = (user(1), user(2));
+    let dao_id = h.create_dao(owner, &[member]);
+    let first = h.add_proposal(owner, dao_id, "First", "");
+    let second = h.add_proposal(owner, dao_id, "Second", "");
+    // the longest title, details and options a proposal takes, four bytes per character, so its
+    // record is close to the bound it is stored with
+    let wide = |character: u32, len: usize| {
+        quoted(&char::from_u32(character).unwrap().to_string().repeat(len))
+    };
+    let options: Vec<String> = (0..6).map(|option| wide(0x1D11E + option, 50)).collect();
+    let largest = ok(h.call(
+        owner,
+        "add_proposal",
+        &format!(
+            "(record {{ dao_id = {}; title = {}; details = {}; amount_requested = 0; \
+             kind = opt variant {{ RankedChoice = record {{ options = vec {{ {} }} }} }} }})",
+            dao_id,
+            wide(0x1D11E, 200),
+            wide(0x1D11E, 2_000),
+            options.join("; ")
+        ),
+    ));
+    let largest = nat64(field(&largest, "id"));
+    // one character more is refused and nothing is stored
+    let too_long = h.call(
+        owner,
+        "add_proposal",
+        &format!(
+            "(record {{ dao_id = {}; title = {}; details = \"\"; amount_requested = 0 }})",
+            dao_id,
+            wide(0x1D11E, 201)
+        ),
+    );
+    assert_eq!(err(too_long), "InvalidInput");
+
+    ok(h.call(member, "upvote", &format!("({})", first)));
+    let thread = comment(&h, first, "First", None);
+    let reply = comment(&h, first, "Reply", Some(thread));
+    comment(&h, largest, &"𝄞".repeat(1_000), None);
+
+    let ids = [first, second, largest, thread, reply];
+    let snapshot = || -> Vec<IDLValue> {
+        let mut records = vec![
+            h.read(owner, "get_dao", &format!("({})", dao_id)),
+            h.read(owner, "get_all_proposals", &format!("({})", dao_id)),
+            h.read(member, "get_my_vote", &format!("({})", first)),
+        ];
+        for proposal in [first, second, largest] {
+            records.push(h.read(owner, "get_proposal", &format!("({})", proposal)));
+            records.push(h.read(
+                owner,
+                "get_all_comments_on_proposal",
+                &format!("({})", proposal),
+            ));
+        }
+        records.into_iter().map(without_countdown).collect()
+    };
+    let before = snapshot();
+    assert_eq!(vec(&ok(before[1].clone())).len(), 3);
+
+    h.upgrade();
+    // nothing is left to migrate, the canister takes calls right away
+    assert!(snapshot() == before);
+
+    let next_proposal = h.add_proposal(owner, dao_id, "After the upgrade", "");
+    let next_comment = comment(&h, second, "After the upgrade", None);
+    for id in [next_proposal, next_comment] {
+        assert!(ids.iter().all(|taken| id > *taken));
+    }
+    assert_ne!(next_proposal, next_comment);
+}